@@ -18,7 +18,7 @@ use async_trait::async_trait;
 use axum::{
     body::HttpBody,
     extract::{
-        rejection::{FailedToDeserializeForm, FormRejection, TypedHeaderRejectionReason},
+        rejection::{FormRejection, TypedHeaderRejectionReason},
         Form, FromRequest, FromRequestParts, TypedHeader,
     },
     response::IntoResponse,
@@ -250,7 +250,7 @@ impl<F> ClientAuthorization<F> {
 #[derive(Debug)]
 pub enum ClientAuthorizationError {
     InvalidHeader,
-    BadForm(FailedToDeserializeForm),
+    BadForm(Box<dyn std::error::Error>),
     ClientIdMismatch { credential: String, form: String },
     UnsupportedClientAssertion { client_assertion_type: String },
     MissingCredentials,
@@ -376,19 +376,29 @@ where
             client_assertion_type,
             client_assertion,
             form,
-        ) = match Form::<AuthorizedForm<F>>::from_request(req, state).await {
-            Ok(Form(form)) => (
-                form.client_id,
-                form.client_secret,
-                form.client_assertion_type,
-                form.client_assertion,
-                Some(form.inner),
-            ),
+        ) = match Form::<Vec<(String, String)>>::from_request(req, state).await {
+            Ok(Form(pairs)) => {
+                // Some parameters, like `resource`, may be repeated, which only
+                // `serde_html_form` can deserialize into sequences
+                let form = url::form_urlencoded::Serializer::new(String::new())
+                    .extend_pairs(pairs)
+                    .finish();
+                let form: AuthorizedForm<F> = serde_html_form::from_str(&form)
+                    .map_err(|e| ClientAuthorizationError::BadForm(Box::new(e)))?;
+
+                (
+                    form.client_id,
+                    form.client_secret,
+                    form.client_assertion_type,
+                    form.client_assertion,
+                    Some(form.inner),
+                )
+            }
             // If it is not a form, continue
             Err(FormRejection::InvalidFormContentType(_err)) => (None, None, None, None, None),
             // If the form could not be read, return a Bad Request error
             Err(FormRejection::FailedToDeserializeForm(err)) => {
-                return Err(ClientAuthorizationError::BadForm(err))
+                return Err(ClientAuthorizationError::BadForm(Box::new(err)))
             }
             // Other errors (body read twice, byte stream broke) return an internal error
             Err(e) => return Err(ClientAuthorizationError::Internal(Box::new(e))),
@@ -518,6 +528,36 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn repeated_resource_test() {
+        let req = Request::builder()
+            .method(Method::POST)
+            .header(
+                http::header::CONTENT_TYPE,
+                mime::APPLICATION_WWW_FORM_URLENCODED.as_ref(),
+            )
+            .body(Full::<Bytes>::new(
+                "client_id=client-id&resource=https%3A%2F%2Fa.example.com%2F&foo=bar&resource=https%3A%2F%2Fb.example.com%2F".into(),
+            ))
+            .unwrap();
+
+        // The repeated resources are deserialized as a sequence
+        assert_eq!(
+            ClientAuthorization::<serde_json::Value>::from_request(req, &())
+                .await
+                .unwrap(),
+            ClientAuthorization {
+                credentials: Credentials::None {
+                    client_id: "client-id".to_owned(),
+                },
+                form: Some(serde_json::json!({
+                    "foo": "bar",
+                    "resource": ["https://a.example.com/", "https://b.example.com/"],
+                })),
+            }
+        );
+    }
+
     #[tokio::test]
     async fn client_secret_basic_test() {
         let req = Request::builder()
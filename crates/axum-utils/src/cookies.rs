@@ -22,6 +22,7 @@ use axum::{
     response::{IntoResponseParts, ResponseParts},
 };
 use axum_extra::extract::cookie::{Cookie, Key, PrivateCookieJar, SameSite};
+use chrono::{DateTime, Utc};
 use http::request::Parts;
 use serde::{de::DeserializeOwned, Serialize};
 use thiserror::Error;
@@ -41,13 +42,18 @@ pub enum CookieDecodeError {
 pub struct CookieManager {
     options: CookieOption,
     key: Key,
+    previous_keys: Vec<Key>,
 }
 
 impl CookieManager {
     #[must_use]
     pub const fn new(base_url: Url, key: Key) -> Self {
         let options = CookieOption::new(base_url);
-        Self { options, key }
+        Self {
+            options,
+            key,
+            previous_keys: Vec::new(),
+        }
     }
 
     #[must_use]
@@ -56,20 +62,41 @@ impl CookieManager {
         Self::new(base_url, key)
     }
 
+    /// Add a key derived from a previous encryption key, which will only be
+    /// used to decrypt the cookies set before the key was rotated
+    #[must_use]
+    pub fn with_previous_key(mut self, key: &[u8]) -> Self {
+        self.previous_keys.push(Key::derive_from(key));
+        self
+    }
+
     #[must_use]
     pub fn cookie_jar(&self) -> CookieJar {
         let inner = PrivateCookieJar::new(self.key.clone());
         let options = self.options.clone();
 
-        CookieJar { inner, options }
+        CookieJar {
+            inner,
+            previous: Vec::new(),
+            options,
+        }
     }
 
     #[must_use]
     pub fn cookie_jar_from_headers(&self, headers: &http::HeaderMap) -> CookieJar {
         let inner = PrivateCookieJar::from_headers(headers, self.key.clone());
+        let previous = self
+            .previous_keys
+            .iter()
+            .map(|key| PrivateCookieJar::from_headers(headers, key.clone()))
+            .collect();
         let options = self.options.clone();
 
-        CookieJar { inner, options }
+        CookieJar {
+            inner,
+            previous,
+            options,
+        }
     }
 }
 
@@ -117,6 +144,8 @@ impl CookieOption {
 /// A cookie jar which encrypts cookies & sets secure options
 pub struct CookieJar {
     inner: PrivateCookieJar<Key>,
+    /// The same cookies, decrypted with the previous keys
+    previous: Vec<PrivateCookieJar<Key>>,
     options: CookieOption,
 }
 
@@ -146,15 +175,51 @@ impl CookieJar {
         self
     }
 
+    /// Save the given payload in a cookie which expires at the given time
+    ///
+    /// `now` is used to compute the `Max-Age` of the cookie.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the payload cannot be serialized
+    #[must_use]
+    pub fn save_until<T: Serialize>(
+        mut self,
+        key: &str,
+        payload: &T,
+        expires_at: DateTime<Utc>,
+        now: DateTime<Utc>,
+    ) -> Self {
+        let serialized =
+            serde_json::to_string(payload).expect("failed to serialize cookie payload");
+
+        let cookie = Cookie::new(key.to_owned(), serialized);
+        let mut cookie = self.options.apply(cookie);
+
+        let max_age = (expires_at - now).num_seconds().max(0);
+        cookie.set_max_age(time::Duration::seconds(max_age));
+
+        self.inner = self.inner.add(cookie);
+
+        self
+    }
+
     /// Load and deserialize a cookie from the jar
     ///
+    /// Cookies which were encrypted with a previous key are also loaded, and
+    /// are encrypted with the current key the next time they are saved.
+    ///
     /// Returns `None` if the cookie is not present
     ///
     /// # Errors
     ///
     /// Returns an error if the cookie cannot be deserialized
     pub fn load<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>, CookieDecodeError> {
-        let Some(cookie) = self.inner.get(key) else {
+        let cookie = self
+            .inner
+            .get(key)
+            .or_else(|| self.previous.iter().find_map(|jar| jar.get(key)));
+        let Some(cookie) = cookie else {
             return Ok(None);
         };
 
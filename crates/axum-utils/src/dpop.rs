@@ -0,0 +1,635 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Support for `DPoP` proofs, as defined by [RFC9449]
+//!
+//! [RFC9449]: https://www.rfc-editor.org/rfc/rfc9449
+
+use std::{
+    collections::{HashMap, VecDeque},
+    convert::Infallible,
+    sync::{Mutex, PoisonError},
+};
+
+use async_trait::async_trait;
+use axum::extract::{FromRef, FromRequestParts, OriginalUri};
+use base64ct::{Base64UrlUnpadded, Encoding};
+use chrono::{DateTime, Duration, Utc};
+use http::{header::AUTHORIZATION, request::Parts, HeaderName, Method};
+use mas_data_model::AccessToken;
+use mas_jose::{
+    jwa::AsymmetricVerifyingKey,
+    jwt::{Jwt, JwtDecodeError},
+};
+use mas_router::UrlBuilder;
+use rand::{
+    distributions::{Alphanumeric, DistString},
+    RngCore,
+};
+use serde::Deserialize;
+use serde_with::{serde_as, TimestampSeconds};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use url::Url;
+
+/// The header carrying the `DPoP` proof
+pub static DPOP: HeaderName = HeaderName::from_static("dpop");
+
+/// The header carrying the nonce the client must use in its next `DPoP` proof
+pub static DPOP_NONCE: HeaderName = HeaderName::from_static("dpop-nonce");
+
+/// The `typ` header value of `DPoP` proofs
+const DPOP_PROOF_TYPE: &str = "dpop+jwt";
+
+/// How far from the current time a proof may have been issued
+const PROOF_LEEWAY: Duration = Duration::microseconds(5 * 60 * 1000 * 1000);
+
+#[serde_as]
+#[derive(Debug, Deserialize)]
+struct DpopProofClaims {
+    jti: String,
+    htm: String,
+    htu: Url,
+    #[serde_as(as = "TimestampSeconds<i64>")]
+    iat: DateTime<Utc>,
+    #[serde(default)]
+    ath: Option<String>,
+    #[serde(default)]
+    nonce: Option<String>,
+}
+
+#[derive(Debug, Error)]
+pub enum DpopError {
+    #[error("missing DPoP proof")]
+    MissingProof,
+
+    #[error("could not decode the DPoP proof")]
+    Decode(#[from] JwtDecodeError),
+
+    #[error("invalid DPoP proof type")]
+    InvalidType,
+
+    #[error("the DPoP proof does not embed a public key")]
+    MissingKey,
+
+    #[error("unsupported DPoP proof signing algorithm")]
+    UnsupportedAlgorithm,
+
+    #[error("invalid DPoP proof signature")]
+    InvalidSignature,
+
+    #[error("the DPoP proof does not match the request method or URI")]
+    RequestMismatch,
+
+    #[error("the DPoP proof was issued too far from the current time")]
+    InvalidIssuedAt,
+
+    #[error("the DPoP proof is not bound to the access token")]
+    AccessTokenMismatch,
+
+    #[error("the DPoP proof was not signed with the key the access token is bound to")]
+    KeyMismatch,
+
+    #[error("the DPoP proof is missing a valid nonce")]
+    UseNonce,
+
+    #[error("the DPoP proof was already used")]
+    Replayed,
+}
+
+/// A verified `DPoP` proof
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DpopProof {
+    jkt: String,
+}
+
+impl DpopProof {
+    /// Verify a `DPoP` proof for the given request
+    ///
+    /// # Parameters
+    ///
+    /// * `proof`: The raw `DPoP` proof, as found in the `DPoP` header
+    /// * `method`: The method of the request
+    /// * `uri`: The absolute URI of the request
+    /// * `now`: The current time
+    /// * `access_token`: The access token presented alongside the proof, if any
+    /// * `store`: The store of issued nonces and of already used proofs
+    /// * `require_nonce`: Whether the proof must carry a nonce issued by the
+    ///   store
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the proof is invalid for this request, or if it was
+    /// already used
+    pub fn verify(
+        proof: &str,
+        method: &Method,
+        uri: &Url,
+        now: DateTime<Utc>,
+        access_token: Option<&str>,
+        store: &dyn DpopNonceStore,
+        require_nonce: bool,
+    ) -> Result<Self, DpopError> {
+        let jwt: Jwt<'_, DpopProofClaims> = Jwt::try_from(proof)?;
+        let header = jwt.header();
+
+        if header.typ() != Some(DPOP_PROOF_TYPE) {
+            return Err(DpopError::InvalidType);
+        }
+
+        let jwk = header.jwk().ok_or(DpopError::MissingKey)?;
+
+        // This rejects symmetric algorithms and keys not suitable for the algorithm
+        let key = AsymmetricVerifyingKey::from_jwk_and_alg(jwk.params(), header.alg())
+            .map_err(|_| DpopError::UnsupportedAlgorithm)?;
+
+        jwt.verify(&key).map_err(|_| DpopError::InvalidSignature)?;
+
+        let claims = jwt.payload();
+
+        // The query and fragment parts are ignored when comparing the URIs
+        let mut htu = claims.htu.clone();
+        htu.set_query(None);
+        htu.set_fragment(None);
+        let mut expected_htu = uri.clone();
+        expected_htu.set_query(None);
+        expected_htu.set_fragment(None);
+
+        if claims.htm != method.as_str() || htu != expected_htu {
+            return Err(DpopError::RequestMismatch);
+        }
+
+        if claims.iat < now - PROOF_LEEWAY || claims.iat > now + PROOF_LEEWAY {
+            return Err(DpopError::InvalidIssuedAt);
+        }
+
+        if let Some(access_token) = access_token {
+            let ath = Base64UrlUnpadded::encode_string(&Sha256::digest(access_token));
+            if claims.ath.as_deref() != Some(ath.as_str()) {
+                return Err(DpopError::AccessTokenMismatch);
+            }
+        }
+
+        if require_nonce {
+            let valid = claims
+                .nonce
+                .as_deref()
+                .is_some_and(|nonce| store.consume(nonce, now));
+            if !valid {
+                return Err(DpopError::UseNonce);
+            }
+        }
+
+        let jkt = jwk.params().thumbprint_sha256();
+
+        // Proofs are only accepted until they are too old, so their identifiers
+        // only need to be remembered until then
+        if !store.record_proof(&jkt, &claims.jti, claims.iat + PROOF_LEEWAY, now) {
+            return Err(DpopError::Replayed);
+        }
+
+        Ok(Self { jkt })
+    }
+
+    /// The JWK SHA-256 thumbprint of the key which signed the proof
+    #[must_use]
+    pub fn jkt(&self) -> &str {
+        &self.jkt
+    }
+}
+
+/// A store of the nonces issued to clients for their `DPoP` proofs, and of the
+/// proofs already used
+pub trait DpopNonceStore: Send + Sync {
+    /// Issue a new nonce
+    fn issue(&self, rng: &mut (dyn RngCore + Send), now: DateTime<Utc>) -> String;
+
+    /// Consume a nonce, returning `false` if it was not issued by this store,
+    /// has expired or was already used
+    fn consume(&self, nonce: &str, now: DateTime<Utc>) -> bool;
+
+    /// Record the use of the proof with the given `jti`, signed by the key
+    /// with the given thumbprint, until `expires_at`
+    ///
+    /// Returns `false` if the proof was already used
+    fn record_proof(
+        &self,
+        jkt: &str,
+        jti: &str,
+        expires_at: DateTime<Utc>,
+        now: DateTime<Utc>,
+    ) -> bool;
+}
+
+/// A [`DpopNonceStore`] which keeps the most recently issued nonces and the
+/// recently used proofs in memory
+pub struct InMemoryDpopNonceStore {
+    nonces: Mutex<VecDeque<(String, DateTime<Utc>)>>,
+    proofs: Mutex<HashMap<(String, String), DateTime<Utc>>>,
+    capacity: usize,
+    ttl: Duration,
+}
+
+impl InMemoryDpopNonceStore {
+    /// Create a new store, holding at most `capacity` nonces, each valid for
+    /// `ttl`
+    #[must_use]
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            nonces: Mutex::new(VecDeque::with_capacity(capacity)),
+            proofs: Mutex::new(HashMap::new()),
+            capacity,
+            ttl,
+        }
+    }
+}
+
+impl Default for InMemoryDpopNonceStore {
+    fn default() -> Self {
+        Self::new(1024, Duration::microseconds(5 * 60 * 1000 * 1000))
+    }
+}
+
+impl DpopNonceStore for InMemoryDpopNonceStore {
+    fn issue(&self, rng: &mut (dyn RngCore + Send), now: DateTime<Utc>) -> String {
+        let nonce = Alphanumeric.sample_string(rng, 32);
+
+        let mut nonces = self.nonces.lock().unwrap_or_else(PoisonError::into_inner);
+        // Evict the oldest nonce once the buffer is full
+        if nonces.len() >= self.capacity {
+            nonces.pop_front();
+        }
+        nonces.push_back((nonce.clone(), now + self.ttl));
+
+        nonce
+    }
+
+    fn consume(&self, nonce: &str, now: DateTime<Utc>) -> bool {
+        let mut nonces = self.nonces.lock().unwrap_or_else(PoisonError::into_inner);
+        let Some(position) = nonces.iter().position(|(issued, _)| issued == nonce) else {
+            return false;
+        };
+
+        let (_, expires_at) = nonces
+            .remove(position)
+            .expect("the nonce to be in the store");
+        expires_at > now
+    }
+
+    fn record_proof(
+        &self,
+        jkt: &str,
+        jti: &str,
+        expires_at: DateTime<Utc>,
+        now: DateTime<Utc>,
+    ) -> bool {
+        let mut proofs = self.proofs.lock().unwrap_or_else(PoisonError::into_inner);
+        // Forget about the proofs which would be rejected anyway
+        proofs.retain(|_, expires_at| *expires_at > now);
+
+        proofs
+            .insert((jkt.to_owned(), jti.to_owned()), expires_at)
+            .is_none()
+    }
+}
+
+/// An extractor for the `DPoP` proof of a request, and the access token
+/// presented with the `DPoP` authorization scheme, if any
+#[derive(Debug)]
+pub struct DpopRequest {
+    proof: Option<String>,
+    token: Option<String>,
+    method: Method,
+    htu: Url,
+}
+
+impl DpopRequest {
+    /// The raw `DPoP` proof, if there was exactly one in the request
+    #[must_use]
+    pub fn proof(&self) -> Option<&str> {
+        self.proof.as_deref()
+    }
+
+    /// The access token presented with the `DPoP` authorization scheme
+    #[must_use]
+    pub fn token(&self) -> Option<&str> {
+        self.token.as_deref()
+    }
+
+    /// Verify the `DPoP` proof of the request, if there is one
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there is a proof but it is invalid
+    pub fn verify_proof(
+        &self,
+        now: DateTime<Utc>,
+        store: &dyn DpopNonceStore,
+        require_nonce: bool,
+    ) -> Result<Option<DpopProof>, DpopError> {
+        let Some(proof) = self.proof.as_deref() else {
+            return Ok(None);
+        };
+
+        let proof = DpopProof::verify(
+            proof,
+            &self.method,
+            &self.htu,
+            now,
+            None,
+            store,
+            require_nonce,
+        )?;
+        Ok(Some(proof))
+    }
+
+    /// Verify that the request proves possession of the key the access token
+    /// is bound to. Access tokens which are not bound to a key are always
+    /// accepted.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the access token is bound to a key and the request
+    /// does not carry a valid proof for it
+    pub fn verify_binding(
+        &self,
+        access_token: &AccessToken,
+        now: DateTime<Utc>,
+        store: &dyn DpopNonceStore,
+    ) -> Result<(), DpopError> {
+        let Some(jkt) = access_token.dpop_jkt.as_deref() else {
+            return Ok(());
+        };
+
+        // Bound tokens must be presented with the DPoP authorization scheme. The
+        // proof is bound to the token as presented, which may be a JWT issued for
+        // the stored one.
+        let Some(token) = self
+            .token
+            .as_deref()
+            .filter(|token| access_token.is_presented_as(token))
+        else {
+            return Err(DpopError::MissingProof);
+        };
+
+        let proof = self.proof.as_deref().ok_or(DpopError::MissingProof)?;
+        let proof = DpopProof::verify(
+            proof,
+            &self.method,
+            &self.htu,
+            now,
+            Some(token),
+            store,
+            false,
+        )?;
+
+        if proof.jkt() != jkt {
+            return Err(DpopError::KeyMismatch);
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for DpopRequest
+where
+    UrlBuilder: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        // Requests with more than one proof are treated as if they had none
+        let mut proofs = parts.headers.get_all(&DPOP).iter();
+        let proof = match (proofs.next(), proofs.next()) {
+            (Some(proof), None) => proof.to_str().ok().map(ToOwned::to_owned),
+            _ => None,
+        };
+
+        let token = parts
+            .headers
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("DPoP "))
+            .map(|token| token.trim().to_owned());
+
+        // Use the original URI, in case the router is nested under a prefix
+        let path = parts
+            .extensions
+            .get::<OriginalUri>()
+            .map_or(parts.uri.path(), |uri| uri.path());
+        let url_builder = UrlBuilder::from_ref(state);
+        let mut htu = url_builder.http_base();
+        htu.set_path(path);
+
+        Ok(Self {
+            proof,
+            token,
+            method: parts.method.clone(),
+            htu,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mas_iana::jose::JsonWebSignatureAlg;
+    use mas_jose::{
+        jwk::{JsonWebKey, JsonWebKeyPublicParameters},
+        jwt::JsonWebSignatureHeader,
+    };
+    use mas_keystore::PrivateKey;
+    use rand::SeedableRng;
+
+    use super::*;
+
+    fn sign_proof(
+        rng: &mut rand_chacha::ChaChaRng,
+        key: &PrivateKey,
+        claims: serde_json::Value,
+    ) -> String {
+        let signer = key
+            .signing_key_for_alg(&JsonWebSignatureAlg::Es256)
+            .unwrap();
+        let jwk = JsonWebKey::new(JsonWebKeyPublicParameters::from(key));
+        let header = JsonWebSignatureHeader::new(JsonWebSignatureAlg::Es256)
+            .with_typ(DPOP_PROOF_TYPE.to_owned())
+            .with_jwk(jwk);
+        Jwt::sign_with_rng(rng, header, claims, &signer)
+            .unwrap()
+            .into_string()
+    }
+
+    #[test]
+    fn test_verify_proof() {
+        let mut rng = rand_chacha::ChaChaRng::seed_from_u64(42);
+        let now = DateTime::<Utc>::UNIX_EPOCH + Duration::microseconds(1_700_000_000_000_000);
+        let key = PrivateKey::generate_ec_p256(&mut rng);
+        let jkt = JsonWebKeyPublicParameters::from(&key).thumbprint_sha256();
+        let uri: Url = "https://example.com/oauth2/token".parse().unwrap();
+
+        let proof = sign_proof(
+            &mut rng,
+            &key,
+            serde_json::json!({
+                "jti": "abc",
+                "htm": "POST",
+                "htu": "https://example.com/oauth2/token",
+                "iat": now.timestamp(),
+            }),
+        );
+
+        let store = InMemoryDpopNonceStore::default();
+        let verified =
+            DpopProof::verify(&proof, &Method::POST, &uri, now, None, &store, false).unwrap();
+        assert_eq!(verified.jkt(), jkt);
+
+        // The same proof can't be used twice
+        let res = DpopProof::verify(&proof, &Method::POST, &uri, now, None, &store, false);
+        assert!(matches!(res, Err(DpopError::Replayed)));
+
+        // Wrong method
+        let res = DpopProof::verify(&proof, &Method::GET, &uri, now, None, &store, false);
+        assert!(matches!(res, Err(DpopError::RequestMismatch)));
+
+        // Wrong URI
+        let other: Url = "https://example.com/oauth2/introspect".parse().unwrap();
+        let res = DpopProof::verify(&proof, &Method::POST, &other, now, None, &store, false);
+        assert!(matches!(res, Err(DpopError::RequestMismatch)));
+
+        // Too old
+        let later = now + Duration::microseconds(10 * 60 * 1000 * 1000);
+        let res = DpopProof::verify(&proof, &Method::POST, &uri, later, None, &store, false);
+        assert!(matches!(res, Err(DpopError::InvalidIssuedAt)));
+
+        // Missing the access token hash
+        let res = DpopProof::verify(
+            &proof,
+            &Method::POST,
+            &uri,
+            now,
+            Some("token"),
+            &store,
+            false,
+        );
+        assert!(matches!(res, Err(DpopError::AccessTokenMismatch)));
+
+        // Missing the nonce
+        let res = DpopProof::verify(&proof, &Method::POST, &uri, now, None, &store, true);
+        assert!(matches!(res, Err(DpopError::UseNonce)));
+
+        // With a nonce and an access token hash
+        let nonce = store.issue(&mut rng, now);
+        let ath = Base64UrlUnpadded::encode_string(&Sha256::digest("token"));
+        let proof = sign_proof(
+            &mut rng,
+            &key,
+            serde_json::json!({
+                "jti": "def",
+                "htm": "POST",
+                "htu": "https://example.com/oauth2/token?query#fragment",
+                "iat": now.timestamp(),
+                "ath": ath,
+                "nonce": nonce,
+            }),
+        );
+        let verified = DpopProof::verify(
+            &proof,
+            &Method::POST,
+            &uri,
+            now,
+            Some("token"),
+            &store,
+            true,
+        )
+        .unwrap();
+        assert_eq!(verified.jkt(), jkt);
+
+        // The nonce can only be used once
+        let proof = sign_proof(
+            &mut rng,
+            &key,
+            serde_json::json!({
+                "jti": "ghi",
+                "htm": "POST",
+                "htu": "https://example.com/oauth2/token",
+                "iat": now.timestamp(),
+                "nonce": nonce,
+            }),
+        );
+        let res = DpopProof::verify(&proof, &Method::POST, &uri, now, None, &store, true);
+        assert!(matches!(res, Err(DpopError::UseNonce)));
+
+        // Not a DPoP proof
+        let signer = key
+            .signing_key_for_alg(&JsonWebSignatureAlg::Es256)
+            .unwrap();
+        let header = JsonWebSignatureHeader::new(JsonWebSignatureAlg::Es256)
+            .with_jwk(JsonWebKey::new(JsonWebKeyPublicParameters::from(&key)));
+        let proof = Jwt::sign_with_rng(&mut rng, header, serde_json::json!({}), &signer)
+            .unwrap()
+            .into_string();
+        let res = DpopProof::verify(&proof, &Method::POST, &uri, now, None, &store, false);
+        assert!(matches!(res, Err(DpopError::InvalidType)));
+    }
+
+    #[test]
+    fn test_nonce_store() {
+        let mut rng = rand_chacha::ChaChaRng::seed_from_u64(42);
+        let now = DateTime::<Utc>::UNIX_EPOCH;
+        let store = InMemoryDpopNonceStore::new(2, Duration::microseconds(60 * 1000 * 1000));
+
+        let first = store.issue(&mut rng, now);
+        assert!(!store.consume("unknown", now));
+        assert!(store.consume(&first, now));
+
+        // Nonces can only be used once
+        assert!(!store.consume(&first, now));
+
+        // Nonces expire after their TTL
+        let second = store.issue(&mut rng, now);
+        assert!(!store.consume(&second, now + Duration::microseconds(61 * 1000 * 1000)));
+
+        // The oldest nonce is evicted once the store is full
+        let first = store.issue(&mut rng, now);
+        let second = store.issue(&mut rng, now);
+        let third = store.issue(&mut rng, now);
+        assert!(!store.consume(&first, now));
+        assert!(store.consume(&second, now));
+        assert!(store.consume(&third, now));
+    }
+
+    #[test]
+    fn test_proof_replay() {
+        let now = DateTime::<Utc>::UNIX_EPOCH;
+        let store = InMemoryDpopNonceStore::default();
+        let expires_at = now + Duration::microseconds(60 * 1000 * 1000);
+
+        assert!(store.record_proof("key", "abc", expires_at, now));
+        assert!(!store.record_proof("key", "abc", expires_at, now));
+
+        // Proof identifiers are scoped to the key which signed them
+        assert!(store.record_proof("other", "abc", expires_at, now));
+
+        // They are forgotten once the proof would be rejected anyway
+        let later = expires_at + Duration::microseconds(1000 * 1000);
+        assert!(store.record_proof(
+            "key",
+            "abc",
+            later + Duration::microseconds(60 * 1000 * 1000),
+            later
+        ));
+    }
+}
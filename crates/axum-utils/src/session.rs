@@ -12,8 +12,9 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use chrono::{DateTime, Utc};
 use mas_data_model::BrowserSession;
-use mas_storage::{user::BrowserSessionRepository, RepositoryAccess};
+use mas_storage::{user::BrowserSessionRepository, Clock, RepositoryAccess};
 use serde::{Deserialize, Serialize};
 use ulid::Ulid;
 
@@ -23,14 +24,26 @@ use crate::cookies::CookieJar;
 #[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct SessionInfo {
     current: Option<Ulid>,
+
+    /// When the session expires
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expires_at: Option<DateTime<Utc>>,
+
+    /// Whether the user asked to be remembered, in which case the cookie
+    /// outlives the browser until the session expires
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    remembered: bool,
 }
 
 impl SessionInfo {
-    /// Forge the cookie from a [`BrowserSession`]
+    /// Forge the cookie from a [`BrowserSession`], and whether the user asked
+    /// to be remembered
     #[must_use]
-    pub fn from_session(session: &BrowserSession) -> Self {
+    pub fn from_session(session: &BrowserSession, remembered: bool) -> Self {
         Self {
             current: Some(session.id),
+            expires_at: session.expires_at,
+            remembered,
         }
     }
 
@@ -38,6 +51,8 @@ impl SessionInfo {
     #[must_use]
     pub fn mark_session_ended(mut self) -> Self {
         self.current = None;
+        self.expires_at = None;
+        self.remembered = false;
         self
     }
 
@@ -49,6 +64,7 @@ impl SessionInfo {
     /// active anymore
     pub async fn load_session<E>(
         &self,
+        clock: &dyn Clock,
         repo: &mut impl RepositoryAccess<Error = E>,
     ) -> Result<Option<BrowserSession>, E> {
         let Some(session_id) = self.current else {
@@ -57,7 +73,7 @@ impl SessionInfo {
 
         let maybe_session = repo
             .browser_session()
-            .lookup(session_id)
+            .lookup(clock, session_id)
             .await?
             // Ensure that the session is still active
             .filter(BrowserSession::active);
@@ -68,23 +84,23 @@ impl SessionInfo {
 
 pub trait SessionInfoExt {
     #[must_use]
-    fn session_info(self) -> (SessionInfo, Self);
+    fn session_info<C: Clock>(self, clock: &C) -> (SessionInfo, Self);
 
     #[must_use]
-    fn update_session_info(self, info: &SessionInfo) -> Self;
+    fn update_session_info<C: Clock>(self, clock: &C, info: &SessionInfo) -> Self;
 
     #[must_use]
-    fn set_session(self, session: &BrowserSession) -> Self
+    fn set_session<C: Clock>(self, clock: &C, session: &BrowserSession, remembered: bool) -> Self
     where
         Self: Sized,
     {
-        let session_info = SessionInfo::from_session(session);
-        self.update_session_info(&session_info)
+        let session_info = SessionInfo::from_session(session, remembered);
+        self.update_session_info(clock, &session_info)
     }
 }
 
 impl SessionInfoExt for CookieJar {
-    fn session_info(self) -> (SessionInfo, Self) {
+    fn session_info<C: Clock>(self, clock: &C) -> (SessionInfo, Self) {
         let info = match self.load("session") {
             Ok(Some(s)) => s,
             Ok(None) => SessionInfo::default(),
@@ -94,11 +110,18 @@ impl SessionInfoExt for CookieJar {
             }
         };
 
-        let jar = self.update_session_info(&info);
+        let jar = self.update_session_info(clock, &info);
         (info, jar)
     }
 
-    fn update_session_info(self, info: &SessionInfo) -> Self {
-        self.save("session", info, true)
+    fn update_session_info<C: Clock>(self, clock: &C, info: &SessionInfo) -> Self {
+        // Unless the user asked to be remembered, the cookie is discarded when
+        // the browser is closed
+        match info.expires_at {
+            Some(expires_at) if info.remembered => {
+                self.save_until("session", info, expires_at, clock.now())
+            }
+            _ => self.save("session", info, false),
+        }
     }
 }
@@ -12,14 +12,14 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::{collections::HashMap, error::Error};
+use std::{borrow::Cow, collections::HashMap, error::Error};
 
 use async_trait::async_trait;
 use axum::{
     body::HttpBody,
     extract::{
         rejection::{FailedToDeserializeForm, FormRejection, TypedHeaderRejectionReason},
-        Form, FromRequest, FromRequestParts, TypedHeader,
+        Form, FromRef, FromRequest, FromRequestParts, TypedHeader,
     },
     response::{IntoResponse, Response},
     BoxError,
@@ -27,12 +27,18 @@ use axum::{
 use headers::{authorization::Bearer, Authorization, Header, HeaderMapExt, HeaderName};
 use http::{header::WWW_AUTHENTICATE, HeaderMap, HeaderValue, Request, StatusCode};
 use mas_data_model::Session;
+use mas_keystore::Keystore;
+use mas_router::UrlBuilder;
 use mas_storage::{
     oauth2::{OAuth2AccessTokenRepository, OAuth2SessionRepository},
     Clock, RepositoryAccess,
 };
+use oauth2_types::scope::Scope;
 use serde::{de::DeserializeOwned, Deserialize};
 use thiserror::Error;
+use url::Url;
+
+use crate::dpop::{DpopError, DpopNonceStore, DpopRequest};
 
 #[derive(Debug, Deserialize)]
 struct AuthorizedForm<F> {
@@ -50,19 +56,80 @@ enum AccessToken {
     None,
 }
 
+/// Find an access token presented by a client, either as an opaque access
+/// token or as a JWT access token signed with one of the keys of the keystore
+///
+/// JWT access tokens are looked up by the ID in their `jti` claim, once their
+/// type, signature, issuer and expiry are checked.
+///
+/// # Errors
+///
+/// Returns an error if the repository fails
+pub async fn find_access_token<E>(
+    repo: &mut impl RepositoryAccess<Error = E>,
+    clock: &impl Clock,
+    key_store: &Keystore,
+    issuer: &Url,
+    token: &str,
+) -> Result<Option<mas_data_model::AccessToken>, E> {
+    match mas_data_model::AccessToken::id_from_jwt(
+        token,
+        &key_store.public_jwks(),
+        issuer,
+        clock.now(),
+    ) {
+        Ok(Some(id)) => repo.oauth2_access_token().lookup(id).await,
+        Ok(None) => repo.oauth2_access_token().find_by_token(token).await,
+        Err(_) => Ok(None),
+    }
+}
+
+/// Get the opaque token under which a token presented by a client is stored
+///
+/// This is the token itself, unless it is a JWT access token, in which case
+/// it is the opaque access token it was issued for, found like
+/// [`find_access_token`] does. Returns `None` if the JWT access token is
+/// invalid or was issued for an unknown access token.
+///
+/// # Errors
+///
+/// Returns an error if the repository fails
+pub async fn stored_token<'a, E>(
+    repo: &mut impl RepositoryAccess<Error = E>,
+    clock: &impl Clock,
+    key_store: &Keystore,
+    issuer: &Url,
+    token: &'a str,
+) -> Result<Option<Cow<'a, str>>, E> {
+    match mas_data_model::AccessToken::id_from_jwt(
+        token,
+        &key_store.public_jwks(),
+        issuer,
+        clock.now(),
+    ) {
+        Ok(Some(id)) => {
+            let access_token = repo.oauth2_access_token().lookup(id).await?;
+            Ok(access_token.map(|access_token| Cow::Owned(access_token.access_token)))
+        }
+        Ok(None) => Ok(Some(Cow::Borrowed(token))),
+        Err(_) => Ok(None),
+    }
+}
+
 impl AccessToken {
     async fn fetch<E>(
         &self,
         repo: &mut impl RepositoryAccess<Error = E>,
+        clock: &impl Clock,
+        key_store: &Keystore,
+        issuer: &Url,
     ) -> Result<(mas_data_model::AccessToken, Session), AuthorizationVerificationError<E>> {
         let token = match self {
             AccessToken::Form(t) | AccessToken::Header(t) => t,
             AccessToken::None => return Err(AuthorizationVerificationError::MissingToken),
         };
 
-        let token = repo
-            .oauth2_access_token()
-            .find_by_token(token.as_str())
+        let token = find_access_token(repo, clock, key_store, issuer, token)
             .await?
             .ok_or(AuthorizationVerificationError::InvalidToken)?;
 
@@ -79,54 +146,100 @@ impl AccessToken {
 #[derive(Debug)]
 pub struct UserAuthorization<F = ()> {
     access_token: AccessToken,
+    dpop: DpopRequest,
     form: Option<F>,
 }
 
 impl<F: Send> UserAuthorization<F> {
     // TODO: take scopes to validate as parameter
-    /// Verify a user authorization and return the session and the protected
-    /// form value
+    /// Verify a user authorization and return the session, the scope granted
+    /// by the access token and the protected form value
+    ///
+    /// `issuer` is the issuer of JWT access tokens, and `resource` is the URL
+    /// of the protected resource: tokens restricted to other resources are
+    /// rejected.
     ///
     /// # Errors
     ///
-    /// Returns an error if the token is invalid, if the user session ended or
-    /// if the form is missing
+    /// Returns an error if the token is invalid or restricted to other
+    /// resources, if the user session ended, if the token is bound to a `DPoP`
+    /// key the request doesn't prove possession of, or if the form is missing
     pub async fn protected_form<E>(
         self,
         repo: &mut impl RepositoryAccess<Error = E>,
         clock: &impl Clock,
-    ) -> Result<(Session, F), AuthorizationVerificationError<E>> {
+        key_store: &Keystore,
+        dpop_nonce_store: &dyn DpopNonceStore,
+        issuer: &Url,
+        resource: &Url,
+    ) -> Result<(Session, Scope, F), AuthorizationVerificationError<E>> {
         let Some(form) = self.form else {
             return Err(AuthorizationVerificationError::MissingForm);
         };
 
-        let (token, session) = self.access_token.fetch(repo).await?;
+        let (token, session) = self
+            .access_token
+            .fetch(repo, clock, key_store, issuer)
+            .await?;
 
-        if !token.is_valid(clock.now()) || !session.is_valid() {
+        if !token.is_valid(clock.now())
+            || !session.is_valid()
+            || !token.allows_resource(&session.resource, resource)
+        {
             return Err(AuthorizationVerificationError::InvalidToken);
         }
 
-        Ok((session, form))
+        self.dpop
+            .verify_binding(&token, clock.now(), dpop_nonce_store)
+            .map_err(AuthorizationVerificationError::InvalidDpopProof)?;
+
+        let scope = token.scope(&session.scope).clone();
+
+        Ok((session, scope, form))
     }
 
     // TODO: take scopes to validate as parameter
-    /// Verify a user authorization and return the session
+    /// Verify a user authorization and return the session, along with the
+    /// scope granted by the access token, which may be narrower than the scope
+    /// of the session
+    ///
+    /// `issuer` is the issuer of JWT access tokens, and `resource` is the URL
+    /// of the protected resource: tokens restricted to other resources are
+    /// rejected.
     ///
     /// # Errors
     ///
-    /// Returns an error if the token is invalid or if the user session ended
+    /// Returns an error if the token is invalid or restricted to other
+    /// resources, if the user session ended or if the token is bound to a
+    /// `DPoP` key the request doesn't prove possession of
     pub async fn protected<E>(
         self,
         repo: &mut impl RepositoryAccess<Error = E>,
         clock: &impl Clock,
-    ) -> Result<Session, AuthorizationVerificationError<E>> {
-        let (token, session) = self.access_token.fetch(repo).await?;
-
-        if !token.is_valid(clock.now()) || !session.is_valid() {
+        key_store: &Keystore,
+        dpop_nonce_store: &dyn DpopNonceStore,
+        issuer: &Url,
+        resource: &Url,
+    ) -> Result<(Session, Scope), AuthorizationVerificationError<E>> {
+        let (token, session) = self
+            .access_token
+            .fetch(repo, clock, key_store, issuer)
+            .await?;
+
+        if !token.is_valid(clock.now())
+            || !session.is_valid()
+            || !token.allows_resource(&session.resource, resource)
+        {
             return Err(AuthorizationVerificationError::InvalidToken);
         }
 
-        Ok(session)
+        self.dpop
+            .verify_binding(&token, clock.now(), dpop_nonce_store)
+            .map_err(AuthorizationVerificationError::InvalidDpopProof)?;
+
+        let scope = token.scope(&session.scope).clone();
+
+        Ok((session, scope))
     }
 }
 
@@ -148,6 +261,9 @@ pub enum AuthorizationVerificationError<E> {
     #[error("missing form")]
     MissingForm,
 
+    #[error("invalid DPoP proof")]
+    InvalidDpopProof(#[source] DpopError),
+
     #[error(transparent)]
     Internal(#[from] E),
 }
@@ -155,6 +271,7 @@ pub enum AuthorizationVerificationError<E> {
 enum BearerError {
     InvalidRequest,
     InvalidToken,
+    InvalidDpopProof,
     #[allow(dead_code)]
     InsufficientScope {
         scope: Option<HeaderValue>,
@@ -166,6 +283,7 @@ impl BearerError {
         match self {
             BearerError::InvalidRequest => HeaderValue::from_static("invalid_request"),
             BearerError::InvalidToken => HeaderValue::from_static("invalid_token"),
+            BearerError::InvalidDpopProof => HeaderValue::from_static("invalid_dpop_proof"),
             BearerError::InsufficientScope { .. } => HeaderValue::from_static("insufficient_scope"),
         }
     }
@@ -190,6 +308,7 @@ enum WwwAuthenticate {
         error: BearerError,
         error_description: Option<HeaderValue>,
     },
+    Dpop(BearerError),
 }
 
 impl Header for WwwAuthenticate {
@@ -230,6 +349,11 @@ impl Header for WwwAuthenticate {
 
                 ("Bearer", params)
             }
+            WwwAuthenticate::Dpop(error) => {
+                let mut params = error.params();
+                params.insert("error", error.error());
+                ("DPoP", params)
+            }
         };
 
         let params = params.into_iter().map(|(k, v)| format!(" {k}={v:?}"));
@@ -283,6 +407,12 @@ where
                 });
                 (StatusCode::BAD_REQUEST, headers).into_response()
             }
+            Self::InvalidDpopProof(_) => {
+                let mut headers = HeaderMap::new();
+
+                headers.typed_insert(WwwAuthenticate::Dpop(BearerError::InvalidDpopProof));
+                (StatusCode::UNAUTHORIZED, headers).into_response()
+            }
             Self::Internal(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
         }
     }
@@ -295,24 +425,34 @@ where
     B: HttpBody + Send + 'static,
     B::Data: Send,
     B::Error: Into<BoxError>,
+    UrlBuilder: FromRef<S>,
     S: Send + Sync,
 {
     type Rejection = UserAuthorizationError;
 
     async fn from_request(req: Request<B>, state: &S) -> Result<Self, Self::Rejection> {
         let (mut parts, body) = req.into_parts();
-        let header =
-            TypedHeader::<Authorization<Bearer>>::from_request_parts(&mut parts, state).await;
-
-        // Take the Authorization header
-        let token_from_header = match header {
-            Ok(header) => Some(header.token().to_owned()),
-            Err(err) => match err.reason() {
-                // If it's missing it is fine
-                TypedHeaderRejectionReason::Missing => None,
-                // If the header could not be parsed, return the error
-                _ => return Err(UserAuthorizationError::InvalidHeader),
-            },
+        let dpop = DpopRequest::from_request_parts(&mut parts, state)
+            .await
+            .unwrap_or_else(|e| match e {});
+
+        // Take the Authorization header, with either the `Bearer` or the `DPoP`
+        // scheme
+        let token_from_header = if let Some(token) = dpop.token() {
+            Some(token.to_owned())
+        } else {
+            let header =
+                TypedHeader::<Authorization<Bearer>>::from_request_parts(&mut parts, state).await;
+
+            match header {
+                Ok(header) => Some(header.token().to_owned()),
+                Err(err) => match err.reason() {
+                    // If it's missing it is fine
+                    TypedHeaderRejectionReason::Missing => None,
+                    // If the header could not be parsed, return the error
+                    _ => return Err(UserAuthorizationError::InvalidHeader),
+                },
+            }
         };
 
         let req = Request::from_parts(parts, body);
@@ -339,6 +479,10 @@ where
             (None, None) => AccessToken::None,
         };
 
-        Ok(UserAuthorization { access_token, form })
+        Ok(UserAuthorization {
+            access_token,
+            dpop,
+            form,
+        })
     }
 }
@@ -12,7 +12,15 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::{convert::Infallible, net::IpAddr, sync::Arc, time::Instant};
+use std::{
+    convert::Infallible,
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
 
 use axum::{
     async_trait,
@@ -21,8 +29,9 @@ use axum::{
 use ipnetwork::IpNetwork;
 use mas_data_model::SiteConfig;
 use mas_handlers::{
-    passwords::PasswordManager, ActivityTracker, BoundActivityTracker, CookieManager, ErrorWrapper,
-    HttpClientFactory, MetadataCache,
+    passwords::PasswordManager, ActivityTracker, BoundActivityTracker, CookieManager,
+    DpopNonceStore, ErrorWrapper, HttpClientFactory, JarVerifier, JwksCache, LoginThrottle,
+    MetadataCache, PwnedPasswords, RateLimiter,
 };
 use mas_i18n::Translator;
 use mas_keystore::{Encrypter, Keystore};
@@ -30,7 +39,9 @@ use mas_matrix::BoxHomeserverConnection;
 use mas_matrix_synapse::SynapseConnection;
 use mas_policy::{Policy, PolicyFactory};
 use mas_router::UrlBuilder;
-use mas_storage::{BoxClock, BoxRepository, BoxRng, Repository, SystemClock};
+use mas_storage::{
+    user::BrowserSessionFilter, BoxClock, BoxRepository, BoxRng, Clock, Repository, SystemClock,
+};
 use mas_storage_pg::PgRepository;
 use mas_templates::Templates;
 use opentelemetry::{
@@ -53,9 +64,15 @@ pub struct AppState {
     pub graphql_schema: mas_graphql::Schema,
     pub http_client_factory: HttpClientFactory,
     pub password_manager: PasswordManager,
+    pub login_throttle: Arc<dyn LoginThrottle>,
+    pub pwned_passwords: Arc<dyn PwnedPasswords>,
+    pub rate_limiter: Arc<dyn RateLimiter>,
     pub metadata_cache: MetadataCache,
+    pub jwks_cache: JwksCache,
     pub site_config: SiteConfig,
     pub activity_tracker: ActivityTracker,
+    pub dpop_nonce_store: Arc<dyn DpopNonceStore>,
+    pub jar_verifier: JarVerifier,
     pub trusted_proxies: Vec<IpNetwork>,
     pub conn_acquisition_histogram: Option<Histogram<u64>>,
 }
@@ -105,6 +122,41 @@ impl AppState {
             .init();
         self.conn_acquisition_histogram = Some(histogram);
 
+        // The observable callbacks can't query the database, so the number of
+        // active browser sessions is counted regularly in the background
+        let active_browser_sessions = Arc::new(AtomicU64::new(0));
+        {
+            let pool = self.pool.clone();
+            let active_browser_sessions = Arc::clone(&active_browser_sessions);
+            tokio::spawn(async move {
+                loop {
+                    match count_active_browser_sessions(&pool).await {
+                        Ok(count) => active_browser_sessions.store(count, Ordering::Relaxed),
+                        Err(e) => tracing::error!(
+                            error = &*e as &dyn std::error::Error,
+                            "Failed to count the active browser sessions"
+                        ),
+                    }
+
+                    tokio::time::sleep(Duration::from_secs(60)).await;
+                }
+            });
+        }
+
+        let browser_sessions = meter
+            .u64_observable_gauge("mas.browser_sessions.active")
+            .with_description("The number of browser sessions which are currently active.")
+            .with_unit(Unit::new("{session}"))
+            .init();
+
+        meter.register_callback(&[browser_sessions.as_any()], move |observer| {
+            observer.observe_u64(
+                &browser_sessions,
+                active_browser_sessions.load(Ordering::Relaxed),
+                &[],
+            );
+        })?;
+
         Ok(())
     }
 
@@ -130,6 +182,7 @@ impl AppState {
         self.metadata_cache
             .warm_up_and_run(
                 http_service,
+                SystemClock::default(),
                 std::time::Duration::from_secs(60 * 15),
                 &mut repo,
             )
@@ -138,6 +191,21 @@ impl AppState {
     }
 }
 
+/// Count the browser sessions which are currently active
+async fn count_active_browser_sessions(
+    pool: &PgPool,
+) -> Result<u64, Box<dyn std::error::Error + Send + Sync>> {
+    let now = SystemClock::default().now();
+    let mut repo = PgRepository::from_pool(pool).await?.boxed();
+    let count = repo
+        .browser_session()
+        .count(BrowserSessionFilter::new().active_only(now))
+        .await?;
+    repo.cancel().await?;
+
+    Ok(u64::try_from(count)?)
+}
+
 impl FromRef<AppState> for PgPool {
     fn from_ref(input: &AppState) -> Self {
         input.pool.clone()
@@ -192,6 +260,24 @@ impl FromRef<AppState> for PasswordManager {
     }
 }
 
+impl FromRef<AppState> for Arc<dyn LoginThrottle> {
+    fn from_ref(input: &AppState) -> Self {
+        Arc::clone(&input.login_throttle)
+    }
+}
+
+impl FromRef<AppState> for Arc<dyn PwnedPasswords> {
+    fn from_ref(input: &AppState) -> Self {
+        Arc::clone(&input.pwned_passwords)
+    }
+}
+
+impl FromRef<AppState> for Arc<dyn RateLimiter> {
+    fn from_ref(input: &AppState) -> Self {
+        Arc::clone(&input.rate_limiter)
+    }
+}
+
 impl FromRef<AppState> for CookieManager {
     fn from_ref(input: &AppState) -> Self {
         input.cookie_manager.clone()
@@ -204,6 +290,24 @@ impl FromRef<AppState> for MetadataCache {
     }
 }
 
+impl FromRef<AppState> for JwksCache {
+    fn from_ref(input: &AppState) -> Self {
+        input.jwks_cache.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<dyn DpopNonceStore> {
+    fn from_ref(input: &AppState) -> Self {
+        Arc::clone(&input.dpop_nonce_store)
+    }
+}
+
+impl FromRef<AppState> for JarVerifier {
+    fn from_ref(input: &AppState) -> Self {
+        input.jar_verifier.clone()
+    }
+}
+
 impl FromRef<AppState> for SiteConfig {
     fn from_ref(input: &AppState) -> Self {
         input.site_config.clone()
@@ -116,6 +116,11 @@ impl Options {
                 let config = SyncConfig::extract(figment)?;
                 let clock = SystemClock::default();
                 let encrypter = config.secrets.encrypter();
+                let key_store = config
+                    .secrets
+                    .key_store()
+                    .await
+                    .context("could not import keys from config")?;
 
                 // Grab a connection to the database
                 let mut conn = database_connection_from_config(&config.database).await?;
@@ -131,6 +136,7 @@ impl Options {
                     config.clients,
                     &mut conn,
                     &encrypter,
+                    &key_store,
                     &clock,
                     prune,
                     dry_run,
@@ -19,7 +19,9 @@ use clap::{ArgAction, CommandFactory, Parser};
 use console::{pad_str, style, Alignment, Style, Term};
 use dialoguer::{theme::ColorfulTheme, Confirm, FuzzySelect, Input, Password};
 use figment::Figment;
-use mas_config::{ConfigurationSection, DatabaseConfig, MatrixConfig, PasswordsConfig};
+use mas_config::{
+    ConfigurationSection, DatabaseConfig, MatrixConfig, PasswordsConfig, SecretsConfig,
+};
 use mas_data_model::{Device, TokenType, Ulid, UpstreamOAuthProvider, User};
 use mas_email::Address;
 use mas_handlers::HttpClientFactory;
@@ -36,7 +38,10 @@ use rand::{RngCore, SeedableRng};
 use sqlx::{types::Uuid, Acquire};
 use tracing::{info, info_span, warn};
 
-use crate::util::{database_connection_from_config, password_manager_from_config};
+use crate::{
+    sync::reencrypt_provider_secrets,
+    util::{database_connection_from_config, password_manager_from_config},
+};
 
 const USER_ATTRIBUTES_HEADING: &str = "User attributes";
 
@@ -88,6 +93,14 @@ enum Subcommand {
     /// Trigger a provisioning job for all users
     ProvisionAllUsers,
 
+    /// Re-encrypt the upstream OAuth 2.0 providers client secrets with the
+    /// current encryption secret, after it was rotated
+    ReencryptSecrets {
+        /// Do a dry run
+        #[arg(long)]
+        dry_run: bool,
+    },
+
     /// Kill all sessions for a user
     KillSessions {
         /// User for which to kill sessions
@@ -114,6 +127,17 @@ enum Subcommand {
         username: String,
     },
 
+    /// Make a user an administrator, who can manage other users from their
+    /// browser session
+    SetAdmin {
+        /// User to make an administrator
+        username: String,
+
+        /// Remove the administrator status instead
+        #[arg(long)]
+        remove: bool,
+    },
+
     /// Register a user
     ///
     /// This will interactively prompt for the user's attributes unless the
@@ -312,6 +336,20 @@ impl Options {
                 Ok(())
             }
 
+            SC::ReencryptSecrets { dry_run } => {
+                let _span = info_span!("cli.manage.reencrypt_secrets").entered();
+                let database_config = DatabaseConfig::extract(figment)?;
+                let secrets_config = SecretsConfig::extract(figment)?;
+                let encrypter = secrets_config.encrypter();
+                let mut conn = database_connection_from_config(&database_config).await?;
+
+                let count =
+                    reencrypt_provider_secrets(&mut conn, &encrypter, &encrypter, dry_run).await?;
+                info!("Re-encrypted the client secret of {count} providers");
+
+                Ok(())
+            }
+
             SC::KillSessions { username, dry_run } => {
                 let _span =
                     info_span!("cli.manage.kill_sessions", user.username = username).entered();
@@ -405,7 +443,7 @@ impl Options {
                     let id = id.into();
                     let browser_session = repo
                         .browser_session()
-                        .lookup(id)
+                        .lookup(&clock, id)
                         .await?
                         .context("Session not found")?;
                     info!(%browser_session.id, "Killing browser session");
@@ -486,6 +524,27 @@ impl Options {
                 Ok(())
             }
 
+            SC::SetAdmin { username, remove } => {
+                let _span = info_span!("cli.manage.set_admin", user.username = username).entered();
+                let config = DatabaseConfig::extract(figment)?;
+                let mut conn = database_connection_from_config(&config).await?;
+                let txn = conn.begin().await?;
+                let mut repo = PgRepository::from_conn(txn);
+
+                let user = repo
+                    .user()
+                    .find_by_username(&username)
+                    .await?
+                    .context("User not found")?;
+
+                info!(%user.id, is_admin = !remove, "Setting the admin status of user");
+
+                repo.user().set_admin(user, !remove).await?;
+                repo.into_inner().commit().await?;
+
+                Ok(())
+            }
+
             SC::RegisterUser {
                 username,
                 password,
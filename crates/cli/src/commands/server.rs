@@ -19,7 +19,10 @@ use clap::Parser;
 use figment::Figment;
 use itertools::Itertools;
 use mas_config::{AppConfig, ClientsConfig, ConfigurationSection, UpstreamOAuth2Config};
-use mas_handlers::{ActivityTracker, CookieManager, HttpClientFactory, MetadataCache};
+use mas_handlers::{
+    ActivityTracker, CookieManager, HttpClientFactory, InMemoryDpopNonceStore, InMemoryRateLimiter,
+    JarVerifier, JwksCache, MetadataCache,
+};
 use mas_listener::{server::Server, shutdown::ShutdownStream};
 use mas_matrix_synapse::SynapseConnection;
 use mas_router::UrlBuilder;
@@ -36,9 +39,9 @@ use tracing::{info, info_span, warn, Instrument};
 use crate::{
     app_state::AppState,
     util::{
-        database_pool_from_config, mailer_from_config, password_manager_from_config,
-        policy_factory_from_config, register_sighup, site_config_from_config,
-        templates_from_config,
+        database_pool_from_config, login_throttle_from_config, mailer_from_config,
+        password_manager_from_config, policy_factory_from_config, pwned_passwords_from_config,
+        register_sighup, site_config_from_config, templates_from_config,
     },
 };
 
@@ -98,6 +101,13 @@ impl Options {
 
         let encrypter = config.secrets.encrypter();
 
+        // Initialize the key store
+        let key_store = config
+            .secrets
+            .key_store()
+            .await
+            .context("could not import keys from config")?;
+
         if self.no_sync {
             info!("Skipping configuration sync");
         } else {
@@ -111,6 +121,7 @@ impl Options {
                 clients_config,
                 &mut conn,
                 &encrypter,
+                &key_store,
                 &SystemClock::default(),
                 false,
                 false,
@@ -118,15 +129,12 @@ impl Options {
             .await?;
         }
 
-        // Initialize the key store
-        let key_store = config
-            .secrets
-            .key_store()
-            .await
-            .context("could not import keys from config")?;
-
-        let cookie_manager =
-            CookieManager::derive_from(config.http.public_base.clone(), &config.secrets.encryption);
+        // Cookies set before an encryption key rotation can still be decrypted
+        // with the previous keys
+        let cookie_manager = config.secrets.previous_encryption.iter().fold(
+            CookieManager::derive_from(config.http.public_base.clone(), &config.secrets.encryption),
+            |cookie_manager, key| cookie_manager.with_previous_key(key),
+        );
 
         // Load and compile the WASM policies (and fallback to the default embedded one)
         info!("Loading and compiling the policy module");
@@ -145,6 +153,8 @@ impl Options {
             &config.matrix,
             &config.experimental,
             &config.passwords,
+            &config.password_reset,
+            &config.upstream_oauth2,
         );
 
         // Load and compile the templates
@@ -169,9 +179,14 @@ impl Options {
             let worker_name = Alphanumeric.sample_string(&mut rng, 10);
 
             info!(worker_name, "Starting task worker");
-            let monitor =
-                mas_tasks::init(&worker_name, &pool, &mailer, homeserver_connection.clone())
-                    .await?;
+            let monitor = mas_tasks::init(
+                &worker_name,
+                &pool,
+                &mailer,
+                homeserver_connection.clone(),
+                &url_builder,
+            )
+            .await?;
             // TODO: grab the handle
             tokio::spawn(monitor.run());
         }
@@ -179,15 +194,39 @@ impl Options {
         let listeners_config = config.http.listeners.clone();
 
         let password_manager = password_manager_from_config(&config.passwords).await?;
+        let login_throttle = Arc::new(login_throttle_from_config(&config.passwords));
+        let pwned_passwords = Arc::new(pwned_passwords_from_config(
+            &config.passwords,
+            &http_client_factory,
+        ));
+        let rate_limiter = Arc::new(InMemoryRateLimiter::default());
 
         // The upstream OIDC metadata cache
-        let metadata_cache = MetadataCache::new();
+        let metadata_cache = MetadataCache::new(
+            config.experimental.upstream_discovery_cache_min_ttl,
+            config.experimental.upstream_discovery_cache_max_ttl,
+        );
+
+        // The upstream OIDC JWKS cache
+        let jwks_cache = JwksCache::new();
 
         // Initialize the activity tracker
-        // Activity is flushed every minute
-        let activity_tracker = ActivityTracker::new(pool.clone(), Duration::from_secs(60));
+        let activity_flush_interval = config
+            .experimental
+            .activity_flush_interval
+            .to_std()
+            .context("invalid activity flush interval")?;
+        let activity_tracker = ActivityTracker::new(pool.clone(), activity_flush_interval);
         let trusted_proxies = config.http.trusted_proxies.clone();
 
+        // The nonces issued for DPoP proofs
+        let dpop_nonce_store = Arc::new(InMemoryDpopNonceStore::default());
+
+        // The cache of client JWKS used to verify signed request objects
+        let jar_verifier = JarVerifier::new(config.experimental.client_jwks_cache_ttl);
+
+        let admin_scopes = config.experimental.admin_scopes.clone();
+
         // Explicitly the config to properly zeroize secret keys
         drop(config);
 
@@ -199,6 +238,12 @@ impl Options {
             &policy_factory,
             homeserver_connection.clone(),
             site_config.clone(),
+            admin_scopes,
+            mas_handlers::BackchannelLogoutDispatcher::new(
+                http_client_factory.clone(),
+                key_store.clone(),
+                url_builder.clone(),
+            ),
         );
 
         let state = {
@@ -207,6 +252,7 @@ impl Options {
                 templates,
                 key_store,
                 metadata_cache,
+                jwks_cache,
                 cookie_manager,
                 encrypter,
                 url_builder,
@@ -215,8 +261,13 @@ impl Options {
                 graphql_schema,
                 http_client_factory,
                 password_manager,
+                login_throttle,
+                pwned_passwords,
+                rate_limiter,
                 site_config,
                 activity_tracker,
+                dpop_nonce_store,
+                jar_verifier,
                 trusted_proxies,
                 conn_acquisition_histogram: None,
             };
@@ -15,8 +15,8 @@
 use clap::Parser;
 use figment::Figment;
 use mas_config::{
-    BrandingConfig, ConfigurationSection, ExperimentalConfig, MatrixConfig, PasswordsConfig,
-    TemplatesConfig,
+    BrandingConfig, ConfigurationSection, ExperimentalConfig, MatrixConfig, PasswordResetConfig,
+    PasswordsConfig, TemplatesConfig, UpstreamOAuth2Config,
 };
 use mas_storage::{Clock, SystemClock};
 use rand::SeedableRng;
@@ -48,6 +48,8 @@ impl Options {
                 let matrix_config = MatrixConfig::extract(figment)?;
                 let experimental_config = ExperimentalConfig::extract(figment)?;
                 let password_config = PasswordsConfig::extract(figment)?;
+                let password_reset_config = PasswordResetConfig::extract(figment)?;
+                let upstream_oauth2_config = UpstreamOAuth2Config::extract(figment)?;
 
                 let clock = SystemClock::default();
                 // XXX: we should disallow SeedableRng::from_entropy
@@ -59,6 +61,8 @@ impl Options {
                     &matrix_config,
                     &experimental_config,
                     &password_config,
+                    &password_reset_config,
+                    &upstream_oauth2_config,
                 );
                 let templates =
                     templates_from_config(&template_config, &site_config, &url_builder).await?;
@@ -111,9 +111,13 @@ async fn try_main() -> anyhow::Result<()> {
             .with_filter(LevelFilter::INFO)
     });
 
+    // Record the duration of the database operations
+    let db_metrics_layer = telemetry::database_metrics_layer();
+
     let subscriber = Registry::default()
         .with(sentry_layer)
         .with(telemetry_layer)
+        .with(db_metrics_layer)
         .with(filter_layer)
         .with(fmt_layer);
     subscriber
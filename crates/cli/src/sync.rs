@@ -16,13 +16,17 @@
 
 use std::collections::{BTreeMap, BTreeSet};
 
+use anyhow::Context;
 use mas_config::{ClientsConfig, UpstreamOAuth2Config};
-use mas_keystore::Encrypter;
+use mas_iana::{jose::JsonWebSignatureAlg, oauth::OAuthClientAuthenticationMethod};
+use mas_keystore::{Encrypter, Keystore};
 use mas_storage::{
+    oauth2::UpsertStaticClientParams,
     upstream_oauth2::{UpstreamOAuthProviderFilter, UpstreamOAuthProviderParams},
     Clock, Pagination, RepositoryAccess,
 };
 use mas_storage_pg::PgRepository;
+use oauth2_types::scope::{Scope, ScopeToken};
 use sqlx::{postgres::PgAdvisoryLock, Connection, PgConnection};
 use tracing::{error, info, info_span, warn};
 
@@ -84,6 +88,7 @@ pub async fn config_sync(
     clients_config: ClientsConfig,
     connection: &mut PgConnection,
     encrypter: &Encrypter,
+    keystore: &Keystore,
     clock: &dyn Clock,
     prune: bool,
     dry_run: bool,
@@ -227,6 +232,22 @@ pub async fn config_sync(
                 }
             }
 
+            let token_endpoint_auth_method: OAuthClientAuthenticationMethod =
+                provider.token_endpoint_auth_method.into();
+            if token_endpoint_auth_method == OAuthClientAuthenticationMethod::PrivateKeyJwt {
+                // The client assertions are signed with the keys from the secrets section
+                let alg = provider
+                    .token_endpoint_auth_signing_alg
+                    .clone()
+                    .unwrap_or(JsonWebSignatureAlg::Rs256);
+                if keystore.signing_key_for_algorithm(&alg).is_none() {
+                    error!(
+                        %alg,
+                        "Provider uses private_key_jwt but no key in the secrets can sign with this algorithm"
+                    );
+                }
+            }
+
             let pkce_mode = match provider.pkce_method {
                 mas_config::UpstreamOAuth2PkceMethod::Auto => {
                     mas_data_model::UpstreamOAuthProviderPkceMode::Auto
@@ -248,7 +269,7 @@ pub async fn config_sync(
                         human_name: provider.human_name,
                         brand_name: provider.brand_name,
                         scope: provider.scope.parse()?,
-                        token_endpoint_auth_method: provider.token_endpoint_auth_method.into(),
+                        token_endpoint_auth_method,
                         token_endpoint_signing_alg: provider
                             .token_endpoint_auth_signing_alg
                             .clone(),
@@ -264,6 +285,7 @@ pub async fn config_sync(
                             .additional_authorization_parameters
                             .into_iter()
                             .collect(),
+                        forward_login_hint: provider.forward_login_hint,
                     },
                 )
                 .await?;
@@ -299,6 +321,17 @@ pub async fn config_sync(
             }
         }
 
+        // Clients used to be able to introspect tokens without any specific
+        // configuration, so deployments upgrading without setting this on the
+        // homeserver client would have it fail to validate any token
+        if !clients_config.is_empty()
+            && clients_config
+                .iter()
+                .all(|client| client.introspection_endpoint_auth_method().is_none())
+        {
+            warn!("No client is allowed to introspect tokens. The homeserver client needs `introspection_endpoint_auth_method` to be set to validate access tokens.");
+        }
+
         for client in clients_config {
             let _span = info_span!("client", client.id = %client.client_id).entered();
             if existing_ids.contains(&client.client_id) {
@@ -315,6 +348,33 @@ pub async fn config_sync(
             let client_auth_method = client.client_auth_method();
             let jwks = client.jwks.as_ref();
             let jwks_uri = client.jwks_uri.as_ref();
+            let introspection_endpoint_auth_method = client.introspection_endpoint_auth_method();
+            let redirect_uri_matching = match client.redirect_uri_matching {
+                mas_config::RedirectUriMatchingConfig::Exact => {
+                    mas_data_model::RedirectUriMatching::Exact
+                }
+                mas_config::RedirectUriMatchingConfig::Normalized => {
+                    mas_data_model::RedirectUriMatching::Normalized
+                }
+            };
+
+            let access_token_format = match client.access_token_format {
+                mas_config::AccessTokenFormatConfig::Opaque => {
+                    mas_data_model::AccessTokenFormat::Opaque
+                }
+                mas_config::AccessTokenFormatConfig::Jwt => mas_data_model::AccessTokenFormat::Jwt,
+            };
+
+            let client_credentials_scope = if client.allow_client_credentials {
+                let scope = client
+                    .client_credentials_scope
+                    .iter()
+                    .map(|s| s.parse::<ScopeToken>())
+                    .collect::<Result<Scope, _>>()?;
+                Some(scope)
+            } else {
+                None
+            };
 
             // TODO: should be moved somewhere else
             let encrypted_client_secret = client_secret
@@ -322,14 +382,30 @@ pub async fn config_sync(
                 .transpose()?;
 
             repo.oauth2_client()
-                .upsert_static(
-                    client.client_id,
+                .upsert_static(UpsertStaticClientParams {
+                    client_id: client.client_id,
                     client_auth_method,
                     encrypted_client_secret,
-                    jwks.cloned(),
-                    jwks_uri.cloned(),
-                    client.redirect_uris,
-                )
+                    jwks: jwks.cloned(),
+                    jwks_uri: jwks_uri.cloned(),
+                    redirect_uris: client.redirect_uris,
+                    redirect_uri_matching,
+                    response_types: client.response_types,
+                    allow_token_exchange: client.allow_token_exchange,
+                    allow_client_credentials: client.allow_client_credentials,
+                    client_credentials_scope,
+                    allowed_resources: client.allowed_resources,
+                    require_signed_request_object: client.require_signed_request_object,
+                    require_pkce: client.require_pkce,
+                    access_token_ttl: client.access_token_ttl,
+                    refresh_token_ttl: client.refresh_token_ttl,
+                    access_token_format,
+                    introspection_endpoint_auth_method,
+                    post_logout_redirect_uris: client.post_logout_redirect_uris,
+                    backchannel_logout_uri: client.backchannel_logout_uri,
+                    backchannel_logout_session_required: client.backchannel_logout_session_required,
+                    trusted: client.trusted,
+                })
                 .await?;
         }
     }
@@ -345,3 +421,191 @@ pub async fn config_sync(
     }
     Ok(())
 }
+
+/// Re-encrypt the client secrets of all the upstream OAuth 2.0 providers
+///
+/// Secrets are decrypted with `old_encrypter` and encrypted again with
+/// `new_encrypter`, all in a single transaction. Secrets which are already
+/// encrypted with the current key of `new_encrypter` are left untouched.
+///
+/// Returns the number of providers which had their secret re-encrypted.
+#[tracing::instrument(name = "config.reencrypt_provider_secrets", skip_all, err(Debug))]
+pub async fn reencrypt_provider_secrets(
+    connection: &mut PgConnection,
+    old_encrypter: &Encrypter,
+    new_encrypter: &Encrypter,
+    dry_run: bool,
+) -> anyhow::Result<usize> {
+    let txn = connection.begin().await?;
+    let mut repo = PgRepository::from_conn(txn);
+
+    // Let's assume we have less than 1000 providers
+    let page = repo
+        .upstream_oauth_provider()
+        .list(
+            UpstreamOAuthProviderFilter::default(),
+            Pagination::first(1000),
+        )
+        .await?;
+
+    if page.has_next_page {
+        warn!("More than 1000 providers in the database, only the first 1000 will be re-encrypted");
+    }
+
+    let mut count = 0;
+    for provider in page.edges {
+        let Some(encrypted_client_secret) = provider.encrypted_client_secret.as_deref() else {
+            continue;
+        };
+
+        if new_encrypter.is_current(encrypted_client_secret) {
+            continue;
+        }
+
+        info!(%provider.id, "Re-encrypting provider client secret");
+        let client_secret = old_encrypter
+            .decrypt_string(encrypted_client_secret)
+            .with_context(|| {
+                format!(
+                    "Could not decrypt the client secret of provider {}",
+                    provider.id
+                )
+            })?;
+        let encrypted_client_secret = new_encrypter.encrypt_to_string(&client_secret)?;
+
+        repo.upstream_oauth_provider()
+            .set_encrypted_client_secret(provider, Some(encrypted_client_secret))
+            .await?;
+        count += 1;
+    }
+
+    let txn = repo.into_inner();
+    if dry_run {
+        info!("Dry run, rolling back changes");
+        txn.rollback().await?;
+    } else {
+        txn.commit().await?;
+    }
+
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use mas_data_model::UpstreamOAuthProviderClaimsImports;
+    use mas_storage::{clock::MockClock, Repository};
+    use oauth2_types::scope::OPENID;
+    use rand::SeedableRng;
+    use sqlx::PgPool;
+
+    use super::*;
+
+    fn provider_params(encrypted_client_secret: Option<String>) -> UpstreamOAuthProviderParams {
+        UpstreamOAuthProviderParams {
+            issuer: "https://example.com/".to_owned(),
+            human_name: None,
+            brand_name: None,
+            scope: Scope::from_iter([OPENID]),
+            token_endpoint_auth_method: OAuthClientAuthenticationMethod::ClientSecretPost,
+            token_endpoint_signing_alg: None,
+            client_id: "client-id".to_owned(),
+            encrypted_client_secret,
+            claims_imports: UpstreamOAuthProviderClaimsImports::default(),
+            token_endpoint_override: None,
+            authorization_endpoint_override: None,
+            jwks_uri_override: None,
+            discovery_mode: mas_data_model::UpstreamOAuthProviderDiscoveryMode::Oidc,
+            pkce_mode: mas_data_model::UpstreamOAuthProviderPkceMode::Auto,
+            additional_authorization_parameters: Vec::new(),
+            forward_login_hint: false,
+        }
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_reencrypt_provider_secrets(pool: PgPool) {
+        let mut rng = rand_chacha::ChaChaRng::seed_from_u64(42);
+        let clock = MockClock::default();
+        let old_encrypter = Encrypter::new(&[0x42; 32]);
+        let new_encrypter = Encrypter::new(&[0x43; 32]).with_previous_key(&[0x42; 32]);
+
+        // Add a batch of providers with secrets encrypted with the old key, plus
+        // one without a secret
+        let mut repo = PgRepository::from_pool(&pool).await.unwrap().boxed();
+        let mut providers = Vec::new();
+        for i in 0..5 {
+            let secret = format!("secret-{i}");
+            let encrypted = old_encrypter.encrypt_to_string(secret.as_bytes()).unwrap();
+            let provider = repo
+                .upstream_oauth_provider()
+                .add(&mut rng, &clock, provider_params(Some(encrypted)))
+                .await
+                .unwrap();
+            providers.push((provider.id, secret));
+        }
+        let without_secret = repo
+            .upstream_oauth_provider()
+            .add(&mut rng, &clock, provider_params(None))
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        // During the migration window, both keys can decrypt the secrets
+        let mut conn = pool.acquire().await.unwrap();
+        let count = reencrypt_provider_secrets(&mut conn, &new_encrypter, &new_encrypter, true)
+            .await
+            .unwrap();
+        assert_eq!(count, 5);
+
+        // The dry run didn't change anything
+        let mut repo = PgRepository::from_pool(&pool).await.unwrap().boxed();
+        for (id, _) in &providers {
+            let provider = repo
+                .upstream_oauth_provider()
+                .lookup(*id)
+                .await
+                .unwrap()
+                .unwrap();
+            let encrypted = provider.encrypted_client_secret.unwrap();
+            assert!(!new_encrypter.is_current(&encrypted));
+        }
+        repo.cancel().await.unwrap();
+
+        let count = reencrypt_provider_secrets(&mut conn, &new_encrypter, &new_encrypter, false)
+            .await
+            .unwrap();
+        assert_eq!(count, 5);
+
+        // All the secrets are now encrypted with the new key only
+        let new_only = Encrypter::new(&[0x43; 32]);
+        let mut repo = PgRepository::from_pool(&pool).await.unwrap().boxed();
+        for (id, secret) in &providers {
+            let provider = repo
+                .upstream_oauth_provider()
+                .lookup(*id)
+                .await
+                .unwrap()
+                .unwrap();
+            let encrypted = provider.encrypted_client_secret.unwrap();
+            assert!(new_only.is_current(&encrypted));
+            assert_eq!(
+                new_only.decrypt_string(&encrypted).unwrap(),
+                secret.as_bytes()
+            );
+            assert!(old_encrypter.decrypt_string(&encrypted).is_err());
+        }
+        let provider = repo
+            .upstream_oauth_provider()
+            .lookup(without_secret.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(provider.encrypted_client_secret.is_none());
+        repo.cancel().await.unwrap();
+
+        // Running it again is a no-op
+        let count = reencrypt_provider_secrets(&mut conn, &new_encrypter, &new_encrypter, false)
+            .await
+            .unwrap();
+        assert_eq!(count, 0);
+    }
+}
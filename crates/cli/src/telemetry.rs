@@ -12,16 +12,21 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use anyhow::Context as _;
-use hyper::{header::CONTENT_TYPE, Body, Response};
+use headers::{authorization::Basic, Authorization, HeaderMapExt};
+use hyper::{
+    header::{CONTENT_TYPE, WWW_AUTHENTICATE},
+    Body, Request, Response,
+};
 use mas_config::{
-    MetricsConfig, MetricsExporterKind, Propagator, TelemetryConfig, TracingConfig,
-    TracingExporterKind,
+    MetricsConfig, MetricsExporterKind, PrometheusBasicAuthConfig, Propagator, TelemetryConfig,
+    TracingConfig, TracingExporterKind,
 };
 use opentelemetry::{
     global,
+    metrics::{Histogram, Unit},
     propagation::{TextMapCompositePropagator, TextMapPropagator},
     trace::TracerProvider as _,
     KeyValue,
@@ -40,11 +45,18 @@ use opentelemetry_sdk::{
 };
 use opentelemetry_semantic_conventions as semcov;
 use prometheus::Registry;
+use subtle::ConstantTimeEq;
 use tokio::sync::OnceCell;
+use tracing::{
+    span::{Attributes, Id},
+    Subscriber,
+};
+use tracing_subscriber::{layer::Context, registry::LookupSpan, Layer};
 use url::Url;
 
 static METER_PROVIDER: OnceCell<SdkMeterProvider> = OnceCell::const_new();
 static PROMETHEUS_REGISTRY: OnceCell<Registry> = OnceCell::const_new();
+static PROMETHEUS_BASIC_AUTH: OnceCell<PrometheusBasicAuthConfig> = OnceCell::const_new();
 
 pub fn setup(config: &TelemetryConfig) -> anyhow::Result<Option<Tracer>> {
     global::set_error_handler(|e| tracing::error!("{}", e))?;
@@ -163,10 +175,45 @@ fn stdout_metric_reader() -> PeriodicReader {
 
 type PromServiceFuture = std::future::Ready<Result<Response<Body>, std::convert::Infallible>>;
 
+/// Check the credentials sent to the Prometheus endpoint, if it requires
+/// HTTP basic authentication
+fn prometheus_authorized<B>(req: &Request<B>) -> bool {
+    let Some(basic_auth) = PROMETHEUS_BASIC_AUTH.get() else {
+        return true;
+    };
+
+    req.headers()
+        .typed_get::<Authorization<Basic>>()
+        .is_some_and(|Authorization(credentials)| {
+            // Compare both parts in constant time, so that the credentials can't be
+            // guessed by timing the responses
+            let username = credentials
+                .username()
+                .as_bytes()
+                .ct_eq(basic_auth.username.as_bytes());
+            let password = credentials
+                .password()
+                .as_bytes()
+                .ct_eq(basic_auth.password.as_bytes());
+            (username & password).into()
+        })
+}
+
 #[allow(clippy::needless_pass_by_value)]
-fn prometheus_service_fn<T>(_req: T) -> PromServiceFuture {
+fn prometheus_service_fn<B>(req: Request<B>) -> PromServiceFuture {
     use prometheus::{Encoder, TextEncoder};
 
+    if !prometheus_authorized(&req) {
+        let response = Response::builder()
+            .status(401)
+            .header(WWW_AUTHENTICATE, r#"Basic realm="metrics""#)
+            .header(CONTENT_TYPE, "text/plain")
+            .body(Body::from("Unauthorized"))
+            .unwrap();
+
+        return std::future::ready(Ok(response));
+    }
+
     let response = if let Some(registry) = PROMETHEUS_REGISTRY.get() {
         let mut buffer = vec![];
         let encoder = TextEncoder::new();
@@ -191,7 +238,7 @@ fn prometheus_service_fn<T>(_req: T) -> PromServiceFuture {
     std::future::ready(Ok(response))
 }
 
-pub fn prometheus_service<T>() -> tower::util::ServiceFn<fn(T) -> PromServiceFuture> {
+pub fn prometheus_service<B>() -> tower::util::ServiceFn<fn(Request<B>) -> PromServiceFuture> {
     if !PROMETHEUS_REGISTRY.initialized() {
         tracing::warn!("A Prometheus resource was mounted on a listener, but the Prometheus exporter was not setup in the config");
     }
@@ -199,10 +246,16 @@ pub fn prometheus_service<T>() -> tower::util::ServiceFn<fn(T) -> PromServiceFut
     tower::service_fn(prometheus_service_fn as _)
 }
 
-fn prometheus_metric_reader() -> anyhow::Result<PrometheusExporter> {
+fn prometheus_metric_reader(
+    basic_auth: Option<&PrometheusBasicAuthConfig>,
+) -> anyhow::Result<PrometheusExporter> {
     let registry = Registry::new();
     PROMETHEUS_REGISTRY.set(registry.clone())?;
 
+    if let Some(basic_auth) = basic_auth {
+        PROMETHEUS_BASIC_AUTH.set(basic_auth.clone())?;
+    }
+
     let exporter = opentelemetry_prometheus::exporter()
         .with_registry(registry)
         .without_scope_info()
@@ -219,9 +272,8 @@ fn init_meter(config: &MetricsConfig) -> anyhow::Result<()> {
         MetricsExporterKind::Otlp => {
             meter_provider_builder.with_reader(otlp_metric_reader(config.endpoint.as_ref())?)
         }
-        MetricsExporterKind::Prometheus => {
-            meter_provider_builder.with_reader(prometheus_metric_reader()?)
-        }
+        MetricsExporterKind::Prometheus => meter_provider_builder
+            .with_reader(prometheus_metric_reader(config.basic_auth.as_ref())?),
     };
 
     let meter_provider = meter_provider_builder.with_resource(resource()).build();
@@ -232,6 +284,65 @@ fn init_meter(config: &MetricsConfig) -> anyhow::Result<()> {
     Ok(())
 }
 
+/// The time at which a database span was entered
+struct SpanStart(Instant);
+
+/// A [`Layer`] recording the duration of the database operations in a
+/// histogram, using the `db.*` spans wrapping the queries
+pub struct DatabaseMetricsLayer {
+    histogram: Histogram<u64>,
+}
+
+/// Create the [`DatabaseMetricsLayer`]. This must be called after the meter
+/// provider was set up.
+pub fn database_metrics_layer() -> DatabaseMetricsLayer {
+    let meter = global::meter_with_version(
+        env!("CARGO_PKG_NAME"),
+        Some(env!("CARGO_PKG_VERSION")),
+        Some(semcov::SCHEMA_URL),
+        None,
+    );
+
+    let histogram = meter
+        .u64_histogram("db.client.operation.duration")
+        .with_description("The time it took to run a database operation.")
+        .with_unit(Unit::new("ms"))
+        .init();
+
+    DatabaseMetricsLayer { histogram }
+}
+
+impl<S> Layer<S> for DatabaseMetricsLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        if !attrs.metadata().name().starts_with("db.") {
+            return;
+        }
+
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(SpanStart(Instant::now()));
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else {
+            return;
+        };
+
+        let Some(SpanStart(start)) = span.extensions_mut().remove::<SpanStart>() else {
+            return;
+        };
+
+        let duration = u64::try_from(start.elapsed().as_millis()).unwrap_or(u64::MAX);
+        self.histogram.record(
+            duration,
+            &[KeyValue::new(semcov::trace::DB_OPERATION, span.name())],
+        );
+    }
+}
+
 fn trace_config() -> opentelemetry_sdk::trace::Config {
     opentelemetry_sdk::trace::config()
         .with_resource(resource())
@@ -16,12 +16,16 @@ use std::time::Duration;
 
 use anyhow::Context;
 use mas_config::{
-    BrandingConfig, DatabaseConfig, EmailConfig, EmailSmtpMode, EmailTransportKind,
-    ExperimentalConfig, MatrixConfig, PasswordsConfig, PolicyConfig, TemplatesConfig,
+    BrandingConfig, BrowserSessionLimitPolicy as BrowserSessionLimitPolicyConfig, DatabaseConfig,
+    EmailConfig, EmailSmtpMode, EmailTransportKind, ExperimentalConfig, MatrixConfig,
+    PasswordResetConfig, PasswordsConfig, PolicyConfig, TemplatesConfig, UpstreamOAuth2Config,
 };
-use mas_data_model::SiteConfig;
+use mas_data_model::{BrowserSessionLimitPolicy, ServiceAccount, SiteConfig};
 use mas_email::{MailTransport, Mailer};
-use mas_handlers::{passwords::PasswordManager, ActivityTracker};
+use mas_handlers::{
+    passwords::PasswordManager, ActivityTracker, HttpClientFactory, InMemoryLoginThrottle,
+    PwnedPasswordChecker,
+};
 use mas_policy::PolicyFactory;
 use mas_router::UrlBuilder;
 use mas_templates::{SiteConfigExt, TemplateLoadingError, Templates};
@@ -38,24 +42,56 @@ pub async fn password_manager_from_config(
         return Ok(PasswordManager::disabled());
     }
 
-    let schemes = config
-        .load()
-        .await?
-        .into_iter()
-        .map(|(version, algorithm, cost, secret)| {
+    let schemes = config.load().await?.into_iter().map(
+        |(version, algorithm, cost, argon2_params, secret)| {
             use mas_handlers::passwords::Hasher;
             let hasher = match algorithm {
                 mas_config::PasswordAlgorithm::Pbkdf2 => Hasher::pbkdf2(secret),
                 mas_config::PasswordAlgorithm::Bcrypt => Hasher::bcrypt(cost, secret),
-                mas_config::PasswordAlgorithm::Argon2id => Hasher::argon2id(secret),
+                mas_config::PasswordAlgorithm::Argon2id => Hasher::argon2id_with_params(
+                    argon2_params.memory_cost,
+                    argon2_params.time_cost,
+                    argon2_params.parallelism,
+                    secret,
+                ),
+                mas_config::PasswordAlgorithm::Argon2i => Hasher::argon2i_with_params(
+                    argon2_params.memory_cost,
+                    argon2_params.time_cost,
+                    argon2_params.parallelism,
+                    secret,
+                ),
+                mas_config::PasswordAlgorithm::Scrypt => Hasher::scrypt(secret),
             };
 
             (version, hasher)
-        });
+        },
+    );
 
     PasswordManager::new(schemes)
 }
 
+pub fn login_throttle_from_config(config: &PasswordsConfig) -> InMemoryLoginThrottle {
+    let config = config.login_throttle();
+    InMemoryLoginThrottle::new(
+        config.max_failures,
+        config.failure_window,
+        config.backoff,
+        config.lockout,
+    )
+}
+
+pub fn pwned_passwords_from_config(
+    config: &PasswordsConfig,
+    http_client_factory: &HttpClientFactory,
+) -> PwnedPasswordChecker {
+    let config = config.pwned_check();
+    if config.enabled {
+        PwnedPasswordChecker::new(http_client_factory.clone(), config.api_url.clone())
+    } else {
+        PwnedPasswordChecker::disabled()
+    }
+}
+
 pub fn mailer_from_config(
     config: &EmailConfig,
     templates: &Templates,
@@ -125,10 +161,20 @@ pub fn site_config_from_config(
     matrix_config: &MatrixConfig,
     experimental_config: &ExperimentalConfig,
     password_config: &PasswordsConfig,
+    password_reset_config: &PasswordResetConfig,
+    upstream_oauth2_config: &UpstreamOAuth2Config,
 ) -> SiteConfig {
     SiteConfig {
         access_token_ttl: experimental_config.access_token_ttl,
+        refresh_token_ttl: experimental_config.refresh_token_ttl,
         compat_token_ttl: experimental_config.compat_token_ttl,
+        authorization_code_ttl: experimental_config.authorization_code_ttl,
+        pushed_authorization_request_ttl: experimental_config.pushed_authorization_request_ttl,
+        discovery_cache_ttl: experimental_config.discovery_cache_ttl,
+        upstream_oauth2_session_max_age: upstream_oauth2_config.session_max_age,
+        upstream_oauth2_max_pending_sessions: upstream_oauth2_config.max_pending_sessions,
+        allowed_code_challenge_methods: experimental_config.allowed_code_challenge_methods.clone(),
+        disable_implicit_flow: experimental_config.disable_implicit_flow,
         server_name: matrix_config.homeserver.clone(),
         policy_uri: branding_config.policy_uri.clone(),
         tos_uri: branding_config.tos_uri.clone(),
@@ -140,6 +186,25 @@ pub fn site_config_from_config(
         displayname_change_allowed: experimental_config.displayname_change_allowed,
         password_change_allowed: password_config.enabled()
             && experimental_config.password_change_allowed,
+        password_reset_enabled: password_config.enabled()
+            && experimental_config.password_change_allowed
+            && password_reset_config.enabled,
+        password_reset_token_ttl: password_reset_config.token_ttl,
+        service_accounts: experimental_config
+            .service_accounts
+            .iter()
+            .map(|account| ServiceAccount {
+                name: account.name.clone(),
+                token_sha256: account.token_sha256,
+            })
+            .collect(),
+        max_active_browser_sessions: experimental_config.max_active_browser_sessions,
+        browser_session_limit_policy: match experimental_config.browser_session_limit_policy {
+            BrowserSessionLimitPolicyConfig::EvictOldest => BrowserSessionLimitPolicy::EvictOldest,
+            BrowserSessionLimitPolicyConfig::RejectNew => BrowserSessionLimitPolicy::RejectNew,
+        },
+        session_ttl: experimental_config.session_ttl,
+        remembered_session_ttl: experimental_config.remembered_session_ttl,
     }
 }
 
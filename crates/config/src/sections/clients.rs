@@ -14,15 +14,18 @@
 
 use std::ops::Deref;
 
+use chrono::Duration;
 use figment::Figment;
-use mas_iana::oauth::OAuthClientAuthenticationMethod;
+use mas_iana::oauth::{OAuthAuthorizationEndpointResponseType, OAuthClientAuthenticationMethod};
 use mas_jose::jwk::PublicJsonWebKeySet;
+use oauth2_types::registration::has_duplicate_query_params;
 use schemars::JsonSchema;
 use serde::{de::Error, Deserialize, Serialize};
+use serde_with::serde_as;
 use ulid::Ulid;
 use url::Url;
 
-use super::ConfigurationSection;
+use super::{experimental::validate_token_ttl, ConfigurationSection};
 
 #[derive(JsonSchema, Serialize, Deserialize, Clone, Debug)]
 #[serde(rename_all = "snake_case")]
@@ -73,7 +76,73 @@ impl std::fmt::Display for ClientAuthMethodConfig {
     }
 }
 
+impl From<ClientAuthMethodConfig> for OAuthClientAuthenticationMethod {
+    fn from(method: ClientAuthMethodConfig) -> Self {
+        match method {
+            ClientAuthMethodConfig::None => OAuthClientAuthenticationMethod::None,
+            ClientAuthMethodConfig::ClientSecretBasic => {
+                OAuthClientAuthenticationMethod::ClientSecretBasic
+            }
+            ClientAuthMethodConfig::ClientSecretPost => {
+                OAuthClientAuthenticationMethod::ClientSecretPost
+            }
+            ClientAuthMethodConfig::ClientSecretJwt => {
+                OAuthClientAuthenticationMethod::ClientSecretJwt
+            }
+            ClientAuthMethodConfig::PrivateKeyJwt => OAuthClientAuthenticationMethod::PrivateKeyJwt,
+        }
+    }
+}
+
+/// How the redirect URI given in requests is compared with the registered ones
+#[derive(JsonSchema, Serialize, Deserialize, Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RedirectUriMatchingConfig {
+    /// `exact`: the redirect URI must be exactly one of the registered URIs
+    #[default]
+    Exact,
+
+    /// `normalized`: trailing slashes and case differences are ignored
+    Normalized,
+}
+
+/// The format of the access tokens issued to a client
+#[derive(JsonSchema, Serialize, Deserialize, Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AccessTokenFormatConfig {
+    /// `opaque`: random strings, which resource servers have to introspect
+    #[default]
+    Opaque,
+
+    /// `jwt`: JWTs signed by the service, which resource servers can validate
+    /// on their own using the published JWKS
+    Jwt,
+}
+
+impl AccessTokenFormatConfig {
+    #[allow(clippy::trivially_copy_pass_by_ref)]
+    const fn is_default(&self) -> bool {
+        matches!(self, AccessTokenFormatConfig::Opaque)
+    }
+}
+
+fn default_response_types() -> Vec<OAuthAuthorizationEndpointResponseType> {
+    vec![OAuthAuthorizationEndpointResponseType::Code]
+}
+
+fn is_default_response_types(value: &[OAuthAuthorizationEndpointResponseType]) -> bool {
+    value == default_response_types().as_slice()
+}
+
+impl RedirectUriMatchingConfig {
+    #[allow(clippy::trivially_copy_pass_by_ref)]
+    const fn is_default(&self) -> bool {
+        matches!(self, RedirectUriMatchingConfig::Exact)
+    }
+}
+
 /// An OAuth 2.0 client configuration
+#[serde_as]
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct ClientConfig {
     /// The client ID
@@ -93,23 +162,235 @@ pub struct ClientConfig {
     pub client_secret: Option<String>,
 
     /// The JSON Web Key Set (JWKS) used by the `private_key_jwt` authentication
-    /// method. Mutually exclusive with `jwks_uri`
+    /// method and to verify signed request objects. Mutually exclusive with
+    /// `jwks_uri`
     #[serde(skip_serializing_if = "Option::is_none")]
     pub jwks: Option<PublicJsonWebKeySet>,
 
     /// The URL of the JSON Web Key Set (JWKS) used by the `private_key_jwt`
-    /// authentication method. Mutually exclusive with `jwks`
+    /// authentication method and to verify signed request objects. Mutually
+    /// exclusive with `jwks`
     #[serde(skip_serializing_if = "Option::is_none")]
     pub jwks_uri: Option<Url>,
 
     /// List of allowed redirect URIs
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub redirect_uris: Vec<Url>,
+
+    /// How the redirect URI given in requests is compared with the registered
+    /// ones.
+    ///
+    /// Defaults to `exact`, which requires the redirect URI to be exactly
+    /// equal to one of the registered URIs
+    #[serde(default, skip_serializing_if = "RedirectUriMatchingConfig::is_default")]
+    pub redirect_uri_matching: RedirectUriMatchingConfig,
+
+    /// List of response types this client is allowed to use in authorization
+    /// requests. Defaults to `["code"]`
+    #[serde(
+        default = "default_response_types",
+        skip_serializing_if = "is_default_response_types"
+    )]
+    pub response_types: Vec<OAuthAuthorizationEndpointResponseType>,
+
+    /// List of URIs the user can be redirected to after logging out, using
+    /// the `post_logout_redirect_uri` parameter of the end session endpoint
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub post_logout_redirect_uris: Vec<Url>,
+
+    /// URI on which this client is notified with a logout token when a
+    /// session it is part of ends, per OpenID Connect Back-Channel Logout
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backchannel_logout_uri: Option<Url>,
+
+    /// Whether this client requires the `sid` claim to be included in the
+    /// logout tokens it receives. Requires `backchannel_logout_uri` to be set
+    #[serde(default)]
+    pub backchannel_logout_session_required: bool,
+
+    /// Whether this client is trusted. Users are not asked for their consent
+    /// when a trusted client requests access on their behalf
+    #[serde(default)]
+    pub trusted: bool,
+
+    /// Whether this client is allowed to exchange access tokens it was issued
+    /// using the token exchange grant
+    #[serde(default)]
+    pub allow_token_exchange: bool,
+
+    /// Whether this client is allowed to get tokens without a user, using the
+    /// client credentials grant. The client must be confidential
+    #[serde(default)]
+    pub allow_client_credentials: bool,
+
+    /// List of scopes this client can request with the client credentials
+    /// grant. Requires `allow_client_credentials` to be set
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub client_credentials_scope: Vec<String>,
+
+    /// List of resources this client can request tokens for, using resource
+    /// indicators. Tokens restricted to a resource are only accepted by the
+    /// resource servers serving it.
+    ///
+    /// For resource servers, this lists the resources they serve
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_resources: Vec<Url>,
+
+    /// Whether this client must send its authorization requests as signed
+    /// request objects, using the `request` parameter. Requires either `jwks`
+    /// or `jwks_uri` to be set
+    #[serde(default)]
+    pub require_signed_request_object: bool,
+
+    /// Whether this client must use PKCE in authorization requests.
+    ///
+    /// Defaults to `true` for public clients, which have no secret, using the
+    /// `none` authentication method
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub require_pkce: Option<bool>,
+
+    /// Time-to-live of the access tokens issued to this client, in seconds.
+    ///
+    /// Defaults to the `experimental.access_token_ttl` option
+    #[schemars(with = "Option<u64>", range(min = 60, max = 86400))]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[serde_as(as = "Option<serde_with::DurationSeconds<i64>>")]
+    pub access_token_ttl: Option<Duration>,
+
+    /// Time-to-live of the refresh tokens issued to this client, in seconds.
+    ///
+    /// Defaults to the `experimental.refresh_token_ttl` option
+    #[schemars(with = "Option<u64>", range(min = 60, max = 86400))]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[serde_as(as = "Option<serde_with::DurationSeconds<i64>>")]
+    pub refresh_token_ttl: Option<Duration>,
+
+    /// Format of the access tokens issued to this client.
+    ///
+    /// JWT access tokens can be validated by resource servers without calling
+    /// the introspection endpoint, but they won't notice if the token was
+    /// revoked before it expired
+    #[serde(default, skip_serializing_if = "AccessTokenFormatConfig::is_default")]
+    pub access_token_format: AccessTokenFormatConfig,
+
+    /// Authentication method used by this client when calling the
+    /// introspection endpoint.
+    ///
+    /// Only clients with this set are considered resource servers, and are
+    /// allowed to introspect tokens. It uses the same credentials as the
+    /// `client_auth_method`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    introspection_endpoint_auth_method: Option<ClientAuthMethodConfig>,
 }
 
 impl ClientConfig {
     fn validate(&self) -> Result<(), figment::error::Error> {
         let auth_method = self.client_auth_method;
+
+        if self.response_types.is_empty() {
+            let error = figment::error::Error::custom("response_types must not be empty");
+            return Err(error.with_path("response_types"));
+        }
+
+        if self.require_signed_request_object {
+            if self.jwks.is_none() && self.jwks_uri.is_none() {
+                let error = figment::error::Error::custom(
+                    "jwks or jwks_uri is required for require_signed_request_object",
+                );
+                return Err(error.with_path("require_signed_request_object"));
+            }
+
+            if self.jwks.is_some() && self.jwks_uri.is_some() {
+                let error =
+                    figment::error::Error::custom("jwks and jwks_uri are mutually exclusive");
+                return Err(error.with_path("jwks"));
+            }
+        }
+
+        if self.allow_client_credentials && matches!(auth_method, ClientAuthMethodConfig::None) {
+            let error = figment::error::Error::custom(
+                "allow_client_credentials is not allowed with the none authentication method",
+            );
+            return Err(error.with_path("allow_client_credentials"));
+        }
+
+        if !self.client_credentials_scope.is_empty() && !self.allow_client_credentials {
+            let error = figment::error::Error::custom(
+                "allow_client_credentials is required for client_credentials_scope",
+            );
+            return Err(error.with_path("client_credentials_scope"));
+        }
+
+        if let Some(resource) = self
+            .allowed_resources
+            .iter()
+            .find(|r| r.fragment().is_some())
+        {
+            let error = figment::error::Error::custom(format!(
+                "resource {resource} must not have a fragment"
+            ));
+            return Err(error.with_path("allowed_resources"));
+        }
+
+        if let Some(redirect_uri) = self
+            .redirect_uris
+            .iter()
+            .find(|uri| has_duplicate_query_params(uri))
+        {
+            let error = figment::error::Error::custom(format!(
+                "redirect URI {redirect_uri} has duplicate query parameters"
+            ));
+            return Err(error.with_path("redirect_uris"));
+        }
+
+        if let Some(ttl) = self.access_token_ttl {
+            validate_token_ttl("access_token_ttl", ttl)?;
+        }
+
+        if let Some(ttl) = self.refresh_token_ttl {
+            validate_token_ttl("refresh_token_ttl", ttl)?;
+        }
+
+        if self.backchannel_logout_session_required && self.backchannel_logout_uri.is_none() {
+            let error = figment::error::Error::custom(
+                "backchannel_logout_uri is required for backchannel_logout_session_required",
+            );
+            return Err(error.with_path("backchannel_logout_session_required"));
+        }
+
+        match self.introspection_endpoint_auth_method {
+            None => {}
+
+            Some(ClientAuthMethodConfig::None) => {
+                let error = figment::error::Error::custom(
+                    "none is not allowed as introspection_endpoint_auth_method",
+                );
+                return Err(error.with_path("introspection_endpoint_auth_method"));
+            }
+
+            Some(ClientAuthMethodConfig::PrivateKeyJwt) => {
+                if self.jwks.is_none() && self.jwks_uri.is_none() {
+                    let error = figment::error::Error::custom(
+                        "jwks or jwks_uri is required for private_key_jwt",
+                    );
+                    return Err(error.with_path("introspection_endpoint_auth_method"));
+                }
+            }
+
+            Some(
+                method @ (ClientAuthMethodConfig::ClientSecretPost
+                | ClientAuthMethodConfig::ClientSecretBasic
+                | ClientAuthMethodConfig::ClientSecretJwt),
+            ) => {
+                if self.client_secret.is_none() {
+                    let error = figment::error::Error::custom(format!(
+                        "client_secret is required for {method}"
+                    ));
+                    return Err(error.with_path("introspection_endpoint_auth_method"));
+                }
+            }
+        }
+
         match self.client_auth_method {
             ClientAuthMethodConfig::PrivateKeyJwt => {
                 if self.jwks.is_none() && self.jwks_uri.is_none() {
@@ -143,14 +424,14 @@ impl ClientConfig {
                     return Err(error.with_path("client_auth_method"));
                 }
 
-                if self.jwks.is_some() {
+                if self.jwks.is_some() && !self.require_signed_request_object {
                     let error = figment::error::Error::custom(format!(
                         "jwks is not allowed with {auth_method}"
                     ));
                     return Err(error.with_path("jwks"));
                 }
 
-                if self.jwks_uri.is_some() {
+                if self.jwks_uri.is_some() && !self.require_signed_request_object {
                     let error = figment::error::Error::custom(format!(
                         "jwks_uri is not allowed with {auth_method}"
                     ));
@@ -166,14 +447,14 @@ impl ClientConfig {
                     return Err(error.with_path("client_secret"));
                 }
 
-                if self.jwks.is_some() {
+                if self.jwks.is_some() && !self.require_signed_request_object {
                     let error = figment::error::Error::custom(
                         "jwks is not allowed with none authentication method",
                     );
                     return Err(error);
                 }
 
-                if self.jwks_uri.is_some() {
+                if self.jwks_uri.is_some() && !self.require_signed_request_object {
                     let error = figment::error::Error::custom(
                         "jwks_uri is not allowed with none authentication method",
                     );
@@ -188,19 +469,14 @@ impl ClientConfig {
     /// Authentication method used for this client
     #[must_use]
     pub fn client_auth_method(&self) -> OAuthClientAuthenticationMethod {
-        match self.client_auth_method {
-            ClientAuthMethodConfig::None => OAuthClientAuthenticationMethod::None,
-            ClientAuthMethodConfig::ClientSecretBasic => {
-                OAuthClientAuthenticationMethod::ClientSecretBasic
-            }
-            ClientAuthMethodConfig::ClientSecretPost => {
-                OAuthClientAuthenticationMethod::ClientSecretPost
-            }
-            ClientAuthMethodConfig::ClientSecretJwt => {
-                OAuthClientAuthenticationMethod::ClientSecretJwt
-            }
-            ClientAuthMethodConfig::PrivateKeyJwt => OAuthClientAuthenticationMethod::PrivateKeyJwt,
-        }
+        self.client_auth_method.into()
+    }
+
+    /// Authentication method used by this client on the introspection
+    /// endpoint, if it is allowed to introspect tokens
+    #[must_use]
+    pub fn introspection_endpoint_auth_method(&self) -> Option<OAuthClientAuthenticationMethod> {
+        self.introspection_endpoint_auth_method.map(Into::into)
     }
 }
 
@@ -274,18 +550,39 @@ mod tests {
                       client_auth_method: none
                       redirect_uris:
                         - https://exemple.fr/callback
+                      post_logout_redirect_uris:
+                        - https://exemple.fr/logged-out
 
                     - client_id: 01GFWR32NCQ12B8Z0J8CPXRRB6
                       client_auth_method: client_secret_basic
                       client_secret: hello
+                      redirect_uri_matching: normalized
+                      response_types: [code, "code id_token", none]
+                      allow_token_exchange: true
+                      backchannel_logout_uri: https://exemple.fr/backchannel-logout
+                      backchannel_logout_session_required: true
+                      introspection_endpoint_auth_method: client_secret_post
+                      trusted: true
+                      allowed_resources:
+                        - https://matrix.exemple.fr/
 
                     - client_id: 01GFWR3WHR93Y5HK389H28VHZ9
                       client_auth_method: client_secret_post
                       client_secret: hello
+                      jwks_uri: https://exemple.fr/jwks.json
+                      require_signed_request_object: true
+                      allow_client_credentials: true
+                      client_credentials_scope:
+                        - urn:mas:admin
+                        - urn:synapse:admin:*
 
                     - client_id: 01GFWR43R2ZZ8HX9CVBNW9TJWG
                       client_auth_method: client_secret_jwt
                       client_secret: hello
+                      require_pkce: true
+                      access_token_ttl: 3600
+                      refresh_token_ttl: 86400
+                      access_token_format: jwt
 
                     - client_id: 01GFWR4BNFDCC4QDG6AMSP1VRR
                       client_auth_method: private_key_jwt
@@ -328,6 +625,97 @@ mod tests {
             );
             assert_eq!(config.0[1].redirect_uris, Vec::new());
 
+            assert_eq!(
+                config.0[0].post_logout_redirect_uris,
+                vec!["https://exemple.fr/logged-out".parse().unwrap()]
+            );
+            assert_eq!(config.0[1].post_logout_redirect_uris, Vec::new());
+
+            assert_eq!(config.0[0].backchannel_logout_uri, None);
+            assert!(!config.0[0].backchannel_logout_session_required);
+            assert_eq!(
+                config.0[1].backchannel_logout_uri,
+                Some("https://exemple.fr/backchannel-logout".parse().unwrap())
+            );
+            assert!(config.0[1].backchannel_logout_session_required);
+
+            assert!(!config.0[0].trusted);
+            assert!(config.0[1].trusted);
+
+            assert_eq!(
+                config.0[0].redirect_uri_matching,
+                RedirectUriMatchingConfig::Exact
+            );
+            assert_eq!(
+                config.0[1].redirect_uri_matching,
+                RedirectUriMatchingConfig::Normalized
+            );
+
+            assert_eq!(
+                config.0[0].response_types,
+                vec![OAuthAuthorizationEndpointResponseType::Code]
+            );
+            assert_eq!(
+                config.0[1].response_types,
+                vec![
+                    OAuthAuthorizationEndpointResponseType::Code,
+                    OAuthAuthorizationEndpointResponseType::CodeIdToken,
+                    OAuthAuthorizationEndpointResponseType::None,
+                ]
+            );
+
+            assert!(!config.0[0].allow_token_exchange);
+            assert!(config.0[1].allow_token_exchange);
+
+            assert!(!config.0[0].allow_client_credentials);
+            assert!(config.0[0].client_credentials_scope.is_empty());
+            assert!(config.0[2].allow_client_credentials);
+            assert_eq!(
+                config.0[2].client_credentials_scope,
+                vec!["urn:mas:admin".to_owned(), "urn:synapse:admin:*".to_owned()]
+            );
+
+            assert!(config.0[0].allowed_resources.is_empty());
+            assert_eq!(
+                config.0[1].allowed_resources,
+                vec![Url::parse("https://matrix.exemple.fr/").unwrap()]
+            );
+
+            assert!(!config.0[0].require_signed_request_object);
+            assert!(config.0[2].require_signed_request_object);
+
+            assert_eq!(config.0[0].introspection_endpoint_auth_method(), None);
+            assert_eq!(
+                config.0[1].introspection_endpoint_auth_method(),
+                Some(OAuthClientAuthenticationMethod::ClientSecretPost)
+            );
+
+            assert_eq!(config.0[0].require_pkce, None);
+            assert_eq!(config.0[3].require_pkce, Some(true));
+
+            assert_eq!(config.0[0].access_token_ttl, None);
+            assert_eq!(
+                config.0[3].access_token_ttl,
+                Some(Duration::try_hours(1).unwrap())
+            );
+            assert_eq!(
+                config.0[3].refresh_token_ttl,
+                Some(Duration::try_hours(24).unwrap())
+            );
+
+            assert_eq!(
+                config.0[0].access_token_format,
+                AccessTokenFormatConfig::Opaque
+            );
+            assert_eq!(
+                config.0[3].access_token_format,
+                AccessTokenFormatConfig::Jwt
+            );
+            assert_eq!(
+                config.0[2].jwks_uri,
+                Some("https://exemple.fr/jwks.json".parse().unwrap())
+            );
+
             Ok(())
         });
     }
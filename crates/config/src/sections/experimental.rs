@@ -13,12 +13,36 @@
 // limitations under the License.
 
 use chrono::Duration;
+use mas_iana::oauth::PkceCodeChallengeMethod;
 use schemars::JsonSchema;
-use serde::{Deserialize, Serialize};
+use serde::{de::Error, Deserialize, Serialize};
 use serde_with::serde_as;
 
 use crate::ConfigurationSection;
 
+/// Minimum time-to-live of access and refresh tokens
+const MIN_TOKEN_TTL: Duration = Duration::microseconds(60 * 1000 * 1000);
+
+/// Maximum time-to-live of access and refresh tokens
+const MAX_TOKEN_TTL: Duration = Duration::microseconds(24 * 60 * 60 * 1000 * 1000);
+
+/// Minimum interval between two writes of the recorded activity
+const MIN_ACTIVITY_FLUSH_INTERVAL: Duration = Duration::microseconds(1000 * 1000);
+
+/// Check that a token time-to-live is within the allowed bounds
+pub(crate) fn validate_token_ttl(name: &str, value: Duration) -> Result<(), figment::error::Error> {
+    if value < MIN_TOKEN_TTL || value > MAX_TOKEN_TTL {
+        let error = figment::error::Error::custom(format!(
+            "{name} must be between {min} and {max} seconds",
+            min = MIN_TOKEN_TTL.num_seconds(),
+            max = MAX_TOKEN_TTL.num_seconds(),
+        ));
+        return Err(error.with_path(name));
+    }
+
+    Ok(())
+}
+
 fn default_token_ttl() -> Duration {
     Duration::microseconds(5 * 60 * 1000 * 1000)
 }
@@ -27,6 +51,94 @@ fn is_default_token_ttl(value: &Duration) -> bool {
     *value == default_token_ttl()
 }
 
+fn default_authorization_code_ttl() -> Duration {
+    Duration::microseconds(10 * 60 * 1000 * 1000)
+}
+
+fn is_default_authorization_code_ttl(value: &Duration) -> bool {
+    *value == default_authorization_code_ttl()
+}
+
+fn default_pushed_authorization_request_ttl() -> Duration {
+    Duration::microseconds(90 * 1000 * 1000)
+}
+
+fn is_default_pushed_authorization_request_ttl(value: &Duration) -> bool {
+    *value == default_pushed_authorization_request_ttl()
+}
+
+fn default_client_jwks_cache_ttl() -> Duration {
+    Duration::microseconds(5 * 60 * 1000 * 1000)
+}
+
+fn is_default_client_jwks_cache_ttl(value: &Duration) -> bool {
+    *value == default_client_jwks_cache_ttl()
+}
+
+fn default_discovery_cache_ttl() -> Duration {
+    Duration::microseconds(5 * 60 * 1000 * 1000)
+}
+
+fn is_default_discovery_cache_ttl(value: &Duration) -> bool {
+    *value == default_discovery_cache_ttl()
+}
+
+fn default_upstream_discovery_cache_min_ttl() -> Duration {
+    Duration::microseconds(5 * 60 * 1000 * 1000)
+}
+
+fn is_default_upstream_discovery_cache_min_ttl(value: &Duration) -> bool {
+    *value == default_upstream_discovery_cache_min_ttl()
+}
+
+fn default_upstream_discovery_cache_max_ttl() -> Duration {
+    Duration::microseconds(24 * 60 * 60 * 1000 * 1000)
+}
+
+fn is_default_upstream_discovery_cache_max_ttl(value: &Duration) -> bool {
+    *value == default_upstream_discovery_cache_max_ttl()
+}
+
+fn default_activity_flush_interval() -> Duration {
+    Duration::microseconds(60 * 1000 * 1000)
+}
+
+fn is_default_activity_flush_interval(value: &Duration) -> bool {
+    *value == default_activity_flush_interval()
+}
+
+fn default_session_ttl() -> Duration {
+    Duration::microseconds(24 * 60 * 60 * 1000 * 1000)
+}
+
+fn is_default_session_ttl(value: &Duration) -> bool {
+    *value == default_session_ttl()
+}
+
+fn default_remembered_session_ttl() -> Duration {
+    Duration::microseconds(30 * 24 * 60 * 60 * 1000 * 1000)
+}
+
+fn is_default_remembered_session_ttl(value: &Duration) -> bool {
+    *value == default_remembered_session_ttl()
+}
+
+fn default_code_challenge_methods() -> Vec<PkceCodeChallengeMethod> {
+    vec![PkceCodeChallengeMethod::Plain, PkceCodeChallengeMethod::S256]
+}
+
+fn is_default_code_challenge_methods(value: &[PkceCodeChallengeMethod]) -> bool {
+    value == default_code_challenge_methods().as_slice()
+}
+
+fn default_admin_scopes() -> Vec<String> {
+    vec!["urn:mas:admin".to_owned()]
+}
+
+fn is_default_admin_scopes(value: &[String]) -> bool {
+    value == default_admin_scopes().as_slice()
+}
+
 const fn default_true() -> bool {
     true
 }
@@ -36,6 +148,41 @@ const fn is_default_true(value: &bool) -> bool {
     *value == default_true()
 }
 
+/// What to do when a user starting a new browser session already has as many
+/// active browser sessions as allowed
+#[derive(Clone, Copy, Debug, Default, Deserialize, JsonSchema, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BrowserSessionLimitPolicy {
+    /// Finish the oldest active browser sessions of the user to make room for
+    /// the new one
+    #[default]
+    EvictOldest,
+
+    /// Refuse to start the new browser session
+    RejectNew,
+}
+
+impl BrowserSessionLimitPolicy {
+    #[allow(clippy::trivially_copy_pass_by_ref)]
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// A service account which can call the GraphQL API with a static token
+#[serde_as]
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize, PartialEq, Eq)]
+pub struct ServiceAccountConfig {
+    /// Name of the service account
+    pub name: String,
+
+    /// Hex-encoded SHA-256 hash of the static bearer token used by the
+    /// service account
+    #[schemars(with = "String", regex(pattern = r"^[0-9a-fA-F]{64}$"))]
+    #[serde_as(as = "serde_with::hex::Hex")]
+    pub token_sha256: [u8; 32],
+}
+
 /// Configuration sections for experimental options
 ///
 /// Do not change these options unless you know what you are doing.
@@ -52,6 +199,14 @@ pub struct ExperimentalConfig {
     #[serde_as(as = "serde_with::DurationSeconds<i64>")]
     pub access_token_ttl: Duration,
 
+    /// Time-to-live of refresh tokens in seconds. A refresh token which was
+    /// not used within that time can no longer be exchanged. Defaults to no
+    /// expiration.
+    #[schemars(with = "Option<u64>", range(min = 60, max = 86400))]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[serde_as(as = "Option<serde_with::DurationSeconds<i64>>")]
+    pub refresh_token_ttl: Option<Duration>,
+
     /// Time-to-live of compatibility access tokens in seconds. Defaults to 5
     /// minutes.
     #[schemars(with = "u64", range(min = 60, max = 86400))]
@@ -62,6 +217,94 @@ pub struct ExperimentalConfig {
     #[serde_as(as = "serde_with::DurationSeconds<i64>")]
     pub compat_token_ttl: Duration,
 
+    /// Maximum time in seconds between the issuance of an authorization code
+    /// and its exchange at the token endpoint. Defaults to 10 minutes.
+    #[schemars(with = "u64", range(min = 10, max = 600))]
+    #[serde(
+        default = "default_authorization_code_ttl",
+        skip_serializing_if = "is_default_authorization_code_ttl"
+    )]
+    #[serde_as(as = "serde_with::DurationSeconds<i64>")]
+    pub authorization_code_ttl: Duration,
+
+    /// Time-to-live of the `request_uri` returned by the pushed authorization
+    /// request endpoint in seconds. Defaults to 90 seconds.
+    #[schemars(with = "u64", range(min = 10, max = 600))]
+    #[serde(
+        default = "default_pushed_authorization_request_ttl",
+        skip_serializing_if = "is_default_pushed_authorization_request_ttl"
+    )]
+    #[serde_as(as = "serde_with::DurationSeconds<i64>")]
+    pub pushed_authorization_request_ttl: Duration,
+
+    /// How long the JSON Web Key Sets fetched from the clients `jwks_uri` to
+    /// verify signed request objects are cached, in seconds. Defaults to 5
+    /// minutes.
+    #[schemars(with = "u64", range(min = 0, max = 86400))]
+    #[serde(
+        default = "default_client_jwks_cache_ttl",
+        skip_serializing_if = "is_default_client_jwks_cache_ttl"
+    )]
+    #[serde_as(as = "serde_with::DurationSeconds<i64>")]
+    pub client_jwks_cache_ttl: Duration,
+
+    /// How long clients may cache the discovery documents, in seconds. This is
+    /// advertised through the `Cache-Control` header. Defaults to 5 minutes.
+    #[schemars(with = "u64", range(min = 0, max = 86400))]
+    #[serde(
+        default = "default_discovery_cache_ttl",
+        skip_serializing_if = "is_default_discovery_cache_ttl"
+    )]
+    #[serde_as(as = "serde_with::DurationSeconds<i64>")]
+    pub discovery_cache_ttl: Duration,
+
+    /// Minimum time the discovery documents of upstream OAuth 2.0 providers
+    /// are cached for, in seconds, regardless of the `Cache-Control` header
+    /// they were served with. Defaults to 5 minutes.
+    #[schemars(with = "u64", range(min = 0, max = 86400))]
+    #[serde(
+        default = "default_upstream_discovery_cache_min_ttl",
+        skip_serializing_if = "is_default_upstream_discovery_cache_min_ttl"
+    )]
+    #[serde_as(as = "serde_with::DurationSeconds<i64>")]
+    pub upstream_discovery_cache_min_ttl: Duration,
+
+    /// Maximum time the discovery documents of upstream OAuth 2.0 providers
+    /// are cached for, in seconds. This is also used when the provider does
+    /// not send a `Cache-Control` header. Defaults to 24 hours.
+    #[schemars(with = "u64", range(min = 0, max = 604800))]
+    #[serde(
+        default = "default_upstream_discovery_cache_max_ttl",
+        skip_serializing_if = "is_default_upstream_discovery_cache_max_ttl"
+    )]
+    #[serde_as(as = "serde_with::DurationSeconds<i64>")]
+    pub upstream_discovery_cache_max_ttl: Duration,
+
+    /// How often the recorded activity of the sessions is written to the
+    /// database, in seconds. The last activity of a session is only updated
+    /// once per interval, however many requests it made. Defaults to 1 minute.
+    #[schemars(with = "u64", range(min = 1, max = 3600))]
+    #[serde(
+        default = "default_activity_flush_interval",
+        skip_serializing_if = "is_default_activity_flush_interval"
+    )]
+    #[serde_as(as = "serde_with::DurationSeconds<i64>")]
+    pub activity_flush_interval: Duration,
+
+    /// PKCE code challenge methods clients are allowed to use in
+    /// authorization requests. Defaults to both `plain` and `S256`. Public
+    /// clients can only use `S256`.
+    #[serde(
+        default = "default_code_challenge_methods",
+        skip_serializing_if = "is_default_code_challenge_methods"
+    )]
+    pub allowed_code_challenge_methods: Vec<PkceCodeChallengeMethod>,
+
+    /// Whether to reject the `token` and `id_token` response types in
+    /// authorization requests, for all clients. Defaults to `false`.
+    #[serde(default)]
+    pub disable_implicit_flow: bool,
+
     /// Whether to enable self-service password registration. Defaults to `true`
     /// if password authentication is enabled.
     #[serde(default = "default_true", skip_serializing_if = "is_default_true")]
@@ -80,17 +323,79 @@ pub struct ExperimentalConfig {
     /// Whether users are allowed to change their passwords. Defaults to `true`.
     #[serde(default = "default_true", skip_serializing_if = "is_default_true")]
     pub password_change_allowed: bool,
+
+    /// Service accounts which can call the GraphQL API with a static bearer
+    /// token. They are treated as administrators.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub service_accounts: Vec<ServiceAccountConfig>,
+
+    /// Scopes which make an OAuth 2.0 session an administrator in the GraphQL
+    /// API, if it has any of them. They should be kept in sync with the
+    /// policy. Defaults to `["urn:mas:admin"]`.
+    #[serde(
+        default = "default_admin_scopes",
+        skip_serializing_if = "is_default_admin_scopes"
+    )]
+    pub admin_scopes: Vec<String>,
+
+    /// Maximum number of active browser sessions a user can have at once. By
+    /// default, there is no limit.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(range(min = 1))]
+    pub max_active_browser_sessions: Option<usize>,
+
+    /// What to do when a user starting a new browser session already has
+    /// `max_active_browser_sessions` active ones. Defaults to `evict_oldest`.
+    #[serde(default, skip_serializing_if = "BrowserSessionLimitPolicy::is_default")]
+    pub browser_session_limit_policy: BrowserSessionLimitPolicy,
+
+    /// Time-to-live of browser sessions in seconds, when the user did not ask
+    /// to be remembered when logging in. Their cookie is also discarded when
+    /// the browser is closed. Defaults to 1 day.
+    #[schemars(with = "u64", range(min = 60, max = 31_536_000))]
+    #[serde(
+        default = "default_session_ttl",
+        skip_serializing_if = "is_default_session_ttl"
+    )]
+    #[serde_as(as = "serde_with::DurationSeconds<i64>")]
+    pub session_ttl: Duration,
+
+    /// Time-to-live of browser sessions in seconds, when the user asked to be
+    /// remembered when logging in. Defaults to 30 days.
+    #[schemars(with = "u64", range(min = 60, max = 31_536_000))]
+    #[serde(
+        default = "default_remembered_session_ttl",
+        skip_serializing_if = "is_default_remembered_session_ttl"
+    )]
+    #[serde_as(as = "serde_with::DurationSeconds<i64>")]
+    pub remembered_session_ttl: Duration,
 }
 
 impl Default for ExperimentalConfig {
     fn default() -> Self {
         Self {
             access_token_ttl: default_token_ttl(),
+            refresh_token_ttl: None,
             compat_token_ttl: default_token_ttl(),
+            authorization_code_ttl: default_authorization_code_ttl(),
+            pushed_authorization_request_ttl: default_pushed_authorization_request_ttl(),
+            client_jwks_cache_ttl: default_client_jwks_cache_ttl(),
+            discovery_cache_ttl: default_discovery_cache_ttl(),
+            upstream_discovery_cache_min_ttl: default_upstream_discovery_cache_min_ttl(),
+            upstream_discovery_cache_max_ttl: default_upstream_discovery_cache_max_ttl(),
+            activity_flush_interval: default_activity_flush_interval(),
+            allowed_code_challenge_methods: default_code_challenge_methods(),
+            disable_implicit_flow: false,
             password_registration_enabled: default_true(),
             email_change_allowed: default_true(),
             displayname_change_allowed: default_true(),
             password_change_allowed: default_true(),
+            service_accounts: Vec::new(),
+            admin_scopes: default_admin_scopes(),
+            max_active_browser_sessions: None,
+            browser_session_limit_policy: BrowserSessionLimitPolicy::default(),
+            session_ttl: default_session_ttl(),
+            remembered_session_ttl: default_remembered_session_ttl(),
         }
     }
 }
@@ -98,14 +403,318 @@ impl Default for ExperimentalConfig {
 impl ExperimentalConfig {
     pub(crate) fn is_default(&self) -> bool {
         is_default_token_ttl(&self.access_token_ttl)
+            && self.refresh_token_ttl.is_none()
             && is_default_token_ttl(&self.compat_token_ttl)
+            && is_default_authorization_code_ttl(&self.authorization_code_ttl)
+            && is_default_pushed_authorization_request_ttl(&self.pushed_authorization_request_ttl)
+            && is_default_client_jwks_cache_ttl(&self.client_jwks_cache_ttl)
+            && is_default_discovery_cache_ttl(&self.discovery_cache_ttl)
+            && is_default_upstream_discovery_cache_min_ttl(&self.upstream_discovery_cache_min_ttl)
+            && is_default_upstream_discovery_cache_max_ttl(&self.upstream_discovery_cache_max_ttl)
+            && is_default_activity_flush_interval(&self.activity_flush_interval)
+            && is_default_code_challenge_methods(&self.allowed_code_challenge_methods)
+            && !self.disable_implicit_flow
             && is_default_true(&self.password_registration_enabled)
             && is_default_true(&self.email_change_allowed)
             && is_default_true(&self.displayname_change_allowed)
             && is_default_true(&self.password_change_allowed)
+            && self.service_accounts.is_empty()
+            && is_default_admin_scopes(&self.admin_scopes)
+            && self.max_active_browser_sessions.is_none()
+            && self.browser_session_limit_policy.is_default()
+            && is_default_session_ttl(&self.session_ttl)
+            && is_default_remembered_session_ttl(&self.remembered_session_ttl)
     }
 }
 
 impl ConfigurationSection for ExperimentalConfig {
     const PATH: Option<&'static str> = Some("experimental");
+
+    fn validate(&self, figment: &figment::Figment) -> Result<(), figment::error::Error> {
+        validate_token_ttl("access_token_ttl", self.access_token_ttl)
+            .and_then(|()| {
+                self.refresh_token_ttl
+                    .map_or(Ok(()), |ttl| validate_token_ttl("refresh_token_ttl", ttl))
+            })
+            .and_then(|()| {
+                if self.max_active_browser_sessions == Some(0) {
+                    let error = figment::error::Error::custom(
+                        "max_active_browser_sessions must be at least 1",
+                    );
+                    return Err(error.with_path("max_active_browser_sessions"));
+                }
+
+                Ok(())
+            })
+            .and_then(|()| {
+                if self.activity_flush_interval < MIN_ACTIVITY_FLUSH_INTERVAL {
+                    let error = figment::error::Error::custom(
+                        "activity_flush_interval must be at least 1 second",
+                    );
+                    return Err(error.with_path("activity_flush_interval"));
+                }
+
+                Ok(())
+            })
+            .and_then(|()| {
+                if self.session_ttl > self.remembered_session_ttl {
+                    let error = figment::error::Error::custom(
+                        "remembered_session_ttl must be at least session_ttl",
+                    );
+                    return Err(error.with_path("remembered_session_ttl"));
+                }
+
+                Ok(())
+            })
+            .map_err(|mut err| {
+                // Save the error location information in the error
+                err.metadata = figment.find_metadata(Self::PATH.unwrap()).cloned();
+                err.profile = Some(figment::Profile::Default);
+                err.path.insert(0, Self::PATH.unwrap().to_owned());
+                err
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use figment::{
+        providers::{Format, Yaml},
+        Figment, Jail,
+    };
+
+    use super::*;
+
+    #[test]
+    fn load_config() {
+        Jail::expect_with(|jail| {
+            jail.create_file(
+                "config.yaml",
+                r"
+                    experimental:
+                      access_token_ttl: 3600
+                      allowed_code_challenge_methods: [S256]
+                      disable_implicit_flow: true
+                ",
+            )?;
+
+            let config = Figment::new()
+                .merge(Yaml::file("config.yaml"))
+                .extract_inner::<ExperimentalConfig>("experimental")?;
+
+            assert_eq!(config.access_token_ttl, Duration::try_hours(1).unwrap());
+            assert_eq!(config.refresh_token_ttl, None);
+            assert_eq!(config.compat_token_ttl, default_token_ttl());
+            assert_eq!(
+                config.allowed_code_challenge_methods,
+                vec![PkceCodeChallengeMethod::S256]
+            );
+            assert!(config.disable_implicit_flow);
+            assert!(!config.is_default());
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn load_token_ttl_out_of_bounds() {
+        Jail::expect_with(|jail| {
+            jail.create_file(
+                "config.yaml",
+                r"
+                    experimental:
+                      access_token_ttl: 600
+                      refresh_token_ttl: 86400
+                ",
+            )?;
+
+            let config = Figment::new().merge(Yaml::file("config.yaml"));
+            let experimental = ExperimentalConfig::extract(&config)?;
+            assert_eq!(
+                experimental.refresh_token_ttl,
+                Some(Duration::try_hours(24).unwrap())
+            );
+
+            jail.create_file(
+                "config.yaml",
+                r"
+                    experimental:
+                      access_token_ttl: 30
+                ",
+            )?;
+
+            let config = Figment::new().merge(Yaml::file("config.yaml"));
+            let error = ExperimentalConfig::extract(&config).unwrap_err();
+            assert_eq!(error.path, vec!["experimental", "access_token_ttl"]);
+
+            jail.create_file(
+                "config.yaml",
+                r"
+                    experimental:
+                      refresh_token_ttl: 172800
+                ",
+            )?;
+
+            let config = Figment::new().merge(Yaml::file("config.yaml"));
+            let error = ExperimentalConfig::extract(&config).unwrap_err();
+            assert_eq!(error.path, vec!["experimental", "refresh_token_ttl"]);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn load_browser_session_limit() {
+        Jail::expect_with(|jail| {
+            jail.create_file(
+                "config.yaml",
+                r"
+                    experimental:
+                      max_active_browser_sessions: 5
+                      browser_session_limit_policy: reject_new
+                ",
+            )?;
+
+            let config = Figment::new().merge(Yaml::file("config.yaml"));
+            let experimental = ExperimentalConfig::extract(&config)?;
+            assert_eq!(experimental.max_active_browser_sessions, Some(5));
+            assert_eq!(
+                experimental.browser_session_limit_policy,
+                BrowserSessionLimitPolicy::RejectNew
+            );
+            assert!(!experimental.is_default());
+
+            jail.create_file(
+                "config.yaml",
+                r"
+                    experimental:
+                      max_active_browser_sessions: 0
+                ",
+            )?;
+
+            let config = Figment::new().merge(Yaml::file("config.yaml"));
+            let error = ExperimentalConfig::extract(&config).unwrap_err();
+            assert_eq!(
+                error.path,
+                vec!["experimental", "max_active_browser_sessions"]
+            );
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn load_activity_flush_interval() {
+        Jail::expect_with(|jail| {
+            jail.create_file(
+                "config.yaml",
+                r"
+                    experimental:
+                      activity_flush_interval: 10
+                ",
+            )?;
+
+            let config = Figment::new().merge(Yaml::file("config.yaml"));
+            let experimental = ExperimentalConfig::extract(&config)?;
+            assert_eq!(
+                experimental.activity_flush_interval,
+                Duration::try_seconds(10).unwrap()
+            );
+            assert!(!experimental.is_default());
+
+            jail.create_file(
+                "config.yaml",
+                r"
+                    experimental:
+                      activity_flush_interval: 0
+                ",
+            )?;
+
+            let config = Figment::new().merge(Yaml::file("config.yaml"));
+            let error = ExperimentalConfig::extract(&config).unwrap_err();
+            assert_eq!(error.path, vec!["experimental", "activity_flush_interval"]);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn load_session_ttl() {
+        Jail::expect_with(|jail| {
+            jail.create_file(
+                "config.yaml",
+                r"
+                    experimental:
+                      session_ttl: 3600
+                ",
+            )?;
+
+            let config = Figment::new().merge(Yaml::file("config.yaml"));
+            let experimental = ExperimentalConfig::extract(&config)?;
+            assert_eq!(experimental.session_ttl, Duration::try_hours(1).unwrap());
+            assert_eq!(
+                experimental.remembered_session_ttl,
+                Duration::try_days(30).unwrap()
+            );
+            assert!(!experimental.is_default());
+
+            jail.create_file(
+                "config.yaml",
+                r"
+                    experimental:
+                      session_ttl: 7200
+                      remembered_session_ttl: 3600
+                ",
+            )?;
+
+            let config = Figment::new().merge(Yaml::file("config.yaml"));
+            let error = ExperimentalConfig::extract(&config).unwrap_err();
+            assert_eq!(error.path, vec!["experimental", "remembered_session_ttl"]);
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn load_service_accounts() {
+        Jail::expect_with(|jail| {
+            jail.create_file(
+                "config.yaml",
+                r"
+                    experimental:
+                      service_accounts:
+                        - name: synapse
+                          token_sha256: 9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08
+                ",
+            )?;
+
+            let config = Figment::new()
+                .merge(Yaml::file("config.yaml"))
+                .extract_inner::<ExperimentalConfig>("experimental")?;
+
+            assert_eq!(config.service_accounts.len(), 1);
+            assert_eq!(config.service_accounts[0].name, "synapse");
+            assert_eq!(config.service_accounts[0].token_sha256[0], 0x9f);
+            assert!(!config.is_default());
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn load_default_config() {
+        Jail::expect_with(|jail| {
+            jail.create_file("config.yaml", "experimental: {}")?;
+
+            let config = Figment::new()
+                .merge(Yaml::file("config.yaml"))
+                .extract_inner::<ExperimentalConfig>("experimental")?;
+
+            assert_eq!(config.access_token_ttl, Duration::try_minutes(5).unwrap());
+            assert_eq!(config.session_ttl, Duration::try_days(1).unwrap());
+            assert!(config.is_default());
+
+            Ok(())
+        });
+    }
 }
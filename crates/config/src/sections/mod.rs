@@ -23,6 +23,7 @@ mod email;
 mod experimental;
 mod http;
 mod matrix;
+mod password_reset;
 mod passwords;
 mod policy;
 mod secrets;
@@ -32,21 +33,28 @@ mod upstream_oauth2;
 
 pub use self::{
     branding::BrandingConfig,
-    clients::{ClientAuthMethodConfig, ClientConfig, ClientsConfig},
+    clients::{
+        AccessTokenFormatConfig, ClientAuthMethodConfig, ClientConfig, ClientsConfig,
+        RedirectUriMatchingConfig,
+    },
     database::DatabaseConfig,
     email::{EmailConfig, EmailSmtpMode, EmailTransportKind},
-    experimental::ExperimentalConfig,
+    experimental::{BrowserSessionLimitPolicy, ExperimentalConfig, ServiceAccountConfig},
     http::{
         BindConfig as HttpBindConfig, HttpConfig, ListenerConfig as HttpListenerConfig,
         Resource as HttpResource, TlsConfig as HttpTlsConfig, UnixOrTcp,
     },
     matrix::MatrixConfig,
-    passwords::{Algorithm as PasswordAlgorithm, PasswordsConfig},
+    password_reset::PasswordResetConfig,
+    passwords::{
+        Algorithm as PasswordAlgorithm, Argon2Params as PasswordArgon2Params, LoginThrottleConfig,
+        PasswordsConfig, PwnedPasswordCheckConfig,
+    },
     policy::PolicyConfig,
     secrets::SecretsConfig,
     telemetry::{
-        MetricsConfig, MetricsExporterKind, Propagator, TelemetryConfig, TracingConfig,
-        TracingExporterKind,
+        MetricsConfig, MetricsExporterKind, PrometheusBasicAuthConfig, Propagator, TelemetryConfig,
+        TracingConfig, TracingExporterKind,
     },
     templates::TemplatesConfig,
     upstream_oauth2::{
@@ -92,6 +100,10 @@ pub struct RootConfig {
     #[serde(default)]
     pub passwords: PasswordsConfig,
 
+    /// Configuration related to the self-service password reset
+    #[serde(default, skip_serializing_if = "PasswordResetConfig::is_default")]
+    pub password_reset: PasswordResetConfig,
+
     /// Configuration related to the homeserver
     pub matrix: MatrixConfig,
 
@@ -121,6 +133,7 @@ impl ConfigurationSection for RootConfig {
         self.templates.validate(figment)?;
         self.email.validate(figment)?;
         self.passwords.validate(figment)?;
+        self.password_reset.validate(figment)?;
         self.secrets.validate(figment)?;
         self.matrix.validate(figment)?;
         self.policy.validate(figment)?;
@@ -150,6 +163,7 @@ impl RootConfig {
             templates: TemplatesConfig::default(),
             email: EmailConfig::default(),
             passwords: PasswordsConfig::default(),
+            password_reset: PasswordResetConfig::default(),
             secrets: SecretsConfig::generate(&mut rng).await?,
             matrix: MatrixConfig::generate(&mut rng),
             policy: PolicyConfig::default(),
@@ -169,6 +183,7 @@ impl RootConfig {
             telemetry: TelemetryConfig::default(),
             templates: TemplatesConfig::default(),
             passwords: PasswordsConfig::default(),
+            password_reset: PasswordResetConfig::default(),
             email: EmailConfig::default(),
             secrets: SecretsConfig::test(),
             matrix: MatrixConfig::test(),
@@ -201,11 +216,17 @@ pub struct AppConfig {
     #[serde(default)]
     pub passwords: PasswordsConfig,
 
+    #[serde(default)]
+    pub password_reset: PasswordResetConfig,
+
     pub matrix: MatrixConfig,
 
     #[serde(default)]
     pub policy: PolicyConfig,
 
+    #[serde(default)]
+    pub upstream_oauth2: UpstreamOAuth2Config,
+
     #[serde(default)]
     pub branding: BrandingConfig,
 
@@ -220,9 +241,11 @@ impl ConfigurationSection for AppConfig {
         self.templates.validate(figment)?;
         self.email.validate(figment)?;
         self.passwords.validate(figment)?;
+        self.password_reset.validate(figment)?;
         self.secrets.validate(figment)?;
         self.matrix.validate(figment)?;
         self.policy.validate(figment)?;
+        self.upstream_oauth2.validate(figment)?;
         self.branding.validate(figment)?;
         self.experimental.validate(figment)?;
 
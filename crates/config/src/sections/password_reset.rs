@@ -0,0 +1,90 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use chrono::Duration;
+use schemars::JsonSchema;
+use serde::{de::Error, Deserialize, Serialize};
+use serde_with::serde_as;
+
+use crate::ConfigurationSection;
+
+/// Minimum time-to-live of password reset links
+const MIN_TOKEN_TTL: Duration = Duration::microseconds(60 * 1000 * 1000);
+
+/// Maximum time-to-live of password reset links
+const MAX_TOKEN_TTL: Duration = Duration::microseconds(24 * 60 * 60 * 1000 * 1000);
+
+fn default_token_ttl() -> Duration {
+    Duration::microseconds(60 * 60 * 1000 * 1000)
+}
+
+fn is_default_token_ttl(value: &Duration) -> bool {
+    *value == default_token_ttl()
+}
+
+/// Configuration section for the self-service password reset
+#[serde_as]
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct PasswordResetConfig {
+    /// Whether users can request a link to reset their password by email.
+    /// Password changes must also be allowed. Defaults to `false`.
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Time-to-live of the password reset links in seconds. Defaults to 1
+    /// hour.
+    #[schemars(with = "u64", range(min = 60, max = 86400))]
+    #[serde(
+        default = "default_token_ttl",
+        skip_serializing_if = "is_default_token_ttl"
+    )]
+    #[serde_as(as = "serde_with::DurationSeconds<i64>")]
+    pub token_ttl: Duration,
+}
+
+impl Default for PasswordResetConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            token_ttl: default_token_ttl(),
+        }
+    }
+}
+
+impl PasswordResetConfig {
+    /// Returns true if the configuration is the default one
+    pub(crate) fn is_default(&self) -> bool {
+        !self.enabled && is_default_token_ttl(&self.token_ttl)
+    }
+}
+
+impl ConfigurationSection for PasswordResetConfig {
+    const PATH: Option<&'static str> = Some("password_reset");
+
+    fn validate(&self, figment: &figment::Figment) -> Result<(), figment::Error> {
+        if self.token_ttl < MIN_TOKEN_TTL || self.token_ttl > MAX_TOKEN_TTL {
+            let mut error = figment::Error::custom(format!(
+                "token_ttl must be between {min} and {max} seconds",
+                min = MIN_TOKEN_TTL.num_seconds(),
+                max = MAX_TOKEN_TTL.num_seconds(),
+            ));
+            error.metadata = figment.find_metadata(Self::PATH.unwrap()).cloned();
+            error.profile = Some(figment::Profile::Default);
+            error.path = vec![Self::PATH.unwrap().to_owned(), "token_ttl".to_owned()];
+            return Err(error);
+        }
+
+        Ok(())
+    }
+}
@@ -14,8 +14,11 @@
 
 use anyhow::bail;
 use camino::Utf8PathBuf;
+use chrono::Duration;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use serde_with::serde_as;
+use url::Url;
 
 use crate::ConfigurationSection;
 
@@ -24,6 +27,9 @@ fn default_schemes() -> Vec<HashingScheme> {
         version: 1,
         algorithm: Algorithm::Argon2id,
         cost: None,
+        memory_cost: None,
+        time_cost: None,
+        parallelism: None,
         secret: None,
         secret_file: None,
     }]
@@ -33,6 +39,136 @@ fn default_enabled() -> bool {
     true
 }
 
+fn default_max_failures() -> u32 {
+    10
+}
+
+fn default_failure_window() -> Duration {
+    Duration::microseconds(15 * 60 * 1000 * 1000)
+}
+
+fn default_backoff() -> Duration {
+    Duration::microseconds(1000 * 1000)
+}
+
+fn default_lockout() -> Duration {
+    Duration::microseconds(15 * 60 * 1000 * 1000)
+}
+
+/// Throttling of the failed password login attempts, to protect accounts
+/// against brute-force attacks
+///
+/// The first failed attempt is free. After the second consecutive failure, the
+/// next attempt is delayed by `backoff`, and that delay doubles with each
+/// subsequent failure. Once `max_failures` is reached, the account is locked
+/// for `lockout`.
+#[serde_as]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct LoginThrottleConfig {
+    /// Number of consecutive failed attempts after which the account is
+    /// temporarily locked. Defaults to 10.
+    #[schemars(range(min = 1))]
+    #[serde(default = "default_max_failures")]
+    pub max_failures: u32,
+
+    /// How long failed attempts are remembered, in seconds. Defaults to 15
+    /// minutes.
+    #[schemars(with = "u64", range(min = 1, max = 86400))]
+    #[serde(default = "default_failure_window")]
+    #[serde_as(as = "serde_with::DurationSeconds<i64>")]
+    pub failure_window: Duration,
+
+    /// Delay enforced after the second consecutive failed attempt, in seconds.
+    /// It doubles with each subsequent failure. Defaults to 1 second.
+    #[schemars(with = "u64", range(min = 0, max = 3600))]
+    #[serde(default = "default_backoff")]
+    #[serde_as(as = "serde_with::DurationSeconds<i64>")]
+    pub backoff: Duration,
+
+    /// How long the account is locked once `max_failures` is reached, in
+    /// seconds. Defaults to 15 minutes.
+    #[schemars(with = "u64", range(min = 1, max = 86400))]
+    #[serde(default = "default_lockout")]
+    #[serde_as(as = "serde_with::DurationSeconds<i64>")]
+    pub lockout: Duration,
+}
+
+impl Default for LoginThrottleConfig {
+    fn default() -> Self {
+        Self {
+            max_failures: default_max_failures(),
+            failure_window: default_failure_window(),
+            backoff: default_backoff(),
+            lockout: default_lockout(),
+        }
+    }
+}
+
+impl LoginThrottleConfig {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    fn validate(&self) -> Result<(), &'static str> {
+        if self.max_failures == 0 {
+            return Err("`login_throttle.max_failures` must be at least 1");
+        }
+
+        if self.failure_window <= Duration::zero() || self.lockout <= Duration::zero() {
+            return Err(
+                "`login_throttle.failure_window` and `login_throttle.lockout` must be positive",
+            );
+        }
+
+        if self.backoff < Duration::zero() {
+            return Err("`login_throttle.backoff` must not be negative");
+        }
+
+        Ok(())
+    }
+}
+
+fn default_pwned_check_enabled() -> bool {
+    false
+}
+
+fn default_pwned_check_api_url() -> Url {
+    Url::parse("https://api.pwnedpasswords.com/").unwrap()
+}
+
+/// Check of the new passwords against the ones which appeared in known data
+/// breaches, using the Have I Been Pwned range API
+///
+/// Only the first 5 characters of the SHA-1 hash of the password are sent to
+/// the API.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct PwnedPasswordCheckConfig {
+    /// Whether new passwords are checked. The service must be able to reach
+    /// the API. Defaults to `false`.
+    #[serde(default = "default_pwned_check_enabled")]
+    pub enabled: bool,
+
+    /// Base URL of the Have I Been Pwned compatible API. Defaults to
+    /// `https://api.pwnedpasswords.com/`.
+    #[serde(default = "default_pwned_check_api_url")]
+    pub api_url: Url,
+}
+
+impl Default for PwnedPasswordCheckConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_pwned_check_enabled(),
+            api_url: default_pwned_check_api_url(),
+        }
+    }
+}
+
+impl PwnedPasswordCheckConfig {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
 /// User password hashing config
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct PasswordsConfig {
@@ -42,6 +178,14 @@ pub struct PasswordsConfig {
 
     #[serde(default = "default_schemes")]
     schemes: Vec<HashingScheme>,
+
+    /// Throttling of the failed login attempts
+    #[serde(default, skip_serializing_if = "LoginThrottleConfig::is_default")]
+    login_throttle: LoginThrottleConfig,
+
+    /// Check of the new passwords against known data breaches
+    #[serde(default, skip_serializing_if = "PwnedPasswordCheckConfig::is_default")]
+    pwned_check: PwnedPasswordCheckConfig,
 }
 
 impl Default for PasswordsConfig {
@@ -49,6 +193,8 @@ impl Default for PasswordsConfig {
         Self {
             enabled: default_enabled(),
             schemes: default_schemes(),
+            login_throttle: LoginThrottleConfig::default(),
+            pwned_check: PwnedPasswordCheckConfig::default(),
         }
     }
 }
@@ -81,6 +227,14 @@ impl ConfigurationSection for PasswordsConfig {
                     "Cannot specify both `secret` and `secret_file`".to_owned(),
                 ));
             }
+
+            if let Err(error) = scheme.argon2_params().validate(scheme.algorithm) {
+                return annotate(figment::Error::from(error.to_owned()));
+            }
+        }
+
+        if let Err(error) = self.login_throttle.validate() {
+            return annotate(figment::Error::from(error.to_owned()));
         }
 
         Ok(())
@@ -94,6 +248,18 @@ impl PasswordsConfig {
         self.enabled
     }
 
+    /// Throttling of the failed login attempts
+    #[must_use]
+    pub fn login_throttle(&self) -> &LoginThrottleConfig {
+        &self.login_throttle
+    }
+
+    /// Check of the new passwords against known data breaches
+    #[must_use]
+    pub fn pwned_check(&self) -> &PwnedPasswordCheckConfig {
+        &self.pwned_check
+    }
+
     /// Load the password hashing schemes defined by the config
     ///
     /// # Errors
@@ -102,7 +268,8 @@ impl PasswordsConfig {
     /// not be read.
     pub async fn load(
         &self,
-    ) -> Result<Vec<(u16, Algorithm, Option<u32>, Option<Vec<u8>>)>, anyhow::Error> {
+    ) -> Result<Vec<(u16, Algorithm, Option<u32>, Argon2Params, Option<Vec<u8>>)>, anyhow::Error>
+    {
         let mut schemes: Vec<&HashingScheme> = self.schemes.iter().collect();
         schemes.sort_unstable_by_key(|a| a.version);
         schemes.dedup_by_key(|a| a.version);
@@ -130,7 +297,13 @@ impl PasswordsConfig {
                 (None, None) => None,
             };
 
-            mapped_result.push((scheme.version, scheme.algorithm, scheme.cost, secret));
+            mapped_result.push((
+                scheme.version,
+                scheme.algorithm,
+                scheme.cost,
+                scheme.argon2_params(),
+                secret,
+            ));
         }
 
         Ok(mapped_result)
@@ -148,6 +321,21 @@ pub struct HashingScheme {
     #[schemars(default = "default_bcrypt_cost")]
     cost: Option<u32>,
 
+    /// Memory cost for the argon2 algorithms, in KiB. Defaults to 19456 (19
+    /// MiB)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    memory_cost: Option<u32>,
+
+    /// Time cost for the argon2 algorithms, as a number of iterations.
+    /// Defaults to 2
+    #[serde(skip_serializing_if = "Option::is_none")]
+    time_cost: Option<u32>,
+
+    /// Degree of parallelism for the argon2 algorithms, as a number of
+    /// lanes. Defaults to 1
+    #[serde(skip_serializing_if = "Option::is_none")]
+    parallelism: Option<u32>,
+
     #[serde(skip_serializing_if = "Option::is_none")]
     secret: Option<String>,
 
@@ -156,6 +344,63 @@ pub struct HashingScheme {
     secret_file: Option<Utf8PathBuf>,
 }
 
+impl HashingScheme {
+    fn argon2_params(&self) -> Argon2Params {
+        Argon2Params {
+            memory_cost: self.memory_cost,
+            time_cost: self.time_cost,
+            parallelism: self.parallelism,
+        }
+    }
+}
+
+/// Parameters of the argon2 algorithms. Unset parameters use the defaults
+/// recommended by OWASP
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Argon2Params {
+    /// Memory cost, in KiB
+    pub memory_cost: Option<u32>,
+
+    /// Time cost, as a number of iterations
+    pub time_cost: Option<u32>,
+
+    /// Degree of parallelism, as a number of lanes
+    pub parallelism: Option<u32>,
+}
+
+impl Argon2Params {
+    /// Check that the parameters are within the bounds argon2 accepts, and
+    /// only set for the argon2 algorithms
+    fn validate(&self, algorithm: Algorithm) -> Result<(), &'static str> {
+        if *self == Self::default() {
+            return Ok(());
+        }
+
+        if !matches!(algorithm, Algorithm::Argon2id | Algorithm::Argon2i) {
+            return Err(
+                "`memory_cost`, `time_cost` and `parallelism` are only supported by argon2",
+            );
+        }
+
+        let parallelism = self.parallelism.unwrap_or(1);
+        if !(1..=0x00FF_FFFF).contains(&parallelism) {
+            return Err("`parallelism` must be between 1 and 16777215");
+        }
+
+        if self.time_cost == Some(0) {
+            return Err("`time_cost` must be at least 1");
+        }
+
+        // argon2 needs at least 8 KiB of memory per lane
+        let memory_cost = self.memory_cost.unwrap_or(19 * 1024);
+        if memory_cost < 8 * parallelism {
+            return Err("`memory_cost` must be at least 8 times the `parallelism`");
+        }
+
+        Ok(())
+    }
+}
+
 #[allow(clippy::unnecessary_wraps)]
 fn default_bcrypt_cost() -> Option<u32> {
     Some(12)
@@ -171,6 +416,206 @@ pub enum Algorithm {
     /// argon2id
     Argon2id,
 
+    /// argon2i
+    Argon2i,
+
     /// PBKDF2
     Pbkdf2,
+
+    /// scrypt
+    Scrypt,
+}
+
+#[cfg(test)]
+mod tests {
+    use figment::{
+        providers::{Format, Yaml},
+        Figment, Jail,
+    };
+
+    use super::*;
+
+    #[test]
+    fn load_argon2_params() {
+        Jail::expect_with(|jail| {
+            jail.create_file(
+                "config.yaml",
+                r"
+                    passwords:
+                      schemes:
+                        - version: 2
+                          algorithm: argon2id
+                          memory_cost: 65536
+                          time_cost: 3
+                          parallelism: 4
+                        - version: 1
+                          algorithm: bcrypt
+                ",
+            )?;
+
+            let figment = Figment::new().merge(Yaml::file("config.yaml"));
+            let config = PasswordsConfig::extract(&figment)?;
+
+            assert_eq!(
+                config.schemes[0].argon2_params(),
+                Argon2Params {
+                    memory_cost: Some(65536),
+                    time_cost: Some(3),
+                    parallelism: Some(4),
+                }
+            );
+            assert_eq!(config.schemes[1].argon2_params(), Argon2Params::default());
+
+            // The parameters also apply to argon2i
+            jail.create_file(
+                "config.yaml",
+                r"
+                    passwords:
+                      schemes:
+                        - version: 1
+                          algorithm: argon2i
+                          time_cost: 4
+                ",
+            )?;
+
+            let figment = Figment::new().merge(Yaml::file("config.yaml"));
+            let config = PasswordsConfig::extract(&figment)?;
+
+            assert!(matches!(config.schemes[0].algorithm, Algorithm::Argon2i));
+            assert_eq!(
+                config.schemes[0].argon2_params(),
+                Argon2Params {
+                    time_cost: Some(4),
+                    ..Argon2Params::default()
+                }
+            );
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn reject_invalid_argon2_params() {
+        Jail::expect_with(|jail| {
+            // The parameters only make sense for argon2id
+            jail.create_file(
+                "config.yaml",
+                r"
+                    passwords:
+                      schemes:
+                        - version: 1
+                          algorithm: scrypt
+                          memory_cost: 65536
+                ",
+            )?;
+            let figment = Figment::new().merge(Yaml::file("config.yaml"));
+            assert!(PasswordsConfig::extract(&figment).is_err());
+
+            // argon2 needs at least 8 KiB of memory per lane
+            jail.create_file(
+                "config.yaml",
+                r"
+                    passwords:
+                      schemes:
+                        - version: 1
+                          algorithm: argon2id
+                          memory_cost: 64
+                          parallelism: 16
+                ",
+            )?;
+            let figment = Figment::new().merge(Yaml::file("config.yaml"));
+            assert!(PasswordsConfig::extract(&figment).is_err());
+
+            // At least one iteration is needed
+            jail.create_file(
+                "config.yaml",
+                r"
+                    passwords:
+                      schemes:
+                        - version: 1
+                          algorithm: argon2id
+                          time_cost: 0
+                ",
+            )?;
+            let figment = Figment::new().merge(Yaml::file("config.yaml"));
+            assert!(PasswordsConfig::extract(&figment).is_err());
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn load_login_throttle() {
+        Jail::expect_with(|jail| {
+            jail.create_file(
+                "config.yaml",
+                r"
+                    passwords:
+                      login_throttle:
+                        max_failures: 5
+                        lockout: 3600
+                ",
+            )?;
+
+            let figment = Figment::new().merge(Yaml::file("config.yaml"));
+            let config = PasswordsConfig::extract(&figment)?;
+
+            assert_eq!(
+                config.login_throttle(),
+                &LoginThrottleConfig {
+                    max_failures: 5,
+                    lockout: Duration::microseconds(3600 * 1000 * 1000),
+                    ..LoginThrottleConfig::default()
+                }
+            );
+
+            // The account has to be locked after at least one failure
+            jail.create_file(
+                "config.yaml",
+                r"
+                    passwords:
+                      login_throttle:
+                        max_failures: 0
+                ",
+            )?;
+            let figment = Figment::new().merge(Yaml::file("config.yaml"));
+            assert!(PasswordsConfig::extract(&figment).is_err());
+
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn load_pwned_check() {
+        Jail::expect_with(|jail| {
+            // It is disabled by default
+            jail.create_file("config.yaml", "passwords: {}")?;
+            let figment = Figment::new().merge(Yaml::file("config.yaml"));
+            let config = PasswordsConfig::extract(&figment)?;
+            assert_eq!(config.pwned_check(), &PwnedPasswordCheckConfig::default());
+            assert!(!config.pwned_check().enabled);
+
+            jail.create_file(
+                "config.yaml",
+                r"
+                    passwords:
+                      pwned_check:
+                        enabled: true
+                        api_url: https://pwned.example.com/
+                ",
+            )?;
+            let figment = Figment::new().merge(Yaml::file("config.yaml"));
+            let config = PasswordsConfig::extract(&figment)?;
+
+            assert_eq!(
+                config.pwned_check(),
+                &PwnedPasswordCheckConfig {
+                    enabled: true,
+                    api_url: Url::parse("https://pwned.example.com/").unwrap(),
+                }
+            );
+
+            Ok(())
+        });
+    }
 }
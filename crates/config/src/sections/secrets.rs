@@ -16,12 +16,9 @@ use std::borrow::Cow;
 
 use anyhow::{bail, Context};
 use camino::Utf8PathBuf;
-use mas_jose::jwk::{JsonWebKey, JsonWebKeySet};
+use mas_jose::jwk::{JsonWebKey, JsonWebKeyPublicParameters, JsonWebKeySet};
 use mas_keystore::{Encrypter, Keystore, PrivateKey};
-use rand::{
-    distributions::{Alphanumeric, DistString},
-    Rng, SeedableRng,
-};
+use rand::{Rng, SeedableRng};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use serde_with::serde_as;
@@ -36,7 +33,9 @@ fn example_secret() -> &'static str {
 
 #[derive(JsonSchema, Serialize, Deserialize, Clone, Debug)]
 pub struct KeyConfig {
-    kid: String,
+    /// The key ID. Defaults to the JWK SHA-256 thumbprint of the public key
+    #[serde(skip_serializing_if = "Option::is_none")]
+    kid: Option<String>,
 
     #[serde(skip_serializing_if = "Option::is_none")]
     password: Option<String>,
@@ -66,6 +65,13 @@ pub struct SecretsConfig {
     #[serde_as(as = "serde_with::hex::Hex")]
     pub encryption: [u8; 32],
 
+    /// Previous encryption keys, used only to decrypt data which was encrypted
+    /// before the current key was rotated in
+    #[schemars(with = "Vec<String>")]
+    #[serde_as(as = "Vec<serde_with::hex::Hex>")]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub previous_encryption: Vec<[u8; 32]>,
+
     /// List of private keys to use for signing and encrypting payloads
     #[serde(default)]
     keys: Vec<KeyConfig>,
@@ -114,8 +120,14 @@ impl SecretsConfig {
                 }
             };
 
+            // Key IDs default to the key thumbprint, so that they are stable and unique
+            let kid = item
+                .kid
+                .clone()
+                .unwrap_or_else(|| JsonWebKeyPublicParameters::from(&key).thumbprint_sha256());
+
             let key = JsonWebKey::new(key)
-                .with_kid(item.kid.clone())
+                .with_kid(kid)
                 .with_use(mas_iana::jose::JsonWebKeyUse::Sig);
             keys.push(key);
         }
@@ -127,7 +139,11 @@ impl SecretsConfig {
     /// Derive an [`Encrypter`] out of the config
     #[must_use]
     pub fn encrypter(&self) -> Encrypter {
-        Encrypter::new(&self.encryption)
+        self.previous_encryption
+            .iter()
+            .fold(Encrypter::new(&self.encryption), |encrypter, key| {
+                encrypter.with_previous_key(key)
+            })
     }
 }
 
@@ -191,7 +207,7 @@ impl SecretsConfig {
         .await
         .context("could not join blocking task")?;
         let rsa_key = KeyConfig {
-            kid: Alphanumeric.sample_string(&mut rng, 10),
+            kid: None,
             password: None,
             password_file: None,
             key: Some(rsa_key.to_pem(pem_rfc7468::LineEnding::LF)?.to_string()),
@@ -209,7 +225,7 @@ impl SecretsConfig {
         .await
         .context("could not join blocking task")?;
         let ec_p256_key = KeyConfig {
-            kid: Alphanumeric.sample_string(&mut rng, 10),
+            kid: None,
             password: None,
             password_file: None,
             key: Some(ec_p256_key.to_pem(pem_rfc7468::LineEnding::LF)?.to_string()),
@@ -227,7 +243,7 @@ impl SecretsConfig {
         .await
         .context("could not join blocking task")?;
         let ec_p384_key = KeyConfig {
-            kid: Alphanumeric.sample_string(&mut rng, 10),
+            kid: None,
             password: None,
             password_file: None,
             key: Some(ec_p384_key.to_pem(pem_rfc7468::LineEnding::LF)?.to_string()),
@@ -245,7 +261,7 @@ impl SecretsConfig {
         .await
         .context("could not join blocking task")?;
         let ec_k256_key = KeyConfig {
-            kid: Alphanumeric.sample_string(&mut rng, 10),
+            kid: None,
             password: None,
             password_file: None,
             key: Some(ec_k256_key.to_pem(pem_rfc7468::LineEnding::LF)?.to_string()),
@@ -254,13 +270,14 @@ impl SecretsConfig {
 
         Ok(Self {
             encryption: rng.gen(),
+            previous_encryption: Vec::new(),
             keys: vec![rsa_key, ec_p256_key, ec_p384_key, ec_k256_key],
         })
     }
 
     pub(crate) fn test() -> Self {
         let rsa_key = KeyConfig {
-            kid: "abcdef".to_owned(),
+            kid: Some("abcdef".to_owned()),
             password: None,
             password_file: None,
             key: Some(
@@ -281,7 +298,7 @@ impl SecretsConfig {
             key_file: None,
         };
         let ecdsa_key = KeyConfig {
-            kid: "ghijkl".to_owned(),
+            kid: Some("ghijkl".to_owned()),
             password: None,
             password_file: None,
             key: Some(
@@ -299,6 +316,7 @@ impl SecretsConfig {
 
         Self {
             encryption: [0xEA; 32],
+            previous_encryption: Vec::new(),
             keys: vec![rsa_key, ecdsa_key],
         }
     }
@@ -99,6 +99,16 @@ pub enum MetricsExporterKind {
     Prometheus,
 }
 
+/// Credentials required to access the Prometheus metrics endpoint
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+pub struct PrometheusBasicAuthConfig {
+    /// The username expected in the HTTP basic authentication
+    pub username: String,
+
+    /// The password expected in the HTTP basic authentication
+    pub password: String,
+}
+
 /// Configuration related to exporting metrics
 #[derive(Clone, Debug, Default, Serialize, Deserialize, JsonSchema)]
 pub struct MetricsConfig {
@@ -110,12 +120,19 @@ pub struct MetricsConfig {
     #[serde(skip_serializing_if = "Option::is_none")]
     #[schemars(url, default = "otlp_endpoint_default")]
     pub endpoint: Option<Url>,
+
+    /// Prometheus exporter: require HTTP basic authentication to scrape the
+    /// metrics endpoint. The endpoint is not protected if this is not set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub basic_auth: Option<PrometheusBasicAuthConfig>,
 }
 
 impl MetricsConfig {
     /// Returns true if all fields are at their default values
     fn is_default(&self) -> bool {
-        matches!(self.exporter, MetricsExporterKind::None) && self.endpoint.is_none()
+        matches!(self.exporter, MetricsExporterKind::None)
+            && self.endpoint.is_none()
+            && self.basic_auth.is_none()
     }
 }
 
@@ -14,26 +14,84 @@
 
 use std::collections::BTreeMap;
 
+use chrono::Duration;
 use mas_iana::{jose::JsonWebSignatureAlg, oauth::OAuthClientAuthenticationMethod};
 use schemars::JsonSchema;
 use serde::{de::Error, Deserialize, Serialize};
-use serde_with::skip_serializing_none;
+use serde_with::{serde_as, skip_serializing_none};
 use ulid::Ulid;
 use url::Url;
 
 use crate::ConfigurationSection;
 
+/// Minimum time an upstream authorization session can be completed in
+const MIN_SESSION_MAX_AGE: Duration = Duration::microseconds(60 * 1000 * 1000);
+
+/// Maximum time an upstream authorization session can be completed in
+const MAX_SESSION_MAX_AGE: Duration = Duration::microseconds(24 * 60 * 60 * 1000 * 1000);
+
+fn default_session_max_age() -> Duration {
+    Duration::microseconds(10 * 60 * 1000 * 1000)
+}
+
+fn is_default_session_max_age(value: &Duration) -> bool {
+    *value == default_session_max_age()
+}
+
+const fn default_max_pending_sessions() -> usize {
+    8
+}
+
+#[allow(clippy::trivially_copy_pass_by_ref)]
+const fn is_default_max_pending_sessions(value: &usize) -> bool {
+    *value == default_max_pending_sessions()
+}
+
 /// Upstream OAuth 2.0 providers configuration
-#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, Default)]
+#[serde_as]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct UpstreamOAuth2Config {
+    /// Time in seconds users have to complete a login with an upstream
+    /// provider, from the moment they are redirected to it. Defaults to 10
+    /// minutes.
+    #[schemars(with = "u64", range(min = 60, max = 86400))]
+    #[serde(
+        default = "default_session_max_age",
+        skip_serializing_if = "is_default_session_max_age"
+    )]
+    #[serde_as(as = "serde_with::DurationSeconds<i64>")]
+    pub session_max_age: Duration,
+
+    /// Maximum number of upstream logins a browser can have in progress at
+    /// the same time. When exceeded, the oldest ones are dropped. Defaults to
+    /// 8.
+    #[schemars(range(min = 1))]
+    #[serde(
+        default = "default_max_pending_sessions",
+        skip_serializing_if = "is_default_max_pending_sessions"
+    )]
+    pub max_pending_sessions: usize,
+
     /// List of OAuth 2.0 providers
     pub providers: Vec<Provider>,
 }
 
+impl Default for UpstreamOAuth2Config {
+    fn default() -> Self {
+        Self {
+            session_max_age: default_session_max_age(),
+            max_pending_sessions: default_max_pending_sessions(),
+            providers: Vec::new(),
+        }
+    }
+}
+
 impl UpstreamOAuth2Config {
     /// Returns true if the configuration is the default one
     pub(crate) fn is_default(&self) -> bool {
-        self.providers.is_empty()
+        is_default_session_max_age(&self.session_max_age)
+            && is_default_max_pending_sessions(&self.max_pending_sessions)
+            && self.providers.is_empty()
     }
 }
 
@@ -41,6 +99,30 @@ impl ConfigurationSection for UpstreamOAuth2Config {
     const PATH: Option<&'static str> = Some("upstream_oauth2");
 
     fn validate(&self, figment: &figment::Figment) -> Result<(), figment::Error> {
+        if self.session_max_age < MIN_SESSION_MAX_AGE || self.session_max_age > MAX_SESSION_MAX_AGE
+        {
+            let mut error = figment::Error::custom(format!(
+                "session_max_age must be between {min} and {max} seconds",
+                min = MIN_SESSION_MAX_AGE.num_seconds(),
+                max = MAX_SESSION_MAX_AGE.num_seconds(),
+            ));
+            error.metadata = figment.find_metadata(Self::PATH.unwrap()).cloned();
+            error.profile = Some(figment::Profile::Default);
+            error.path = vec![Self::PATH.unwrap().to_owned(), "session_max_age".to_owned()];
+            return Err(error);
+        }
+
+        if self.max_pending_sessions == 0 {
+            let mut error = figment::Error::custom("max_pending_sessions must be at least 1");
+            error.metadata = figment.find_metadata(Self::PATH.unwrap()).cloned();
+            error.profile = Some(figment::Profile::Default);
+            error.path = vec![
+                Self::PATH.unwrap().to_owned(),
+                "max_pending_sessions".to_owned(),
+            ];
+            return Err(error);
+        }
+
         for (index, provider) in self.providers.iter().enumerate() {
             let annotate = |mut error: figment::Error| {
                 error.metadata = figment
@@ -454,4 +536,11 @@ pub struct Provider {
     /// Orders of the keys are not preserved.
     #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
     pub additional_authorization_parameters: BTreeMap<String, String>,
+
+    /// Whether the `login_hint` sent by the client in its authorization
+    /// request should be forwarded to the provider
+    ///
+    /// Defaults to `false`.
+    #[serde(default)]
+    pub forward_login_hint: bool,
 }
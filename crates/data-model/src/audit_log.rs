@@ -0,0 +1,164 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use thiserror::Error;
+use ulid::Ulid;
+
+/// A security-sensitive action recorded in the audit log
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    /// A user logged in
+    UserLoggedIn,
+
+    /// A user logged out
+    UserLoggedOut,
+
+    /// The password of a user was changed, either by themselves or through a
+    /// password reset
+    PasswordChanged,
+
+    /// An access token was issued to a client
+    TokenIssued,
+
+    /// A token was revoked by a client
+    TokenRevoked,
+
+    /// A user was locked by an administrator
+    UserLocked,
+
+    /// A user was unlocked by an administrator
+    UserUnlocked,
+
+    /// A user was deactivated by an administrator
+    UserDeactivated,
+
+    /// A user was reactivated by an administrator
+    UserReactivated,
+}
+
+impl AuditAction {
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::UserLoggedIn => "user_logged_in",
+            Self::UserLoggedOut => "user_logged_out",
+            Self::PasswordChanged => "password_changed",
+            Self::TokenIssued => "token_issued",
+            Self::TokenRevoked => "token_revoked",
+            Self::UserLocked => "user_locked",
+            Self::UserUnlocked => "user_unlocked",
+            Self::UserDeactivated => "user_deactivated",
+            Self::UserReactivated => "user_reactivated",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Error)]
+#[error("Invalid audit action {0:?}")]
+pub struct InvalidAuditActionError(String);
+
+impl std::str::FromStr for AuditAction {
+    type Err = InvalidAuditActionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "user_logged_in" => Ok(Self::UserLoggedIn),
+            "user_logged_out" => Ok(Self::UserLoggedOut),
+            "password_changed" => Ok(Self::PasswordChanged),
+            "token_issued" => Ok(Self::TokenIssued),
+            "token_revoked" => Ok(Self::TokenRevoked),
+            "user_locked" => Ok(Self::UserLocked),
+            "user_unlocked" => Ok(Self::UserUnlocked),
+            "user_deactivated" => Ok(Self::UserDeactivated),
+            "user_reactivated" => Ok(Self::UserReactivated),
+            s => Err(InvalidAuditActionError(s.to_owned())),
+        }
+    }
+}
+
+impl std::fmt::Display for AuditAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The kind of session used to perform an action
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditSessionKind {
+    /// A browser session
+    Browser,
+
+    /// An OAuth 2.0 session
+    OAuth2,
+}
+
+impl AuditSessionKind {
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Browser => "browser",
+            Self::OAuth2 => "oauth2",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Error)]
+#[error("Invalid audit session kind {0:?}")]
+pub struct InvalidAuditSessionKindError(String);
+
+impl std::str::FromStr for AuditSessionKind {
+    type Err = InvalidAuditSessionKindError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "browser" => Ok(Self::Browser),
+            "oauth2" => Ok(Self::OAuth2),
+            s => Err(InvalidAuditSessionKindError(s.to_owned())),
+        }
+    }
+}
+
+impl std::fmt::Display for AuditSessionKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// An entry of the audit log
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AuditLogEntry {
+    pub id: Ulid,
+    pub created_at: DateTime<Utc>,
+
+    /// The user who performed the action, if any
+    pub actor_user_id: Option<Ulid>,
+
+    /// The session used to perform the action, if any
+    pub actor_session_id: Option<Ulid>,
+
+    /// The kind of session [`Self::actor_session_id`] refers to
+    pub actor_session_kind: Option<AuditSessionKind>,
+
+    pub action: AuditAction,
+
+    /// The user affected by the action, if any
+    pub target_user_id: Option<Ulid>,
+
+    /// Additional details about the action
+    pub metadata: serde_json::Value,
+}
@@ -16,8 +16,10 @@
 
 use thiserror::Error;
 
+pub(crate) mod audit_log;
 pub(crate) mod compat;
 pub(crate) mod oauth2;
+pub mod password_reset;
 mod site_config;
 pub(crate) mod tokens;
 pub(crate) mod upstream_oauth2;
@@ -32,17 +34,24 @@ pub struct InvalidTransitionError;
 pub use ulid::Ulid;
 
 pub use self::{
+    audit_log::{
+        AuditAction, AuditLogEntry, AuditSessionKind, InvalidAuditActionError,
+        InvalidAuditSessionKindError,
+    },
     compat::{
         CompatAccessToken, CompatRefreshToken, CompatRefreshTokenState, CompatSession,
         CompatSessionState, CompatSsoLogin, CompatSsoLoginState, Device,
     },
     oauth2::{
-        AuthorizationCode, AuthorizationGrant, AuthorizationGrantStage, Client, DeviceCodeGrant,
-        DeviceCodeGrantState, InvalidRedirectUriError, JwksOrJwksUri, Pkce, Session, SessionState,
+        AccessTokenFormat, AuthorizationCode, AuthorizationGrant, AuthorizationGrantStage, Client,
+        DeviceCodeGrant, DeviceCodeGrantState, InvalidRedirectUriError, JwksOrJwksUri, Pkce,
+        PushedAuthorizationRequest, RedirectUriMatching, Session, SessionState,
+        PUSHED_AUTHORIZATION_REQUEST_URI_PREFIX,
     },
-    site_config::SiteConfig,
+    site_config::{BrowserSessionLimitPolicy, ServiceAccount, SiteConfig},
     tokens::{
-        AccessToken, AccessTokenState, RefreshToken, RefreshTokenState, TokenFormatError, TokenType,
+        AccessToken, AccessTokenState, InvalidJwtAccessToken, RefreshToken, RefreshTokenState,
+        TokenFormatError, TokenType,
     },
     upstream_oauth2::{
         UpsreamOAuthProviderSetEmailVerification, UpstreamOAuthAuthorizationSession,
@@ -53,7 +62,8 @@ pub use self::{
     },
     user_agent::{DeviceType, UserAgent},
     users::{
-        Authentication, AuthenticationMethod, BrowserSession, Password, User, UserEmail,
-        UserEmailVerification, UserEmailVerificationState,
+        Authentication, AuthenticationMethod, BrowserSession, Password, Totp, User, UserEmail,
+        UserEmailVerification, UserEmailVerificationState, UserPasswordResetToken,
+        UserRecoveryCode, WebAuthnCredential,
     },
 };
@@ -72,10 +72,12 @@ pub enum AuthorizationGrantStage {
     Fulfilled {
         session_id: Ulid,
         fulfilled_at: DateTime<Utc>,
+        code_expires_at: DateTime<Utc>,
     },
     Exchanged {
         session_id: Ulid,
         fulfilled_at: DateTime<Utc>,
+        code_expires_at: DateTime<Utc>,
         exchanged_at: DateTime<Utc>,
     },
     Cancelled {
@@ -92,11 +94,13 @@ impl AuthorizationGrantStage {
     fn fulfill(
         self,
         fulfilled_at: DateTime<Utc>,
+        code_expires_at: DateTime<Utc>,
         session: &Session,
     ) -> Result<Self, InvalidTransitionError> {
         match self {
             Self::Pending => Ok(Self::Fulfilled {
                 fulfilled_at,
+                code_expires_at,
                 session_id: session.id,
             }),
             _ => Err(InvalidTransitionError),
@@ -107,9 +111,11 @@ impl AuthorizationGrantStage {
         match self {
             Self::Fulfilled {
                 fulfilled_at,
+                code_expires_at,
                 session_id,
             } => Ok(Self::Exchanged {
                 fulfilled_at,
+                code_expires_at,
                 exchanged_at,
                 session_id,
             }),
@@ -160,11 +166,14 @@ pub struct AuthorizationGrant {
     pub scope: Scope,
     pub state: Option<String>,
     pub nonce: Option<String>,
+    pub login_hint: Option<String>,
+    pub resource: Vec<Url>,
     pub max_age: Option<NonZeroU32>,
     pub response_mode: ResponseMode,
     pub response_type_id_token: bool,
     pub created_at: DateTime<Utc>,
     pub requires_consent: bool,
+    pub requires_reauth: bool,
 }
 
 impl std::ops::Deref for AuthorizationGrant {
@@ -187,6 +196,46 @@ impl AuthorizationGrant {
         self.created_at - max_age
     }
 
+    /// Check whether an authentication which happened at `auth_time` is
+    /// recent enough to fulfill this authorization grant.
+    ///
+    /// The authentication must not be older than the requested `max_age`,
+    /// counted from when the grant was created. If the client asked for the
+    /// user to reauthenticate (with `prompt=login` or `max_age=0`), the
+    /// authentication must have happened after the grant was created.
+    ///
+    /// Both timestamps come from the server clock, so no leeway is applied.
+    #[must_use]
+    pub fn is_authentication_fresh(&self, auth_time: DateTime<Utc>) -> bool {
+        if self.requires_reauth {
+            auth_time > self.created_at
+        } else {
+            auth_time >= self.max_auth_time()
+        }
+    }
+
+    /// Get the username hinted by the client in its `login_hint`, if any.
+    ///
+    /// Only hints of the form `mxid:@username:server_name` are understood, and
+    /// only if they point to a user of this homeserver. Other hints are
+    /// ignored.
+    #[must_use]
+    pub fn login_hint_username(&self, server_name: &str) -> Option<&str> {
+        let mxid = self.login_hint.as_deref()?.strip_prefix("mxid:@")?;
+        let (username, hint_server_name) = mxid.split_once(':')?;
+        if hint_server_name != server_name {
+            return None;
+        }
+
+        // Localparts are made of lowercase letters, digits and a few symbols
+        let valid = !username.is_empty()
+            && username
+                .bytes()
+                .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b"-._=/+".contains(&b));
+
+        valid.then_some(username)
+    }
+
     /// Mark the authorization grant as exchanged.
     ///
     /// # Errors
@@ -199,7 +248,8 @@ impl AuthorizationGrant {
         Ok(self)
     }
 
-    /// Mark the authorization grant as fulfilled.
+    /// Mark the authorization grant as fulfilled, with an authorization code
+    /// valid until `code_expires_at`.
     ///
     /// # Errors
     ///
@@ -209,9 +259,10 @@ impl AuthorizationGrant {
     pub fn fulfill(
         mut self,
         fulfilled_at: DateTime<Utc>,
+        code_expires_at: DateTime<Utc>,
         session: &Session,
     ) -> Result<Self, InvalidTransitionError> {
-        self.stage = self.stage.fulfill(fulfilled_at, session)?;
+        self.stage = self.stage.fulfill(fulfilled_at, code_expires_at, session)?;
         Ok(self)
     }
 
@@ -245,11 +296,78 @@ impl AuthorizationGrant {
             scope: Scope::from_iter([OPENID, PROFILE]),
             state: Some(Alphanumeric.sample_string(rng, 10)),
             nonce: Some(Alphanumeric.sample_string(rng, 10)),
+            login_hint: None,
+            resource: Vec::new(),
             max_age: None,
             response_mode: ResponseMode::Query,
             response_type_id_token: false,
             created_at: now,
             requires_consent: false,
+            requires_reauth: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+
+    use super::*;
+
+    #[test]
+    fn test_authentication_freshness() {
+        let mut rng = rand_chacha::ChaChaRng::seed_from_u64(42);
+        let now = DateTime::UNIX_EPOCH + Duration::try_days(365).unwrap();
+        let mut grant = AuthorizationGrant::sample(now, &mut rng);
+
+        // Without a max_age, any authentication from the last year is fine
+        assert!(grant.is_authentication_fresh(now - Duration::try_days(30).unwrap()));
+        assert!(grant.is_authentication_fresh(now));
+
+        // With a max_age, the boundary itself is still fresh
+        grant.max_age = NonZeroU32::new(60);
+        let max_age = Duration::try_seconds(60).unwrap();
+        assert!(grant.is_authentication_fresh(now - max_age));
+        assert!(!grant.is_authentication_fresh(now - max_age - Duration::try_seconds(1).unwrap()));
+
+        // Authentications which happened after the grant was created are fresh
+        assert!(grant.is_authentication_fresh(now + Duration::try_seconds(5).unwrap()));
+
+        // When a reauthentication is required, only authentications after the
+        // grant creation are valid
+        grant.requires_reauth = true;
+        assert!(!grant.is_authentication_fresh(now - Duration::try_seconds(1).unwrap()));
+        assert!(!grant.is_authentication_fresh(now));
+        assert!(grant.is_authentication_fresh(now + Duration::try_seconds(1).unwrap()));
+    }
+
+    #[test]
+    fn test_login_hint_username() {
+        let mut rng = rand_chacha::ChaChaRng::seed_from_u64(42);
+        let now = DateTime::UNIX_EPOCH;
+        let mut grant = AuthorizationGrant::sample(now, &mut rng);
+
+        assert_eq!(grant.login_hint_username("example.com"), None);
+
+        grant.login_hint = Some("mxid:@alice:example.com".to_owned());
+        assert_eq!(grant.login_hint_username("example.com"), Some("alice"));
+
+        // Users of other homeservers are ignored
+        assert_eq!(grant.login_hint_username("example.org"), None);
+
+        // So are malformed hints
+        for hint in [
+            "alice",
+            "@alice:example.com",
+            "email:alice@example.com",
+            "mxid:alice:example.com",
+            "mxid:@:example.com",
+            "mxid:@alice",
+            "mxid:@Alice:example.com",
+            "mxid:@al ice:example.com",
+        ] {
+            grant.login_hint = Some(hint.to_owned());
+            assert_eq!(grant.login_hint_username("example.com"), None, "{hint}");
         }
     }
 }
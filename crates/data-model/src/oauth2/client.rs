@@ -12,15 +12,17 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Duration, Utc};
 use mas_iana::{
     jose::JsonWebSignatureAlg,
     oauth::{OAuthAuthorizationEndpointResponseType, OAuthClientAuthenticationMethod},
 };
 use mas_jose::jwk::PublicJsonWebKeySet;
-use oauth2_types::{oidc::ApplicationType, requests::GrantType};
+use oauth2_types::{
+    oidc::ApplicationType, requests::GrantType, response_type::ResponseType, scope::Scope,
+};
 use rand::RngCore;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 use ulid::Ulid;
 use url::Url;
@@ -35,6 +37,93 @@ pub enum JwksOrJwksUri {
     JwksUri(Url),
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum RedirectUriMatching {
+    /// The redirect URI must be exactly equal to one of the registered URIs
+    #[default]
+    Exact,
+
+    /// Trailing slashes and case differences are ignored when comparing the
+    /// redirect URI with the registered URIs
+    Normalized,
+}
+
+#[derive(Debug, Clone, Error)]
+#[error("Invalid redirect URI matching mode {0:?}")]
+pub struct InvalidRedirectUriMatchingError(String);
+
+impl std::str::FromStr for RedirectUriMatching {
+    type Err = InvalidRedirectUriMatchingError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "exact" => Ok(Self::Exact),
+            "normalized" => Ok(Self::Normalized),
+            s => Err(InvalidRedirectUriMatchingError(s.to_owned())),
+        }
+    }
+}
+
+impl RedirectUriMatching {
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Exact => "exact",
+            Self::Normalized => "normalized",
+        }
+    }
+}
+
+impl std::fmt::Display for RedirectUriMatching {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum AccessTokenFormat {
+    /// Random strings, which resource servers have to introspect
+    #[default]
+    Opaque,
+
+    /// Signed JWTs, which resource servers can validate on their own
+    Jwt,
+}
+
+#[derive(Debug, Clone, Error)]
+#[error("Invalid access token format {0:?}")]
+pub struct InvalidAccessTokenFormatError(String);
+
+impl std::str::FromStr for AccessTokenFormat {
+    type Err = InvalidAccessTokenFormatError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "opaque" => Ok(Self::Opaque),
+            "jwt" => Ok(Self::Jwt),
+            s => Err(InvalidAccessTokenFormatError(s.to_owned())),
+        }
+    }
+}
+
+impl AccessTokenFormat {
+    #[must_use]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Opaque => "opaque",
+            Self::Jwt => "jwt",
+        }
+    }
+}
+
+impl std::fmt::Display for AccessTokenFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub struct Client {
     pub id: Ulid,
@@ -49,6 +138,10 @@ pub struct Client {
     /// Array of Redirection URI values used by the Client
     pub redirect_uris: Vec<Url>,
 
+    /// How the redirect URI given in requests is compared with the
+    /// registered ones
+    pub redirect_uri_matching: RedirectUriMatching,
+
     /// Array containing a list of the OAuth 2.0 response_type values that the
     /// Client is declaring that it will restrict itself to using
     pub response_types: Vec<OAuthAuthorizationEndpointResponseType>,
@@ -86,6 +179,10 @@ pub struct Client {
     /// JWS alg algorithm REQUIRED for signing UserInfo Responses.
     pub userinfo_signed_response_alg: Option<JsonWebSignatureAlg>,
 
+    /// JWS alg algorithm REQUIRED for signing JWT-secured authorization
+    /// responses
+    pub authorization_signed_response_alg: Option<JsonWebSignatureAlg>,
+
     /// Requested authentication method for the token endpoint
     pub token_endpoint_auth_method: Option<OAuthClientAuthenticationMethod>,
 
@@ -97,12 +194,72 @@ pub struct Client {
     /// URI using the https scheme that a third party can use to initiate a
     /// login by the RP
     pub initiate_login_uri: Option<Url>,
+
+    /// Whether the client must send its authorization requests as signed
+    /// request objects
+    pub require_signed_request_object: bool,
+
+    /// Whether the client must use PKCE. If not set, only public clients
+    /// must use it
+    pub require_pkce: Option<bool>,
+
+    /// Time-to-live of the access tokens issued to the client, overriding the
+    /// site-wide setting
+    #[serde(skip)]
+    pub access_token_ttl: Option<Duration>,
+
+    /// Time-to-live of the refresh tokens issued to the client, overriding the
+    /// site-wide setting
+    #[serde(skip)]
+    pub refresh_token_ttl: Option<Duration>,
+
+    /// The format of the access tokens issued to the client
+    #[serde(skip)]
+    pub access_token_format: AccessTokenFormat,
+
+    /// The scopes the client can request with the client credentials grant.
+    /// If not set, they are only restricted by the policy
+    #[serde(skip)]
+    pub client_credentials_scope: Option<Scope>,
+
+    /// The resources this client can request tokens for, using resource
+    /// indicators. For resource servers, the resources they serve
+    #[serde(skip)]
+    pub allowed_resources: Vec<Url>,
+
+    /// Authentication method used by the client when calling the
+    /// introspection endpoint. Only clients registered as resource servers
+    /// have one, others are not allowed to introspect tokens
+    pub introspection_endpoint_auth_method: Option<OAuthClientAuthenticationMethod>,
+
+    /// Array of URIs the End-User can be redirected to after logging out
+    pub post_logout_redirect_uris: Vec<Url>,
+
+    /// URI the client should be notified on when a session it is part of
+    /// ends, through a back-channel logout token
+    pub backchannel_logout_uri: Option<Url>,
+
+    /// Whether the client requires the `sid` claim to be included in the
+    /// back-channel logout tokens it receives
+    pub backchannel_logout_session_required: bool,
+
+    /// Whether the client is trusted, in which case users are not asked for
+    /// their consent when it requests access
+    pub trusted: bool,
 }
 
-#[derive(Debug, Error)]
+#[derive(Debug, Error, PartialEq, Eq)]
 pub enum InvalidRedirectUriError {
-    #[error("redirect_uri is not allowed for this client")]
-    NotAllowed,
+    #[error("redirect_uri does not match any of the redirect_uris registered for this client")]
+    Mismatch,
+
+    #[error(
+        "redirect_uri does not exactly match any of the redirect_uris registered for this client"
+    )]
+    NotExactMatch,
+
+    #[error("redirect_uri must not include a fragment")]
+    FragmentNotAllowed,
 
     #[error("multiple redirect_uris registered for this client")]
     MultipleRegistered,
@@ -112,25 +269,70 @@ pub enum InvalidRedirectUriError {
 }
 
 impl Client {
+    /// Whether this is a public client, which doesn't authenticate to the token
+    /// endpoint
+    #[must_use]
+    pub fn is_public(&self) -> bool {
+        matches!(
+            self.token_endpoint_auth_method,
+            None | Some(OAuthClientAuthenticationMethod::None)
+        )
+    }
+
+    /// Whether this client must use PKCE in authorization requests. Defaults to
+    /// requiring it for public clients
+    #[must_use]
+    pub fn requires_pkce(&self) -> bool {
+        self.require_pkce.unwrap_or_else(|| self.is_public())
+    }
+
+    /// Whether this client is allowed to use the given response type in
+    /// authorization requests
+    #[must_use]
+    pub fn allows_response_type(&self, response_type: &ResponseType) -> bool {
+        OAuthAuthorizationEndpointResponseType::try_from(response_type.clone())
+            .is_ok_and(|response_type| self.response_types.contains(&response_type))
+    }
+
     /// Determine which redirect URI to use for the given request.
     ///
+    /// The given URL must be byte-for-byte equal to one of the registered
+    /// ones, unless the client opted in to [`RedirectUriMatching::Normalized`].
+    /// Native clients redirecting to `http` on the loopback interface may use
+    /// any port, as per RFC 8252 §7.3. So may clients without an application
+    /// type, like the static ones, as they always could.
+    ///
     /// # Errors
     ///
     /// Returns an error if:
     ///
+    ///  - no URL was registered,
+    ///  - the given URL has a fragment,
     ///  - no URL was given but multiple redirect URIs are registered,
-    ///  - no URL was registered, or
+    ///  - the given URL only matches a registered one after normalization, but
+    ///    the client requires an exact match, or
     ///  - the given URL is not registered
     pub fn resolve_redirect_uri<'a>(
         &'a self,
         redirect_uri: &'a Option<Url>,
     ) -> Result<&'a Url, InvalidRedirectUriError> {
+        let any_loopback_port =
+            matches!(self.application_type, None | Some(ApplicationType::Native));
         match (&self.redirect_uris[..], redirect_uri) {
             ([], _) => Err(InvalidRedirectUriError::NoneRegistered),
+            (_, Some(uri)) if uri.fragment().is_some() => {
+                Err(InvalidRedirectUriError::FragmentNotAllowed)
+            }
             ([one], None) => Ok(one),
             (_, None) => Err(InvalidRedirectUriError::MultipleRegistered),
-            (uris, Some(uri)) if uri_matches_one_of(uri, uris) => Ok(uri),
-            _ => Err(InvalidRedirectUriError::NotAllowed),
+            (uris, Some(uri)) if uri_matches_one_of(uri, uris, any_loopback_port) => Ok(uri),
+            (uris, Some(uri)) if uri_matches_one_of_normalized(uri, uris, any_loopback_port) => {
+                match self.redirect_uri_matching {
+                    RedirectUriMatching::Normalized => Ok(uri),
+                    RedirectUriMatching::Exact => Err(InvalidRedirectUriError::NotExactMatch),
+                }
+            }
+            _ => Err(InvalidRedirectUriError::Mismatch),
         }
     }
 
@@ -147,6 +349,7 @@ impl Client {
                     Url::parse("https://client1.example.com/redirect").unwrap(),
                     Url::parse("https://client1.example.com/redirect2").unwrap(),
                 ],
+                redirect_uri_matching: RedirectUriMatching::Exact,
                 response_types: vec![OAuthAuthorizationEndpointResponseType::Code],
                 grant_types: vec![GrantType::AuthorizationCode, GrantType::RefreshToken],
                 contacts: vec!["foo@client1.example.com".to_owned()],
@@ -162,7 +365,25 @@ impl Client {
                 token_endpoint_auth_signing_alg: None,
                 id_token_signed_response_alg: None,
                 userinfo_signed_response_alg: None,
+                authorization_signed_response_alg: None,
                 jwks: None,
+                require_signed_request_object: false,
+                require_pkce: None,
+                access_token_ttl: None,
+                refresh_token_ttl: None,
+                access_token_format: AccessTokenFormat::Opaque,
+                client_credentials_scope: None,
+                allowed_resources: Vec::new(),
+                introspection_endpoint_auth_method: None,
+                post_logout_redirect_uris: vec![Url::parse(
+                    "https://client1.example.com/logged-out",
+                )
+                .unwrap()],
+                backchannel_logout_uri: Some(
+                    Url::parse("https://client1.example.com/backchannel-logout").unwrap(),
+                ),
+                backchannel_logout_session_required: true,
+                trusted: false,
             },
             // Another client without any URIs set
             Self {
@@ -171,6 +392,7 @@ impl Client {
                 encrypted_client_secret: None,
                 application_type: Some(ApplicationType::Native),
                 redirect_uris: vec![Url::parse("https://client2.example.com/redirect").unwrap()],
+                redirect_uri_matching: RedirectUriMatching::Normalized,
                 response_types: vec![OAuthAuthorizationEndpointResponseType::Code],
                 grant_types: vec![GrantType::AuthorizationCode, GrantType::RefreshToken],
                 contacts: vec!["foo@client2.example.com".to_owned()],
@@ -184,7 +406,20 @@ impl Client {
                 token_endpoint_auth_signing_alg: None,
                 id_token_signed_response_alg: None,
                 userinfo_signed_response_alg: None,
+                authorization_signed_response_alg: None,
                 jwks: None,
+                require_signed_request_object: false,
+                require_pkce: None,
+                access_token_ttl: None,
+                refresh_token_ttl: None,
+                access_token_format: AccessTokenFormat::Opaque,
+                client_credentials_scope: None,
+                allowed_resources: Vec::new(),
+                introspection_endpoint_auth_method: None,
+                post_logout_redirect_uris: Vec::new(),
+                backchannel_logout_uri: None,
+                backchannel_logout_session_required: false,
+                trusted: false,
             },
         ]
     }
@@ -193,12 +428,18 @@ impl Client {
 /// The hosts that match the loopback interface.
 const LOCAL_HOSTS: &[&str] = &["localhost", "127.0.0.1", "[::1]"];
 
+/// Whether the given URI is an `http` URI on the loopback interface, on which
+/// native clients can't know the port in advance
+fn is_loopback_uri(uri: &Url) -> bool {
+    uri.scheme() == "http" && LOCAL_HOSTS.contains(&uri.host_str().unwrap_or_default())
+}
+
 /// Whether the given URI matches one of the registered URIs.
 ///
-/// If the URI host is one if `localhost`, `127.0.0.1` or `[::1]`, any port is
-/// accepted.
-fn uri_matches_one_of(uri: &Url, registered_uris: &[Url]) -> bool {
-    if LOCAL_HOSTS.contains(&uri.host_str().unwrap_or_default()) {
+/// If `any_loopback_port` is set and the URI is an `http` URI on `localhost`,
+/// `127.0.0.1` or `[::1]`, any port is accepted.
+fn uri_matches_one_of(uri: &Url, registered_uris: &[Url], any_loopback_port: bool) -> bool {
+    if any_loopback_port && is_loopback_uri(uri) {
         let mut uri = uri.clone();
         // Try matching without the port first
         if uri.set_port(None).is_ok() && registered_uris.contains(&uri) {
@@ -209,8 +450,43 @@ fn uri_matches_one_of(uri: &Url, registered_uris: &[Url]) -> bool {
     registered_uris.contains(uri)
 }
 
+/// Normalize a URI for [`RedirectUriMatching::Normalized`] comparisons, by
+/// lowercasing it and removing the trailing slash of its path.
+fn normalize_uri(uri: &Url) -> String {
+    let mut uri = uri.clone();
+    let path = uri.path().trim_end_matches('/').to_owned();
+    uri.set_path(&path);
+    uri.as_str().to_lowercase()
+}
+
+/// Whether the given URI matches one of the registered URIs once both are
+/// normalized.
+fn uri_matches_one_of_normalized(
+    uri: &Url,
+    registered_uris: &[Url],
+    any_loopback_port: bool,
+) -> bool {
+    let matches = |uri: &Url| {
+        let normalized = normalize_uri(uri);
+        registered_uris
+            .iter()
+            .any(|registered| normalize_uri(registered) == normalized)
+    };
+
+    if any_loopback_port && is_loopback_uri(uri) {
+        let mut uri = uri.clone();
+        // Try matching without the port first
+        if uri.set_port(None).is_ok() && matches(&uri) {
+            return true;
+        }
+    }
+
+    matches(uri)
+}
+
 #[cfg(test)]
 mod tests {
+    use rand::SeedableRng;
     use url::Url;
 
     use super::*;
@@ -225,25 +501,197 @@ mod tests {
         // Non-loopback interface URIs.
         assert!(uri_matches_one_of(
             &Url::parse("https://example.org").unwrap(),
-            registered_uris
+            registered_uris,
+            true,
         ));
         assert!(!uri_matches_one_of(
             &Url::parse("https://example.org:8080").unwrap(),
-            registered_uris
+            registered_uris,
+            true,
         ));
 
         // Loopback interface URIS.
         assert!(uri_matches_one_of(
             &Url::parse("http://127.0.0.1").unwrap(),
-            registered_uris
+            registered_uris,
+            true,
         ));
         assert!(uri_matches_one_of(
             &Url::parse("http://127.0.0.1:8080").unwrap(),
-            registered_uris
+            registered_uris,
+            true,
         ));
         assert!(!uri_matches_one_of(
             &Url::parse("http://localhost").unwrap(),
-            registered_uris
+            registered_uris,
+            true,
+        ));
+
+        // The port can only vary if allowed
+        assert!(uri_matches_one_of(
+            &Url::parse("http://127.0.0.1").unwrap(),
+            registered_uris,
+            false,
+        ));
+        assert!(!uri_matches_one_of(
+            &Url::parse("http://127.0.0.1:8080").unwrap(),
+            registered_uris,
+            false,
+        ));
+    }
+
+    #[test]
+    fn test_resolve_redirect_uri_matching() {
+        let now = DateTime::<Utc>::UNIX_EPOCH;
+        let mut rng = rand_chacha::ChaChaRng::seed_from_u64(42);
+        let mut client = Client::samples(now, &mut rng).remove(0);
+        client.redirect_uris = vec![Url::parse("https://app/cb").unwrap()];
+
+        let exact = Some(Url::parse("https://app/cb").unwrap());
+        let trailing_slash = Some(Url::parse("https://app/cb/").unwrap());
+        let uppercase = Some(Url::parse("https://app/CB").unwrap());
+        let other = Some(Url::parse("https://app/other").unwrap());
+
+        client.redirect_uri_matching = RedirectUriMatching::Exact;
+        assert_eq!(
+            client.resolve_redirect_uri(&exact).unwrap(),
+            exact.as_ref().unwrap()
+        );
+        assert!(matches!(
+            client.resolve_redirect_uri(&trailing_slash),
+            Err(InvalidRedirectUriError::NotExactMatch)
+        ));
+        assert!(matches!(
+            client.resolve_redirect_uri(&uppercase),
+            Err(InvalidRedirectUriError::NotExactMatch)
+        ));
+        assert!(matches!(
+            client.resolve_redirect_uri(&other),
+            Err(InvalidRedirectUriError::Mismatch)
+        ));
+
+        client.redirect_uri_matching = RedirectUriMatching::Normalized;
+        assert_eq!(
+            client.resolve_redirect_uri(&exact).unwrap(),
+            exact.as_ref().unwrap()
+        );
+        assert_eq!(
+            client.resolve_redirect_uri(&trailing_slash).unwrap(),
+            trailing_slash.as_ref().unwrap()
+        );
+        assert_eq!(
+            client.resolve_redirect_uri(&uppercase).unwrap(),
+            uppercase.as_ref().unwrap()
+        );
+        assert!(matches!(
+            client.resolve_redirect_uri(&other),
+            Err(InvalidRedirectUriError::Mismatch)
         ));
     }
+
+    #[test]
+    fn test_resolve_redirect_uri_policy() {
+        use InvalidRedirectUriError::{FragmentNotAllowed, Mismatch, NotExactMatch};
+
+        let now = DateTime::<Utc>::UNIX_EPOCH;
+        let mut rng = rand_chacha::ChaChaRng::seed_from_u64(42);
+        let mut client = Client::samples(now, &mut rng).remove(0);
+        client.redirect_uri_matching = RedirectUriMatching::Exact;
+        client.redirect_uris = vec![
+            Url::parse("https://app.example.com/cb").unwrap(),
+            Url::parse("com.example.app:/callback").unwrap(),
+            Url::parse("http://localhost/cb").unwrap(),
+            Url::parse("https://localhost/cb").unwrap(),
+        ];
+
+        let web = Some(ApplicationType::Web);
+        let native = Some(ApplicationType::Native);
+        let cases = [
+            (web, "https://app.example.com/cb", Ok(())),
+            // Trailing slashes and case differences in the path only match with
+            // normalization
+            (web, "https://app.example.com/cb/", Err(NotExactMatch)),
+            (web, "https://app.example.com/CB", Err(NotExactMatch)),
+            // Hosts are case-insensitive, and the URL parser lowercases them
+            (web, "https://APP.example.com/cb", Ok(())),
+            (web, "https://app.example.com/cb?foo=bar", Err(Mismatch)),
+            (
+                web,
+                "https://app.example.com/cb#foo",
+                Err(FragmentNotAllowed),
+            ),
+            (web, "https://app.example.com:8443/cb", Err(Mismatch)),
+            // Custom schemes, as used by mobile apps
+            (native, "com.example.app:/callback", Ok(())),
+            (native, "com.example.app:/callback/", Err(NotExactMatch)),
+            (native, "com.example.app:/other", Err(Mismatch)),
+            (native, "com.example.other:/callback", Err(Mismatch)),
+            (
+                native,
+                "com.example.app:/callback#foo",
+                Err(FragmentNotAllowed),
+            ),
+            // Native clients on the loopback interface may use any port
+            (native, "http://localhost/cb", Ok(())),
+            (native, "http://localhost:1234/cb", Ok(())),
+            (native, "http://localhost:1234/other", Err(Mismatch)),
+            (native, "https://localhost:1234/cb", Err(Mismatch)),
+            (web, "http://localhost/cb", Ok(())),
+            (web, "http://localhost:1234/cb", Err(Mismatch)),
+            // So may clients without an application type, like static ones
+            (None, "http://localhost:1234/cb", Ok(())),
+            (None, "https://app.example.com:8443/cb", Err(Mismatch)),
+        ];
+
+        for (application_type, uri, expected) in cases {
+            client.application_type = application_type;
+            let uri = Some(Url::parse(uri).unwrap());
+            let result = client.resolve_redirect_uri(&uri);
+            assert_eq!(result.as_ref().err(), expected.err().as_ref(), "{uri:?}");
+            if let Ok(resolved) = result {
+                assert_eq!(Some(resolved), uri.as_ref());
+            }
+        }
+
+        // The redirect URI can only be omitted if a single one is registered
+        assert_eq!(
+            client.resolve_redirect_uri(&None).unwrap_err(),
+            InvalidRedirectUriError::MultipleRegistered
+        );
+        client.redirect_uris.truncate(1);
+        assert_eq!(
+            client.resolve_redirect_uri(&None).unwrap(),
+            &client.redirect_uris[0]
+        );
+        client.redirect_uris.clear();
+        assert_eq!(
+            client.resolve_redirect_uri(&None).unwrap_err(),
+            InvalidRedirectUriError::NoneRegistered
+        );
+    }
+
+    #[test]
+    fn test_allows_response_type() {
+        let now = DateTime::<Utc>::UNIX_EPOCH;
+        let mut rng = rand_chacha::ChaChaRng::seed_from_u64(42);
+        let mut client = Client::samples(now, &mut rng).remove(0);
+        client.response_types = vec![
+            OAuthAuthorizationEndpointResponseType::Code,
+            OAuthAuthorizationEndpointResponseType::CodeIdToken,
+        ];
+
+        let allows =
+            |response_type: &str| client.allows_response_type(&response_type.parse().unwrap());
+
+        assert!(allows("code"));
+        assert!(allows("code id_token"));
+        // The order of the response type values doesn't matter
+        assert!(allows("id_token code"));
+
+        // A subset of an allowed response type isn't allowed by itself
+        assert!(!allows("id_token"));
+        assert!(!allows("code id_token token"));
+        assert!(!allows("code token"));
+        assert!(!allows("none"));
+    }
 }
@@ -14,7 +14,7 @@
 
 use std::net::IpAddr;
 
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Duration, Utc};
 use oauth2_types::scope::Scope;
 use serde::Serialize;
 use ulid::Ulid;
@@ -196,6 +196,14 @@ pub struct DeviceCodeGrant {
     /// The time at which this device code grant will expire.
     pub expires_at: DateTime<Utc>,
 
+    /// The last time the client polled the token endpoint with this device
+    /// code.
+    pub last_polled_at: Option<DateTime<Utc>>,
+
+    /// The minimum interval between two polls of the token endpoint. It grows
+    /// each time the client is asked to slow down.
+    pub poll_interval: Duration,
+
     /// The IP address of the client which requested this device code grant.
     pub ip_address: Option<IpAddr>,
 
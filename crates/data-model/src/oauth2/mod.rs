@@ -15,11 +15,17 @@
 mod authorization_grant;
 mod client;
 mod device_code_grant;
+mod pushed_authorization_request;
 mod session;
 
 pub use self::{
     authorization_grant::{AuthorizationCode, AuthorizationGrant, AuthorizationGrantStage, Pkce},
-    client::{Client, InvalidRedirectUriError, JwksOrJwksUri},
+    client::{
+        AccessTokenFormat, Client, InvalidRedirectUriError, JwksOrJwksUri, RedirectUriMatching,
+    },
     device_code_grant::{DeviceCodeGrant, DeviceCodeGrantState},
+    pushed_authorization_request::{
+        PushedAuthorizationRequest, PUSHED_AUTHORIZATION_REQUEST_URI_PREFIX,
+    },
     session::{Session, SessionState},
 };
@@ -0,0 +1,104 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use ulid::Ulid;
+
+/// The prefix used for the `request_uri` returned by the pushed authorization
+/// request endpoint, as defined in RFC 9126.
+pub const PUSHED_AUTHORIZATION_REQUEST_URI_PREFIX: &str = "urn:ietf:params:oauth:request_uri:";
+
+/// An authorization request pushed by a client through the pushed
+/// authorization request endpoint.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PushedAuthorizationRequest {
+    pub id: Ulid,
+
+    /// The ID of the client which pushed this request.
+    pub client_id: Ulid,
+
+    /// The parameters of the authorization request, including the `client_id`.
+    pub parameters: BTreeMap<String, String>,
+
+    /// The time at which this request was pushed.
+    pub created_at: DateTime<Utc>,
+
+    /// The time after which the `request_uri` can't be used anymore.
+    pub expires_at: DateTime<Utc>,
+}
+
+impl PushedAuthorizationRequest {
+    /// The `request_uri` the client should use to reference this request on
+    /// the authorization endpoint.
+    #[must_use]
+    pub fn request_uri(&self) -> String {
+        format!("{PUSHED_AUTHORIZATION_REQUEST_URI_PREFIX}{}", self.id)
+    }
+
+    /// Extract the ID of a pushed authorization request from a `request_uri`.
+    ///
+    /// Returns [`None`] if the `request_uri` wasn't issued by this server.
+    #[must_use]
+    pub fn id_from_request_uri(request_uri: &str) -> Option<Ulid> {
+        request_uri
+            .strip_prefix(PUSHED_AUTHORIZATION_REQUEST_URI_PREFIX)?
+            .parse()
+            .ok()
+    }
+
+    /// Whether this request has expired at the given time.
+    #[must_use]
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        now >= self.expires_at
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_uri_roundtrip() {
+        let id = Ulid::nil();
+        let now = DateTime::UNIX_EPOCH;
+        let request = PushedAuthorizationRequest {
+            id,
+            client_id: Ulid::nil(),
+            parameters: BTreeMap::new(),
+            created_at: now,
+            expires_at: now,
+        };
+
+        let request_uri = request.request_uri();
+        assert!(request_uri.starts_with("urn:ietf:params:oauth:request_uri:"));
+        assert_eq!(
+            PushedAuthorizationRequest::id_from_request_uri(&request_uri),
+            Some(id)
+        );
+
+        assert_eq!(
+            PushedAuthorizationRequest::id_from_request_uri("https://example.com/request"),
+            None
+        );
+        assert_eq!(
+            PushedAuthorizationRequest::id_from_request_uri(
+                "urn:ietf:params:oauth:request_uri:not-a-ulid"
+            ),
+            None
+        );
+    }
+}
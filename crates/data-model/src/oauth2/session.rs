@@ -18,6 +18,7 @@ use chrono::{DateTime, Utc};
 use oauth2_types::scope::Scope;
 use serde::Serialize;
 use ulid::Ulid;
+use url::Url;
 
 use crate::{InvalidTransitionError, UserAgent};
 
@@ -78,6 +79,19 @@ pub struct Session {
     pub user_agent: Option<UserAgent>,
     pub last_active_at: Option<DateTime<Utc>>,
     pub last_active_ip: Option<IpAddr>,
+
+    /// When the user authenticated for the authorization which created this
+    /// session, as reported by the `auth_time` claim of the ID tokens
+    pub auth_time: Option<DateTime<Utc>>,
+
+    /// The resources the access tokens of this session are restricted to, as
+    /// requested with resource indicators. If empty, the tokens are not
+    /// audience-restricted
+    pub resource: Vec<Url>,
+
+    /// The JWK SHA-256 thumbprint of the `DPoP` key the refresh tokens of this
+    /// session are bound to, if any
+    pub dpop_jkt: Option<String>,
 }
 
 impl std::ops::Deref for Session {
@@ -0,0 +1,155 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Single-use tokens to reset the password of a user.
+//!
+//! A token is made of the ID of the stored token, and of a random secret of
+//! which only a hash is stored, separated by a dot. The ID is used to find the
+//! stored token, and the secret is then compared to the stored hash in
+//! constant time.
+
+use base64ct::{Base64, Encoding};
+use chrono::{DateTime, Utc};
+use rand::{distributions::Alphanumeric, Rng};
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+use thiserror::Error;
+use ulid::Ulid;
+
+use crate::UserPasswordResetToken;
+
+/// Length of the secret part of the tokens
+const SECRET_LENGTH: usize = 32;
+
+/// Why a password reset token can't be used
+#[derive(Debug, Error, PartialEq, Eq, Clone, Copy)]
+pub enum ResetError {
+    /// The token is malformed, or doesn't match any stored token
+    #[error("unknown password reset token")]
+    UnknownToken,
+
+    /// The token expired
+    #[error("password reset token expired")]
+    ExpiredToken,
+
+    /// The token was already used
+    #[error("password reset token already used")]
+    AlreadyUsed,
+}
+
+/// Generate a new secret for a password reset token
+pub fn generate_secret(rng: &mut impl Rng) -> String {
+    rng.sample_iter(&Alphanumeric)
+        .take(SECRET_LENGTH)
+        .map(char::from)
+        .collect()
+}
+
+/// Hash the secret of a password reset token for storage
+///
+/// Secrets are random and long enough that a fast hash is sufficient.
+#[must_use]
+pub fn hash_secret(secret: &str) -> String {
+    Base64::encode_string(&Sha256::digest(secret.as_bytes()))
+}
+
+/// Format the token given to the user, from the ID of the stored token and its
+/// secret
+#[must_use]
+pub fn format_token(id: Ulid, secret: &str) -> String {
+    format!("{id}.{secret}")
+}
+
+/// Split a token in the ID of the stored token and its secret
+///
+/// # Errors
+///
+/// Returns [`ResetError::UnknownToken`] if the token is malformed
+pub fn parse_token(token: &str) -> Result<(Ulid, &str), ResetError> {
+    let (id, secret) = token.split_once('.').ok_or(ResetError::UnknownToken)?;
+    let id = id.parse().map_err(|_| ResetError::UnknownToken)?;
+    Ok((id, secret))
+}
+
+/// Check that a secret matches a stored token, and that the token can still be
+/// used at the given time
+///
+/// # Errors
+///
+/// Returns a [`ResetError`] explaining why the token can't be used
+pub fn verify(
+    token: &UserPasswordResetToken,
+    secret: &str,
+    now: DateTime<Utc>,
+) -> Result<(), ResetError> {
+    let hashed_secret = hash_secret(secret);
+    if !bool::from(
+        hashed_secret
+            .as_bytes()
+            .ct_eq(token.hashed_secret.as_bytes()),
+    ) {
+        return Err(ResetError::UnknownToken);
+    }
+
+    if token.is_consumed() {
+        return Err(ResetError::AlreadyUsed);
+    }
+
+    if token.is_expired(now) {
+        return Err(ResetError::ExpiredToken);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use rand::SeedableRng;
+
+    use super::*;
+
+    #[test]
+    fn test_token() {
+        let mut rng = rand_chacha::ChaChaRng::seed_from_u64(42);
+        let now = DateTime::<Utc>::UNIX_EPOCH;
+        let id = Ulid::from_datetime_with_source(now.into(), &mut rng);
+        let secret = generate_secret(&mut rng);
+        assert_eq!(secret.len(), SECRET_LENGTH);
+
+        let token = format_token(id, &secret);
+        assert_eq!(parse_token(&token), Ok((id, secret.as_str())));
+        assert_eq!(parse_token("garbage"), Err(ResetError::UnknownToken));
+        assert_eq!(parse_token("garbage.secret"), Err(ResetError::UnknownToken));
+
+        let mut stored = UserPasswordResetToken {
+            id,
+            user_id: Ulid::nil(),
+            hashed_secret: hash_secret(&secret),
+            created_at: now,
+            expires_at: now + Duration::try_hours(1).unwrap(),
+            consumed_at: None,
+        };
+
+        assert_eq!(verify(&stored, &secret, now), Ok(()));
+        assert_eq!(verify(&stored, "wrong", now), Err(ResetError::UnknownToken));
+        assert_eq!(
+            verify(&stored, &secret, stored.expires_at),
+            Err(ResetError::ExpiredToken)
+        );
+
+        stored.consumed_at = Some(now);
+        assert_eq!(verify(&stored, &secret, now), Err(ResetError::AlreadyUsed));
+    }
+}
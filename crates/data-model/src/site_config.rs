@@ -13,8 +13,31 @@
 // limitations under the License.
 
 use chrono::Duration;
+use mas_iana::oauth::PkceCodeChallengeMethod;
 use url::Url;
 
+/// A service account which can call the GraphQL API with a static token.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServiceAccount {
+    /// The name of the service account.
+    pub name: String,
+
+    /// The SHA-256 hash of the static token used by the service account.
+    pub token_sha256: [u8; 32],
+}
+
+/// What to do when a user starting a new browser session already has as many
+/// active browser sessions as allowed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BrowserSessionLimitPolicy {
+    /// Finish the oldest active browser sessions of the user.
+    #[default]
+    EvictOldest,
+
+    /// Refuse to start the new browser session.
+    RejectNew,
+}
+
 /// Random site configuration we want accessible in various places.
 #[allow(clippy::struct_excessive_bools)]
 #[derive(Debug, Clone)]
@@ -22,9 +45,34 @@ pub struct SiteConfig {
     /// Time-to-live of access tokens.
     pub access_token_ttl: Duration,
 
+    /// Time-to-live of refresh tokens, if they expire.
+    pub refresh_token_ttl: Option<Duration>,
+
     /// Time-to-live of compatibility access tokens.
     pub compat_token_ttl: Duration,
 
+    /// Maximum lifetime of authorization codes.
+    pub authorization_code_ttl: Duration,
+
+    /// Time-to-live of pushed authorization requests.
+    pub pushed_authorization_request_ttl: Duration,
+
+    /// How long clients may cache the discovery documents.
+    pub discovery_cache_ttl: Duration,
+
+    /// How long users have to complete a login with an upstream provider.
+    pub upstream_oauth2_session_max_age: Duration,
+
+    /// How many upstream logins a browser can have in progress at once.
+    pub upstream_oauth2_max_pending_sessions: usize,
+
+    /// PKCE code challenge methods clients are allowed to use.
+    pub allowed_code_challenge_methods: Vec<PkceCodeChallengeMethod>,
+
+    /// Whether the `token` and `id_token` response types are rejected for all
+    /// clients.
+    pub disable_implicit_flow: bool,
+
     /// The server name, e.g. "matrix.org".
     pub server_name: String,
 
@@ -51,4 +99,26 @@ pub struct SiteConfig {
 
     /// Whether users can change their password.
     pub password_change_allowed: bool,
+
+    /// Whether users can request a link to reset their password by email.
+    pub password_reset_enabled: bool,
+
+    /// How long the password reset links are valid for.
+    pub password_reset_token_ttl: Duration,
+
+    /// Service accounts which can call the GraphQL API with a static token.
+    pub service_accounts: Vec<ServiceAccount>,
+
+    /// Maximum number of active browser sessions a user can have at once.
+    pub max_active_browser_sessions: Option<usize>,
+
+    /// What to do when a user goes over `max_active_browser_sessions`.
+    pub browser_session_limit_policy: BrowserSessionLimitPolicy,
+
+    /// Time-to-live of browser sessions when the user did not ask to be
+    /// remembered.
+    pub session_ttl: Duration,
+
+    /// Time-to-live of browser sessions when the user asked to be remembered.
+    pub remembered_session_ttl: Duration,
 }
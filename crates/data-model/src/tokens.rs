@@ -15,9 +15,13 @@
 use chrono::{DateTime, Utc};
 use crc::{Crc, CRC_32_ISO_HDLC};
 use mas_iana::oauth::OAuthTokenTypeHint;
+use mas_jose::{jwk::PublicJsonWebKeySet, jwt::Jwt};
+use oauth2_types::scope::Scope;
 use rand::{distributions::Alphanumeric, Rng, RngCore};
+use serde::Deserialize;
 use thiserror::Error;
 use ulid::Ulid;
+use url::Url;
 
 use crate::InvalidTransitionError;
 
@@ -63,6 +67,18 @@ pub struct AccessToken {
     pub access_token: String,
     pub created_at: DateTime<Utc>,
     pub expires_at: Option<DateTime<Utc>>,
+
+    /// The JWK SHA-256 thumbprint of the `DPoP` key this token is bound to, if
+    /// any
+    pub dpop_jkt: Option<String>,
+
+    /// The scope of this access token, if it was narrowed down from the scope
+    /// of its session when it was issued
+    pub scope: Option<Scope>,
+
+    /// The resources this access token is restricted to, if they were narrowed
+    /// down from the resources of its session when it was issued
+    pub resource: Option<Vec<Url>>,
 }
 
 impl AccessToken {
@@ -71,6 +87,90 @@ impl AccessToken {
         self.id.to_string()
     }
 
+    /// The scope this access token grants, given the scope of its session
+    #[must_use]
+    pub fn scope<'a>(&'a self, session_scope: &'a Scope) -> &'a Scope {
+        self.scope.as_ref().unwrap_or(session_scope)
+    }
+
+    /// Get the ID of the access token a JWT access token was issued for, from
+    /// its `jti` claim
+    ///
+    /// Returns `None` if the token is not a JWT, like opaque access tokens.
+    ///
+    /// # Parameters
+    ///
+    /// * `token` - The token presented by the client
+    /// * `jwks` - The keys the JWT access tokens are signed with
+    /// * `issuer` - The issuer of the JWT access tokens
+    /// * `now` - The current time
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the token is a JWT which is not a JWT access token,
+    /// which was not signed by one of the given keys, which was issued by
+    /// another issuer, which expired, or which doesn't have a valid `jti`
+    /// claim
+    pub fn id_from_jwt(
+        token: &str,
+        jwks: &PublicJsonWebKeySet,
+        issuer: &Url,
+        now: DateTime<Utc>,
+    ) -> Result<Option<Ulid>, InvalidJwtAccessToken> {
+        let Ok(jwt) = Jwt::<JwtClaims>::try_from(token) else {
+            return Ok(None);
+        };
+
+        // Other kinds of JWTs signed by the service, like ID tokens, must not
+        // be usable as access tokens
+        if jwt.header().typ() != Some("at+jwt") {
+            return Err(InvalidJwtAccessToken);
+        }
+
+        jwt.verify_with_jwks(jwks)
+            .map_err(|_| InvalidJwtAccessToken)?;
+
+        let claims = jwt.payload();
+        if claims.iss != issuer.as_str() || claims.exp.is_some_and(|exp| exp <= now) {
+            return Err(InvalidJwtAccessToken);
+        }
+
+        let id = claims.jti.parse().map_err(|_| InvalidJwtAccessToken)?;
+
+        Ok(Some(id))
+    }
+
+    /// Whether a token presented by a client is this access token, either as
+    /// the opaque access token or as a JWT access token issued for it
+    ///
+    /// The signature of JWT access tokens is not checked here, so this must
+    /// only be used on a token which was already used to find this access
+    /// token.
+    #[must_use]
+    pub fn is_presented_as(&self, token: &str) -> bool {
+        if token == self.access_token {
+            return true;
+        }
+
+        Jwt::<JwtClaims>::try_from(token).is_ok_and(|jwt| jwt.payload().jti == self.jti())
+    }
+
+    /// The resources this access token is restricted to, given the resources
+    /// of its session. If empty, the access token is not audience-restricted
+    #[must_use]
+    pub fn resource<'a>(&'a self, session_resource: &'a [Url]) -> &'a [Url] {
+        self.resource.as_deref().unwrap_or(session_resource)
+    }
+
+    /// Whether this access token can be used on the given resource, given the
+    /// resources of its session: either it is not audience-restricted, or the
+    /// resource is one of those it is restricted to
+    #[must_use]
+    pub fn allows_resource(&self, session_resource: &[Url], resource: &Url) -> bool {
+        let restricted = self.resource(session_resource);
+        restricted.is_empty() || restricted.contains(resource)
+    }
+
     /// Whether the access token is valid, i.e. not revoked and not expired
     ///
     /// # Parameters
@@ -117,6 +217,10 @@ pub enum RefreshTokenState {
     Valid,
     Consumed {
         consumed_at: DateTime<Utc>,
+        next_refresh_token_id: Option<Ulid>,
+    },
+    Revoked {
+        revoked_at: DateTime<Utc>,
     },
 }
 
@@ -125,11 +229,30 @@ impl RefreshTokenState {
     ///
     /// # Errors
     ///
-    /// Returns an error if the refresh token is already consumed.
-    fn consume(self, consumed_at: DateTime<Utc>) -> Result<Self, InvalidTransitionError> {
+    /// Returns an error if the refresh token is already consumed or revoked.
+    fn consume(
+        self,
+        consumed_at: DateTime<Utc>,
+        replaced_by: &RefreshToken,
+    ) -> Result<Self, InvalidTransitionError> {
         match self {
-            Self::Valid => Ok(Self::Consumed { consumed_at }),
-            Self::Consumed { .. } => Err(InvalidTransitionError),
+            Self::Valid => Ok(Self::Consumed {
+                consumed_at,
+                next_refresh_token_id: Some(replaced_by.id),
+            }),
+            Self::Consumed { .. } | Self::Revoked { .. } => Err(InvalidTransitionError),
+        }
+    }
+
+    /// Revoke the refresh token, returning a new state.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the refresh token is already consumed or revoked.
+    fn revoke(self, revoked_at: DateTime<Utc>) -> Result<Self, InvalidTransitionError> {
+        match self {
+            Self::Valid => Ok(Self::Revoked { revoked_at }),
+            Self::Consumed { .. } | Self::Revoked { .. } => Err(InvalidTransitionError),
         }
     }
 
@@ -148,6 +271,27 @@ impl RefreshTokenState {
     pub fn is_consumed(&self) -> bool {
         matches!(self, Self::Consumed { .. })
     }
+
+    /// Returns the ID of the refresh token which replaced this one, if it was
+    /// consumed by rotating it.
+    #[must_use]
+    pub fn next_refresh_token_id(&self) -> Option<Ulid> {
+        match self {
+            Self::Consumed {
+                next_refresh_token_id,
+                ..
+            } => *next_refresh_token_id,
+            Self::Valid | Self::Revoked { .. } => None,
+        }
+    }
+
+    /// Returns `true` if the refresh token state is [`Revoked`].
+    ///
+    /// [`Revoked`]: RefreshTokenState::Revoked
+    #[must_use]
+    pub fn is_revoked(&self) -> bool {
+        matches!(self, Self::Revoked { .. })
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -176,11 +320,30 @@ impl RefreshToken {
 
     /// Consumes the refresh token and returns the consumed token.
     ///
+    /// # Parameters
+    ///
+    /// * `consumed_at` - The time at which the refresh token was consumed
+    /// * `replaced_by` - The refresh token which replaces this one
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the refresh token is already consumed or revoked.
+    pub fn consume(
+        mut self,
+        consumed_at: DateTime<Utc>,
+        replaced_by: &Self,
+    ) -> Result<Self, InvalidTransitionError> {
+        self.state = self.state.consume(consumed_at, replaced_by)?;
+        Ok(self)
+    }
+
+    /// Revokes the refresh token and returns the revoked token.
+    ///
     /// # Errors
     ///
-    /// Returns an error if the refresh token is already consumed.
-    pub fn consume(mut self, consumed_at: DateTime<Utc>) -> Result<Self, InvalidTransitionError> {
-        self.state = self.state.consume(consumed_at)?;
+    /// Returns an error if the refresh token is already consumed or revoked.
+    pub fn revoke(mut self, revoked_at: DateTime<Utc>) -> Result<Self, InvalidTransitionError> {
+        self.state = self.state.revoke(revoked_at)?;
         Ok(self)
     }
 }
@@ -348,6 +511,21 @@ fn base62_encode(mut num: u32) -> String {
 const CRC: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
 
 /// Invalid token
+/// The claims of JWT access tokens used to find the access token they were
+/// issued for
+#[derive(Deserialize)]
+struct JwtClaims {
+    jti: String,
+    iss: String,
+    #[serde(default, with = "chrono::serde::ts_seconds_option")]
+    exp: Option<DateTime<Utc>>,
+}
+
+/// A JWT access token was not signed by the service or is malformed
+#[derive(Debug, Error, PartialEq, Eq)]
+#[error("invalid JWT access token")]
+pub struct InvalidJwtAccessToken;
+
 #[derive(Debug, Error, PartialEq, Eq)]
 pub enum TokenFormatError {
     /// Overall token format is invalid
@@ -29,14 +29,24 @@ pub struct User {
     pub primary_user_email_id: Option<Ulid>,
     pub created_at: DateTime<Utc>,
     pub locked_at: Option<DateTime<Utc>>,
+    pub deactivated_at: Option<DateTime<Utc>>,
     pub can_request_admin: bool,
+    /// Whether the user is an administrator of this server, which lets them
+    /// manage other users from their browser session
+    pub is_admin: bool,
 }
 
 impl User {
-    /// Returns `true` unless the user is locked.
+    /// Returns `true` unless the user is locked or deactivated.
     #[must_use]
     pub fn is_valid(&self) -> bool {
-        self.locked_at.is_none()
+        self.locked_at.is_none() && self.deactivated_at.is_none()
+    }
+
+    /// Returns `true` if the user was deactivated.
+    #[must_use]
+    pub fn is_deactivated(&self) -> bool {
+        self.deactivated_at.is_some()
     }
 }
 
@@ -51,7 +61,9 @@ impl User {
             primary_user_email_id: None,
             created_at: now,
             locked_at: None,
+            deactivated_at: None,
             can_request_admin: false,
+            is_admin: false,
         }]
     }
 }
@@ -74,18 +86,135 @@ pub struct Authentication {
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub enum AuthenticationMethod {
-    Password { user_password_id: Ulid },
-    UpstreamOAuth2 { upstream_oauth2_session_id: Ulid },
+    Password {
+        user_password_id: Ulid,
+    },
+    UpstreamOAuth2 {
+        upstream_oauth2_session_id: Ulid,
+        /// The upstream link the user authenticated with, if it is known
+        upstream_oauth_link_id: Option<Ulid>,
+    },
+    WebAuthn {
+        user_webauthn_credential_id: Ulid,
+    },
+    Totp {
+        user_totp_id: Ulid,
+    },
+    RecoveryCode {
+        user_recovery_code_id: Ulid,
+    },
     Unknown,
 }
 
+impl AuthenticationMethod {
+    /// Returns the authentication method reference value, as defined by RFC
+    /// 8176, if there is one matching this method.
+    #[must_use]
+    pub fn amr(&self) -> Option<&'static str> {
+        match self {
+            Self::Password { .. } => Some("pwd"),
+            Self::WebAuthn { .. } => Some("hwk"),
+            Self::Totp { .. } | Self::RecoveryCode { .. } => Some("otp"),
+            Self::UpstreamOAuth2 { .. } | Self::Unknown => None,
+        }
+    }
+}
+
+/// A WebAuthn credential (security key or passkey) registered by a user
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct WebAuthnCredential {
+    pub id: Ulid,
+    pub user_id: Ulid,
+
+    /// The credential ID, as chosen by the authenticator
+    pub credential_id: Vec<u8>,
+
+    /// The COSE-encoded public key of the credential
+    pub public_key: Vec<u8>,
+
+    /// The last signature counter reported by the authenticator
+    pub sign_count: u32,
+
+    /// The AAGUID of the authenticator model which created the credential
+    pub aaguid: [u8; 16],
+
+    pub created_at: DateTime<Utc>,
+}
+
+/// A TOTP (RFC 6238) second factor enrolled by a user
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Totp {
+    pub id: Ulid,
+    pub user_id: Ulid,
+
+    /// The shared secret, encrypted at rest
+    pub encrypted_secret: String,
+
+    pub created_at: DateTime<Utc>,
+
+    /// The last time step counter which was successfully used, to prevent
+    /// replaying codes
+    pub last_used_counter: Option<u64>,
+}
+
+/// A single-use recovery code, which can be used instead of a TOTP code
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct UserRecoveryCode {
+    pub id: Ulid,
+    pub user_id: Ulid,
+
+    /// The hash of the recovery code
+    pub hashed_code: String,
+
+    pub created_at: DateTime<Utc>,
+    pub consumed_at: Option<DateTime<Utc>>,
+}
+
+impl UserRecoveryCode {
+    /// Whether the recovery code was already used
+    #[must_use]
+    pub fn is_consumed(&self) -> bool {
+        self.consumed_at.is_some()
+    }
+}
+
+/// A single-use, time-limited token to reset the password of a user
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct UserPasswordResetToken {
+    pub id: Ulid,
+    pub user_id: Ulid,
+
+    /// The hash of the secret part of the token
+    pub hashed_secret: String,
+
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub consumed_at: Option<DateTime<Utc>>,
+}
+
+impl UserPasswordResetToken {
+    /// Whether the token was already used
+    #[must_use]
+    pub fn is_consumed(&self) -> bool {
+        self.consumed_at.is_some()
+    }
+
+    /// Whether the token expired at the given time
+    #[must_use]
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at <= now
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub struct BrowserSession {
     pub id: Ulid,
     pub user: User,
     pub created_at: DateTime<Utc>,
     pub finished_at: Option<DateTime<Utc>>,
+    pub expires_at: Option<DateTime<Utc>>,
     pub user_agent: Option<UserAgent>,
+    pub ip_address: Option<IpAddr>,
     pub last_active_at: Option<DateTime<Utc>>,
     pub last_active_ip: Option<IpAddr>,
 }
@@ -95,6 +224,16 @@ impl BrowserSession {
     pub fn active(&self) -> bool {
         self.finished_at.is_none() && self.user.is_valid()
     }
+
+    /// Consider the session as finished when it expired, if it was not
+    /// finished before that
+    #[must_use]
+    pub fn finish_if_expired(mut self, now: DateTime<Utc>) -> Self {
+        if self.finished_at.is_none() {
+            self.finished_at = self.expires_at.filter(|expires_at| *expires_at <= now);
+        }
+        self
+    }
 }
 
 impl BrowserSession {
@@ -107,9 +246,11 @@ impl BrowserSession {
                 user,
                 created_at: now,
                 finished_at: None,
+                expires_at: None,
                 user_agent: Some(UserAgent::parse(
                     "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/93.0.0.0 Safari/537.36".to_owned()
                 )),
+                ip_address: None,
                 last_active_at: Some(now),
                 last_active_ip: None,
             })
@@ -180,6 +321,10 @@ impl Deref for UserEmailVerification {
 }
 
 impl UserEmailVerification {
+    /// How many wrong codes can be submitted before the pending codes of an
+    /// email address are invalidated
+    pub const MAX_FAILED_ATTEMPTS: u32 = 5;
+
     #[doc(hidden)]
     #[must_use]
     pub fn samples(now: chrono::DateTime<Utc>, rng: &mut impl Rng) -> Vec<Self> {
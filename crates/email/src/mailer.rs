@@ -18,7 +18,7 @@ use lettre::{
     message::{Mailbox, MessageBuilder, MultiPart},
     AsyncTransport, Message,
 };
-use mas_templates::{EmailVerificationContext, Templates, WithLanguage};
+use mas_templates::{EmailPasswordResetContext, EmailVerificationContext, Templates, WithLanguage};
 use thiserror::Error;
 
 use crate::MailTransport;
@@ -112,6 +112,55 @@ impl Mailer {
         Ok(())
     }
 
+    fn prepare_password_reset_email(
+        &self,
+        to: Mailbox,
+        context: &WithLanguage<EmailPasswordResetContext>,
+    ) -> Result<Message, Error> {
+        let plain = self.templates.render_email_password_reset_txt(context)?;
+
+        let html = self.templates.render_email_password_reset_html(context)?;
+
+        let multipart = MultiPart::alternative_plain_html(plain, html);
+
+        let subject = self
+            .templates
+            .render_email_password_reset_subject(context)?;
+
+        let message = self
+            .base_message()
+            .subject(subject.trim())
+            .to(to)
+            .multipart(multipart)?;
+
+        Ok(message)
+    }
+
+    /// Send a password reset link to a user
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the email failed rendering or failed sending
+    #[tracing::instrument(
+        name = "email.password_reset.send",
+        skip_all,
+        fields(
+            email.to = %to,
+            email.language = %context.language(),
+            user.id = %context.user().id,
+        ),
+        err,
+    )]
+    pub async fn send_password_reset_email(
+        &self,
+        to: Mailbox,
+        context: &WithLanguage<EmailPasswordResetContext>,
+    ) -> Result<(), Error> {
+        let message = self.prepare_password_reset_email(to, context)?;
+        self.transport.send(message).await?;
+        Ok(())
+    }
+
     /// Test the connetion to the mail server
     ///
     /// # Errors
@@ -16,7 +16,8 @@
 #![allow(clippy::module_name_repetitions, clippy::unused_async)]
 
 use async_graphql::EmptySubscription;
-use mas_data_model::{BrowserSession, Session, User};
+use mas_data_model::{AuditAction, BrowserSession, Session, User};
+use mas_storage::audit_log::AuditLogParams;
 use ulid::Ulid;
 
 mod model;
@@ -25,10 +26,10 @@ mod query;
 mod state;
 
 pub use self::{
-    model::{CreationEvent, Node},
+    model::{CreationEvent, LastAuthenticationLoader, Node},
     mutations::Mutation,
     query::Query,
-    state::{BoxState, State},
+    state::{BoxState, EndingSessions, PendingLogouts, State},
 };
 
 pub type Schema = async_graphql::Schema<Query, Mutation, EmptySubscription>;
@@ -53,6 +54,12 @@ pub enum Requester {
 
     /// The requester is a OAuth2 session, with an access token.
     OAuth2Session(Box<(Session, Option<User>)>),
+
+    /// The requester is a service account, authenticated with a static token.
+    ServiceAccount {
+        /// The name of the service account, as set in the configuration.
+        name: String,
+    },
 }
 
 trait OwnerId {
@@ -108,7 +115,7 @@ impl Requester {
     fn browser_session(&self) -> Option<&BrowserSession> {
         match self {
             Self::BrowserSession(session) => Some(session),
-            Self::OAuth2Session(_) | Self::Anonymous => None,
+            Self::OAuth2Session(_) | Self::ServiceAccount { .. } | Self::Anonymous => None,
         }
     }
 
@@ -116,21 +123,37 @@ impl Requester {
         match self {
             Self::BrowserSession(session) => Some(&session.user),
             Self::OAuth2Session(tuple) => tuple.1.as_ref(),
-            Self::Anonymous => None,
+            Self::ServiceAccount { .. } | Self::Anonymous => None,
         }
     }
 
     fn oauth2_session(&self) -> Option<&Session> {
         match self {
             Self::OAuth2Session(tuple) => Some(&tuple.0),
-            Self::BrowserSession(_) | Self::Anonymous => None,
+            Self::BrowserSession(_) | Self::ServiceAccount { .. } | Self::Anonymous => None,
+        }
+    }
+
+    /// Build the parameters of an audit log entry for an action performed by
+    /// the requester.
+    fn audit_log_params(&self, action: AuditAction) -> AuditLogParams {
+        let mut params = AuditLogParams::new(action);
+
+        if let Some(user) = self.user() {
+            params = params.with_actor(user);
+        }
+
+        match self {
+            Self::BrowserSession(session) => params.with_actor_browser_session(session),
+            Self::OAuth2Session(tuple) => params.with_actor_oauth2_session(&tuple.0),
+            Self::ServiceAccount { .. } | Self::Anonymous => params,
         }
     }
 
     /// Returns true if the requester can access the resource.
-    fn is_owner_or_admin(&self, resource: &impl OwnerId) -> bool {
+    fn is_owner_or_admin(&self, admin_scopes: &[String], resource: &impl OwnerId) -> bool {
         // If the requester is an admin, they can do anything.
-        if self.is_admin() {
+        if self.is_admin(admin_scopes) {
             return true;
         }
 
@@ -146,14 +169,20 @@ impl Requester {
         user.id == owner_id
     }
 
-    fn is_admin(&self) -> bool {
+    /// Returns true if the requester is an administrator.
+    ///
+    /// OAuth 2.0 sessions are administrators if they have any of the given
+    /// scopes, which have to be in sync with the policy. Browser sessions are
+    /// administrators if their user is an administrator.
+    fn is_admin(&self, admin_scopes: &[String]) -> bool {
         match self {
-            Self::OAuth2Session(tuple) => {
-                // TODO: is this the right scope?
-                // This has to be in sync with the policy
-                tuple.0.scope.contains("urn:mas:admin")
-            }
-            Self::BrowserSession(_) | Self::Anonymous => false,
+            Self::OAuth2Session(tuple) => admin_scopes
+                .iter()
+                .any(|scope| tuple.0.scope.contains(scope.as_str())),
+            Self::BrowserSession(session) => session.user.is_admin,
+            // Service accounts are configured by the server administrator
+            Self::ServiceAccount { .. } => true,
+            Self::Anonymous => false,
         }
     }
 }
@@ -172,3 +201,101 @@ where
         session.map(Into::into).unwrap_or_default()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, Utc};
+    use mas_data_model::SessionState;
+
+    use super::*;
+
+    fn oauth2_session_requester(scope: &str) -> Requester {
+        let session = Session {
+            id: Ulid::nil(),
+            state: SessionState::Valid,
+            created_at: DateTime::<Utc>::UNIX_EPOCH,
+            user_id: None,
+            user_session_id: None,
+            client_id: Ulid::nil(),
+            scope: scope.parse().unwrap(),
+            user_agent: None,
+            last_active_at: None,
+            last_active_ip: None,
+            auth_time: None,
+            resource: Vec::new(),
+            dpop_jkt: None,
+        };
+
+        Requester::OAuth2Session(Box::new((session, None)))
+    }
+
+    fn browser_session_requester(user_id: Ulid, is_admin: bool) -> Requester {
+        let user = User {
+            id: user_id,
+            username: "alice".to_owned(),
+            sub: user_id.to_string(),
+            primary_user_email_id: None,
+            created_at: DateTime::<Utc>::UNIX_EPOCH,
+            locked_at: None,
+            deactivated_at: None,
+            // Being able to request admin access alone doesn't grant it
+            can_request_admin: true,
+            is_admin,
+        };
+
+        let session = BrowserSession {
+            id: Ulid::nil(),
+            user,
+            created_at: DateTime::<Utc>::UNIX_EPOCH,
+            finished_at: None,
+            expires_at: None,
+            user_agent: None,
+            ip_address: None,
+            last_active_at: None,
+            last_active_ip: None,
+        };
+
+        Requester::from(session)
+    }
+
+    #[test]
+    fn test_admin_scopes() {
+        let default_scopes = ["urn:mas:admin".to_owned()];
+        let custom_scopes = [
+            "urn:example:admin".to_owned(),
+            "urn:example:root".to_owned(),
+        ];
+
+        let requester = oauth2_session_requester("openid urn:mas:admin");
+        assert!(requester.is_admin(&default_scopes));
+        assert!(!requester.is_admin(&custom_scopes));
+
+        // Any of the configured scopes confers admin
+        let requester = oauth2_session_requester("openid urn:example:root");
+        assert!(requester.is_admin(&custom_scopes));
+        assert!(!requester.is_admin(&default_scopes));
+        assert!(requester.is_owner_or_admin(&custom_scopes, &UserId(Ulid::nil())));
+
+        let requester = oauth2_session_requester("openid");
+        assert!(!requester.is_admin(&custom_scopes));
+        assert!(!requester.is_admin(&[]));
+    }
+
+    #[test]
+    fn test_browser_session_admin() {
+        let admin_scopes = ["urn:mas:admin".to_owned()];
+        let alice = Ulid::from_parts(1, 1);
+        let bob = Ulid::from_parts(1, 2);
+
+        // A regular user can only access their own resources
+        let requester = browser_session_requester(alice, false);
+        assert!(!requester.is_admin(&admin_scopes));
+        assert!(requester.is_owner_or_admin(&admin_scopes, &UserId(alice)));
+        assert!(!requester.is_owner_or_admin(&admin_scopes, &UserId(bob)));
+
+        // An admin user can access anyone's resources from their browser session
+        let requester = browser_session_requester(alice, true);
+        assert!(requester.is_admin(&admin_scopes));
+        assert!(requester.is_owner_or_admin(&admin_scopes, &UserId(bob)));
+    }
+}
@@ -0,0 +1,113 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use async_graphql::{Description, Enum, Object, ID};
+use chrono::{DateTime, Utc};
+
+use super::NodeType;
+
+/// A security-sensitive action recorded in the audit log.
+#[derive(Enum, Copy, Clone, Eq, PartialEq)]
+pub enum AuditAction {
+    /// A user logged in.
+    UserLoggedIn,
+
+    /// A user logged out.
+    UserLoggedOut,
+
+    /// The password of a user was changed.
+    PasswordChanged,
+
+    /// An access token was issued to a client.
+    TokenIssued,
+
+    /// A token was revoked by a client.
+    TokenRevoked,
+
+    /// A user was locked by an administrator.
+    UserLocked,
+
+    /// A user was unlocked by an administrator.
+    UserUnlocked,
+
+    /// A user was deactivated by an administrator.
+    UserDeactivated,
+
+    /// A user was reactivated by an administrator.
+    UserReactivated,
+}
+
+impl From<mas_data_model::AuditAction> for AuditAction {
+    fn from(action: mas_data_model::AuditAction) -> Self {
+        match action {
+            mas_data_model::AuditAction::UserLoggedIn => Self::UserLoggedIn,
+            mas_data_model::AuditAction::UserLoggedOut => Self::UserLoggedOut,
+            mas_data_model::AuditAction::PasswordChanged => Self::PasswordChanged,
+            mas_data_model::AuditAction::TokenIssued => Self::TokenIssued,
+            mas_data_model::AuditAction::TokenRevoked => Self::TokenRevoked,
+            mas_data_model::AuditAction::UserLocked => Self::UserLocked,
+            mas_data_model::AuditAction::UserUnlocked => Self::UserUnlocked,
+            mas_data_model::AuditAction::UserDeactivated => Self::UserDeactivated,
+            mas_data_model::AuditAction::UserReactivated => Self::UserReactivated,
+        }
+    }
+}
+
+/// An entry of the audit log, recording a security-sensitive action.
+#[derive(Description)]
+pub struct AuditLogEntry(pub mas_data_model::AuditLogEntry);
+
+#[Object(use_type_description)]
+impl AuditLogEntry {
+    /// ID of the object.
+    pub async fn id(&self) -> ID {
+        NodeType::AuditLogEntry.id(self.0.id)
+    }
+
+    /// When the action was performed.
+    pub async fn created_at(&self) -> DateTime<Utc> {
+        self.0.created_at
+    }
+
+    /// The action which was performed.
+    pub async fn action(&self) -> AuditAction {
+        self.0.action.into()
+    }
+
+    /// The ID of the user who performed the action, if any.
+    pub async fn actor_id(&self) -> Option<ID> {
+        self.0.actor_user_id.map(|id| NodeType::User.id(id))
+    }
+
+    /// The ID of the browser or OAuth 2.0 session used to perform the action,
+    /// if any.
+    pub async fn actor_session_id(&self) -> Option<ID> {
+        let id = self.0.actor_session_id?;
+        let node_type = match self.0.actor_session_kind? {
+            mas_data_model::AuditSessionKind::Browser => NodeType::BrowserSession,
+            mas_data_model::AuditSessionKind::OAuth2 => NodeType::OAuth2Session,
+        };
+        Some(node_type.id(id))
+    }
+
+    /// The ID of the user affected by the action, if any.
+    pub async fn target_id(&self) -> Option<ID> {
+        self.0.target_user_id.map(|id| NodeType::User.id(id))
+    }
+
+    /// Additional details about the action, as a JSON-encoded object.
+    pub async fn metadata(&self) -> String {
+        self.0.metadata.to_string()
+    }
+}
@@ -12,21 +12,26 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::collections::HashMap;
+
 use async_graphql::{
     connection::{query, Connection, Edge, OpaqueCursor},
-    Context, Description, Object, ID,
+    dataloader::{DataLoader, Loader},
+    Context, Description, Enum, Object, ID,
 };
 use chrono::{DateTime, Utc};
 use mas_data_model::Device;
 use mas_storage::{
-    app_session::AppSessionFilter, user::BrowserSessionRepository, Pagination, RepositoryAccess,
+    app_session::AppSessionFilter, user::BrowserSessionRepository, Clock, Pagination,
+    RepositoryAccess,
 };
+use ulid::Ulid;
 
 use super::{
     AppSession, CompatSession, Cursor, NodeCursor, NodeType, OAuth2Session, PreloadedTotalCount,
     SessionState, User, UserAgent,
 };
-use crate::state::ContextExt;
+use crate::state::{BoxState, ContextExt};
 
 /// A browser session represents a logged in user in a browser.
 #[derive(Description)]
@@ -55,17 +60,64 @@ impl BrowserSession {
         &self,
         ctx: &Context<'_>,
     ) -> Result<Option<Authentication>, async_graphql::Error> {
+        // Go through the loader, so that listing many sessions results in a
+        // single query
+        let last_authentication = ctx
+            .data_unchecked::<DataLoader<LastAuthenticationLoader>>()
+            .load_one(self.0.id)
+            .await?;
+
+        Ok(last_authentication.map(Authentication))
+    }
+
+    /// Get the list of authentications of this session, the most recent first.
+    async fn authentications(
+        &self,
+        ctx: &Context<'_>,
+
+        #[graphql(desc = "Returns the elements in the list that come after the cursor.")]
+        after: Option<String>,
+        #[graphql(desc = "Returns the elements in the list that come before the cursor.")]
+        before: Option<String>,
+        #[graphql(desc = "Returns the first *n* elements from the list.")] first: Option<i32>,
+        #[graphql(desc = "Returns the last *n* elements from the list.")] last: Option<i32>,
+    ) -> Result<Connection<Cursor, Authentication>, async_graphql::Error> {
         let state = ctx.state();
         let mut repo = state.repository().await?;
 
-        let last_authentication = repo
-            .browser_session()
-            .get_last_authentication(&self.0)
-            .await?;
+        query(
+            after,
+            before,
+            first,
+            last,
+            |after, before, first, last| async move {
+                let after_id = after
+                    .map(|x: OpaqueCursor<NodeCursor>| x.extract_for_type(NodeType::Authentication))
+                    .transpose()?;
+                let before_id = before
+                    .map(|x: OpaqueCursor<NodeCursor>| x.extract_for_type(NodeType::Authentication))
+                    .transpose()?;
+                let pagination = Pagination::try_new(before_id, after_id, first, last)?;
 
-        repo.cancel().await?;
+                let page = repo
+                    .browser_session()
+                    .list_authentications(&self.0, pagination)
+                    .await?;
 
-        Ok(last_authentication.map(Authentication))
+                repo.cancel().await?;
+
+                let mut connection = Connection::new(page.has_previous_page, page.has_next_page);
+                connection.edges.extend(page.edges.into_iter().map(|a| {
+                    Edge::new(
+                        OpaqueCursor(NodeCursor(NodeType::Authentication, a.id)),
+                        Authentication(a),
+                    )
+                }));
+
+                Ok::<_, async_graphql::Error>(connection)
+            },
+        )
+        .await
     }
 
     /// When the object was created.
@@ -78,9 +130,16 @@ impl BrowserSession {
         self.0.finished_at
     }
 
+    /// When the session expires, if it does.
+    pub async fn expires_at(&self) -> Option<DateTime<Utc>> {
+        self.0.expires_at
+    }
+
     /// The state of the session.
-    pub async fn state(&self) -> SessionState {
-        if self.0.finished_at.is_some() {
+    pub async fn state(&self, ctx: &Context<'_>) -> SessionState {
+        let now = ctx.state().clock().now();
+        // Sessions past their expiry are considered finished
+        if self.0.clone().finish_if_expired(now).finished_at.is_some() {
             SessionState::Finished
         } else {
             SessionState::Active
@@ -92,6 +151,11 @@ impl BrowserSession {
         self.0.user_agent.clone().map(UserAgent::from)
     }
 
+    /// The IP address from which the session was created.
+    pub async fn ip_address(&self) -> Option<String> {
+        self.0.ip_address.map(|ip| ip.to_string())
+    }
+
     /// The last IP address used by the session.
     pub async fn last_active_ip(&self) -> Option<String> {
         self.0.last_active_ip.map(|ip| ip.to_string())
@@ -194,6 +258,42 @@ impl BrowserSession {
     }
 }
 
+/// A [`Loader`] which batches the lookup of the last authentication of browser
+/// sessions.
+///
+/// It does not cache the results, so it can be shared between requests.
+pub struct LastAuthenticationLoader {
+    state: BoxState,
+}
+
+impl LastAuthenticationLoader {
+    /// Create a new [`DataLoader`] for the last authentication of browser
+    /// sessions
+    #[must_use]
+    pub fn new(state: BoxState) -> DataLoader<Self> {
+        DataLoader::new(Self { state }, tokio::spawn)
+    }
+}
+
+#[async_trait::async_trait]
+impl Loader<Ulid> for LastAuthenticationLoader {
+    type Value = mas_data_model::Authentication;
+    type Error = async_graphql::Error;
+
+    async fn load(&self, keys: &[Ulid]) -> Result<HashMap<Ulid, Self::Value>, Self::Error> {
+        let mut repo = self.state.repository().await?;
+
+        let authentications = repo
+            .browser_session()
+            .get_last_authentication_batch(keys.iter().copied().collect())
+            .await?;
+
+        repo.cancel().await?;
+
+        Ok(authentications.into_iter().collect())
+    }
+}
+
 /// An authentication records when a user enter their credential in a browser
 /// session.
 #[derive(Description)]
@@ -210,4 +310,42 @@ impl Authentication {
     pub async fn created_at(&self) -> DateTime<Utc> {
         self.0.created_at
     }
+
+    /// The method used to authenticate.
+    pub async fn method(&self) -> AuthenticationMethod {
+        match self.0.authentication_method {
+            mas_data_model::AuthenticationMethod::Password { .. } => AuthenticationMethod::Password,
+            mas_data_model::AuthenticationMethod::UpstreamOAuth2 { .. } => {
+                AuthenticationMethod::UpstreamOauth2
+            }
+            mas_data_model::AuthenticationMethod::WebAuthn { .. } => AuthenticationMethod::WebAuthn,
+            mas_data_model::AuthenticationMethod::Totp { .. } => AuthenticationMethod::Totp,
+            mas_data_model::AuthenticationMethod::RecoveryCode { .. } => {
+                AuthenticationMethod::RecoveryCode
+            }
+            mas_data_model::AuthenticationMethod::Unknown => AuthenticationMethod::Unknown,
+        }
+    }
+}
+
+/// The method used to authenticate in a browser session.
+#[derive(Enum, Copy, Clone, Eq, PartialEq)]
+pub enum AuthenticationMethod {
+    /// The user entered their password.
+    Password,
+
+    /// The user authenticated through an upstream OAuth 2.0 provider.
+    UpstreamOauth2,
+
+    /// The user authenticated with a WebAuthn security key.
+    WebAuthn,
+
+    /// The user entered a code from their TOTP authenticator app.
+    Totp,
+
+    /// The user entered one of their recovery codes.
+    RecoveryCode,
+
+    /// The authentication method is not known.
+    Unknown,
 }
@@ -140,10 +140,11 @@ impl CompatSession {
         };
 
         let state = ctx.state();
+        let clock = state.clock();
         let mut repo = state.repository().await?;
         let browser_session = repo
             .browser_session()
-            .lookup(user_session_id)
+            .lookup(&clock, user_session_id)
             .await?
             .context("Could not load browser session")?;
         repo.cancel().await?;
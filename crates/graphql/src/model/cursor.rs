@@ -13,6 +13,8 @@
 // limitations under the License.
 
 use async_graphql::connection::OpaqueCursor;
+use chrono::{DateTime, Utc};
+use mas_storage::pagination::PaginationCursor;
 use serde::{Deserialize, Serialize};
 use ulid::Ulid;
 
@@ -40,3 +42,23 @@ impl NodeCursor {
 }
 
 pub type Cursor = OpaqueCursor<NodeCursor>;
+
+/// A cursor which also carries the value of the column the list is ordered
+/// by, so that pagination stays stable when that column has duplicate values
+#[derive(Serialize, Deserialize, PartialEq, Eq)]
+pub struct OrderedNodeCursor(pub NodeType, pub Ulid, pub Option<DateTime<Utc>>);
+
+impl OrderedNodeCursor {
+    pub fn extract_for_type(
+        &self,
+        node_type: NodeType,
+    ) -> Result<PaginationCursor, async_graphql::Error> {
+        if self.0 == node_type {
+            Ok(PaginationCursor::new(self.1, self.2))
+        } else {
+            Err(async_graphql::Error::new("invalid cursor"))
+        }
+    }
+}
+
+pub type OrderedCursor = OpaqueCursor<OrderedNodeCursor>;
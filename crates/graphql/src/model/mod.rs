@@ -14,7 +14,9 @@
 
 use async_graphql::{Enum, Interface, Object, SimpleObject};
 use chrono::{DateTime, Utc};
+use mas_storage::pagination::{PaginationOrder, PaginationOrderBy};
 
+mod audit_log;
 mod browser_sessions;
 mod compat_sessions;
 mod cursor;
@@ -27,9 +29,10 @@ mod users;
 mod viewer;
 
 pub use self::{
-    browser_sessions::{Authentication, BrowserSession},
+    audit_log::{AuditAction, AuditLogEntry},
+    browser_sessions::{Authentication, BrowserSession, LastAuthenticationLoader},
     compat_sessions::{CompatSession, CompatSsoLogin},
-    cursor::{Cursor, NodeCursor},
+    cursor::{Cursor, NodeCursor, OrderedCursor, OrderedNodeCursor},
     node::{Node, NodeType},
     oauth::{OAuth2Client, OAuth2Session},
     site_config::{SiteConfig, SITE_CONFIG_ID},
@@ -76,6 +79,48 @@ pub enum SessionState {
     Finished,
 }
 
+/// The order in which sessions are listed
+#[derive(Enum, Copy, Clone, Eq, PartialEq)]
+pub enum SessionOrderBy {
+    /// The oldest sessions come first.
+    CreatedAtAsc,
+
+    /// The most recent sessions come first.
+    CreatedAtDesc,
+
+    /// The sessions which were finished first come first. Active sessions come
+    /// last.
+    FinishedAtAsc,
+
+    /// The most recently finished sessions come first. Active sessions come
+    /// first.
+    FinishedAtDesc,
+}
+
+impl SessionOrderBy {
+    /// The column and the order to use when listing sessions
+    fn ordering(self) -> (PaginationOrderBy, PaginationOrder) {
+        match self {
+            Self::CreatedAtAsc => (PaginationOrderBy::CreatedAt, PaginationOrder::Ascending),
+            Self::CreatedAtDesc => (PaginationOrderBy::CreatedAt, PaginationOrder::Descending),
+            Self::FinishedAtAsc => (PaginationOrderBy::FinishedAt, PaginationOrder::Ascending),
+            Self::FinishedAtDesc => (PaginationOrderBy::FinishedAt, PaginationOrder::Descending),
+        }
+    }
+
+    /// The value of the ordering column for a session, to put in its cursor
+    fn cursor_value(
+        self,
+        created_at: DateTime<Utc>,
+        finished_at: Option<DateTime<Utc>>,
+    ) -> Option<DateTime<Utc>> {
+        match self {
+            Self::CreatedAtAsc | Self::CreatedAtDesc => Some(created_at),
+            Self::FinishedAtAsc | Self::FinishedAtDesc => finished_at,
+        }
+    }
+}
+
 /// The type of a user agent
 #[derive(Enum, Copy, Clone, Eq, PartialEq)]
 pub enum DeviceType {
@@ -24,6 +24,7 @@ use super::{
 
 #[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum NodeType {
+    AuditLogEntry,
     Authentication,
     BrowserSession,
     CompatSession,
@@ -48,6 +49,7 @@ pub enum InvalidID {
 impl NodeType {
     fn to_prefix(self) -> &'static str {
         match self {
+            NodeType::AuditLogEntry => "audit_log_entry",
             NodeType::Authentication => "authentication",
             NodeType::BrowserSession => "browser_session",
             NodeType::CompatSession => "compat_session",
@@ -63,6 +65,7 @@ impl NodeType {
 
     fn from_prefix(prefix: &str) -> Option<Self> {
         match prefix {
+            "audit_log_entry" => Some(NodeType::AuditLogEntry),
             "authentication" => Some(NodeType::Authentication),
             "browser_session" => Some(NodeType::BrowserSession),
             "compat_session" => Some(NodeType::CompatSession),
@@ -90,10 +90,11 @@ impl OAuth2Session {
         };
 
         let state = ctx.state();
+        let clock = state.clock();
         let mut repo = state.repository().await?;
         let browser_session = repo
             .browser_session()
-            .lookup(user_session_id)
+            .lookup(&clock, user_session_id)
             .await?
             .context("Could not load browser session")?;
         repo.cancel().await?;
@@ -108,7 +109,10 @@ impl OAuth2Session {
             return Ok(None);
         };
 
-        if !ctx.requester().is_owner_or_admin(&UserId(user_id)) {
+        if !ctx
+            .requester()
+            .is_owner_or_admin(state.admin_scopes(), &UserId(user_id))
+        {
             return Err(async_graphql::Error::new("Unauthorized"));
         }
 
@@ -25,14 +25,15 @@ use mas_storage::{
     oauth2::{OAuth2SessionFilter, OAuth2SessionRepository},
     upstream_oauth2::{UpstreamOAuthLinkFilter, UpstreamOAuthLinkRepository},
     user::{BrowserSessionFilter, BrowserSessionRepository, UserEmailFilter, UserEmailRepository},
-    Pagination, RepositoryAccess,
+    Clock, Pagination, RepositoryAccess,
 };
 
 use super::{
+    audit_log::AuditLogEntry,
     compat_sessions::{CompatSessionType, CompatSsoLogin},
     matrix::MatrixUser,
-    BrowserSession, CompatSession, Cursor, NodeCursor, NodeType, OAuth2Session,
-    PreloadedTotalCount, SessionState, UpstreamOAuth2Link,
+    BrowserSession, CompatSession, Cursor, NodeCursor, NodeType, OAuth2Session, OrderedCursor,
+    OrderedNodeCursor, PreloadedTotalCount, SessionOrderBy, SessionState, UpstreamOAuth2Link,
 };
 use crate::state::ContextExt;
 
@@ -74,6 +75,11 @@ impl User {
         self.0.locked_at
     }
 
+    /// When the user was deactivated.
+    pub async fn deactivated_at(&self) -> Option<DateTime<Utc>> {
+        self.0.deactivated_at
+    }
+
     /// Whether the user can request admin privileges.
     pub async fn can_request_admin(&self) -> bool {
         self.0.can_request_admin
@@ -171,6 +177,11 @@ impl User {
         #[graphql(name = "type", desc = "List only sessions with the given type.")]
         type_param: Option<CompatSessionType>,
 
+        #[graphql(
+            desc = "List only sessions which have, or don't have, Synapse admin privileges."
+        )]
+        is_synapse_admin: Option<bool>,
+
         #[graphql(desc = "Returns the elements in the list that come after the cursor.")]
         after: Option<String>,
         #[graphql(desc = "Returns the elements in the list that come before the cursor.")]
@@ -207,6 +218,10 @@ impl User {
                     Some(CompatSessionType::Unknown) => filter.unknown_only(),
                     None => filter,
                 };
+                let filter = match is_synapse_admin {
+                    Some(is_synapse_admin) => filter.with_synapse_admin(is_synapse_admin),
+                    None => filter,
+                };
 
                 let page = repo.compat_session().list(filter, pagination).await?;
 
@@ -247,14 +262,18 @@ impl User {
         #[graphql(name = "state", desc = "List only sessions in the given state.")]
         state_param: Option<SessionState>,
 
+        #[graphql(desc = "How to order the sessions.")] order_by: Option<SessionOrderBy>,
+
         #[graphql(desc = "Returns the elements in the list that come after the cursor.")]
         after: Option<String>,
         #[graphql(desc = "Returns the elements in the list that come before the cursor.")]
         before: Option<String>,
         #[graphql(desc = "Returns the first *n* elements from the list.")] first: Option<i32>,
         #[graphql(desc = "Returns the last *n* elements from the list.")] last: Option<i32>,
-    ) -> Result<Connection<Cursor, BrowserSession, PreloadedTotalCount>, async_graphql::Error> {
+    ) -> Result<Connection<OrderedCursor, BrowserSession, PreloadedTotalCount>, async_graphql::Error>
+    {
         let state = ctx.state();
+        let clock = state.clock();
         let mut repo = state.repository().await?;
 
         query(
@@ -263,29 +282,31 @@ impl User {
             first,
             last,
             |after, before, first, last| async move {
-                let after_id = after
-                    .map(|x: OpaqueCursor<NodeCursor>| x.extract_for_type(NodeType::BrowserSession))
+                let after_cursor = after
+                    .map(|x: OrderedCursor| x.extract_for_type(NodeType::BrowserSession))
                     .transpose()?;
-                let before_id = before
-                    .map(|x: OpaqueCursor<NodeCursor>| x.extract_for_type(NodeType::BrowserSession))
+                let before_cursor = before
+                    .map(|x: OrderedCursor| x.extract_for_type(NodeType::BrowserSession))
                     .transpose()?;
-                let pagination = Pagination::try_new(before_id, after_id, first, last)?;
+                let mut pagination = Pagination::try_new(before_cursor, after_cursor, first, last)?;
+                if let Some(order_by) = order_by {
+                    let (column, order) = order_by.ordering();
+                    pagination = pagination.ordered_by(column, order);
+                }
 
                 let filter = BrowserSessionFilter::new().for_user(&self.0);
                 let filter = match state_param {
-                    Some(SessionState::Active) => filter.active_only(),
-                    Some(SessionState::Finished) => filter.finished_only(),
+                    Some(SessionState::Active) => filter.active_only(clock.now()),
+                    Some(SessionState::Finished) => filter.finished_only(clock.now()),
                     None => filter,
                 };
 
-                let page = repo.browser_session().list(filter, pagination).await?;
+                // Preload the total count if requested, in the same query as the page
+                let pagination =
+                    pagination.with_count(ctx.look_ahead().field("totalCount").exists());
 
-                // Preload the total count if requested
-                let count = if ctx.look_ahead().field("totalCount").exists() {
-                    Some(repo.browser_session().count(filter).await?)
-                } else {
-                    None
-                };
+                let page = repo.browser_session().list(filter, pagination).await?;
+                let count = page.total_count.map(usize::try_from).transpose()?;
 
                 repo.cancel().await?;
 
@@ -295,8 +316,10 @@ impl User {
                     PreloadedTotalCount(count),
                 );
                 connection.edges.extend(page.edges.into_iter().map(|u| {
+                    let value = order_by
+                        .and_then(|order_by| order_by.cursor_value(u.created_at, u.finished_at));
                     Edge::new(
-                        OpaqueCursor(NodeCursor(NodeType::BrowserSession, u.id)),
+                        OpaqueCursor(OrderedNodeCursor(NodeType::BrowserSession, u.id, value)),
                         BrowserSession(u),
                     )
                 }));
@@ -387,13 +410,16 @@ impl User {
 
         #[graphql(desc = "List only sessions for the given client.")] client: Option<ID>,
 
+        #[graphql(desc = "How to order the sessions.")] order_by: Option<SessionOrderBy>,
+
         #[graphql(desc = "Returns the elements in the list that come after the cursor.")]
         after: Option<String>,
         #[graphql(desc = "Returns the elements in the list that come before the cursor.")]
         before: Option<String>,
         #[graphql(desc = "Returns the first *n* elements from the list.")] first: Option<i32>,
         #[graphql(desc = "Returns the last *n* elements from the list.")] last: Option<i32>,
-    ) -> Result<Connection<Cursor, OAuth2Session, PreloadedTotalCount>, async_graphql::Error> {
+    ) -> Result<Connection<OrderedCursor, OAuth2Session, PreloadedTotalCount>, async_graphql::Error>
+    {
         let state = ctx.state();
         let mut repo = state.repository().await?;
 
@@ -403,13 +429,17 @@ impl User {
             first,
             last,
             |after, before, first, last| async move {
-                let after_id = after
-                    .map(|x: OpaqueCursor<NodeCursor>| x.extract_for_type(NodeType::OAuth2Session))
+                let after_cursor = after
+                    .map(|x: OrderedCursor| x.extract_for_type(NodeType::OAuth2Session))
                     .transpose()?;
-                let before_id = before
-                    .map(|x: OpaqueCursor<NodeCursor>| x.extract_for_type(NodeType::OAuth2Session))
+                let before_cursor = before
+                    .map(|x: OrderedCursor| x.extract_for_type(NodeType::OAuth2Session))
                     .transpose()?;
-                let pagination = Pagination::try_new(before_id, after_id, first, last)?;
+                let mut pagination = Pagination::try_new(before_cursor, after_cursor, first, last)?;
+                if let Some(order_by) = order_by {
+                    let (column, order) = order_by.ordering();
+                    pagination = pagination.ordered_by(column, order);
+                }
 
                 let client = if let Some(id) = client {
                     // Load the client if we're filtering by it
@@ -455,8 +485,14 @@ impl User {
                 );
 
                 connection.edges.extend(page.edges.into_iter().map(|s| {
+                    let finished_at = match s.state {
+                        mas_data_model::SessionState::Valid => None,
+                        mas_data_model::SessionState::Finished { finished_at } => Some(finished_at),
+                    };
+                    let value = order_by
+                        .and_then(|order_by| order_by.cursor_value(s.created_at, finished_at));
                     Edge::new(
-                        OpaqueCursor(NodeCursor(NodeType::OAuth2Session, s.id)),
+                        OpaqueCursor(OrderedNodeCursor(NodeType::OAuth2Session, s.id, value)),
                         OAuth2Session(s),
                     )
                 }));
@@ -534,6 +570,59 @@ impl User {
         .await
     }
 
+    /// Get the audit log entries in which this user is either the actor or the
+    /// target, chronologically sorted. This is only available to
+    /// administrators.
+    async fn audit_log_entries(
+        &self,
+        ctx: &Context<'_>,
+
+        #[graphql(desc = "Returns the elements in the list that come after the cursor.")]
+        after: Option<String>,
+        #[graphql(desc = "Returns the elements in the list that come before the cursor.")]
+        before: Option<String>,
+        #[graphql(desc = "Returns the first *n* elements from the list.")] first: Option<i32>,
+        #[graphql(desc = "Returns the last *n* elements from the list.")] last: Option<i32>,
+    ) -> Result<Connection<Cursor, AuditLogEntry>, async_graphql::Error> {
+        let state = ctx.state();
+        if !ctx.requester().is_admin(state.admin_scopes()) {
+            return Err(async_graphql::Error::new("Unauthorized"));
+        }
+
+        let mut repo = state.repository().await?;
+
+        query(
+            after,
+            before,
+            first,
+            last,
+            |after, before, first, last| async move {
+                let after_id = after
+                    .map(|x: OpaqueCursor<NodeCursor>| x.extract_for_type(NodeType::AuditLogEntry))
+                    .transpose()?;
+                let before_id = before
+                    .map(|x: OpaqueCursor<NodeCursor>| x.extract_for_type(NodeType::AuditLogEntry))
+                    .transpose()?;
+                let pagination = Pagination::try_new(before_id, after_id, first, last)?;
+
+                let page = repo.audit_log().list_for_user(&self.0, pagination).await?;
+
+                repo.cancel().await?;
+
+                let mut connection = Connection::new(page.has_previous_page, page.has_next_page);
+                connection.edges.extend(page.edges.into_iter().map(|e| {
+                    Edge::new(
+                        OpaqueCursor(NodeCursor(NodeType::AuditLogEntry, e.id)),
+                        AuditLogEntry(e),
+                    )
+                }));
+
+                Ok::<_, async_graphql::Error>(connection)
+            },
+        )
+        .await
+    }
+
     /// Get the list of both compat and OAuth 2.0 sessions, chronologically
     /// sorted
     #[allow(clippy::too_many_arguments)]
@@ -562,6 +651,7 @@ impl User {
     ) -> Result<Connection<Cursor, AppSession, PreloadedTotalCount>, async_graphql::Error> {
         let state = ctx.state();
         let requester = ctx.requester();
+        let clock = state.clock();
         let mut repo = state.repository().await?;
 
         query(
@@ -606,9 +696,9 @@ impl User {
 
                         let Some(session) = repo
                             .browser_session()
-                            .lookup(id)
+                            .lookup(&clock, id)
                             .await?
-                            .filter(|u| requester.is_owner_or_admin(u))
+                            .filter(|u| requester.is_owner_or_admin(state.admin_scopes(), u))
                         else {
                             // If we couldn't find the session or if the requester can't access it,
                             // return an empty list
@@ -17,7 +17,7 @@ use mas_storage::RepositoryAccess;
 
 use crate::{
     model::{BrowserSession, NodeType},
-    state::ContextExt,
+    state::{ContextExt, EndingSessions},
 };
 
 #[derive(Default)]
@@ -35,6 +35,7 @@ pub struct EndBrowserSessionInput {
 /// The payload of the `endBrowserSession` mutation.
 pub enum EndBrowserSessionPayload {
     NotFound,
+    AlreadyFinished(Box<mas_data_model::BrowserSession>),
     Ended(Box<mas_data_model::BrowserSession>),
 }
 
@@ -44,6 +45,9 @@ enum EndBrowserSessionStatus {
     /// The session was ended.
     Ended,
 
+    /// The session was already ended.
+    AlreadyFinished,
+
     /// The session was not found.
     NotFound,
 }
@@ -54,6 +58,7 @@ impl EndBrowserSessionPayload {
     async fn status(&self) -> EndBrowserSessionStatus {
         match self {
             Self::Ended(_) => EndBrowserSessionStatus::Ended,
+            Self::AlreadyFinished(_) => EndBrowserSessionStatus::AlreadyFinished,
             Self::NotFound => EndBrowserSessionStatus::NotFound,
         }
     }
@@ -61,7 +66,9 @@ impl EndBrowserSessionPayload {
     /// Returns the ended session.
     async fn browser_session(&self) -> Option<BrowserSession> {
         match self {
-            Self::Ended(session) => Some(BrowserSession(*session.clone())),
+            Self::Ended(session) | Self::AlreadyFinished(session) => {
+                Some(BrowserSession(*session.clone()))
+            }
             Self::NotFound => None,
         }
     }
@@ -82,20 +89,35 @@ impl BrowserSessionMutations {
         let mut repo = state.repository().await?;
         let clock = state.clock();
 
-        let session = repo.browser_session().lookup(browser_session_id).await?;
+        let session = repo
+            .browser_session()
+            .lookup(&clock, browser_session_id)
+            .await?;
 
         let Some(session) = session else {
             return Ok(EndBrowserSessionPayload::NotFound);
         };
 
-        if !requester.is_owner_or_admin(&session) {
+        if !requester.is_owner_or_admin(state.admin_scopes(), &session) {
             return Ok(EndBrowserSessionPayload::NotFound);
         }
 
+        // Ending an already finished session is a no-op
+        if session.finished_at.is_some() {
+            repo.cancel().await?;
+            return Ok(EndBrowserSessionPayload::AlreadyFinished(Box::new(session)));
+        }
+
+        let logouts = state
+            .prepare_backchannel_logout(&mut repo, EndingSessions::BrowserSession(&session))
+            .await?;
+
         let session = repo.browser_session().finish(&clock, session).await?;
 
         repo.save().await?;
 
+        logouts.dispatch();
+
         Ok(EndBrowserSessionPayload::Ended(Box::new(session)))
     }
 }
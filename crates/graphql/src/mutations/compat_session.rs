@@ -91,7 +91,7 @@ impl CompatSessionMutations {
             return Ok(EndCompatSessionPayload::NotFound);
         };
 
-        if !requester.is_owner_or_admin(&session) {
+        if !requester.is_owner_or_admin(state.admin_scopes(), &session) {
             return Ok(EndCompatSessionPayload::NotFound);
         }
 
@@ -83,12 +83,14 @@ impl MatrixMutations {
         let id = NodeType::User.extract_ulid(&input.user_id)?;
         let requester = ctx.requester();
 
-        if !requester.is_owner_or_admin(&UserId(id)) {
+        if !requester.is_owner_or_admin(state.admin_scopes(), &UserId(id)) {
             return Err(async_graphql::Error::new("Unauthorized"));
         }
 
         // Allow non-admins to change their display name if the site config allows it
-        if !requester.is_admin() && !state.site_config().displayname_change_allowed {
+        if !requester.is_admin(state.admin_scopes())
+            && !state.site_config().displayname_change_allowed
+        {
             return Err(async_graphql::Error::new("Unauthorized"));
         }
 
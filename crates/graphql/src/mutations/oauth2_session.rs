@@ -19,8 +19,8 @@ use mas_data_model::{Device, TokenType};
 use mas_storage::{
     job::{DeleteDeviceJob, JobRepositoryExt, ProvisionDeviceJob},
     oauth2::{
-        OAuth2AccessTokenRepository, OAuth2ClientRepository, OAuth2RefreshTokenRepository,
-        OAuth2SessionRepository,
+        OAuth2AccessTokenRepository, OAuth2ClientRepository, OAuth2ConsentRepository,
+        OAuth2RefreshTokenRepository, OAuth2SessionRepository,
     },
     user::UserRepository,
     RepositoryAccess,
@@ -28,8 +28,8 @@ use mas_storage::{
 use oauth2_types::scope::Scope;
 
 use crate::{
-    model::{NodeType, OAuth2Session},
-    state::ContextExt,
+    model::{NodeType, OAuth2Client, OAuth2Session},
+    state::{ContextExt, EndingSessions},
 };
 
 #[derive(Default)]
@@ -118,6 +118,48 @@ impl EndOAuth2SessionPayload {
     }
 }
 
+/// The input of the `revokeOauth2Consent` mutation.
+#[derive(InputObject)]
+pub struct RevokeOAuth2ConsentInput {
+    /// The ID of the client to revoke the consent for.
+    oauth2_client_id: ID,
+}
+
+/// The payload of the `revokeOauth2Consent` mutation.
+pub enum RevokeOAuth2ConsentPayload {
+    NotFound,
+    Revoked(mas_data_model::Client),
+}
+
+/// The status of the `revokeOauth2Consent` mutation.
+#[derive(Enum, Copy, Clone, PartialEq, Eq, Debug)]
+enum RevokeOAuth2ConsentStatus {
+    /// The consent was revoked.
+    Revoked,
+
+    /// The client was not found, or the user had not consented to it.
+    NotFound,
+}
+
+#[Object]
+impl RevokeOAuth2ConsentPayload {
+    /// The status of the mutation.
+    async fn status(&self) -> RevokeOAuth2ConsentStatus {
+        match self {
+            Self::Revoked(_) => RevokeOAuth2ConsentStatus::Revoked,
+            Self::NotFound => RevokeOAuth2ConsentStatus::NotFound,
+        }
+    }
+
+    /// Returns the client for which the consent was revoked.
+    async fn oauth2_client(&self) -> Option<OAuth2Client> {
+        match self {
+            Self::Revoked(client) => Some(OAuth2Client(client.clone())),
+            Self::NotFound => None,
+        }
+    }
+}
+
 #[Object]
 impl OAuth2SessionMutations {
     /// Create a new arbitrary OAuth 2.0 Session.
@@ -134,7 +176,7 @@ impl OAuth2SessionMutations {
         let permanent = input.permanent.unwrap_or(false);
         let requester = ctx.requester();
 
-        if !requester.is_admin() {
+        if !requester.is_admin(state.admin_scopes()) {
             return Err(async_graphql::Error::new("Unauthorized"));
         }
 
@@ -225,7 +267,7 @@ impl OAuth2SessionMutations {
             return Ok(EndOAuth2SessionPayload::NotFound);
         };
 
-        if !requester.is_owner_or_admin(&session) {
+        if !requester.is_owner_or_admin(state.admin_scopes(), &session) {
             return Ok(EndOAuth2SessionPayload::NotFound);
         }
 
@@ -252,10 +294,51 @@ impl OAuth2SessionMutations {
             }
         }
 
+        let logouts = state
+            .prepare_backchannel_logout(&mut repo, EndingSessions::OAuth2Session(&session))
+            .await?;
+
         let session = repo.oauth2_session().finish(&clock, session).await?;
 
         repo.save().await?;
 
+        logouts.dispatch();
+
         Ok(EndOAuth2SessionPayload::Ended(session))
     }
+
+    /// Revoke the consent the current user gave to an OAuth 2.0 client.
+    ///
+    /// The user will be asked for consent again the next time the client
+    /// requests authorization.
+    async fn revoke_oauth2_consent(
+        &self,
+        ctx: &Context<'_>,
+        input: RevokeOAuth2ConsentInput,
+    ) -> Result<RevokeOAuth2ConsentPayload, async_graphql::Error> {
+        let state = ctx.state();
+        let oauth2_client_id = NodeType::OAuth2Client.extract_ulid(&input.oauth2_client_id)?;
+        let requester = ctx.requester();
+
+        let Some(user) = requester.user() else {
+            return Err(async_graphql::Error::new("Unauthorized"));
+        };
+
+        let mut repo = state.repository().await?;
+
+        let client = repo.oauth2_client().lookup(oauth2_client_id).await?;
+        let Some(client) = client else {
+            return Ok(RevokeOAuth2ConsentPayload::NotFound);
+        };
+
+        let revoked = repo.oauth2_consent().revoke(&client, user).await?;
+
+        repo.save().await?;
+
+        if revoked {
+            Ok(RevokeOAuth2ConsentPayload::Revoked(client))
+        } else {
+            Ok(RevokeOAuth2ConsentPayload::NotFound)
+        }
+    }
 }
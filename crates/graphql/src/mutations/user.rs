@@ -14,15 +14,16 @@
 
 use anyhow::Context as _;
 use async_graphql::{Context, Description, Enum, InputObject, Object, ID};
+use mas_data_model::AuditAction;
 use mas_storage::{
     job::{DeactivateUserJob, JobRepositoryExt, ProvisionUserJob},
-    user::UserRepository,
+    user::{BrowserSessionRepository, UserRepository},
 };
 use tracing::{info, warn};
 
 use crate::{
     model::{NodeType, User},
-    state::ContextExt,
+    state::{ContextExt, EndingSessions},
     UserId,
 };
 
@@ -140,6 +141,144 @@ impl LockUserPayload {
     }
 }
 
+/// The input for the `unlockUser` mutation.
+#[derive(InputObject)]
+struct UnlockUserInput {
+    /// The ID of the user to unlock.
+    user_id: ID,
+}
+
+/// The status of the `unlockUser` mutation.
+#[derive(Enum, Copy, Clone, Eq, PartialEq)]
+enum UnlockUserStatus {
+    /// The user was unlocked.
+    Unlocked,
+
+    /// The user was not found.
+    NotFound,
+}
+
+/// The payload for the `unlockUser` mutation.
+#[derive(Description)]
+enum UnlockUserPayload {
+    /// The user was unlocked.
+    Unlocked(mas_data_model::User),
+
+    /// The user was not found.
+    NotFound,
+}
+
+#[Object(use_type_description)]
+impl UnlockUserPayload {
+    /// Status of the operation
+    async fn status(&self) -> UnlockUserStatus {
+        match self {
+            Self::Unlocked(_) => UnlockUserStatus::Unlocked,
+            Self::NotFound => UnlockUserStatus::NotFound,
+        }
+    }
+
+    /// The user that was unlocked.
+    async fn user(&self) -> Option<User> {
+        match self {
+            Self::Unlocked(user) => Some(User(user.clone())),
+            Self::NotFound => None,
+        }
+    }
+}
+
+/// The input for the `deactivateUser` mutation.
+#[derive(InputObject)]
+struct DeactivateUserInput {
+    /// The ID of the user to deactivate.
+    user_id: ID,
+}
+
+/// The status of the `deactivateUser` mutation.
+#[derive(Enum, Copy, Clone, Eq, PartialEq)]
+enum DeactivateUserStatus {
+    /// The user was deactivated.
+    Deactivated,
+
+    /// The user was not found.
+    NotFound,
+}
+
+/// The payload for the `deactivateUser` mutation.
+#[derive(Description)]
+enum DeactivateUserPayload {
+    /// The user was deactivated.
+    Deactivated(mas_data_model::User),
+
+    /// The user was not found.
+    NotFound,
+}
+
+#[Object(use_type_description)]
+impl DeactivateUserPayload {
+    /// Status of the operation
+    async fn status(&self) -> DeactivateUserStatus {
+        match self {
+            Self::Deactivated(_) => DeactivateUserStatus::Deactivated,
+            Self::NotFound => DeactivateUserStatus::NotFound,
+        }
+    }
+
+    /// The user that was deactivated.
+    async fn user(&self) -> Option<User> {
+        match self {
+            Self::Deactivated(user) => Some(User(user.clone())),
+            Self::NotFound => None,
+        }
+    }
+}
+
+/// The input for the `reactivateUser` mutation.
+#[derive(InputObject)]
+struct ReactivateUserInput {
+    /// The ID of the user to reactivate.
+    user_id: ID,
+}
+
+/// The status of the `reactivateUser` mutation.
+#[derive(Enum, Copy, Clone, Eq, PartialEq)]
+enum ReactivateUserStatus {
+    /// The user was reactivated.
+    Reactivated,
+
+    /// The user was not found.
+    NotFound,
+}
+
+/// The payload for the `reactivateUser` mutation.
+#[derive(Description)]
+enum ReactivateUserPayload {
+    /// The user was reactivated.
+    Reactivated(mas_data_model::User),
+
+    /// The user was not found.
+    NotFound,
+}
+
+#[Object(use_type_description)]
+impl ReactivateUserPayload {
+    /// Status of the operation
+    async fn status(&self) -> ReactivateUserStatus {
+        match self {
+            Self::Reactivated(_) => ReactivateUserStatus::Reactivated,
+            Self::NotFound => ReactivateUserStatus::NotFound,
+        }
+    }
+
+    /// The user that was reactivated.
+    async fn user(&self) -> Option<User> {
+        match self {
+            Self::Reactivated(user) => Some(User(user.clone())),
+            Self::NotFound => None,
+        }
+    }
+}
+
 /// The input for the `setCanRequestAdmin` mutation.
 #[derive(InputObject)]
 struct SetCanRequestAdminInput {
@@ -242,7 +381,7 @@ impl UserMutations {
         let clock = state.clock();
         let mut rng = state.rng();
 
-        if !requester.is_admin() {
+        if !requester.is_admin(state.admin_scopes()) {
             return Err(async_graphql::Error::new("Unauthorized"));
         }
 
@@ -292,7 +431,7 @@ impl UserMutations {
         let state = ctx.state();
         let requester = ctx.requester();
 
-        if !requester.is_admin() {
+        if !requester.is_admin(state.admin_scopes()) {
             return Err(async_graphql::Error::new("Unauthorized"));
         }
 
@@ -307,9 +446,44 @@ impl UserMutations {
 
         let deactivate = input.deactivate.unwrap_or(false);
 
-        let user = repo.user().lock(&state.clock(), user).await?;
+        let clock = state.clock();
+        let mut rng = state.rng();
+
+        // The sessions of a locked user can't be used anymore, so notify the
+        // clients which asked for it
+        let logouts = state
+            .prepare_backchannel_logout(&mut repo, EndingSessions::User(&user))
+            .await?;
+
+        let user = repo.user().lock(&clock, user).await?;
+
+        repo.audit_log()
+            .record(
+                &mut rng,
+                &clock,
+                requester
+                    .audit_log_params(AuditAction::UserLocked)
+                    .with_target(&user),
+            )
+            .await?;
 
         if deactivate {
+            // End all the active browser sessions of the user in the same
+            // transaction, so that they can't be used anymore
+            repo.browser_session()
+                .finish_all_for_user(&clock, &user)
+                .await?;
+
+            repo.audit_log()
+                .record(
+                    &mut rng,
+                    &clock,
+                    requester
+                        .audit_log_params(AuditAction::UserDeactivated)
+                        .with_target(&user),
+                )
+                .await?;
+
             info!("Scheduling deactivation of user {}", user.id);
             repo.job()
                 .schedule_job(DeactivateUserJob::new(&user, deactivate))
@@ -318,9 +492,150 @@ impl UserMutations {
 
         repo.save().await?;
 
+        logouts.dispatch();
+
         Ok(LockUserPayload::Locked(user))
     }
 
+    /// Unlock a user. This is only available to administrators.
+    async fn unlock_user(
+        &self,
+        ctx: &Context<'_>,
+        input: UnlockUserInput,
+    ) -> Result<UnlockUserPayload, async_graphql::Error> {
+        let state = ctx.state();
+        let requester = ctx.requester();
+
+        if !requester.is_admin(state.admin_scopes()) {
+            return Err(async_graphql::Error::new("Unauthorized"));
+        }
+
+        let mut repo = state.repository().await?;
+
+        let user_id = NodeType::User.extract_ulid(&input.user_id)?;
+        let user = repo.user().lookup(user_id).await?;
+
+        let Some(user) = user else {
+            return Ok(UnlockUserPayload::NotFound);
+        };
+
+        let user = repo.user().unlock(user).await?;
+
+        let clock = state.clock();
+        let mut rng = state.rng();
+        repo.audit_log()
+            .record(
+                &mut rng,
+                &clock,
+                requester
+                    .audit_log_params(AuditAction::UserUnlocked)
+                    .with_target(&user),
+            )
+            .await?;
+
+        repo.save().await?;
+
+        Ok(UnlockUserPayload::Unlocked(user))
+    }
+
+    /// Deactivate a user, without deleting any of their data. Deactivated
+    /// users can't log in until they are reactivated. This is only available
+    /// to administrators.
+    async fn deactivate_user(
+        &self,
+        ctx: &Context<'_>,
+        input: DeactivateUserInput,
+    ) -> Result<DeactivateUserPayload, async_graphql::Error> {
+        let state = ctx.state();
+        let requester = ctx.requester();
+
+        if !requester.is_admin(state.admin_scopes()) {
+            return Err(async_graphql::Error::new("Unauthorized"));
+        }
+
+        let mut repo = state.repository().await?;
+
+        let user_id = NodeType::User.extract_ulid(&input.user_id)?;
+        let user = repo.user().lookup(user_id).await?;
+
+        let Some(user) = user else {
+            return Ok(DeactivateUserPayload::NotFound);
+        };
+
+        let clock = state.clock();
+        let mut rng = state.rng();
+
+        let logouts = state
+            .prepare_backchannel_logout(&mut repo, EndingSessions::User(&user))
+            .await?;
+
+        let user = repo.user().deactivate(&clock, user).await?;
+
+        // End all the active browser sessions of the user, so that they can't
+        // be used anymore
+        repo.browser_session()
+            .finish_all_for_user(&clock, &user)
+            .await?;
+
+        repo.audit_log()
+            .record(
+                &mut rng,
+                &clock,
+                requester
+                    .audit_log_params(AuditAction::UserDeactivated)
+                    .with_target(&user),
+            )
+            .await?;
+
+        repo.save().await?;
+
+        logouts.dispatch();
+
+        Ok(DeactivateUserPayload::Deactivated(user))
+    }
+
+    /// Reactivate a deactivated user. This is only available to
+    /// administrators.
+    async fn reactivate_user(
+        &self,
+        ctx: &Context<'_>,
+        input: ReactivateUserInput,
+    ) -> Result<ReactivateUserPayload, async_graphql::Error> {
+        let state = ctx.state();
+        let requester = ctx.requester();
+
+        if !requester.is_admin(state.admin_scopes()) {
+            return Err(async_graphql::Error::new("Unauthorized"));
+        }
+
+        let mut repo = state.repository().await?;
+
+        let user_id = NodeType::User.extract_ulid(&input.user_id)?;
+        let user = repo.user().lookup(user_id).await?;
+
+        let Some(user) = user else {
+            return Ok(ReactivateUserPayload::NotFound);
+        };
+
+        let user = repo.user().reactivate(user).await?;
+
+        let clock = state.clock();
+        let mut rng = state.rng();
+        repo.audit_log()
+            .record(
+                &mut rng,
+                &clock,
+                requester
+                    .audit_log_params(AuditAction::UserReactivated)
+                    .with_target(&user),
+            )
+            .await?;
+
+        repo.save().await?;
+
+        Ok(ReactivateUserPayload::Reactivated(user))
+    }
+
     /// Set whether a user can request admin. This is only available to
     /// administrators.
     async fn set_can_request_admin(
@@ -331,7 +646,7 @@ impl UserMutations {
         let state = ctx.state();
         let requester = ctx.requester();
 
-        if !requester.is_admin() {
+        if !requester.is_admin(state.admin_scopes()) {
             return Err(async_graphql::Error::new("Unauthorized"));
         }
 
@@ -364,7 +679,7 @@ impl UserMutations {
         let user_id = NodeType::User.extract_ulid(&input.user_id)?;
         let requester = ctx.requester();
 
-        if !requester.is_owner_or_admin(&UserId(user_id)) {
+        if !requester.is_owner_or_admin(state.admin_scopes(), &UserId(user_id)) {
             return Err(async_graphql::Error::new("Unauthorized"));
         }
 
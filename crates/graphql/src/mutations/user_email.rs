@@ -14,10 +14,12 @@
 
 use anyhow::Context as _;
 use async_graphql::{Context, Description, Enum, InputObject, Object, ID};
+use chrono::Duration;
+use mas_data_model::UserEmailVerificationState;
 use mas_storage::{
     job::{JobRepositoryExt, ProvisionUserJob, VerifyEmailJob},
     user::{UserEmailRepository, UserRepository},
-    RepositoryAccess,
+    BoxRepository, Clock, RepositoryAccess,
 };
 
 use crate::{
@@ -31,6 +33,26 @@ pub struct UserEmailMutations {
     _private: (),
 }
 
+/// Check whether a new verification email can be sent for the given email
+/// address, to avoid sending too many emails to the same address, even if it
+/// was added by multiple users
+async fn can_send_verification_email(
+    repo: &mut BoxRepository,
+    clock: &dyn Clock,
+    user_email: &mas_data_model::UserEmail,
+) -> Result<bool, async_graphql::Error> {
+    let cooldown = Duration::try_minutes(1).unwrap();
+
+    let latest = repo
+        .user_email()
+        .find_latest_verification_code(clock, user_email)
+        .await?;
+
+    Ok(latest.map_or(true, |verification| {
+        verification.created_at + cooldown <= clock.now()
+    }))
+}
+
 /// The input for the `addEmail` mutation
 #[derive(InputObject)]
 struct AddEmailInput {
@@ -137,6 +159,8 @@ enum SendVerificationEmailStatus {
     Sent,
     /// The email address is already verified
     AlreadyVerified,
+    /// A verification email was sent too recently
+    RateLimited,
 }
 
 /// The payload of the `sendVerificationEmail` mutation
@@ -144,6 +168,7 @@ enum SendVerificationEmailStatus {
 enum SendVerificationEmailPayload {
     Sent(mas_data_model::UserEmail),
     AlreadyVerified(mas_data_model::UserEmail),
+    RateLimited(mas_data_model::UserEmail),
 }
 
 #[Object(use_type_description)]
@@ -155,6 +180,9 @@ impl SendVerificationEmailPayload {
             SendVerificationEmailPayload::AlreadyVerified(_) => {
                 SendVerificationEmailStatus::AlreadyVerified
             }
+            SendVerificationEmailPayload::RateLimited(_) => {
+                SendVerificationEmailStatus::RateLimited
+            }
         }
     }
 
@@ -162,7 +190,8 @@ impl SendVerificationEmailPayload {
     async fn email(&self) -> UserEmail {
         match self {
             SendVerificationEmailPayload::Sent(email)
-            | SendVerificationEmailPayload::AlreadyVerified(email) => UserEmail(email.clone()),
+            | SendVerificationEmailPayload::AlreadyVerified(email)
+            | SendVerificationEmailPayload::RateLimited(email) => UserEmail(email.clone()),
         }
     }
 
@@ -173,7 +202,8 @@ impl SendVerificationEmailPayload {
 
         let user_id = match self {
             SendVerificationEmailPayload::Sent(email)
-            | SendVerificationEmailPayload::AlreadyVerified(email) => email.user_id,
+            | SendVerificationEmailPayload::AlreadyVerified(email)
+            | SendVerificationEmailPayload::RateLimited(email) => email.user_id,
         };
 
         let user = repo
@@ -204,6 +234,8 @@ enum VerifyEmailStatus {
     AlreadyVerified,
     /// The verification code is invalid
     InvalidCode,
+    /// The verification code has expired
+    CodeExpired,
 }
 
 /// The payload of the `verifyEmail` mutation
@@ -212,6 +244,7 @@ enum VerifyEmailPayload {
     Verified(mas_data_model::UserEmail),
     AlreadyVerified(mas_data_model::UserEmail),
     InvalidCode,
+    CodeExpired,
 }
 
 #[Object(use_type_description)]
@@ -222,6 +255,7 @@ impl VerifyEmailPayload {
             VerifyEmailPayload::Verified(_) => VerifyEmailStatus::Verified,
             VerifyEmailPayload::AlreadyVerified(_) => VerifyEmailStatus::AlreadyVerified,
             VerifyEmailPayload::InvalidCode => VerifyEmailStatus::InvalidCode,
+            VerifyEmailPayload::CodeExpired => VerifyEmailStatus::CodeExpired,
         }
     }
 
@@ -231,7 +265,7 @@ impl VerifyEmailPayload {
             VerifyEmailPayload::Verified(email) | VerifyEmailPayload::AlreadyVerified(email) => {
                 Some(UserEmail(email.clone()))
             }
-            VerifyEmailPayload::InvalidCode => None,
+            VerifyEmailPayload::InvalidCode | VerifyEmailPayload::CodeExpired => None,
         }
     }
 
@@ -244,7 +278,7 @@ impl VerifyEmailPayload {
             VerifyEmailPayload::Verified(email) | VerifyEmailPayload::AlreadyVerified(email) => {
                 email.user_id
             }
-            VerifyEmailPayload::InvalidCode => return Ok(None),
+            VerifyEmailPayload::InvalidCode | VerifyEmailPayload::CodeExpired => return Ok(None),
         };
 
         let user = repo
@@ -385,18 +419,18 @@ impl UserEmailMutations {
         let id = NodeType::User.extract_ulid(&input.user_id)?;
         let requester = ctx.requester();
 
-        if !requester.is_owner_or_admin(&UserId(id)) {
+        if !requester.is_owner_or_admin(state.admin_scopes(), &UserId(id)) {
             return Err(async_graphql::Error::new("Unauthorized"));
         }
 
         // Allow non-admins to change their email address if the site config allows it
-        if !requester.is_admin() && !state.site_config().email_change_allowed {
+        if !requester.is_admin(state.admin_scopes()) && !state.site_config().email_change_allowed {
             return Err(async_graphql::Error::new("Unauthorized"));
         }
 
         // Only admins can skip validation
         if (input.skip_verification.is_some() || input.skip_policy_check.is_some())
-            && !requester.is_admin()
+            && !requester.is_admin(state.admin_scopes())
         {
             return Err(async_graphql::Error::new("Unauthorized"));
         }
@@ -453,7 +487,7 @@ impl UserEmailMutations {
                     .user_email()
                     .mark_as_verified(&state.clock(), user_email)
                     .await?;
-            } else {
+            } else if can_send_verification_email(&mut repo, &state.clock(), &user_email).await? {
                 // TODO: figure out the locale
                 repo.job()
                     .schedule_job(VerifyEmailJob::new(&user_email))
@@ -489,27 +523,30 @@ impl UserEmailMutations {
             .await?
             .context("User email not found")?;
 
-        if !requester.is_owner_or_admin(&user_email) {
+        if !requester.is_owner_or_admin(state.admin_scopes(), &user_email) {
             return Err(async_graphql::Error::new("User email not found"));
         }
 
-        // Schedule a job to verify the email address if needed
-        let needs_verification = user_email.confirmed_at.is_none();
-        if needs_verification {
-            // TODO: figure out the locale
-            repo.job()
-                .schedule_job(VerifyEmailJob::new(&user_email))
-                .await?;
+        if user_email.confirmed_at.is_some() {
+            repo.cancel().await?;
+            return Ok(SendVerificationEmailPayload::AlreadyVerified(user_email));
         }
 
+        // Don't send a new email if one was sent too recently
+        if !can_send_verification_email(&mut repo, &state.clock(), &user_email).await? {
+            repo.cancel().await?;
+            return Ok(SendVerificationEmailPayload::RateLimited(user_email));
+        }
+
+        // Schedule a job to verify the email address
+        // TODO: figure out the locale
+        repo.job()
+            .schedule_job(VerifyEmailJob::new(&user_email))
+            .await?;
+
         repo.save().await?;
 
-        let payload = if needs_verification {
-            SendVerificationEmailPayload::Sent(user_email)
-        } else {
-            SendVerificationEmailPayload::AlreadyVerified(user_email)
-        };
-        Ok(payload)
+        Ok(SendVerificationEmailPayload::Sent(user_email))
     }
 
     /// Submit a verification code for an email address
@@ -531,7 +568,7 @@ impl UserEmailMutations {
             .await?
             .context("User email not found")?;
 
-        if !requester.is_owner_or_admin(&user_email) {
+        if !requester.is_owner_or_admin(state.admin_scopes(), &user_email) {
             return Err(async_graphql::Error::new("User email not found"));
         }
 
@@ -548,13 +585,29 @@ impl UserEmailMutations {
         let verification = repo
             .user_email()
             .find_verification_code(&clock, &user_email, &input.code)
-            .await?
-            .filter(|v| v.is_valid());
+            .await?;
 
         let Some(verification) = verification else {
+            // Count the failed attempt, so that the pending codes get invalidated
+            // after too many of them
+            repo.user_email()
+                .record_failed_verification_attempt(&clock, &user_email)
+                .await?;
+            repo.save().await?;
+
             return Ok(VerifyEmailPayload::InvalidCode);
         };
 
+        match verification.state {
+            UserEmailVerificationState::Valid => {}
+            UserEmailVerificationState::Expired { .. } => {
+                return Ok(VerifyEmailPayload::CodeExpired);
+            }
+            UserEmailVerificationState::AlreadyUsed { .. } => {
+                return Ok(VerifyEmailPayload::InvalidCode);
+            }
+        }
+
         repo.user_email()
             .consume_verification_code(&clock, verification)
             .await?;
@@ -601,12 +654,12 @@ impl UserEmailMutations {
             return Ok(RemoveEmailPayload::NotFound);
         };
 
-        if !requester.is_owner_or_admin(&user_email) {
+        if !requester.is_owner_or_admin(state.admin_scopes(), &user_email) {
             return Ok(RemoveEmailPayload::NotFound);
         }
 
         // Allow non-admins to remove their email address if the site config allows it
-        if !requester.is_admin() && !state.site_config().email_change_allowed {
+        if !requester.is_admin(state.admin_scopes()) && !state.site_config().email_change_allowed {
             return Err(async_graphql::Error::new("Unauthorized"));
         }
 
@@ -650,13 +703,13 @@ impl UserEmailMutations {
             return Ok(SetPrimaryEmailPayload::NotFound);
         };
 
-        if !requester.is_owner_or_admin(&user_email) {
-            return Err(async_graphql::Error::new("Unauthorized"));
+        if !requester.is_owner_or_admin(state.admin_scopes(), &user_email) {
+            return Ok(SetPrimaryEmailPayload::NotFound);
         }
 
         // Allow non-admins to change their primary email address if the site config
         // allows it
-        if !requester.is_admin() && !state.site_config().email_change_allowed {
+        if !requester.is_admin(state.admin_scopes()) && !state.site_config().email_change_allowed {
             return Err(async_graphql::Error::new("Unauthorized"));
         }
 
@@ -26,13 +26,17 @@ use crate::{
 
 mod session;
 mod upstream_oauth;
+mod user;
 mod viewer;
 
-use self::{session::SessionQuery, upstream_oauth::UpstreamOAuthQuery, viewer::ViewerQuery};
+use self::{
+    session::SessionQuery, upstream_oauth::UpstreamOAuthQuery, user::UserQuery,
+    viewer::ViewerQuery,
+};
 
 /// The query root of the GraphQL interface.
 #[derive(Default, MergedObject)]
-pub struct Query(BaseQuery, UpstreamOAuthQuery, SessionQuery, ViewerQuery);
+pub struct Query(BaseQuery, UserQuery, UpstreamOAuthQuery, SessionQuery, ViewerQuery);
 
 impl Query {
     #[must_use]
@@ -87,15 +91,15 @@ impl BaseQuery {
     async fn user(&self, ctx: &Context<'_>, id: ID) -> Result<Option<User>, async_graphql::Error> {
         let id = NodeType::User.extract_ulid(&id)?;
 
+        let state = ctx.state();
         let requester = ctx.requester();
-        if !requester.is_owner_or_admin(&UserId(id)) {
+        if !requester.is_owner_or_admin(state.admin_scopes(), &UserId(id)) {
             return Ok(None);
         }
 
         // We could avoid the database lookup if the requester is the user we're looking
         // for but that would make the code more complex and we're not very
         // concerned about performance yet
-        let state = ctx.state();
         let mut repo = state.repository().await?;
         let user = repo.user().lookup(id).await?;
         repo.cancel().await?;
@@ -120,7 +124,7 @@ impl BaseQuery {
         };
 
         // Users can only see themselves, except for admins
-        if !requester.is_owner_or_admin(&user) {
+        if !requester.is_owner_or_admin(state.admin_scopes(), &user) {
             return Ok(None);
         }
 
@@ -137,15 +141,16 @@ impl BaseQuery {
         let id = NodeType::BrowserSession.extract_ulid(&id)?;
         let requester = ctx.requester();
 
+        let clock = state.clock();
         let mut repo = state.repository().await?;
-        let browser_session = repo.browser_session().lookup(id).await?;
+        let browser_session = repo.browser_session().lookup(&clock, id).await?;
         repo.cancel().await?;
 
         let Some(browser_session) = browser_session else {
             return Ok(None);
         };
 
-        if !requester.is_owner_or_admin(&browser_session) {
+        if !requester.is_owner_or_admin(state.admin_scopes(), &browser_session) {
             return Ok(None);
         }
 
@@ -170,7 +175,7 @@ impl BaseQuery {
             return Ok(None);
         };
 
-        if !requester.is_owner_or_admin(&compat_session) {
+        if !requester.is_owner_or_admin(state.admin_scopes(), &compat_session) {
             return Ok(None);
         }
 
@@ -195,7 +200,7 @@ impl BaseQuery {
             return Ok(None);
         };
 
-        if !requester.is_owner_or_admin(&oauth2_session) {
+        if !requester.is_owner_or_admin(state.admin_scopes(), &oauth2_session) {
             return Ok(None);
         }
 
@@ -220,7 +225,7 @@ impl BaseQuery {
             return Ok(None);
         };
 
-        if !requester.is_owner_or_admin(&user_email) {
+        if !requester.is_owner_or_admin(state.admin_scopes(), &user_email) {
             return Ok(None);
         }
 
@@ -244,7 +249,7 @@ impl BaseQuery {
 
         let ret = match node_type {
             // TODO
-            NodeType::Authentication | NodeType::CompatSsoLogin => None,
+            NodeType::AuditLogEntry | NodeType::Authentication | NodeType::CompatSsoLogin => None,
 
             NodeType::UpstreamOAuth2Provider => UpstreamOAuthQuery
                 .upstream_oauth2_provider(ctx, id)
@@ -47,15 +47,15 @@ impl SessionQuery {
         device_id: String,
     ) -> Result<Option<Session>, async_graphql::Error> {
         let user_id = NodeType::User.extract_ulid(&user_id)?;
+        let state = ctx.state();
         let requester = ctx.requester();
-        if !requester.is_owner_or_admin(&UserId(user_id)) {
+        if !requester.is_owner_or_admin(state.admin_scopes(), &UserId(user_id)) {
             return Ok(None);
         }
 
         let Ok(device) = Device::try_from(device_id) else {
             return Ok(None);
         };
-        let state = ctx.state();
         let mut repo = state.repository().await?;
 
         // Lookup the user
@@ -49,7 +49,7 @@ impl UpstreamOAuthQuery {
             return Ok(None);
         };
 
-        if !requester.is_owner_or_admin(&link) {
+        if !requester.is_owner_or_admin(state.admin_scopes(), &link) {
             return Ok(None);
         }
 
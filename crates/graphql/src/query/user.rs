@@ -0,0 +1,113 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use async_graphql::{
+    connection::{query, Connection, Edge, OpaqueCursor},
+    Context, InputObject, Object,
+};
+use mas_storage::{user::UserFilter, Pagination, RepositoryAccess};
+
+use crate::{
+    model::{Cursor, NodeCursor, NodeType, PreloadedTotalCount, User},
+    state::ContextExt,
+};
+
+#[derive(Default)]
+pub struct UserQuery;
+
+/// Filters to apply when listing users
+#[derive(InputObject, Default)]
+struct UsersFilter {
+    /// Only return users whose username starts with this prefix
+    username_prefix: Option<String>,
+
+    /// Only return users which can (or can't) request admin privileges
+    can_request_admin: Option<bool>,
+}
+
+#[Object]
+impl UserQuery {
+    /// Get a list of users.
+    ///
+    /// This is only available to administrators.
+    async fn users(
+        &self,
+        ctx: &Context<'_>,
+
+        #[graphql(desc = "Filters to apply to the list of users.")] filter: Option<UsersFilter>,
+
+        #[graphql(desc = "Returns the elements in the list that come after the cursor.")]
+        after: Option<String>,
+        #[graphql(desc = "Returns the elements in the list that come before the cursor.")]
+        before: Option<String>,
+        #[graphql(desc = "Returns the first *n* elements from the list.")] first: Option<i32>,
+        #[graphql(desc = "Returns the last *n* elements from the list.")] last: Option<i32>,
+    ) -> Result<Connection<Cursor, User, PreloadedTotalCount>, async_graphql::Error> {
+        let state = ctx.state();
+        let requester = ctx.requester();
+        if !requester.is_admin(state.admin_scopes()) {
+            return Err(async_graphql::Error::new("Unauthorized"));
+        }
+
+        let mut repo = state.repository().await?;
+        let filter = filter.unwrap_or_default();
+
+        query(
+            after,
+            before,
+            first,
+            last,
+            |after, before, first, last| async move {
+                let after_id = after
+                    .map(|x: OpaqueCursor<NodeCursor>| x.extract_for_type(NodeType::User))
+                    .transpose()?;
+                let before_id = before
+                    .map(|x: OpaqueCursor<NodeCursor>| x.extract_for_type(NodeType::User))
+                    .transpose()?;
+                let pagination = Pagination::try_new(before_id, after_id, first, last)?;
+
+                let mut storage_filter = UserFilter::new();
+                if let Some(prefix) = filter.username_prefix.as_deref() {
+                    storage_filter = storage_filter.with_username_prefix(prefix);
+                }
+                if let Some(can_request_admin) = filter.can_request_admin {
+                    storage_filter = storage_filter.with_can_request_admin(can_request_admin);
+                }
+
+                let page = repo.user().list(storage_filter, pagination).await?;
+
+                // Preload the total count if requested
+                let count = if ctx.look_ahead().field("totalCount").exists() {
+                    Some(repo.user().count(storage_filter).await?)
+                } else {
+                    None
+                };
+
+                repo.cancel().await?;
+
+                let mut connection = Connection::with_additional_fields(
+                    page.has_previous_page,
+                    page.has_next_page,
+                    PreloadedTotalCount(count),
+                );
+                connection.edges.extend(page.edges.into_iter().map(|u| {
+                    Edge::new(OpaqueCursor(NodeCursor(NodeType::User, u.id)), User(u))
+                }));
+
+                Ok::<_, async_graphql::Error>(connection)
+            },
+        )
+        .await
+    }
+}
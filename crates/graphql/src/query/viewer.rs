@@ -35,7 +35,7 @@ impl ViewerQuery {
                 Some(user) => Viewer::user(user.clone()),
                 None => Viewer::anonymous(),
             },
-            Requester::Anonymous => Viewer::anonymous(),
+            Requester::ServiceAccount { .. } | Requester::Anonymous => Viewer::anonymous(),
         }
     }
 
@@ -46,7 +46,7 @@ impl ViewerQuery {
         match requester {
             Requester::BrowserSession(session) => ViewerSession::browser_session(*session.clone()),
             Requester::OAuth2Session(tuple) => ViewerSession::oauth2_session(tuple.0.clone()),
-            Requester::Anonymous => ViewerSession::anonymous(),
+            Requester::ServiceAccount { .. } | Requester::Anonymous => ViewerSession::anonymous(),
         }
     }
 }
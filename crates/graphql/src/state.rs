@@ -12,7 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use mas_data_model::SiteConfig;
+use mas_data_model::{BrowserSession, Session, SiteConfig, User};
 use mas_matrix::HomeserverConnection;
 use mas_policy::Policy;
 use mas_storage::{BoxClock, BoxRepository, BoxRng, RepositoryError};
@@ -27,6 +27,50 @@ pub trait State {
     fn clock(&self) -> BoxClock;
     fn rng(&self) -> BoxRng;
     fn site_config(&self) -> &SiteConfig;
+
+    /// The scopes which make an OAuth 2.0 session an administrator
+    fn admin_scopes(&self) -> &[String];
+
+    /// Prepare the back-channel logout notifications for the clients of the
+    /// sessions which are about to end.
+    ///
+    /// This must be called before the sessions are ended, and the returned
+    /// notifications dispatched once the change is saved.
+    async fn prepare_backchannel_logout(
+        &self,
+        repo: &mut BoxRepository,
+        sessions: EndingSessions<'_>,
+    ) -> Result<PendingLogouts, RepositoryError>;
+}
+
+/// The sessions ending in a mutation, for which clients should be notified
+#[derive(Debug, Clone, Copy)]
+pub enum EndingSessions<'a> {
+    /// A single browser session, and the OAuth 2.0 sessions started from it
+    BrowserSession(&'a BrowserSession),
+
+    /// A single OAuth 2.0 session
+    OAuth2Session(&'a Session),
+
+    /// All the sessions of a user
+    User(&'a User),
+}
+
+/// Back-channel logout notifications waiting for the change to be saved
+#[must_use = "the notifications must be dispatched once the change is saved"]
+pub struct PendingLogouts(Box<dyn FnOnce() + Send>);
+
+impl PendingLogouts {
+    /// Wrap the function which sends the notifications
+    #[must_use]
+    pub fn new(dispatch: impl FnOnce() + Send + 'static) -> Self {
+        Self(Box::new(dispatch))
+    }
+
+    /// Send the notifications
+    pub fn dispatch(self) {
+        (self.0)();
+    }
 }
 
 pub type BoxState = Box<dyn State + Send + Sync + 'static>;
@@ -203,3 +203,72 @@ impl ActivityTracker {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use mas_storage::RepositoryAccess;
+
+    use super::*;
+    use crate::test_utils::{init_tracing, TestState};
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_browser_session_activity(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool.clone()).await.unwrap();
+        let mut rng = state.rng();
+
+        // Use a long interval, so that the activity is only written on manual flushes
+        let tracker = ActivityTracker::new(pool, std::time::Duration::from_secs(3600));
+
+        let mut repo = state.repository().await.unwrap();
+        let user = repo
+            .user()
+            .add(&mut rng, &state.clock, "john".to_owned())
+            .await
+            .unwrap();
+        let session = repo
+            .browser_session()
+            .add(&mut rng, &state.clock, &user, None, None, None)
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+        assert!(session.last_active_at.is_none());
+
+        let ip = "203.0.113.1".parse().unwrap();
+        tracker
+            .record_browser_session(&state.clock, &session, Some(ip))
+            .await;
+        state.clock.advance(Duration::try_seconds(10).unwrap());
+        let last_active_at = state.clock.now();
+        tracker
+            .record_browser_session(&state.clock, &session, Some(ip))
+            .await;
+
+        // Nothing is written until the tracker is flushed
+        let mut repo = state.repository().await.unwrap();
+        let session = repo
+            .browser_session()
+            .lookup(&state.clock, session.id)
+            .await
+            .unwrap()
+            .unwrap();
+        repo.cancel().await.unwrap();
+        assert!(session.last_active_at.is_none());
+
+        // Once flushed, only the latest activity is recorded
+        tracker.flush().await;
+        let mut repo = state.repository().await.unwrap();
+        let session = repo
+            .browser_session()
+            .lookup(&state.clock, session.id)
+            .await
+            .unwrap()
+            .unwrap();
+        repo.cancel().await.unwrap();
+        assert_eq!(session.last_active_at, Some(last_active_at));
+        assert_eq!(session.last_active_ip, Some(ip));
+
+        tracker.shutdown().await;
+    }
+}
@@ -12,6 +12,8 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::sync::Arc;
+
 use axum::{extract::State, response::IntoResponse, Json, TypedHeader};
 use chrono::Duration;
 use hyper::StatusCode;
@@ -26,7 +28,7 @@ use mas_storage::{
         CompatSsoLoginRepository,
     },
     job::{JobRepositoryExt, ProvisionDeviceJob},
-    user::{UserPasswordRepository, UserRepository},
+    user::{UserPasswordRepository, UserRepository, UserTotpRepository},
     BoxClock, BoxRepository, BoxRng, Clock, RepositoryAccess,
 };
 use rand::{CryptoRng, RngCore};
@@ -36,7 +38,13 @@ use thiserror::Error;
 use zeroize::Zeroizing;
 
 use super::MatrixError;
-use crate::{impl_from_error_for_route, passwords::PasswordManager, BoundActivityTracker};
+use crate::{
+    impl_from_error_for_route,
+    login_throttle::{throttle_password_check, Throttled},
+    metrics,
+    passwords::PasswordManager,
+    BoundActivityTracker, LoginThrottle,
+};
 
 #[derive(Debug, Serialize)]
 #[serde(tag = "type")]
@@ -162,6 +170,12 @@ pub enum RouteError {
     #[error("password verification failed")]
     PasswordVerificationFailed(#[source] anyhow::Error),
 
+    #[error("user has a second factor")]
+    SecondFactorRequired,
+
+    #[error("too many failed login attempts, retry after {retry_after}")]
+    RateLimited { retry_after: Duration },
+
     #[error("login took too long")]
     LoginTookTooLong,
 
@@ -171,6 +185,14 @@ pub enum RouteError {
 
 impl_from_error_for_route!(mas_storage::RepositoryError);
 
+impl From<Throttled> for RouteError {
+    fn from(e: Throttled) -> Self {
+        Self::RateLimited {
+            retry_after: e.retry_after,
+        }
+    }
+}
+
 impl IntoResponse for RouteError {
     fn into_response(self) -> axum::response::Response {
         let event_id = sentry::capture_error(&self);
@@ -186,22 +208,48 @@ impl IntoResponse for RouteError {
                 status: StatusCode::BAD_REQUEST,
             },
             Self::UserNotFound | Self::NoPassword | Self::PasswordVerificationFailed(_) => {
+                metrics::record_authentication_failure("compat_password");
                 MatrixError {
                     errcode: "M_UNAUTHORIZED",
                     error: "Invalid username/password",
                     status: StatusCode::FORBIDDEN,
                 }
             }
-            Self::LoginTookTooLong => MatrixError {
-                errcode: "M_UNAUTHORIZED",
-                error: "Login token expired",
-                status: StatusCode::FORBIDDEN,
-            },
-            Self::InvalidLoginToken => MatrixError {
-                errcode: "M_UNAUTHORIZED",
-                error: "Invalid login token",
+            Self::SecondFactorRequired => MatrixError {
+                errcode: "M_FORBIDDEN",
+                error: "This account requires a second factor, log in through SSO instead",
                 status: StatusCode::FORBIDDEN,
             },
+            Self::RateLimited { retry_after } => {
+                // This error carries the delay, which doesn't fit in a `MatrixError`
+                let body = serde_json::json!({
+                    "errcode": "M_LIMIT_EXCEEDED",
+                    "error": "Too many failed login attempts",
+                    "retry_after_ms": retry_after.num_milliseconds(),
+                });
+                return (
+                    SentryEventID::from(event_id),
+                    StatusCode::TOO_MANY_REQUESTS,
+                    Json(body),
+                )
+                    .into_response();
+            }
+            Self::LoginTookTooLong => {
+                metrics::record_authentication_failure("compat_token");
+                MatrixError {
+                    errcode: "M_UNAUTHORIZED",
+                    error: "Login token expired",
+                    status: StatusCode::FORBIDDEN,
+                }
+            }
+            Self::InvalidLoginToken => {
+                metrics::record_authentication_failure("compat_token");
+                MatrixError {
+                    errcode: "M_UNAUTHORIZED",
+                    error: "Invalid login token",
+                    status: StatusCode::FORBIDDEN,
+                }
+            }
         };
 
         (SentryEventID::from(event_id), response).into_response()
@@ -213,6 +261,7 @@ pub(crate) async fn post(
     mut rng: BoxRng,
     clock: BoxClock,
     State(password_manager): State<PasswordManager>,
+    State(login_throttle): State<Arc<dyn LoginThrottle>>,
     mut repo: BoxRepository,
     activity_tracker: BoundActivityTracker,
     State(homeserver): State<BoxHomeserverConnection>,
@@ -233,6 +282,7 @@ pub(crate) async fn post(
                 &mut rng,
                 &clock,
                 &password_manager,
+                &*login_throttle,
                 &mut repo,
                 user,
                 password,
@@ -366,37 +416,52 @@ async fn user_password_login(
     mut rng: &mut (impl RngCore + CryptoRng + Send),
     clock: &impl Clock,
     password_manager: &PasswordManager,
+    login_throttle: &dyn LoginThrottle,
     repo: &mut BoxRepository,
     username: String,
     password: String,
 ) -> Result<(CompatSession, User), RouteError> {
-    // Find the user
-    let user = repo
-        .user()
-        .find_by_username(&username)
-        .await?
-        .filter(mas_data_model::User::is_valid)
-        .ok_or(RouteError::UserNotFound)?;
-
-    // Lookup its password
-    let user_password = repo
-        .user_password()
-        .active(&user)
-        .await?
-        .ok_or(RouteError::NoPassword)?;
-
-    // Verify the password
-    let password = Zeroizing::new(password.into_bytes());
+    // The failed attempts are counted together with the ones on the login page,
+    // so that this can't be used to bypass the throttle
+    let (user, user_password, new_password_hash) =
+        throttle_password_check(login_throttle, clock, &username, async {
+            // Find the user
+            let user = repo
+                .user()
+                .find_by_username(&username)
+                .await?
+                .filter(mas_data_model::User::is_valid)
+                .ok_or(RouteError::UserNotFound)?;
+
+            // Lookup its password
+            let user_password = repo
+                .user_password()
+                .active(&user)
+                .await?
+                .ok_or(RouteError::NoPassword)?;
+
+            // Verify the password
+            let password = Zeroizing::new(password.into_bytes());
+
+            let new_password_hash = password_manager
+                .verify_and_upgrade(
+                    &mut rng,
+                    user_password.version,
+                    password,
+                    user_password.hashed_password.clone(),
+                )
+                .await
+                .map_err(RouteError::PasswordVerificationFailed)?;
+
+            Ok::<_, RouteError>((user, user_password, new_password_hash))
+        })
+        .await?;
 
-    let new_password_hash = password_manager
-        .verify_and_upgrade(
-            &mut rng,
-            user_password.version,
-            password,
-            user_password.hashed_password.clone(),
-        )
-        .await
-        .map_err(RouteError::PasswordVerificationFailed)?;
+    // The password alone isn't enough for users with a second factor, which
+    // can't be checked here. They have to go through the SSO login flow
+    if repo.user_totp().lookup_for_user(&user).await?.is_some() {
+        return Err(RouteError::SecondFactorRequired);
+    }
 
     if let Some((version, hashed_password)) = new_password_hash {
         // Save the upgraded password if needed
@@ -429,11 +494,15 @@ async fn user_password_login(
 #[cfg(test)]
 mod tests {
     use hyper::Request;
+    use mas_storage::compat::CompatSessionFilter;
     use rand::distributions::{Alphanumeric, DistString};
     use sqlx::PgPool;
 
     use super::*;
-    use crate::test_utils::{init_tracing, RequestBuilderExt, ResponseExt, TestState};
+    use crate::{
+        test_utils::{init_tracing, RequestBuilderExt, ResponseExt, TestState},
+        InMemoryLoginThrottle,
+    };
 
     /// Test that the server advertises the right login flows.
     #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
@@ -630,6 +699,171 @@ mod tests {
         assert_eq!(body, old_body);
     }
 
+    /// Test that failed password logins using the Matrix compatibility API
+    /// are throttled like on the login page.
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_user_password_login_throttled(pool: PgPool) {
+        init_tracing();
+        let mut state = TestState::from_pool(pool).await.unwrap();
+        state.login_throttle = Arc::new(InMemoryLoginThrottle::new(
+            3,
+            Duration::try_minutes(10).unwrap(),
+            Duration::try_seconds(1).unwrap(),
+            Duration::try_minutes(5).unwrap(),
+        ));
+        let mut rng = state.rng();
+
+        let mut repo = state.repository().await.unwrap();
+        let user = repo
+            .user()
+            .add(&mut rng, &state.clock, "alice".to_owned())
+            .await
+            .unwrap();
+        let (version, hashed_password) = state
+            .password_manager
+            .hash(&mut rng, Zeroizing::new("password".as_bytes().to_vec()))
+            .await
+            .unwrap();
+        repo.user_password()
+            .add(
+                &mut rng,
+                &state.clock,
+                &user,
+                version,
+                hashed_password,
+                None,
+            )
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        let login = |password: &str| {
+            Request::post("/_matrix/client/v3/login").json(serde_json::json!({
+                "type": "m.login.password",
+                "identifier": {
+                    "type": "m.id.user",
+                    "user": "alice",
+                },
+                "password": password,
+            }))
+        };
+
+        // The first two failures are reported as such
+        for _ in 0..2 {
+            let response = state.request(login("wrongpassword")).await;
+            response.assert_status(StatusCode::FORBIDDEN);
+        }
+
+        // The second failure starts the backoff, even the right password is
+        // rejected until then
+        let response = state.request(login("password")).await;
+        response.assert_status(StatusCode::TOO_MANY_REQUESTS);
+        let body: serde_json::Value = response.json();
+        assert_eq!(body["errcode"], "M_LIMIT_EXCEEDED");
+        assert_eq!(body["retry_after_ms"], 1000);
+
+        // The third failure locks the account
+        state.clock.advance(Duration::try_seconds(1).unwrap());
+        let response = state.request(login("wrongpassword")).await;
+        response.assert_status(StatusCode::FORBIDDEN);
+
+        let response = state.request(login("password")).await;
+        response.assert_status(StatusCode::TOO_MANY_REQUESTS);
+
+        // Once the lockout is over, the right password works
+        state.clock.advance(Duration::try_minutes(5).unwrap());
+        let response = state.request(login("password")).await;
+        response.assert_status(StatusCode::OK);
+    }
+
+    /// Test that users with a second factor can't login with only their
+    /// password using the Matrix compatibility API.
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_user_password_login_with_totp(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let mut rng = state.rng();
+
+        // Provision a user with a password and a TOTP secret
+        let mut repo = state.repository().await.unwrap();
+
+        let user = repo
+            .user()
+            .add(&mut rng, &state.clock, "alice".to_owned())
+            .await
+            .unwrap();
+
+        let (version, hashed_password) = state
+            .password_manager
+            .hash(&mut rng, Zeroizing::new("password".to_owned().into_bytes()))
+            .await
+            .unwrap();
+
+        repo.user_password()
+            .add(
+                &mut rng,
+                &state.clock,
+                &user,
+                version,
+                hashed_password,
+                None,
+            )
+            .await
+            .unwrap();
+
+        let secret = crate::totp::generate_secret(&mut rng);
+        repo.user_totp()
+            .add(
+                &mut rng,
+                &state.clock,
+                &user,
+                state.encrypter.encrypt_to_string(&secret).unwrap(),
+            )
+            .await
+            .unwrap();
+
+        repo.save().await.unwrap();
+
+        // The right password isn't enough
+        let request = Request::post("/_matrix/client/v3/login").json(serde_json::json!({
+            "type": "m.login.password",
+            "identifier": {
+                "type": "m.id.user",
+                "user": "alice",
+            },
+            "password": "password",
+        }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::FORBIDDEN);
+        let body: serde_json::Value = response.json();
+        assert_eq!(body["errcode"], "M_FORBIDDEN");
+
+        // A wrong password doesn't tell that the user has a second factor
+        let request = Request::post("/_matrix/client/v3/login").json(serde_json::json!({
+            "type": "m.login.password",
+            "identifier": {
+                "type": "m.id.user",
+                "user": "alice",
+            },
+            "password": "wrongpassword",
+        }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::FORBIDDEN);
+        let body: serde_json::Value = response.json();
+        assert_eq!(body["errcode"], "M_UNAUTHORIZED");
+
+        // And no session was started
+        let mut repo = state.repository().await.unwrap();
+        let count = repo
+            .compat_session()
+            .count(CompatSessionFilter::new().for_user(&user))
+            .await
+            .unwrap();
+        assert_eq!(count, 0);
+    }
+
     /// Test the response of an unsupported login flow.
     #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
     async fn test_unsupported_login(pool: PgPool) {
@@ -0,0 +1,213 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A cache of documents fetched from remote servers, like discovery documents
+//! or JSON Web Key Sets
+
+use std::{borrow::Borrow, collections::HashMap, future::Future, hash::Hash, sync::Arc};
+
+use chrono::{DateTime, Utc};
+use tokio::sync::{Mutex, RwLock};
+
+/// A value held by a [`FetchCache`], along with when it was fetched
+#[derive(Debug)]
+pub(crate) struct CachedValue<V> {
+    pub(crate) value: Arc<V>,
+    pub(crate) fetched_at: DateTime<Utc>,
+    pub(crate) expires_at: DateTime<Utc>,
+}
+
+type Slot<V> = Arc<Mutex<Option<CachedValue<V>>>>;
+
+/// A cache of values fetched from remote servers, keyed by `K`
+///
+/// Each key has its own lock, which is held while its value is fetched. This
+/// way, concurrent requests for the same key wait for a single fetch, without
+/// blocking the requests for other keys. Failures are not cached, and evict
+/// the previously cached value.
+pub(crate) struct FetchCache<K, V> {
+    slots: Arc<RwLock<HashMap<K, Slot<V>>>>,
+}
+
+impl<K, V> Clone for FetchCache<K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            slots: Arc::clone(&self.slots),
+        }
+    }
+}
+
+impl<K, V> Default for FetchCache<K, V> {
+    fn default() -> Self {
+        Self {
+            slots: Arc::default(),
+        }
+    }
+}
+
+impl<K, V> std::fmt::Debug for FetchCache<K, V> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FetchCache").finish_non_exhaustive()
+    }
+}
+
+impl<K: Hash + Eq, V> FetchCache<K, V> {
+    /// Get the slot of the given key, creating it if needed
+    async fn slot<Q>(&self, key: &Q) -> Slot<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        if let Some(slot) = self.slots.read().await.get(key) {
+            return Arc::clone(slot);
+        }
+
+        let mut slots = self.slots.write().await;
+        Arc::clone(slots.entry(key.to_owned()).or_default())
+    }
+
+    /// Get the value for the given key, fetching it if there is no cached
+    /// value, or if `is_usable` returns false for it
+    ///
+    /// `fetch` returns the new value along with when it expires.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error of `fetch` if the value had to be fetched and it
+    /// failed
+    pub(crate) async fn get_or_fetch<Q, E, Fut>(
+        &self,
+        key: &Q,
+        now: DateTime<Utc>,
+        is_usable: impl FnOnce(&CachedValue<V>) -> bool,
+        fetch: impl FnOnce() -> Fut,
+    ) -> Result<Arc<V>, E>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+        Fut: Future<Output = Result<(V, DateTime<Utc>), E>>,
+    {
+        let slot = self.slot(key).await;
+        let mut slot = slot.lock().await;
+
+        // Another request may have fetched it while we were waiting for the lock
+        if let Some(cached) = &*slot {
+            if is_usable(cached) {
+                return Ok(Arc::clone(&cached.value));
+            }
+        }
+
+        match fetch().await {
+            Ok((value, expires_at)) => {
+                let value = Arc::new(value);
+                *slot = Some(CachedValue {
+                    value: Arc::clone(&value),
+                    fetched_at: now,
+                    expires_at,
+                });
+                Ok(value)
+            }
+            Err(e) => {
+                *slot = None;
+                Err(e)
+            }
+        }
+    }
+
+    /// Fetch the value for the given key, even if there is a cached value
+    ///
+    /// # Errors
+    ///
+    /// Returns the error of `fetch` if it failed
+    pub(crate) async fn refresh<Q, E, Fut>(
+        &self,
+        key: &Q,
+        now: DateTime<Utc>,
+        fetch: impl FnOnce() -> Fut,
+    ) -> Result<Arc<V>, E>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+        Fut: Future<Output = Result<(V, DateTime<Utc>), E>>,
+    {
+        self.get_or_fetch(key, now, |_| false, fetch).await
+    }
+
+    /// List the keys which currently have a cached value
+    pub(crate) async fn keys(&self) -> Vec<K>
+    where
+        K: Clone,
+    {
+        let slots: Vec<(K, Slot<V>)> = self
+            .slots
+            .read()
+            .await
+            .iter()
+            .map(|(key, slot)| (key.clone(), Arc::clone(slot)))
+            .collect();
+
+        let mut keys = Vec::with_capacity(slots.len());
+        for (key, slot) in slots {
+            if slot.lock().await.is_some() {
+                keys.push(key);
+            }
+        }
+        keys
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use chrono::Duration;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_fetch_cache() {
+        let now = DateTime::<Utc>::UNIX_EPOCH;
+        let cache: FetchCache<String, u32> = FetchCache::default();
+        let calls = &AtomicUsize::new(0);
+        let fetch = move || async move {
+            calls.fetch_add(1, Ordering::SeqCst);
+            // Give the other requests a chance to wait on the lock
+            tokio::task::yield_now().await;
+            Ok::<_, ()>((42, now + Duration::minutes(1)))
+        };
+        let is_fresh = |cached: &CachedValue<u32>| now < cached.expires_at;
+
+        // Concurrent requests for the same key only fetch once
+        let (a, b) = tokio::join!(
+            cache.get_or_fetch("a", now, is_fresh, fetch),
+            cache.get_or_fetch("a", now, is_fresh, fetch),
+        );
+        assert_eq!(*a.unwrap(), 42);
+        assert_eq!(*b.unwrap(), 42);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // Other keys are fetched separately
+        cache.get_or_fetch("b", now, is_fresh, fetch).await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        // Failures evict the cached value
+        let res = cache.refresh("b", now, || async { Err(()) }).await;
+        assert!(res.is_err());
+        assert_eq!(cache.keys().await, vec!["a".to_owned()]);
+
+        // Refreshing always fetches
+        cache.refresh("a", now, fetch).await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+}
@@ -29,12 +29,18 @@ use futures_util::TryStreamExt;
 use headers::{authorization::Bearer, Authorization, ContentType, HeaderValue};
 use hyper::header::CACHE_CONTROL;
 use mas_axum_utils::{
-    cookies::CookieJar, sentry::SentryEventID, FancyError, SessionInfo, SessionInfoExt,
+    cookies::CookieJar,
+    dpop::{DpopNonceStore, DpopRequest},
+    sentry::SentryEventID,
+    user_authorization::find_access_token,
+    FancyError, SessionInfo, SessionInfoExt,
 };
-use mas_data_model::{SiteConfig, User};
-use mas_graphql::{Requester, Schema};
+use mas_data_model::{ServiceAccount, SiteConfig, User};
+use mas_graphql::{EndingSessions, PendingLogouts, Requester, Schema};
+use mas_keystore::Keystore;
 use mas_matrix::HomeserverConnection;
 use mas_policy::{InstantiateError, Policy, PolicyFactory};
+use mas_router::UrlBuilder;
 use mas_storage::{
     BoxClock, BoxRepository, BoxRng, Clock, Repository, RepositoryError, SystemClock,
 };
@@ -42,19 +48,23 @@ use mas_storage_pg::PgRepository;
 use opentelemetry_semantic_conventions::trace::{GRAPHQL_DOCUMENT, GRAPHQL_OPERATION_NAME};
 use rand::{thread_rng, SeedableRng};
 use rand_chacha::ChaChaRng;
+use sha2::{Digest, Sha256};
 use sqlx::PgPool;
 use tracing::{info_span, Instrument};
 
-use crate::{impl_from_error_for_route, BoundActivityTracker};
+use crate::{impl_from_error_for_route, BackchannelLogoutDispatcher, BoundActivityTracker};
 
 #[cfg(test)]
 mod tests;
 
+#[derive(Clone)]
 struct GraphQLState {
     pool: PgPool,
     homeserver_connection: Arc<dyn HomeserverConnection<Error = anyhow::Error>>,
     policy_factory: Arc<PolicyFactory>,
     site_config: SiteConfig,
+    admin_scopes: Vec<String>,
+    backchannel_logout: BackchannelLogoutDispatcher,
 }
 
 #[async_trait]
@@ -75,6 +85,10 @@ impl mas_graphql::State for GraphQLState {
         &self.site_config
     }
 
+    fn admin_scopes(&self) -> &[String] {
+        &self.admin_scopes
+    }
+
     fn homeserver_connection(&self) -> &dyn HomeserverConnection<Error = anyhow::Error> {
         self.homeserver_connection.as_ref()
     }
@@ -91,6 +105,18 @@ impl mas_graphql::State for GraphQLState {
         let rng = ChaChaRng::from_rng(rng).expect("Failed to seed rng");
         Box::new(rng)
     }
+
+    async fn prepare_backchannel_logout(
+        &self,
+        repo: &mut BoxRepository,
+        sessions: EndingSessions<'_>,
+    ) -> Result<PendingLogouts, RepositoryError> {
+        let mut rng = self.rng();
+        let clock = self.clock();
+        self.backchannel_logout
+            .prepare_for_graphql(&mut rng, &clock, repo, sessions)
+            .await
+    }
 }
 
 #[must_use]
@@ -99,19 +125,26 @@ pub fn schema(
     policy_factory: &Arc<PolicyFactory>,
     homeserver_connection: impl HomeserverConnection<Error = anyhow::Error> + 'static,
     site_config: SiteConfig,
+    admin_scopes: Vec<String>,
+    backchannel_logout: BackchannelLogoutDispatcher,
 ) -> Schema {
     let state = GraphQLState {
         pool: pool.clone(),
         policy_factory: Arc::clone(policy_factory),
         homeserver_connection: Arc::new(homeserver_connection),
         site_config,
+        admin_scopes,
+        backchannel_logout,
     };
+    let last_authentication_loader =
+        mas_graphql::LastAuthenticationLoader::new(Box::new(state.clone()));
     let state: mas_graphql::BoxState = Box::new(state);
 
     mas_graphql::schema_builder()
         .extension(Tracing)
         .extension(ApolloTracing)
         .data(state)
+        .data(last_authentication_loader)
         .finish()
 }
 
@@ -146,6 +179,9 @@ pub enum RouteError {
     #[error("Missing scope")]
     MissingScope,
 
+    #[error("The user is deactivated")]
+    UserDeactivated,
+
     #[error(transparent)]
     ParseRequest(#[from] async_graphql::ParseRequestError),
 }
@@ -184,6 +220,15 @@ impl IntoResponse for RouteError {
                     .into_response()
             }
 
+            Self::UserDeactivated => {
+                let error = async_graphql::Error::new("User is deactivated");
+                (
+                    StatusCode::UNAUTHORIZED,
+                    Json(serde_json::json!({"errors": [error]})),
+                )
+                    .into_response()
+            }
+
             Self::ParseRequest(e) => {
                 let error = async_graphql::Error::new_with_source(e);
                 (
@@ -198,26 +243,61 @@ impl IntoResponse for RouteError {
     }
 }
 
+/// Find the service account using the given static token, if any
+fn find_service_account<'a>(
+    service_accounts: &'a [ServiceAccount],
+    token: &str,
+) -> Option<&'a ServiceAccount> {
+    let token_sha256: [u8; 32] = Sha256::digest(token.as_bytes()).into();
+    service_accounts
+        .iter()
+        .find(|account| account.token_sha256 == token_sha256)
+}
+
 async fn get_requester(
     clock: &impl Clock,
     activity_tracker: &BoundActivityTracker,
     mut repo: BoxRepository,
+    site_config: &SiteConfig,
     session_info: SessionInfo,
+    key_store: &Keystore,
     token: Option<&str>,
+    dpop: &DpopRequest,
+    dpop_nonce_store: &dyn DpopNonceStore,
+    url_builder: &UrlBuilder,
 ) -> Result<Requester, RouteError> {
-    let requester = if let Some(token) = token {
-        let token = repo
-            .oauth2_access_token()
-            .find_by_token(token)
-            .await?
-            .ok_or(RouteError::InvalidToken)?;
-
-        let session = repo
+    // Static service account tokens take precedence over everything else, so
+    // that a service never accidentally acts with a browser session
+    let service_account =
+        token.and_then(|token| find_service_account(&site_config.service_accounts, token));
+
+    let requester = if let Some(service_account) = service_account {
+        Requester::ServiceAccount {
+            name: service_account.name.clone(),
+        }
+    } else if let Some(token) = token {
+        let token = find_access_token(
+            &mut repo,
+            clock,
+            key_store,
+            &url_builder.oidc_issuer(),
+            token,
+        )
+        .await?
+        .ok_or(RouteError::InvalidToken)?;
+
+        let mut session = repo
             .oauth2_session()
             .lookup(token.session_id)
             .await?
             .ok_or(RouteError::LoadFailed)?;
 
+        // The token might have been issued with a narrower scope than its session,
+        // in which case it only grants that scope
+        if let Some(scope) = &token.scope {
+            session.scope = scope.clone();
+        }
+
         activity_tracker
             .record_oauth2_session(clock, &session)
             .await;
@@ -234,6 +314,12 @@ async fn get_requester(
             None
         };
 
+        // Deactivated users can't use the API anymore, even with a token issued
+        // before they were deactivated
+        if user.as_ref().is_some_and(User::is_deactivated) {
+            return Err(RouteError::UserDeactivated);
+        }
+
         // If there is a user for this session, check that it is not locked
         let user_valid = user.as_ref().map_or(true, User::is_valid);
 
@@ -241,15 +327,32 @@ async fn get_requester(
             return Err(RouteError::InvalidToken);
         }
 
+        // If the token is restricted to some resources, the API must be one of them
+        if !token.allows_resource(&session.resource, &url_builder.graphql_endpoint()) {
+            return Err(RouteError::InvalidToken);
+        }
+
+        // If the token is bound to a DPoP key, the request must prove possession of it
+        if dpop
+            .verify_binding(&token, clock.now(), dpop_nonce_store)
+            .is_err()
+        {
+            return Err(RouteError::InvalidToken);
+        }
+
         if !session.scope.contains("urn:mas:graphql:*") {
             return Err(RouteError::MissingScope);
         }
 
         Requester::OAuth2Session(Box::new((session, user)))
     } else {
-        let maybe_session = session_info.load_session(&mut repo).await?;
+        let maybe_session = session_info.load_session(clock, &mut repo).await?;
 
         if let Some(session) = maybe_session.as_ref() {
+            if session.user.is_deactivated() {
+                return Err(RouteError::UserDeactivated);
+            }
+
             activity_tracker
                 .record_browser_session(clock, session)
                 .await;
@@ -263,19 +366,38 @@ async fn get_requester(
 
 pub async fn post(
     State(schema): State<Schema>,
+    State(site_config): State<SiteConfig>,
+    State(dpop_nonce_store): State<Arc<dyn DpopNonceStore>>,
+    State(key_store): State<Keystore>,
+    State(url_builder): State<UrlBuilder>,
     clock: BoxClock,
     repo: BoxRepository,
     activity_tracker: BoundActivityTracker,
     cookie_jar: CookieJar,
     content_type: Option<TypedHeader<ContentType>>,
     authorization: Option<TypedHeader<Authorization<Bearer>>>,
+    dpop: DpopRequest,
     body: BodyStream,
 ) -> Result<impl IntoResponse, RouteError> {
-    let token = authorization
-        .as_ref()
-        .map(|TypedHeader(Authorization(bearer))| bearer.token());
-    let (session_info, _cookie_jar) = cookie_jar.session_info();
-    let requester = get_requester(&clock, &activity_tracker, repo, session_info, token).await?;
+    let token = dpop.token().or_else(|| {
+        authorization
+            .as_ref()
+            .map(|TypedHeader(Authorization(bearer))| bearer.token())
+    });
+    let (session_info, _cookie_jar) = cookie_jar.session_info(&clock);
+    let requester = get_requester(
+        &clock,
+        &activity_tracker,
+        repo,
+        &site_config,
+        session_info,
+        &key_store,
+        token,
+        &dpop,
+        &*dpop_nonce_store,
+        &url_builder,
+    )
+    .await?;
 
     let content_type = content_type.map(|TypedHeader(h)| h.to_string());
 
@@ -304,18 +426,37 @@ pub async fn post(
 
 pub async fn get(
     State(schema): State<Schema>,
+    State(site_config): State<SiteConfig>,
+    State(dpop_nonce_store): State<Arc<dyn DpopNonceStore>>,
+    State(key_store): State<Keystore>,
+    State(url_builder): State<UrlBuilder>,
     clock: BoxClock,
     repo: BoxRepository,
     activity_tracker: BoundActivityTracker,
     cookie_jar: CookieJar,
     authorization: Option<TypedHeader<Authorization<Bearer>>>,
+    dpop: DpopRequest,
     RawQuery(query): RawQuery,
 ) -> Result<impl IntoResponse, FancyError> {
-    let token = authorization
-        .as_ref()
-        .map(|TypedHeader(Authorization(bearer))| bearer.token());
-    let (session_info, _cookie_jar) = cookie_jar.session_info();
-    let requester = get_requester(&clock, &activity_tracker, repo, session_info, token).await?;
+    let token = dpop.token().or_else(|| {
+        authorization
+            .as_ref()
+            .map(|TypedHeader(Authorization(bearer))| bearer.token())
+    });
+    let (session_info, _cookie_jar) = cookie_jar.session_info(&clock);
+    let requester = get_requester(
+        &clock,
+        &activity_tracker,
+        repo,
+        &site_config,
+        session_info,
+        &key_store,
+        token,
+        &dpop,
+        &*dpop_nonce_store,
+        &url_builder,
+    )
+    .await?;
 
     let request =
         async_graphql::http::parse_query_string(&query.unwrap_or_default())?.data(requester);
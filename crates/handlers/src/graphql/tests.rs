@@ -13,8 +13,10 @@
 // limitations under the License.
 
 use axum::http::Request;
+use chrono::Duration;
 use hyper::StatusCode;
-use mas_data_model::{AccessToken, Client, TokenType, User};
+use mas_axum_utils::SessionInfoExt;
+use mas_data_model::{AccessToken, Client, ServiceAccount, SiteConfig, TokenType, User};
 use mas_router::SimpleRoute;
 use mas_storage::{
     oauth2::{OAuth2AccessTokenRepository, OAuth2ClientRepository},
@@ -25,11 +27,14 @@ use oauth2_types::{
     requests::AccessTokenResponse,
     scope::{Scope, ScopeToken, OPENID},
 };
+use sha2::{Digest, Sha256};
 use sqlx::PgPool;
 
 use crate::{
     test_utils,
-    test_utils::{init_tracing, RequestBuilderExt, ResponseExt, TestState},
+    test_utils::{
+        init_tracing, test_site_config, CookieHelper, RequestBuilderExt, ResponseExt, TestState,
+    },
 };
 
 async fn create_test_client(state: &TestState) -> Client {
@@ -46,6 +51,8 @@ async fn create_test_client(state: &TestState) -> Client {
             None,
             vec![],
             vec![],
+            vec![],
+            None,
             None,
             None,
             None,
@@ -58,6 +65,7 @@ async fn create_test_client(state: &TestState) -> Client {
             None,
             None,
             None,
+            Vec::new(),
         )
         .await
         .unwrap();
@@ -94,7 +102,7 @@ async fn start_oauth_session(
 
     let browser_session = repo
         .browser_session()
-        .add(&mut rng, &state.clock, user, None)
+        .add(&mut rng, &state.clock, user, None, None, None)
         .await
         .unwrap();
 
@@ -230,6 +238,139 @@ async fn test_oauth2_viewer(pool: PgPool) {
     );
 }
 
+const VIEWER_QUERY: &str = r"
+    query {
+        viewer {
+            __typename
+
+            ... on User {
+                id
+            }
+        }
+
+        viewerSession {
+            __typename
+
+            ... on BrowserSession {
+                id
+            }
+
+            ... on Oauth2Session {
+                id
+            }
+        }
+    }
+";
+
+/// Test that the `viewer` and `viewerSession` fields resolve according to
+/// each kind of requester
+#[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+async fn test_viewer_and_viewer_session(pool: PgPool) {
+    init_tracing();
+    let state = service_account_state(pool).await;
+    let client = create_test_client(&state).await;
+    let user = create_test_user(&state, "alice").await;
+
+    // Anonymous requester
+    let request = Request::post("/graphql").json(serde_json::json!({ "query": VIEWER_QUERY }));
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(
+        response.data,
+        serde_json::json!({
+            "viewer": {
+                "__typename": "Anonymous",
+            },
+            "viewerSession": {
+                "__typename": "Anonymous",
+            },
+        })
+    );
+
+    // Browser session requester
+    let mut repo = state.repository().await.unwrap();
+    let browser_session = repo
+        .browser_session()
+        .add(&mut state.rng(), &state.clock, &user, None, None, None)
+        .await
+        .unwrap();
+    repo.save().await.unwrap();
+
+    let cookies = CookieHelper::new();
+    let cookie_jar = state
+        .cookie_jar()
+        .set_session(&state.clock, &browser_session, false);
+    cookies.import(cookie_jar);
+
+    let request = Request::post("/graphql").json(serde_json::json!({ "query": VIEWER_QUERY }));
+    let request = cookies.with_cookies(request);
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(
+        response.data,
+        serde_json::json!({
+            "viewer": {
+                "__typename": "User",
+                "id": format!("user:{}", user.id),
+            },
+            "viewerSession": {
+                "__typename": "BrowserSession",
+                "id": format!("browser_session:{}", browser_session.id),
+            },
+        })
+    );
+
+    // OAuth 2.0 session requester: the session is the OAuth 2.0 session, not
+    // the browser session it was started from
+    let access_token =
+        start_oauth_session(&state, &client, &user, Scope::from_iter([GRAPHQL])).await;
+
+    let request = Request::post("/graphql")
+        .bearer(&access_token.access_token)
+        .json(serde_json::json!({ "query": VIEWER_QUERY }));
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(
+        response.data,
+        serde_json::json!({
+            "viewer": {
+                "__typename": "User",
+                "id": format!("user:{}", user.id),
+            },
+            "viewerSession": {
+                "__typename": "Oauth2Session",
+                "id": format!("oauth2_session:{}", access_token.session_id),
+            },
+        })
+    );
+
+    // Service account requester
+    let request = Request::post("/graphql")
+        .bearer(SERVICE_ACCOUNT_TOKEN)
+        .json(serde_json::json!({ "query": VIEWER_QUERY }));
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(
+        response.data,
+        serde_json::json!({
+            "viewer": {
+                "__typename": "Anonymous",
+            },
+            "viewerSession": {
+                "__typename": "Anonymous",
+            },
+        })
+    );
+}
+
 /// Test that the GraphQL endpoint requires the GraphQL scope.
 #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
 async fn test_oauth2_no_scope(pool: PgPool) {
@@ -775,3 +916,1522 @@ async fn test_add_user(pool: PgPool) {
         })
     );
 }
+
+/// Test locking and unlocking users with the GraphQL API
+#[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+async fn test_lock_unlock_user(pool: PgPool) {
+    init_tracing();
+    let state = TestState::from_pool(pool).await.unwrap();
+
+    let client = create_test_client(&state).await;
+    let alice = create_test_user(&state, "alice").await;
+    let bob = create_test_user(&state, "bob").await;
+
+    // Regular access token
+    let access_token =
+        start_oauth_session(&state, &client, &alice, Scope::from_iter([GRAPHQL])).await;
+    let access_token = access_token.access_token;
+
+    // Admin access token
+    let access_token_admin =
+        start_oauth_session(&state, &client, &alice, Scope::from_iter([GRAPHQL, ADMIN])).await;
+    let access_token_admin = access_token_admin.access_token;
+
+    // Give bob an active browser session
+    let mut repo = state.repository().await.unwrap();
+    let mut rng = state.rng();
+    let bob_session = repo
+        .browser_session()
+        .add(&mut rng, &state.clock, &bob, None, None, None)
+        .await
+        .unwrap();
+    repo.save().await.unwrap();
+
+    let lock_query = serde_json::json!({
+        "query": r"
+            mutation LockUser($id: ID!) {
+                lockUser(input: { userId: $id, deactivate: true }) {
+                    status
+                    user {
+                        username
+                    }
+                }
+            }
+        ",
+        "variables": {
+            "id": format!("user:{id}", id = bob.id),
+        },
+    });
+
+    // It should fail without the admin scope
+    let request = Request::post("/graphql")
+        .bearer(&access_token)
+        .json(lock_query.clone());
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert_eq!(response.errors.len(), 1);
+
+    // It should work with the admin scope
+    let request = Request::post("/graphql")
+        .bearer(&access_token_admin)
+        .json(lock_query);
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(
+        response.data,
+        serde_json::json!({
+            "lockUser": {
+                "status": "LOCKED",
+                "user": {
+                    "username": "bob",
+                },
+            }
+        })
+    );
+
+    // The user should be locked, and its browser session finished
+    let mut repo = state.repository().await.unwrap();
+    let user = repo.user().lookup(bob.id).await.unwrap().unwrap();
+    assert!(!user.is_valid());
+    let session = repo
+        .browser_session()
+        .lookup(&state.clock, bob_session.id)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(!session.active());
+    repo.cancel().await.unwrap();
+
+    let unlock_query = serde_json::json!({
+        "query": r"
+            mutation UnlockUser($id: ID!) {
+                unlockUser(input: { userId: $id }) {
+                    status
+                    user {
+                        username
+                    }
+                }
+            }
+        ",
+        "variables": {
+            "id": format!("user:{id}", id = bob.id),
+        },
+    });
+
+    // Unlocking also requires the admin scope
+    let request = Request::post("/graphql")
+        .bearer(&access_token)
+        .json(unlock_query.clone());
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert_eq!(response.errors.len(), 1);
+
+    let request = Request::post("/graphql")
+        .bearer(&access_token_admin)
+        .json(unlock_query);
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(
+        response.data,
+        serde_json::json!({
+            "unlockUser": {
+                "status": "UNLOCKED",
+                "user": {
+                    "username": "bob",
+                },
+            }
+        })
+    );
+
+    let mut repo = state.repository().await.unwrap();
+    let user = repo.user().lookup(bob.id).await.unwrap().unwrap();
+    assert!(user.is_valid());
+    repo.cancel().await.unwrap();
+}
+
+/// Test deactivating and reactivating users with the GraphQL API
+#[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+async fn test_deactivate_reactivate_user(pool: PgPool) {
+    init_tracing();
+    let state = TestState::from_pool(pool).await.unwrap();
+
+    let client = create_test_client(&state).await;
+    let alice = create_test_user(&state, "alice").await;
+    let bob = create_test_user(&state, "bob").await;
+
+    // Regular access token
+    let access_token =
+        start_oauth_session(&state, &client, &alice, Scope::from_iter([GRAPHQL])).await;
+    let access_token = access_token.access_token;
+
+    // Admin access token
+    let access_token_admin =
+        start_oauth_session(&state, &client, &alice, Scope::from_iter([GRAPHQL, ADMIN])).await;
+    let access_token_admin = access_token_admin.access_token;
+
+    // Bob's own access token
+    let access_token_bob =
+        start_oauth_session(&state, &client, &bob, Scope::from_iter([GRAPHQL])).await;
+    let access_token_bob = access_token_bob.access_token;
+
+    let viewer_query = serde_json::json!({
+        "query": r"
+            query {
+                viewer {
+                    __typename
+                }
+            }
+        ",
+    });
+
+    let deactivate_query = serde_json::json!({
+        "query": r"
+            mutation DeactivateUser($id: ID!) {
+                deactivateUser(input: { userId: $id }) {
+                    status
+                    user {
+                        username
+                        deactivatedAt
+                    }
+                }
+            }
+        ",
+        "variables": {
+            "id": format!("user:{id}", id = bob.id),
+        },
+    });
+
+    // It should fail without the admin scope
+    let request = Request::post("/graphql")
+        .bearer(&access_token)
+        .json(deactivate_query.clone());
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert_eq!(response.errors.len(), 1);
+
+    // It should work with the admin scope
+    let request = Request::post("/graphql")
+        .bearer(&access_token_admin)
+        .json(deactivate_query);
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(
+        response.data["deactivateUser"]["status"],
+        serde_json::json!("DEACTIVATED")
+    );
+    assert!(response.data["deactivateUser"]["user"]["deactivatedAt"].is_string());
+
+    // Bob can't use the API anymore
+    let request = Request::post("/graphql")
+        .bearer(&access_token_bob)
+        .json(viewer_query.clone());
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::UNAUTHORIZED);
+    let response: GraphQLResponse = response.json();
+    assert_eq!(response.errors.len(), 1);
+
+    // But their data is still there
+    let mut repo = state.repository().await.unwrap();
+    let user = repo.user().lookup(bob.id).await.unwrap().unwrap();
+    assert!(user.is_deactivated());
+    repo.cancel().await.unwrap();
+
+    let reactivate_query = serde_json::json!({
+        "query": r"
+            mutation ReactivateUser($id: ID!) {
+                reactivateUser(input: { userId: $id }) {
+                    status
+                    user {
+                        username
+                        deactivatedAt
+                    }
+                }
+            }
+        ",
+        "variables": {
+            "id": format!("user:{id}", id = bob.id),
+        },
+    });
+
+    // Reactivating also requires the admin scope
+    let request = Request::post("/graphql")
+        .bearer(&access_token)
+        .json(reactivate_query.clone());
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert_eq!(response.errors.len(), 1);
+
+    let request = Request::post("/graphql")
+        .bearer(&access_token_admin)
+        .json(reactivate_query);
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(
+        response.data,
+        serde_json::json!({
+            "reactivateUser": {
+                "status": "REACTIVATED",
+                "user": {
+                    "username": "bob",
+                    "deactivatedAt": null,
+                },
+            }
+        })
+    );
+
+    // Bob can use the API again
+    let request = Request::post("/graphql")
+        .bearer(&access_token_bob)
+        .json(viewer_query);
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+}
+
+/// Test listing users through the GraphQL API
+#[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+async fn test_list_users(pool: PgPool) {
+    init_tracing();
+    let state = TestState::from_pool(pool).await.unwrap();
+
+    let client = create_test_client(&state).await;
+    let user = create_test_user(&state, "alice").await;
+    create_test_user(&state, "bob").await;
+
+    // Regular access token
+    let access_token =
+        start_oauth_session(&state, &client, &user, Scope::from_iter([GRAPHQL])).await;
+    let access_token = access_token.access_token;
+
+    // Admin access token
+    let access_token_admin =
+        start_oauth_session(&state, &client, &user, Scope::from_iter([GRAPHQL, ADMIN])).await;
+    let access_token_admin = access_token_admin.access_token;
+
+    let query = serde_json::json!({
+        "query": r#"
+            query {
+                users(filter: { usernamePrefix: "b" }) {
+                    totalCount
+                    edges {
+                        node {
+                            username
+                        }
+                    }
+                }
+            }
+        "#,
+    });
+
+    // Listing users requires the admin scope
+    let request = Request::post("/graphql")
+        .bearer(&access_token)
+        .json(query.clone());
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert_eq!(response.errors.len(), 1, "{:?}", response.errors);
+    assert_eq!(response.errors[0]["message"], "Unauthorized");
+
+    // Anonymous requests are also rejected
+    let request = Request::post("/graphql").json(query.clone());
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert_eq!(response.errors.len(), 1, "{:?}", response.errors);
+    assert_eq!(response.errors[0]["message"], "Unauthorized");
+
+    // With the admin scope, it should list the users matching the filter
+    let request = Request::post("/graphql")
+        .bearer(&access_token_admin)
+        .json(query);
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(
+        response.data,
+        serde_json::json!({
+            "users": {
+                "totalCount": 1,
+                "edges": [
+                    {
+                        "node": {
+                            "username": "bob",
+                        },
+                    },
+                ],
+            },
+        })
+    );
+}
+
+/// Test that an admin can end the browser session of another user
+#[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+async fn test_end_browser_session(pool: PgPool) {
+    init_tracing();
+    let state = TestState::from_pool(pool).await.unwrap();
+
+    let client = create_test_client(&state).await;
+    let alice = create_test_user(&state, "alice").await;
+    let bob = create_test_user(&state, "bob").await;
+
+    // Start a browser session for bob
+    let mut repo = state.repository().await.unwrap();
+    let browser_session = repo
+        .browser_session()
+        .add(&mut state.rng(), &state.clock, &bob, None, None, None)
+        .await
+        .unwrap();
+    repo.save().await.unwrap();
+    let browser_session_id = format!("browser_session:{}", browser_session.id);
+
+    // Regular access token for alice
+    let access_token =
+        start_oauth_session(&state, &client, &alice, Scope::from_iter([GRAPHQL])).await;
+    let access_token = access_token.access_token;
+
+    // Admin access token for alice
+    let access_token_admin =
+        start_oauth_session(&state, &client, &alice, Scope::from_iter([GRAPHQL, ADMIN])).await;
+    let access_token_admin = access_token_admin.access_token;
+
+    let query = serde_json::json!({
+        "query": r#"
+            mutation EndBrowserSession($id: ID!) {
+                endBrowserSession(input: { browserSessionId: $id }) {
+                    status
+                    browserSession {
+                        id
+                    }
+                }
+            }
+        "#,
+        "variables": {
+            "id": browser_session_id,
+        },
+    });
+
+    // Without the admin scope, alice can't see bob's session
+    let request = Request::post("/graphql")
+        .bearer(&access_token)
+        .json(query.clone());
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(
+        response.data,
+        serde_json::json!({
+            "endBrowserSession": {
+                "status": "NOT_FOUND",
+                "browserSession": null,
+            },
+        })
+    );
+
+    // With the admin scope, alice can end it
+    let request = Request::post("/graphql")
+        .bearer(&access_token_admin)
+        .json(query.clone());
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(
+        response.data,
+        serde_json::json!({
+            "endBrowserSession": {
+                "status": "ENDED",
+                "browserSession": {
+                    "id": browser_session_id,
+                },
+            },
+        })
+    );
+
+    // Ending it again should report that it was already finished
+    let request = Request::post("/graphql")
+        .bearer(&access_token_admin)
+        .json(query.clone());
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(
+        response.data,
+        serde_json::json!({
+            "endBrowserSession": {
+                "status": "ALREADY_FINISHED",
+                "browserSession": {
+                    "id": browser_session_id,
+                },
+            },
+        })
+    );
+
+    // Without the admin scope, alice can end her own session
+    let mut repo = state.repository().await.unwrap();
+    let own_browser_session = repo
+        .browser_session()
+        .add(&mut state.rng(), &state.clock, &alice, None, None, None)
+        .await
+        .unwrap();
+    repo.save().await.unwrap();
+    let own_browser_session_id = format!("browser_session:{}", own_browser_session.id);
+
+    let mut query = query;
+    query["variables"]["id"] = own_browser_session_id.clone().into();
+    let request = Request::post("/graphql").bearer(&access_token).json(query);
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(
+        response.data,
+        serde_json::json!({
+            "endBrowserSession": {
+                "status": "ENDED",
+                "browserSession": {
+                    "id": own_browser_session_id,
+                },
+            },
+        })
+    );
+}
+
+/// Test that the last authentication of browser sessions is exposed when
+/// listing them
+#[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+async fn test_browser_session_last_authentication(pool: PgPool) {
+    init_tracing();
+    let state = TestState::from_pool(pool).await.unwrap();
+
+    let client = create_test_client(&state).await;
+    let alice = create_test_user(&state, "alice").await;
+
+    // This also starts a browser session for alice, which never authenticated
+    let access_token =
+        start_oauth_session(&state, &client, &alice, Scope::from_iter([GRAPHQL])).await;
+    let access_token = access_token.access_token;
+
+    // Start another browser session, authenticated with a password
+    let mut repo = state.repository().await.unwrap();
+    let mut rng = state.rng();
+    let password = repo
+        .user_password()
+        .add(&mut rng, &state.clock, &alice, 1, "hashed".to_owned(), None)
+        .await
+        .unwrap();
+    let browser_session = repo
+        .browser_session()
+        .add(&mut rng, &state.clock, &alice, None, None, None)
+        .await
+        .unwrap();
+    let authentication = repo
+        .browser_session()
+        .authenticate_with_password(&mut rng, &state.clock, &browser_session, &password)
+        .await
+        .unwrap();
+    repo.save().await.unwrap();
+
+    let request = Request::post("/graphql")
+        .bearer(&access_token)
+        .json(serde_json::json!({
+            "query": r"
+                query {
+                    viewer {
+                        ... on User {
+                            browserSessions(first: 10) {
+                                edges {
+                                    node {
+                                        id
+                                        lastAuthentication {
+                                            id
+                                            createdAt
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            ",
+        }));
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+
+    let edges = response.data["viewer"]["browserSessions"]["edges"]
+        .as_array()
+        .unwrap();
+    assert_eq!(edges.len(), 2);
+
+    for edge in edges {
+        let node = &edge["node"];
+        if node["id"] == format!("browser_session:{}", browser_session.id) {
+            assert_eq!(
+                node["lastAuthentication"]["id"],
+                format!("authentication:{}", authentication.id)
+            );
+            assert!(node["lastAuthentication"]["createdAt"].is_string());
+        } else {
+            assert!(node["lastAuthentication"].is_null());
+        }
+    }
+}
+
+/// Test listing all the authentications of a browser session, the most recent
+/// first
+#[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+async fn test_browser_session_authentications(pool: PgPool) {
+    init_tracing();
+    let state = TestState::from_pool(pool).await.unwrap();
+
+    let client = create_test_client(&state).await;
+    let alice = create_test_user(&state, "alice").await;
+
+    let access_token =
+        start_oauth_session(&state, &client, &alice, Scope::from_iter([GRAPHQL])).await;
+    let access_token = access_token.access_token;
+
+    // Start a browser session, authenticated twice with a password
+    let mut repo = state.repository().await.unwrap();
+    let mut rng = state.rng();
+    let password = repo
+        .user_password()
+        .add(&mut rng, &state.clock, &alice, 1, "hashed".to_owned(), None)
+        .await
+        .unwrap();
+    let browser_session = repo
+        .browser_session()
+        .add(&mut rng, &state.clock, &alice, None, None, None)
+        .await
+        .unwrap();
+    let first = repo
+        .browser_session()
+        .authenticate_with_password(&mut rng, &state.clock, &browser_session, &password)
+        .await
+        .unwrap();
+    state.clock.advance(Duration::minutes(1));
+    let second = repo
+        .browser_session()
+        .authenticate_with_password(&mut rng, &state.clock, &browser_session, &password)
+        .await
+        .unwrap();
+    repo.save().await.unwrap();
+
+    let query = |after: Option<String>| {
+        Request::post("/graphql")
+            .bearer(&access_token)
+            .json(serde_json::json!({
+                "query": r"
+                    query($id: ID!, $after: String) {
+                        node(id: $id) {
+                            ... on BrowserSession {
+                                authentications(first: 1, after: $after) {
+                                    edges {
+                                        cursor
+                                        node {
+                                            id
+                                            method
+                                        }
+                                    }
+                                    pageInfo {
+                                        hasNextPage
+                                    }
+                                }
+                            }
+                        }
+                    }
+                ",
+                "variables": {
+                    "id": format!("browser_session:{}", browser_session.id),
+                    "after": after,
+                },
+            }))
+    };
+
+    // The most recent authentication comes first
+    let response = state.request(query(None)).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    let connection = &response.data["node"]["authentications"];
+    assert_eq!(
+        connection["edges"][0]["node"],
+        serde_json::json!({
+            "id": format!("authentication:{}", second.id),
+            "method": "PASSWORD",
+        })
+    );
+    assert_eq!(connection["pageInfo"]["hasNextPage"], true);
+    let cursor = connection["edges"][0]["cursor"]
+        .as_str()
+        .unwrap()
+        .to_owned();
+
+    let response = state.request(query(Some(cursor))).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    let connection = &response.data["node"]["authentications"];
+    assert_eq!(
+        connection["edges"][0]["node"]["id"],
+        format!("authentication:{}", first.id)
+    );
+    assert_eq!(connection["pageInfo"]["hasNextPage"], false);
+}
+
+/// Test that the browser sessions of the user can be paginated through and
+/// filtered by state, and that other users can't list them
+#[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+async fn test_browser_sessions_pagination(pool: PgPool) {
+    init_tracing();
+    let state = TestState::from_pool(pool).await.unwrap();
+
+    let client = create_test_client(&state).await;
+    let alice = create_test_user(&state, "alice").await;
+    let bob = create_test_user(&state, "bob").await;
+
+    // This also starts a first browser session for alice
+    let access_token =
+        start_oauth_session(&state, &client, &alice, Scope::from_iter([GRAPHQL])).await;
+    let access_token = access_token.access_token;
+    let bob_access_token =
+        start_oauth_session(&state, &client, &bob, Scope::from_iter([GRAPHQL])).await;
+    let bob_access_token = bob_access_token.access_token;
+
+    // Start two more browser sessions for alice
+    let mut repo = state.repository().await.unwrap();
+    for _ in 0..2 {
+        repo.browser_session()
+            .add(&mut state.rng(), &state.clock, &alice, None, None, None)
+            .await
+            .unwrap();
+    }
+    repo.save().await.unwrap();
+
+    let query = r"
+        query BrowserSessions($id: ID!, $first: Int, $after: String, $state: SessionState) {
+            user(id: $id) {
+                browserSessions(first: $first, after: $after, state: $state) {
+                    totalCount
+                    edges {
+                        cursor
+                        node {
+                            id
+                        }
+                    }
+                    pageInfo {
+                        hasNextPage
+                        endCursor
+                    }
+                }
+            }
+        }
+    ";
+    let user_id = format!("user:{}", alice.id);
+
+    // First page
+    let request = Request::post("/graphql")
+        .bearer(&access_token)
+        .json(serde_json::json!({
+            "query": query,
+            "variables": { "id": user_id, "first": 2 },
+        }));
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    let sessions = &response.data["user"]["browserSessions"];
+    assert_eq!(sessions["totalCount"], 3);
+    let first_page = sessions["edges"].as_array().unwrap().clone();
+    assert_eq!(first_page.len(), 2);
+    assert_eq!(sessions["pageInfo"]["hasNextPage"], true);
+    let end_cursor = sessions["pageInfo"]["endCursor"]
+        .as_str()
+        .unwrap()
+        .to_owned();
+
+    // Cursors are opaque, and don't leak the raw session ID
+    for edge in &first_page {
+        let id = edge["node"]["id"].as_str().unwrap();
+        let ulid = id.strip_prefix("browser_session:").unwrap();
+        assert!(!edge["cursor"].as_str().unwrap().contains(ulid));
+    }
+
+    // Second page
+    let request = Request::post("/graphql")
+        .bearer(&access_token)
+        .json(serde_json::json!({
+            "query": query,
+            "variables": { "id": user_id, "first": 2, "after": end_cursor },
+        }));
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    let sessions = &response.data["user"]["browserSessions"];
+    let second_page = sessions["edges"].as_array().unwrap();
+    assert_eq!(second_page.len(), 1);
+    assert_eq!(sessions["pageInfo"]["hasNextPage"], false);
+    assert!(first_page
+        .iter()
+        .all(|edge| edge["node"]["id"] != second_page[0]["node"]["id"]));
+
+    // Finish one of the sessions, and filter by state
+    let mut repo = state.repository().await.unwrap();
+    let session_id: ulid::Ulid = second_page[0]["node"]["id"]
+        .as_str()
+        .unwrap()
+        .strip_prefix("browser_session:")
+        .unwrap()
+        .parse()
+        .unwrap();
+    let session = repo
+        .browser_session()
+        .lookup(&state.clock, session_id)
+        .await
+        .unwrap()
+        .unwrap();
+    repo.browser_session()
+        .finish(&state.clock, session)
+        .await
+        .unwrap();
+    repo.save().await.unwrap();
+
+    for (session_state, expected) in [("ACTIVE", 2), ("FINISHED", 1)] {
+        let request = Request::post("/graphql")
+            .bearer(&access_token)
+            .json(serde_json::json!({
+                "query": query,
+                "variables": { "id": user_id, "first": 10, "state": session_state },
+            }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let response: GraphQLResponse = response.json();
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        let sessions = &response.data["user"]["browserSessions"];
+        assert_eq!(sessions["totalCount"], expected);
+        assert_eq!(sessions["edges"].as_array().unwrap().len(), expected);
+    }
+
+    // Bob can't list alice's sessions
+    let request = Request::post("/graphql")
+        .bearer(&bob_access_token)
+        .json(serde_json::json!({
+            "query": query,
+            "variables": { "id": user_id, "first": 2 },
+        }));
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert!(response.data["user"].is_null());
+}
+
+/// Test that the OAuth 2.0 sessions of the user can be listed and filtered by
+/// state and by client.
+#[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+async fn test_user_oauth2_sessions(pool: PgPool) {
+    init_tracing();
+    let state = TestState::from_pool(pool).await.unwrap();
+
+    let user = create_test_user(&state, "alice").await;
+    let client = create_test_client(&state).await;
+    let other_client = create_test_client(&state).await;
+
+    // The session used to query the API
+    let access_token =
+        start_oauth_session(&state, &client, &user, Scope::from_iter([GRAPHQL])).await;
+    let active_session_id = access_token.session_id;
+    let access_token = access_token.access_token;
+
+    // Another session, for another client, which is finished
+    let other_token =
+        start_oauth_session(&state, &other_client, &user, Scope::from_iter([OPENID])).await;
+    let mut repo = state.repository().await.unwrap();
+    let finished_session = repo
+        .oauth2_session()
+        .lookup(other_token.session_id)
+        .await
+        .unwrap()
+        .unwrap();
+    let finished_session = repo
+        .oauth2_session()
+        .finish(&state.clock, finished_session)
+        .await
+        .unwrap();
+    repo.save().await.unwrap();
+
+    let req = Request::post("/graphql")
+        .bearer(&access_token)
+        .json(serde_json::json!({
+            "query": r"
+                query UserOAuth2Sessions($clientId: ID!) {
+                    viewer {
+                        ... on User {
+                            active: oauth2Sessions(state: ACTIVE, first: 10) {
+                                totalCount
+                                edges {
+                                    node {
+                                        id
+                                        scope
+                                        client { id }
+                                        finishedAt
+                                    }
+                                }
+                            }
+                            finished: oauth2Sessions(state: FINISHED, first: 10) {
+                                totalCount
+                                edges {
+                                    node {
+                                        id
+                                        scope
+                                        client { id }
+                                    }
+                                }
+                            }
+                            forClient: oauth2Sessions(client: $clientId, first: 10) {
+                                totalCount
+                                edges {
+                                    node {
+                                        id
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            ",
+            "variables": {
+                "clientId": format!("oauth2_client:{}", other_client.id),
+            },
+        }));
+
+    let response = state.request(req).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+
+    let viewer = &response.data["viewer"];
+    assert_eq!(
+        viewer["active"],
+        serde_json::json!({
+            "totalCount": 1,
+            "edges": [{
+                "node": {
+                    "id": format!("oauth2_session:{active_session_id}"),
+                    "scope": "urn:mas:graphql:*",
+                    "client": { "id": format!("oauth2_client:{}", client.id) },
+                    "finishedAt": null,
+                },
+            }],
+        })
+    );
+    assert_eq!(
+        viewer["finished"],
+        serde_json::json!({
+            "totalCount": 1,
+            "edges": [{
+                "node": {
+                    "id": format!("oauth2_session:{}", finished_session.id),
+                    "scope": "openid",
+                    "client": { "id": format!("oauth2_client:{}", other_client.id) },
+                },
+            }],
+        })
+    );
+    assert_eq!(
+        viewer["forClient"],
+        serde_json::json!({
+            "totalCount": 1,
+            "edges": [{
+                "node": {
+                    "id": format!("oauth2_session:{}", finished_session.id),
+                },
+            }],
+        })
+    );
+}
+
+/// Test that the OAuth 2.0 sessions of the user can be ordered by their finish
+/// date, and that the cursors keep that order across pages.
+#[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+async fn test_user_oauth2_sessions_order(pool: PgPool) {
+    init_tracing();
+    let state = TestState::from_pool(pool).await.unwrap();
+
+    let user = create_test_user(&state, "alice").await;
+    let client = create_test_client(&state).await;
+
+    // The session used to query the API, which stays active
+    let access_token =
+        start_oauth_session(&state, &client, &user, Scope::from_iter([GRAPHQL])).await;
+    let active_session_id = access_token.session_id;
+    let access_token = access_token.access_token;
+
+    // Two other sessions, finished one after the other
+    let mut finished_session_ids = Vec::new();
+    for _ in 0..2 {
+        let token = start_oauth_session(&state, &client, &user, Scope::from_iter([OPENID])).await;
+        state.clock.advance(Duration::minutes(1));
+        let mut repo = state.repository().await.unwrap();
+        let session = repo
+            .oauth2_session()
+            .lookup(token.session_id)
+            .await
+            .unwrap()
+            .unwrap();
+        repo.oauth2_session()
+            .finish(&state.clock, session)
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+        finished_session_ids.push(token.session_id);
+    }
+
+    let query = |after: Option<String>| {
+        Request::post("/graphql")
+            .bearer(&access_token)
+            .json(serde_json::json!({
+                "query": r"
+                    query UserOAuth2Sessions($after: String) {
+                        viewer {
+                            ... on User {
+                                oauth2Sessions(orderBy: FINISHED_AT_DESC, first: 2, after: $after) {
+                                    edges {
+                                        cursor
+                                        node {
+                                            id
+                                        }
+                                    }
+                                    pageInfo {
+                                        hasNextPage
+                                    }
+                                }
+                            }
+                        }
+                    }
+                ",
+                "variables": {
+                    "after": after,
+                },
+            }))
+    };
+
+    // The active session comes first, then the most recently finished one
+    let response = state.request(query(None)).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+
+    let sessions = &response.data["viewer"]["oauth2Sessions"];
+    assert_eq!(sessions["pageInfo"]["hasNextPage"], true);
+    let edges = sessions["edges"].as_array().unwrap();
+    assert_eq!(edges.len(), 2);
+    assert_eq!(
+        edges[0]["node"]["id"],
+        format!("oauth2_session:{active_session_id}")
+    );
+    assert_eq!(
+        edges[1]["node"]["id"],
+        format!("oauth2_session:{}", finished_session_ids[1])
+    );
+    let cursor = edges[1]["cursor"].as_str().unwrap().to_owned();
+
+    // The next page has the session which was finished first
+    let response = state.request(query(Some(cursor))).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+
+    let sessions = &response.data["viewer"]["oauth2Sessions"];
+    assert_eq!(sessions["pageInfo"]["hasNextPage"], false);
+    assert_eq!(
+        sessions["edges"],
+        serde_json::json!([{
+            "cursor": sessions["edges"][0]["cursor"],
+            "node": {
+                "id": format!("oauth2_session:{}", finished_session_ids[0]),
+            },
+        }])
+    );
+}
+
+/// Test that the `setPrimaryEmail` mutation only accepts verified email
+/// addresses owned by the requester.
+#[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+async fn test_set_primary_email(pool: PgPool) {
+    init_tracing();
+    let state = TestState::from_pool(pool).await.unwrap();
+
+    let alice = create_test_user(&state, "alice").await;
+    let bob = create_test_user(&state, "bob").await;
+    let client = create_test_client(&state).await;
+
+    let access_token =
+        start_oauth_session(&state, &client, &alice, Scope::from_iter([GRAPHQL])).await;
+    let access_token = access_token.access_token;
+
+    let mut repo = state.repository().await.unwrap();
+    let mut rng = state.rng();
+    let unverified = repo
+        .user_email()
+        .add(
+            &mut rng,
+            &state.clock,
+            &alice,
+            "unverified@example.com".to_owned(),
+        )
+        .await
+        .unwrap();
+    let verified = repo
+        .user_email()
+        .add(
+            &mut rng,
+            &state.clock,
+            &alice,
+            "alice@example.com".to_owned(),
+        )
+        .await
+        .unwrap();
+    let verified = repo
+        .user_email()
+        .mark_as_verified(&state.clock, verified)
+        .await
+        .unwrap();
+    let foreign = repo
+        .user_email()
+        .add(&mut rng, &state.clock, &bob, "bob@example.com".to_owned())
+        .await
+        .unwrap();
+    let foreign = repo
+        .user_email()
+        .mark_as_verified(&state.clock, foreign)
+        .await
+        .unwrap();
+    repo.save().await.unwrap();
+
+    let set_primary_email = |user_email_id: String| {
+        Request::post("/graphql")
+            .bearer(&access_token)
+            .json(serde_json::json!({
+                "query": r"
+                    mutation SetPrimaryEmail($id: ID!) {
+                        setPrimaryEmail(input: { userEmailId: $id }) {
+                            status
+                            user {
+                                id
+                                primaryEmail {
+                                    id
+                                    email
+                                }
+                            }
+                        }
+                    }
+                ",
+                "variables": {
+                    "id": user_email_id,
+                },
+            }))
+    };
+
+    // An unverified email can't be set as primary
+    let response = state
+        .request(set_primary_email(format!("user_email:{}", unverified.id)))
+        .await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(
+        response.data,
+        serde_json::json!({
+            "setPrimaryEmail": {
+                "status": "UNVERIFIED",
+                "user": null,
+            },
+        })
+    );
+
+    // An email belonging to another user looks like it doesn't exist
+    let response = state
+        .request(set_primary_email(format!("user_email:{}", foreign.id)))
+        .await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(
+        response.data,
+        serde_json::json!({
+            "setPrimaryEmail": {
+                "status": "NOT_FOUND",
+                "user": null,
+            },
+        })
+    );
+
+    // A verified email owned by the user can be set as primary
+    let response = state
+        .request(set_primary_email(format!("user_email:{}", verified.id)))
+        .await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(
+        response.data,
+        serde_json::json!({
+            "setPrimaryEmail": {
+                "status": "SET",
+                "user": {
+                    "id": format!("user:{}", alice.id),
+                    "primaryEmail": {
+                        "id": format!("user_email:{}", verified.id),
+                        "email": "alice@example.com",
+                    },
+                },
+            },
+        })
+    );
+
+    // The other user's primary email was left untouched
+    let mut repo = state.repository().await.unwrap();
+    let bob = repo.user().lookup(bob.id).await.unwrap().unwrap();
+    assert_ne!(bob.primary_user_email_id, Some(foreign.id));
+}
+
+/// Test the `sendVerificationEmail` and `verifyEmail` mutations
+#[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+async fn test_verify_email(pool: PgPool) {
+    init_tracing();
+    let state = TestState::from_pool(pool).await.unwrap();
+
+    let user = create_test_user(&state, "alice").await;
+    let client = create_test_client(&state).await;
+
+    let access_token =
+        start_oauth_session(&state, &client, &user, Scope::from_iter([GRAPHQL])).await;
+    let access_token = access_token.access_token;
+
+    let mut repo = state.repository().await.unwrap();
+    let mut rng = state.rng();
+    let user_email = repo
+        .user_email()
+        .add(
+            &mut rng,
+            &state.clock,
+            &user,
+            "alice@example.com".to_owned(),
+        )
+        .await
+        .unwrap();
+    repo.user_email()
+        .add_verification_code(
+            &mut rng,
+            &state.clock,
+            &user_email,
+            Duration::try_minutes(5).unwrap(),
+            "123456".to_owned(),
+        )
+        .await
+        .unwrap();
+    repo.save().await.unwrap();
+
+    let user_email_id = format!("user_email:{}", user_email.id);
+
+    let send_verification_email = || {
+        Request::post("/graphql")
+            .bearer(&access_token)
+            .json(serde_json::json!({
+                "query": r"
+                    mutation SendVerificationEmail($id: ID!) {
+                        sendVerificationEmail(input: { userEmailId: $id }) {
+                            status
+                        }
+                    }
+                ",
+                "variables": {
+                    "id": user_email_id,
+                },
+            }))
+    };
+    let verify_email = |code: &str| {
+        Request::post("/graphql")
+            .bearer(&access_token)
+            .json(serde_json::json!({
+                "query": r"
+                    mutation VerifyEmail($id: ID!, $code: String!) {
+                        verifyEmail(input: { userEmailId: $id, code: $code }) {
+                            status
+                        }
+                    }
+                ",
+                "variables": {
+                    "id": user_email_id,
+                    "code": code,
+                },
+            }))
+    };
+
+    // A code was just sent, so sending another one is rate-limited
+    let response = state.request(send_verification_email()).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(
+        response.data,
+        serde_json::json!({ "sendVerificationEmail": { "status": "RATE_LIMITED" } })
+    );
+
+    // A wrong code is rejected
+    let response = state.request(verify_email("654321")).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(
+        response.data,
+        serde_json::json!({ "verifyEmail": { "status": "INVALID_CODE" } })
+    );
+
+    // Once the code expired, it is rejected with a distinct status
+    state.clock.advance(Duration::try_minutes(10).unwrap());
+    let response = state.request(verify_email("123456")).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(
+        response.data,
+        serde_json::json!({ "verifyEmail": { "status": "CODE_EXPIRED" } })
+    );
+
+    // Enough time passed to send a new email
+    let response = state.request(send_verification_email()).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(
+        response.data,
+        serde_json::json!({ "sendVerificationEmail": { "status": "SENT" } })
+    );
+
+    // Add a fresh code, as the job sending the email isn't run in the tests
+    let mut repo = state.repository().await.unwrap();
+    repo.user_email()
+        .add_verification_code(
+            &mut rng,
+            &state.clock,
+            &user_email,
+            Duration::try_minutes(5).unwrap(),
+            "424242".to_owned(),
+        )
+        .await
+        .unwrap();
+    repo.save().await.unwrap();
+
+    // Too many wrong codes invalidate the pending code
+    for _ in 0..5 {
+        let response = state.request(verify_email("000000")).await;
+        response.assert_status(StatusCode::OK);
+        let response: GraphQLResponse = response.json();
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        assert_eq!(
+            response.data,
+            serde_json::json!({ "verifyEmail": { "status": "INVALID_CODE" } })
+        );
+    }
+
+    let response = state.request(verify_email("424242")).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(
+        response.data,
+        serde_json::json!({ "verifyEmail": { "status": "CODE_EXPIRED" } })
+    );
+
+    // A new code works
+    let mut repo = state.repository().await.unwrap();
+    repo.user_email()
+        .add_verification_code(
+            &mut rng,
+            &state.clock,
+            &user_email,
+            Duration::try_minutes(5).unwrap(),
+            "434343".to_owned(),
+        )
+        .await
+        .unwrap();
+    repo.save().await.unwrap();
+
+    let response = state.request(verify_email("434343")).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(
+        response.data,
+        serde_json::json!({ "verifyEmail": { "status": "VERIFIED" } })
+    );
+
+    // Verifying again tells the email is already verified
+    let response = state.request(verify_email("434343")).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(
+        response.data,
+        serde_json::json!({ "verifyEmail": { "status": "ALREADY_VERIFIED" } })
+    );
+}
+
+/// The static token used by the service account in the tests
+const SERVICE_ACCOUNT_TOKEN: &str = "service-account-static-token";
+
+/// Create a test state with a `synapse` service account, using the
+/// [`SERVICE_ACCOUNT_TOKEN`] static token
+async fn service_account_state(pool: PgPool) -> TestState {
+    let site_config = SiteConfig {
+        service_accounts: vec![ServiceAccount {
+            name: "synapse".to_owned(),
+            token_sha256: Sha256::digest(SERVICE_ACCOUNT_TOKEN.as_bytes()).into(),
+        }],
+        ..test_site_config()
+    };
+
+    TestState::from_pool_with_site_config(pool, site_config)
+        .await
+        .unwrap()
+}
+
+const SERVICE_ACCOUNT_QUERY: &str = r"
+    query {
+        viewer {
+            __typename
+        }
+
+        users {
+            totalCount
+        }
+    }
+";
+
+/// Test that a service account can call the API as an admin with its static
+/// token
+#[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+async fn test_service_account(pool: PgPool) {
+    init_tracing();
+    let state = service_account_state(pool).await;
+    create_test_user(&state, "alice").await;
+
+    let request = Request::post("/graphql")
+        .bearer(SERVICE_ACCOUNT_TOKEN)
+        .json(serde_json::json!({ "query": SERVICE_ACCOUNT_QUERY }));
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(
+        response.data,
+        serde_json::json!({
+            "viewer": {
+                "__typename": "Anonymous",
+            },
+            "users": {
+                "totalCount": 1,
+            },
+        })
+    );
+
+    // Any other token is looked up as an access token, and rejected
+    let request = Request::post("/graphql")
+        .bearer("not-the-service-account-token")
+        .json(serde_json::json!({ "query": SERVICE_ACCOUNT_QUERY }));
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::UNAUTHORIZED);
+}
+
+/// Test that the static token of a service account takes precedence over the
+/// browser session cookie
+#[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+async fn test_service_account_precedence(pool: PgPool) {
+    init_tracing();
+    let state = service_account_state(pool).await;
+    let user = create_test_user(&state, "alice").await;
+
+    // Start a browser session for the user, and save it in the cookies
+    let mut repo = state.repository().await.unwrap();
+    let browser_session = repo
+        .browser_session()
+        .add(&mut state.rng(), &state.clock, &user, None, None, None)
+        .await
+        .unwrap();
+    repo.save().await.unwrap();
+
+    let cookies = CookieHelper::new();
+    let cookie_jar = state
+        .cookie_jar()
+        .set_session(&state.clock, &browser_session, false);
+    cookies.import(cookie_jar);
+
+    // With only the cookie, the requester is the user, which isn't an admin
+    let request = Request::post("/graphql").json(serde_json::json!({
+        "query": "query { viewer { __typename } }",
+    }));
+    let request = cookies.with_cookies(request);
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(
+        response.data,
+        serde_json::json!({
+            "viewer": {
+                "__typename": "User",
+            },
+        })
+    );
+
+    let request = Request::post("/graphql")
+        .json(serde_json::json!({ "query": "query { users { totalCount } }" }));
+    let request = cookies.with_cookies(request);
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert_eq!(response.errors.len(), 1, "{:?}", response.errors);
+    assert_eq!(response.errors[0]["message"], "Unauthorized");
+
+    // With both the cookie and the static token, the service account wins
+    let request = Request::post("/graphql")
+        .bearer(SERVICE_ACCOUNT_TOKEN)
+        .json(serde_json::json!({ "query": SERVICE_ACCOUNT_QUERY }));
+    let request = cookies.with_cookies(request);
+    let response = state.request(request).await;
+    response.assert_status(StatusCode::OK);
+    let response: GraphQLResponse = response.json();
+    assert!(response.errors.is_empty(), "{:?}", response.errors);
+    assert_eq!(
+        response.data,
+        serde_json::json!({
+            "viewer": {
+                "__typename": "Anonymous",
+            },
+            "users": {
+                "totalCount": 1,
+            },
+        })
+    );
+}
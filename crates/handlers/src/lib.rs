@@ -23,7 +23,7 @@
     clippy::let_with_type_underscore,
 )]
 
-use std::{convert::Infallible, time::Duration};
+use std::{convert::Infallible, sync::Arc, time::Duration};
 
 use axum::{
     body::{Bytes, HttpBody},
@@ -40,7 +40,11 @@ use hyper::{
     },
     StatusCode, Version,
 };
-use mas_axum_utils::{cookies::CookieJar, FancyError};
+use mas_axum_utils::{
+    cookies::CookieJar,
+    dpop::{DpopRequest, DPOP, DPOP_NONCE},
+    FancyError,
+};
 use mas_data_model::SiteConfig;
 use mas_http::CorsLayerExt;
 use mas_keystore::{Encrypter, Keystore};
@@ -59,11 +63,18 @@ mod graphql;
 mod health;
 mod oauth2;
 pub mod passwords;
+pub mod totp;
 pub mod upstream_oauth2;
 mod views;
 
 mod activity_tracker;
+mod fetch_cache;
+mod login_throttle;
+mod metrics;
 mod preferred_language;
+mod pwned_passwords;
+mod rate_limit;
+mod session_limit;
 #[cfg(test)]
 mod test_utils;
 
@@ -84,14 +95,21 @@ macro_rules! impl_from_error_for_route {
 }
 
 pub use mas_axum_utils::{
-    cookies::CookieManager, http_client_factory::HttpClientFactory, ErrorWrapper,
+    cookies::CookieManager,
+    dpop::{DpopNonceStore, InMemoryDpopNonceStore},
+    http_client_factory::HttpClientFactory,
+    ErrorWrapper,
 };
 
 pub use self::{
     activity_tracker::{ActivityTracker, Bound as BoundActivityTracker},
     graphql::schema as graphql_schema,
+    login_throttle::{InMemoryLoginThrottle, LoginThrottle},
+    oauth2::{backchannel_logout::BackchannelLogoutDispatcher, jar::JarVerifier},
     preferred_language::PreferredLanguage,
-    upstream_oauth2::cache::MetadataCache,
+    pwned_passwords::{PwnedPasswordChecker, PwnedPasswords},
+    rate_limit::{InMemoryRateLimiter, RateLimit, RateLimiter},
+    upstream_oauth2::cache::{JwksCache, MetadataCache},
 };
 
 pub fn healthcheck_router<S, B>() -> Router<S, B>
@@ -110,11 +128,16 @@ where
     <B as HttpBody>::Error: std::error::Error + Send + Sync,
     S: Clone + Send + Sync + 'static,
     mas_graphql::Schema: FromRef<S>,
+    SiteConfig: FromRef<S>,
     BoundActivityTracker: FromRequestParts<S>,
     BoxRepository: FromRequestParts<S>,
     BoxClock: FromRequestParts<S>,
     Encrypter: FromRef<S>,
+    Keystore: FromRef<S>,
+    UrlBuilder: FromRef<S>,
     CookieJar: FromRequestParts<S>,
+    DpopRequest: FromRequestParts<S>,
+    Arc<dyn DpopNonceStore>: FromRef<S>,
 {
     let mut router = Router::new()
         .route(
@@ -131,6 +154,7 @@ where
                     ACCEPT_LANGUAGE,
                     CONTENT_LANGUAGE,
                     CONTENT_TYPE,
+                    DPOP.clone(),
                 ]),
         );
 
@@ -159,6 +183,10 @@ where
             mas_router::OidcConfiguration::route(),
             get(self::oauth2::discovery::get),
         )
+        .route(
+            mas_router::OAuth2AuthorizationServerMetadata::route(),
+            get(self::oauth2::discovery::get),
+        )
         .route(
             mas_router::Webfinger::route(),
             get(self::oauth2::webfinger::get),
@@ -195,6 +223,9 @@ where
     BoxClock: FromRequestParts<S>,
     BoxRng: FromRequestParts<S>,
     Policy: FromRequestParts<S>,
+    DpopRequest: FromRequestParts<S>,
+    Arc<dyn DpopNonceStore>: FromRef<S>,
+    JarVerifier: FromRef<S>,
 {
     // All those routes are API-like, with a common CORS layer
     Router::new()
@@ -229,6 +260,10 @@ where
             mas_router::OAuth2DeviceAuthorizationEndpoint::route(),
             post(self::oauth2::device::authorize::post),
         )
+        .route(
+            mas_router::OAuth2PushedAuthorizationEndpoint::route(),
+            post(self::oauth2::pushed_authorization::post),
+        )
         .layer(
             CorsLayer::new()
                 .allow_origin(Any)
@@ -239,7 +274,9 @@ where
                     ACCEPT_LANGUAGE,
                     CONTENT_LANGUAGE,
                     CONTENT_TYPE,
+                    DPOP.clone(),
                 ])
+                .expose_headers([DPOP_NONCE.clone()])
                 .max_age(Duration::from_secs(60 * 60)),
         )
 }
@@ -255,6 +292,7 @@ where
     SiteConfig: FromRef<S>,
     BoxHomeserverConnection: FromRef<S>,
     PasswordManager: FromRef<S>,
+    Arc<dyn LoginThrottle>: FromRef<S>,
     BoundActivityTracker: FromRequestParts<S>,
     BoxRepository: FromRequestParts<S>,
     BoxClock: FromRequestParts<S>,
@@ -318,7 +356,13 @@ where
     Keystore: FromRef<S>,
     HttpClientFactory: FromRef<S>,
     PasswordManager: FromRef<S>,
+    Arc<dyn LoginThrottle>: FromRef<S>,
+    Arc<dyn PwnedPasswords>: FromRef<S>,
+    Arc<dyn RateLimiter>: FromRef<S>,
+    PgPool: FromRef<S>,
     MetadataCache: FromRef<S>,
+    JwksCache: FromRef<S>,
+    JarVerifier: FromRef<S>,
     SiteConfig: FromRef<S>,
     BoxHomeserverConnection: FromRef<S>,
     BoxClock: FromRequestParts<S>,
@@ -359,6 +403,10 @@ where
             mas_router::Login::route(),
             get(self::views::login::get).post(self::views::login::post),
         )
+        .route(
+            mas_router::LoginTotp::route(),
+            get(self::views::login_totp::get).post(self::views::login_totp::post),
+        )
         .route(mas_router::Logout::route(), post(self::views::logout::post))
         .route(
             mas_router::Reauth::route(),
@@ -372,6 +420,15 @@ where
             mas_router::AccountPassword::route(),
             get(self::views::account::password::get).post(self::views::account::password::post),
         )
+        .route(
+            mas_router::ResetPasswordRequest::route(),
+            get(self::views::reset_password_request::get)
+                .post(self::views::reset_password_request::post),
+        )
+        .route(
+            mas_router::ResetPassword::route(),
+            get(self::views::reset_password::get).post(self::views::reset_password::post),
+        )
         .route(
             mas_router::AccountVerifyEmail::route(),
             get(self::views::account::emails::verify::get)
@@ -386,6 +443,10 @@ where
             mas_router::OAuth2AuthorizationEndpoint::route(),
             get(self::oauth2::authorization::get),
         )
+        .route(
+            mas_router::OAuth2EndSessionEndpoint::route(),
+            get(self::oauth2::end_session::get).post(self::oauth2::end_session::get),
+        )
         .route(
             mas_router::ContinueAuthorizationGrant::route(),
             get(self::oauth2::authorization::complete::get),
@@ -0,0 +1,278 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Brute-force protection of the password login
+
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{Mutex, PoisonError},
+};
+
+use chrono::{DateTime, Duration, Utc};
+use mas_storage::Clock;
+use thiserror::Error;
+
+/// A store of the failed password login attempts, keyed on the username
+pub trait LoginThrottle: Send + Sync {
+    /// Start a login attempt for this username, if it is allowed right now
+    ///
+    /// The attempt counts as a failure until it is recorded as a success, so
+    /// that concurrent attempts can't get around the throttle.
+    ///
+    /// # Errors
+    ///
+    /// Returns how long to wait before trying again if the attempt is
+    /// throttled
+    fn attempt(&self, username: &str, now: DateTime<Utc>) -> Result<(), Duration>;
+
+    /// Record a successful login for this username, resetting its failures
+    fn record_success(&self, username: &str);
+}
+
+/// Too many failed attempts were recorded recently
+#[derive(Debug, Clone, Copy, Error)]
+#[error("too many failed attempts, retry after {retry_after}")]
+pub(crate) struct Throttled {
+    pub retry_after: Duration,
+}
+
+/// Run a password check for the given username through the throttle
+///
+/// Every password verification goes through this, so that the failed
+/// attempts are counted the same way on every login path. The check isn't even
+/// started if there were too many failed attempts recently. Any error counts
+/// as a failed attempt, and a successful check resets the counter.
+///
+/// # Errors
+///
+/// Returns the error of the check, or [`Throttled`] converted into the same
+/// error type if the attempt is throttled
+pub(crate) async fn throttle_password_check<T, E>(
+    login_throttle: &dyn LoginThrottle,
+    clock: &impl Clock,
+    username: &str,
+    check: impl Future<Output = Result<T, E>>,
+) -> Result<T, E>
+where
+    E: From<Throttled>,
+{
+    login_throttle
+        .attempt(username, clock.now())
+        .map_err(|retry_after| Throttled { retry_after })?;
+
+    let value = check.await?;
+    login_throttle.record_success(username);
+    Ok(value)
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Failures {
+    count: u32,
+    last_attempt: DateTime<Utc>,
+    locked_until: Option<DateTime<Utc>>,
+}
+
+impl Failures {
+    /// Whether those failures can be forgotten
+    fn is_expired(&self, now: DateTime<Utc>, window: Duration) -> bool {
+        match self.locked_until {
+            Some(locked_until) => locked_until <= now,
+            None => self.last_attempt + window <= now,
+        }
+    }
+}
+
+/// The failures of all the usernames
+#[derive(Debug, Default)]
+struct FailuresMap {
+    entries: HashMap<String, Failures>,
+
+    /// The size of the map at which the expired entries are removed. It
+    /// doubles after each cleanup, so that it doesn't happen on every attempt.
+    cleanup_at: usize,
+}
+
+/// A [`LoginThrottle`] which keeps the failed attempts in memory
+///
+/// The first failure is free. After the second consecutive failure, the
+/// next attempt is delayed by the `backoff`, which doubles with each
+/// subsequent failure. Once `max_failures` are reached, the username is locked
+/// for the `lockout` duration. Failures older than the `window` are forgotten.
+///
+/// Failures are not shared between instances of the service.
+pub struct InMemoryLoginThrottle {
+    failures: Mutex<FailuresMap>,
+    max_failures: u32,
+    window: Duration,
+    backoff: Duration,
+    lockout: Duration,
+}
+
+impl InMemoryLoginThrottle {
+    /// Create a new throttle
+    #[must_use]
+    pub fn new(max_failures: u32, window: Duration, backoff: Duration, lockout: Duration) -> Self {
+        Self {
+            failures: Mutex::new(FailuresMap::default()),
+            max_failures,
+            window,
+            backoff,
+            lockout,
+        }
+    }
+
+    /// How long to wait after this number of consecutive failures
+    fn delay(&self, count: u32) -> Duration {
+        if count < 2 {
+            return Duration::zero();
+        }
+
+        // Cap the exponent, the delay is capped by the lockout anyway
+        let factor = 1 << (count - 2).min(20);
+        let delay = self.backoff * factor;
+        delay.min(self.lockout)
+    }
+}
+
+impl Default for InMemoryLoginThrottle {
+    fn default() -> Self {
+        Self::new(
+            10,
+            Duration::microseconds(15 * 60 * 1000 * 1000),
+            Duration::microseconds(1000 * 1000),
+            Duration::microseconds(15 * 60 * 1000 * 1000),
+        )
+    }
+}
+
+impl LoginThrottle for InMemoryLoginThrottle {
+    fn attempt(&self, username: &str, now: DateTime<Utc>) -> Result<(), Duration> {
+        let mut failures = self.failures.lock().unwrap_or_else(PoisonError::into_inner);
+
+        // Forget about the expired entries, so that the map doesn't grow forever
+        if failures.entries.len() >= failures.cleanup_at {
+            failures
+                .entries
+                .retain(|_, entry| !entry.is_expired(now, self.window));
+            failures.cleanup_at = (failures.entries.len() * 2).max(64);
+        }
+
+        if let Some(entry) = failures.entries.get(username) {
+            if entry.is_expired(now, self.window) {
+                failures.entries.remove(username);
+            } else {
+                let allowed_at = entry
+                    .locked_until
+                    .unwrap_or_else(|| entry.last_attempt + self.delay(entry.count));
+
+                if allowed_at > now {
+                    return Err(allowed_at - now);
+                }
+            }
+        }
+
+        // Count the attempt as failed right away, it is forgotten if it succeeds
+        let entry = failures
+            .entries
+            .entry(username.to_owned())
+            .or_insert(Failures {
+                count: 0,
+                last_attempt: now,
+                locked_until: None,
+            });
+
+        entry.count += 1;
+        entry.last_attempt = now;
+        if entry.count >= self.max_failures {
+            entry.locked_until = Some(now + self.lockout);
+        }
+
+        Ok(())
+    }
+
+    fn record_success(&self, username: &str) {
+        self.failures
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .entries
+            .remove(username);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lockout_then_unlock() {
+        let mut now = DateTime::<Utc>::UNIX_EPOCH;
+        let throttle = InMemoryLoginThrottle::new(
+            4,
+            Duration::minutes(10),
+            Duration::seconds(1),
+            Duration::minutes(5),
+        );
+
+        // The first failure doesn't delay the next attempt
+        assert!(throttle.attempt("alice", now).is_ok());
+        assert!(throttle.attempt("alice", now).is_ok());
+
+        // Then the delay doubles after each failure
+        assert_eq!(throttle.attempt("alice", now), Err(Duration::seconds(1)));
+        now += Duration::seconds(1);
+        assert!(throttle.attempt("alice", now).is_ok());
+        assert_eq!(throttle.attempt("alice", now), Err(Duration::seconds(2)));
+
+        // Other usernames are not affected
+        assert!(throttle.attempt("bob", now).is_ok());
+
+        // The fourth failure locks the account
+        now += Duration::seconds(2);
+        assert!(throttle.attempt("alice", now).is_ok());
+        assert_eq!(throttle.attempt("alice", now), Err(Duration::minutes(5)));
+        now += Duration::minutes(4);
+        assert_eq!(throttle.attempt("alice", now), Err(Duration::minutes(1)));
+
+        // Once the lockout is over, the counter starts over
+        now += Duration::minutes(1);
+        assert!(throttle.attempt("alice", now).is_ok());
+        assert!(throttle.attempt("alice", now).is_ok());
+    }
+
+    #[test]
+    fn test_reset() {
+        let now = DateTime::<Utc>::UNIX_EPOCH;
+        let throttle = InMemoryLoginThrottle::new(
+            4,
+            Duration::minutes(10),
+            Duration::seconds(1),
+            Duration::minutes(5),
+        );
+
+        assert!(throttle.attempt("alice", now).is_ok());
+        assert!(throttle.attempt("alice", now).is_ok());
+        assert!(throttle.attempt("alice", now).is_err());
+
+        // Failures are forgotten after the window
+        let later = now + Duration::minutes(10);
+        assert!(throttle.attempt("alice", later).is_ok());
+        assert!(throttle.attempt("alice", later).is_ok());
+        assert!(throttle.attempt("alice", later).is_err());
+
+        // A successful login resets the counter
+        throttle.record_success("alice");
+        assert!(throttle.attempt("alice", later).is_ok());
+    }
+}
@@ -0,0 +1,108 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Application-level metrics recorded by the handlers
+
+use std::sync::OnceLock;
+
+use opentelemetry::{metrics::Counter, Key};
+
+const GRANT_TYPE: Key = Key::from_static_str("grant_type");
+const METHOD: Key = Key::from_static_str("method");
+const ERROR: Key = Key::from_static_str("error");
+
+struct Metrics {
+    token_requests: Counter<u64>,
+    tokens_issued: Counter<u64>,
+    authentication_failures: Counter<u64>,
+    upstream_oauth2_callback_errors: Counter<u64>,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let meter = opentelemetry::global::meter_with_version(
+            env!("CARGO_PKG_NAME"),
+            Some(env!("CARGO_PKG_VERSION")),
+            Some(opentelemetry_semantic_conventions::SCHEMA_URL),
+            None,
+        );
+
+        let token_requests = meter
+            .u64_counter("mas.oauth2.token_requests")
+            .with_description("The number of requests made to the token endpoint")
+            .with_unit(opentelemetry::metrics::Unit::new("{request}"))
+            .init();
+
+        let tokens_issued = meter
+            .u64_counter("mas.oauth2.tokens_issued")
+            .with_description("The number of access tokens issued by the token endpoint")
+            .with_unit(opentelemetry::metrics::Unit::new("{token}"))
+            .init();
+
+        let authentication_failures = meter
+            .u64_counter("mas.authentication.failures")
+            .with_description("The number of failed authentication attempts")
+            .with_unit(opentelemetry::metrics::Unit::new("{attempt}"))
+            .init();
+
+        let upstream_oauth2_callback_errors = meter
+            .u64_counter("mas.upstream_oauth2.callback_errors")
+            .with_description("The number of errors when handling upstream OAuth 2.0 callbacks")
+            .with_unit(opentelemetry::metrics::Unit::new("{error}"))
+            .init();
+
+        Self {
+            token_requests,
+            tokens_issued,
+            authentication_failures,
+            upstream_oauth2_callback_errors,
+        }
+    }
+}
+
+/// The instruments are created lazily, so that they are created after the
+/// meter provider was set up
+fn metrics() -> &'static Metrics {
+    static METRICS: OnceLock<Metrics> = OnceLock::new();
+    METRICS.get_or_init(Metrics::new)
+}
+
+/// Record a request to the token endpoint with the given grant type
+pub(crate) fn record_token_request(grant_type: &'static str) {
+    metrics()
+        .token_requests
+        .add(1, &[GRANT_TYPE.string(grant_type)]);
+}
+
+/// Record that the token endpoint issued an access token with the given grant
+/// type
+pub(crate) fn record_token_issued(grant_type: &'static str) {
+    metrics()
+        .tokens_issued
+        .add(1, &[GRANT_TYPE.string(grant_type)]);
+}
+
+/// Record a failed authentication attempt with the given method
+pub(crate) fn record_authentication_failure(method: &'static str) {
+    metrics()
+        .authentication_failures
+        .add(1, &[METHOD.string(method)]);
+}
+
+/// Record an error while handling an upstream OAuth 2.0 callback
+pub(crate) fn record_upstream_oauth2_callback_error(error: &'static str) {
+    metrics()
+        .upstream_oauth2_callback_errors
+        .add(1, &[ERROR.string(error)]);
+}
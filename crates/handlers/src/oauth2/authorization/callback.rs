@@ -14,23 +14,48 @@
 
 #![allow(clippy::module_name_repetitions)]
 
-use std::collections::HashMap;
+use std::collections::HashSet;
 
 use axum::response::{Html, IntoResponse, Redirect, Response};
-use mas_data_model::AuthorizationGrant;
-use mas_templates::{FormPostContext, Templates};
-use oauth2_types::requests::ResponseMode;
+use chrono::{DateTime, Duration, Utc};
+use mas_data_model::{AuthorizationGrant, Client};
+use mas_iana::jose::JsonWebSignatureAlg;
+use mas_jose::jwt::{JsonWebSignatureHeader, Jwt, JwtSignatureError};
+use mas_keystore::{Keystore, WrongAlgorithmError};
+use mas_router::UrlBuilder;
+use mas_storage::Clock;
+use mas_templates::{FormPostContext, Templates, WebMessageContext};
+use oauth2_types::{registration::DEFAULT_SIGNING_ALGORITHM, requests::ResponseMode};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaChaRng;
 use serde::Serialize;
 use thiserror::Error;
 use url::Url;
 
+/// How long the JWT-secured authorization responses are valid for
+const RESPONSE_JWT_TTL: Duration = Duration::microseconds(10 * 60 * 1000 * 1000);
+
+/// Query parameters of the redirect URI which are always dropped, as the client
+/// would mistake them for parameters of the authorization response
+const RESERVED_PARAMS: [&str; 7] = [
+    "state",
+    "code",
+    "error",
+    "error_description",
+    "error_uri",
+    "response",
+    "iss",
+];
+
 #[derive(Debug, Clone)]
 enum CallbackDestinationMode {
     Query {
-        existing_params: HashMap<String, String>,
+        /// The query parameters of the redirect URI, in their original order
+        existing_params: Vec<(String, String)>,
     },
     Fragment,
     FormPost,
+    WebMessage,
 }
 
 #[derive(Debug, Clone)]
@@ -38,6 +63,103 @@ pub struct CallbackDestination {
     mode: CallbackDestinationMode,
     safe_redirect_uri: Url,
     state: Option<String>,
+
+    /// Whether the parameters must be signed in a JWT, as per JARM
+    jwt: bool,
+    signer: Option<ResponseSigner>,
+}
+
+/// Signs the authorization response parameters in a JWT, for clients which
+/// asked for a JWT-secured authorization response
+#[derive(Clone)]
+pub struct ResponseSigner {
+    key_store: Keystore,
+    alg: Option<JsonWebSignatureAlg>,
+    issuer: String,
+    client_id: String,
+    expires_at: DateTime<Utc>,
+    rng: ChaChaRng,
+}
+
+impl std::fmt::Debug for ResponseSigner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ResponseSigner")
+            .field("alg", &self.alg)
+            .field("issuer", &self.issuer)
+            .field("client_id", &self.client_id)
+            .field("expires_at", &self.expires_at)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ResponseSigner {
+    /// Create a new [`ResponseSigner`] for responses sent to the given client
+    pub fn new(
+        rng: &mut impl Rng,
+        clock: &impl Clock,
+        key_store: &Keystore,
+        url_builder: &UrlBuilder,
+        client: &Client,
+    ) -> Self {
+        // Use the algorithm the client registered, or else the default one if the
+        // keystore can sign with it, so that keystores without RSA keys still work
+        let alg = client
+            .authorization_signed_response_alg
+            .clone()
+            .or_else(|| {
+                let available = key_store.available_signing_algorithms();
+                if available.contains(DEFAULT_SIGNING_ALGORITHM) {
+                    Some(DEFAULT_SIGNING_ALGORITHM.clone())
+                } else {
+                    available.into_iter().next()
+                }
+            });
+
+        Self {
+            key_store: key_store.clone(),
+            alg,
+            issuer: url_builder.oidc_issuer().to_string(),
+            client_id: client.client_id.clone(),
+            expires_at: clock.now() + RESPONSE_JWT_TTL,
+            rng: ChaChaRng::from_seed(rng.gen()),
+        }
+    }
+
+    fn sign<T: Serialize>(mut self, params: T) -> Result<String, CallbackDestinationError> {
+        #[derive(Serialize)]
+        struct ResponseClaims<T> {
+            iss: String,
+            aud: String,
+            #[serde(with = "chrono::serde::ts_seconds")]
+            exp: DateTime<Utc>,
+
+            #[serde(flatten)]
+            params: T,
+        }
+
+        let alg = self
+            .alg
+            .ok_or(CallbackDestinationError::MissingSigningKey)?;
+        let key = self
+            .key_store
+            .signing_key_for_algorithm(&alg)
+            .ok_or(CallbackDestinationError::MissingSigningKey)?;
+        let signer = key.params().signing_key_for_alg(&alg)?;
+        let mut header = JsonWebSignatureHeader::new(alg);
+        if let Some(kid) = key.kid() {
+            header = header.with_kid(kid);
+        }
+
+        let claims = ResponseClaims {
+            iss: self.issuer,
+            aud: self.client_id,
+            exp: self.expires_at,
+            params,
+        };
+
+        let jwt = Jwt::sign_with_rng(&mut self.rng, header, claims, &signer)?;
+        Ok(jwt.into_string())
+    }
 }
 
 #[derive(Debug, Error)]
@@ -54,11 +176,23 @@ pub enum IntoCallbackDestinationError {
 
 #[derive(Debug, Error)]
 pub enum CallbackDestinationError {
-    #[error("Failed to render the form_post template")]
+    #[error("Failed to render the form_post or web_message template")]
     FormPostRender(#[from] mas_templates::TemplateError),
 
     #[error("Failed to serialize parameters query string")]
     ParamsSerialization(#[from] serde_urlencoded::ser::Error),
+
+    #[error("No signer was set up for the JWT-secured authorization response")]
+    MissingSigner,
+
+    #[error("No key is available to sign the authorization response")]
+    MissingSigningKey,
+
+    #[error("Invalid key to sign the authorization response")]
+    InvalidSigningKey(#[from] WrongAlgorithmError),
+
+    #[error("Failed to sign the authorization response")]
+    Signing(#[from] JwtSignatureError),
 }
 
 impl TryFrom<&AuthorizationGrant> for CallbackDestination {
@@ -83,8 +217,9 @@ impl CallbackDestination {
             return Err(IntoCallbackDestinationError::RedirectUriFragmentNotAllowed);
         }
 
+        let jwt = mode.is_jwt();
         let mode = match mode {
-            ResponseMode::Query => {
+            ResponseMode::Query | ResponseMode::QueryJwt => {
                 let existing_params = redirect_uri
                     .query()
                     .map(serde_urlencoded::from_str)
@@ -96,8 +231,9 @@ impl CallbackDestination {
 
                 CallbackDestinationMode::Query { existing_params }
             }
-            ResponseMode::Fragment => CallbackDestinationMode::Fragment,
-            ResponseMode::FormPost => CallbackDestinationMode::FormPost,
+            ResponseMode::Fragment | ResponseMode::FragmentJwt => CallbackDestinationMode::Fragment,
+            ResponseMode::FormPost | ResponseMode::FormPostJwt => CallbackDestinationMode::FormPost,
+            ResponseMode::WebMessage => CallbackDestinationMode::WebMessage,
             _ => return Err(IntoCallbackDestinationError::UnsupportedResponseMode),
         };
 
@@ -105,19 +241,67 @@ impl CallbackDestination {
             mode,
             safe_redirect_uri: redirect_uri,
             state,
+            jwt,
+            signer: None,
         })
     }
 
+    /// Set the signer used for JWT-secured authorization responses. It is only
+    /// used if the response mode asks for one.
+    #[must_use]
+    pub fn with_signer(mut self, signer: ResponseSigner) -> Self {
+        self.signer = Some(signer);
+        self
+    }
+
     pub async fn go<T: Serialize + Send + Sync>(
         self,
         templates: &Templates,
         params: T,
     ) -> Result<Response, CallbackDestinationError> {
-        #[derive(Serialize)]
-        struct AllParams<'s, T> {
-            #[serde(flatten, skip_serializing_if = "Option::is_none")]
-            existing: Option<&'s HashMap<String, String>>,
+        let redirect_uri = self.safe_redirect_uri;
+        let state = self.state;
 
+        // With JWT-secured responses, the parameters, including the state, are
+        // signed and sent in a single `response` parameter
+        if self.jwt {
+            #[derive(Serialize)]
+            struct JwtParams<T> {
+                #[serde(skip_serializing_if = "Option::is_none")]
+                state: Option<String>,
+
+                #[serde(flatten)]
+                params: T,
+            }
+
+            #[derive(Serialize)]
+            struct JwtResponse {
+                response: String,
+            }
+
+            let signer = self.signer.ok_or(CallbackDestinationError::MissingSigner)?;
+            let response = signer.sign(JwtParams { state, params })?;
+            return Self::deliver(
+                self.mode,
+                redirect_uri,
+                templates,
+                None,
+                JwtResponse { response },
+            );
+        }
+
+        Self::deliver(self.mode, redirect_uri, templates, state, params)
+    }
+
+    fn deliver<T: Serialize>(
+        mode: CallbackDestinationMode,
+        mut redirect_uri: Url,
+        templates: &Templates,
+        state: Option<String>,
+        params: T,
+    ) -> Result<Response, CallbackDestinationError> {
+        #[derive(Serialize)]
+        struct AllParams<T> {
             #[serde(skip_serializing_if = "Option::is_none")]
             state: Option<String>,
 
@@ -125,18 +309,11 @@ impl CallbackDestination {
             params: T,
         }
 
-        let mut redirect_uri = self.safe_redirect_uri;
-        let state = self.state;
-
-        match self.mode {
+        match mode {
             CallbackDestinationMode::Query { existing_params } => {
-                let merged = AllParams {
-                    existing: Some(&existing_params),
-                    state,
-                    params,
-                };
-
-                let new_qs = serde_urlencoded::to_string(merged)?;
+                let merged = AllParams { state, params };
+                let response_qs = serde_urlencoded::to_string(merged)?;
+                let new_qs = merge_query(&existing_params, &response_qs);
 
                 redirect_uri.set_query(Some(&new_qs));
 
@@ -144,11 +321,7 @@ impl CallbackDestination {
             }
 
             CallbackDestinationMode::Fragment => {
-                let merged = AllParams {
-                    existing: None,
-                    state,
-                    params,
-                };
+                let merged = AllParams { state, params };
 
                 let new_qs = serde_urlencoded::to_string(merged)?;
 
@@ -158,15 +331,158 @@ impl CallbackDestination {
             }
 
             CallbackDestinationMode::FormPost => {
-                let merged = AllParams {
-                    existing: None,
-                    state,
-                    params,
-                };
+                let merged = AllParams { state, params };
                 let ctx = FormPostContext::new(redirect_uri, merged);
                 let rendered = templates.render_form_post(&ctx)?;
                 Ok(Html(rendered).into_response())
             }
+
+            CallbackDestinationMode::WebMessage => {
+                let merged = AllParams { state, params };
+                let ctx = WebMessageContext::new(&redirect_uri, merged);
+                let rendered = templates.render_web_message(&ctx)?;
+                Ok(Html(rendered).into_response())
+            }
+        }
+    }
+}
+
+/// Merge the query parameters of the redirect URI with the ones of the
+/// authorization response.
+///
+/// The parameters of the redirect URI keep their original order and come
+/// first. Those which have the same name as a response parameter, or which
+/// could be mistaken for one, are dropped, so that the response parameters
+/// always win.
+fn merge_query(existing_params: &[(String, String)], response_qs: &str) -> String {
+    let response_names: HashSet<_> = url::form_urlencoded::parse(response_qs.as_bytes())
+        .map(|(name, _)| name)
+        .collect();
+
+    let mut serializer = url::form_urlencoded::Serializer::new(String::new());
+    for (name, value) in existing_params {
+        if RESERVED_PARAMS.contains(&name.as_str()) || response_names.contains(name.as_str()) {
+            continue;
         }
+
+        serializer.append_pair(name, value);
+    }
+
+    let mut qs = serializer.finish();
+    if !response_qs.is_empty() {
+        if !qs.is_empty() {
+            qs.push('&');
+        }
+        qs.push_str(response_qs);
+    }
+
+    qs
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use mas_keystore::{JsonWebKey, JsonWebKeySet, PrivateKey};
+    use mas_storage::clock::MockClock;
+
+    use super::*;
+
+    fn existing_params(redirect_uri: &str) -> Vec<(String, String)> {
+        let destination = CallbackDestination::try_new(
+            &ResponseMode::Query,
+            Url::parse(redirect_uri).unwrap(),
+            None,
+        )
+        .unwrap();
+
+        // The query is moved out of the redirect URI
+        assert_eq!(destination.safe_redirect_uri.query(), None);
+
+        let CallbackDestinationMode::Query { existing_params } = destination.mode else {
+            panic!("expected the query response mode");
+        };
+
+        existing_params
+    }
+
+    #[test]
+    fn test_merge_query() {
+        // Without any existing parameters, the response is left as is
+        let existing = existing_params("https://example.com/callback");
+        assert_eq!(
+            merge_query(&existing, "state=abc&code=def"),
+            "state=abc&code=def"
+        );
+
+        // Existing parameters keep their order, and come first
+        let existing = existing_params("https://example.com/callback?z=1&a=2&m=3");
+        assert_eq!(
+            merge_query(&existing, "state=abc&code=def"),
+            "z=1&a=2&m=3&state=abc&code=def"
+        );
+
+        // The response parameters win over the existing ones
+        let existing = existing_params("https://example.com/callback?code=evil&foo=bar");
+        assert_eq!(
+            merge_query(&existing, "state=abc&code=def"),
+            "foo=bar&state=abc&code=def"
+        );
+
+        // A state in the redirect URI is dropped, even if the response has none
+        let existing = existing_params("https://example.com/callback?state=evil&foo=bar");
+        assert_eq!(merge_query(&existing, "code=def"), "foo=bar&code=def");
+        let existing = existing_params("https://example.com/callback?state=evil");
+        assert_eq!(
+            merge_query(&existing, "error=access_denied"),
+            "error=access_denied"
+        );
+
+        // Percent-encoded values are preserved
+        let existing =
+            existing_params("https://example.com/callback?next=%2Fhome%3Fa%3Db&name=a%26b");
+        assert_eq!(
+            existing,
+            vec![
+                ("next".to_owned(), "/home?a=b".to_owned()),
+                ("name".to_owned(), "a&b".to_owned()),
+            ]
+        );
+        assert_eq!(
+            merge_query(&existing, "code=def"),
+            "next=%2Fhome%3Fa%3Db&name=a%26b&code=def"
+        );
+    }
+
+    #[test]
+    fn test_response_signer_algorithm() {
+        let clock = MockClock::default();
+        let mut rng = ChaChaRng::seed_from_u64(42);
+        let url_builder = UrlBuilder::new("https://example.com/".parse().unwrap(), None, None);
+        let ec = PrivateKey::load_pem(include_str!(
+            "../../../../keystore/tests/keys/ec-p256.pkcs8.pem"
+        ))
+        .unwrap();
+        let key_store = Keystore::new(JsonWebKeySet::new(vec![
+            JsonWebKey::new(ec).with_kid("test-ec")
+        ]));
+        let mut client = Client::samples(clock.now(), &mut rng).remove(0);
+        let params = HashMap::from([("state", "abc")]);
+
+        // Without any RSA key, the response is signed with another algorithm
+        let signer = ResponseSigner::new(&mut rng, &clock, &key_store, &url_builder, &client);
+        let jwt = signer.sign(&params).unwrap();
+        let jwt: Jwt<'_, HashMap<String, serde_json::Value>> = Jwt::try_from(jwt.as_str()).unwrap();
+        assert_eq!(jwt.header().alg(), &JsonWebSignatureAlg::Es256);
+        jwt.verify_with_jwks(&key_store.public_jwks()).unwrap();
+        assert_eq!(jwt.payload()["state"], "abc");
+
+        // The algorithm registered by the client is always used
+        client.authorization_signed_response_alg = Some(JsonWebSignatureAlg::Rs256);
+        let signer = ResponseSigner::new(&mut rng, &clock, &key_store, &url_builder, &client);
+        assert!(matches!(
+            signer.sign(&params),
+            Err(CallbackDestinationError::MissingSigningKey)
+        ));
     }
 }
@@ -18,12 +18,15 @@ use axum::{
 };
 use hyper::StatusCode;
 use mas_axum_utils::{cookies::CookieJar, csrf::CsrfExt, sentry::SentryEventID, SessionInfoExt};
-use mas_data_model::{AuthorizationGrant, BrowserSession, Client, Device};
+use mas_data_model::{AuthorizationGrant, BrowserSession, Client, Device, SiteConfig};
 use mas_keystore::Keystore;
 use mas_policy::{EvaluationResult, Policy};
 use mas_router::{PostAuthAction, UrlBuilder};
 use mas_storage::{
-    oauth2::{OAuth2AuthorizationGrantRepository, OAuth2ClientRepository, OAuth2SessionRepository},
+    oauth2::{
+        OAuth2AuthorizationGrantRepository, OAuth2ClientRepository, OAuth2ConsentRepository,
+        OAuth2SessionRepository,
+    },
     user::BrowserSessionRepository,
     BoxClock, BoxRepository, BoxRng, Clock, RepositoryAccess,
 };
@@ -33,7 +36,7 @@ use thiserror::Error;
 use tracing::warn;
 use ulid::Ulid;
 
-use super::callback::CallbackDestination;
+use super::callback::{CallbackDestination, ResponseSigner};
 use crate::{
     impl_from_error_for_route, oauth2::generate_id_token, BoundActivityTracker, PreferredLanguage,
 };
@@ -95,15 +98,16 @@ pub(crate) async fn get(
     State(templates): State<Templates>,
     State(url_builder): State<UrlBuilder>,
     State(key_store): State<Keystore>,
+    State(site_config): State<SiteConfig>,
     policy: Policy,
     activity_tracker: BoundActivityTracker,
     mut repo: BoxRepository,
     cookie_jar: CookieJar,
     Path(grant_id): Path<Ulid>,
 ) -> Result<Response, RouteError> {
-    let (session_info, cookie_jar) = cookie_jar.session_info();
+    let (session_info, cookie_jar) = cookie_jar.session_info(&clock);
 
-    let maybe_session = session_info.load_session(&mut repo).await?;
+    let maybe_session = session_info.load_session(&clock, &mut repo).await?;
 
     let grant = repo
         .oauth2_authorization_grant()
@@ -111,7 +115,7 @@ pub(crate) async fn get(
         .await?
         .ok_or(RouteError::NotFound)?;
 
-    let callback_destination = CallbackDestination::try_from(&grant)?;
+    let mut callback_destination = CallbackDestination::try_from(&grant)?;
     let continue_grant = PostAuthAction::continue_grant(grant.id);
 
     let Some(session) = maybe_session else {
@@ -134,6 +138,14 @@ pub(crate) async fn get(
         .await?
         .ok_or(RouteError::NoSuchClient)?;
 
+    callback_destination = callback_destination.with_signer(ResponseSigner::new(
+        &mut rng,
+        &clock,
+        &key_store,
+        &url_builder,
+        &client,
+    ));
+
     match complete(
         &mut rng,
         &clock,
@@ -142,6 +154,7 @@ pub(crate) async fn get(
         key_store,
         policy,
         &url_builder,
+        &site_config,
         grant,
         &client,
         &session,
@@ -211,6 +224,7 @@ pub(crate) async fn complete(
     key_store: Keystore,
     mut policy: Policy,
     url_builder: &UrlBuilder,
+    site_config: &SiteConfig,
     grant: AuthorizationGrant,
     client: &Client,
     browser_session: &BrowserSession,
@@ -225,13 +239,25 @@ pub(crate) async fn complete(
         .browser_session()
         .get_last_authentication(browser_session)
         .await?;
-    let authentication = authentication.filter(|auth| auth.created_at > grant.max_auth_time());
+    // If the user never authenticated in this browser session, or if the
+    // authentication is too old for the grant, they have to authenticate again
+    let authentication =
+        authentication.filter(|auth| grant.is_authentication_fresh(auth.created_at));
 
     let Some(valid_authentication) = authentication else {
         repo.save().await?;
         return Err(GrantCompletionError::RequiresReauth);
     };
 
+    // Record that the user reauthenticated, so that we don't ask again
+    let grant = if grant.requires_reauth {
+        repo.oauth2_authorization_grant()
+            .reauthenticated(grant)
+            .await?
+    } else {
+        grant
+    };
+
     // Run through the policy
     let res = policy
         .evaluate_authorization_grant(&grant, client, &browser_session.user)
@@ -242,8 +268,8 @@ pub(crate) async fn complete(
     }
 
     let current_consent = repo
-        .oauth2_client()
-        .get_consent_for_user(client, &browser_session.user)
+        .oauth2_consent()
+        .find(client, &browser_session.user)
         .await?;
 
     let lacks_consent = grant
@@ -252,8 +278,10 @@ pub(crate) async fn complete(
         .filter(|scope| Device::from_scope_token(scope).is_none())
         .any(|_| true);
 
-    // Check if the client lacks consent *or* if consent was explicitly asked
-    if lacks_consent || grant.requires_consent {
+    // Check if the client lacks consent *or* if consent was explicitly asked.
+    // Trusted clients never need the user to consent to what they requested,
+    // but they still get the consent screen when they explicitly ask for it
+    if grant.requires_consent || (!client.trusted && lacks_consent) {
         repo.save().await?;
         return Err(GrantCompletionError::RequiresConsent);
     }
@@ -264,11 +292,36 @@ pub(crate) async fn complete(
         .add_from_browser_session(rng, clock, client, browser_session, grant.scope.clone())
         .await?;
 
+    // Remember which authentication was used, so that the ID tokens issued
+    // later for this session report it
+    let session = repo
+        .oauth2_session()
+        .record_auth_time(session, valid_authentication.created_at)
+        .await?;
+
+    // The tokens of the session are restricted to the resources requested by
+    // the client, if any
+    let session = if grant.resource.is_empty() {
+        session
+    } else {
+        repo.oauth2_session()
+            .record_resource(session, grant.resource.clone())
+            .await?
+    };
+
     let grant = repo
         .oauth2_authorization_grant()
-        .fulfill(clock, &session, grant)
+        .fulfill(clock, &session, grant, site_config.authorization_code_ttl)
         .await?;
 
+    // The "none" response type doesn't return any credentials, so the session can
+    // never be used: end it right away
+    let session = if grant.code.is_none() && !grant.response_type_id_token {
+        repo.oauth2_session().finish(clock, session).await?
+    } else {
+        session
+    };
+
     // Yep! Let's complete the auth now
     let mut params = AuthorizationResponse::default();
 
@@ -281,6 +334,7 @@ pub(crate) async fn complete(
             &key_store,
             client,
             Some(&grant),
+            &session,
             browser_session,
             None,
             Some(&valid_authentication),
@@ -12,21 +12,33 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::{collections::BTreeMap, num::NonZeroU32};
+
 use axum::{
-    extract::{Form, State},
+    extract::{RawQuery, State},
     response::{Html, IntoResponse, Response},
 };
 use hyper::StatusCode;
-use mas_axum_utils::{cookies::CookieJar, csrf::CsrfExt, sentry::SentryEventID, SessionInfoExt};
-use mas_data_model::{AuthorizationCode, Pkce};
+use mas_axum_utils::{
+    cookies::CookieJar, csrf::CsrfExt, http_client_factory::HttpClientFactory,
+    sentry::SentryEventID, SessionInfoExt,
+};
+use mas_data_model::{AuthorizationCode, Client, Pkce, PushedAuthorizationRequest, SiteConfig};
+use mas_i18n::DataLocale;
+use mas_iana::oauth::PkceCodeChallengeMethod;
 use mas_keystore::Keystore;
 use mas_policy::Policy;
 use mas_router::{PostAuthAction, UrlBuilder};
 use mas_storage::{
-    oauth2::{OAuth2AuthorizationGrantRepository, OAuth2ClientRepository},
+    oauth2::{
+        OAuth2AuthorizationGrantRepository, OAuth2ClientRepository,
+        OAuth2PushedAuthRequestRepository,
+    },
     BoxClock, BoxRepository, BoxRng,
 };
-use mas_templates::{PolicyViolationContext, TemplateContext, Templates};
+use mas_templates::{
+    ErrorContext, OAuth2ErrorContext, PolicyViolationContext, TemplateContext, Templates,
+};
 use oauth2_types::{
     errors::{ClientError, ClientErrorCode},
     pkce,
@@ -37,8 +49,13 @@ use rand::{distributions::Alphanumeric, Rng};
 use serde::Deserialize;
 use thiserror::Error;
 use tracing::warn;
+use url::Url;
 
-use self::{callback::CallbackDestination, complete::GrantCompletionError};
+use self::{
+    callback::{CallbackDestination, ResponseSigner},
+    complete::GrantCompletionError,
+};
+use super::jar::{JarError, JarVerifier};
 use crate::{impl_from_error_for_route, BoundActivityTracker, PreferredLanguage};
 
 mod callback;
@@ -58,8 +75,23 @@ pub enum RouteError {
     #[error("invalid parameters")]
     IntoCallbackDestination(#[from] self::callback::IntoCallbackDestinationError),
 
-    #[error("invalid redirect uri")]
-    UnknownRedirectUri(#[from] mas_data_model::InvalidRedirectUriError),
+    #[error("invalid or expired request_uri")]
+    InvalidRequestUri,
+
+    #[error("invalid pushed authorization request")]
+    InvalidPushedRequest(#[source] serde_json::Error),
+
+    #[error("invalid request object")]
+    InvalidRequestObject(#[from] JarError),
+
+    #[error("invalid signed authorization request")]
+    InvalidSignedRequest(#[source] serde_json::Error),
+
+    #[error("invalid parameters")]
+    InvalidParameters(#[source] serde_json::Error),
+
+    #[error("the request and request_uri parameters can't be used together")]
+    RequestAndRequestUri,
 }
 
 impl IntoResponse for RouteError {
@@ -79,9 +111,32 @@ impl IntoResponse for RouteError {
             RouteError::IntoCallbackDestination(e) => {
                 (StatusCode::BAD_REQUEST, e.to_string()).into_response()
             }
-            RouteError::UnknownRedirectUri(e) => (
+            RouteError::InvalidRequestUri => {
+                (StatusCode::BAD_REQUEST, "invalid or expired request_uri").into_response()
+            }
+            RouteError::InvalidPushedRequest(e) => (
                 StatusCode::BAD_REQUEST,
-                format!("Invalid redirect URI ({e})"),
+                format!("Invalid pushed authorization request ({e})"),
+            )
+                .into_response(),
+            RouteError::InvalidRequestObject(e) => (
+                StatusCode::BAD_REQUEST,
+                format!("Invalid request object ({e})"),
+            )
+                .into_response(),
+            RouteError::InvalidSignedRequest(e) => (
+                StatusCode::BAD_REQUEST,
+                format!("Invalid signed authorization request ({e})"),
+            )
+                .into_response(),
+            RouteError::InvalidParameters(e) => (
+                StatusCode::BAD_REQUEST,
+                format!("Invalid authorization request parameters ({e})"),
+            )
+                .into_response(),
+            RouteError::RequestAndRequestUri => (
+                StatusCode::BAD_REQUEST,
+                "The request and request_uri parameters can't be used together",
             )
                 .into_response(),
         };
@@ -99,39 +154,166 @@ impl_from_error_for_route!(mas_policy::EvaluationError);
 #[derive(Deserialize)]
 pub(crate) struct Params {
     #[serde(flatten)]
-    auth: AuthorizationRequest,
+    pub(crate) auth: AuthorizationRequest,
+
+    #[serde(flatten)]
+    pub(crate) pkce: Option<pkce::AuthorizationRequest>,
+}
+
+impl Params {
+    /// Parse the parameters of an authorization request, as saved by the
+    /// pushed authorization request endpoint
+    pub(crate) fn from_parameters(
+        parameters: BTreeMap<String, String>,
+    ) -> Result<Self, serde_json::Error> {
+        serde_json::from_value(serde_json::to_value(parameters)?)
+    }
+}
+
+/// An authorization request carrying a signed request object, alongside
+/// parameters passed in the clear
+#[derive(Deserialize)]
+pub(crate) struct SignedParams {
+    client_id: String,
+    request: String,
 
     #[serde(flatten)]
-    pkce: Option<pkce::AuthorizationRequest>,
+    parameters: BTreeMap<String, String>,
+}
+
+/// The parameters accepted by the authorization endpoint: either the full
+/// authorization request, a signed request object, or a reference to a
+/// request previously pushed through the pushed authorization request
+/// endpoint
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub(crate) enum RequestParams {
+    Pushed {
+        client_id: String,
+        request_uri: String,
+    },
+    Signed(SignedParams),
+    Direct(Params),
+}
+
+impl RequestParams {
+    /// Parse the parameters of an authorization request, as found in the query
+    fn from_parameters(parameters: BTreeMap<String, String>) -> Result<Self, serde_json::Error> {
+        serde_json::from_value(serde_json::to_value(parameters)?)
+    }
+
+    fn client_id(&self) -> &str {
+        match self {
+            RequestParams::Pushed { client_id, .. }
+            | RequestParams::Signed(SignedParams { client_id, .. }) => client_id,
+            RequestParams::Direct(params) => &params.auth.client_id,
+        }
+    }
+}
+
+/// Parameters which tell where and how to reply to the client. If any of those
+/// is repeated, the error can't be sent back to the client.
+const CALLBACK_PARAMETERS: [&str; 4] = [
+    "client_id",
+    "redirect_uri",
+    "response_type",
+    "response_mode",
+];
+
+/// Split the query string of an authorization request into its parameters.
+///
+/// Parameters must not be included more than once as per RFC 6749 §3.1, so
+/// this also returns the name of the first repeated parameter, if any. Only the
+/// first value of a repeated parameter is kept.
+///
+/// The only exception is the `resource` parameter, which RFC 8707 allows to
+/// repeat: its values are joined with spaces.
+fn parse_query(query: &str) -> (BTreeMap<String, String>, Option<String>) {
+    let mut parameters: BTreeMap<String, String> = BTreeMap::new();
+    let mut duplicate_parameter = None;
+
+    for (name, value) in url::form_urlencoded::parse(query.as_bytes()) {
+        if name == "resource" {
+            if let Some(resource) = parameters.get_mut("resource") {
+                resource.push(' ');
+                resource.push_str(&value);
+                continue;
+            }
+        }
+
+        if parameters.contains_key(name.as_ref()) {
+            duplicate_parameter.get_or_insert_with(|| name.into_owned());
+        } else {
+            parameters.insert(name.into_owned(), value.into_owned());
+        }
+    }
+
+    (parameters, duplicate_parameter)
+}
+
+/// Render the error page shown when an error can't be sent back to a known
+/// client
+///
+/// The `return_uri` is a valid redirect URI of the client, which can be used to
+/// go back to it, as long as it doesn't carry the error.
+fn render_oauth2_error(
+    templates: &Templates,
+    locale: DataLocale,
+    client: Client,
+    return_uri: Option<Url>,
+    error: &ClientError,
+) -> Result<Response, RouteError> {
+    let ctx = OAuth2ErrorContext::new(client, error)
+        .with_return_uri(return_uri)
+        .with_language(locale);
+
+    let content = templates.render_oauth2_error(&ctx)?;
+    Ok((StatusCode::BAD_REQUEST, Html(content)).into_response())
 }
 
 /// Given a list of response types and an optional user-defined response mode,
 /// figure out what response mode must be used, and emit an error if the
-/// suggested response mode isn't allowed for the given response types.
+/// suggested response mode isn't allowed for the given response types or
+/// redirect URI.
 fn resolve_response_mode(
     response_type: &ResponseType,
+    redirect_uri: &Url,
     suggested_response_mode: Option<ResponseMode>,
 ) -> Result<ResponseMode, RouteError> {
     use ResponseMode as M;
 
+    // The "web_message" response mode posts the response to the origin of the
+    // redirect URI, which means it must have a non-opaque origin
+    if suggested_response_mode == Some(M::WebMessage) && !redirect_uri.origin().is_tuple() {
+        return Err(RouteError::InvalidResponseMode);
+    }
+
     // If the response type includes either "token" or "id_token", the default
-    // response mode is "fragment" and the response mode "query" must not be
-    // used
+    // response mode is "fragment" and the response modes "query" and "query.jwt"
+    // must not be used. The "jwt" response mode is a shorthand for the JWT
+    // variant of the default response mode.
     if response_type.has_token() || response_type.has_id_token() {
         match suggested_response_mode {
             None => Ok(M::Fragment),
-            Some(M::Query) => Err(RouteError::InvalidResponseMode),
+            Some(M::Jwt) => Ok(M::FragmentJwt),
+            Some(M::Query | M::QueryJwt) => Err(RouteError::InvalidResponseMode),
             Some(mode) => Ok(mode),
         }
+    } else if suggested_response_mode == Some(M::Jwt) {
+        Ok(M::QueryJwt)
     } else {
-        // In other cases, all response modes are allowed, defaulting to "query"
+        // In other cases, including the "none" response type which doesn't return
+        // any credentials, all response modes are allowed, defaulting to "query"
         Ok(suggested_response_mode.unwrap_or(M::Query))
     }
 }
 
 #[tracing::instrument(
     name = "handlers.oauth2.authorization.get",
-    fields(client.id = %params.auth.client_id),
+    fields(
+        client.id = tracing::field::Empty,
+        oauth2.duplicate_parameter = tracing::field::Empty,
+    ),
     skip_all,
     err,
 )]
@@ -143,35 +325,192 @@ pub(crate) async fn get(
     State(templates): State<Templates>,
     State(key_store): State<Keystore>,
     State(url_builder): State<UrlBuilder>,
+    State(http_client_factory): State<HttpClientFactory>,
+    State(jar_verifier): State<JarVerifier>,
+    State(site_config): State<SiteConfig>,
     policy: Policy,
     activity_tracker: BoundActivityTracker,
     mut repo: BoxRepository,
     cookie_jar: CookieJar,
-    Form(params): Form<Params>,
+    RawQuery(query): RawQuery,
 ) -> Result<Response, RouteError> {
+    let (parameters, duplicate_parameter) = parse_query(query.as_deref().unwrap_or_default());
+    let span = tracing::Span::current();
+
+    if let Some(name) = &duplicate_parameter {
+        span.record("oauth2.duplicate_parameter", name.as_str());
+
+        if CALLBACK_PARAMETERS.contains(&name.as_str()) {
+            let error = ClientError::from(ClientErrorCode::InvalidRequest)
+                .with_description(format!("Parameter {name:?} is included more than once"));
+
+            // The error can't be sent back to the client, but unless the client_id
+            // itself is repeated, the client can still be shown to the user, with a
+            // link back to it if the first redirect_uri is valid
+            let client = match parameters.get("client_id") {
+                Some(client_id) if name != "client_id" => {
+                    repo.oauth2_client().find_by_client_id(client_id).await?
+                }
+                _ => None,
+            };
+
+            let Some(client) = client else {
+                let ctx = ErrorContext::new()
+                    .with_code("invalid_request")
+                    .with_description(error.error_description.unwrap_or_default().into_owned())
+                    .with_language(&locale);
+
+                let content = templates.render_error(&ctx)?;
+                return Ok((StatusCode::BAD_REQUEST, Html(content)).into_response());
+            };
+
+            let redirect_uri = parameters
+                .get("redirect_uri")
+                .and_then(|redirect_uri| Url::parse(redirect_uri).ok());
+            let return_uri = client.resolve_redirect_uri(&redirect_uri).ok().cloned();
+
+            return render_oauth2_error(&templates, locale, client, return_uri, &error);
+        }
+    }
+
+    // A request object can either be passed by value or by reference, not both
+    if parameters.contains_key("request") && parameters.contains_key("request_uri") {
+        return Err(RouteError::RequestAndRequestUri);
+    }
+
+    let params =
+        RequestParams::from_parameters(parameters).map_err(RouteError::InvalidParameters)?;
+    span.record("client.id", params.client_id());
+
     // First, figure out what client it is
     let client = repo
         .oauth2_client()
-        .find_by_client_id(&params.auth.client_id)
+        .find_by_client_id(params.client_id())
         .await?
         .ok_or(RouteError::ClientNotFound)?;
 
-    // And resolve the redirect_uri and response_mode
-    let redirect_uri = client
-        .resolve_redirect_uri(&params.auth.redirect_uri)?
-        .clone();
+    // Pushed requests were already checked by the pushed authorization request
+    // endpoint, so only direct requests are considered unsigned
+    let signed = !matches!(params, RequestParams::Direct(_));
+
+    // If the request references a pushed authorization request, load it. The
+    // parameters of the pushed request must be used, ignoring any other
+    // parameter passed in the query
+    let params = match params {
+        RequestParams::Direct(params) => params,
+        RequestParams::Signed(SignedParams {
+            request,
+            mut parameters,
+            ..
+        }) => {
+            // The claims of the request object take precedence over the parameters
+            // passed in the query
+            let claims = jar_verifier
+                .verify(
+                    &http_client_factory,
+                    clock.now(),
+                    &url_builder.oidc_issuer(),
+                    &client,
+                    &request,
+                )
+                .await?;
+            parameters.extend(claims);
+            parameters.insert("client_id".to_owned(), client.client_id.clone());
+
+            Params::from_parameters(parameters).map_err(RouteError::InvalidSignedRequest)?
+        }
+        RequestParams::Pushed { request_uri, .. } => {
+            let id = PushedAuthorizationRequest::id_from_request_uri(&request_uri)
+                .ok_or(RouteError::InvalidRequestUri)?;
+
+            let pushed_request = repo
+                .oauth2_pushed_auth_request()
+                .lookup(id)
+                .await?
+                .filter(|request| request.client_id == client.id)
+                .filter(|request| !request.is_expired(clock.now()))
+                .ok_or(RouteError::InvalidRequestUri)?;
+
+            Params::from_parameters(pushed_request.parameters)
+                .map_err(RouteError::InvalidPushedRequest)?
+        }
+    };
+
+    // And resolve the redirect_uri and response_mode. If the redirect_uri is
+    // invalid, the error can't be sent back to the client, so show it to the
+    // user instead
+    let redirect_uri = match client.resolve_redirect_uri(&params.auth.redirect_uri) {
+        Ok(redirect_uri) => redirect_uri.clone(),
+        Err(e) => {
+            let error = ClientError::from(ClientErrorCode::InvalidRedirectUri)
+                .with_description(e.to_string());
+
+            // If the client has a single redirect URI, it is the one which would be
+            // used without any redirect_uri parameter, and it can be used to go
+            // back to the client
+            let return_uri = client.resolve_redirect_uri(&None).ok().cloned();
+
+            return render_oauth2_error(&templates, locale, client, return_uri, &error);
+        }
+    };
     let response_type = params.auth.response_type;
-    let response_mode = resolve_response_mode(&response_type, params.auth.response_mode)?;
+    let response_mode =
+        match resolve_response_mode(&response_type, &redirect_uri, params.auth.response_mode) {
+            Ok(response_mode) => response_mode,
+            Err(RouteError::InvalidResponseMode) => {
+                // The redirect URI is valid, so the error can still be sent back to the
+                // client, using the default response mode of the response type
+                let response_mode = resolve_response_mode(&response_type, &redirect_uri, None)?;
+                let callback_destination =
+                    CallbackDestination::try_new(&response_mode, redirect_uri, params.auth.state)?
+                        .with_signer(ResponseSigner::new(
+                            &mut rng,
+                            &clock,
+                            &key_store,
+                            &url_builder,
+                            &client,
+                        ));
+                let response = callback_destination
+                    .go(
+                        &templates,
+                        ClientError::new(
+                            ClientErrorCode::InvalidRequest,
+                            "The requested response mode is not allowed",
+                        ),
+                    )
+                    .await?;
+                return Ok(response);
+            }
+            Err(e) => return Err(e),
+        };
 
     // Now we have a proper callback destination to go to on error
     let callback_destination = CallbackDestination::try_new(
         &response_mode,
         redirect_uri.clone(),
         params.auth.state.clone(),
-    )?;
+    )?
+    .with_signer(ResponseSigner::new(
+        &mut rng,
+        &clock,
+        &key_store,
+        &url_builder,
+        &client,
+    ));
+
+    if let Some(name) = duplicate_parameter {
+        let response = callback_destination
+            .go(
+                &templates,
+                ClientError::from(ClientErrorCode::InvalidRequest)
+                    .with_description(format!("Parameter {name:?} is included more than once")),
+            )
+            .await?;
+        return Ok(response);
+    }
 
     // Get the session info from the cookie
-    let (session_info, cookie_jar) = cookie_jar.session_info();
+    let (session_info, cookie_jar) = cookie_jar.session_info(&clock);
     let (csrf_token, cookie_jar) = cookie_jar.csrf_token(&clock, &mut rng);
 
     // One day, we will have try blocks
@@ -179,11 +518,24 @@ pub(crate) async fn get(
         let templates = templates.clone();
         let callback_destination = callback_destination.clone();
         async move {
-            let maybe_session = session_info.load_session(&mut repo).await?;
+            let maybe_session = session_info.load_session(&clock, &mut repo).await?;
             let prompt = params.auth.prompt.as_deref().unwrap_or_default();
 
-            // Check if the request/request_uri/registration params are used. If so, reply
-            // with the right error since we don't support them.
+            // Clients which require signed request objects must not send unsigned
+            // requests
+            if client.require_signed_request_object && !signed {
+                return Ok(callback_destination
+                    .go(
+                        &templates,
+                        ClientError::from(ClientErrorCode::InvalidRequest),
+                    )
+                    .await?);
+            }
+
+            // Check if the request/registration params are still there. If so, reply with
+            // the right error since we don't support them. The `request` and
+            // `request_uri` params are consumed when resolving signed and pushed
+            // authorization requests above, so they can't be nested.
             if params.auth.request.is_some() {
                 return Ok(callback_destination
                     .go(
@@ -193,11 +545,27 @@ pub(crate) async fn get(
                     .await?);
             }
 
-            if params.auth.request_uri.is_some() {
+            // The implicit and hybrid flows can be disabled for all clients
+            if site_config.disable_implicit_flow
+                && (response_type.has_token() || response_type.has_id_token())
+            {
+                return Ok(callback_destination
+                    .go(
+                        &templates,
+                        ClientError::from(ClientErrorCode::UnsupportedResponseType),
+                    )
+                    .await?);
+            }
+
+            // The client must be allowed to use the requested response type. Dynamically
+            // registered clients can only register response types which match their grant
+            // types, so this also checks that only clients allowed to use the `implicit`
+            // grant type can ask for an `id_token`
+            if !client.allows_response_type(&response_type) {
                 return Ok(callback_destination
                     .go(
                         &templates,
-                        ClientError::from(ClientErrorCode::RequestUriNotSupported),
+                        ClientError::from(ClientErrorCode::UnauthorizedClient),
                     )
                     .await?);
             }
@@ -213,13 +581,15 @@ pub(crate) async fn get(
                     .await?);
             }
 
-            // If the client asked for a `id_token` response type, we must check if it can
-            // use the `implicit` grant type
-            if response_type.has_id_token() && !client.grant_types.contains(&GrantType::Implicit) {
+            // ID tokens returned from the authorization endpoint must be bound to a nonce
+            if response_type.has_id_token() && params.auth.nonce.is_none() {
                 return Ok(callback_destination
                     .go(
                         &templates,
-                        ClientError::from(ClientErrorCode::UnauthorizedClient),
+                        ClientError::new(
+                            ClientErrorCode::InvalidRequest,
+                            "The nonce parameter is required when requesting an ID token",
+                        ),
                     )
                     .await?);
             }
@@ -243,6 +613,38 @@ pub(crate) async fn get(
                     .await?);
             }
 
+            // Users with a locked account can't authorize clients, even if they still
+            // have an active browser session
+            if maybe_session
+                .as_ref()
+                .is_some_and(|session| !session.user.is_valid())
+            {
+                return Ok(callback_destination
+                    .go(
+                        &templates,
+                        ClientError::new(
+                            ClientErrorCode::AccessDenied,
+                            "The user account is locked",
+                        ),
+                    )
+                    .await?);
+            }
+
+            // Clients can only restrict their tokens to the resources they are
+            // allowed to request
+            let resource = params.auth.resource.unwrap_or_default();
+            if !resource
+                .iter()
+                .all(|resource| client.allowed_resources.contains(resource))
+            {
+                return Ok(callback_destination
+                    .go(
+                        &templates,
+                        ClientError::from(ClientErrorCode::InvalidTarget),
+                    )
+                    .await?);
+            }
+
             let code: Option<AuthorizationCode> = if response_type.has_code() {
                 // Check if it is allowed to use this grant type
                 if !client.grant_types.contains(&GrantType::AuthorizationCode) {
@@ -254,6 +656,33 @@ pub(crate) async fn get(
                         .await?);
                 }
 
+                // Some clients must use PKCE, which is the case by default for public
+                // clients
+                if client.requires_pkce() && params.pkce.is_none() {
+                    return Ok(callback_destination
+                        .go(
+                            &templates,
+                            ClientError::from(ClientErrorCode::InvalidRequest),
+                        )
+                        .await?);
+                }
+
+                // Public clients may only use the S256 method, and the method must be
+                // allowed by the server configuration
+                if params.pkce.as_ref().is_some_and(|p| {
+                    (client.is_public() && p.code_challenge_method != PkceCodeChallengeMethod::S256)
+                        || !site_config
+                            .allowed_code_challenge_methods
+                            .contains(&p.code_challenge_method)
+                }) {
+                    return Ok(callback_destination
+                        .go(
+                            &templates,
+                            ClientError::from(ClientErrorCode::InvalidRequest),
+                        )
+                        .await?);
+                }
+
                 // 32 random alphanumeric characters, about 190bit of entropy
                 let code: String = (&mut rng)
                     .sample_iter(&Alphanumeric)
@@ -283,6 +712,10 @@ pub(crate) async fn get(
             };
 
             let requires_consent = prompt.contains(&Prompt::Consent);
+            // A `max_age` of 0 is equivalent to `prompt=login`
+            let max_age = params.auth.max_age.and_then(NonZeroU32::new);
+            let requires_reauth =
+                prompt.contains(&Prompt::Login) || params.auth.max_age == Some(0);
 
             let grant = repo
                 .oauth2_authorization_grant()
@@ -295,10 +728,13 @@ pub(crate) async fn get(
                     code,
                     params.auth.state.clone(),
                     params.auth.nonce,
-                    params.auth.max_age,
+                    max_age,
                     response_mode,
                     response_type.has_id_token(),
                     requires_consent,
+                    requires_reauth,
+                    params.auth.login_hint,
+                    resource,
                 )
                 .await?;
             let continue_grant = PostAuthAction::continue_grant(grant.id);
@@ -324,11 +760,10 @@ pub(crate) async fn get(
                         .into_response()
                 }
 
-                // Special case when we already have a session but prompt=login|select_account
-                Some(session)
-                    if prompt.contains(&Prompt::Login)
-                        || prompt.contains(&Prompt::SelectAccount) =>
-                {
+                // Special case when we already have a session but prompt=select_account.
+                // prompt=login is handled when completing the grant, through the
+                // `requires_reauth` flag
+                Some(session) if prompt.contains(&Prompt::SelectAccount) => {
                     // TODO: better pages here
                     repo.save().await?;
 
@@ -351,6 +786,7 @@ pub(crate) async fn get(
                         key_store,
                         policy,
                         &url_builder,
+                        &site_config,
                         grant,
                         &client,
                         &user_session,
@@ -367,11 +803,9 @@ pub(crate) async fn get(
                                 .await?
                         }
                         Err(GrantCompletionError::RequiresReauth) => {
+                            // The authentication is too old for the requested `max_age`
                             callback_destination
-                                .go(
-                                    &templates,
-                                    ClientError::from(ClientErrorCode::InteractionRequired),
-                                )
+                                .go(&templates, ClientError::from(ClientErrorCode::LoginRequired))
                                 .await?
                         }
                         Err(GrantCompletionError::PolicyViolation(_grant, _res)) => {
@@ -401,6 +835,7 @@ pub(crate) async fn get(
                         key_store,
                         policy,
                         &url_builder,
+                        &site_config,
                         grant,
                         &client,
                         &user_session,
@@ -454,3 +889,1313 @@ pub(crate) async fn get(
 
     Ok((cookie_jar, response).into_response())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use chrono::Duration;
+    use hyper::{header::LOCATION, Request, StatusCode};
+    use mas_axum_utils::SessionInfoExt;
+    use mas_data_model::{
+        AccessTokenFormat, BrowserSession, Password, RedirectUriMatching, SiteConfig,
+    };
+    use mas_iana::oauth::{
+        OAuthAuthorizationEndpointResponseType, OAuthClientAuthenticationMethod,
+        PkceCodeChallengeMethod,
+    };
+    use mas_jose::{claims::hash_token, jwt::Jwt};
+    use mas_router::{Route, SimpleRoute};
+    use mas_storage::{
+        oauth2::{
+            OAuth2AuthorizationGrantRepository, OAuth2SessionFilter, UpsertStaticClientParams,
+        },
+        Clock, RepositoryAccess,
+    };
+    use oauth2_types::{
+        registration::ClientRegistrationResponse,
+        scope::{Scope, OPENID},
+    };
+    use sqlx::PgPool;
+    use ulid::Ulid;
+    use url::Url;
+
+    use crate::test_utils::{
+        init_tracing, test_site_config, CookieHelper, RequestBuilderExt, ResponseExt, TestState,
+    };
+
+    /// A PKCE code challenge, using the S256 method
+    const CODE_CHALLENGE: &str = "bwWFMyPfdG9qreDhH2lmftFx_dFeLDalzcT1gb_j68g";
+
+    /// Provision a public client which can use the authorization code grant,
+    /// and return its client ID
+    async fn register_client(state: &TestState) -> String {
+        register_client_with_auth_method(state, "none").await
+    }
+
+    /// Provision a client using the given token endpoint authentication
+    /// method, which can use the authorization code grant, and return its
+    /// client ID
+    async fn register_client_with_auth_method(
+        state: &TestState,
+        token_endpoint_auth_method: &str,
+    ) -> String {
+        let request =
+            Request::post(mas_router::OAuth2RegistrationEndpoint::PATH).json(serde_json::json!({
+                "client_uri": "https://example.com/",
+                "redirect_uris": ["https://example.com/callback"],
+                "contacts": ["contact@example.com"],
+                "token_endpoint_auth_method": token_endpoint_auth_method,
+                "response_types": ["code"],
+                "grant_types": ["authorization_code"],
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::CREATED);
+
+        let ClientRegistrationResponse { client_id, .. } = response.json();
+        client_id
+    }
+
+    /// Provision a public client with the given response types and grant types,
+    /// and return its client ID
+    async fn register_client_with_response_types(
+        state: &TestState,
+        response_types: &[&str],
+        grant_types: &[&str],
+    ) -> String {
+        let request =
+            Request::post(mas_router::OAuth2RegistrationEndpoint::PATH).json(serde_json::json!({
+                "client_uri": "https://example.com/",
+                "redirect_uris": ["https://example.com/callback"],
+                "contacts": ["contact@example.com"],
+                "token_endpoint_auth_method": "none",
+                "response_types": response_types,
+                "grant_types": grant_types,
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::CREATED);
+
+        let ClientRegistrationResponse { client_id, .. } = response.json();
+        client_id
+    }
+
+    /// Extract the query parameters of the redirect in the response
+    fn redirect_params(response: &hyper::Response<String>) -> Vec<(String, String)> {
+        let location = response
+            .headers()
+            .get(LOCATION)
+            .expect("Missing Location header")
+            .to_str()
+            .unwrap();
+        let location = Url::parse(location).unwrap();
+        assert_eq!(
+            location.origin().ascii_serialization(),
+            "https://example.com"
+        );
+        assert_eq!(location.path(), "/callback");
+
+        location.query_pairs().into_owned().collect()
+    }
+
+    /// Extract the fragment parameters of the redirect in the response
+    fn fragment_params(response: &hyper::Response<String>) -> Vec<(String, String)> {
+        let location = response
+            .headers()
+            .get(LOCATION)
+            .expect("Missing Location header")
+            .to_str()
+            .unwrap();
+        let location = Url::parse(location).unwrap();
+        assert_eq!(
+            location.origin().ascii_serialization(),
+            "https://example.com"
+        );
+        assert_eq!(location.path(), "/callback");
+
+        let fragment = location.fragment().unwrap_or_default();
+        url::form_urlencoded::parse(fragment.as_bytes())
+            .into_owned()
+            .collect()
+    }
+
+    /// Provision a user with a freshly authenticated browser session, which
+    /// already consented to the `openid` scope for the given client, and save
+    /// its session in the cookies
+    async fn authenticated_session(
+        state: &TestState,
+        client_id: &str,
+        cookies: &CookieHelper,
+    ) -> (BrowserSession, Password) {
+        let (browser_session, password) =
+            authenticated_session_without_consent(state, cookies).await;
+
+        let mut repo = state.repository().await.unwrap();
+        let client = repo
+            .oauth2_client()
+            .find_by_client_id(client_id)
+            .await
+            .unwrap()
+            .unwrap();
+        repo.oauth2_consent()
+            .add(
+                &mut state.rng(),
+                &state.clock,
+                &client,
+                &browser_session.user,
+                &Scope::from_iter([OPENID]),
+            )
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        (browser_session, password)
+    }
+
+    /// Provision a user with a freshly authenticated browser session, and save
+    /// its session in the cookies
+    async fn authenticated_session_without_consent(
+        state: &TestState,
+        cookies: &CookieHelper,
+    ) -> (BrowserSession, Password) {
+        let mut repo = state.repository().await.unwrap();
+        let user = repo
+            .user()
+            .add(&mut state.rng(), &state.clock, "alice".to_owned())
+            .await
+            .unwrap();
+        let password = repo
+            .user_password()
+            .add(
+                &mut state.rng(),
+                &state.clock,
+                &user,
+                1,
+                "hashed".to_owned(),
+                None,
+            )
+            .await
+            .unwrap();
+        let browser_session = repo
+            .browser_session()
+            .add(&mut state.rng(), &state.clock, &user, None, None, None)
+            .await
+            .unwrap();
+        repo.browser_session()
+            .authenticate_with_password(&mut state.rng(), &state.clock, &browser_session, &password)
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        let cookie_jar = state.cookie_jar();
+        let cookie_jar = cookie_jar.set_session(&state.clock, &browser_session, false);
+        cookies.import(cookie_jar);
+
+        (browser_session, password)
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_prompt_none_login_required(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let client_id = register_client(&state).await;
+
+        // Without any session, prompt=none should immediately redirect back to the
+        // client with a `login_required` error
+        let request = Request::get(format!(
+            "{}?response_type=code&client_id={client_id}&redirect_uri=https://example.com/callback&scope=openid&state=abc&prompt=none&code_challenge_method=S256&code_challenge={CODE_CHALLENGE}",
+            mas_router::OAuth2AuthorizationEndpoint::PATH,
+        ))
+        .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::SEE_OTHER);
+
+        let params = redirect_params(&response);
+        assert!(params.contains(&("error".to_owned(), "login_required".to_owned())));
+        assert!(params.contains(&("state".to_owned(), "abc".to_owned())));
+        assert!(!params.iter().any(|(key, _)| key == "code"));
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_duplicate_parameters(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let client_id = register_client(&state).await;
+
+        // A repeated state is reported back to the client
+        let request = Request::get(format!(
+            "{}?response_type=code&client_id={client_id}&redirect_uri=https://example.com/callback&scope=openid&state=abc&state=def&code_challenge_method=S256&code_challenge={CODE_CHALLENGE}",
+            mas_router::OAuth2AuthorizationEndpoint::PATH,
+        ))
+        .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::SEE_OTHER);
+
+        let params = redirect_params(&response);
+        assert!(params.contains(&("error".to_owned(), "invalid_request".to_owned())));
+        assert!(!params.iter().any(|(key, _)| key == "code"));
+
+        // A repeated client_id can't be, as we don't know which client to reply to
+        let request = Request::get(format!(
+            "{}?response_type=code&client_id={client_id}&client_id={client_id}&redirect_uri=https://example.com/callback&scope=openid&state=abc&code_challenge_method=S256&code_challenge={CODE_CHALLENGE}",
+            mas_router::OAuth2AuthorizationEndpoint::PATH,
+        ))
+        .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        assert!(!response.headers().contains_key(LOCATION));
+        assert!(response.body().contains("invalid_request"));
+        assert!(!response.body().contains("https://example.com/callback"));
+
+        // Neither can a repeated redirect_uri, but the client is still known, so
+        // the user can go back to it through the first redirect_uri
+        let request = Request::get(format!(
+            "{}?response_type=code&client_id={client_id}&redirect_uri=https://example.com/callback&redirect_uri=https://evil.example.com/callback&scope=openid&state=abc&code_challenge_method=S256&code_challenge={CODE_CHALLENGE}",
+            mas_router::OAuth2AuthorizationEndpoint::PATH,
+        ))
+        .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        assert!(!response.headers().contains_key(LOCATION));
+        assert!(response.body().contains("invalid_request"));
+        assert!(response.body().contains(&client_id));
+        assert!(response
+            .body()
+            .contains(r#"href="https://example.com/callback""#));
+        assert!(!response.body().contains("state=abc"));
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_invalid_redirect_uri(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let client_id = register_client(&state).await;
+
+        // None of those should ever redirect, but show an error page instead
+        for redirect_uri in [
+            "https://example.com/other",
+            "https://example.com/callback/",
+            "https://example.com/callback%23fragment",
+            "https://evil.example.com/callback",
+        ] {
+            let request = Request::get(format!(
+                "{}?response_type=code&client_id={client_id}&redirect_uri={redirect_uri}&scope=openid&state=abc&code_challenge_method=S256&code_challenge={CODE_CHALLENGE}",
+                mas_router::OAuth2AuthorizationEndpoint::PATH,
+            ))
+            .empty();
+            let response = state.request(request).await;
+            response.assert_status(StatusCode::BAD_REQUEST);
+            assert!(!response.headers().contains_key(LOCATION));
+            assert!(response.body().contains("invalid_redirect_uri"));
+
+            // The page shows the client, with a link back to its only registered
+            // redirect URI, which doesn't carry any parameter
+            assert!(response.body().contains(&client_id));
+            assert!(response
+                .body()
+                .contains(r#"href="https://example.com/callback""#));
+            assert!(!response.body().contains("state=abc"));
+        }
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_request_and_request_uri(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let client_id = register_client(&state).await;
+
+        let request = Request::get(format!(
+            "{}?client_id={client_id}&request_uri=urn:ietf:params:oauth:request_uri:abc&request=eyJhbGciOiJub25lIn0.e30.",
+            mas_router::OAuth2AuthorizationEndpoint::PATH,
+        ))
+        .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_prompt_none_silent_success(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let client_id = register_client(&state).await;
+        let cookies = CookieHelper::new();
+        authenticated_session(&state, &client_id, &cookies).await;
+
+        // With an active session, prompt=none should complete the grant without any
+        // interaction and redirect back to the client with a code
+        let request = Request::get(format!(
+            "{}?response_type=code&client_id={client_id}&redirect_uri=https://example.com/callback&scope=openid&state=abc&prompt=none&code_challenge_method=S256&code_challenge={CODE_CHALLENGE}",
+            mas_router::OAuth2AuthorizationEndpoint::PATH,
+        ))
+        .empty();
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::SEE_OTHER);
+
+        let params = redirect_params(&response);
+        assert!(!params.iter().any(|(key, _)| key == "error"));
+        assert!(params.contains(&("state".to_owned(), "abc".to_owned())));
+        assert!(params.iter().any(|(key, _)| key == "code"));
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_prompt_none_consent_required(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let client_id = register_client(&state).await;
+        let cookies = CookieHelper::new();
+        authenticated_session_without_consent(&state, &cookies).await;
+
+        // With an active session which didn't consent yet, prompt=none should
+        // redirect back to the client with a `consent_required` error
+        let request = Request::get(format!(
+            "{}?response_type=code&client_id={client_id}&redirect_uri=https://example.com/callback&scope=openid&state=abc&prompt=none&code_challenge_method=S256&code_challenge={CODE_CHALLENGE}",
+            mas_router::OAuth2AuthorizationEndpoint::PATH,
+        ))
+        .empty();
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::SEE_OTHER);
+
+        let params = redirect_params(&response);
+        assert!(params.contains(&("error".to_owned(), "consent_required".to_owned())));
+        assert!(params.contains(&("state".to_owned(), "abc".to_owned())));
+        assert!(!params.iter().any(|(key, _)| key == "code"));
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_trusted_client_skips_consent(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+
+        // Provision a trusted static client
+        let mut repo = state.repository().await.unwrap();
+        let client_id = Ulid::from_datetime_with_source(state.clock.now().into(), &mut state.rng());
+        let client = repo
+            .oauth2_client()
+            .upsert_static(UpsertStaticClientParams {
+                client_id,
+                client_auth_method: OAuthClientAuthenticationMethod::ClientSecretBasic,
+                encrypted_client_secret: None,
+                jwks: None,
+                jwks_uri: None,
+                redirect_uris: vec![Url::parse("https://example.com/callback").unwrap()],
+                redirect_uri_matching: RedirectUriMatching::Exact,
+                response_types: vec![OAuthAuthorizationEndpointResponseType::Code],
+                allow_token_exchange: false,
+                allow_client_credentials: false,
+                client_credentials_scope: None,
+                allowed_resources: Vec::new(),
+                require_signed_request_object: false,
+                require_pkce: None,
+                access_token_ttl: None,
+                refresh_token_ttl: None,
+                access_token_format: AccessTokenFormat::Opaque,
+                introspection_endpoint_auth_method: None,
+                post_logout_redirect_uris: Vec::new(),
+                backchannel_logout_uri: None,
+                backchannel_logout_session_required: false,
+                trusted: true,
+            })
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+        let client_id = client.client_id;
+
+        let cookies = CookieHelper::new();
+        authenticated_session_without_consent(&state, &cookies).await;
+
+        // The user never consented, but the grant completes without showing
+        // them the consent screen
+        let request = Request::get(format!(
+            "{}?response_type=code&client_id={client_id}&redirect_uri=https://example.com/callback&scope=openid&state=abc&prompt=none",
+            mas_router::OAuth2AuthorizationEndpoint::PATH,
+        ))
+        .empty();
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::SEE_OTHER);
+
+        let params = redirect_params(&response);
+        assert!(!params.iter().any(|(key, _)| key == "error"));
+        assert!(params.contains(&("state".to_owned(), "abc".to_owned())));
+        assert!(params.iter().any(|(key, _)| key == "code"));
+
+        // Unless the client explicitly asks for the consent screen
+        let request = Request::get(format!(
+            "{}?response_type=code&client_id={client_id}&redirect_uri=https://example.com/callback&scope=openid&state=abc&prompt=consent",
+            mas_router::OAuth2AuthorizationEndpoint::PATH,
+        ))
+        .empty();
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::SEE_OTHER);
+
+        let location = response
+            .headers()
+            .get(LOCATION)
+            .expect("Missing Location header")
+            .to_str()
+            .unwrap();
+        assert!(location.contains("/consent/"));
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_prompt_none_max_age(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let client_id = register_client(&state).await;
+        let cookies = CookieHelper::new();
+        authenticated_session(&state, &client_id, &cookies).await;
+
+        state.clock.advance(Duration::minutes(10));
+
+        // The authentication is recent enough for this max_age, so the grant
+        // completes silently
+        let request = Request::get(format!(
+            "{}?response_type=code&client_id={client_id}&redirect_uri=https://example.com/callback&scope=openid&state=abc&prompt=none&max_age=3600&code_challenge_method=S256&code_challenge={CODE_CHALLENGE}",
+            mas_router::OAuth2AuthorizationEndpoint::PATH,
+        ))
+        .empty();
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::SEE_OTHER);
+
+        let params = redirect_params(&response);
+        assert!(!params.iter().any(|(key, _)| key == "error"));
+        assert!(params.iter().any(|(key, _)| key == "code"));
+
+        // But it is too old for this one, and prompt=none can't ask the user to
+        // log in again, so we get back a `login_required` error
+        let request = Request::get(format!(
+            "{}?response_type=code&client_id={client_id}&redirect_uri=https://example.com/callback&scope=openid&state=abc&prompt=none&max_age=60&code_challenge_method=S256&code_challenge={CODE_CHALLENGE}",
+            mas_router::OAuth2AuthorizationEndpoint::PATH,
+        ))
+        .empty();
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::SEE_OTHER);
+
+        let params = redirect_params(&response);
+        assert!(params.contains(&("error".to_owned(), "login_required".to_owned())));
+        assert!(params.contains(&("state".to_owned(), "abc".to_owned())));
+        assert!(!params.iter().any(|(key, _)| key == "code"));
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_max_age_reauth(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let client_id = register_client(&state).await;
+        let cookies = CookieHelper::new();
+        authenticated_session(&state, &client_id, &cookies).await;
+
+        // Without prompt=none, an authentication older than max_age sends the user
+        // to the reauth page, carrying the grant along
+        state.clock.advance(Duration::minutes(10));
+        let request = Request::get(format!(
+            "{}?response_type=code&client_id={client_id}&redirect_uri=https://example.com/callback&scope=openid&state=abc&max_age=60&code_challenge_method=S256&code_challenge={CODE_CHALLENGE}",
+            mas_router::OAuth2AuthorizationEndpoint::PATH,
+        ))
+        .empty();
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::SEE_OTHER);
+
+        let location = response.headers().get(LOCATION).unwrap().to_str().unwrap();
+        let location = Url::parse("https://example.com/")
+            .unwrap()
+            .join(location)
+            .unwrap();
+        assert_eq!(location.path(), mas_router::Reauth::route());
+        assert!(location.query_pairs().any(|(key, _)| key == "id"));
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_max_age_zero(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let client_id = register_client(&state).await;
+        let cookies = CookieHelper::new();
+        authenticated_session(&state, &client_id, &cookies).await;
+
+        // max_age=0 is equivalent to prompt=login, even if the user just
+        // authenticated
+        let request = Request::get(format!(
+            "{}?response_type=code&client_id={client_id}&redirect_uri=https://example.com/callback&scope=openid&state=abc&max_age=0&code_challenge_method=S256&code_challenge={CODE_CHALLENGE}",
+            mas_router::OAuth2AuthorizationEndpoint::PATH,
+        ))
+        .empty();
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::SEE_OTHER);
+
+        let location = response.headers().get(LOCATION).unwrap().to_str().unwrap();
+        let location = Url::parse("https://example.com/")
+            .unwrap()
+            .join(location)
+            .unwrap();
+        assert_eq!(location.path(), mas_router::Reauth::route());
+
+        // With prompt=none, the client gets a login_required error instead
+        let request = Request::get(format!(
+            "{}?response_type=code&client_id={client_id}&redirect_uri=https://example.com/callback&scope=openid&state=abc&prompt=none&max_age=0&code_challenge_method=S256&code_challenge={CODE_CHALLENGE}",
+            mas_router::OAuth2AuthorizationEndpoint::PATH,
+        ))
+        .empty();
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::SEE_OTHER);
+        let params = redirect_params(&response);
+        assert!(params.contains(&("error".to_owned(), "login_required".to_owned())));
+        assert!(!params.iter().any(|(key, _)| key == "code"));
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_hybrid_id_token(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+
+        // Provision a client which can use the hybrid flow
+        let request =
+            Request::post(mas_router::OAuth2RegistrationEndpoint::PATH).json(serde_json::json!({
+                "client_uri": "https://example.com/",
+                "redirect_uris": ["https://example.com/callback"],
+                "contacts": ["contact@example.com"],
+                "token_endpoint_auth_method": "none",
+                "response_types": ["code id_token"],
+                "grant_types": ["authorization_code", "implicit"],
+            }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::CREATED);
+        let ClientRegistrationResponse { client_id, .. } = response.json();
+
+        let cookies = CookieHelper::new();
+        let (browser_session, _password) =
+            authenticated_session(&state, &client_id, &cookies).await;
+        let mut repo = state.repository().await.unwrap();
+        let authentication = repo
+            .browser_session()
+            .get_last_authentication(&browser_session)
+            .await
+            .unwrap()
+            .unwrap();
+        repo.cancel().await.unwrap();
+
+        // Without a nonce, the request is rejected
+        let request = Request::get(format!(
+            "{}?response_type=code+id_token&client_id={client_id}&redirect_uri=https://example.com/callback&scope=openid&state=abc&code_challenge_method=S256&code_challenge={CODE_CHALLENGE}",
+            mas_router::OAuth2AuthorizationEndpoint::PATH,
+        ))
+        .empty();
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::SEE_OTHER);
+        let params = fragment_params(&response);
+        assert!(params.contains(&("error".to_owned(), "invalid_request".to_owned())));
+        assert!(!params.iter().any(|(key, _)| key == "id_token"));
+
+        // With a nonce, both a code and an ID token are returned in the fragment
+        let request = Request::get(format!(
+            "{}?response_type=code+id_token&client_id={client_id}&redirect_uri=https://example.com/callback&scope=openid&state=abc&nonce=xyz&code_challenge_method=S256&code_challenge={CODE_CHALLENGE}",
+            mas_router::OAuth2AuthorizationEndpoint::PATH,
+        ))
+        .empty();
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::SEE_OTHER);
+        let params = fragment_params(&response);
+        assert!(!params.iter().any(|(key, _)| key == "error"));
+        let param = |name: &str| {
+            params
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.clone())
+                .unwrap_or_else(|| panic!("missing {name} parameter"))
+        };
+        let code = param("code");
+        let id_token = param("id_token");
+
+        // The ID token is signed with one of our keys
+        let id_token: Jwt<'_, HashMap<String, serde_json::Value>> =
+            Jwt::try_from(id_token.as_str()).unwrap();
+        id_token
+            .verify_with_jwks(&state.key_store.public_jwks())
+            .unwrap();
+
+        let alg = id_token.header().alg().clone();
+        let claims = id_token.payload();
+        assert_eq!(claims["iss"], state.url_builder.oidc_issuer().as_str());
+        assert_eq!(claims["sub"], browser_session.user.sub.as_str());
+        assert_eq!(claims["aud"], client_id.as_str());
+        assert_eq!(claims["nonce"], "xyz");
+        assert_eq!(claims["auth_time"], authentication.created_at.timestamp());
+        assert_eq!(claims["c_hash"], hash_token(&alg, &code).unwrap().as_str());
+        // No access token is returned from the authorization endpoint
+        assert!(claims.get("at_hash").is_none());
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_locked_user(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let client_id = register_client(&state).await;
+        let cookies = CookieHelper::new();
+        let (browser_session, _password) =
+            authenticated_session(&state, &client_id, &cookies).await;
+
+        // Lock the user, while keeping its browser session active
+        let mut repo = state.repository().await.unwrap();
+        repo.user()
+            .lock(&state.clock, browser_session.user)
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        // The grant should not complete and redirect back to the client with an
+        // `access_denied` error
+        let request = Request::get(format!(
+            "{}?response_type=code&client_id={client_id}&redirect_uri=https://example.com/callback&scope=openid&state=abc&code_challenge_method=S256&code_challenge={CODE_CHALLENGE}",
+            mas_router::OAuth2AuthorizationEndpoint::PATH,
+        ))
+        .empty();
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::SEE_OTHER);
+
+        let params = redirect_params(&response);
+        assert!(params.contains(&("error".to_owned(), "access_denied".to_owned())));
+        assert!(params.contains(&(
+            "error_description".to_owned(),
+            "The user account is locked".to_owned()
+        )));
+        assert!(!params.iter().any(|(key, _)| key == "code"));
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_response_type_none(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let client_id =
+            register_client_with_response_types(&state, &["code", "none"], &["authorization_code"])
+                .await;
+        let cookies = CookieHelper::new();
+        authenticated_session(&state, &client_id, &cookies).await;
+
+        // With the "none" response type, the grant completes and redirects back to
+        // the client in the query, with only the state
+        let request = Request::get(format!(
+            "{}?response_type=none&client_id={client_id}&redirect_uri=https://example.com/callback&scope=openid&state=abc&prompt=none",
+            mas_router::OAuth2AuthorizationEndpoint::PATH,
+        ))
+        .empty();
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::SEE_OTHER);
+
+        let params = redirect_params(&response);
+        assert_eq!(params, vec![("state".to_owned(), "abc".to_owned())]);
+
+        // No credentials were issued, so no usable session was left behind
+        let mut repo = state.repository().await.unwrap();
+        let active_sessions = repo
+            .oauth2_session()
+            .count(OAuth2SessionFilter::new().active_only())
+            .await
+            .unwrap();
+        assert_eq!(active_sessions, 0);
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_web_message_response_mode(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let client_id = register_client(&state).await;
+
+        // With the web_message response mode, the error is posted to the origin of
+        // the redirect URI by an HTML page instead of a redirect
+        let request = Request::get(format!(
+            "{}?response_type=code&response_mode=web_message&client_id={client_id}&redirect_uri=https://example.com/callback&scope=openid&state=abc&prompt=none&code_challenge_method=S256&code_challenge={CODE_CHALLENGE}",
+            mas_router::OAuth2AuthorizationEndpoint::PATH,
+        ))
+        .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+
+        let body = response.body();
+        assert!(body.contains(r#""https://example.com""#));
+        assert!(body.contains(r#""error":"login_required""#));
+        assert!(body.contains(r#""state":"abc""#));
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_form_post_error_response(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let client_id = register_client(&state).await;
+
+        // With the form_post response mode, the error is sent back through an
+        // auto-submitting HTML form targeting the redirect URI
+        let request = Request::get(format!(
+            "{}?response_type=code&response_mode=form_post&client_id={client_id}&redirect_uri=https://example.com/callback&scope=openid&state=abc&prompt=none&code_challenge_method=S256&code_challenge={CODE_CHALLENGE}",
+            mas_router::OAuth2AuthorizationEndpoint::PATH,
+        ))
+        .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+
+        let body = response.body();
+        assert!(body.contains(r#"<form method="post" action="https:"#));
+        assert!(body.contains("example.com"));
+        assert!(body.contains(r#"name="error" value="login_required""#));
+        assert!(body.contains(r#"name="state" value="abc""#));
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_fragment_error_response(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let client_id = register_client(&state).await;
+
+        // An explicit fragment response mode is honoured for errors
+        let request = Request::get(format!(
+            "{}?response_type=code&response_mode=fragment&client_id={client_id}&redirect_uri=https://example.com/callback&scope=openid&state=abc&prompt=none&code_challenge_method=S256&code_challenge={CODE_CHALLENGE}",
+            mas_router::OAuth2AuthorizationEndpoint::PATH,
+        ))
+        .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::SEE_OTHER);
+        let params = fragment_params(&response);
+        assert!(params.contains(&("error".to_owned(), "login_required".to_owned())));
+        assert!(params.contains(&("state".to_owned(), "abc".to_owned())));
+        assert!(redirect_params(&response).is_empty());
+
+        // The query response mode isn't allowed when requesting an ID token: the
+        // error is sent in the fragment, which is the default for this response type
+        let request = Request::get(format!(
+            "{}?response_type=code+id_token&response_mode=query&client_id={client_id}&redirect_uri=https://example.com/callback&scope=openid&state=abc&nonce=xyz&code_challenge_method=S256&code_challenge={CODE_CHALLENGE}",
+            mas_router::OAuth2AuthorizationEndpoint::PATH,
+        ))
+        .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::SEE_OTHER);
+        let params = fragment_params(&response);
+        assert!(params.contains(&("error".to_owned(), "invalid_request".to_owned())));
+        assert!(params.contains(&("state".to_owned(), "abc".to_owned())));
+        assert!(redirect_params(&response).is_empty());
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_jwt_response_mode(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let client_id = register_client(&state).await;
+
+        // With the jwt response mode and the code response type, the parameters are
+        // signed in a JWT sent in the query
+        let request = Request::get(format!(
+            "{}?response_type=code&response_mode=jwt&client_id={client_id}&redirect_uri=https://example.com/callback&scope=openid&state=abc&prompt=none&code_challenge_method=S256&code_challenge={CODE_CHALLENGE}",
+            mas_router::OAuth2AuthorizationEndpoint::PATH,
+        ))
+        .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::SEE_OTHER);
+        let params = redirect_params(&response);
+        assert_eq!(params.len(), 1);
+        let (name, response) = &params[0];
+        assert_eq!(name, "response");
+
+        let jwt: Jwt<'_, HashMap<String, serde_json::Value>> =
+            Jwt::try_from(response.as_str()).unwrap();
+        jwt.verify_with_jwks(&state.key_store.public_jwks())
+            .unwrap();
+        let claims = jwt.payload();
+        assert_eq!(claims["iss"], state.url_builder.oidc_issuer().as_str());
+        assert_eq!(claims["aud"], client_id);
+        assert!(claims["exp"].is_i64());
+        assert_eq!(claims["error"], "login_required");
+        assert_eq!(claims["state"], "abc");
+
+        // The JWT can also be sent through an auto-submitting form
+        let request = Request::get(format!(
+            "{}?response_type=code&response_mode=form_post.jwt&client_id={client_id}&redirect_uri=https://example.com/callback&scope=openid&state=abc&prompt=none&code_challenge_method=S256&code_challenge={CODE_CHALLENGE}",
+            mas_router::OAuth2AuthorizationEndpoint::PATH,
+        ))
+        .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+
+        let body = response.body();
+        assert!(body.contains(r#"name="response" value=""#));
+        assert!(!body.contains(r#"name="state""#));
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_prompt_login_forces_reauth(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let client_id = register_client(&state).await;
+        let cookies = CookieHelper::new();
+        let (browser_session, password) = authenticated_session(&state, &client_id, &cookies).await;
+
+        // The authentication is recent enough, but prompt=login should still send
+        // the user to the reauth page
+        state.clock.advance(Duration::minutes(1));
+        let request = Request::get(format!(
+            "{}?response_type=code&client_id={client_id}&redirect_uri=https://example.com/callback&scope=openid&state=abc&prompt=login&code_challenge_method=S256&code_challenge={CODE_CHALLENGE}",
+            mas_router::OAuth2AuthorizationEndpoint::PATH,
+        ))
+        .empty();
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::SEE_OTHER);
+
+        let location = response.headers().get(LOCATION).unwrap().to_str().unwrap();
+        let location = Url::parse("https://example.com/")
+            .unwrap()
+            .join(location)
+            .unwrap();
+        assert_eq!(location.path(), mas_router::Reauth::route());
+        let grant_id = location
+            .query_pairs()
+            .find(|(key, _)| key == "id")
+            .map(|(_, value)| value.parse().unwrap())
+            .expect("missing grant ID");
+        let continue_grant = mas_router::ContinueAuthorizationGrant(grant_id);
+
+        // Without reauthenticating, continuing the grant sends back to the reauth
+        // page
+        let request = Request::get(continue_grant.path_and_query()).empty();
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::SEE_OTHER);
+        let location = response.headers().get(LOCATION).unwrap().to_str().unwrap();
+        assert!(location.starts_with(mas_router::Reauth::route()));
+
+        // Once reauthenticated, the grant completes, and the reauth requirement
+        // is recorded as satisfied
+        state.clock.advance(Duration::minutes(1));
+        let mut repo = state.repository().await.unwrap();
+        repo.browser_session()
+            .authenticate_with_password(&mut state.rng(), &state.clock, &browser_session, &password)
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        let request = Request::get(continue_grant.path_and_query()).empty();
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::SEE_OTHER);
+
+        let params = redirect_params(&response);
+        assert!(!params.iter().any(|(key, _)| key == "error"));
+        assert!(params.iter().any(|(key, _)| key == "code"));
+
+        let mut repo = state.repository().await.unwrap();
+        let grant = repo
+            .oauth2_authorization_grant()
+            .lookup(grant_id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(!grant.requires_reauth);
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_pkce_public_client(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let client_id = register_client(&state).await;
+
+        // With the S256 method, the user is asked to log in
+        let request = Request::get(format!(
+            "{}?response_type=code&client_id={client_id}&redirect_uri=https://example.com/callback&scope=openid&state=abc&code_challenge_method=S256&code_challenge={CODE_CHALLENGE}",
+            mas_router::OAuth2AuthorizationEndpoint::PATH,
+        ))
+        .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::SEE_OTHER);
+        let location = response.headers().get(LOCATION).unwrap().to_str().unwrap();
+        assert!(location.starts_with(mas_router::Login::route()));
+
+        // The plain method is rejected
+        let request = Request::get(format!(
+            "{}?response_type=code&client_id={client_id}&redirect_uri=https://example.com/callback&scope=openid&state=abc&code_challenge_method=plain&code_challenge={CODE_CHALLENGE}",
+            mas_router::OAuth2AuthorizationEndpoint::PATH,
+        ))
+        .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::SEE_OTHER);
+        let params = redirect_params(&response);
+        assert!(params.contains(&("error".to_owned(), "invalid_request".to_owned())));
+        assert!(params.contains(&("state".to_owned(), "abc".to_owned())));
+
+        // Omitting PKCE is rejected
+        let request = Request::get(format!(
+            "{}?response_type=code&client_id={client_id}&redirect_uri=https://example.com/callback&scope=openid&state=abc",
+            mas_router::OAuth2AuthorizationEndpoint::PATH,
+        ))
+        .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::SEE_OTHER);
+        let params = redirect_params(&response);
+        assert!(params.contains(&("error".to_owned(), "invalid_request".to_owned())));
+        assert!(params.contains(&("state".to_owned(), "abc".to_owned())));
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_pkce_confidential_client(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let client_id = register_client_with_auth_method(&state, "client_secret_basic").await;
+
+        // Confidential clients can still omit PKCE
+        let request = Request::get(format!(
+            "{}?response_type=code&client_id={client_id}&redirect_uri=https://example.com/callback&scope=openid&state=abc",
+            mas_router::OAuth2AuthorizationEndpoint::PATH,
+        ))
+        .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::SEE_OTHER);
+        let location = response.headers().get(LOCATION).unwrap().to_str().unwrap();
+        assert!(location.starts_with(mas_router::Login::route()));
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_login_hint(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let client_id = register_client_with_auth_method(&state, "client_secret_basic").await;
+
+        // Start an authorization request with the given login hint, and load the
+        // login page it redirects to
+        let login_page = |login_hint: &'static str| {
+            let state = &state;
+            let client_id = &client_id;
+            async move {
+                let request = Request::get(format!(
+                    "{}?response_type=code&client_id={client_id}&redirect_uri=https://example.com/callback&scope=openid&state=abc&login_hint={login_hint}",
+                    mas_router::OAuth2AuthorizationEndpoint::PATH,
+                ))
+                .empty();
+                let response = state.request(request).await;
+                response.assert_status(StatusCode::SEE_OTHER);
+                let location = response.headers().get(LOCATION).unwrap().to_str().unwrap();
+                assert!(location.starts_with(mas_router::Login::route()));
+
+                let response = state.request(Request::get(location).empty()).await;
+                response.assert_status(StatusCode::OK);
+                response.into_body()
+            }
+        };
+
+        // A hint for a user of this homeserver pre-fills the username
+        let body = login_page("mxid:@alice:example.com").await;
+        assert!(body.contains(r#"value="alice""#));
+
+        // Hints for other homeservers, or malformed ones, are ignored
+        let body = login_page("mxid:@alice:example.org").await;
+        assert!(!body.contains(r#"value="alice""#));
+        let body = login_page("alice").await;
+        assert!(!body.contains(r#"value="alice""#));
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_resource_indicators(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+
+        // Provision a static client which can only request tokens for one resource
+        let mut repo = state.repository().await.unwrap();
+        let client_id = Ulid::from_datetime_with_source(state.clock.now().into(), &mut state.rng());
+        let client = repo
+            .oauth2_client()
+            .upsert_static(UpsertStaticClientParams {
+                client_id,
+                client_auth_method: OAuthClientAuthenticationMethod::ClientSecretBasic,
+                encrypted_client_secret: None,
+                jwks: None,
+                jwks_uri: None,
+                redirect_uris: vec![Url::parse("https://example.com/callback").unwrap()],
+                redirect_uri_matching: RedirectUriMatching::Exact,
+                response_types: vec![OAuthAuthorizationEndpointResponseType::Code],
+                allow_token_exchange: false,
+                allow_client_credentials: false,
+                client_credentials_scope: None,
+                allowed_resources: vec![Url::parse("https://a.example.com/").unwrap()],
+                require_signed_request_object: false,
+                require_pkce: None,
+                access_token_ttl: None,
+                refresh_token_ttl: None,
+                access_token_format: AccessTokenFormat::Opaque,
+                introspection_endpoint_auth_method: None,
+                post_logout_redirect_uris: Vec::new(),
+                backchannel_logout_uri: None,
+                backchannel_logout_session_required: false,
+                trusted: false,
+            })
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+        let client_id = client.client_id;
+
+        // Asking for another resource is refused
+        let request = Request::get(format!(
+            "{}?response_type=code&client_id={client_id}&redirect_uri=https://example.com/callback&scope=openid&state=abc&resource=https://b.example.com/",
+            mas_router::OAuth2AuthorizationEndpoint::PATH,
+        ))
+        .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::SEE_OTHER);
+        let params = redirect_params(&response);
+        assert!(params.contains(&("error".to_owned(), "invalid_target".to_owned())));
+        assert!(params.contains(&("state".to_owned(), "abc".to_owned())));
+
+        // Even alongside an allowed one
+        let request = Request::get(format!(
+            "{}?response_type=code&client_id={client_id}&redirect_uri=https://example.com/callback&scope=openid&state=abc&resource=https://a.example.com/&resource=https://b.example.com/",
+            mas_router::OAuth2AuthorizationEndpoint::PATH,
+        ))
+        .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::SEE_OTHER);
+        let params = redirect_params(&response);
+        assert!(params.contains(&("error".to_owned(), "invalid_target".to_owned())));
+
+        // The allowed resource goes through to the login page
+        let request = Request::get(format!(
+            "{}?response_type=code&client_id={client_id}&redirect_uri=https://example.com/callback&scope=openid&state=abc&resource=https://a.example.com/",
+            mas_router::OAuth2AuthorizationEndpoint::PATH,
+        ))
+        .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::SEE_OTHER);
+        let location = response.headers().get(LOCATION).unwrap().to_str().unwrap();
+        assert!(location.starts_with(mas_router::Login::route()));
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_pkce_required_by_client(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+
+        // Provision a confidential client which explicitly requires PKCE
+        let mut repo = state.repository().await.unwrap();
+        let client_id = Ulid::from_datetime_with_source(state.clock.now().into(), &mut state.rng());
+        let client = repo
+            .oauth2_client()
+            .upsert_static(UpsertStaticClientParams {
+                client_id,
+                client_auth_method: OAuthClientAuthenticationMethod::ClientSecretBasic,
+                encrypted_client_secret: None,
+                jwks: None,
+                jwks_uri: None,
+                redirect_uris: vec![Url::parse("https://example.com/callback").unwrap()],
+                redirect_uri_matching: RedirectUriMatching::Exact,
+                response_types: vec![OAuthAuthorizationEndpointResponseType::Code],
+                allow_token_exchange: false,
+                allow_client_credentials: false,
+                client_credentials_scope: None,
+                allowed_resources: Vec::new(),
+                require_signed_request_object: false,
+                require_pkce: Some(true),
+                access_token_ttl: None,
+                refresh_token_ttl: None,
+                access_token_format: AccessTokenFormat::Opaque,
+                introspection_endpoint_auth_method: None,
+                post_logout_redirect_uris: Vec::new(),
+                backchannel_logout_uri: None,
+                backchannel_logout_session_required: false,
+                trusted: false,
+            })
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+        let client_id = client.client_id;
+
+        // Omitting PKCE is rejected
+        let request = Request::get(format!(
+            "{}?response_type=code&client_id={client_id}&redirect_uri=https://example.com/callback&scope=openid&state=abc",
+            mas_router::OAuth2AuthorizationEndpoint::PATH,
+        ))
+        .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::SEE_OTHER);
+        let params = redirect_params(&response);
+        assert!(params.contains(&("error".to_owned(), "invalid_request".to_owned())));
+        assert!(params.contains(&("state".to_owned(), "abc".to_owned())));
+
+        // Confidential clients can still use the plain method
+        let request = Request::get(format!(
+            "{}?response_type=code&client_id={client_id}&redirect_uri=https://example.com/callback&scope=openid&state=abc&code_challenge_method=plain&code_challenge={CODE_CHALLENGE}",
+            mas_router::OAuth2AuthorizationEndpoint::PATH,
+        ))
+        .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::SEE_OTHER);
+        let location = response.headers().get(LOCATION).unwrap().to_str().unwrap();
+        assert!(location.starts_with(mas_router::Login::route()));
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_pkce_allowed_methods(pool: PgPool) {
+        init_tracing();
+        let site_config = SiteConfig {
+            allowed_code_challenge_methods: vec![PkceCodeChallengeMethod::S256],
+            ..test_site_config()
+        };
+        let state = TestState::from_pool_with_site_config(pool, site_config)
+            .await
+            .unwrap();
+        let client_id = register_client_with_auth_method(&state, "client_secret_basic").await;
+
+        // The plain method is rejected, even for confidential clients
+        let request = Request::get(format!(
+            "{}?response_type=code&client_id={client_id}&redirect_uri=https://example.com/callback&scope=openid&state=abc&code_challenge_method=plain&code_challenge={CODE_CHALLENGE}",
+            mas_router::OAuth2AuthorizationEndpoint::PATH,
+        ))
+        .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::SEE_OTHER);
+        let params = redirect_params(&response);
+        assert!(params.contains(&("error".to_owned(), "invalid_request".to_owned())));
+        assert!(params.contains(&("state".to_owned(), "abc".to_owned())));
+
+        // The S256 method is accepted
+        let request = Request::get(format!(
+            "{}?response_type=code&client_id={client_id}&redirect_uri=https://example.com/callback&scope=openid&state=abc&code_challenge_method=S256&code_challenge={CODE_CHALLENGE}",
+            mas_router::OAuth2AuthorizationEndpoint::PATH,
+        ))
+        .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::SEE_OTHER);
+        let location = response.headers().get(LOCATION).unwrap().to_str().unwrap();
+        assert!(location.starts_with(mas_router::Login::route()));
+
+        // The discovery document only advertises the allowed methods
+        let request = Request::get("/.well-known/openid-configuration").empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let metadata: serde_json::Value = response.json();
+        assert_eq!(
+            metadata["code_challenge_methods_supported"],
+            serde_json::json!(["S256"])
+        );
+    }
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_response_type_allow_list(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+
+        // This client can use the implicit grant, but didn't register the hybrid
+        // response type
+        let client_id = register_client_with_response_types(
+            &state,
+            &["code"],
+            &["authorization_code", "implicit"],
+        )
+        .await;
+
+        let request = Request::get(format!(
+            "{}?response_type=code+id_token&client_id={client_id}&redirect_uri=https://example.com/callback&scope=openid&state=abc&nonce=xyz&code_challenge_method=S256&code_challenge={CODE_CHALLENGE}",
+            mas_router::OAuth2AuthorizationEndpoint::PATH,
+        ))
+        .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::SEE_OTHER);
+        let params = fragment_params(&response);
+        assert!(params.contains(&("error".to_owned(), "unauthorized_client".to_owned())));
+        assert!(params.contains(&("state".to_owned(), "abc".to_owned())));
+
+        // This client registered only the hybrid response type, which doesn't allow
+        // using either of its parts alone
+        let client_id = register_client_with_response_types(
+            &state,
+            &["code id_token"],
+            &["authorization_code", "implicit"],
+        )
+        .await;
+
+        let request = Request::get(format!(
+            "{}?response_type=code&client_id={client_id}&redirect_uri=https://example.com/callback&scope=openid&state=abc&code_challenge_method=S256&code_challenge={CODE_CHALLENGE}",
+            mas_router::OAuth2AuthorizationEndpoint::PATH,
+        ))
+        .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::SEE_OTHER);
+        let params = redirect_params(&response);
+        assert!(params.contains(&("error".to_owned(), "unauthorized_client".to_owned())));
+
+        let request = Request::get(format!(
+            "{}?response_type=id_token&client_id={client_id}&redirect_uri=https://example.com/callback&scope=openid&state=abc&nonce=xyz",
+            mas_router::OAuth2AuthorizationEndpoint::PATH,
+        ))
+        .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::SEE_OTHER);
+        let params = fragment_params(&response);
+        assert!(params.contains(&("error".to_owned(), "unauthorized_client".to_owned())));
+
+        // The order of the values in the response type doesn't matter
+        let request = Request::get(format!(
+            "{}?response_type=id_token+code&client_id={client_id}&redirect_uri=https://example.com/callback&scope=openid&state=abc&nonce=xyz&code_challenge_method=S256&code_challenge={CODE_CHALLENGE}",
+            mas_router::OAuth2AuthorizationEndpoint::PATH,
+        ))
+        .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::SEE_OTHER);
+        let location = response.headers().get(LOCATION).unwrap().to_str().unwrap();
+        assert!(location.starts_with(mas_router::Login::route()));
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_disable_implicit_flow(pool: PgPool) {
+        init_tracing();
+        let site_config = SiteConfig {
+            disable_implicit_flow: true,
+            ..test_site_config()
+        };
+        let state = TestState::from_pool_with_site_config(pool, site_config)
+            .await
+            .unwrap();
+        let client_id = register_client_with_response_types(
+            &state,
+            &["code", "code id_token"],
+            &["authorization_code", "implicit"],
+        )
+        .await;
+
+        // The hybrid flow is rejected, even though the client is allowed to use it
+        let request = Request::get(format!(
+            "{}?response_type=code+id_token&client_id={client_id}&redirect_uri=https://example.com/callback&scope=openid&state=abc&nonce=xyz&code_challenge_method=S256&code_challenge={CODE_CHALLENGE}",
+            mas_router::OAuth2AuthorizationEndpoint::PATH,
+        ))
+        .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::SEE_OTHER);
+        let params = fragment_params(&response);
+        assert!(params.contains(&("error".to_owned(), "unsupported_response_type".to_owned())));
+
+        // The authorization code flow still works
+        let request = Request::get(format!(
+            "{}?response_type=code&client_id={client_id}&redirect_uri=https://example.com/callback&scope=openid&state=abc&code_challenge_method=S256&code_challenge={CODE_CHALLENGE}",
+            mas_router::OAuth2AuthorizationEndpoint::PATH,
+        ))
+        .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::SEE_OTHER);
+        let location = response.headers().get(LOCATION).unwrap().to_str().unwrap();
+        assert!(location.starts_with(mas_router::Login::route()));
+
+        // The discovery document only advertises the code response type
+        let request = Request::get("/.well-known/openid-configuration").empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let metadata: serde_json::Value = response.json();
+        assert_eq!(
+            metadata["response_types_supported"],
+            serde_json::json!(["code"])
+        );
+    }
+}
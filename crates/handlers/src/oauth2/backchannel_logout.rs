@@ -0,0 +1,575 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Notify clients of ended sessions, as defined by [OpenID Connect
+//! Back-Channel Logout]
+//!
+//! [OpenID Connect Back-Channel Logout]: https://openid.net/specs/openid-connect-backchannel-1_0.html
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    convert::Infallible,
+};
+
+use axum::{
+    async_trait,
+    extract::{FromRef, FromRequestParts},
+    BoxError,
+};
+use chrono::Duration;
+use hyper::http::request::Parts;
+use mas_axum_utils::http_client_factory::HttpClientFactory;
+use mas_data_model::{BrowserSession, Client, Session, User};
+use mas_graphql::{EndingSessions, PendingLogouts};
+use mas_http::HttpServiceExt;
+use mas_iana::jose::JsonWebSignatureAlg;
+use mas_jose::{
+    claims,
+    constraints::Constrainable,
+    jwt::{JsonWebSignatureHeader, Jwt},
+};
+use mas_keystore::Keystore;
+use mas_router::UrlBuilder;
+use mas_storage::{
+    oauth2::OAuth2SessionFilter, BoxRepository, Clock, Pagination, RepositoryAccess,
+    RepositoryError,
+};
+use serde::Serialize;
+use tower::{Service, ServiceExt};
+use tracing::Instrument;
+use ulid::Ulid;
+use url::Url;
+
+use super::IdTokenSignatureError;
+
+/// The event identifying a logout token
+pub const BACKCHANNEL_LOGOUT_EVENT: &str = "http://schemas.openid.net/event/backchannel-logout";
+
+/// A logout token ready to be sent to a client
+#[derive(Debug, Clone)]
+pub struct PendingLogout {
+    client_id: String,
+    backchannel_logout_uri: Url,
+    logout_token: String,
+}
+
+#[derive(Serialize)]
+struct LogoutTokenRequest {
+    logout_token: String,
+}
+
+/// Builds logout tokens for the clients which took part in a browser session,
+/// and sends them to their `backchannel_logout_uri`
+#[derive(Debug, Clone)]
+pub struct BackchannelLogoutDispatcher {
+    http_client_factory: HttpClientFactory,
+    key_store: Keystore,
+    url_builder: UrlBuilder,
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for BackchannelLogoutDispatcher
+where
+    HttpClientFactory: FromRef<S>,
+    Keystore: FromRef<S>,
+    UrlBuilder: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(_parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        Ok(Self::new(
+            HttpClientFactory::from_ref(state),
+            Keystore::from_ref(state),
+            UrlBuilder::from_ref(state),
+        ))
+    }
+}
+
+impl BackchannelLogoutDispatcher {
+    /// Create a new dispatcher
+    #[must_use]
+    pub fn new(
+        http_client_factory: HttpClientFactory,
+        key_store: Keystore,
+        url_builder: UrlBuilder,
+    ) -> Self {
+        Self {
+            http_client_factory,
+            key_store,
+            url_builder,
+        }
+    }
+
+    /// Build the logout tokens for the clients which have an active session
+    /// started from the given browser session, and which registered a
+    /// `backchannel_logout_uri`
+    ///
+    /// This should be called before the browser session is ended, and the
+    /// returned logouts dispatched once the change is saved.
+    ///
+    /// # Parameters
+    ///
+    /// * `rng`: The random number generator used for the token IDs and
+    ///   signatures
+    /// * `clock`: The clock used to generate timestamps
+    /// * `repo`: The repository used to load the sessions and their clients
+    /// * `browser_session`: The browser session which is ending
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the underlying repository fails
+    pub async fn prepare<R: RepositoryAccess>(
+        &self,
+        rng: &mut (impl rand::RngCore + rand::CryptoRng),
+        clock: &impl Clock,
+        repo: &mut R,
+        browser_session: &BrowserSession,
+    ) -> Result<Vec<PendingLogout>, R::Error> {
+        let filter = OAuth2SessionFilter::new()
+            .for_browser_session(browser_session)
+            .active_only();
+        let sessions = list_sessions(repo, filter).await?;
+
+        self.prepare_for_oauth2_sessions(rng, clock, repo, &browser_session.user, &sessions)
+            .await
+    }
+
+    /// Build the logout tokens for the clients which have an active session
+    /// for the given user, for example when all their sessions are ended at
+    /// once
+    ///
+    /// This should be called before the sessions are ended, and the returned
+    /// logouts dispatched once the change is saved.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the underlying repository fails
+    pub async fn prepare_for_user<R: RepositoryAccess>(
+        &self,
+        rng: &mut (impl rand::RngCore + rand::CryptoRng),
+        clock: &impl Clock,
+        repo: &mut R,
+        user: &User,
+    ) -> Result<Vec<PendingLogout>, R::Error> {
+        let filter = OAuth2SessionFilter::new().for_user(user).active_only();
+        let sessions = list_sessions(repo, filter).await?;
+
+        self.prepare_for_oauth2_sessions(rng, clock, repo, user, &sessions)
+            .await
+    }
+
+    /// Build the logout tokens for the clients of the given OAuth 2.0
+    /// sessions of a user, if they registered a `backchannel_logout_uri`
+    ///
+    /// The `sid` claim is set to the browser session the OAuth 2.0 session was
+    /// started from. Clients which require it are skipped for sessions which
+    /// weren't started from a browser session.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the underlying repository fails
+    pub async fn prepare_for_oauth2_sessions<R: RepositoryAccess>(
+        &self,
+        rng: &mut (impl rand::RngCore + rand::CryptoRng),
+        clock: &impl Clock,
+        repo: &mut R,
+        user: &User,
+        sessions: &[Session],
+    ) -> Result<Vec<PendingLogout>, R::Error> {
+        // A client gets one logout token per browser session
+        let targets: BTreeSet<(Ulid, Option<Ulid>)> = sessions
+            .iter()
+            .map(|session| (session.client_id, session.user_session_id))
+            .collect();
+
+        let client_ids = targets.iter().map(|(client_id, _)| *client_id).collect();
+        let clients = repo.oauth2_client().load_batch(client_ids).await?;
+
+        let mut logouts = Vec::new();
+        for (client_id, sid) in targets {
+            let Some(client) = clients.get(&client_id) else {
+                continue;
+            };
+
+            let Some(backchannel_logout_uri) = client.backchannel_logout_uri.clone() else {
+                continue;
+            };
+
+            if sid.is_none() && client.backchannel_logout_session_required {
+                tracing::debug!(
+                    client.id = %client.id,
+                    "Not notifying the client of a session without a browser session, as it requires the sid claim",
+                );
+                continue;
+            }
+
+            match self.logout_token(rng, clock, client, &user.sub, sid) {
+                Ok(logout_token) => logouts.push(PendingLogout {
+                    client_id: client.client_id.clone(),
+                    backchannel_logout_uri,
+                    logout_token,
+                }),
+                Err(e) => {
+                    tracing::warn!(
+                        error = &e as &dyn std::error::Error,
+                        client.id = %client.id,
+                        "Failed to sign the logout token",
+                    );
+                }
+            }
+        }
+
+        Ok(logouts)
+    }
+
+    /// Prepare the logouts for sessions ended through the GraphQL API, which
+    /// doesn't know about the dispatcher
+    pub(crate) async fn prepare_for_graphql(
+        &self,
+        rng: &mut (impl rand::RngCore + rand::CryptoRng),
+        clock: &impl Clock,
+        repo: &mut BoxRepository,
+        sessions: EndingSessions<'_>,
+    ) -> Result<PendingLogouts, RepositoryError> {
+        let logouts = match sessions {
+            EndingSessions::BrowserSession(browser_session) => {
+                self.prepare(rng, clock, repo, browser_session).await?
+            }
+            EndingSessions::OAuth2Session(session) => {
+                let user = match session.user_id {
+                    Some(user_id) => repo.user().lookup(user_id).await?,
+                    None => None,
+                };
+
+                match user {
+                    Some(user) => {
+                        self.prepare_for_oauth2_sessions(
+                            rng,
+                            clock,
+                            repo,
+                            &user,
+                            std::slice::from_ref(session),
+                        )
+                        .await?
+                    }
+                    // Sessions without a user, like client credentials, have
+                    // nothing to notify
+                    None => Vec::new(),
+                }
+            }
+            EndingSessions::User(user) => self.prepare_for_user(rng, clock, repo, user).await?,
+        };
+
+        let dispatcher = self.clone();
+        Ok(PendingLogouts::new(move || dispatcher.dispatch(logouts)))
+    }
+
+    /// Send the logout tokens to the clients, each in its own task.
+    ///
+    /// Failures are logged and don't affect the other clients
+    pub fn dispatch(&self, logouts: Vec<PendingLogout>) {
+        for logout in logouts {
+            let http_client_factory = self.http_client_factory.clone();
+            let span = tracing::info_span!(
+                "oauth2.backchannel_logout",
+                client.id = %logout.client_id,
+                backchannel_logout_uri = %logout.backchannel_logout_uri,
+            );
+
+            tokio::spawn(
+                async move {
+                    if let Err(e) = send_logout_token(&http_client_factory, logout).await {
+                        tracing::warn!(
+                            error = &*e as &dyn std::error::Error,
+                            "Failed to notify the client of the logout",
+                        );
+                    }
+                }
+                .instrument(span),
+            );
+        }
+    }
+
+    /// Sign a logout token for the given client, user and optional browser
+    /// session
+    fn logout_token(
+        &self,
+        rng: &mut (impl rand::RngCore + rand::CryptoRng),
+        clock: &impl Clock,
+        client: &Client,
+        sub: &str,
+        sid: Option<Ulid>,
+    ) -> Result<String, IdTokenSignatureError> {
+        let mut claims = HashMap::new();
+        let now = clock.now();
+        let jti = Ulid::from_datetime_with_source(now.into(), rng);
+        claims::ISS.insert(&mut claims, self.url_builder.oidc_issuer().to_string())?;
+        claims::SUB.insert(&mut claims, sub)?;
+        claims::AUD.insert(&mut claims, client.client_id.clone())?;
+        claims::IAT.insert(&mut claims, now)?;
+        claims::EXP.insert(&mut claims, now + Duration::try_minutes(2).unwrap())?;
+        claims::JTI.insert(&mut claims, jti.to_string())?;
+        claims::EVENTS.insert(
+            &mut claims,
+            HashMap::from([(
+                BACKCHANNEL_LOGOUT_EVENT.to_owned(),
+                serde_json::Value::Object(serde_json::Map::new()),
+            )]),
+        )?;
+        if let Some(sid) = sid {
+            claims::SID.insert(&mut claims, sid.to_string())?;
+        }
+
+        // Logout tokens are signed like ID tokens, so that the client can
+        // verify them the same way
+        let alg = client
+            .id_token_signed_response_alg
+            .clone()
+            .unwrap_or(JsonWebSignatureAlg::Rs256);
+        let key = self
+            .key_store
+            .signing_key_for_algorithm(&alg)
+            .ok_or(IdTokenSignatureError::InvalidSigningKey)?;
+
+        let signer = key.params().signing_key_for_alg(&alg)?;
+        let header = JsonWebSignatureHeader::new(alg)
+            .with_kid(key.kid().ok_or(IdTokenSignatureError::InvalidSigningKey)?)
+            .with_typ("logout+jwt".to_owned());
+        let logout_token = Jwt::sign_with_rng(rng, header, claims, &signer)?;
+
+        Ok(logout_token.into_string())
+    }
+}
+
+/// List all the OAuth 2.0 sessions matching the filter
+async fn list_sessions<R: RepositoryAccess>(
+    repo: &mut R,
+    filter: OAuth2SessionFilter<'_>,
+) -> Result<Vec<Session>, R::Error> {
+    let mut sessions = Vec::new();
+    let mut pagination = Pagination::first(100);
+    loop {
+        let page = repo.oauth2_session().list(filter, pagination).await?;
+        let next = match page.edges.last() {
+            Some(last) if page.has_next_page => Some(last.id),
+            _ => None,
+        };
+        sessions.extend(page.edges);
+
+        match next {
+            Some(id) => pagination = pagination.after(id),
+            None => break,
+        }
+    }
+
+    Ok(sessions)
+}
+
+async fn send_logout_token(
+    http_client_factory: &HttpClientFactory,
+    logout: PendingLogout,
+) -> Result<(), BoxError> {
+    let request =
+        hyper::Request::post(logout.backchannel_logout_uri.as_str()).body(LogoutTokenRequest {
+            logout_token: logout.logout_token,
+        })?;
+
+    let mut client = http_client_factory
+        .client("client.backchannel_logout")
+        .request_bytes_to_body()
+        .form_urlencoded_request();
+
+    let response = client.ready().await?.call(request).await?;
+
+    if !response.status().is_success() {
+        return Err(format!("client responded with status {}", response.status()).into());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use mas_iana::oauth::{
+        OAuthAuthorizationEndpointResponseType, OAuthClientAuthenticationMethod,
+    };
+    use mas_storage::oauth2::UpsertStaticClientParams;
+    use oauth2_types::scope::{Scope, OPENID};
+    use serde_json::Value;
+    use sqlx::PgPool;
+    use wiremock::{
+        matchers::{body_string_contains, method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use super::*;
+    use crate::test_utils::{init_tracing, TestState};
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_backchannel_logout(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let mut rng = state.rng();
+
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/backchannel-logout"))
+            .and(body_string_contains("logout_token="))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let mut repo = state.repository().await.unwrap();
+
+        // One client which wants to be notified, and one which doesn't
+        let notified_client = repo
+            .oauth2_client()
+            .upsert_static(UpsertStaticClientParams {
+                client_id: Ulid::from_datetime_with_source(state.clock.now().into(), &mut rng),
+                client_auth_method: OAuthClientAuthenticationMethod::None,
+                encrypted_client_secret: None,
+                jwks: None,
+                jwks_uri: None,
+                redirect_uris: vec!["https://example.com/callback".parse().unwrap()],
+                redirect_uri_matching: mas_data_model::RedirectUriMatching::Exact,
+                response_types: vec![OAuthAuthorizationEndpointResponseType::Code],
+                allow_token_exchange: false,
+                allow_client_credentials: false,
+                client_credentials_scope: None,
+                allowed_resources: Vec::new(),
+                require_signed_request_object: false,
+                require_pkce: None,
+                access_token_ttl: None,
+                refresh_token_ttl: None,
+                access_token_format: mas_data_model::AccessTokenFormat::Opaque,
+                introspection_endpoint_auth_method: None,
+                post_logout_redirect_uris: Vec::new(),
+                backchannel_logout_uri: Some(
+                    format!("{}/backchannel-logout", mock_server.uri())
+                        .parse()
+                        .unwrap(),
+                ),
+                backchannel_logout_session_required: true,
+                trusted: false,
+            })
+            .await
+            .unwrap();
+        let other_client = repo
+            .oauth2_client()
+            .upsert_static(UpsertStaticClientParams {
+                client_id: Ulid::from_datetime_with_source(state.clock.now().into(), &mut rng),
+                client_auth_method: OAuthClientAuthenticationMethod::None,
+                encrypted_client_secret: None,
+                jwks: None,
+                jwks_uri: None,
+                redirect_uris: vec!["https://example.com/callback".parse().unwrap()],
+                redirect_uri_matching: mas_data_model::RedirectUriMatching::Exact,
+                response_types: vec![OAuthAuthorizationEndpointResponseType::Code],
+                allow_token_exchange: false,
+                allow_client_credentials: false,
+                client_credentials_scope: None,
+                allowed_resources: Vec::new(),
+                require_signed_request_object: false,
+                require_pkce: None,
+                access_token_ttl: None,
+                refresh_token_ttl: None,
+                access_token_format: mas_data_model::AccessTokenFormat::Opaque,
+                introspection_endpoint_auth_method: None,
+                post_logout_redirect_uris: Vec::new(),
+                backchannel_logout_uri: None,
+                backchannel_logout_session_required: false,
+                trusted: false,
+            })
+            .await
+            .unwrap();
+
+        let user = repo
+            .user()
+            .add(&mut rng, &state.clock, "alice".to_owned())
+            .await
+            .unwrap();
+        let browser_session = repo
+            .browser_session()
+            .add(&mut rng, &state.clock, &user, None, None, None)
+            .await
+            .unwrap();
+
+        let scope = Scope::from_iter([OPENID]);
+        for client in [&notified_client, &other_client] {
+            repo.oauth2_session()
+                .add_from_browser_session(
+                    &mut rng,
+                    &state.clock,
+                    client,
+                    &browser_session,
+                    scope.clone(),
+                )
+                .await
+                .unwrap();
+        }
+
+        let dispatcher = BackchannelLogoutDispatcher::new(
+            state.http_client_factory.clone(),
+            state.key_store.clone(),
+            state.url_builder.clone(),
+        );
+        let logouts = dispatcher
+            .prepare(&mut rng, &state.clock, &mut repo, &browser_session)
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        // Only the client with a backchannel_logout_uri gets a logout token
+        assert_eq!(logouts.len(), 1);
+
+        for logout in logouts {
+            send_logout_token(&state.http_client_factory, logout)
+                .await
+                .unwrap();
+        }
+
+        let requests = mock_server.received_requests().await.unwrap();
+        assert_eq!(requests.len(), 1);
+        let body: HashMap<String, String> =
+            serde_urlencoded::from_bytes(&requests[0].body).unwrap();
+        let logout_token = body.get("logout_token").unwrap();
+
+        // The logout token is signed with our keys
+        let jwt: Jwt<'_, HashMap<String, Value>> = Jwt::try_from(logout_token.as_str()).unwrap();
+        jwt.verify_with_jwks(&state.key_store.public_jwks())
+            .unwrap();
+        assert_eq!(jwt.header().typ(), Some("logout+jwt"));
+
+        let mut claims = jwt.payload().clone();
+        claims::ISS
+            .extract_required_with_options(&mut claims, state.url_builder.oidc_issuer().as_str())
+            .unwrap();
+        claims::AUD
+            .extract_required_with_options(&mut claims, &notified_client.client_id)
+            .unwrap();
+        assert_eq!(claims::SUB.extract_required(&mut claims).unwrap(), user.sub);
+        assert_eq!(
+            claims::SID.extract_required(&mut claims).unwrap(),
+            browser_session.id.to_string()
+        );
+        assert!(claims::JTI.extract_required(&mut claims).is_ok());
+        assert!(claims::EVENTS
+            .extract_required(&mut claims)
+            .unwrap()
+            .contains_key(BACKCHANNEL_LOGOUT_EVENT));
+        assert!(!claims.contains_key("nonce"));
+    }
+}
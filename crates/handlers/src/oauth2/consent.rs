@@ -27,7 +27,7 @@ use mas_data_model::{AuthorizationGrantStage, Device};
 use mas_policy::Policy;
 use mas_router::{PostAuthAction, UrlBuilder};
 use mas_storage::{
-    oauth2::{OAuth2AuthorizationGrantRepository, OAuth2ClientRepository},
+    oauth2::{OAuth2AuthorizationGrantRepository, OAuth2ClientRepository, OAuth2ConsentRepository},
     BoxClock, BoxRepository, BoxRng,
 };
 use mas_templates::{ConsentContext, PolicyViolationContext, TemplateContext, Templates};
@@ -91,9 +91,9 @@ pub(crate) async fn get(
     cookie_jar: CookieJar,
     Path(grant_id): Path<Ulid>,
 ) -> Result<Response, RouteError> {
-    let (session_info, cookie_jar) = cookie_jar.session_info();
+    let (session_info, cookie_jar) = cookie_jar.session_info(&clock);
 
-    let maybe_session = session_info.load_session(&mut repo).await?;
+    let maybe_session = session_info.load_session(&clock, &mut repo).await?;
 
     let grant = repo
         .oauth2_authorization_grant()
@@ -166,9 +166,9 @@ pub(crate) async fn post(
 ) -> Result<Response, RouteError> {
     cookie_jar.verify_form(&clock, form)?;
 
-    let (session_info, cookie_jar) = cookie_jar.session_info();
+    let (session_info, cookie_jar) = cookie_jar.session_info(&clock);
 
-    let maybe_session = session_info.load_session(&mut repo).await?;
+    let maybe_session = session_info.load_session(&clock, &mut repo).await?;
 
     let grant = repo
         .oauth2_authorization_grant()
@@ -208,8 +208,8 @@ pub(crate) async fn post(
         .cloned()
         .collect();
 
-    repo.oauth2_client()
-        .give_consent_for_user(
+    repo.oauth2_consent()
+        .add(
             &mut rng,
             &clock,
             &client,
@@ -27,7 +27,10 @@ use mas_router::UrlBuilder;
 use mas_storage::{oauth2::OAuth2DeviceCodeGrantParams, BoxClock, BoxRepository, BoxRng};
 use oauth2_types::{
     errors::{ClientError, ClientErrorCode},
-    requests::{DeviceAuthorizationRequest, DeviceAuthorizationResponse, GrantType},
+    requests::{
+        DeviceAuthorizationRequest, DeviceAuthorizationResponse, GrantType,
+        DEFAULT_DEVICE_AUTHORIZATION_INTERVAL,
+    },
     scope::ScopeToken,
 };
 use rand::distributions::{Alphanumeric, DistString};
@@ -130,7 +133,7 @@ pub(crate) async fn post(
     let ip_address = activity_tracker.ip();
 
     let device_code = Alphanumeric.sample_string(&mut rng, 32);
-    let user_code = Alphanumeric.sample_string(&mut rng, 6).to_uppercase();
+    let user_code = super::generate_user_code(&mut rng);
 
     let device_code = repo
         .oauth2_device_code_grant()
@@ -143,6 +146,7 @@ pub(crate) async fn post(
                 device_code,
                 user_code,
                 expires_in,
+                poll_interval: DEFAULT_DEVICE_AUTHORIZATION_INTERVAL,
                 user_agent,
                 ip_address,
             },
@@ -157,7 +161,7 @@ pub(crate) async fn post(
         verification_uri: url_builder.device_code_link(),
         verification_uri_complete: Some(url_builder.device_code_link_full(device_code.user_code)),
         expires_in,
-        interval: Some(Duration::microseconds(5 * 1000 * 1000)),
+        interval: Some(device_code.poll_interval),
     };
 
     Ok((
@@ -212,6 +216,11 @@ mod tests {
 
         let response: DeviceAuthorizationResponse = response.json();
         assert_eq!(response.device_code.len(), 32);
-        assert_eq!(response.user_code.len(), 6);
+        assert_eq!(response.user_code.len(), 8);
+        // User codes only use characters which can't be confused
+        assert!(response
+            .user_code
+            .chars()
+            .all(|c| c.is_ascii_uppercase() && !"AEIOUY".contains(c)));
     }
 }
@@ -12,12 +12,15 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::{net::IpAddr, sync::Arc};
+
 use axum::{
     extract::{Query, State},
     response::{IntoResponse, Response},
     Form,
 };
 use axum_extra::response::Html;
+use chrono::Duration;
 use mas_axum_utils::{
     cookies::CookieJar,
     csrf::{CsrfExt, ProtectedForm},
@@ -26,11 +29,28 @@ use mas_axum_utils::{
 use mas_router::UrlBuilder;
 use mas_storage::{BoxClock, BoxRepository, BoxRng};
 use mas_templates::{
-    DeviceLinkContext, DeviceLinkFormField, FieldError, FormState, TemplateContext, Templates,
+    DeviceLinkContext, DeviceLinkFormField, FieldError, FormError, FormState, TemplateContext,
+    Templates,
 };
 use serde::{Deserialize, Serialize};
 
-use crate::PreferredLanguage;
+use super::normalize_user_code;
+use crate::{BoundActivityTracker, PreferredLanguage, RateLimit, RateLimiter};
+
+/// How many user codes can be entered from a single IP address, so that user
+/// codes can't be brute-forced
+const CODE_ATTEMPTS_LIMIT: RateLimit = RateLimit {
+    max_requests: 10,
+    window: Duration::microseconds(15 * 60 * 1000 * 1000),
+};
+
+/// The key under which attempts at entering a user code are rate-limited.
+///
+/// There is none if the IP address of the client isn't known, so that those
+/// clients don't share a single limit.
+fn rate_limit_key(ip: Option<IpAddr>) -> Option<String> {
+    ip.map(|ip| format!("device_link:{ip}"))
+}
 
 // We use this struct for both the form and the query parameters. This is useful
 // to build a form state from the query parameters. The query parameter is only
@@ -58,7 +78,7 @@ pub(crate) async fn get(
     // may want to make the form readonly instead at some point? tbd
     if let Some(Query(params)) = query {
         // Validate that it's a full code
-        if params.code.len() == 6 && params.code.chars().all(|c| c.is_ascii_alphanumeric()) {
+        if normalize_user_code(&params.code).is_some() {
             form_state = FormState::from_form(&params);
         }
     };
@@ -81,20 +101,48 @@ pub(crate) async fn post(
     PreferredLanguage(locale): PreferredLanguage,
     State(templates): State<Templates>,
     State(url_builder): State<UrlBuilder>,
+    State(rate_limiter): State<Arc<dyn RateLimiter>>,
+    activity_tracker: BoundActivityTracker,
     cookie_jar: CookieJar,
     Form(form): Form<ProtectedForm<Params>>,
 ) -> Result<Response, FancyError> {
     let form = cookie_jar.verify_form(&clock, form)?;
     let (csrf_token, cookie_jar) = cookie_jar.csrf_token(&clock, &mut rng);
 
-    let code = form.code.to_uppercase();
-    let grant = repo
-        .oauth2_device_code_grant()
-        .find_by_user_code(&code)
-        .await?
-        // XXX: We should have different error messages for already exchanged and expired
-        .filter(|grant| grant.is_pending())
-        .filter(|grant| grant.expires_at > clock.now());
+    // Don't even look up the code if there were too many attempts recently, so
+    // that user codes can't be brute-forced. Every attempt is counted, so that
+    // concurrent requests can't get around the limit.
+    let rate_limit_key = rate_limit_key(activity_tracker.ip());
+    let limited = rate_limit_key
+        .as_deref()
+        .map(|key| rate_limiter.hit(key, CODE_ATTEMPTS_LIMIT, clock.now()));
+    if let Some(Err(retry_after)) = limited {
+        // Round up to the next second, so that retrying right on time works
+        let seconds = (retry_after + Duration::milliseconds(999)).num_seconds();
+        let form_state = FormState::from_form(&form).with_error_on_form(FormError::RateLimited {
+            retry_after: seconds.try_into().unwrap_or_default(),
+        });
+
+        let ctx = DeviceLinkContext::new()
+            .with_form_state(form_state)
+            .with_csrf(csrf_token.form_value())
+            .with_language(locale);
+
+        let content = templates.render_device_link(&ctx)?;
+
+        return Ok((cookie_jar, Html(content)).into_response());
+    }
+
+    let grant = if let Some(code) = normalize_user_code(&form.code) {
+        repo.oauth2_device_code_grant()
+            .find_by_user_code(&code)
+            .await?
+            // XXX: We should have different error messages for already exchanged and expired
+            .filter(|grant| grant.is_pending())
+            .filter(|grant| grant.expires_at > clock.now())
+    } else {
+        None
+    };
 
     let Some(grant) = grant else {
         let form_state = FormState::from_form(&form)
@@ -116,3 +164,113 @@ pub(crate) async fn post(
 
     Ok((cookie_jar, destination).into_response())
 }
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use hyper::{header::LOCATION, Request, StatusCode};
+    use mas_router::SimpleRoute;
+    use oauth2_types::{
+        registration::ClientRegistrationResponse, requests::DeviceAuthorizationResponse,
+    };
+    use sqlx::PgPool;
+
+    use crate::test_utils::{
+        init_tracing, CookieHelper, RequestBuilderExt, ResponseExt, TestState,
+    };
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_device_link_rate_limited(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let cookies = CookieHelper::new();
+
+        // Provision a client and start a device code grant
+        let request =
+            Request::post(mas_router::OAuth2RegistrationEndpoint::PATH).json(serde_json::json!({
+                "client_uri": "https://example.com/",
+                "token_endpoint_auth_method": "none",
+                "grant_types": ["urn:ietf:params:oauth:grant-type:device_code"],
+                "response_types": [],
+            }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::CREATED);
+        let response: ClientRegistrationResponse = response.json();
+
+        let request = Request::post(mas_router::OAuth2DeviceAuthorizationEndpoint::PATH).form(
+            serde_json::json!({
+                "client_id": response.client_id,
+                "scope": "openid",
+            }),
+        );
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let grant: DeviceAuthorizationResponse = response.json();
+
+        // Render the link page to get a CSRF token
+        let request = Request::get("/link").empty();
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        cookies.save_cookies(&response);
+        response.assert_status(StatusCode::OK);
+        let csrf_token = response
+            .body()
+            .split("name=\"csrf\" value=\"")
+            .nth(1)
+            .unwrap()
+            .split('\"')
+            .next()
+            .unwrap()
+            .to_owned();
+
+        // The code is case-insensitive, and separators are ignored
+        let (first, second) = grant.user_code.split_at(4);
+        let good_code = format!("{}-{}", first.to_lowercase(), second);
+
+        // Each step waits for some time, tries a code from an IP address, and
+        // checks the error message, if any
+        let mut steps = vec![(0, "192.0.2.1", "BBBBBBBB", Some("data-invalid")); 9];
+        steps.extend([
+            (0, "192.0.2.1", "not-even-a-code", Some("data-invalid")),
+            // The tenth failure reached the limit, even the right code is rejected
+            (
+                60,
+                "192.0.2.1",
+                good_code.as_str(),
+                Some("try again in 840 seconds"),
+            ),
+            // Other clients are not affected
+            (0, "192.0.2.2", good_code.as_str(), None),
+            // Once the window is over, the right code works
+            (840, "192.0.2.1", good_code.as_str(), None),
+        ]);
+
+        for (wait, ip, code, error) in steps {
+            state.clock.advance(Duration::seconds(wait));
+            let request =
+                Request::post("/link")
+                    .header("X-Forwarded-For", ip)
+                    .form(serde_json::json!({
+                        "csrf": csrf_token,
+                        "code": code,
+                    }));
+            let request = cookies.with_cookies(request);
+            let response = state.request(request).await;
+
+            if let Some(error) = error {
+                response.assert_status(StatusCode::OK);
+                assert!(
+                    response.body().contains(error),
+                    "Response body: {}",
+                    response.body()
+                );
+            } else {
+                response.assert_status(StatusCode::SEE_OTHER);
+                assert!(response.headers()[LOCATION]
+                    .to_str()
+                    .unwrap()
+                    .starts_with("/device/"));
+            }
+        }
+    }
+}
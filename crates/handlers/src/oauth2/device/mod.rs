@@ -12,6 +12,40 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use rand::Rng;
+
 pub mod authorize;
 pub mod consent;
 pub mod link;
+
+/// The characters user codes are made of. Those are the uppercase consonants
+/// suggested by RFC 8628, which avoid characters which can easily be confused
+/// with one another, like `0` and `O` or `1` and `I`.
+const USER_CODE_CHARSET: &[u8] = b"BCDFGHJKLMNPQRSTVWXZ";
+
+/// The length of the user codes
+const USER_CODE_LENGTH: usize = 8;
+
+/// Generate a new random user code
+fn generate_user_code(rng: &mut impl Rng) -> String {
+    (0..USER_CODE_LENGTH)
+        .map(|_| char::from(USER_CODE_CHARSET[rng.gen_range(0..USER_CODE_CHARSET.len())]))
+        .collect()
+}
+
+/// Normalize a user code entered by a user, ignoring the case and the
+/// separators they may have typed.
+///
+/// Returns `None` if this can't be a valid user code
+fn normalize_user_code(code: &str) -> Option<String> {
+    let code: String = code
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != '-')
+        .map(|c| c.to_ascii_uppercase())
+        .collect();
+
+    let valid =
+        code.len() == USER_CODE_LENGTH && code.bytes().all(|c| USER_CODE_CHARSET.contains(&c));
+
+    valid.then_some(code)
+}
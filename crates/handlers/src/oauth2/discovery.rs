@@ -12,10 +12,11 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use axum::{extract::State, response::IntoResponse, Json};
-use mas_iana::oauth::{
-    OAuthAuthorizationEndpointResponseType, OAuthClientAuthenticationMethod,
-    PkceCodeChallengeMethod,
+use axum::{extract::State, response::IntoResponse, Json, TypedHeader};
+use headers::CacheControl;
+use mas_iana::{
+    jose::JsonWebSignatureAlg,
+    oauth::{OAuthAuthorizationEndpointResponseType, OAuthClientAuthenticationMethod},
 };
 use mas_jose::jwa::SUPPORTED_SIGNING_ALGORITHMS;
 use mas_keystore::Keystore;
@@ -27,7 +28,7 @@ use oauth2_types::{
 };
 use serde::Serialize;
 
-use crate::SiteConfig;
+use crate::{oauth2::jar::REQUEST_OBJECT_SIGNING_ALGORITHMS, SiteConfig};
 
 #[derive(Debug, Serialize)]
 struct DiscoveryResponse {
@@ -73,20 +74,32 @@ pub(crate) async fn get(
     let introspection_endpoint = Some(url_builder.oauth_introspection_endpoint());
     let revocation_endpoint = Some(url_builder.oauth_revocation_endpoint());
     let userinfo_endpoint = Some(url_builder.oidc_userinfo_endpoint());
+    let end_session_endpoint = Some(url_builder.oidc_end_session_endpoint());
     let registration_endpoint = Some(url_builder.oauth_registration_endpoint());
+    let pushed_authorization_request_endpoint =
+        Some(url_builder.oauth_pushed_authorization_request_endpoint());
 
     let scopes_supported = Some(vec![scope::OPENID.to_string(), scope::EMAIL.to_string()]);
 
-    let response_types_supported = Some(vec![
-        OAuthAuthorizationEndpointResponseType::Code.into(),
-        OAuthAuthorizationEndpointResponseType::IdToken.into(),
-        OAuthAuthorizationEndpointResponseType::CodeIdToken.into(),
-    ]);
+    let response_types_supported = if site_config.disable_implicit_flow {
+        Some(vec![OAuthAuthorizationEndpointResponseType::Code.into()])
+    } else {
+        Some(vec![
+            OAuthAuthorizationEndpointResponseType::Code.into(),
+            OAuthAuthorizationEndpointResponseType::IdToken.into(),
+            OAuthAuthorizationEndpointResponseType::CodeIdToken.into(),
+        ])
+    };
 
     let response_modes_supported = Some(vec![
         ResponseMode::FormPost,
         ResponseMode::Query,
         ResponseMode::Fragment,
+        ResponseMode::WebMessage,
+        ResponseMode::Jwt,
+        ResponseMode::QueryJwt,
+        ResponseMode::FragmentJwt,
+        ResponseMode::FormPostJwt,
     ]);
 
     let grant_types_supported = Some(vec![
@@ -94,6 +107,7 @@ pub(crate) async fn get(
         GrantType::RefreshToken,
         GrantType::ClientCredentials,
         GrantType::DeviceCode,
+        GrantType::TokenExchange,
     ]);
 
     let token_endpoint_auth_methods_supported = client_auth_methods_supported.clone();
@@ -109,15 +123,25 @@ pub(crate) async fn get(
     let introspection_endpoint_auth_signing_alg_values_supported =
         client_auth_signing_alg_values_supported;
 
-    let code_challenge_methods_supported = Some(vec![
-        PkceCodeChallengeMethod::Plain,
-        PkceCodeChallengeMethod::S256,
+    let code_challenge_methods_supported = Some(site_config.allowed_code_challenge_methods.clone());
+
+    let dpop_signing_alg_values_supported = Some(vec![
+        JsonWebSignatureAlg::Rs256,
+        JsonWebSignatureAlg::Rs384,
+        JsonWebSignatureAlg::Rs512,
+        JsonWebSignatureAlg::Ps256,
+        JsonWebSignatureAlg::Ps384,
+        JsonWebSignatureAlg::Ps512,
+        JsonWebSignatureAlg::Es256,
+        JsonWebSignatureAlg::Es384,
+        JsonWebSignatureAlg::Es256K,
     ]);
 
     let subject_types_supported = Some(vec![SubjectType::Public]);
 
     let id_token_signing_alg_values_supported = jwt_signing_alg_values_supported.clone();
-    let userinfo_signing_alg_values_supported = jwt_signing_alg_values_supported;
+    let userinfo_signing_alg_values_supported = jwt_signing_alg_values_supported.clone();
+    let authorization_signing_alg_values_supported = jwt_signing_alg_values_supported;
 
     let display_values_supported = Some(vec![Display::Page]);
 
@@ -133,10 +157,14 @@ pub(crate) async fn get(
         "auth_time".to_owned(),
         "at_hash".to_owned(),
         "c_hash".to_owned(),
+        "amr".to_owned(),
+        "sid".to_owned(),
     ]);
 
     let claims_parameter_supported = Some(false);
-    let request_parameter_supported = Some(false);
+    let request_parameter_supported = Some(true);
+    let request_object_signing_alg_values_supported =
+        Some(REQUEST_OBJECT_SIGNING_ALGORITHMS.to_vec());
     let request_uri_parameter_supported = Some(false);
 
     let prompt_values_supported = Some({
@@ -178,13 +206,23 @@ pub(crate) async fn get(
         claims_supported,
         claims_parameter_supported,
         request_parameter_supported,
+        request_object_signing_alg_values_supported,
         request_uri_parameter_supported,
         prompt_values_supported,
         device_authorization_endpoint,
+        dpop_signing_alg_values_supported,
+        authorization_signing_alg_values_supported,
+        pushed_authorization_request_endpoint,
+        end_session_endpoint,
         ..ProviderMetadata::default()
     };
 
-    Json(DiscoveryResponse {
+    // Let clients cache the document for a while
+    let cache_control = CacheControl::new()
+        .with_public()
+        .with_max_age(site_config.discovery_cache_ttl.to_std().unwrap_or_default());
+
+    let response = Json(DiscoveryResponse {
         standard,
         graphql_endpoint: url_builder.graphql_endpoint(),
         account_management_uri: url_builder.account_management_uri(),
@@ -197,12 +235,15 @@ pub(crate) async fn get(
             "org.matrix.session_end".to_owned(),
             "org.matrix.cross_signing_reset".to_owned(),
         ],
-    })
+    });
+
+    (TypedHeader(cache_control), response)
 }
 
 #[cfg(test)]
 mod tests {
-    use hyper::{Request, StatusCode};
+    use hyper::{header::CACHE_CONTROL, Request, StatusCode};
+    use mas_router::SimpleRoute;
     use oauth2_types::oidc::ProviderMetadata;
     use sqlx::PgPool;
 
@@ -222,4 +263,53 @@ mod tests {
             .validate(state.url_builder.oidc_issuer().as_str())
             .expect("Invalid metadata");
     }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_discovery_required_fields(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+
+        for path in [
+            mas_router::OidcConfiguration::PATH,
+            mas_router::OAuth2AuthorizationServerMetadata::PATH,
+        ] {
+            let request = Request::get(path).empty();
+            let response = state.request(request).await;
+            response.assert_status(StatusCode::OK);
+            response.assert_header_value(CACHE_CONTROL, "public, max-age=300");
+
+            let metadata: ProviderMetadata = response.json();
+            assert_eq!(
+                metadata.issuer.as_deref(),
+                Some(state.url_builder.oidc_issuer().as_str())
+            );
+            assert_eq!(
+                metadata.authorization_endpoint,
+                Some(state.url_builder.oauth_authorization_endpoint())
+            );
+            assert_eq!(
+                metadata.token_endpoint,
+                Some(state.url_builder.oauth_token_endpoint())
+            );
+            assert_eq!(
+                metadata.userinfo_endpoint,
+                Some(state.url_builder.oidc_userinfo_endpoint())
+            );
+            assert_eq!(
+                metadata.end_session_endpoint,
+                Some(state.url_builder.oidc_end_session_endpoint())
+            );
+            assert_eq!(metadata.jwks_uri, Some(state.url_builder.jwks_uri()));
+            assert!(metadata.scopes_supported.is_some());
+            assert!(metadata.response_types_supported.is_some());
+            assert!(metadata.grant_types_supported.is_some());
+            assert!(metadata.subject_types_supported.is_some());
+            assert!(metadata.id_token_signing_alg_values_supported.is_some());
+            assert!(metadata.token_endpoint_auth_methods_supported.is_some());
+            assert_eq!(
+                metadata.authorization_signing_alg_values_supported,
+                Some(state.key_store.available_signing_algorithms())
+            );
+        }
+    }
 }
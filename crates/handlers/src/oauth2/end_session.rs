@@ -0,0 +1,443 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::HashMap;
+
+use axum::{
+    extract::{Form, State},
+    response::{Html, IntoResponse, Redirect, Response},
+};
+use hyper::StatusCode;
+use mas_axum_utils::{cookies::CookieJar, sentry::SentryEventID, SessionInfoExt};
+use mas_jose::{
+    claims::{self, ClaimError},
+    jwt::Jwt,
+};
+use mas_keystore::Keystore;
+use mas_router::UrlBuilder;
+use mas_storage::{
+    oauth2::OAuth2ClientRepository, user::BrowserSessionRepository, BoxClock, BoxRepository, BoxRng,
+};
+use mas_templates::{EmptyContext, TemplateContext, Templates};
+use oauth2_types::oidc::RpInitiatedLogoutRequest;
+use serde_json::Value;
+use thiserror::Error;
+use ulid::Ulid;
+
+use crate::{
+    impl_from_error_for_route, BackchannelLogoutDispatcher, BoundActivityTracker, PreferredLanguage,
+};
+
+#[derive(Debug, Error)]
+pub(crate) enum RouteError {
+    #[error(transparent)]
+    Internal(Box<dyn std::error::Error + Send + Sync + 'static>),
+
+    #[error("missing id_token_hint")]
+    MissingIdTokenHint,
+
+    #[error("invalid id_token_hint")]
+    InvalidIdTokenHint,
+
+    #[error("invalid id_token_hint")]
+    InvalidIdTokenHintClaim(#[from] ClaimError),
+
+    #[error("could not find client")]
+    ClientNotFound,
+
+    #[error("post_logout_redirect_uri is not registered for this client")]
+    UnknownPostLogoutRedirectUri,
+}
+
+impl_from_error_for_route!(mas_storage::RepositoryError);
+impl_from_error_for_route!(mas_templates::TemplateError);
+
+impl IntoResponse for RouteError {
+    fn into_response(self) -> Response {
+        let event_id = sentry::capture_error(&self);
+        let response = match self {
+            Self::Internal(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+            Self::MissingIdTokenHint
+            | Self::InvalidIdTokenHint
+            | Self::InvalidIdTokenHintClaim(_)
+            | Self::ClientNotFound
+            | Self::UnknownPostLogoutRedirectUri => {
+                (StatusCode::BAD_REQUEST, self.to_string()).into_response()
+            }
+        };
+
+        (SentryEventID::from(event_id), response).into_response()
+    }
+}
+
+#[tracing::instrument(name = "handlers.oauth2.end_session.get", skip_all, err)]
+pub(crate) async fn get(
+    clock: BoxClock,
+    mut rng: BoxRng,
+    mut repo: BoxRepository,
+    cookie_jar: CookieJar,
+    PreferredLanguage(locale): PreferredLanguage,
+    State(templates): State<Templates>,
+    State(key_store): State<Keystore>,
+    State(url_builder): State<UrlBuilder>,
+    activity_tracker: BoundActivityTracker,
+    backchannel_logout: BackchannelLogoutDispatcher,
+    Form(params): Form<RpInitiatedLogoutRequest>,
+) -> Result<Response, RouteError> {
+    // Ending sessions without an ID token hint would let any website sign users
+    // out, so we require one to know which session to end
+    let id_token_hint = params
+        .id_token_hint
+        .as_deref()
+        .ok_or(RouteError::MissingIdTokenHint)?;
+
+    // The ID token must have been issued by us. It is fine if it expired, as
+    // RPs usually keep the ID token around for the whole session
+    let jwt: Jwt<'_, HashMap<String, Value>> =
+        Jwt::try_from(id_token_hint).map_err(|_| RouteError::InvalidIdTokenHint)?;
+    jwt.verify_with_jwks(&key_store.public_jwks())
+        .map_err(|_| RouteError::InvalidIdTokenHint)?;
+
+    let mut claims = jwt.payload().clone();
+    claims::ISS.extract_required_with_options(&mut claims, url_builder.oidc_issuer().as_str())?;
+    let sub = claims::SUB.extract_required(&mut claims)?;
+    let sid = claims::SID.extract_optional(&mut claims)?;
+
+    // The client is the audience of the ID token, unless explicitly given
+    let client_id = match (params.client_id, claims.get("aud")) {
+        (Some(client_id), _) => client_id,
+        (None, Some(Value::String(aud))) => aud.clone(),
+        (None, _) => return Err(RouteError::InvalidIdTokenHint),
+    };
+    claims::AUD.extract_required_with_options(&mut claims, &client_id)?;
+
+    let client = repo
+        .oauth2_client()
+        .find_by_client_id(&client_id)
+        .await?
+        .ok_or(RouteError::ClientNotFound)?;
+
+    // Check the redirect URI before ending anything, so that we don't log the
+    // user out on an invalid request
+    let post_logout_redirect_uri = params
+        .post_logout_redirect_uri
+        .map(|uri| {
+            if client.post_logout_redirect_uris.contains(&uri) {
+                Ok(uri)
+            } else {
+                Err(RouteError::UnknownPostLogoutRedirectUri)
+            }
+        })
+        .transpose()?;
+
+    let (session_info, mut cookie_jar) = cookie_jar.session_info(&clock);
+
+    // End the session the ID token was issued for. This works even if the
+    // request doesn't carry the session cookie
+    let mut sessions = Vec::new();
+    if let Some(session_id) = sid.as_deref().and_then(|sid| sid.parse::<Ulid>().ok()) {
+        if let Some(session) = repo.browser_session().lookup(&clock, session_id).await? {
+            sessions.push(session);
+        }
+    }
+
+    let cookie_session = session_info.load_session(&clock, &mut repo).await?;
+    let cookie_session_id = cookie_session.as_ref().map(|session| session.id);
+    if let Some(session) = cookie_session {
+        if sessions.iter().all(|s| s.id != session.id) {
+            sessions.push(session);
+        }
+    }
+
+    let mut logouts = Vec::new();
+    for session in sessions {
+        // Only end sessions which belong to the user of the ID token
+        if session.user.sub != sub || !session.active() {
+            continue;
+        }
+
+        activity_tracker
+            .record_browser_session(&clock, &session)
+            .await;
+
+        if cookie_session_id == Some(session.id) {
+            cookie_jar =
+                cookie_jar.update_session_info(&clock, &session_info.clone().mark_session_ended());
+        }
+
+        logouts.extend(
+            backchannel_logout
+                .prepare(&mut rng, &clock, &mut repo, &session)
+                .await?,
+        );
+
+        repo.browser_session().finish(&clock, session).await?;
+    }
+
+    repo.save().await?;
+
+    backchannel_logout.dispatch(logouts);
+
+    if let Some(mut post_logout_redirect_uri) = post_logout_redirect_uri {
+        if let Some(state) = params.state {
+            post_logout_redirect_uri
+                .query_pairs_mut()
+                .append_pair("state", &state);
+        }
+
+        return Ok((cookie_jar, Redirect::to(post_logout_redirect_uri.as_str())).into_response());
+    }
+
+    let ctx = EmptyContext.with_language(locale);
+    let content = templates.render_logged_out(&ctx)?;
+
+    Ok((cookie_jar, Html(content)).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::{
+        header::{LOCATION, SET_COOKIE},
+        Request, StatusCode,
+    };
+    use mas_data_model::BrowserSession;
+    use mas_iana::jose::JsonWebSignatureAlg;
+    use mas_jose::{constraints::Constrainable, jwt::JsonWebSignatureHeader};
+    use mas_router::SimpleRoute;
+    use mas_storage::{Clock, RepositoryAccess};
+    use oauth2_types::registration::ClientRegistrationResponse;
+    use sqlx::PgPool;
+
+    use super::*;
+    use crate::test_utils::{
+        init_tracing, CookieHelper, RequestBuilderExt, ResponseExt, TestState,
+    };
+
+    /// Register a client which can be redirected to
+    /// `https://example.com/logged-out` after logging out
+    async fn register_client(state: &TestState) -> String {
+        let request =
+            Request::post(mas_router::OAuth2RegistrationEndpoint::PATH).json(serde_json::json!({
+                "client_uri": "https://example.com/",
+                "redirect_uris": ["https://example.com/callback"],
+                "post_logout_redirect_uris": ["https://example.com/logged-out"],
+                "contacts": ["contact@example.com"],
+                "token_endpoint_auth_method": "none",
+                "response_types": ["code"],
+                "grant_types": ["authorization_code"],
+            }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::CREATED);
+        let ClientRegistrationResponse { client_id, .. } = response.json();
+        client_id
+    }
+
+    /// Provision a user with an active browser session, and save it in the
+    /// cookies
+    async fn browser_session(state: &TestState, cookies: &CookieHelper) -> BrowserSession {
+        let mut repo = state.repository().await.unwrap();
+        let user = repo
+            .user()
+            .add(&mut state.rng(), &state.clock, "alice".to_owned())
+            .await
+            .unwrap();
+        let browser_session = repo
+            .browser_session()
+            .add(&mut state.rng(), &state.clock, &user, None, None, None)
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        let cookie_jar = state.cookie_jar();
+        let cookie_jar = cookie_jar.set_session(&state.clock, &browser_session, false);
+        cookies.import(cookie_jar);
+
+        browser_session
+    }
+
+    /// Sign an ID token for the given client and browser session, like the
+    /// token endpoint would
+    fn id_token(state: &TestState, client_id: &str, browser_session: &BrowserSession) -> String {
+        let mut claims = HashMap::new();
+        let now = state.clock.now();
+        claims::ISS
+            .insert(&mut claims, state.url_builder.oidc_issuer().to_string())
+            .unwrap();
+        claims::SUB
+            .insert(&mut claims, &browser_session.user.sub)
+            .unwrap();
+        claims::AUD
+            .insert(&mut claims, client_id.to_owned())
+            .unwrap();
+        claims::IAT.insert(&mut claims, now).unwrap();
+        claims::EXP.insert(&mut claims, now).unwrap();
+        claims::SID
+            .insert(&mut claims, browser_session.id.to_string())
+            .unwrap();
+
+        let alg = JsonWebSignatureAlg::Rs256;
+        let key = state.key_store.signing_key_for_algorithm(&alg).unwrap();
+        let signer = key.params().signing_key_for_alg(&alg).unwrap();
+        let header = JsonWebSignatureHeader::new(alg).with_kid(key.kid().unwrap());
+        Jwt::sign_with_rng(&mut state.rng(), header, claims, &signer)
+            .unwrap()
+            .into_string()
+    }
+
+    async fn is_session_active(state: &TestState, browser_session: &BrowserSession) -> bool {
+        let mut repo = state.repository().await.unwrap();
+        let session = repo
+            .browser_session()
+            .lookup(&state.clock, browser_session.id)
+            .await
+            .unwrap()
+            .unwrap();
+        repo.cancel().await.unwrap();
+        session.active()
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_end_session_redirect(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let client_id = register_client(&state).await;
+        let cookies = CookieHelper::new();
+        let browser_session = browser_session(&state, &cookies).await;
+        let id_token = id_token(&state, &client_id, &browser_session);
+
+        let request = Request::get(format!(
+            "{}?id_token_hint={id_token}&post_logout_redirect_uri=https://example.com/logged-out&state=abc",
+            mas_router::OAuth2EndSessionEndpoint::PATH,
+        ))
+        .empty();
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::SEE_OTHER);
+        response.assert_header_value(LOCATION, "https://example.com/logged-out?state=abc");
+
+        // The session cookie is cleared, and the session ended
+        assert!(response.headers().contains_key(SET_COOKIE));
+        assert!(!is_session_active(&state, &browser_session).await);
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_end_session_form_post(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let client_id = register_client(&state).await;
+        let cookies = CookieHelper::new();
+        let browser_session = browser_session(&state, &cookies).await;
+        let id_token = id_token(&state, &client_id, &browser_session);
+
+        let request =
+            Request::post(mas_router::OAuth2EndSessionEndpoint::PATH).form(serde_json::json!({
+                "id_token_hint": id_token,
+                "post_logout_redirect_uri": "https://example.com/logged-out",
+            }));
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::SEE_OTHER);
+        response.assert_header_value(LOCATION, "https://example.com/logged-out");
+        assert!(!is_session_active(&state, &browser_session).await);
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_end_session_unknown_redirect_uri(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let client_id = register_client(&state).await;
+        let cookies = CookieHelper::new();
+        let browser_session = browser_session(&state, &cookies).await;
+        let id_token = id_token(&state, &client_id, &browser_session);
+
+        let request = Request::get(format!(
+            "{}?id_token_hint={id_token}&post_logout_redirect_uri=https://example.com/other",
+            mas_router::OAuth2EndSessionEndpoint::PATH,
+        ))
+        .empty();
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+
+        // The session is left untouched
+        assert!(is_session_active(&state, &browser_session).await);
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_end_session_logged_out_page(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let client_id = register_client(&state).await;
+        let cookies = CookieHelper::new();
+        let browser_session = browser_session(&state, &cookies).await;
+        let id_token = id_token(&state, &client_id, &browser_session);
+
+        let request = Request::get(format!(
+            "{}?id_token_hint={id_token}&state=abc",
+            mas_router::OAuth2EndSessionEndpoint::PATH,
+        ))
+        .empty();
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        assert!(response.body().contains("Signed out"));
+        assert!(!is_session_active(&state, &browser_session).await);
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_end_session_without_cookie(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let client_id = register_client(&state).await;
+        let browser_session = browser_session(&state, &CookieHelper::new()).await;
+        let id_token = id_token(&state, &client_id, &browser_session);
+
+        // The session the ID token was issued for is ended, even without the
+        // session cookie
+        let request = Request::get(format!(
+            "{}?id_token_hint={id_token}",
+            mas_router::OAuth2EndSessionEndpoint::PATH,
+        ))
+        .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        assert!(!is_session_active(&state, &browser_session).await);
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_end_session_invalid_id_token_hint(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let cookies = CookieHelper::new();
+        let browser_session = browser_session(&state, &cookies).await;
+
+        // Without an ID token hint, nothing happens
+        let request = Request::get(mas_router::OAuth2EndSessionEndpoint::PATH).empty();
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+
+        // Same with an ID token which wasn't issued by us
+        let request = Request::get(format!(
+            "{}?id_token_hint=eyJhbGciOiJub25lIn0.eyJzdWIiOiJhbGljZSJ9.",
+            mas_router::OAuth2EndSessionEndpoint::PATH,
+        ))
+        .empty();
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+
+        assert!(is_session_active(&state, &browser_session).await);
+    }
+}
@@ -12,31 +12,51 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use axum::{extract::State, response::IntoResponse, Json};
-use hyper::StatusCode;
+use axum::{
+    extract::State,
+    response::{IntoResponse, Response},
+    Json, TypedHeader,
+};
+use chrono::{DateTime, Utc};
+use headers::ContentType;
+use hyper::{HeaderMap, StatusCode};
 use mas_axum_utils::{
     client_authorization::{ClientAuthorization, CredentialsVerificationError},
     http_client_factory::HttpClientFactory,
     sentry::SentryEventID,
+    user_authorization::stored_token,
 };
 use mas_data_model::{TokenFormatError, TokenType};
-use mas_iana::oauth::{OAuthClientAuthenticationMethod, OAuthTokenTypeHint};
-use mas_keystore::Encrypter;
+use mas_iana::{
+    jose::JsonWebSignatureAlg,
+    oauth::{OAuthClientAuthenticationMethod, OAuthTokenTypeHint},
+};
+use mas_jose::{
+    constraints::Constrainable,
+    jwt::{JsonWebSignatureHeader, Jwt},
+};
+use mas_keystore::{Encrypter, Keystore};
+use mas_router::UrlBuilder;
 use mas_storage::{
     compat::{CompatAccessTokenRepository, CompatRefreshTokenRepository, CompatSessionRepository},
     oauth2::{OAuth2AccessTokenRepository, OAuth2RefreshTokenRepository, OAuth2SessionRepository},
     user::UserRepository,
-    BoxClock, BoxRepository, Clock,
+    BoxClock, BoxRepository, BoxRng, Clock,
 };
 use oauth2_types::{
     errors::{ClientError, ClientErrorCode},
-    requests::{IntrospectionRequest, IntrospectionResponse},
+    requests::{Confirmation, IntrospectionRequest, IntrospectionResponse},
     scope::ScopeToken,
 };
+use serde::Serialize;
+use serde_with::{serde_as, TimestampSeconds};
 use thiserror::Error;
+use url::Url;
 
 use crate::{impl_from_error_for_route, ActivityTracker};
 
+use super::accepts_media_type;
+
 #[derive(Debug, Error)]
 pub enum RouteError {
     /// An internal error occurred.
@@ -71,6 +91,11 @@ pub enum RouteError {
     #[error("invalid oauth session")]
     InvalidOAuthSession,
 
+    /// The token is restricted to resources the resource server doesn't
+    /// serve.
+    #[error("token is restricted to other resources")]
+    WrongAudience,
+
     /// The OAuth session could not be found in the database.
     #[error("unknown oauth session")]
     CantLoadOAuthSession,
@@ -92,6 +117,9 @@ pub enum RouteError {
     #[error("bad request")]
     BadRequest,
 
+    #[error("no suitable key found for signing")]
+    InvalidSigningKey,
+
     #[error(transparent)]
     ClientCredentialsVerification(#[from] CredentialsVerificationError),
 }
@@ -103,7 +131,8 @@ impl IntoResponse for RouteError {
             e @ (Self::Internal(_)
             | Self::CantLoadCompatSession
             | Self::CantLoadOAuthSession
-            | Self::CantLoadUser) => (
+            | Self::CantLoadUser
+            | Self::InvalidSigningKey) => (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 Json(
                     ClientError::from(ClientErrorCode::ServerError).with_description(e.to_string()),
@@ -129,6 +158,7 @@ impl IntoResponse for RouteError {
             | Self::InvalidUser
             | Self::InvalidCompatSession
             | Self::InvalidOAuthSession
+            | Self::WrongAudience
             | Self::InvalidTokenFormat(_) => Json(INACTIVE).into_response(),
             Self::NotAllowed => (
                 StatusCode::UNAUTHORIZED,
@@ -146,7 +176,27 @@ impl IntoResponse for RouteError {
     }
 }
 
+impl RouteError {
+    /// Whether this error means that the token is not active, in which case
+    /// the response is an inactive introspection response
+    fn is_inactive(&self) -> bool {
+        matches!(
+            self,
+            Self::UnknownToken(_)
+                | Self::UnexpectedTokenType
+                | Self::InvalidToken(_)
+                | Self::InvalidUser
+                | Self::InvalidCompatSession
+                | Self::InvalidOAuthSession
+                | Self::WrongAudience
+                | Self::InvalidTokenFormat(_)
+        )
+    }
+}
+
 impl_from_error_for_route!(mas_storage::RepositoryError);
+impl_from_error_for_route!(mas_keystore::WrongAlgorithmError);
+impl_from_error_for_route!(mas_jose::jwt::JwtSignatureError);
 
 const INACTIVE: IntrospectionResponse = IntrospectionResponse {
     active: false,
@@ -161,8 +211,36 @@ const INACTIVE: IntrospectionResponse = IntrospectionResponse {
     aud: None,
     iss: None,
     jti: None,
+    device_id: None,
+    cnf: None,
 };
 
+/// The claims of a signed introspection response, as per [RFC 9701]
+///
+/// [RFC 9701]: https://www.rfc-editor.org/rfc/rfc9701
+#[serde_as]
+#[derive(Serialize)]
+struct SignedIntrospectionResponse {
+    iss: String,
+    aud: String,
+    #[serde_as(as = "TimestampSeconds")]
+    iat: DateTime<Utc>,
+    token_introspection: IntrospectionResponse,
+}
+
+/// The media type used to ask for and send signed introspection responses
+const TOKEN_INTROSPECTION_JWT: &str = "application/token-introspection+jwt";
+
+/// The audience of a token restricted to the given resources, if it is
+/// restricted
+fn audience(resource: &[Url]) -> Option<Vec<String>> {
+    if resource.is_empty() {
+        None
+    } else {
+        Some(resource.iter().map(Url::to_string).collect())
+    }
+}
+
 const API_SCOPE: ScopeToken = ScopeToken::from_static("urn:matrix:org.matrix.msc2967.client:api:*");
 const SYNAPSE_ADMIN_SCOPE: ScopeToken = ScopeToken::from_static("urn:synapse:admin:*");
 
@@ -174,13 +252,17 @@ const SYNAPSE_ADMIN_SCOPE: ScopeToken = ScopeToken::from_static("urn:synapse:adm
 )]
 #[allow(clippy::too_many_lines)]
 pub(crate) async fn post(
+    mut rng: BoxRng,
     clock: BoxClock,
     State(http_client_factory): State<HttpClientFactory>,
     mut repo: BoxRepository,
     activity_tracker: ActivityTracker,
     State(encrypter): State<Encrypter>,
+    State(key_store): State<Keystore>,
+    State(url_builder): State<UrlBuilder>,
+    headers: HeaderMap,
     client_authorization: ClientAuthorization<IntrospectionRequest>,
-) -> Result<impl IntoResponse, RouteError> {
+) -> Result<Response, RouteError> {
     let client = client_authorization
         .credentials
         .fetch(&mut repo)
@@ -188,8 +270,14 @@ pub(crate) async fn post(
         .unwrap()
         .ok_or(RouteError::ClientNotFound)?;
 
-    let method = match &client.token_endpoint_auth_method {
+    // Only resource servers, which have an authentication method set for the
+    // introspection endpoint, are allowed to introspect tokens
+    let method = match &client.introspection_endpoint_auth_method {
         None | Some(OAuthClientAuthenticationMethod::None) => {
+            tracing::warn!(
+                client.id = %client.id,
+                "Client tried to introspect a token without an introspection_endpoint_auth_method set"
+            );
             return Err(RouteError::NotAllowed);
         }
         Some(c) => c,
@@ -204,273 +292,349 @@ pub(crate) async fn post(
         return Err(RouteError::BadRequest);
     };
 
-    let token = &form.token;
-    let token_type = TokenType::check(token)?;
-    if let Some(hint) = form.token_type_hint {
-        if token_type != hint {
-            return Err(RouteError::UnexpectedTokenType);
+    // Resource servers can ask for a signed response
+    let signed = accepts_media_type(&headers, TOKEN_INTROSPECTION_JWT);
+
+    // One day, we will have try blocks
+    let res: Result<IntrospectionResponse, RouteError> = async {
+        // JWT access tokens are introspected as the opaque access token they
+        // were issued for
+        let token = stored_token(
+            &mut repo,
+            &clock,
+            &key_store,
+            &url_builder.oidc_issuer(),
+            &form.token,
+        )
+        .await?
+        .ok_or(RouteError::UnknownToken(TokenType::AccessToken))?;
+        let token = &*token;
+        let token_type = TokenType::check(token)?;
+        if let Some(hint) = form.token_type_hint {
+            if token_type != hint {
+                return Err(RouteError::UnexpectedTokenType);
+            }
         }
-    }
 
-    // XXX: we should get the IP from the client introspecting the token
-    let ip = None;
+        // XXX: we should get the IP from the client introspecting the token
+        let ip = None;
 
-    let reply = match token_type {
-        TokenType::AccessToken => {
-            let access_token = repo
-                .oauth2_access_token()
-                .find_by_token(token)
-                .await?
-                .ok_or(RouteError::UnknownToken(TokenType::AccessToken))?;
+        let reply = match token_type {
+            TokenType::AccessToken => {
+                // Load the token, its session and its user at once, as this is a hot path
+                let (access_token, session, user) = repo
+                    .oauth2_access_token()
+                    .lookup_for_introspection(token)
+                    .await?
+                    .ok_or(RouteError::UnknownToken(TokenType::AccessToken))?;
 
-            if !access_token.is_valid(clock.now()) {
-                return Err(RouteError::InvalidToken(TokenType::AccessToken));
-            }
+                if !access_token.is_valid(clock.now()) {
+                    return Err(RouteError::InvalidToken(TokenType::AccessToken));
+                }
 
-            let session = repo
-                .oauth2_session()
-                .lookup(access_token.session_id)
-                .await?
-                .ok_or(RouteError::InvalidOAuthSession)?;
+                if !session.is_valid() {
+                    return Err(RouteError::InvalidOAuthSession);
+                }
 
-            if !session.is_valid() {
-                return Err(RouteError::InvalidOAuthSession);
+                // Access tokens restricted to some resources are only active for the
+                // resource servers serving one of them
+                let resource = access_token.resource(&session.resource);
+                if !resource.is_empty()
+                    && !resource
+                        .iter()
+                        .any(|resource| client.allowed_resources.contains(resource))
+                {
+                    return Err(RouteError::WrongAudience);
+                }
+                let aud = audience(resource);
+
+                // The session might not have a user on it (for Client Credentials grants for
+                // example)
+                let (sub, username) = if let Some(user) = user {
+                    if !user.is_valid() {
+                        return Err(RouteError::InvalidUser);
+                    }
+
+                    (Some(user.sub), Some(user.username))
+                } else {
+                    (None, None)
+                };
+
+                activity_tracker
+                    .record_oauth2_session(&clock, &session, ip)
+                    .await;
+
+                // The access token might have been issued with a narrower scope than its
+                // session
+                let scope = access_token.scope(&session.scope).clone();
+
+                IntrospectionResponse {
+                    active: true,
+                    scope: Some(scope),
+                    client_id: Some(session.client_id.to_string()),
+                    username,
+                    token_type: Some(OAuthTokenTypeHint::AccessToken),
+                    exp: access_token.expires_at,
+                    iat: Some(access_token.created_at),
+                    nbf: Some(access_token.created_at),
+                    sub,
+                    aud,
+                    iss: None,
+                    jti: Some(access_token.jti()),
+                    device_id: None,
+                    cnf: access_token
+                        .dpop_jkt
+                        .map(|jkt| Confirmation { jkt: Some(jkt) }),
+                }
             }
 
-            // The session might not have a user on it (for Client Credentials grants for
-            // example), so we're optionally fetching the user
-            let (sub, username) = if let Some(user_id) = session.user_id {
-                let user = repo
-                    .user()
-                    .lookup(user_id)
+            TokenType::RefreshToken => {
+                let refresh_token = repo
+                    .oauth2_refresh_token()
+                    .find_by_token(token)
                     .await?
-                    .ok_or(RouteError::CantLoadUser)?;
+                    .ok_or(RouteError::UnknownToken(TokenType::RefreshToken))?;
 
-                if !user.is_valid() {
-                    return Err(RouteError::InvalidUser);
+                if !refresh_token.is_valid() {
+                    return Err(RouteError::InvalidToken(TokenType::RefreshToken));
                 }
 
-                (Some(user.sub), Some(user.username))
-            } else {
-                (None, None)
-            };
-
-            activity_tracker
-                .record_oauth2_session(&clock, &session, ip)
-                .await;
-
-            IntrospectionResponse {
-                active: true,
-                scope: Some(session.scope),
-                client_id: Some(session.client_id.to_string()),
-                username,
-                token_type: Some(OAuthTokenTypeHint::AccessToken),
-                exp: access_token.expires_at,
-                iat: Some(access_token.created_at),
-                nbf: Some(access_token.created_at),
-                sub,
-                aud: None,
-                iss: None,
-                jti: Some(access_token.jti()),
-            }
-        }
+                let session = repo
+                    .oauth2_session()
+                    .lookup(refresh_token.session_id)
+                    .await?
+                    .ok_or(RouteError::CantLoadOAuthSession)?;
 
-        TokenType::RefreshToken => {
-            let refresh_token = repo
-                .oauth2_refresh_token()
-                .find_by_token(token)
-                .await?
-                .ok_or(RouteError::UnknownToken(TokenType::RefreshToken))?;
+                if !session.is_valid() {
+                    return Err(RouteError::InvalidOAuthSession);
+                }
 
-            if !refresh_token.is_valid() {
-                return Err(RouteError::InvalidToken(TokenType::RefreshToken));
+                // The session might not have a user on it (for Client Credentials grants for
+                // example), so we're optionally fetching the user
+                let (sub, username) = if let Some(user_id) = session.user_id {
+                    let user = repo
+                        .user()
+                        .lookup(user_id)
+                        .await?
+                        .ok_or(RouteError::CantLoadUser)?;
+
+                    if !user.is_valid() {
+                        return Err(RouteError::InvalidUser);
+                    }
+
+                    (Some(user.sub), Some(user.username))
+                } else {
+                    (None, None)
+                };
+
+                activity_tracker
+                    .record_oauth2_session(&clock, &session, ip)
+                    .await;
+
+                IntrospectionResponse {
+                    active: true,
+                    aud: audience(&session.resource),
+                    scope: Some(session.scope),
+                    client_id: Some(session.client_id.to_string()),
+                    username,
+                    token_type: Some(OAuthTokenTypeHint::RefreshToken),
+                    exp: None,
+                    iat: Some(refresh_token.created_at),
+                    nbf: Some(refresh_token.created_at),
+                    sub,
+                    iss: None,
+                    jti: Some(refresh_token.jti()),
+                    device_id: None,
+                    cnf: None,
+                }
             }
 
-            let session = repo
-                .oauth2_session()
-                .lookup(refresh_token.session_id)
-                .await?
-                .ok_or(RouteError::CantLoadOAuthSession)?;
+            TokenType::CompatAccessToken => {
+                let access_token = repo
+                    .compat_access_token()
+                    .find_by_token(token)
+                    .await?
+                    .ok_or(RouteError::UnknownToken(TokenType::CompatAccessToken))?;
 
-            if !session.is_valid() {
-                return Err(RouteError::InvalidOAuthSession);
-            }
+                if !access_token.is_valid(clock.now()) {
+                    return Err(RouteError::InvalidToken(TokenType::CompatAccessToken));
+                }
+
+                let session = repo
+                    .compat_session()
+                    .lookup(access_token.session_id)
+                    .await?
+                    .ok_or(RouteError::CantLoadCompatSession)?;
+
+                if !session.is_valid() {
+                    return Err(RouteError::InvalidCompatSession);
+                }
 
-            // The session might not have a user on it (for Client Credentials grants for
-            // example), so we're optionally fetching the user
-            let (sub, username) = if let Some(user_id) = session.user_id {
                 let user = repo
                     .user()
-                    .lookup(user_id)
+                    .lookup(session.user_id)
                     .await?
                     .ok_or(RouteError::CantLoadUser)?;
 
                 if !user.is_valid() {
-                    return Err(RouteError::InvalidUser);
+                    return Err(RouteError::InvalidUser)?;
                 }
 
-                (Some(user.sub), Some(user.username))
-            } else {
-                (None, None)
-            };
-
-            activity_tracker
-                .record_oauth2_session(&clock, &session, ip)
-                .await;
-
-            IntrospectionResponse {
-                active: true,
-                scope: Some(session.scope),
-                client_id: Some(session.client_id.to_string()),
-                username,
-                token_type: Some(OAuthTokenTypeHint::RefreshToken),
-                exp: None,
-                iat: Some(refresh_token.created_at),
-                nbf: Some(refresh_token.created_at),
-                sub,
-                aud: None,
-                iss: None,
-                jti: Some(refresh_token.jti()),
-            }
-        }
-
-        TokenType::CompatAccessToken => {
-            let access_token = repo
-                .compat_access_token()
-                .find_by_token(token)
-                .await?
-                .ok_or(RouteError::UnknownToken(TokenType::CompatAccessToken))?;
-
-            if !access_token.is_valid(clock.now()) {
-                return Err(RouteError::InvalidToken(TokenType::CompatAccessToken));
+                // Grant the synapse admin scope if the session has the admin flag set.
+                let synapse_admin = session.is_synapse_admin.then_some(SYNAPSE_ADMIN_SCOPE);
+                let device_scope = session.device.to_scope_token();
+                let scope = [API_SCOPE, device_scope]
+                    .into_iter()
+                    .chain(synapse_admin)
+                    .collect();
+
+                activity_tracker
+                    .record_compat_session(&clock, &session, ip)
+                    .await;
+
+                IntrospectionResponse {
+                    active: true,
+                    scope: Some(scope),
+                    client_id: Some("legacy".into()),
+                    username: Some(user.username),
+                    token_type: Some(OAuthTokenTypeHint::AccessToken),
+                    exp: access_token.expires_at,
+                    iat: Some(access_token.created_at),
+                    nbf: Some(access_token.created_at),
+                    sub: Some(user.sub),
+                    aud: None,
+                    iss: None,
+                    jti: None,
+                    device_id: Some(session.device.as_str().to_owned()),
+                    cnf: None,
+                }
             }
 
-            let session = repo
-                .compat_session()
-                .lookup(access_token.session_id)
-                .await?
-                .ok_or(RouteError::CantLoadCompatSession)?;
+            TokenType::CompatRefreshToken => {
+                let refresh_token = repo
+                    .compat_refresh_token()
+                    .find_by_token(token)
+                    .await?
+                    .ok_or(RouteError::UnknownToken(TokenType::CompatRefreshToken))?;
 
-            if !session.is_valid() {
-                return Err(RouteError::InvalidCompatSession);
-            }
+                if !refresh_token.is_valid() {
+                    return Err(RouteError::InvalidToken(TokenType::CompatRefreshToken));
+                }
 
-            let user = repo
-                .user()
-                .lookup(session.user_id)
-                .await?
-                .ok_or(RouteError::CantLoadUser)?;
+                let session = repo
+                    .compat_session()
+                    .lookup(refresh_token.session_id)
+                    .await?
+                    .ok_or(RouteError::CantLoadCompatSession)?;
 
-            if !user.is_valid() {
-                return Err(RouteError::InvalidUser)?;
-            }
+                if !session.is_valid() {
+                    return Err(RouteError::InvalidCompatSession);
+                }
 
-            // Grant the synapse admin scope if the session has the admin flag set.
-            let synapse_admin = session.is_synapse_admin.then_some(SYNAPSE_ADMIN_SCOPE);
-            let device_scope = session.device.to_scope_token();
-            let scope = [API_SCOPE, device_scope]
-                .into_iter()
-                .chain(synapse_admin)
-                .collect();
-
-            activity_tracker
-                .record_compat_session(&clock, &session, ip)
-                .await;
-
-            IntrospectionResponse {
-                active: true,
-                scope: Some(scope),
-                client_id: Some("legacy".into()),
-                username: Some(user.username),
-                token_type: Some(OAuthTokenTypeHint::AccessToken),
-                exp: access_token.expires_at,
-                iat: Some(access_token.created_at),
-                nbf: Some(access_token.created_at),
-                sub: Some(user.sub),
-                aud: None,
-                iss: None,
-                jti: None,
-            }
-        }
+                let user = repo
+                    .user()
+                    .lookup(session.user_id)
+                    .await?
+                    .ok_or(RouteError::CantLoadUser)?;
 
-        TokenType::CompatRefreshToken => {
-            let refresh_token = repo
-                .compat_refresh_token()
-                .find_by_token(token)
-                .await?
-                .ok_or(RouteError::UnknownToken(TokenType::CompatRefreshToken))?;
+                if !user.is_valid() {
+                    return Err(RouteError::InvalidUser)?;
+                }
 
-            if !refresh_token.is_valid() {
-                return Err(RouteError::InvalidToken(TokenType::CompatRefreshToken));
+                // Grant the synapse admin scope if the session has the admin flag set.
+                let synapse_admin = session.is_synapse_admin.then_some(SYNAPSE_ADMIN_SCOPE);
+                let device_scope = session.device.to_scope_token();
+                let scope = [API_SCOPE, device_scope]
+                    .into_iter()
+                    .chain(synapse_admin)
+                    .collect();
+
+                activity_tracker
+                    .record_compat_session(&clock, &session, ip)
+                    .await;
+
+                IntrospectionResponse {
+                    active: true,
+                    scope: Some(scope),
+                    client_id: Some("legacy".into()),
+                    username: Some(user.username),
+                    token_type: Some(OAuthTokenTypeHint::RefreshToken),
+                    exp: None,
+                    iat: Some(refresh_token.created_at),
+                    nbf: Some(refresh_token.created_at),
+                    sub: Some(user.sub),
+                    aud: None,
+                    iss: None,
+                    jti: None,
+                    device_id: Some(session.device.as_str().to_owned()),
+                    cnf: None,
+                }
             }
+        };
 
-            let session = repo
-                .compat_session()
-                .lookup(refresh_token.session_id)
-                .await?
-                .ok_or(RouteError::CantLoadCompatSession)?;
-
-            if !session.is_valid() {
-                return Err(RouteError::InvalidCompatSession);
-            }
+        Ok(reply)
+    }
+    .await;
 
-            let user = repo
-                .user()
-                .lookup(session.user_id)
-                .await?
-                .ok_or(RouteError::CantLoadUser)?;
+    let reply = match res {
+        Ok(reply) => reply,
+        // Inactive tokens also get a signed response if one was asked for
+        Err(e) if signed && e.is_inactive() => INACTIVE,
+        Err(e) => return Err(e),
+    };
 
-            if !user.is_valid() {
-                return Err(RouteError::InvalidUser)?;
-            }
+    if !signed {
+        return Ok(Json(reply).into_response());
+    }
 
-            // Grant the synapse admin scope if the session has the admin flag set.
-            let synapse_admin = session.is_synapse_admin.then_some(SYNAPSE_ADMIN_SCOPE);
-            let device_scope = session.device.to_scope_token();
-            let scope = [API_SCOPE, device_scope]
-                .into_iter()
-                .chain(synapse_admin)
-                .collect();
-
-            activity_tracker
-                .record_compat_session(&clock, &session, ip)
-                .await;
-
-            IntrospectionResponse {
-                active: true,
-                scope: Some(scope),
-                client_id: Some("legacy".into()),
-                username: Some(user.username),
-                token_type: Some(OAuthTokenTypeHint::RefreshToken),
-                exp: None,
-                iat: Some(refresh_token.created_at),
-                nbf: Some(refresh_token.created_at),
-                sub: Some(user.sub),
-                aud: None,
-                iss: None,
-                jti: None,
-            }
-        }
+    let alg = JsonWebSignatureAlg::Rs256;
+    let key = key_store
+        .signing_key_for_algorithm(&alg)
+        .ok_or(RouteError::InvalidSigningKey)?;
+
+    let signer = key.params().signing_key_for_alg(&alg)?;
+    let header = JsonWebSignatureHeader::new(alg)
+        .with_kid(key.kid().ok_or(RouteError::InvalidSigningKey)?)
+        .with_typ("token-introspection+jwt".to_owned());
+
+    let claims = SignedIntrospectionResponse {
+        iss: url_builder.oidc_issuer().to_string(),
+        aud: client.client_id,
+        iat: clock.now(),
+        token_introspection: reply,
     };
 
-    Ok(Json(reply))
+    let token = Jwt::sign_with_rng(&mut rng, header, claims, &signer)?;
+    let content_type = ContentType::from(TOKEN_INTROSPECTION_JWT.parse::<mime::Mime>().unwrap());
+    Ok((TypedHeader(content_type), token.into_string()).into_response())
 }
 
 #[cfg(test)]
 mod tests {
     use chrono::Duration;
-    use hyper::{Request, StatusCode};
-    use mas_data_model::{AccessToken, RefreshToken};
-    use mas_iana::oauth::OAuthTokenTypeHint;
-    use mas_router::{OAuth2Introspection, OAuth2RegistrationEndpoint, SimpleRoute};
-    use mas_storage::Clock;
+    use hyper::{
+        header::{ACCEPT, CONTENT_TYPE},
+        Request, StatusCode,
+    };
+    use mas_data_model::{AccessToken, AccessTokenFormat, RedirectUriMatching, RefreshToken};
+    use mas_iana::oauth::{
+        OAuthAuthorizationEndpointResponseType, OAuthClientAuthenticationMethod, OAuthTokenTypeHint,
+    };
+    use mas_jose::jwt::{JsonWebSignatureHeader, Jwt};
+    use mas_router::{
+        OAuth2Introspection, OAuth2RegistrationEndpoint, OAuth2Revocation, OAuth2TokenEndpoint,
+        SimpleRoute,
+    };
+    use mas_storage::{oauth2::UpsertStaticClientParams, Clock};
     use oauth2_types::{
         registration::ClientRegistrationResponse,
-        requests::IntrospectionResponse,
+        requests::{AccessTokenResponse, IntrospectionResponse},
         scope::{Scope, OPENID},
     };
-    use serde_json::json;
+    use serde_json::{json, Value};
     use sqlx::PgPool;
+    use ulid::Ulid;
+    use url::Url;
     use zeroize::Zeroizing;
 
     use crate::{
@@ -478,24 +642,62 @@ mod tests {
         test_utils::{init_tracing, RequestBuilderExt, ResponseExt, TestState},
     };
 
+    /// Provision a static client which is allowed to introspect tokens, serving
+    /// the given resources, and return its client ID and secret
+    async fn provision_resource_server(
+        state: &TestState,
+        allowed_resources: Vec<Url>,
+    ) -> (String, String) {
+        let client_secret = "introspecting-secret";
+        let encrypted_client_secret = state
+            .encrypter
+            .encrypt_to_string(client_secret.as_bytes())
+            .unwrap();
+
+        let mut repo = state.repository().await.unwrap();
+        let client_id = Ulid::from_datetime_with_source(state.clock.now().into(), &mut state.rng());
+        repo.oauth2_client()
+            .upsert_static(UpsertStaticClientParams {
+                client_id,
+                client_auth_method: OAuthClientAuthenticationMethod::ClientSecretBasic,
+                encrypted_client_secret: Some(encrypted_client_secret),
+                jwks: None,
+                jwks_uri: None,
+                redirect_uris: Vec::new(),
+                redirect_uri_matching: RedirectUriMatching::Exact,
+                response_types: vec![OAuthAuthorizationEndpointResponseType::Code],
+                allow_token_exchange: false,
+                allow_client_credentials: false,
+                client_credentials_scope: None,
+                allowed_resources,
+                require_signed_request_object: false,
+                require_pkce: None,
+                access_token_ttl: None,
+                refresh_token_ttl: None,
+                access_token_format: AccessTokenFormat::Opaque,
+                introspection_endpoint_auth_method: Some(
+                    OAuthClientAuthenticationMethod::ClientSecretBasic,
+                ),
+                post_logout_redirect_uris: Vec::new(),
+                backchannel_logout_uri: None,
+                backchannel_logout_session_required: false,
+                trusted: false,
+            })
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        (client_id.to_string(), client_secret.to_owned())
+    }
+
     #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
     async fn test_introspect_oauth_tokens(pool: PgPool) {
         init_tracing();
         let state = TestState::from_pool(pool).await.unwrap();
 
         // Provision a client which will be used to do introspection requests
-        let request = Request::post(OAuth2RegistrationEndpoint::PATH).json(json!({
-            "contacts": ["hello@introspecting.com"],
-            "client_uri": "https://introspecting.com/",
-            "grant_types": [],
-            "token_endpoint_auth_method": "client_secret_basic",
-        }));
-
-        let response = state.request(request).await;
-        response.assert_status(StatusCode::CREATED);
-        let client: ClientRegistrationResponse = response.json();
-        let introspecting_client_id = client.client_id;
-        let introspecting_client_secret = client.client_secret.unwrap();
+        let (introspecting_client_id, introspecting_client_secret) =
+            provision_resource_server(&state, Vec::new()).await;
 
         // Provision a client which will be used to generate tokens
         let request = Request::post(OAuth2RegistrationEndpoint::PATH).json(json!({
@@ -528,7 +730,7 @@ mod tests {
 
         let browser_session = repo
             .browser_session()
-            .add(&mut state.rng(), &state.clock, &user, None)
+            .add(&mut state.rng(), &state.clock, &user, None, None, None)
             .await
             .unwrap();
 
@@ -568,6 +770,7 @@ mod tests {
         assert_eq!(response.username, Some("alice".to_owned()));
         assert_eq!(response.client_id, Some(client_id.clone()));
         assert_eq!(response.token_type, Some(OAuthTokenTypeHint::AccessToken));
+        assert_eq!(response.device_id, None);
         assert_eq!(response.scope, Some(Scope::from_iter([OPENID])));
 
         // Do the same request, but with a token_type_hint
@@ -678,23 +881,123 @@ mod tests {
     }
 
     #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
-    async fn test_introspect_compat_tokens(pool: PgPool) {
+    async fn test_introspect_audience_restricted_tokens(pool: PgPool) {
         init_tracing();
         let state = TestState::from_pool(pool).await.unwrap();
 
-        // Provision a client which will be used to do introspection requests
+        let resource_a = Url::parse("https://a.example.com/").unwrap();
+        let resource_b = Url::parse("https://b.example.com/").unwrap();
+
+        // Provision two resource servers, each serving a different resource
+        let (client_a_id, client_a_secret) =
+            provision_resource_server(&state, vec![resource_a.clone()]).await;
+        let (client_b_id, client_b_secret) =
+            provision_resource_server(&state, vec![resource_b.clone()]).await;
+
+        // Provision a client which will be used to generate tokens
         let request = Request::post(OAuth2RegistrationEndpoint::PATH).json(json!({
-            "contacts": ["hello@introspecting.com"],
-            "client_uri": "https://introspecting.com/",
-            "grant_types": [],
-            "token_endpoint_auth_method": "client_secret_basic",
+            "contacts": ["hello@client.com"],
+            "client_uri": "https://client.com/",
+            "redirect_uris": ["https://client.com/"],
+            "response_types": ["code"],
+            "grant_types": ["authorization_code", "refresh_token"],
+            "token_endpoint_auth_method": "none",
         }));
 
         let response = state.request(request).await;
         response.assert_status(StatusCode::CREATED);
-        let client: ClientRegistrationResponse = response.json();
-        let introspecting_client_id = client.client_id;
-        let introspecting_client_secret = client.client_secret.unwrap();
+        let ClientRegistrationResponse { client_id, .. } = response.json();
+
+        let mut repo = state.repository().await.unwrap();
+        let user = repo
+            .user()
+            .add(&mut state.rng(), &state.clock, "alice".to_owned())
+            .await
+            .unwrap();
+
+        let client = repo
+            .oauth2_client()
+            .find_by_client_id(&client_id)
+            .await
+            .unwrap()
+            .unwrap();
+
+        let browser_session = repo
+            .browser_session()
+            .add(&mut state.rng(), &state.clock, &user, None, None, None)
+            .await
+            .unwrap();
+
+        // Provision a session restricted to the first resource
+        let session = repo
+            .oauth2_session()
+            .add_from_browser_session(
+                &mut state.rng(),
+                &state.clock,
+                &client,
+                &browser_session,
+                Scope::from_iter([OPENID]),
+            )
+            .await
+            .unwrap();
+        let session = repo
+            .oauth2_session()
+            .record_resource(session, vec![resource_a.clone()])
+            .await
+            .unwrap();
+
+        let (AccessToken { access_token, .. }, RefreshToken { refresh_token, .. }) =
+            generate_token_pair(
+                &mut state.rng(),
+                &state.clock,
+                &mut repo,
+                &session,
+                Duration::microseconds(5 * 60 * 1000 * 1000),
+            )
+            .await
+            .unwrap();
+
+        repo.save().await.unwrap();
+
+        // The resource server of the other resource sees the token as inactive
+        let request = Request::post(OAuth2Introspection::PATH)
+            .basic_auth(&client_b_id, &client_b_secret)
+            .form(json!({ "token": access_token }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let response: IntrospectionResponse = response.json();
+        assert!(!response.active);
+
+        // But the resource server of the resource sees it as active, with the
+        // resource as its audience
+        let request = Request::post(OAuth2Introspection::PATH)
+            .basic_auth(&client_a_id, &client_a_secret)
+            .form(json!({ "token": access_token }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let response: IntrospectionResponse = response.json();
+        assert!(response.active);
+        assert_eq!(response.aud, Some(vec![resource_a.to_string()]));
+
+        // The audience of the refresh token is reported as well
+        let request = Request::post(OAuth2Introspection::PATH)
+            .basic_auth(&client_b_id, &client_b_secret)
+            .form(json!({ "token": refresh_token }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let response: IntrospectionResponse = response.json();
+        assert!(response.active);
+        assert_eq!(response.aud, Some(vec![resource_a.to_string()]));
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_introspect_compat_tokens(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+
+        // Provision a client which will be used to do introspection requests
+        let (introspecting_client_id, introspecting_client_secret) =
+            provision_resource_server(&state, Vec::new()).await;
 
         // Provision a user with a password, so that we can use the password flow
         let mut repo = state.repository().await.unwrap();
@@ -757,6 +1060,7 @@ mod tests {
         assert_eq!(response.client_id, Some("legacy".to_owned()));
         assert_eq!(response.token_type, Some(OAuthTokenTypeHint::AccessToken));
         assert_eq!(response.scope, Some(expected_scope.clone()));
+        assert_eq!(response.device_id.as_deref(), Some(device_id));
 
         // Do the same request, but with a token_type_hint
         let request = Request::post(OAuth2Introspection::PATH)
@@ -815,8 +1119,9 @@ mod tests {
             .form(json!({ "token": access_token }));
         let response = state.request(request).await;
         response.assert_status(StatusCode::OK);
-        let response: IntrospectionResponse = response.json();
-        assert!(!response.active); // It shouldn't be active anymore
+        // It shouldn't be active anymore, and nothing else should be disclosed
+        let response: serde_json::Value = response.json();
+        assert_eq!(response, json!({ "active": false }));
 
         // But the refresh token should still be valid
         let request = Request::post(OAuth2Introspection::PATH)
@@ -827,4 +1132,291 @@ mod tests {
         let response: IntrospectionResponse = response.json();
         assert!(response.active);
     }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_introspect_not_allowed(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+
+        // A regular confidential client, without an introspection endpoint
+        // authentication method, shouldn't be able to introspect tokens
+        let request = Request::post(OAuth2RegistrationEndpoint::PATH).json(json!({
+            "contacts": ["hello@introspecting.com"],
+            "client_uri": "https://introspecting.com/",
+            "grant_types": [],
+            "token_endpoint_auth_method": "client_secret_basic",
+        }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::CREATED);
+        let client: ClientRegistrationResponse = response.json();
+        let client_id = client.client_id;
+        let client_secret = client.client_secret.unwrap();
+
+        let request = Request::post(OAuth2Introspection::PATH)
+            .basic_auth(&client_id, &client_secret)
+            .form(json!({ "token": "some_token" }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::UNAUTHORIZED);
+
+        // A resource server with the wrong secret shouldn't be able to either
+        let (client_id, _client_secret) = provision_resource_server(&state, Vec::new()).await;
+        let request = Request::post(OAuth2Introspection::PATH)
+            .basic_auth(&client_id, "wrong-secret")
+            .form(json!({ "token": "some_token" }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::UNAUTHORIZED);
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_introspect_signed_response(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+
+        let (introspecting_client_id, introspecting_client_secret) =
+            provision_resource_server(&state, Vec::new()).await;
+
+        // Provision a client which will be used to generate tokens
+        let request = Request::post(OAuth2RegistrationEndpoint::PATH).json(json!({
+            "contacts": ["hello@client.com"],
+            "client_uri": "https://client.com/",
+            "redirect_uris": ["https://client.com/"],
+            "response_types": ["code"],
+            "grant_types": ["authorization_code", "refresh_token"],
+            "token_endpoint_auth_method": "none",
+        }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::CREATED);
+        let ClientRegistrationResponse { client_id, .. } = response.json();
+
+        let mut repo = state.repository().await.unwrap();
+        let user = repo
+            .user()
+            .add(&mut state.rng(), &state.clock, "alice".to_owned())
+            .await
+            .unwrap();
+
+        let client = repo
+            .oauth2_client()
+            .find_by_client_id(&client_id)
+            .await
+            .unwrap()
+            .unwrap();
+
+        let browser_session = repo
+            .browser_session()
+            .add(&mut state.rng(), &state.clock, &user, None, None, None)
+            .await
+            .unwrap();
+
+        let session = repo
+            .oauth2_session()
+            .add_from_browser_session(
+                &mut state.rng(),
+                &state.clock,
+                &client,
+                &browser_session,
+                Scope::from_iter([OPENID]),
+            )
+            .await
+            .unwrap();
+
+        let (AccessToken { access_token, .. }, _) = generate_token_pair(
+            &mut state.rng(),
+            &state.clock,
+            &mut repo,
+            &session,
+            Duration::microseconds(5 * 60 * 1000 * 1000),
+        )
+        .await
+        .unwrap();
+
+        repo.save().await.unwrap();
+
+        // Ask for a signed response
+        let request = Request::post(OAuth2Introspection::PATH)
+            .header(ACCEPT, "application/token-introspection+jwt")
+            .basic_auth(&introspecting_client_id, &introspecting_client_secret)
+            .form(json!({ "token": access_token }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        response.assert_header_value(CONTENT_TYPE, "application/token-introspection+jwt");
+
+        let jwt: Jwt<'_, Value> = Jwt::try_from(response.body().as_str()).unwrap();
+        assert_eq!(jwt.header().typ(), Some("token-introspection+jwt"));
+        let claims = jwt.payload();
+        assert_eq!(claims["aud"], introspecting_client_id.as_str());
+        assert_eq!(claims["iss"], state.url_builder.oidc_issuer().as_str());
+        assert_eq!(claims["token_introspection"]["active"], true);
+        assert_eq!(claims["token_introspection"]["username"], "alice");
+        assert_eq!(
+            claims["token_introspection"]["client_id"],
+            client_id.as_str()
+        );
+
+        // Unknown tokens also get a signed, inactive response
+        let request = Request::post(OAuth2Introspection::PATH)
+            .header(ACCEPT, "application/token-introspection+jwt")
+            .basic_auth(&introspecting_client_id, &introspecting_client_secret)
+            .form(json!({ "token": "mat_unknown" }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        response.assert_header_value(CONTENT_TYPE, "application/token-introspection+jwt");
+
+        let jwt: Jwt<'_, Value> = Jwt::try_from(response.body().as_str()).unwrap();
+        assert_eq!(jwt.payload()["token_introspection"]["active"], false);
+
+        // Without the Accept header, the response is plain JSON
+        let request = Request::post(OAuth2Introspection::PATH)
+            .basic_auth(&introspecting_client_id, &introspecting_client_secret)
+            .form(json!({ "token": access_token }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let response: IntrospectionResponse = response.json();
+        assert!(response.active);
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_introspect_jwt_access_tokens(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+
+        let (introspecting_client_id, introspecting_client_secret) =
+            provision_resource_server(&state, Vec::new()).await;
+
+        // Provision a client which asks for JWT access tokens
+        let client_secret = "secret";
+        let encrypted_client_secret = state
+            .encrypter
+            .encrypt_to_string(client_secret.as_bytes())
+            .unwrap();
+
+        let mut repo = state.repository().await.unwrap();
+        let client_id = Ulid::from_datetime_with_source(state.clock.now().into(), &mut state.rng());
+        repo.oauth2_client()
+            .upsert_static(UpsertStaticClientParams {
+                client_id,
+                client_auth_method: OAuthClientAuthenticationMethod::ClientSecretPost,
+                encrypted_client_secret: Some(encrypted_client_secret),
+                jwks: None,
+                jwks_uri: None,
+                redirect_uris: Vec::new(),
+                redirect_uri_matching: RedirectUriMatching::Exact,
+                response_types: Vec::new(),
+                allow_token_exchange: false,
+                allow_client_credentials: true,
+                client_credentials_scope: None,
+                allowed_resources: Vec::new(),
+                require_signed_request_object: false,
+                require_pkce: None,
+                access_token_ttl: None,
+                refresh_token_ttl: None,
+                access_token_format: AccessTokenFormat::Jwt,
+                introspection_endpoint_auth_method: None,
+                post_logout_redirect_uris: Vec::new(),
+                backchannel_logout_uri: None,
+                backchannel_logout_session_required: false,
+                trusted: false,
+            })
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+        let client_id = client_id.to_string();
+
+        let request = Request::post(OAuth2TokenEndpoint::PATH).form(json!({
+            "grant_type": "client_credentials",
+            "client_id": client_id,
+            "client_secret": client_secret,
+        }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let AccessTokenResponse { access_token, .. } = response.json();
+
+        // The access token is a JWT, which refers to the stored access token by its
+        // ID
+        let jwt: Jwt<'_, Value> = Jwt::try_from(access_token.as_str()).unwrap();
+        assert_eq!(jwt.header().typ(), Some("at+jwt"));
+        let claims = jwt.payload();
+        assert_eq!(claims["iss"], state.url_builder.oidc_issuer().as_str());
+        assert_eq!(claims["sub"], client_id.as_str());
+        assert_eq!(claims["client_id"], client_id.as_str());
+        let jti = claims["jti"].as_str().unwrap().to_owned();
+        assert!(jti.parse::<Ulid>().is_ok());
+
+        // It can be introspected like an opaque token
+        let request = Request::post(OAuth2Introspection::PATH)
+            .basic_auth(&introspecting_client_id, &introspecting_client_secret)
+            .form(json!({ "token": access_token }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let response: IntrospectionResponse = response.json();
+        assert!(response.active);
+        assert_eq!(response.client_id, Some(client_id.clone()));
+        assert_eq!(response.token_type, Some(OAuthTokenTypeHint::AccessToken));
+
+        // But not once its signature is tampered with
+        let (unsigned, _signature) = access_token.rsplit_once('.').unwrap();
+        let request = Request::post(OAuth2Introspection::PATH)
+            .basic_auth(&introspecting_client_id, &introspecting_client_secret)
+            .form(json!({ "token": format!("{unsigned}.AAAA") }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let response: IntrospectionResponse = response.json();
+        assert!(!response.active);
+
+        // And its ID alone is not a token
+        let request = Request::post(OAuth2Introspection::PATH)
+            .basic_auth(&introspecting_client_id, &introspecting_client_secret)
+            .form(json!({ "token": jti }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let response: IntrospectionResponse = response.json();
+        assert!(!response.active);
+
+        // Nor are other JWTs signed by the service which refer to it, like JWTs of
+        // another type or from another issuer
+        let alg = state
+            .key_store
+            .available_signing_algorithms()
+            .into_iter()
+            .next()
+            .unwrap();
+        let key = state.key_store.signing_key_for_algorithm(&alg).unwrap();
+        let signer = key.params().signing_key_for_alg(&alg).unwrap();
+        let mut other_issuer = claims.clone();
+        other_issuer["iss"] = "https://other.example.com/".into();
+        for (typ, claims) in [("JWT", claims.clone()), ("at+jwt", other_issuer)] {
+            let header = JsonWebSignatureHeader::new(alg.clone())
+                .with_kid(key.kid().unwrap())
+                .with_typ(typ.to_owned());
+            let token = Jwt::sign_with_rng(&mut state.rng(), header, claims, &signer).unwrap();
+
+            let request = Request::post(OAuth2Introspection::PATH)
+                .basic_auth(&introspecting_client_id, &introspecting_client_secret)
+                .form(json!({ "token": token.as_str() }));
+            let response = state.request(request).await;
+            response.assert_status(StatusCode::OK);
+            let response: IntrospectionResponse = response.json();
+            assert!(!response.active);
+        }
+
+        // And revoked
+        let request = Request::post(OAuth2Revocation::PATH).form(json!({
+            "token": access_token,
+            "client_id": client_id,
+            "client_secret": client_secret,
+        }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+
+        // After which it is reported as inactive, even though it didn't expire
+        let request = Request::post(OAuth2Introspection::PATH)
+            .basic_auth(&introspecting_client_id, &introspecting_client_secret)
+            .form(json!({ "token": access_token }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let response: IntrospectionResponse = response.json();
+        assert!(!response.active);
+    }
 }
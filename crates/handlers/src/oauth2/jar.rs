@@ -0,0 +1,466 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Support for JWT-secured authorization requests, as defined by [RFC9101]
+//!
+//! [RFC9101]: https://www.rfc-editor.org/rfc/rfc9101
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+};
+
+use axum::BoxError;
+use chrono::{DateTime, Duration, Utc};
+use mas_axum_utils::http_client_factory::HttpClientFactory;
+use mas_data_model::{Client, JwksOrJwksUri};
+use mas_http::HttpServiceExt;
+use mas_iana::jose::JsonWebSignatureAlg;
+use mas_jose::{
+    claims::{self, ClaimError, TimeOptions},
+    jwk::PublicJsonWebKeySet,
+    jwt::{Jwt, JwtDecodeError},
+};
+use serde_json::Value;
+use thiserror::Error;
+use tower::{Service, ServiceExt};
+use url::Url;
+
+use crate::fetch_cache::FetchCache;
+
+/// The algorithms accepted to sign request objects
+pub const REQUEST_OBJECT_SIGNING_ALGORITHMS: [JsonWebSignatureAlg; 6] = [
+    JsonWebSignatureAlg::Rs256,
+    JsonWebSignatureAlg::Rs384,
+    JsonWebSignatureAlg::Rs512,
+    JsonWebSignatureAlg::Es256,
+    JsonWebSignatureAlg::Es384,
+    JsonWebSignatureAlg::Es512,
+];
+
+/// Claims of the request object which are not authorization request
+/// parameters
+const REGISTERED_CLAIMS: [&str; 4] = ["iat", "jti", "request", "request_uri"];
+
+#[derive(Debug, Error)]
+pub enum JarError {
+    #[error("could not decode the request object")]
+    Decode(#[from] JwtDecodeError),
+
+    #[error("unsupported request object signing algorithm")]
+    UnsupportedAlgorithm,
+
+    #[error("the client has no JSON Web Key Set to verify request objects")]
+    MissingJwks,
+
+    #[error("could not fetch the client JSON Web Key Set")]
+    JwksFetch(#[source] BoxError),
+
+    #[error("invalid request object signature")]
+    InvalidSignature,
+
+    #[error("invalid request object claims")]
+    Claim(#[from] ClaimError),
+
+    #[error("the request object was not issued for this client")]
+    ClientMismatch,
+}
+
+/// Verifies signed request objects, caching the JSON Web Key Sets fetched
+/// from the clients `jwks_uri`
+#[derive(Debug, Clone)]
+pub struct JarVerifier {
+    cache: FetchCache<Url, PublicJsonWebKeySet>,
+    ttl: Duration,
+}
+
+impl JarVerifier {
+    /// Create a new verifier, caching the fetched JSON Web Key Sets for the
+    /// given duration
+    #[must_use]
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            cache: FetchCache::default(),
+            ttl,
+        }
+    }
+
+    /// Verify a request object sent by a client, and return the authorization
+    /// request parameters it carries
+    ///
+    /// # Parameters
+    ///
+    /// * `http_client_factory`: The factory used to fetch the client JWKS
+    /// * `now`: The current time
+    /// * `issuer`: The issuer of this server, which must be the audience of
+    ///   the request object
+    /// * `client`: The client which sent the request
+    /// * `request`: The raw request object, as found in the `request`
+    ///   parameter
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request object is invalid for this client
+    pub async fn verify(
+        &self,
+        http_client_factory: &HttpClientFactory,
+        now: DateTime<Utc>,
+        issuer: &Url,
+        client: &Client,
+        request: &str,
+    ) -> Result<BTreeMap<String, String>, JarError> {
+        let jwt: Jwt<'_, HashMap<String, Value>> = Jwt::try_from(request)?;
+
+        if !REQUEST_OBJECT_SIGNING_ALGORITHMS.contains(jwt.header().alg()) {
+            return Err(JarError::UnsupportedAlgorithm);
+        }
+
+        let jwks = client.jwks.as_ref().ok_or(JarError::MissingJwks)?;
+        let jwks = self.jwks(http_client_factory, now, jwks).await?;
+        jwt.verify_with_jwks(&jwks)
+            .map_err(|_| JarError::InvalidSignature)?;
+
+        let mut claims = jwt.payload().clone();
+
+        // The request object must be issued by the client for this server
+        claims::ISS.extract_optional_with_options(&mut claims, client.client_id.as_str())?;
+        claims::AUD.extract_required_with_options(&mut claims, &issuer.to_string())?;
+
+        let time_options = TimeOptions::new(now);
+        claims::EXP.extract_optional_with_options(&mut claims, &time_options)?;
+        claims::NBF.extract_optional_with_options(&mut claims, &time_options)?;
+
+        for claim in REGISTERED_CLAIMS {
+            claims.remove(claim);
+        }
+
+        if claims
+            .get("client_id")
+            .is_some_and(|client_id| client_id.as_str() != Some(client.client_id.as_str()))
+        {
+            return Err(JarError::ClientMismatch);
+        }
+
+        // Authorization request parameters are strings, so anything else, like
+        // the `claims` parameter, is passed as serialized JSON
+        let parameters = claims
+            .into_iter()
+            .map(|(key, value)| {
+                let value = match value {
+                    Value::String(value) => value,
+                    value => value.to_string(),
+                };
+                (key, value)
+            })
+            .collect();
+
+        Ok(parameters)
+    }
+
+    /// Get the client JWKS, fetching it if it is not cached or if the cached
+    /// entry expired
+    async fn jwks(
+        &self,
+        http_client_factory: &HttpClientFactory,
+        now: DateTime<Utc>,
+        jwks: &JwksOrJwksUri,
+    ) -> Result<Arc<PublicJsonWebKeySet>, JarError> {
+        let uri = match jwks {
+            JwksOrJwksUri::Jwks(jwks) => return Ok(Arc::new(jwks.clone())),
+            JwksOrJwksUri::JwksUri(uri) => uri,
+        };
+
+        self.cache
+            .get_or_fetch(
+                uri,
+                now,
+                |cached| now < cached.expires_at,
+                || async {
+                    let jwks = fetch_jwks(http_client_factory, uri)
+                        .await
+                        .map_err(JarError::JwksFetch)?;
+                    Ok((jwks, now + self.ttl))
+                },
+            )
+            .await
+    }
+}
+
+async fn fetch_jwks(
+    http_client_factory: &HttpClientFactory,
+    uri: &Url,
+) -> Result<PublicJsonWebKeySet, BoxError> {
+    let request = hyper::Request::builder()
+        .uri(uri.as_str())
+        .body(mas_http::EmptyBody::new())?;
+
+    let mut client = http_client_factory
+        .client("client.fetch_jwks")
+        .response_body_to_bytes()
+        .json_response::<PublicJsonWebKeySet>();
+
+    let response = client.ready().await?.call(request).await?;
+
+    Ok(response.into_body())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use hyper::{header::LOCATION, Request, StatusCode};
+    use mas_data_model::{AccessTokenFormat, RedirectUriMatching};
+    use mas_iana::{
+        jose::JsonWebSignatureAlg,
+        oauth::{OAuthAuthorizationEndpointResponseType, OAuthClientAuthenticationMethod},
+    };
+    use mas_jose::{
+        jwk::{JsonWebKey, JsonWebKeyPublicParameters, PublicJsonWebKeySet},
+        jwt::{JsonWebSignatureHeader, Jwt},
+    };
+    use mas_keystore::PrivateKey;
+    use mas_router::SimpleRoute;
+    use mas_storage::{oauth2::UpsertStaticClientParams, Clock, RepositoryAccess};
+    use oauth2_types::errors::{ClientError, ClientErrorCode};
+    use sqlx::PgPool;
+    use ulid::Ulid;
+    use url::Url;
+    use wiremock::{
+        matchers::{method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use crate::test_utils::{init_tracing, RequestBuilderExt, ResponseExt, TestState};
+
+    /// Serve the public part of the given key as a JWKS. The JWKS is expected
+    /// to be fetched only once, since it should be cached afterwards
+    async fn serve_jwks(key: &PrivateKey) -> MockServer {
+        let jwks =
+            PublicJsonWebKeySet::new(vec![
+                JsonWebKey::new(JsonWebKeyPublicParameters::from(key)).with_kid("jar")
+            ]);
+
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/jwks.json"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(&jwks))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        mock_server
+    }
+
+    /// Provision a public client fetching its JWKS from the given server, and
+    /// return its client ID
+    async fn provision_client(
+        state: &TestState,
+        mock_server: &MockServer,
+        require_signed_request_object: bool,
+    ) -> String {
+        let jwks_uri = Url::parse(&format!("{}/jwks.json", mock_server.uri())).unwrap();
+
+        let mut repo = state.repository().await.unwrap();
+        let client_id = Ulid::from_datetime_with_source(state.clock.now().into(), &mut state.rng());
+        let client = repo
+            .oauth2_client()
+            .upsert_static(UpsertStaticClientParams {
+                client_id,
+                client_auth_method: OAuthClientAuthenticationMethod::None,
+                encrypted_client_secret: None,
+                jwks: None,
+                jwks_uri: Some(jwks_uri),
+                redirect_uris: vec![Url::parse("https://example.com/callback").unwrap()],
+                redirect_uri_matching: RedirectUriMatching::Exact,
+                response_types: vec![OAuthAuthorizationEndpointResponseType::Code],
+                allow_token_exchange: false,
+                allow_client_credentials: false,
+                client_credentials_scope: None,
+                allowed_resources: Vec::new(),
+                require_signed_request_object,
+                require_pkce: None,
+                access_token_ttl: None,
+                refresh_token_ttl: None,
+                access_token_format: AccessTokenFormat::Opaque,
+                introspection_endpoint_auth_method: None,
+                post_logout_redirect_uris: Vec::new(),
+                backchannel_logout_uri: None,
+                backchannel_logout_session_required: false,
+                trusted: false,
+            })
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        client.client_id
+    }
+
+    /// Sign a request object with the given key, carrying a `prompt=none`
+    /// authorization request for the given client
+    fn sign_request_object(
+        state: &TestState,
+        key: &PrivateKey,
+        client_id: &str,
+        audience: &str,
+    ) -> String {
+        let claims = serde_json::json!({
+            "iss": client_id,
+            "aud": audience,
+            "exp": (state.clock.now() + Duration::minutes(5)).timestamp(),
+            "client_id": client_id,
+            "response_type": "code",
+            "redirect_uri": "https://example.com/callback",
+            "scope": "openid",
+            "state": "signed",
+            "prompt": "none",
+        });
+
+        let signer = key
+            .signing_key_for_alg(&JsonWebSignatureAlg::Es256)
+            .unwrap();
+        let header = JsonWebSignatureHeader::new(JsonWebSignatureAlg::Es256).with_kid("jar");
+        Jwt::sign_with_rng(&mut state.rng(), header, claims, &signer)
+            .unwrap()
+            .into_string()
+    }
+
+    /// Extract the query parameters of the redirect in the response
+    fn redirect_params(response: &hyper::Response<String>) -> Vec<(String, String)> {
+        let location = response
+            .headers()
+            .get(LOCATION)
+            .expect("Missing Location header")
+            .to_str()
+            .unwrap();
+        let location = Url::parse(location).unwrap();
+        assert_eq!(
+            location.origin().ascii_serialization(),
+            "https://example.com"
+        );
+        assert_eq!(location.path(), "/callback");
+
+        location.query_pairs().into_owned().collect()
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_authorization_request_object(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let key = PrivateKey::generate_ec_p256(&mut state.rng());
+        let mock_server = serve_jwks(&key).await;
+        let client_id = provision_client(&state, &mock_server, true).await;
+        let issuer = state.url_builder.oidc_issuer();
+
+        // The claims of the request object take precedence over the query
+        // parameters. The JWKS is fetched once and then cached across requests
+        for _ in 0..2 {
+            let request_object = sign_request_object(&state, &key, &client_id, issuer.as_str());
+            let request = Request::get(format!(
+                "{}?client_id={client_id}&state=query&request={request_object}",
+                mas_router::OAuth2AuthorizationEndpoint::PATH,
+            ))
+            .empty();
+            let response = state.request(request).await;
+            response.assert_status(StatusCode::SEE_OTHER);
+
+            let params = redirect_params(&response);
+            assert!(params.contains(&("error".to_owned(), "login_required".to_owned())));
+            assert!(params.contains(&("state".to_owned(), "signed".to_owned())));
+        }
+
+        // Unsigned requests are rejected for this client
+        let request = Request::get(format!(
+            "{}?response_type=code&client_id={client_id}&redirect_uri=https://example.com/callback&scope=openid&state=abc&prompt=none",
+            mas_router::OAuth2AuthorizationEndpoint::PATH,
+        ))
+        .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::SEE_OTHER);
+        let params = redirect_params(&response);
+        assert!(params.contains(&("error".to_owned(), "invalid_request".to_owned())));
+        assert!(params.contains(&("state".to_owned(), "abc".to_owned())));
+
+        // Request objects signed with another key are rejected
+        let other_key = PrivateKey::generate_ec_p256(&mut state.rng());
+        let request_object = sign_request_object(&state, &other_key, &client_id, issuer.as_str());
+        let request = Request::get(format!(
+            "{}?client_id={client_id}&request={request_object}",
+            mas_router::OAuth2AuthorizationEndpoint::PATH,
+        ))
+        .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+
+        // So are request objects issued for another audience
+        let request_object =
+            sign_request_object(&state, &key, &client_id, "https://other.example.com/");
+        let request = Request::get(format!(
+            "{}?client_id={client_id}&request={request_object}",
+            mas_router::OAuth2AuthorizationEndpoint::PATH,
+        ))
+        .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_pushed_request_object(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let key = PrivateKey::generate_ec_p256(&mut state.rng());
+        let mock_server = serve_jwks(&key).await;
+        let client_id = provision_client(&state, &mock_server, true).await;
+        let issuer = state.url_builder.oidc_issuer();
+
+        // Unsigned requests can't be pushed for this client
+        let request = Request::post(mas_router::OAuth2PushedAuthorizationEndpoint::PATH).form(
+            serde_json::json!({
+                "client_id": client_id,
+                "response_type": "code",
+                "redirect_uri": "https://example.com/callback",
+                "scope": "openid",
+                "prompt": "none",
+            }),
+        );
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        let error: ClientError = response.json();
+        assert_eq!(error.error, ClientErrorCode::InvalidRequest);
+
+        // Signed requests are accepted, and the parameters they carry are used
+        // when the request is replayed on the authorization endpoint
+        let request_object = sign_request_object(&state, &key, &client_id, issuer.as_str());
+        let request = Request::post(mas_router::OAuth2PushedAuthorizationEndpoint::PATH).form(
+            serde_json::json!({
+                "client_id": client_id,
+                "request": request_object,
+            }),
+        );
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::CREATED);
+        let response: oauth2_types::requests::PushedAuthorizationResponse = response.json();
+
+        let request = Request::get(format!(
+            "{}?client_id={client_id}&request_uri={}",
+            mas_router::OAuth2AuthorizationEndpoint::PATH,
+            response.request_uri,
+        ))
+        .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::SEE_OTHER);
+
+        let params = redirect_params(&response);
+        assert!(params.contains(&("error".to_owned(), "login_required".to_owned())));
+        assert!(params.contains(&("state".to_owned(), "signed".to_owned())));
+    }
+}
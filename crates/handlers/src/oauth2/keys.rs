@@ -12,11 +12,69 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use axum::{extract::State, response::IntoResponse, Json};
+use std::time::Duration;
+
+use axum::{extract::State, response::IntoResponse, Json, TypedHeader};
+use headers::CacheControl;
 use mas_keystore::Keystore;
 
 #[tracing::instrument(name = "handlers.oauth2.keys.get", skip_all)]
 pub(crate) async fn get(State(key_store): State<Keystore>) -> impl IntoResponse {
+    // Only the public parameters of the keys are exposed here
     let jwks = key_store.public_jwks();
-    Json(jwks)
+
+    let cache_control = CacheControl::new()
+        .with_public()
+        .with_max_age(Duration::from_secs(60 * 60));
+
+    (TypedHeader(cache_control), Json(jwks))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use hyper::{header::CACHE_CONTROL, Request, StatusCode};
+    use mas_iana::jose::JsonWebSignatureAlg;
+    use mas_jose::{
+        constraints::Constrainable,
+        jwt::{JsonWebSignatureHeader, Jwt},
+    };
+    use mas_router::SimpleRoute;
+    use sqlx::PgPool;
+
+    use crate::test_utils::{init_tracing, RequestBuilderExt, ResponseExt, TestState};
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_keys(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+
+        let request = Request::get(mas_router::OAuth2Keys::PATH).empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        response.assert_header_value(CACHE_CONTROL, "public, max-age=3600");
+
+        // No private key material should ever be exposed
+        let raw: serde_json::Value = response.json();
+        for key in raw["keys"].as_array().unwrap() {
+            for private in ["d", "p", "q", "dp", "dq", "qi", "oth"] {
+                assert!(key.get(private).is_none(), "{private} is exposed");
+            }
+        }
+
+        // The published keys can verify a signature made with the private key
+        let jwks = response.json();
+        let alg = JsonWebSignatureAlg::Rs256;
+        let key = state.key_store.signing_key_for_algorithm(&alg).unwrap();
+        let signer = key.params().signing_key_for_alg(&alg).unwrap();
+        let header = JsonWebSignatureHeader::new(alg).with_kid(key.kid().unwrap());
+        let claims = HashMap::from([("hello".to_owned(), "world".to_owned())]);
+        let jwt = Jwt::sign_with_rng(&mut state.rng(), header, claims, &signer)
+            .unwrap()
+            .into_string();
+
+        let jwt: Jwt<'_, HashMap<String, String>> = Jwt::try_from(jwt.as_str()).unwrap();
+        jwt.verify_with_jwks(&jwks).unwrap();
+    }
 }
@@ -15,9 +15,10 @@
 use std::collections::HashMap;
 
 use chrono::Duration;
+use hyper::{header::ACCEPT, HeaderMap};
 use mas_data_model::{
-    AccessToken, Authentication, AuthorizationGrant, BrowserSession, Client, RefreshToken, Session,
-    TokenType,
+    AccessToken, AccessTokenFormat, Authentication, AuthorizationGrant, BrowserSession, Client,
+    RefreshToken, Session, TokenType, User,
 };
 use mas_iana::jose::JsonWebSignatureAlg;
 use mas_jose::{
@@ -31,11 +32,15 @@ use mas_storage::{Clock, RepositoryAccess};
 use thiserror::Error;
 
 pub mod authorization;
+pub mod backchannel_logout;
 pub mod consent;
 pub mod device;
 pub mod discovery;
+pub mod end_session;
 pub mod introspection;
+pub mod jar;
 pub mod keys;
+pub mod pushed_authorization;
 pub mod registration;
 pub mod revoke;
 pub mod token;
@@ -60,8 +65,9 @@ pub(crate) fn generate_id_token(
     key_store: &Keystore,
     client: &Client,
     grant: Option<&AuthorizationGrant>,
+    session: &Session,
     browser_session: &BrowserSession,
-    access_token: Option<&AccessToken>,
+    access_token: Option<&str>,
     last_authentication: Option<&Authentication>,
 ) -> Result<String, IdTokenSignatureError> {
     let mut claims = HashMap::new();
@@ -71,13 +77,29 @@ pub(crate) fn generate_id_token(
     claims::AUD.insert(&mut claims, client.client_id.clone())?;
     claims::IAT.insert(&mut claims, now)?;
     claims::EXP.insert(&mut claims, now + Duration::try_hours(1).unwrap())?;
+    claims::SID.insert(&mut claims, browser_session.id.to_string())?;
 
     if let Some(nonce) = grant.and_then(|grant| grant.nonce.as_ref()) {
         claims::NONCE.insert(&mut claims, nonce)?;
     }
 
-    if let Some(last_authentication) = last_authentication {
-        claims::AUTH_TIME.insert(&mut claims, last_authentication.created_at)?;
+    // Prefer the authentication time recorded when the session was authorized,
+    // so that all the ID tokens of a session report the same `auth_time`
+    let auth_time = session
+        .auth_time
+        .or(last_authentication.map(|auth| auth.created_at));
+
+    if let Some(auth_time) = auth_time {
+        claims::AUTH_TIME.insert(&mut claims, auth_time)?;
+    }
+
+    // Only report the authentication method if it is the one which happened at
+    // `auth_time`
+    if let Some(amr) = last_authentication
+        .filter(|auth| Some(auth.created_at) == auth_time)
+        .and_then(|auth| auth.authentication_method.amr())
+    {
+        claims::AMR.insert(&mut claims, vec![amr.to_owned()])?;
     }
 
     let alg = client
@@ -89,7 +111,7 @@ pub(crate) fn generate_id_token(
         .ok_or(IdTokenSignatureError::InvalidSigningKey)?;
 
     if let Some(access_token) = access_token {
-        claims::AT_HASH.insert(&mut claims, hash_token(&alg, &access_token.access_token)?)?;
+        claims::AT_HASH.insert(&mut claims, hash_token(&alg, access_token)?)?;
     }
 
     if let Some(code) = grant.and_then(|grant| grant.code.as_ref()) {
@@ -104,6 +126,78 @@ pub(crate) fn generate_id_token(
     Ok(id_token.into_string())
 }
 
+/// Get the access token to hand out to a client, in the format it asked for
+///
+/// JWT access tokens (RFC 9068) carry the ID of the stored access token in
+/// their `jti` claim, so that they can be introspected and revoked like opaque
+/// ones once their signature is checked.
+pub(crate) fn format_access_token(
+    rng: &mut (impl rand::RngCore + rand::CryptoRng),
+    url_builder: &UrlBuilder,
+    key_store: &Keystore,
+    client: &Client,
+    session: &Session,
+    user: Option<&User>,
+    access_token: &AccessToken,
+) -> Result<String, IdTokenSignatureError> {
+    if client.access_token_format == AccessTokenFormat::Opaque {
+        return Ok(access_token.access_token.clone());
+    }
+
+    let mut claims = HashMap::new();
+    claims::ISS.insert(&mut claims, url_builder.oidc_issuer().to_string())?;
+    // Tokens which aren't bound to a user are about the client itself
+    let sub = user.map_or_else(|| client.client_id.clone(), |user| user.sub.clone());
+    claims::SUB.insert(&mut claims, sub)?;
+
+    let resource = access_token.resource(&session.resource);
+    if resource.is_empty() {
+        claims::AUD.insert(&mut claims, client.client_id.clone())?;
+    } else {
+        let resource: Vec<String> = resource.iter().map(ToString::to_string).collect();
+        claims::AUD.insert(&mut claims, resource)?;
+    }
+
+    claims::IAT.insert(&mut claims, access_token.created_at)?;
+    if let Some(expires_at) = access_token.expires_at {
+        claims::EXP.insert(&mut claims, expires_at)?;
+    }
+    claims::JTI.insert(&mut claims, access_token.jti())?;
+    claims::CLIENT_ID.insert(&mut claims, client.client_id.clone())?;
+    claims::SCOPE.insert(&mut claims, access_token.scope(&session.scope).to_string())?;
+
+    // Resource servers are required to support RS256 for JWT access tokens,
+    // which comes first if the keystore has an RSA key
+    let alg = key_store
+        .available_signing_algorithms()
+        .into_iter()
+        .next()
+        .ok_or(IdTokenSignatureError::InvalidSigningKey)?;
+    let key = key_store
+        .signing_key_for_algorithm(&alg)
+        .ok_or(IdTokenSignatureError::InvalidSigningKey)?;
+
+    let signer = key.params().signing_key_for_alg(&alg)?;
+    let header = JsonWebSignatureHeader::new(alg)
+        .with_kid(key.kid().ok_or(IdTokenSignatureError::InvalidSigningKey)?)
+        .with_typ("at+jwt".to_owned());
+    let access_token = Jwt::sign_with_rng(rng, header, claims, &signer)?;
+
+    Ok(access_token.into_string())
+}
+
+/// Whether the `Accept` header lists the given media type, ignoring any
+/// parameters
+pub(crate) fn accepts_media_type(headers: &HeaderMap, media_type: &str) -> bool {
+    headers
+        .get_all(ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|media_range| media_range.split(';').next())
+        .any(|candidate| candidate.trim() == media_type)
+}
+
 pub(crate) async fn generate_token_pair<R: RepositoryAccess>(
     rng: &mut (impl rand::RngCore + Send),
     clock: &impl Clock,
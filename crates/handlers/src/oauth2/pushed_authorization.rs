@@ -0,0 +1,374 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::BTreeMap;
+
+use axum::{extract::State, response::IntoResponse, Json, TypedHeader};
+use headers::{CacheControl, Pragma};
+use hyper::StatusCode;
+use mas_axum_utils::{
+    client_authorization::{ClientAuthorization, CredentialsVerificationError},
+    http_client_factory::HttpClientFactory,
+    sentry::SentryEventID,
+};
+use mas_data_model::SiteConfig;
+use mas_keystore::Encrypter;
+use mas_router::UrlBuilder;
+use mas_storage::{oauth2::OAuth2PushedAuthRequestRepository, BoxClock, BoxRepository, BoxRng};
+use oauth2_types::{
+    errors::{ClientError, ClientErrorCode},
+    requests::PushedAuthorizationResponse,
+};
+use serde::Deserialize;
+use serde_with::{formats::PreferOne, serde_as, OneOrMany};
+use thiserror::Error;
+
+use super::{
+    authorization::Params,
+    jar::{JarError, JarVerifier},
+};
+use crate::impl_from_error_for_route;
+
+#[derive(Debug, Error)]
+pub(crate) enum RouteError {
+    #[error(transparent)]
+    Internal(Box<dyn std::error::Error + Send + Sync + 'static>),
+
+    #[error("client not found")]
+    ClientNotFound,
+
+    #[error("client not allowed")]
+    ClientNotAllowed,
+
+    #[error("could not verify client credentials")]
+    ClientCredentialsVerification(#[from] CredentialsVerificationError),
+
+    #[error("the request_uri parameter can't be pushed")]
+    RequestUriPushed,
+
+    #[error("the {0:?} parameter is repeated")]
+    RepeatedParameter(String),
+
+    #[error("the client must send a signed request object")]
+    SignedRequestRequired,
+
+    #[error("invalid request object")]
+    InvalidRequestObject(#[from] JarError),
+
+    #[error("invalid authorization request")]
+    InvalidRequest(#[source] serde_json::Error),
+
+    #[error("invalid redirect uri")]
+    UnknownRedirectUri(#[from] mas_data_model::InvalidRedirectUriError),
+}
+
+impl_from_error_for_route!(mas_storage::RepositoryError);
+
+/// The parameters of a pushed authorization request
+///
+/// Only the `resource` parameter may be repeated, as per [RFC 8707].
+///
+/// [RFC 8707]: https://www.rfc-editor.org/rfc/rfc8707
+#[serde_as]
+#[derive(Deserialize, Default)]
+#[serde(transparent)]
+pub(crate) struct PushedParameters(
+    #[serde_as(as = "BTreeMap<_, OneOrMany<_, PreferOne>>")] BTreeMap<String, Vec<String>>,
+);
+
+impl PushedParameters {
+    /// Get the parameters in the form the authorization endpoint expects, with
+    /// the values of the `resource` parameter joined with spaces
+    fn into_parameters(self) -> Result<BTreeMap<String, String>, RouteError> {
+        self.0
+            .into_iter()
+            .map(|(name, values)| {
+                if values.len() > 1 && name != "resource" {
+                    return Err(RouteError::RepeatedParameter(name));
+                }
+
+                let value = values.join(" ");
+                Ok((name, value))
+            })
+            .collect()
+    }
+}
+
+impl IntoResponse for RouteError {
+    fn into_response(self) -> axum::response::Response {
+        let event_id = sentry::capture_error(&self);
+
+        let response = match self {
+            Self::Internal(_) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ClientError::from(ClientErrorCode::ServerError)),
+            ),
+            Self::ClientNotFound | Self::ClientCredentialsVerification(_) => (
+                StatusCode::UNAUTHORIZED,
+                Json(ClientError::from(ClientErrorCode::InvalidClient)),
+            ),
+            Self::ClientNotAllowed => (
+                StatusCode::UNAUTHORIZED,
+                Json(ClientError::from(ClientErrorCode::UnauthorizedClient)),
+            ),
+            Self::RequestUriPushed
+            | Self::RepeatedParameter(_)
+            | Self::SignedRequestRequired
+            | Self::InvalidRequestObject(_)
+            | Self::InvalidRequest(_)
+            | Self::UnknownRedirectUri(_) => (
+                StatusCode::BAD_REQUEST,
+                Json(ClientError::from(ClientErrorCode::InvalidRequest)),
+            ),
+        };
+
+        (SentryEventID::from(event_id), response).into_response()
+    }
+}
+
+#[tracing::instrument(
+    name = "handlers.oauth2.pushed_authorization.post",
+    fields(client.id = client_authorization.client_id()),
+    skip_all,
+    err,
+)]
+pub(crate) async fn post(
+    mut rng: BoxRng,
+    clock: BoxClock,
+    mut repo: BoxRepository,
+    State(site_config): State<SiteConfig>,
+    State(http_client_factory): State<HttpClientFactory>,
+    State(encrypter): State<Encrypter>,
+    State(url_builder): State<UrlBuilder>,
+    State(jar_verifier): State<JarVerifier>,
+    client_authorization: ClientAuthorization<PushedParameters>,
+) -> Result<impl IntoResponse, RouteError> {
+    let client = client_authorization
+        .credentials
+        .fetch(&mut repo)
+        .await?
+        .ok_or(RouteError::ClientNotFound)?;
+
+    // Reuse the token endpoint auth method to verify the client
+    let method = client
+        .token_endpoint_auth_method
+        .as_ref()
+        .ok_or(RouteError::ClientNotAllowed)?;
+
+    client_authorization
+        .credentials
+        .verify(&http_client_factory, &encrypter, method, &client)
+        .await?;
+
+    let mut parameters = client_authorization
+        .form
+        .unwrap_or_default()
+        .into_parameters()?;
+
+    // The request_uri parameter must not be provided when pushing a request
+    if parameters.contains_key("request_uri") {
+        return Err(RouteError::RequestUriPushed);
+    }
+
+    // If the request carries a signed request object, verify it and store the
+    // parameters it carries, which take precedence over the ones passed in the
+    // clear
+    if let Some(request) = parameters.remove("request") {
+        let claims = jar_verifier
+            .verify(
+                &http_client_factory,
+                clock.now(),
+                &url_builder.oidc_issuer(),
+                &client,
+                &request,
+            )
+            .await?;
+        parameters.extend(claims);
+    } else if client.require_signed_request_object {
+        return Err(RouteError::SignedRequestRequired);
+    }
+
+    // The client_id is consumed by the client authorization, put it back so that
+    // the request can be replayed on the authorization endpoint
+    parameters.insert("client_id".to_owned(), client.client_id.clone());
+
+    // Validate the request now, so that the client gets the error here instead of
+    // on the authorization endpoint
+    let params = Params::from_parameters(parameters.clone()).map_err(RouteError::InvalidRequest)?;
+    client.resolve_redirect_uri(&params.auth.redirect_uri)?;
+
+    let expires_in = site_config.pushed_authorization_request_ttl;
+
+    let pushed_request = repo
+        .oauth2_pushed_auth_request()
+        .add(&mut rng, &clock, &client, parameters, expires_in)
+        .await?;
+
+    repo.save().await?;
+
+    let response = PushedAuthorizationResponse {
+        request_uri: pushed_request.request_uri(),
+        expires_in,
+    };
+
+    Ok((
+        StatusCode::CREATED,
+        TypedHeader(CacheControl::new().with_no_store()),
+        TypedHeader(Pragma::no_cache()),
+        Json(response),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use hyper::{header::LOCATION, Request, StatusCode};
+    use mas_router::SimpleRoute;
+    use oauth2_types::{
+        errors::{ClientError, ClientErrorCode},
+        registration::ClientRegistrationResponse,
+        requests::PushedAuthorizationResponse,
+    };
+    use url::Url;
+
+    use crate::test_utils::{init_tracing, RequestBuilderExt, ResponseExt, TestState};
+
+    /// Provision a public client which can use the authorization code grant,
+    /// and return its client ID
+    async fn register_client(state: &TestState) -> String {
+        let request =
+            Request::post(mas_router::OAuth2RegistrationEndpoint::PATH).json(serde_json::json!({
+                "client_uri": "https://example.com/",
+                "redirect_uris": ["https://example.com/callback"],
+                "contacts": ["contact@example.com"],
+                "token_endpoint_auth_method": "none",
+                "response_types": ["code"],
+                "grant_types": ["authorization_code"],
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::CREATED);
+
+        let ClientRegistrationResponse { client_id, .. } = response.json();
+        client_id
+    }
+
+    /// Push an authorization request with `prompt=none` for the given client
+    async fn push_request(state: &TestState, client_id: &str) -> PushedAuthorizationResponse {
+        let request = Request::post(mas_router::OAuth2PushedAuthorizationEndpoint::PATH).form(
+            serde_json::json!({
+                "client_id": client_id,
+                "response_type": "code",
+                "redirect_uri": "https://example.com/callback",
+                "scope": "openid",
+                "state": "abc",
+                "prompt": "none",
+                "code_challenge_method": "S256",
+                "code_challenge": "bwWFMyPfdG9qreDhH2lmftFx_dFeLDalzcT1gb_j68g",
+            }),
+        );
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::CREATED);
+        response.json()
+    }
+
+    #[tokio::test]
+    async fn test_pushed_authorization_request() {
+        init_tracing();
+        let state = TestState::in_memory().await.unwrap();
+        let client_id = register_client(&state).await;
+
+        let response = push_request(&state, &client_id).await;
+        assert!(response
+            .request_uri
+            .starts_with("urn:ietf:params:oauth:request_uri:"));
+        assert_eq!(response.expires_in, Duration::try_seconds(90).unwrap());
+
+        // Using the request_uri on the authorization endpoint replays the pushed
+        // request: with prompt=none and no session, we get back to the client with
+        // a login_required error
+        let request = Request::get(format!(
+            "{}?client_id={client_id}&request_uri={}",
+            mas_router::OAuth2AuthorizationEndpoint::PATH,
+            response.request_uri,
+        ))
+        .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::SEE_OTHER);
+
+        let location = response.headers().get(LOCATION).unwrap().to_str().unwrap();
+        let location = Url::parse(location).unwrap();
+        assert_eq!(location.path(), "/callback");
+        let params: Vec<(String, String)> = location.query_pairs().into_owned().collect();
+        assert!(params.contains(&("error".to_owned(), "login_required".to_owned())));
+        assert!(params.contains(&("state".to_owned(), "abc".to_owned())));
+    }
+
+    #[tokio::test]
+    async fn test_pushed_authorization_request_invalid() {
+        init_tracing();
+        let state = TestState::in_memory().await.unwrap();
+        let client_id = register_client(&state).await;
+        let other_client_id = register_client(&state).await;
+
+        // Pushing a request_uri is not allowed
+        let request = Request::post(mas_router::OAuth2PushedAuthorizationEndpoint::PATH).form(
+            serde_json::json!({
+                "client_id": client_id,
+                "request_uri": "urn:ietf:params:oauth:request_uri:foo",
+            }),
+        );
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        let error: ClientError = response.json();
+        assert_eq!(error.error, ClientErrorCode::InvalidRequest);
+
+        // Pushing an unknown redirect_uri is not allowed
+        let request = Request::post(mas_router::OAuth2PushedAuthorizationEndpoint::PATH).form(
+            serde_json::json!({
+                "client_id": client_id,
+                "response_type": "code",
+                "redirect_uri": "https://example.com/other",
+                "scope": "openid",
+            }),
+        );
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        let error: ClientError = response.json();
+        assert_eq!(error.error, ClientErrorCode::InvalidRequest);
+
+        let pushed = push_request(&state, &client_id).await;
+
+        // The request_uri can't be used by another client
+        let request = Request::get(format!(
+            "{}?client_id={other_client_id}&request_uri={}",
+            mas_router::OAuth2AuthorizationEndpoint::PATH,
+            pushed.request_uri,
+        ))
+        .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+
+        // The request_uri can't be used once expired
+        state.clock.advance(Duration::try_minutes(2).unwrap());
+        let request = Request::get(format!(
+            "{}?client_id={client_id}&request_uri={}",
+            mas_router::OAuth2AuthorizationEndpoint::PATH,
+            pushed.request_uri,
+        ))
+        .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+    }
+}
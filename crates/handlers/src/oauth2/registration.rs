@@ -15,7 +15,7 @@
 use axum::{extract::State, response::IntoResponse, Json};
 use hyper::StatusCode;
 use mas_axum_utils::sentry::SentryEventID;
-use mas_iana::oauth::OAuthClientAuthenticationMethod;
+use mas_iana::oauth::{OAuthAuthorizationEndpointResponseType, OAuthClientAuthenticationMethod};
 use mas_keystore::Encrypter;
 use mas_policy::{Policy, Violation};
 use mas_storage::{oauth2::OAuth2ClientRepository, BoxClock, BoxRepository, BoxRng};
@@ -91,7 +91,8 @@ impl IntoResponse for RouteError {
             // return an `invalid_client_metadata` error.
             Self::InvalidClientMetadata(
                 ClientMetadataVerificationError::MissingRedirectUris
-                | ClientMetadataVerificationError::RedirectUriWithFragment(_),
+                | ClientMetadataVerificationError::RedirectUriWithFragment(_)
+                | ClientMetadataVerificationError::RedirectUriWithDuplicateQueryParam(_),
             ) => (
                 StatusCode::BAD_REQUEST,
                 Json(ClientError::from(ClientErrorCode::InvalidRedirectUri)),
@@ -261,6 +262,14 @@ pub(crate) async fn post(
         _ => (None, None),
     };
 
+    // Response types with unknown values can't be used in authorization requests,
+    // so there is no point in saving them
+    let response_types: Vec<OAuthAuthorizationEndpointResponseType> = metadata
+        .response_types()
+        .into_iter()
+        .filter_map(|response_type| response_type.try_into().ok())
+        .collect();
+
     let client = repo
         .oauth2_client()
         .add(
@@ -269,7 +278,7 @@ pub(crate) async fn post(
             metadata.redirect_uris().to_vec(),
             encrypted_client_secret,
             metadata.application_type.clone(),
-            //&metadata.response_types(),
+            response_types,
             metadata.grant_types().to_vec(),
             metadata.contacts.clone().unwrap_or_default(),
             metadata
@@ -285,9 +294,14 @@ pub(crate) async fn post(
             // XXX: those might not be right, should be function calls
             metadata.id_token_signed_response_alg.clone(),
             metadata.userinfo_signed_response_alg.clone(),
+            metadata.authorization_signed_response_alg.clone(),
             metadata.token_endpoint_auth_method.clone(),
             metadata.token_endpoint_auth_signing_alg.clone(),
             metadata.initiate_login_uri.clone(),
+            metadata
+                .post_logout_redirect_uris
+                .clone()
+                .unwrap_or_default(),
         )
         .await?;
 
@@ -312,7 +326,6 @@ mod tests {
         errors::{ClientError, ClientErrorCode},
         registration::ClientRegistrationResponse,
     };
-    use sqlx::PgPool;
     use url::Url;
 
     use crate::{
@@ -340,10 +353,10 @@ mod tests {
         assert!(!url_is_public_suffix("http://somerandominternaldomain"));
     }
 
-    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
-    async fn test_registration_error(pool: PgPool) {
+    #[tokio::test]
+    async fn test_registration_error() {
         init_tracing();
-        let state = TestState::from_pool(pool).await.unwrap();
+        let state = TestState::in_memory().await.unwrap();
 
         // Body is not a JSON
         let request = Request::post(mas_router::OAuth2RegistrationEndpoint::PATH)
@@ -437,10 +450,10 @@ mod tests {
         );
     }
 
-    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
-    async fn test_registration(pool: PgPool) {
+    #[tokio::test]
+    async fn test_registration() {
         init_tracing();
-        let state = TestState::from_pool(pool).await.unwrap();
+        let state = TestState::in_memory().await.unwrap();
 
         // A successful registration with no authentication should not return a client
         // secret
@@ -18,13 +18,16 @@ use mas_axum_utils::{
     client_authorization::{ClientAuthorization, CredentialsVerificationError},
     http_client_factory::HttpClientFactory,
     sentry::SentryEventID,
+    user_authorization::stored_token,
 };
-use mas_data_model::{Device, TokenType};
+use mas_data_model::{AccessToken, AuditAction, Device, RefreshToken, TokenType};
 use mas_iana::oauth::OAuthTokenTypeHint;
-use mas_keystore::Encrypter;
+use mas_keystore::{Encrypter, Keystore};
+use mas_router::UrlBuilder;
 use mas_storage::{
+    audit_log::AuditLogParams,
     job::{DeleteDeviceJob, JobRepositoryExt},
-    BoxClock, BoxRepository, RepositoryAccess,
+    BoxClock, BoxRepository, BoxRng, RepositoryAccess,
 };
 use oauth2_types::{
     errors::{ClientError, ClientErrorCode},
@@ -105,6 +108,12 @@ impl IntoResponse for RouteError {
 
 impl_from_error_for_route!(mas_storage::RepositoryError);
 
+/// The token presented to the revocation endpoint
+enum RevokedToken {
+    AccessToken(AccessToken),
+    RefreshToken(RefreshToken),
+}
+
 impl From<mas_data_model::TokenFormatError> for RouteError {
     fn from(_e: mas_data_model::TokenFormatError) -> Self {
         Self::UnknownToken
@@ -119,10 +128,13 @@ impl From<mas_data_model::TokenFormatError> for RouteError {
 )]
 pub(crate) async fn post(
     clock: BoxClock,
+    mut rng: BoxRng,
     State(http_client_factory): State<HttpClientFactory>,
     mut repo: BoxRepository,
     activity_tracker: BoundActivityTracker,
     State(encrypter): State<Encrypter>,
+    State(key_store): State<Keystore>,
+    State(url_builder): State<UrlBuilder>,
     client_authorization: ClientAuthorization<RevocationRequest>,
 ) -> Result<impl IntoResponse, RouteError> {
     let client = client_authorization
@@ -145,27 +157,40 @@ pub(crate) async fn post(
         return Err(RouteError::BadRequest);
     };
 
-    let token_type = TokenType::check(&form.token)?;
-
-    // Find the ID of the session to end.
-    let session_id = match (form.token_type_hint, token_type) {
+    // JWT access tokens are revoked as the opaque access token they were issued
+    // for
+    let stored_token = stored_token(
+        &mut repo,
+        &clock,
+        &key_store,
+        &url_builder.oidc_issuer(),
+        &form.token,
+    )
+    .await?
+    .ok_or(RouteError::UnknownToken)?;
+    let token_type = TokenType::check(&stored_token)?;
+
+    // Find the token to revoke, and the ID of the session to end.
+    let (token, session_id) = match (form.token_type_hint, token_type) {
         (Some(OAuthTokenTypeHint::AccessToken) | None, TokenType::AccessToken) => {
             let access_token = repo
                 .oauth2_access_token()
-                .find_by_token(&form.token)
+                .find_by_token(&stored_token)
                 .await?
                 .ok_or(RouteError::UnknownToken)?;
 
             if !access_token.is_valid(clock.now()) {
                 return Err(RouteError::UnknownToken);
             }
-            access_token.session_id
+
+            let session_id = access_token.session_id;
+            (RevokedToken::AccessToken(access_token), session_id)
         }
 
         (Some(OAuthTokenTypeHint::RefreshToken) | None, TokenType::RefreshToken) => {
             let refresh_token = repo
                 .oauth2_refresh_token()
-                .find_by_token(&form.token)
+                .find_by_token(&stored_token)
                 .await?
                 .ok_or(RouteError::UnknownToken)?;
 
@@ -173,7 +198,8 @@ pub(crate) async fn post(
                 return Err(RouteError::UnknownToken);
             }
 
-            refresh_token.session_id
+            let session_id = refresh_token.session_id;
+            (RevokedToken::RefreshToken(refresh_token), session_id)
         }
 
         // This case can happen if there is a mismatch between the token type hint and the guessed
@@ -233,7 +259,47 @@ pub(crate) async fn post(
         }
     }
 
-    // Now that we checked everything, we can end the session.
+    let revoked_token_type = match token {
+        RevokedToken::AccessToken(_) => "access_token",
+        RevokedToken::RefreshToken(_) => "refresh_token",
+    };
+    let mut params = AuditLogParams::new(AuditAction::TokenRevoked)
+        .with_actor_oauth2_session(&session)
+        .with_metadata(serde_json::json!({
+            "client_id": client.id.to_string(),
+            "token_type": revoked_token_type,
+        }));
+    if let Some(user_id) = session.user_id {
+        params = params.with_target_id(user_id);
+    }
+    repo.audit_log().record(&mut rng, &clock, params).await?;
+
+    // Now that we checked everything, we can revoke the token itself. Revoking a
+    // refresh token also revokes the access token issued alongside it.
+    match token {
+        RevokedToken::AccessToken(access_token) => {
+            repo.oauth2_access_token()
+                .revoke(&clock, access_token)
+                .await?;
+        }
+
+        RevokedToken::RefreshToken(refresh_token) => {
+            if let Some(access_token_id) = refresh_token.access_token_id {
+                let access_token = repo.oauth2_access_token().lookup(access_token_id).await?;
+                if let Some(access_token) = access_token.filter(|t| t.state.is_valid()) {
+                    repo.oauth2_access_token()
+                        .revoke(&clock, access_token)
+                        .await?;
+                }
+            }
+
+            repo.oauth2_refresh_token()
+                .revoke(&clock, refresh_token)
+                .await?;
+        }
+    }
+
+    // End the session, which invalidates all the other tokens issued in it
     repo.oauth2_session().finish(&clock, session).await?;
 
     repo.save().await?;
@@ -245,7 +311,6 @@ pub(crate) async fn post(
 mod tests {
     use chrono::Duration;
     use hyper::Request;
-    use mas_data_model::{AccessToken, RefreshToken};
     use mas_router::SimpleRoute;
     use mas_storage::RepositoryAccess;
     use oauth2_types::{
@@ -296,7 +361,7 @@ mod tests {
 
         let browser_session = repo
             .browser_session()
-            .add(&mut state.rng(), &state.clock, &user, None)
+            .add(&mut state.rng(), &state.clock, &user, None, None, None)
             .await
             .unwrap();
 
@@ -460,5 +525,148 @@ mod tests {
         response.assert_status(StatusCode::OK);
 
         assert!(!state.is_access_token_valid(&access_token).await);
+
+        // The refresh token itself is marked as revoked, along with the access token
+        // issued with it
+        let mut repo = state.repository().await.unwrap();
+        let revoked_refresh_token = repo
+            .oauth2_refresh_token()
+            .find_by_token(&refresh_token)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(revoked_refresh_token.is_revoked());
+        let revoked_access_token = repo
+            .oauth2_access_token()
+            .find_by_token(&access_token)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(revoked_access_token.state.is_revoked());
+        repo.cancel().await.unwrap();
+
+        // The revoked refresh token can't be used anymore
+        let request =
+            Request::post(mas_router::OAuth2TokenEndpoint::PATH).form(serde_json::json!({
+                "grant_type": "refresh_token",
+                "refresh_token": refresh_token,
+                "client_id": client_id,
+                "client_secret": client_secret,
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_revoke_unknown_or_foreign_token(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+
+        // Register two clients
+        let mut credentials = Vec::new();
+        for _ in 0..2 {
+            let request = Request::post(mas_router::OAuth2RegistrationEndpoint::PATH).json(
+                serde_json::json!({
+                    "client_uri": "https://example.com/",
+                    "redirect_uris": ["https://example.com/callback"],
+                    "contacts": ["contact@example.com"],
+                    "token_endpoint_auth_method": "client_secret_post",
+                    "response_types": ["code"],
+                    "grant_types": ["authorization_code", "refresh_token"],
+                }),
+            );
+
+            let response = state.request(request).await;
+            response.assert_status(StatusCode::CREATED);
+
+            let client_registration: ClientRegistrationResponse = response.json();
+            credentials.push((
+                client_registration.client_id,
+                client_registration.client_secret.unwrap(),
+            ));
+        }
+        let (client_id, client_secret) = credentials[0].clone();
+        let (other_client_id, other_client_secret) = credentials[1].clone();
+
+        // Start a session for the first client
+        let mut repo = state.repository().await.unwrap();
+
+        let user = repo
+            .user()
+            .add(&mut state.rng(), &state.clock, "alice".to_owned())
+            .await
+            .unwrap();
+
+        let browser_session = repo
+            .browser_session()
+            .add(&mut state.rng(), &state.clock, &user, None, None, None)
+            .await
+            .unwrap();
+
+        let client = repo
+            .oauth2_client()
+            .find_by_client_id(&client_id)
+            .await
+            .unwrap()
+            .unwrap();
+
+        let session = repo
+            .oauth2_session()
+            .add_from_browser_session(
+                &mut state.rng(),
+                &state.clock,
+                &client,
+                &browser_session,
+                Scope::from_iter([OPENID]),
+            )
+            .await
+            .unwrap();
+
+        let (AccessToken { access_token, .. }, RefreshToken { refresh_token, .. }) =
+            generate_token_pair(
+                &mut state.rng(),
+                &state.clock,
+                &mut repo,
+                &session,
+                Duration::microseconds(5 * 60 * 1000 * 1000),
+            )
+            .await
+            .unwrap();
+
+        repo.save().await.unwrap();
+
+        // Revoking a token which doesn't exist succeeds, whether it looks like one
+        // of our tokens or not
+        let unknown_token = TokenType::AccessToken.generate(&mut state.rng());
+        for token in [unknown_token.as_str(), "not-a-token"] {
+            let request =
+                Request::post(mas_router::OAuth2Revocation::PATH).form(serde_json::json!({
+                    "token": token,
+                    "client_id": client_id,
+                    "client_secret": client_secret,
+                }));
+
+            let response = state.request(request).await;
+            response.assert_status(StatusCode::OK);
+        }
+
+        // The other client can't revoke the tokens of the first one
+        for token in [&access_token, &refresh_token] {
+            let request =
+                Request::post(mas_router::OAuth2Revocation::PATH).form(serde_json::json!({
+                    "token": token,
+                    "client_id": other_client_id,
+                    "client_secret": other_client_secret,
+                }));
+
+            let response = state.request(request).await;
+            response.assert_status(StatusCode::UNAUTHORIZED);
+            let error: ClientError = response.json();
+            assert_eq!(error.error, ClientErrorCode::UnauthorizedClient);
+        }
+
+        // The tokens are still valid
+        assert!(state.is_access_token_valid(&access_token).await);
     }
 }
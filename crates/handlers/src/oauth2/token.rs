@@ -12,29 +12,36 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::sync::Arc;
+
 use axum::{extract::State, response::IntoResponse, Json, TypedHeader};
-use chrono::{DateTime, Duration, Utc};
+use chrono::{DateTime, Utc};
 use headers::{CacheControl, HeaderMap, HeaderMapExt, Pragma};
 use hyper::StatusCode;
 use mas_axum_utils::{
     client_authorization::{ClientAuthorization, CredentialsVerificationError},
+    dpop::{DpopError, DpopNonceStore, DpopProof, DpopRequest, DPOP_NONCE},
     http_client_factory::HttpClientFactory,
     sentry::SentryEventID,
+    user_authorization::find_access_token,
 };
 use mas_data_model::{
-    AuthorizationGrantStage, Client, Device, DeviceCodeGrantState, SiteConfig, TokenType, UserAgent,
+    AccessTokenFormat, AuditAction, AuthorizationGrantStage, Client, Device, DeviceCodeGrantState,
+    Session, SiteConfig, TokenType, UserAgent,
 };
+use mas_iana::oauth::OAuthAccessTokenType;
 use mas_keystore::{Encrypter, Keystore};
 use mas_oidc_client::types::scope::ScopeToken;
 use mas_policy::Policy;
 use mas_router::UrlBuilder;
 use mas_storage::{
+    audit_log::AuditLogParams,
     job::{JobRepositoryExt, ProvisionDeviceJob},
     oauth2::{
         OAuth2AccessTokenRepository, OAuth2AuthorizationGrantRepository,
         OAuth2RefreshTokenRepository, OAuth2SessionRepository,
     },
-    user::BrowserSessionRepository,
+    user::{BrowserSessionRepository, UserRepository},
     BoxClock, BoxRepository, BoxRng, Clock, RepositoryAccess,
 };
 use oauth2_types::{
@@ -42,7 +49,8 @@ use oauth2_types::{
     pkce::CodeChallengeError,
     requests::{
         AccessTokenRequest, AccessTokenResponse, AuthorizationCodeGrant, ClientCredentialsGrant,
-        DeviceCodeGrant, GrantType, RefreshTokenGrant,
+        DeviceCodeGrant, GrantType, RefreshTokenGrant, TokenExchangeGrant, TokenTypeIdentifier,
+        DEFAULT_DEVICE_AUTHORIZATION_INTERVAL,
     },
     scope,
 };
@@ -53,8 +61,8 @@ use tracing::debug;
 use ulid::Ulid;
 use url::Url;
 
-use super::{generate_id_token, generate_token_pair};
-use crate::{impl_from_error_for_route, BoundActivityTracker};
+use super::{format_access_token, generate_id_token, generate_token_pair};
+use crate::{impl_from_error_for_route, metrics, BoundActivityTracker};
 
 #[serde_as]
 #[skip_serializing_none]
@@ -132,17 +140,47 @@ pub(crate) enum RouteError {
     #[error("device code grant is still pending")]
     DeviceCodePending,
 
+    #[error("device code grant is polled too often")]
+    DeviceCodeSlowDown,
+
     #[error("device code grant was rejected")]
     DeviceCodeRejected,
 
     #[error("device code grant was already exchanged")]
     DeviceCodeExchanged,
+
+    #[error("access token not found")]
+    AccessTokenNotFound,
+
+    #[error("access token {0} is invalid")]
+    AccessTokenInvalid(Ulid),
+
+    #[error("unsupported token type")]
+    UnsupportedTokenType,
+
+    #[error("requested scope is not allowed")]
+    ScopeNotAllowed,
+
+    #[error("requested resource is not allowed")]
+    ResourceNotAllowed,
+
+    #[error("invalid DPoP proof")]
+    InvalidDpopProof(#[from] DpopError),
+
+    #[error("the DPoP proof must carry a nonce")]
+    DpopNonceRequired(String),
 }
 
 impl IntoResponse for RouteError {
     fn into_response(self) -> axum::response::Response {
         let event_id = sentry::capture_error(&self);
 
+        // Give the client the nonce to use in its next DPoP proof
+        let dpop_nonce = match &self {
+            Self::DpopNonceRequired(nonce) => Some([(DPOP_NONCE.clone(), nonce.clone())]),
+            _ => None,
+        };
+
         let response = match self {
             Self::Internal(_) | Self::NoSuchBrowserSession | Self::NoSuchOAuthSession => (
                 StatusCode::INTERNAL_SERVER_ERROR,
@@ -191,10 +229,16 @@ impl IntoResponse for RouteError {
                 StatusCode::FORBIDDEN,
                 Json(ClientError::from(ClientErrorCode::AuthorizationPending)),
             ),
+            Self::DeviceCodeSlowDown => (
+                StatusCode::FORBIDDEN,
+                Json(ClientError::from(ClientErrorCode::SlowDown)),
+            ),
             Self::InvalidGrant
             | Self::DeviceCodeExchanged
             | Self::RefreshTokenNotFound
             | Self::RefreshTokenInvalid(_)
+            | Self::AccessTokenNotFound
+            | Self::AccessTokenInvalid(_)
             | Self::SessionInvalid(_)
             | Self::ClientIDMismatch { .. }
             | Self::GrantNotFound => (
@@ -205,16 +249,41 @@ impl IntoResponse for RouteError {
                 StatusCode::BAD_REQUEST,
                 Json(ClientError::from(ClientErrorCode::UnsupportedGrantType)),
             ),
+            Self::UnsupportedTokenType => (
+                StatusCode::BAD_REQUEST,
+                Json(ClientError::from(ClientErrorCode::UnsupportedTokenType)),
+            ),
+            Self::ScopeNotAllowed => (
+                StatusCode::BAD_REQUEST,
+                Json(ClientError::from(ClientErrorCode::InvalidScope)),
+            ),
+            Self::ResourceNotAllowed => (
+                StatusCode::BAD_REQUEST,
+                Json(ClientError::from(ClientErrorCode::InvalidTarget)),
+            ),
+            Self::InvalidDpopProof(err) => (
+                StatusCode::BAD_REQUEST,
+                Json(
+                    ClientError::from(ClientErrorCode::InvalidDpopProof)
+                        .with_description(err.to_string()),
+                ),
+            ),
+            Self::DpopNonceRequired(_) => (
+                StatusCode::BAD_REQUEST,
+                Json(ClientError::from(ClientErrorCode::UseDpopNonce)),
+            ),
         };
 
-        (SentryEventID::from(event_id), response).into_response()
+        (SentryEventID::from(event_id), dpop_nonce, response).into_response()
     }
 }
 
 impl_from_error_for_route!(mas_storage::RepositoryError);
 impl_from_error_for_route!(mas_policy::EvaluationError);
 impl_from_error_for_route!(super::IdTokenSignatureError);
+impl_from_error_for_route!(hyper::header::InvalidHeaderValue);
 
+#[allow(clippy::too_many_lines)]
 #[tracing::instrument(
     name = "handlers.oauth2.token.post",
     fields(client.id = client_authorization.client_id()),
@@ -231,8 +300,10 @@ pub(crate) async fn post(
     mut repo: BoxRepository,
     State(site_config): State<SiteConfig>,
     State(encrypter): State<Encrypter>,
+    State(dpop_nonce_store): State<Arc<dyn DpopNonceStore>>,
     policy: Policy,
     user_agent: Option<TypedHeader<headers::UserAgent>>,
+    dpop: DpopRequest,
     client_authorization: ClientAuthorization<AccessTokenRequest>,
 ) -> Result<impl IntoResponse, RouteError> {
     let user_agent = user_agent.map(|ua| UserAgent::parse(ua.as_str().to_owned()));
@@ -254,7 +325,20 @@ pub(crate) async fn post(
 
     let form = client_authorization.form.ok_or(RouteError::BadRequest)?;
 
-    let (reply, repo) = match form {
+    let grant_type = grant_type_label(&form);
+    metrics::record_token_request(grant_type);
+
+    // If the client sent a DPoP proof, it must carry a nonce we issued
+    let dpop_proof = dpop
+        .verify_proof(clock.now(), &*dpop_nonce_store, true)
+        .map_err(|e| match e {
+            DpopError::UseNonce => {
+                RouteError::DpopNonceRequired(dpop_nonce_store.issue(&mut rng, clock.now()))
+            }
+            e => RouteError::InvalidDpopProof(e),
+        })?;
+
+    let (reply, mut repo) = match form {
         AccessTokenRequest::AuthorizationCode(grant) => {
             authorization_code_grant(
                 &mut rng,
@@ -277,9 +361,12 @@ pub(crate) async fn post(
                 &activity_tracker,
                 &grant,
                 &client,
+                &key_store,
+                &url_builder,
                 &site_config,
                 repo,
                 user_agent,
+                dpop_proof.as_ref().map(DpopProof::jkt),
             )
             .await?
         }
@@ -290,6 +377,8 @@ pub(crate) async fn post(
                 &activity_tracker,
                 &grant,
                 &client,
+                &key_store,
+                &url_builder,
                 &site_config,
                 repo,
                 policy,
@@ -312,20 +401,113 @@ pub(crate) async fn post(
             )
             .await?
         }
+        AccessTokenRequest::TokenExchange(grant) => {
+            token_exchange_grant(
+                &mut rng,
+                &clock,
+                &activity_tracker,
+                &grant,
+                &client,
+                &key_store,
+                &url_builder,
+                &site_config,
+                repo,
+                user_agent,
+                dpop_proof.as_ref().map(DpopProof::jkt),
+            )
+            .await?
+        }
         _ => {
             return Err(RouteError::UnsupportedGrantType);
         }
     };
 
-    repo.save().await?;
-
     let mut headers = HeaderMap::new();
     headers.typed_insert(CacheControl::new().with_no_store());
     headers.typed_insert(Pragma::no_cache());
 
+    // Bind the access token to the key used to sign the DPoP proof, as well as
+    // the refresh tokens of its session, so that they can only be used with a
+    // proof signed by the same key
+    let reply = if let Some(dpop_proof) = dpop_proof {
+        let access_token = find_access_token(
+            &mut repo,
+            &clock,
+            &key_store,
+            &url_builder.oidc_issuer(),
+            &reply.access_token,
+        )
+        .await?
+        .ok_or_else(|| RouteError::Internal("issued access token not found".into()))?;
+
+        let session = repo
+            .oauth2_session()
+            .lookup(access_token.session_id)
+            .await?
+            .ok_or(RouteError::NoSuchOAuthSession)?;
+
+        if session.dpop_jkt.is_none() {
+            repo.oauth2_session()
+                .bind_dpop_key(session, dpop_proof.jkt().to_owned())
+                .await?;
+        }
+
+        repo.oauth2_access_token()
+            .bind_dpop_key(access_token, dpop_proof.jkt().to_owned())
+            .await?;
+
+        let nonce = dpop_nonce_store.issue(&mut rng, clock.now());
+        headers.insert(DPOP_NONCE.clone(), nonce.try_into()?);
+
+        reply.with_token_type(OAuthAccessTokenType::DPoP)
+    } else {
+        reply
+    };
+
+    repo.save().await?;
+
+    metrics::record_token_issued(grant_type);
+
     Ok((headers, Json(reply)))
 }
 
+/// The `grant_type` of a token request, as used in the metrics attributes
+fn grant_type_label(form: &AccessTokenRequest) -> &'static str {
+    match form {
+        AccessTokenRequest::AuthorizationCode(_) => "authorization_code",
+        AccessTokenRequest::RefreshToken(_) => "refresh_token",
+        AccessTokenRequest::ClientCredentials(_) => "client_credentials",
+        AccessTokenRequest::DeviceCode(_) => "urn:ietf:params:oauth:grant-type:device_code",
+        AccessTokenRequest::TokenExchange(_) => "urn:ietf:params:oauth:grant-type:token-exchange",
+        _ => "unsupported",
+    }
+}
+
+/// Record in the audit log that a new access token was issued for the given
+/// session
+async fn record_token_issued(
+    rng: &mut BoxRng,
+    clock: &impl Clock,
+    repo: &mut BoxRepository,
+    session: &Session,
+    grant_type: &'static str,
+) -> Result<(), RouteError> {
+    let mut params = AuditLogParams::new(AuditAction::TokenIssued)
+        .with_actor_oauth2_session(session)
+        .with_metadata(serde_json::json!({
+            "client_id": session.client_id.to_string(),
+            "grant_type": grant_type,
+        }));
+
+    if let Some(user_id) = session.user_id {
+        params = params.with_target_id(user_id);
+    }
+
+    repo.audit_log().record(rng, clock, params).await?;
+
+    Ok(())
+}
+
 #[allow(clippy::too_many_lines)] // TODO: refactor some parts out
 async fn authorization_code_grant(
     mut rng: &mut BoxRng,
@@ -361,20 +543,12 @@ async fn authorization_code_grant(
             exchanged_at,
             fulfilled_at,
             session_id,
+            ..
         } => {
             debug!(%exchanged_at, %fulfilled_at, "Authorization code was already exchanged");
 
-            // Ending the session if the token was already exchanged more than 20s ago
-            if now - exchanged_at > Duration::microseconds(20 * 1000 * 1000) {
-                debug!("Ending potentially compromised session");
-                let session = repo
-                    .oauth2_session()
-                    .lookup(session_id)
-                    .await?
-                    .ok_or(RouteError::NoSuchOAuthSession)?;
-                repo.oauth2_session().finish(clock, session).await?;
-                repo.save().await?;
-            }
+            end_replayed_session(clock, &mut repo, session_id).await?;
+            repo.save().await?;
 
             return Err(RouteError::InvalidGrant);
         }
@@ -384,10 +558,12 @@ async fn authorization_code_grant(
         }
         AuthorizationGrantStage::Fulfilled {
             session_id,
-            fulfilled_at,
+            code_expires_at,
+            ..
         } => {
-            if now - fulfilled_at > Duration::microseconds(10 * 60 * 1000 * 1000) {
-                debug!("Code exchange took more than 10 minutes");
+            // The lifetime is the one configured when the code was issued
+            if now >= code_expires_at {
+                debug!(%code_expires_at, "Authorization code expired");
                 return Err(RouteError::InvalidGrant);
             }
 
@@ -425,6 +601,8 @@ async fn authorization_code_grant(
         }
     };
 
+    let resource = narrow_resource(grant.resource.as_deref(), &session)?;
+
     let Some(user_session_id) = session.user_session_id else {
         tracing::warn!("No user session associated with this OAuth2 session");
         return Err(RouteError::InvalidGrant);
@@ -432,18 +610,42 @@ async fn authorization_code_grant(
 
     let browser_session = repo
         .browser_session()
-        .lookup(user_session_id)
+        .lookup(clock, user_session_id)
         .await?
         .ok_or(RouteError::NoSuchBrowserSession)?;
 
+    // The user might have been locked or deactivated since the code was issued
+    if !browser_session.user.is_valid() {
+        return Err(RouteError::InvalidGrant);
+    }
+
     let last_authentication = repo
         .browser_session()
         .get_last_authentication(&browser_session)
         .await?;
 
-    let ttl = site_config.access_token_ttl;
-    let (access_token, refresh_token) =
+    let ttl = client
+        .access_token_ttl
+        .unwrap_or(site_config.access_token_ttl);
+    let (mut access_token, refresh_token) =
         generate_token_pair(&mut rng, clock, &mut repo, &session, ttl).await?;
+    if let Some(resource) = resource {
+        access_token = repo
+            .oauth2_access_token()
+            .set_resource(access_token, resource)
+            .await?;
+    }
+    record_token_issued(rng, clock, &mut repo, &session, "authorization_code").await?;
+
+    let access_token_str = format_access_token(
+        &mut rng,
+        url_builder,
+        key_store,
+        client,
+        &session,
+        Some(&browser_session.user),
+        &access_token,
+    )?;
 
     let id_token = if session.scope.contains(&scope::OPENID) {
         Some(generate_id_token(
@@ -453,15 +655,16 @@ async fn authorization_code_grant(
             key_store,
             client,
             Some(&authz_grant),
+            &session,
             &browser_session,
-            Some(&access_token),
+            Some(&access_token_str),
             last_authentication.as_ref(),
         )?)
     } else {
         None
     };
 
-    let mut params = AccessTokenResponse::new(access_token.access_token)
+    let mut params = AccessTokenResponse::new(access_token_str)
         .with_expires_in(ttl)
         .with_refresh_token(refresh_token.refresh_token)
         .with_scope(session.scope.clone());
@@ -484,9 +687,21 @@ async fn authorization_code_grant(
         }
     }
 
-    repo.oauth2_authorization_grant()
+    // Marking the grant as exchanged only succeeds once, even with concurrent
+    // requests. If another request exchanged the code in the meantime, it was
+    // replayed, and the tokens issued here are revoked along with the session.
+    let exchanged = repo
+        .oauth2_authorization_grant()
         .exchange(clock, authz_grant)
         .await?;
+    if exchanged.is_none() {
+        debug!("Authorization code was exchanged by a concurrent request");
+
+        end_replayed_session(clock, &mut repo, session.id).await?;
+        repo.save().await?;
+
+        return Err(RouteError::InvalidGrant);
+    }
 
     // XXX: there is a potential (but unlikely) race here, where the activity for
     // the session is recorded before the transaction is committed. We would have to
@@ -498,15 +713,72 @@ async fn authorization_code_grant(
     Ok((params, repo))
 }
 
+/// End the session of an authorization code which was replayed, which revokes
+/// all the tokens issued from this code
+async fn end_replayed_session(
+    clock: &impl Clock,
+    repo: &mut BoxRepository,
+    session_id: Ulid,
+) -> Result<(), RouteError> {
+    let session = repo
+        .oauth2_session()
+        .lookup(session_id)
+        .await?
+        .ok_or(RouteError::NoSuchOAuthSession)?;
+
+    if session.is_valid() {
+        debug!("Ending potentially compromised session");
+        repo.oauth2_session().finish(clock, session).await?;
+    }
+
+    Ok(())
+}
+
+/// Find out which resources a new access token should be restricted to, from
+/// the ones the client asked for
+///
+/// Returns `None` if the access token is restricted to the same resources as
+/// its session.
+///
+/// # Errors
+///
+/// Returns an error if the client asked for resources the session is not
+/// restricted to.
+fn narrow_resource(
+    requested: Option<&[Url]>,
+    session: &Session,
+) -> Result<Option<Vec<Url>>, RouteError> {
+    let Some(requested) = requested.filter(|requested| !requested.is_empty()) else {
+        return Ok(None);
+    };
+
+    if !requested
+        .iter()
+        .all(|resource| session.resource.contains(resource))
+    {
+        return Err(RouteError::ResourceNotAllowed);
+    }
+
+    if requested == session.resource {
+        Ok(None)
+    } else {
+        Ok(Some(requested.to_vec()))
+    }
+}
+
+#[allow(clippy::too_many_lines)]
 async fn refresh_token_grant(
     rng: &mut BoxRng,
     clock: &impl Clock,
     activity_tracker: &BoundActivityTracker,
     grant: &RefreshTokenGrant,
     client: &Client,
+    key_store: &Keystore,
+    url_builder: &UrlBuilder,
     site_config: &SiteConfig,
     mut repo: BoxRepository,
     user_agent: Option<UserAgent>,
+    dpop_jkt: Option<&str>,
 ) -> Result<(AccessTokenResponse, BoxRepository), RouteError> {
     // Check that the client is allowed to use this grant type
     if !client.grant_types.contains(&GrantType::RefreshToken) {
@@ -534,10 +806,6 @@ async fn refresh_token_grant(
             .await?;
     }
 
-    if !refresh_token.is_valid() {
-        return Err(RouteError::RefreshTokenInvalid(refresh_token.id));
-    }
-
     if !session.is_valid() {
         return Err(RouteError::SessionInvalid(session.id));
     }
@@ -550,17 +818,93 @@ async fn refresh_token_grant(
         });
     }
 
+    // A refresh token which was already rotated is being reused, which means it
+    // probably leaked. End the whole session, so that neither the legitimate
+    // client nor the attacker can use the tokens issued since then.
+    if refresh_token.is_consumed() {
+        tracing::warn!(
+            refresh_token.id = %refresh_token.id,
+            session.id = %session.id,
+            "Refresh token reused, ending the session",
+        );
+        repo.oauth2_session().finish(clock, session).await?;
+        repo.save().await?;
+        return Err(RouteError::RefreshTokenInvalid(refresh_token.id));
+    }
+
+    if !refresh_token.is_valid() {
+        return Err(RouteError::RefreshTokenInvalid(refresh_token.id));
+    }
+
+    // Locked or deactivated users can't get new tokens
+    let user = if let Some(user_id) = session.user_id {
+        let user = repo
+            .user()
+            .lookup(user_id)
+            .await?
+            .filter(mas_data_model::User::is_valid)
+            .ok_or(RouteError::InvalidGrant)?;
+        Some(user)
+    } else {
+        None
+    };
+
+    // Refresh tokens of a session bound to a DPoP key can only be used with a
+    // proof signed by the same key, as per RFC 9449 section 5. The new access
+    // token is then bound to that key as well.
+    match (session.dpop_jkt.as_deref(), dpop_jkt) {
+        (Some(_), None) => return Err(RouteError::InvalidDpopProof(DpopError::MissingProof)),
+        (Some(expected), Some(jkt)) if expected != jkt => {
+            return Err(RouteError::InvalidDpopProof(DpopError::KeyMismatch));
+        }
+        _ => {}
+    }
+
+    // Refresh tokens which were not used within their time-to-live, if any,
+    // can't be exchanged anymore
+    let refresh_token_ttl = client.refresh_token_ttl.or(site_config.refresh_token_ttl);
+    if refresh_token_ttl.is_some_and(|ttl| refresh_token.created_at + ttl < clock.now()) {
+        return Err(RouteError::RefreshTokenInvalid(refresh_token.id));
+    }
+
+    // The client can ask for a narrower scope than the one of the session. This
+    // only applies to the new access token: the session scope is left untouched,
+    // so that a later refresh can ask for the full scope again.
+    let scope = match &grant.scope {
+        Some(scope) if !scope.is_subset(&session.scope) => {
+            return Err(RouteError::ScopeNotAllowed);
+        }
+        Some(scope) if *scope != session.scope => Some(scope.clone()),
+        _ => None,
+    };
+    let resource = narrow_resource(grant.resource.as_deref(), &session)?;
+
     activity_tracker
         .record_oauth2_session(clock, &session)
         .await;
 
-    let ttl = site_config.access_token_ttl;
-    let (new_access_token, new_refresh_token) =
+    let ttl = client
+        .access_token_ttl
+        .unwrap_or(site_config.access_token_ttl);
+    let (mut new_access_token, new_refresh_token) =
         generate_token_pair(rng, clock, &mut repo, &session, ttl).await?;
+    if let Some(scope) = scope {
+        new_access_token = repo
+            .oauth2_access_token()
+            .set_scope(new_access_token, scope)
+            .await?;
+    }
+    if let Some(resource) = resource {
+        new_access_token = repo
+            .oauth2_access_token()
+            .set_resource(new_access_token, resource)
+            .await?;
+    }
+    record_token_issued(rng, clock, &mut repo, &session, "refresh_token").await?;
 
     let refresh_token = repo
         .oauth2_refresh_token()
-        .consume(clock, refresh_token)
+        .consume(clock, refresh_token, &new_refresh_token)
         .await?;
 
     if let Some(access_token_id) = refresh_token.access_token_id {
@@ -572,10 +916,23 @@ async fn refresh_token_grant(
         }
     }
 
-    let params = AccessTokenResponse::new(new_access_token.access_token)
+    // JWT access tokens are about the user of the session, if any
+    let user = user.filter(|_| client.access_token_format == AccessTokenFormat::Jwt);
+    let access_token_str = format_access_token(
+        rng,
+        url_builder,
+        key_store,
+        client,
+        &session,
+        user.as_ref(),
+        &new_access_token,
+    )?;
+
+    let scope = new_access_token.scope(&session.scope).clone();
+    let params = AccessTokenResponse::new(access_token_str)
         .with_expires_in(ttl)
         .with_refresh_token(new_refresh_token.refresh_token)
-        .with_scope(session.scope);
+        .with_scope(scope);
 
     Ok((params, repo))
 }
@@ -586,6 +943,8 @@ async fn client_credentials_grant(
     activity_tracker: &BoundActivityTracker,
     grant: &ClientCredentialsGrant,
     client: &Client,
+    key_store: &Keystore,
+    url_builder: &UrlBuilder,
     site_config: &SiteConfig,
     mut repo: BoxRepository,
     mut policy: Policy,
@@ -602,6 +961,23 @@ async fn client_credentials_grant(
         .clone()
         .unwrap_or_else(|| std::iter::empty::<ScopeToken>().collect());
 
+    // Static clients can only request the scopes they were configured with
+    if let Some(allowed_scope) = &client.client_credentials_scope {
+        if !scope.is_subset(allowed_scope) {
+            return Err(RouteError::ScopeNotAllowed);
+        }
+    }
+
+    // Clients can only restrict their tokens to the resources they are allowed
+    // to request
+    let resource = grant.resource.clone().unwrap_or_default();
+    if !resource
+        .iter()
+        .all(|resource| client.allowed_resources.contains(resource))
+    {
+        return Err(RouteError::ResourceNotAllowed);
+    }
+
     // Make the request go through the policy engine
     let res = policy
         .evaluate_client_credentials_grant(&scope, client)
@@ -616,6 +992,13 @@ async fn client_credentials_grant(
         .add_from_client_credentials(rng, clock, client, scope)
         .await?;
 
+    if !resource.is_empty() {
+        session = repo
+            .oauth2_session()
+            .record_resource(session, resource)
+            .await?;
+    }
+
     if let Some(user_agent) = user_agent {
         session = repo
             .oauth2_session()
@@ -623,15 +1006,27 @@ async fn client_credentials_grant(
             .await?;
     }
 
-    let ttl = site_config.access_token_ttl;
+    let ttl = client
+        .access_token_ttl
+        .unwrap_or(site_config.access_token_ttl);
     let access_token_str = TokenType::AccessToken.generate(rng);
 
     let access_token = repo
         .oauth2_access_token()
         .add(rng, clock, &session, access_token_str, Some(ttl))
         .await?;
-
-    let mut params = AccessTokenResponse::new(access_token.access_token).with_expires_in(ttl);
+    record_token_issued(rng, clock, &mut repo, &session, "client_credentials").await?;
+
+    let access_token_str = format_access_token(
+        rng,
+        url_builder,
+        key_store,
+        client,
+        &session,
+        None,
+        &access_token,
+    )?;
+    let mut params = AccessTokenResponse::new(access_token_str).with_expires_in(ttl);
 
     // XXX: there is a potential (but unlikely) race here, where the activity for
     // the session is recorded before the transaction is committed. We would have to
@@ -648,6 +1043,170 @@ async fn client_credentials_grant(
     Ok((params, repo))
 }
 
+#[allow(clippy::too_many_lines)]
+async fn token_exchange_grant(
+    rng: &mut BoxRng,
+    clock: &impl Clock,
+    activity_tracker: &BoundActivityTracker,
+    grant: &TokenExchangeGrant,
+    client: &Client,
+    key_store: &Keystore,
+    url_builder: &UrlBuilder,
+    site_config: &SiteConfig,
+    mut repo: BoxRepository,
+    user_agent: Option<UserAgent>,
+    dpop_jkt: Option<&str>,
+) -> Result<(AccessTokenResponse, BoxRepository), RouteError> {
+    // Check that the client is allowed to use this grant type
+    if !client.grant_types.contains(&GrantType::TokenExchange) {
+        return Err(RouteError::UnauthorizedClient);
+    }
+
+    // We only support exchanging access tokens for now
+    if grant.subject_token_type != TokenTypeIdentifier::AccessToken {
+        return Err(RouteError::UnsupportedTokenType);
+    }
+
+    if grant
+        .requested_token_type
+        .as_ref()
+        .is_some_and(|t| *t != TokenTypeIdentifier::AccessToken)
+    {
+        return Err(RouteError::UnsupportedTokenType);
+    }
+
+    let subject_token = find_access_token(
+        &mut repo,
+        clock,
+        key_store,
+        &url_builder.oidc_issuer(),
+        &grant.subject_token,
+    )
+    .await?
+    .ok_or(RouteError::AccessTokenNotFound)?;
+
+    if !subject_token.is_valid(clock.now()) {
+        return Err(RouteError::AccessTokenInvalid(subject_token.id));
+    }
+
+    // A DPoP-bound token can only be exchanged with a proof signed by the same key
+    if subject_token
+        .dpop_jkt
+        .as_deref()
+        .is_some_and(|jkt| dpop_jkt != Some(jkt))
+    {
+        return Err(RouteError::InvalidDpopProof(DpopError::KeyMismatch));
+    }
+
+    let subject_session = repo
+        .oauth2_session()
+        .lookup(subject_token.session_id)
+        .await?
+        .ok_or(RouteError::NoSuchOAuthSession)?;
+
+    if !subject_session.is_valid() {
+        return Err(RouteError::SessionInvalid(subject_session.id));
+    }
+
+    // Only the client to which the subject token was issued can exchange it
+    if client.id != subject_session.client_id {
+        return Err(RouteError::ClientIDMismatch {
+            expected: subject_session.client_id,
+            actual: client.id,
+        });
+    }
+
+    // Tokens which aren't bound to a user, like the ones obtained with the client
+    // credentials grant, can't be exchanged
+    let user_id = subject_session.user_id.ok_or(RouteError::InvalidGrant)?;
+    let user = repo
+        .user()
+        .lookup(user_id)
+        .await?
+        .filter(mas_data_model::User::is_valid)
+        .ok_or(RouteError::InvalidGrant)?;
+
+    let browser_session = if let Some(user_session_id) = subject_session.user_session_id {
+        let browser_session = repo
+            .browser_session()
+            .lookup(clock, user_session_id)
+            .await?
+            .ok_or(RouteError::NoSuchBrowserSession)?;
+        Some(browser_session)
+    } else {
+        None
+    };
+
+    // The new token can only be down-scoped from the subject token, which itself
+    // may have a narrower scope than its session
+    let subject_scope = subject_token.scope(&subject_session.scope);
+    let scope = match &grant.scope {
+        Some(scope) if !scope.is_subset(subject_scope) => {
+            return Err(RouteError::ScopeNotAllowed);
+        }
+        Some(scope) => scope.clone(),
+        None => subject_scope.clone(),
+    };
+
+    let mut session = repo
+        .oauth2_session()
+        .add(
+            rng,
+            clock,
+            client,
+            Some(&user),
+            browser_session.as_ref(),
+            scope,
+        )
+        .await?;
+
+    if let Some(user_agent) = user_agent {
+        session = repo
+            .oauth2_session()
+            .record_user_agent(session, user_agent)
+            .await?;
+    }
+
+    let ttl = client
+        .access_token_ttl
+        .unwrap_or(site_config.access_token_ttl);
+    let access_token_str = TokenType::AccessToken.generate(rng);
+
+    let access_token = repo
+        .oauth2_access_token()
+        .add(rng, clock, &session, access_token_str, Some(ttl))
+        .await?;
+    record_token_issued(
+        rng,
+        clock,
+        &mut repo,
+        &session,
+        "urn:ietf:params:oauth:grant-type:token-exchange",
+    )
+    .await?;
+
+    activity_tracker
+        .record_oauth2_session(clock, &session)
+        .await;
+
+    let access_token_str = format_access_token(
+        rng,
+        url_builder,
+        key_store,
+        client,
+        &session,
+        Some(&user),
+        &access_token,
+    )?;
+    let params = AccessTokenResponse::new(access_token_str)
+        .with_expires_in(ttl)
+        .with_scope(session.scope)
+        .with_issued_token_type(TokenTypeIdentifier::AccessToken);
+
+    Ok((params, repo))
+}
+
+#[allow(clippy::too_many_lines)]
 async fn device_code_grant(
     rng: &mut BoxRng,
     clock: &impl Clock,
@@ -685,6 +1244,29 @@ async fn device_code_grant(
 
     let browser_session_id = match &grant.state {
         DeviceCodeGrantState::Pending => {
+            // Ask the client to slow down if it polls more often than the current
+            // interval, and make it wait 5 more seconds for all the subsequent polls, as
+            // per RFC 8628 section 3.5
+            let now = clock.now();
+            let slow_down = grant
+                .last_polled_at
+                .is_some_and(|last_polled_at| now - last_polled_at < grant.poll_interval);
+            let poll_interval = if slow_down {
+                grant.poll_interval + DEFAULT_DEVICE_AUTHORIZATION_INTERVAL
+            } else {
+                grant.poll_interval
+            };
+
+            // Save the poll, even though we're returning an error
+            repo.oauth2_device_code_grant()
+                .record_poll(clock, grant, poll_interval)
+                .await?;
+            repo.save().await?;
+
+            if slow_down {
+                return Err(RouteError::DeviceCodeSlowDown);
+            }
+
             return Err(RouteError::DeviceCodePending);
         }
         DeviceCodeGrantState::Rejected { .. } => {
@@ -700,7 +1282,7 @@ async fn device_code_grant(
 
     let browser_session = repo
         .browser_session()
-        .lookup(*browser_session_id)
+        .lookup(clock, *browser_session_id)
         .await?
         .ok_or(RouteError::NoSuchBrowserSession)?;
 
@@ -718,16 +1300,34 @@ async fn device_code_grant(
             .await?;
     }
 
-    let ttl = site_config.access_token_ttl;
+    let ttl = client
+        .access_token_ttl
+        .unwrap_or(site_config.access_token_ttl);
     let access_token_str = TokenType::AccessToken.generate(rng);
 
     let access_token = repo
         .oauth2_access_token()
         .add(rng, clock, &session, access_token_str, Some(ttl))
         .await?;
-
-    let mut params =
-        AccessTokenResponse::new(access_token.access_token.clone()).with_expires_in(ttl);
+    record_token_issued(
+        rng,
+        clock,
+        &mut repo,
+        &session,
+        "urn:ietf:params:oauth:grant-type:device_code",
+    )
+    .await?;
+
+    let access_token_str = format_access_token(
+        rng,
+        url_builder,
+        key_store,
+        client,
+        &session,
+        Some(&browser_session.user),
+        &access_token,
+    )?;
+    let mut params = AccessTokenResponse::new(access_token_str.clone()).with_expires_in(ttl);
 
     // If the client uses the refresh token grant type, we also generate a refresh
     // token
@@ -751,8 +1351,9 @@ async fn device_code_grant(
             key_store,
             client,
             None,
+            &session,
             &browser_session,
-            Some(&access_token),
+            Some(&access_token_str),
             None,
         )?;
 
@@ -790,18 +1391,33 @@ async fn device_code_grant(
 
 #[cfg(test)]
 mod tests {
+    use std::collections::HashMap;
+
+    use base64ct::{Base64UrlUnpadded, Encoding};
+    use chrono::Duration;
     use hyper::Request;
-    use mas_data_model::{AccessToken, AuthorizationCode, RefreshToken};
+    use mas_data_model::{AccessToken, AuthorizationCode, Pkce, RefreshToken};
+    use mas_iana::{jose::JsonWebSignatureAlg, oauth::PkceCodeChallengeMethod};
+    use mas_jose::{
+        claims::hash_token,
+        jwk::{JsonWebKey, JsonWebKeyPublicParameters},
+        jwt::{JsonWebSignatureHeader, Jwt},
+    };
+    use mas_keystore::PrivateKey;
     use mas_router::SimpleRoute;
+    use mas_storage::oauth2::UpsertStaticClientParams;
     use oauth2_types::{
         registration::ClientRegistrationResponse,
         requests::{DeviceAuthorizationResponse, ResponseMode},
-        scope::{Scope, OPENID},
+        scope::{Scope, EMAIL, OPENID},
     };
+    use sha2::{Digest, Sha256};
     use sqlx::PgPool;
 
     use super::*;
-    use crate::test_utils::{init_tracing, RequestBuilderExt, ResponseExt, TestState};
+    use crate::test_utils::{
+        init_tracing, test_site_config, RequestBuilderExt, ResponseExt, TestState,
+    };
 
     #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
     async fn test_auth_code_grant(pool: PgPool) {
@@ -836,7 +1452,27 @@ mod tests {
 
         let browser_session = repo
             .browser_session()
-            .add(&mut state.rng(), &state.clock, &user, None)
+            .add(&mut state.rng(), &state.clock, &user, None, None, None)
+            .await
+            .unwrap();
+
+        // Authenticate the browser session with a password, so that the ID token
+        // carries the authentication time and method
+        let password = repo
+            .user_password()
+            .add(
+                &mut state.rng(),
+                &state.clock,
+                &user,
+                1,
+                "hashed".to_owned(),
+                None,
+            )
+            .await
+            .unwrap();
+        let authentication = repo
+            .browser_session()
+            .authenticate_with_password(&mut state.rng(), &state.clock, &browser_session, &password)
             .await
             .unwrap();
 
@@ -868,6 +1504,9 @@ mod tests {
                 ResponseMode::Query,
                 false,
                 false,
+                false,
+                None,
+                Vec::new(),
             )
             .await
             .unwrap();
@@ -887,7 +1526,12 @@ mod tests {
         // And fulfill it
         let grant = repo
             .oauth2_authorization_grant()
-            .fulfill(&state.clock, &session, grant)
+            .fulfill(
+                &state.clock,
+                &session,
+                grant,
+                state.site_config.authorization_code_ttl,
+            )
             .await
             .unwrap();
 
@@ -905,11 +1549,41 @@ mod tests {
         let response = state.request(request).await;
         response.assert_status(StatusCode::OK);
 
-        let AccessTokenResponse { access_token, .. } = response.json();
+        let AccessTokenResponse {
+            access_token,
+            refresh_token,
+            id_token,
+            ..
+        } = response.json();
 
         // Check that the token is valid
         assert!(state.is_access_token_valid(&access_token).await);
 
+        // Check that the ID token is signed by us and has the expected claims
+        let id_token = id_token.expect("an ID token");
+        let id_token: Jwt<'_, HashMap<String, serde_json::Value>> =
+            Jwt::try_from(id_token.as_str()).unwrap();
+        id_token
+            .verify_with_jwks(&state.key_store.public_jwks())
+            .unwrap();
+
+        let alg = id_token.header().alg().clone();
+        let claims = id_token.payload();
+        let now = state.clock.now().timestamp();
+        assert_eq!(claims["iss"], state.url_builder.oidc_issuer().as_str());
+        assert_eq!(claims["sub"], user.sub.as_str());
+        assert_eq!(claims["aud"], client.client_id.as_str());
+        assert_eq!(claims["iat"], now);
+        assert_eq!(claims["exp"], now + 60 * 60);
+        assert_eq!(claims["nonce"], "nonce");
+        assert_eq!(claims["auth_time"], authentication.created_at.timestamp());
+        assert_eq!(claims["amr"], serde_json::json!(["pwd"]));
+        assert_eq!(
+            claims["at_hash"],
+            hash_token(&alg, &access_token).unwrap().as_str()
+        );
+        assert_eq!(claims["c_hash"], hash_token(&alg, code).unwrap().as_str());
+
         // Exchange it again, this it should fail
         let request =
             Request::post(mas_router::OAuth2TokenEndpoint::PATH).form(serde_json::json!({
@@ -924,13 +1598,27 @@ mod tests {
         let error: ClientError = response.json();
         assert_eq!(error.error, ClientErrorCode::InvalidGrant);
 
-        // The token should still be valid
-        assert!(state.is_access_token_valid(&access_token).await);
+        // And it should have revoked the token we got
+        assert!(!state.is_access_token_valid(&access_token).await);
 
-        // Now wait a bit
-        state.clock.advance(Duration::try_minutes(1).unwrap());
+        // Including the refresh token, as the whole session was ended
+        let mut repo = state.repository().await.unwrap();
+        let refresh_token = repo
+            .oauth2_refresh_token()
+            .find_by_token(&refresh_token.expect("a refresh token"))
+            .await
+            .unwrap()
+            .unwrap();
+        let session = repo
+            .oauth2_session()
+            .lookup(refresh_token.session_id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(!session.is_valid());
+        repo.cancel().await.unwrap();
 
-        // Exchange it again, this it should fail
+        // Replaying it again still fails
         let request =
             Request::post(mas_router::OAuth2TokenEndpoint::PATH).form(serde_json::json!({
                 "grant_type": "authorization_code",
@@ -944,9 +1632,6 @@ mod tests {
         let error: ClientError = response.json();
         assert_eq!(error.error, ClientErrorCode::InvalidGrant);
 
-        // And it should have revoked the token we got
-        assert!(!state.is_access_token_valid(&access_token).await);
-
         // Try another one and wait for too long before exchanging it
         let mut repo = state.repository().await.unwrap();
         let code = "thisisanothercode";
@@ -968,6 +1653,9 @@ mod tests {
                 ResponseMode::Query,
                 false,
                 false,
+                false,
+                None,
+                Vec::new(),
             )
             .await
             .unwrap();
@@ -987,16 +1675,19 @@ mod tests {
         // And fulfill it
         let grant = repo
             .oauth2_authorization_grant()
-            .fulfill(&state.clock, &session, grant)
+            .fulfill(
+                &state.clock,
+                &session,
+                grant,
+                state.site_config.authorization_code_ttl,
+            )
             .await
             .unwrap();
 
         repo.save().await.unwrap();
 
-        // Now wait a bit
-        state
-            .clock
-            .advance(Duration::microseconds(15 * 60 * 1000 * 1000));
+        // Now wait for longer than the authorization code lifetime
+        state.clock.advance(Duration::try_seconds(61).unwrap());
 
         // Exchange it, it should fail
         let request =
@@ -1013,6 +1704,159 @@ mod tests {
         assert_eq!(error, ClientErrorCode::InvalidGrant);
     }
 
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_auth_code_grant_pkce(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+
+        // This verifier and challenge come from the RFC7636 appendices
+        let verifier = "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk";
+        let challenge = "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM";
+
+        // Provision a client
+        let request =
+            Request::post(mas_router::OAuth2RegistrationEndpoint::PATH).json(serde_json::json!({
+                "client_uri": "https://example.com/",
+                "redirect_uris": ["https://example.com/callback"],
+                "contacts": ["contact@example.com"],
+                "token_endpoint_auth_method": "none",
+                "response_types": ["code"],
+                "grant_types": ["authorization_code"],
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::CREATED);
+
+        let ClientRegistrationResponse { client_id, .. } = response.json();
+
+        // Provision a user with a browser session
+        let mut repo = state.repository().await.unwrap();
+
+        let user = repo
+            .user()
+            .add(&mut state.rng(), &state.clock, "alice".to_owned())
+            .await
+            .unwrap();
+
+        let browser_session = repo
+            .browser_session()
+            .add(&mut state.rng(), &state.clock, &user, None, None, None)
+            .await
+            .unwrap();
+
+        let client = repo
+            .oauth2_client()
+            .find_by_client_id(&client_id)
+            .await
+            .unwrap()
+            .unwrap();
+
+        // Start and fulfill a few grants, with and without a code challenge
+        let grants = [
+            ("s256code", Some(PkceCodeChallengeMethod::S256), challenge),
+            ("plaincode", Some(PkceCodeChallengeMethod::Plain), verifier),
+            ("nopkcecode", None, ""),
+        ];
+
+        for (code, method, challenge) in grants {
+            let grant = repo
+                .oauth2_authorization_grant()
+                .add(
+                    &mut state.rng(),
+                    &state.clock,
+                    &client,
+                    "https://example.com/redirect".parse().unwrap(),
+                    Scope::from_iter([OPENID]),
+                    Some(AuthorizationCode {
+                        code: code.to_owned(),
+                        pkce: method.map(|method| Pkce::new(method, challenge.to_owned())),
+                    }),
+                    Some("state".to_owned()),
+                    Some("nonce".to_owned()),
+                    None,
+                    ResponseMode::Query,
+                    false,
+                    false,
+                    false,
+                    None,
+                    Vec::new(),
+                )
+                .await
+                .unwrap();
+
+            let session = repo
+                .oauth2_session()
+                .add_from_browser_session(
+                    &mut state.rng(),
+                    &state.clock,
+                    &client,
+                    &browser_session,
+                    grant.scope.clone(),
+                )
+                .await
+                .unwrap();
+
+            repo.oauth2_authorization_grant()
+                .fulfill(
+                    &state.clock,
+                    &session,
+                    grant,
+                    state.site_config.authorization_code_ttl,
+                )
+                .await
+                .unwrap();
+        }
+
+        repo.save().await.unwrap();
+
+        let exchange = |code: &str, code_verifier: Option<&str>| {
+            let mut form = serde_json::json!({
+                "grant_type": "authorization_code",
+                "code": code,
+                "redirect_uri": "https://example.com/redirect",
+                "client_id": client.client_id,
+            });
+            if let Some(code_verifier) = code_verifier {
+                form["code_verifier"] = code_verifier.into();
+            }
+            Request::post(mas_router::OAuth2TokenEndpoint::PATH).form(form)
+        };
+
+        // Omitting the verifier is rejected
+        let response = state.request(exchange("s256code", None)).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        let ClientError { error, .. } = response.json();
+        assert_eq!(error, ClientErrorCode::InvalidRequest);
+
+        // So is a verifier which doesn't match the challenge
+        let wrong_verifier = "xxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxx";
+        let response = state
+            .request(exchange("s256code", Some(wrong_verifier)))
+            .await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        let ClientError { error, .. } = response.json();
+        assert_eq!(error, ClientErrorCode::InvalidGrant);
+
+        // The right verifier works, with both methods
+        let response = state.request(exchange("s256code", Some(verifier))).await;
+        response.assert_status(StatusCode::OK);
+        let AccessTokenResponse { access_token, .. } = response.json();
+        assert!(state.is_access_token_valid(&access_token).await);
+
+        let response = state.request(exchange("plaincode", Some(verifier))).await;
+        response.assert_status(StatusCode::OK);
+
+        // Sending a verifier when there was no challenge is rejected
+        let response = state.request(exchange("nopkcecode", Some(verifier))).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        let ClientError { error, .. } = response.json();
+        assert_eq!(error, ClientErrorCode::InvalidRequest);
+
+        // But the code can still be exchanged without one
+        let response = state.request(exchange("nopkcecode", None)).await;
+        response.assert_status(StatusCode::OK);
+    }
+
     #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
     async fn test_refresh_token_grant(pool: PgPool) {
         init_tracing();
@@ -1046,7 +1890,7 @@ mod tests {
 
         let browser_session = repo
             .browser_session()
-            .add(&mut state.rng(), &state.clock, &user, None)
+            .add(&mut state.rng(), &state.clock, &user, None, None, None)
             .await
             .unwrap();
 
@@ -1110,6 +1954,42 @@ mod tests {
         // Check that the old token is no longer valid
         assert!(!state.is_access_token_valid(&old_access_token).await);
 
+        // Check that the old refresh token points to the one which replaced it
+        let mut repo = state.repository().await.unwrap();
+        let old_refresh_token_lookup = repo
+            .oauth2_refresh_token()
+            .find_by_token(&old_refresh_token)
+            .await
+            .unwrap()
+            .unwrap();
+        let new_refresh_token_lookup = repo
+            .oauth2_refresh_token()
+            .find_by_token(&refresh_token)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(old_refresh_token_lookup.is_consumed());
+        assert_eq!(
+            old_refresh_token_lookup.state.next_refresh_token_id(),
+            Some(new_refresh_token_lookup.id)
+        );
+        repo.cancel().await.unwrap();
+
+        // Call it again with the new token, it should rotate it again
+        let request =
+            Request::post(mas_router::OAuth2TokenEndpoint::PATH).form(serde_json::json!({
+                "grant_type": "refresh_token",
+                "refresh_token": refresh_token,
+                "client_id": client.client_id,
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let response: AccessTokenResponse = response.json();
+        let access_token = response.access_token;
+        let refresh_token = response.refresh_token.expect("to have a refresh token");
+        assert!(state.is_access_token_valid(&access_token).await);
+
         // Call it again with the old token, it should fail
         let request =
             Request::post(mas_router::OAuth2TokenEndpoint::PATH).form(serde_json::json!({
@@ -1123,7 +2003,10 @@ mod tests {
         let ClientError { error, .. } = response.json();
         assert_eq!(error, ClientErrorCode::InvalidGrant);
 
-        // Call it again with the new token, it should work
+        // Reusing a rotated token ends the whole session, so the latest tokens are
+        // no longer valid either
+        assert!(!state.is_access_token_valid(&access_token).await);
+
         let request =
             Request::post(mas_router::OAuth2TokenEndpoint::PATH).form(serde_json::json!({
                 "grant_type": "refresh_token",
@@ -1132,12 +2015,22 @@ mod tests {
             }));
 
         let response = state.request(request).await;
-        response.assert_status(StatusCode::OK);
-        let _: AccessTokenResponse = response.json();
+        response.assert_status(StatusCode::BAD_REQUEST);
+        let ClientError { error, .. } = response.json();
+        assert_eq!(error, ClientErrorCode::InvalidGrant);
+
+        let mut repo = state.repository().await.unwrap();
+        let session = repo
+            .oauth2_session()
+            .lookup(session.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(!session.is_valid());
     }
 
     #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
-    async fn test_client_credentials(pool: PgPool) {
+    async fn test_refresh_token_grant_locked_user(pool: PgPool) {
         init_tracing();
         let state = TestState::from_pool(pool).await.unwrap();
 
@@ -1145,95 +2038,470 @@ mod tests {
         let request =
             Request::post(mas_router::OAuth2RegistrationEndpoint::PATH).json(serde_json::json!({
                 "client_uri": "https://example.com/",
+                "redirect_uris": ["https://example.com/callback"],
                 "contacts": ["contact@example.com"],
-                "token_endpoint_auth_method": "client_secret_post",
-                "grant_types": ["client_credentials"],
+                "token_endpoint_auth_method": "none",
+                "response_types": ["code"],
+                "grant_types": ["authorization_code", "refresh_token"],
             }));
 
         let response = state.request(request).await;
         response.assert_status(StatusCode::CREATED);
 
-        let response: ClientRegistrationResponse = response.json();
-        let client_id = response.client_id;
-        let client_secret = response.client_secret.expect("to have a client secret");
+        let ClientRegistrationResponse { client_id, .. } = response.json();
 
-        // Call the token endpoint with an empty scope
-        let request =
-            Request::post(mas_router::OAuth2TokenEndpoint::PATH).form(serde_json::json!({
-                "grant_type": "client_credentials",
-                "client_id": client_id,
-                "client_secret": client_secret,
-            }));
+        let mut repo = state.repository().await.unwrap();
 
-        let response = state.request(request).await;
-        response.assert_status(StatusCode::OK);
+        let user = repo
+            .user()
+            .add(&mut state.rng(), &state.clock, "alice".to_owned())
+            .await
+            .unwrap();
 
-        let response: AccessTokenResponse = response.json();
-        assert!(response.refresh_token.is_none());
-        assert!(response.expires_in.is_some());
-        assert!(response.scope.is_none());
+        let browser_session = repo
+            .browser_session()
+            .add(&mut state.rng(), &state.clock, &user, None, None, None)
+            .await
+            .unwrap();
 
-        // Revoke the token
-        let request = Request::post(mas_router::OAuth2Revocation::PATH).form(serde_json::json!({
-            "token": response.access_token,
-            "client_id": client_id,
-            "client_secret": client_secret,
-        }));
+        let client = repo
+            .oauth2_client()
+            .find_by_client_id(&client_id)
+            .await
+            .unwrap()
+            .unwrap();
 
-        let response = state.request(request).await;
-        response.assert_status(StatusCode::OK);
+        let session = repo
+            .oauth2_session()
+            .add_from_browser_session(
+                &mut state.rng(),
+                &state.clock,
+                &client,
+                &browser_session,
+                Scope::from_iter([OPENID]),
+            )
+            .await
+            .unwrap();
+
+        let (_, RefreshToken { refresh_token, .. }) = generate_token_pair(
+            &mut state.rng(),
+            &state.clock,
+            &mut repo,
+            &session,
+            Duration::microseconds(5 * 60 * 1000 * 1000),
+        )
+        .await
+        .unwrap();
+
+        // Lock the user, the refresh token can't be used anymore
+        repo.user().lock(&state.clock, user).await.unwrap();
+        repo.save().await.unwrap();
 
-        // We should be allowed to ask for the GraphQL API scope
         let request =
             Request::post(mas_router::OAuth2TokenEndpoint::PATH).form(serde_json::json!({
-                "grant_type": "client_credentials",
-                "client_id": client_id,
-                "client_secret": client_secret,
-                "scope": "urn:mas:graphql:*"
+                "grant_type": "refresh_token",
+                "refresh_token": refresh_token,
+                "client_id": client.client_id,
             }));
 
         let response = state.request(request).await;
-        response.assert_status(StatusCode::OK);
-
-        let response: AccessTokenResponse = response.json();
-        assert!(response.refresh_token.is_none());
-        assert!(response.expires_in.is_some());
-        assert_eq!(response.scope, Some("urn:mas:graphql:*".parse().unwrap()));
-
-        // Revoke the token
-        let request = Request::post(mas_router::OAuth2Revocation::PATH).form(serde_json::json!({
-            "token": response.access_token,
-            "client_id": client_id,
-            "client_secret": client_secret,
-        }));
+        response.assert_status(StatusCode::BAD_REQUEST);
+        let ClientError { error, .. } = response.json();
+        assert_eq!(error, ClientErrorCode::InvalidGrant);
+    }
 
-        let response = state.request(request).await;
-        response.assert_status(StatusCode::OK);
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_refresh_token_grant_scope(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
 
-        // We should be NOT allowed to ask for the MAS admin scope
+        // Provision a client
         let request =
-            Request::post(mas_router::OAuth2TokenEndpoint::PATH).form(serde_json::json!({
-                "grant_type": "client_credentials",
-                "client_id": client_id,
-                "client_secret": client_secret,
-                "scope": "urn:mas:admin"
+            Request::post(mas_router::OAuth2RegistrationEndpoint::PATH).json(serde_json::json!({
+                "client_uri": "https://example.com/",
+                "redirect_uris": ["https://example.com/callback"],
+                "contacts": ["contact@example.com"],
+                "token_endpoint_auth_method": "none",
+                "response_types": ["code"],
+                "grant_types": ["authorization_code", "refresh_token"],
             }));
 
         let response = state.request(request).await;
-        response.assert_status(StatusCode::FORBIDDEN);
+        response.assert_status(StatusCode::CREATED);
 
-        let ClientError { error, .. } = response.json();
-        assert_eq!(error, ClientErrorCode::InvalidScope);
+        let ClientRegistrationResponse { client_id, .. } = response.json();
 
-        // Now, if we add the client to the admin list in the policy, it should work
-        let state = {
-            let mut state = state;
-            state.policy_factory = crate::test_utils::policy_factory(serde_json::json!({
-                "admin_clients": [client_id]
-            }))
-            .await
-            .unwrap();
-            state
+        // Provision a user and a session with two scopes
+        let mut repo = state.repository().await.unwrap();
+
+        let user = repo
+            .user()
+            .add(&mut state.rng(), &state.clock, "alice".to_owned())
+            .await
+            .unwrap();
+
+        let browser_session = repo
+            .browser_session()
+            .add(&mut state.rng(), &state.clock, &user, None, None, None)
+            .await
+            .unwrap();
+
+        let client = repo
+            .oauth2_client()
+            .find_by_client_id(&client_id)
+            .await
+            .unwrap()
+            .unwrap();
+
+        let session = repo
+            .oauth2_session()
+            .add_from_browser_session(
+                &mut state.rng(),
+                &state.clock,
+                &client,
+                &browser_session,
+                Scope::from_iter([OPENID, EMAIL]),
+            )
+            .await
+            .unwrap();
+
+        let (_, RefreshToken { refresh_token, .. }) = generate_token_pair(
+            &mut state.rng(),
+            &state.clock,
+            &mut repo,
+            &session,
+            Duration::microseconds(5 * 60 * 1000 * 1000),
+        )
+        .await
+        .unwrap();
+
+        repo.save().await.unwrap();
+
+        let refresh = |refresh_token: &str, scope: &str| {
+            Request::post(mas_router::OAuth2TokenEndpoint::PATH).form(serde_json::json!({
+                "grant_type": "refresh_token",
+                "refresh_token": refresh_token,
+                "client_id": client.client_id,
+                "scope": scope,
+            }))
+        };
+
+        // Asking for a scope the session doesn't have is rejected
+        let response = state.request(refresh(&refresh_token, "openid phone")).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        let ClientError { error, .. } = response.json();
+        assert_eq!(error, ClientErrorCode::InvalidScope);
+
+        // Asking for a narrower scope issues a token with only that scope
+        let response = state.request(refresh(&refresh_token, "openid")).await;
+        response.assert_status(StatusCode::OK);
+        let response: AccessTokenResponse = response.json();
+        assert_eq!(response.scope, Some(Scope::from_iter([OPENID])));
+        let refresh_token = response.refresh_token.expect("to have a refresh token");
+
+        let mut repo = state.repository().await.unwrap();
+        let access_token = repo
+            .oauth2_access_token()
+            .find_by_token(&response.access_token)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(access_token.scope, Some(Scope::from_iter([OPENID])));
+
+        // The session keeps its original scope
+        let session = repo
+            .oauth2_session()
+            .lookup(session.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(session.scope, Scope::from_iter([OPENID, EMAIL]));
+        repo.cancel().await.unwrap();
+
+        // So the next refresh can go back up to the original scope
+        let response = state.request(refresh(&refresh_token, "openid email")).await;
+        response.assert_status(StatusCode::OK);
+        let response: AccessTokenResponse = response.json();
+        assert_eq!(response.scope, Some(Scope::from_iter([OPENID, EMAIL])));
+
+        let mut repo = state.repository().await.unwrap();
+        let access_token = repo
+            .oauth2_access_token()
+            .find_by_token(&response.access_token)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(access_token.scope, None);
+        repo.cancel().await.unwrap();
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_access_token_ttl(pool: PgPool) {
+        init_tracing();
+        let site_config = SiteConfig {
+            access_token_ttl: Duration::try_hours(1).unwrap(),
+            ..test_site_config()
+        };
+        let state = TestState::from_pool_with_site_config(pool, site_config)
+            .await
+            .unwrap();
+
+        // Provision a client
+        let request =
+            Request::post(mas_router::OAuth2RegistrationEndpoint::PATH).json(serde_json::json!({
+                "client_uri": "https://example.com/",
+                "contacts": ["contact@example.com"],
+                "token_endpoint_auth_method": "client_secret_post",
+                "grant_types": ["client_credentials"],
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::CREATED);
+
+        let response: ClientRegistrationResponse = response.json();
+        let client_id = response.client_id;
+        let client_secret = response.client_secret.expect("to have a client secret");
+
+        // The configured TTL should be applied to the access token
+        let request =
+            Request::post(mas_router::OAuth2TokenEndpoint::PATH).form(serde_json::json!({
+                "grant_type": "client_credentials",
+                "client_id": client_id,
+                "client_secret": client_secret,
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+
+        let response: AccessTokenResponse = response.json();
+        assert_eq!(response.expires_in, Some(Duration::try_hours(1).unwrap()));
+
+        // And the token should be expired after that time
+        let mut repo = state.repository().await.unwrap();
+        let access_token = repo
+            .oauth2_access_token()
+            .find_by_token(&response.access_token)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(access_token.is_valid(state.clock.now()));
+        state.clock.advance(Duration::try_minutes(61).unwrap());
+        assert!(!access_token.is_valid(state.clock.now()));
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_client_token_ttl(pool: PgPool) {
+        init_tracing();
+        let site_config = SiteConfig {
+            access_token_ttl: Duration::try_hours(1).unwrap(),
+            refresh_token_ttl: Some(Duration::try_hours(2).unwrap()),
+            ..test_site_config()
+        };
+        let state = TestState::from_pool_with_site_config(pool, site_config)
+            .await
+            .unwrap();
+
+        // Provision a client which overrides the TTLs
+        let mut repo = state.repository().await.unwrap();
+        let client = repo
+            .oauth2_client()
+            .upsert_static(UpsertStaticClientParams {
+                client_id: Ulid::from_datetime_with_source(
+                    state.clock.now().into(),
+                    &mut state.rng(),
+                ),
+                client_auth_method: mas_iana::oauth::OAuthClientAuthenticationMethod::None,
+                encrypted_client_secret: None,
+                jwks: None,
+                jwks_uri: None,
+                redirect_uris: vec!["https://example.com/callback".parse().unwrap()],
+                redirect_uri_matching: mas_data_model::RedirectUriMatching::Exact,
+                response_types: vec![mas_iana::oauth::OAuthAuthorizationEndpointResponseType::Code],
+                allow_token_exchange: false,
+                allow_client_credentials: false,
+                client_credentials_scope: None,
+                allowed_resources: Vec::new(),
+                require_signed_request_object: false,
+                require_pkce: None,
+                access_token_ttl: Some(Duration::try_minutes(10).unwrap()),
+                refresh_token_ttl: Some(Duration::try_minutes(30).unwrap()),
+                access_token_format: mas_data_model::AccessTokenFormat::Opaque,
+                introspection_endpoint_auth_method: None,
+                post_logout_redirect_uris: Vec::new(),
+                backchannel_logout_uri: None,
+                backchannel_logout_session_required: false,
+                trusted: false,
+            })
+            .await
+            .unwrap();
+
+        let user = repo
+            .user()
+            .add(&mut state.rng(), &state.clock, "alice".to_owned())
+            .await
+            .unwrap();
+
+        let browser_session = repo
+            .browser_session()
+            .add(&mut state.rng(), &state.clock, &user, None, None, None)
+            .await
+            .unwrap();
+
+        let session = repo
+            .oauth2_session()
+            .add_from_browser_session(
+                &mut state.rng(),
+                &state.clock,
+                &client,
+                &browser_session,
+                Scope::from_iter([OPENID]),
+            )
+            .await
+            .unwrap();
+
+        let (_, RefreshToken { refresh_token, .. }) = generate_token_pair(
+            &mut state.rng(),
+            &state.clock,
+            &mut repo,
+            &session,
+            Duration::try_minutes(10).unwrap(),
+        )
+        .await
+        .unwrap();
+
+        repo.save().await.unwrap();
+
+        // The client TTL is used for the new access token
+        let request =
+            Request::post(mas_router::OAuth2TokenEndpoint::PATH).form(serde_json::json!({
+                "grant_type": "refresh_token",
+                "refresh_token": refresh_token,
+                "client_id": client.client_id,
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let response: AccessTokenResponse = response.json();
+        assert_eq!(
+            response.expires_in,
+            Some(Duration::try_minutes(10).unwrap())
+        );
+        let refresh_token = response.refresh_token.expect("to have a refresh token");
+
+        // The new refresh token expires after the client TTL, not the site-wide one
+        state.clock.advance(Duration::try_minutes(31).unwrap());
+
+        let request =
+            Request::post(mas_router::OAuth2TokenEndpoint::PATH).form(serde_json::json!({
+                "grant_type": "refresh_token",
+                "refresh_token": refresh_token,
+                "client_id": client.client_id,
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        let ClientError { error, .. } = response.json();
+        assert_eq!(error, ClientErrorCode::InvalidGrant);
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_client_credentials(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+
+        // Provision a client
+        let request =
+            Request::post(mas_router::OAuth2RegistrationEndpoint::PATH).json(serde_json::json!({
+                "client_uri": "https://example.com/",
+                "contacts": ["contact@example.com"],
+                "token_endpoint_auth_method": "client_secret_post",
+                "grant_types": ["client_credentials"],
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::CREATED);
+
+        let response: ClientRegistrationResponse = response.json();
+        let client_id = response.client_id;
+        let client_secret = response.client_secret.expect("to have a client secret");
+
+        // Call the token endpoint with an empty scope
+        let request =
+            Request::post(mas_router::OAuth2TokenEndpoint::PATH).form(serde_json::json!({
+                "grant_type": "client_credentials",
+                "client_id": client_id,
+                "client_secret": client_secret,
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+
+        let response: AccessTokenResponse = response.json();
+        assert!(response.refresh_token.is_none());
+        assert!(response.expires_in.is_some());
+        assert!(response.scope.is_none());
+
+        // Revoke the token
+        let request = Request::post(mas_router::OAuth2Revocation::PATH).form(serde_json::json!({
+            "token": response.access_token,
+            "client_id": client_id,
+            "client_secret": client_secret,
+        }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+
+        // We should be allowed to ask for the GraphQL API scope
+        let request =
+            Request::post(mas_router::OAuth2TokenEndpoint::PATH).form(serde_json::json!({
+                "grant_type": "client_credentials",
+                "client_id": client_id,
+                "client_secret": client_secret,
+                "scope": "urn:mas:graphql:*"
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+
+        let response: AccessTokenResponse = response.json();
+        assert!(response.refresh_token.is_none());
+        assert!(response.expires_in.is_some());
+        assert_eq!(response.scope, Some("urn:mas:graphql:*".parse().unwrap()));
+
+        // Revoke the token
+        let request = Request::post(mas_router::OAuth2Revocation::PATH).form(serde_json::json!({
+            "token": response.access_token,
+            "client_id": client_id,
+            "client_secret": client_secret,
+        }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+
+        // We should be NOT allowed to ask for the MAS admin scope
+        let request =
+            Request::post(mas_router::OAuth2TokenEndpoint::PATH).form(serde_json::json!({
+                "grant_type": "client_credentials",
+                "client_id": client_id,
+                "client_secret": client_secret,
+                "scope": "urn:mas:admin"
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::FORBIDDEN);
+
+        let ClientError { error, .. } = response.json();
+        assert_eq!(error, ClientErrorCode::InvalidScope);
+
+        // Now, if we add the client to the admin list in the policy, it should work
+        let state = {
+            let mut state = state;
+            state.policy_factory = crate::test_utils::policy_factory(serde_json::json!({
+                "admin_clients": [client_id]
+            }))
+            .await
+            .unwrap();
+            state
         };
 
         let request =
@@ -1263,6 +2531,170 @@ mod tests {
         response.assert_status(StatusCode::OK);
     }
 
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_static_client_credentials(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+
+        let client_secret = "client-secret";
+        let encrypted_client_secret = state
+            .encrypter
+            .encrypt_to_string(client_secret.as_bytes())
+            .unwrap();
+
+        // Provision two static clients, only one of them being allowed to use the
+        // client credentials grant, with a restricted set of scopes and resources
+        let mut repo = state.repository().await.unwrap();
+        let mut clients = Vec::new();
+        for allow_client_credentials in [true, false] {
+            let client = repo
+                .oauth2_client()
+                .upsert_static(UpsertStaticClientParams {
+                    client_id: Ulid::from_datetime_with_source(
+                        state.clock.now().into(),
+                        &mut state.rng(),
+                    ),
+                    client_auth_method:
+                        mas_iana::oauth::OAuthClientAuthenticationMethod::ClientSecretPost,
+                    encrypted_client_secret: Some(encrypted_client_secret.clone()),
+                    jwks: None,
+                    jwks_uri: None,
+                    redirect_uris: Vec::new(),
+                    redirect_uri_matching: mas_data_model::RedirectUriMatching::Exact,
+                    response_types: vec![
+                        mas_iana::oauth::OAuthAuthorizationEndpointResponseType::Code,
+                    ],
+                    allow_token_exchange: false,
+                    allow_client_credentials,
+                    client_credentials_scope: allow_client_credentials
+                        .then(|| "urn:mas:graphql:*".parse().unwrap()),
+                    allowed_resources: vec!["https://a.example.com/".parse().unwrap()],
+                    require_signed_request_object: false,
+                    require_pkce: None,
+                    access_token_ttl: None,
+                    refresh_token_ttl: None,
+                    access_token_format: mas_data_model::AccessTokenFormat::Opaque,
+                    introspection_endpoint_auth_method: None,
+                    post_logout_redirect_uris: Vec::new(),
+                    backchannel_logout_uri: None,
+                    backchannel_logout_session_required: false,
+                    trusted: false,
+                })
+                .await
+                .unwrap();
+            clients.push(client);
+        }
+        repo.save().await.unwrap();
+        let [allowed, denied] = clients.try_into().unwrap();
+
+        // The allowed client can get a token for a scope it was configured with
+        let request =
+            Request::post(mas_router::OAuth2TokenEndpoint::PATH).form(serde_json::json!({
+                "grant_type": "client_credentials",
+                "client_id": allowed.client_id,
+                "client_secret": client_secret,
+                "scope": "urn:mas:graphql:*",
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+
+        let response: AccessTokenResponse = response.json();
+        assert!(response.refresh_token.is_none());
+        assert_eq!(response.scope, Some("urn:mas:graphql:*".parse().unwrap()));
+
+        // The token is active, and isn't attached to a user
+        let mut repo = state.repository().await.unwrap();
+        let access_token = repo
+            .oauth2_access_token()
+            .find_by_token(&response.access_token)
+            .await
+            .unwrap()
+            .expect("access token exists");
+        let session = repo
+            .oauth2_session()
+            .lookup(access_token.session_id)
+            .await
+            .unwrap()
+            .expect("session exists");
+        repo.cancel().await.unwrap();
+        assert!(session.user_id.is_none());
+        assert!(session.user_session_id.is_none());
+
+        // ...but not for other scopes, even if the policy would allow them
+        let request =
+            Request::post(mas_router::OAuth2TokenEndpoint::PATH).form(serde_json::json!({
+                "grant_type": "client_credentials",
+                "client_id": allowed.client_id,
+                "client_secret": client_secret,
+                "scope": "urn:mas:graphql:* openid",
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        let ClientError { error, .. } = response.json();
+        assert_eq!(error, ClientErrorCode::InvalidScope);
+
+        // The token can be restricted to a resource the client is allowed to request
+        let request =
+            Request::post(mas_router::OAuth2TokenEndpoint::PATH).form(serde_json::json!({
+                "grant_type": "client_credentials",
+                "client_id": allowed.client_id,
+                "client_secret": client_secret,
+                "resource": "https://a.example.com/",
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let response: AccessTokenResponse = response.json();
+
+        let mut repo = state.repository().await.unwrap();
+        let access_token = repo
+            .oauth2_access_token()
+            .find_by_token(&response.access_token)
+            .await
+            .unwrap()
+            .expect("access token exists");
+        let session = repo
+            .oauth2_session()
+            .lookup(access_token.session_id)
+            .await
+            .unwrap()
+            .expect("session exists");
+        repo.cancel().await.unwrap();
+        assert_eq!(
+            session.resource,
+            vec![Url::parse("https://a.example.com/").unwrap()]
+        );
+
+        // ...but not to other resources, passed as repeated parameters
+        let request = Request::post(mas_router::OAuth2TokenEndpoint::PATH).form([
+            ("grant_type", "client_credentials"),
+            ("client_id", allowed.client_id.as_str()),
+            ("client_secret", client_secret),
+            ("resource", "https://a.example.com/"),
+            ("resource", "https://b.example.com/"),
+        ]);
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        let ClientError { error, .. } = response.json();
+        assert_eq!(error, ClientErrorCode::InvalidTarget);
+
+        // The other client can't use the grant at all
+        let request =
+            Request::post(mas_router::OAuth2TokenEndpoint::PATH).form(serde_json::json!({
+                "grant_type": "client_credentials",
+                "client_id": denied.client_id,
+                "client_secret": client_secret,
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::UNAUTHORIZED);
+        let ClientError { error, .. } = response.json();
+        assert_eq!(error, ClientErrorCode::UnauthorizedClient);
+    }
+
     #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
     async fn test_device_code_grant(pool: PgPool) {
         init_tracing();
@@ -1288,15 +2720,59 @@ mod tests {
         let request = Request::post(mas_router::OAuth2DeviceAuthorizationEndpoint::PATH).form(
             serde_json::json!({
                 "client_id": client_id,
-                "scope": "openid",
-            }),
-        );
+                "scope": "openid",
+            }),
+        );
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+
+        let device_grant: DeviceAuthorizationResponse = response.json();
+
+        // Poll the token endpoint, it should be pending
+        let request =
+            Request::post(mas_router::OAuth2TokenEndpoint::PATH).form(serde_json::json!({
+                "grant_type": "urn:ietf:params:oauth:grant-type:device_code",
+                "device_code": device_grant.device_code,
+                "client_id": client_id,
+            }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::FORBIDDEN);
+
+        let ClientError { error, .. } = response.json();
+        assert_eq!(error, ClientErrorCode::AuthorizationPending);
+
+        // Polling again right away should ask the client to slow down
+        let request =
+            Request::post(mas_router::OAuth2TokenEndpoint::PATH).form(serde_json::json!({
+                "grant_type": "urn:ietf:params:oauth:grant-type:device_code",
+                "device_code": device_grant.device_code,
+                "client_id": client_id,
+            }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::FORBIDDEN);
+
+        let ClientError { error, .. } = response.json();
+        assert_eq!(error, ClientErrorCode::SlowDown);
+
+        // The interval grew by 5 seconds, so waiting for the advertised interval
+        // isn't enough anymore
+        state.clock.advance(device_grant.interval());
+        let request =
+            Request::post(mas_router::OAuth2TokenEndpoint::PATH).form(serde_json::json!({
+                "grant_type": "urn:ietf:params:oauth:grant-type:device_code",
+                "device_code": device_grant.device_code,
+                "client_id": client_id,
+            }));
         let response = state.request(request).await;
-        response.assert_status(StatusCode::OK);
+        response.assert_status(StatusCode::FORBIDDEN);
 
-        let device_grant: DeviceAuthorizationResponse = response.json();
+        let ClientError { error, .. } = response.json();
+        assert_eq!(error, ClientErrorCode::SlowDown);
 
-        // Poll the token endpoint, it should be pending
+        // It grew by 5 more seconds. Once it has passed, it should be pending again
+        state
+            .clock
+            .advance(device_grant.interval() + Duration::try_seconds(10).unwrap());
         let request =
             Request::post(mas_router::OAuth2TokenEndpoint::PATH).form(serde_json::json!({
                 "grant_type": "urn:ietf:params:oauth:grant-type:device_code",
@@ -1322,7 +2798,7 @@ mod tests {
 
         let browser_session = repo
             .browser_session()
-            .add(&mut state.rng(), &state.clock, &user, None)
+            .add(&mut state.rng(), &state.clock, &user, None, None, None)
             .await
             .unwrap();
 
@@ -1449,6 +2925,562 @@ mod tests {
         assert_eq!(error, ClientErrorCode::AccessDenied);
     }
 
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_token_exchange(pool: PgPool) {
+        const ACCESS_TOKEN: &str = "urn:ietf:params:oauth:token-type:access_token";
+
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let mut repo = state.repository().await.unwrap();
+
+        // Provision two clients allowed to exchange tokens, and one which isn't
+        let mut clients = Vec::new();
+        for allow_token_exchange in [true, true, false] {
+            let client_id =
+                Ulid::from_datetime_with_source(state.clock.now().into(), &mut state.rng());
+            let client = repo
+                .oauth2_client()
+                .upsert_static(UpsertStaticClientParams {
+                    client_id,
+                    client_auth_method: mas_iana::oauth::OAuthClientAuthenticationMethod::None,
+                    encrypted_client_secret: None,
+                    jwks: None,
+                    jwks_uri: None,
+                    redirect_uris: vec![],
+                    redirect_uri_matching: mas_data_model::RedirectUriMatching::Exact,
+                    response_types: vec![
+                        mas_iana::oauth::OAuthAuthorizationEndpointResponseType::Code,
+                    ],
+                    allow_token_exchange,
+                    allow_client_credentials: false,
+                    client_credentials_scope: None,
+                    allowed_resources: Vec::new(),
+                    require_signed_request_object: false,
+                    require_pkce: None,
+                    access_token_ttl: None,
+                    refresh_token_ttl: None,
+                    access_token_format: mas_data_model::AccessTokenFormat::Opaque,
+                    introspection_endpoint_auth_method: None,
+                    post_logout_redirect_uris: Vec::new(),
+                    backchannel_logout_uri: None,
+                    backchannel_logout_session_required: false,
+                    trusted: false,
+                })
+                .await
+                .unwrap();
+            clients.push(client);
+        }
+        let [client, other_client, disallowed_client] = &clients[..] else {
+            unreachable!()
+        };
+
+        // Start a session for alice on the first client
+        let user = repo
+            .user()
+            .add(&mut state.rng(), &state.clock, "alice".to_owned())
+            .await
+            .unwrap();
+
+        let browser_session = repo
+            .browser_session()
+            .add(&mut state.rng(), &state.clock, &user, None, None, None)
+            .await
+            .unwrap();
+
+        let session = repo
+            .oauth2_session()
+            .add_from_browser_session(
+                &mut state.rng(),
+                &state.clock,
+                client,
+                &browser_session,
+                "openid urn:matrix:org.matrix.msc2967.client:api:*"
+                    .parse()
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        let subject_token = TokenType::AccessToken.generate(&mut state.rng());
+        repo.oauth2_access_token()
+            .add(
+                &mut state.rng(),
+                &state.clock,
+                &session,
+                subject_token.clone(),
+                None,
+            )
+            .await
+            .unwrap();
+
+        repo.save().await.unwrap();
+
+        let exchange = |client: &Client, subject_token_type: &str, scope: Option<&str>| {
+            let mut form = serde_json::json!({
+                "grant_type": "urn:ietf:params:oauth:grant-type:token-exchange",
+                "client_id": client.client_id,
+                "subject_token": subject_token,
+                "subject_token_type": subject_token_type,
+            });
+            if let Some(scope) = scope {
+                form["scope"] = scope.into();
+            }
+            Request::post(mas_router::OAuth2TokenEndpoint::PATH).form(form)
+        };
+
+        // A client which doesn't own the subject token can't exchange it
+        let response = state
+            .request(exchange(other_client, ACCESS_TOKEN, None))
+            .await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        let ClientError { error, .. } = response.json();
+        assert_eq!(error, ClientErrorCode::InvalidGrant);
+
+        // A client which isn't allowed to exchange tokens is rejected
+        let response = state
+            .request(exchange(disallowed_client, ACCESS_TOKEN, None))
+            .await;
+        response.assert_status(StatusCode::UNAUTHORIZED);
+        let ClientError { error, .. } = response.json();
+        assert_eq!(error, ClientErrorCode::UnauthorizedClient);
+
+        // Only access tokens can be exchanged
+        let response = state
+            .request(exchange(
+                client,
+                "urn:ietf:params:oauth:token-type:refresh_token",
+                None,
+            ))
+            .await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        let ClientError { error, .. } = response.json();
+        assert_eq!(error, ClientErrorCode::UnsupportedTokenType);
+
+        // The scope can't be extended
+        let response = state
+            .request(exchange(client, ACCESS_TOKEN, Some("openid urn:mas:admin")))
+            .await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        let ClientError { error, .. } = response.json();
+        assert_eq!(error, ClientErrorCode::InvalidScope);
+
+        // But it can be down-scoped
+        let response = state
+            .request(exchange(client, ACCESS_TOKEN, Some("openid")))
+            .await;
+        response.assert_status(StatusCode::OK);
+        let response: AccessTokenResponse = response.json();
+        assert!(response.refresh_token.is_none());
+        assert!(response.expires_in.is_some());
+        assert_eq!(response.scope, Some(Scope::from_iter([OPENID])));
+        assert_eq!(
+            response.issued_token_type,
+            Some(TokenTypeIdentifier::AccessToken)
+        );
+
+        // The new token is bound to the same user, in a new session
+        let mut repo = state.repository().await.unwrap();
+        let access_token = repo
+            .oauth2_access_token()
+            .find_by_token(&response.access_token)
+            .await
+            .unwrap()
+            .unwrap();
+        let new_session = repo
+            .oauth2_session()
+            .lookup(access_token.session_id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_ne!(new_session.id, session.id);
+        assert_eq!(new_session.user_id, Some(user.id));
+        assert_eq!(new_session.user_session_id, Some(browser_session.id));
+        assert_eq!(new_session.scope, Scope::from_iter([OPENID]));
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_dpop_bound_token(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let mut rng = state.rng();
+
+        // Provision a client
+        let request =
+            Request::post(mas_router::OAuth2RegistrationEndpoint::PATH).json(serde_json::json!({
+                "client_uri": "https://example.com/",
+                "contacts": ["contact@example.com"],
+                "token_endpoint_auth_method": "client_secret_post",
+                "grant_types": ["client_credentials"],
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::CREATED);
+
+        let response: ClientRegistrationResponse = response.json();
+        let client_id = response.client_id;
+        let client_secret = response.client_secret.expect("to have a client secret");
+
+        let key = PrivateKey::generate_ec_p256(&mut rng);
+        let jkt = JsonWebKeyPublicParameters::from(&key).thumbprint_sha256();
+        let signer = key
+            .signing_key_for_alg(&JsonWebSignatureAlg::Es256)
+            .unwrap();
+        let mut sign_proof = |claims: serde_json::Value| {
+            let header = JsonWebSignatureHeader::new(JsonWebSignatureAlg::Es256)
+                .with_typ("dpop+jwt".to_owned())
+                .with_jwk(JsonWebKey::new(JsonWebKeyPublicParameters::from(&key)));
+            Jwt::sign_with_rng(&mut rng, header, claims, &signer)
+                .unwrap()
+                .into_string()
+        };
+
+        let token_request = |proof: &str| {
+            Request::post(mas_router::OAuth2TokenEndpoint::PATH)
+                .header("DPoP", proof)
+                .form(serde_json::json!({
+                    "grant_type": "client_credentials",
+                    "client_id": client_id,
+                    "client_secret": client_secret,
+                    "scope": "urn:mas:graphql:*",
+                }))
+        };
+
+        // A proof without a nonce is rejected, and a nonce is given to the client
+        let proof = sign_proof(serde_json::json!({
+            "jti": "first",
+            "htm": "POST",
+            "htu": state.url_builder.oauth_token_endpoint(),
+            "iat": state.clock.now().timestamp(),
+        }));
+        let response = state.request(token_request(&proof)).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        let nonce = response
+            .headers()
+            .get("DPoP-Nonce")
+            .expect("a DPoP nonce")
+            .to_str()
+            .unwrap()
+            .to_owned();
+        let error: ClientError = response.json();
+        assert_eq!(error.error, ClientErrorCode::UseDpopNonce);
+
+        // A proof for another endpoint is rejected
+        let proof = sign_proof(serde_json::json!({
+            "jti": "second",
+            "htm": "POST",
+            "htu": state.url_builder.oauth_introspection_endpoint(),
+            "iat": state.clock.now().timestamp(),
+            "nonce": nonce,
+        }));
+        let response = state.request(token_request(&proof)).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        let error: ClientError = response.json();
+        assert_eq!(error.error, ClientErrorCode::InvalidDpopProof);
+
+        // With the nonce, the token is issued and bound to the key
+        let proof = sign_proof(serde_json::json!({
+            "jti": "third",
+            "htm": "POST",
+            "htu": state.url_builder.oauth_token_endpoint(),
+            "iat": state.clock.now().timestamp(),
+            "nonce": nonce,
+        }));
+        let response = state.request(token_request(&proof)).await;
+        response.assert_status(StatusCode::OK);
+        assert!(response.headers().contains_key("DPoP-Nonce"));
+        let response: AccessTokenResponse = response.json();
+        assert_eq!(response.token_type, OAuthAccessTokenType::DPoP);
+        let access_token = response.access_token;
+
+        let mut repo = state.repository().await.unwrap();
+        let token = repo
+            .oauth2_access_token()
+            .find_by_token(&access_token)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(token.dpop_jkt.as_deref(), Some(jkt.as_str()));
+        repo.cancel().await.unwrap();
+
+        let query = serde_json::json!({ "query": "{ viewer { __typename } }" });
+
+        // The token can't be used as a bearer token
+        let request = Request::post(mas_router::GraphQL::PATH)
+            .bearer(&access_token)
+            .json(&query);
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::UNAUTHORIZED);
+
+        // It can be used with a proof of possession of the key
+        let ath = Base64UrlUnpadded::encode_string(&Sha256::digest(&access_token));
+        let proof = sign_proof(serde_json::json!({
+            "jti": "fourth",
+            "htm": "POST",
+            "htu": state.url_builder.graphql_endpoint(),
+            "iat": state.clock.now().timestamp(),
+            "ath": ath,
+        }));
+        let request = Request::post(mas_router::GraphQL::PATH)
+            .header("Authorization", format!("DPoP {access_token}"))
+            .header("DPoP", &proof)
+            .json(&query);
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+
+        // The same proof can't be used twice
+        let request = Request::post(mas_router::GraphQL::PATH)
+            .header("Authorization", format!("DPoP {access_token}"))
+            .header("DPoP", &proof)
+            .json(&query);
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::UNAUTHORIZED);
+
+        // The nonce can't be used twice either
+        let proof = sign_proof(serde_json::json!({
+            "jti": "fifth",
+            "htm": "POST",
+            "htu": state.url_builder.oauth_token_endpoint(),
+            "iat": state.clock.now().timestamp(),
+            "nonce": nonce,
+        }));
+        let response = state.request(token_request(&proof)).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        let error: ClientError = response.json();
+        assert_eq!(error.error, ClientErrorCode::UseDpopNonce);
+
+        // But not with a proof signed by another key
+        let other_key = PrivateKey::generate_ec_p256(&mut state.rng());
+        let other_signer = other_key
+            .signing_key_for_alg(&JsonWebSignatureAlg::Es256)
+            .unwrap();
+        let header = JsonWebSignatureHeader::new(JsonWebSignatureAlg::Es256)
+            .with_typ("dpop+jwt".to_owned())
+            .with_jwk(JsonWebKey::new(JsonWebKeyPublicParameters::from(
+                &other_key,
+            )));
+        let proof = Jwt::sign_with_rng(
+            &mut state.rng(),
+            header,
+            serde_json::json!({
+                "jti": "sixth",
+                "htm": "POST",
+                "htu": state.url_builder.graphql_endpoint(),
+                "iat": state.clock.now().timestamp(),
+                "ath": ath,
+            }),
+            &other_signer,
+        )
+        .unwrap()
+        .into_string();
+        let request = Request::post(mas_router::GraphQL::PATH)
+            .header("Authorization", format!("DPoP {access_token}"))
+            .header("DPoP", &proof)
+            .json(&query);
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::UNAUTHORIZED);
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_dpop_bound_refresh_token(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let mut rng = state.rng();
+
+        // Provision a client
+        let request =
+            Request::post(mas_router::OAuth2RegistrationEndpoint::PATH).json(serde_json::json!({
+                "client_uri": "https://example.com/",
+                "redirect_uris": ["https://example.com/callback"],
+                "contacts": ["contact@example.com"],
+                "token_endpoint_auth_method": "none",
+                "response_types": ["code"],
+                "grant_types": ["authorization_code", "refresh_token"],
+            }));
+
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::CREATED);
+
+        let ClientRegistrationResponse { client_id, .. } = response.json();
+
+        let key = PrivateKey::generate_ec_p256(&mut rng);
+        let jkt = JsonWebKeyPublicParameters::from(&key).thumbprint_sha256();
+
+        // Provision a user and a session bound to the DPoP key
+        let mut repo = state.repository().await.unwrap();
+
+        let user = repo
+            .user()
+            .add(&mut state.rng(), &state.clock, "alice".to_owned())
+            .await
+            .unwrap();
+
+        let browser_session = repo
+            .browser_session()
+            .add(&mut state.rng(), &state.clock, &user, None, None)
+            .await
+            .unwrap();
+
+        let client = repo
+            .oauth2_client()
+            .find_by_client_id(&client_id)
+            .await
+            .unwrap()
+            .unwrap();
+
+        let session = repo
+            .oauth2_session()
+            .add_from_browser_session(
+                &mut state.rng(),
+                &state.clock,
+                &client,
+                &browser_session,
+                Scope::from_iter([OPENID]),
+            )
+            .await
+            .unwrap();
+
+        let session = repo
+            .oauth2_session()
+            .bind_dpop_key(session, jkt.clone())
+            .await
+            .unwrap();
+
+        let (_, RefreshToken { refresh_token, .. }) = generate_token_pair(
+            &mut state.rng(),
+            &state.clock,
+            &mut repo,
+            &session,
+            Duration::microseconds(5 * 60 * 1000 * 1000),
+        )
+        .await
+        .unwrap();
+
+        repo.save().await.unwrap();
+
+        let mut sign_proof = |key: &PrivateKey, claims: serde_json::Value| {
+            let signer = key
+                .signing_key_for_alg(&JsonWebSignatureAlg::Es256)
+                .unwrap();
+            let header = JsonWebSignatureHeader::new(JsonWebSignatureAlg::Es256)
+                .with_typ("dpop+jwt".to_owned())
+                .with_jwk(JsonWebKey::new(JsonWebKeyPublicParameters::from(key)));
+            Jwt::sign_with_rng(&mut rng, header, claims, &signer)
+                .unwrap()
+                .into_string()
+        };
+
+        let refresh = |refresh_token: &str, proof: Option<String>| {
+            let mut request = Request::post(mas_router::OAuth2TokenEndpoint::PATH);
+            if let Some(proof) = proof {
+                request = request.header("DPoP", proof);
+            }
+            request.form(serde_json::json!({
+                "grant_type": "refresh_token",
+                "refresh_token": refresh_token,
+                "client_id": client_id,
+            }))
+        };
+
+        // The refresh token can't be used without a proof
+        let response = state.request(refresh(&refresh_token, None)).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        let error: ClientError = response.json();
+        assert_eq!(error.error, ClientErrorCode::InvalidDpopProof);
+
+        // Get a nonce
+        let proof = sign_proof(
+            &key,
+            serde_json::json!({
+                "jti": "first",
+                "htm": "POST",
+                "htu": state.url_builder.oauth_token_endpoint(),
+                "iat": state.clock.now().timestamp(),
+            }),
+        );
+        let response = state.request(refresh(&refresh_token, Some(proof))).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        let nonce = response.headers()["DPoP-Nonce"]
+            .to_str()
+            .unwrap()
+            .to_owned();
+
+        // With a proof signed by the key of the session, a new token pair is issued
+        // and the access token is bound to the same key
+        let proof = sign_proof(
+            &key,
+            serde_json::json!({
+                "jti": "second",
+                "htm": "POST",
+                "htu": state.url_builder.oauth_token_endpoint(),
+                "iat": state.clock.now().timestamp(),
+                "nonce": nonce,
+            }),
+        );
+        let response = state.request(refresh(&refresh_token, Some(proof))).await;
+        response.assert_status(StatusCode::OK);
+        let nonce = response.headers()["DPoP-Nonce"]
+            .to_str()
+            .unwrap()
+            .to_owned();
+        let response: AccessTokenResponse = response.json();
+        assert_eq!(response.token_type, OAuthAccessTokenType::DPoP);
+        let access_token = response.access_token;
+        let refresh_token = response.refresh_token.unwrap();
+
+        let mut repo = state.repository().await.unwrap();
+        let token = repo
+            .oauth2_access_token()
+            .find_by_token(&access_token)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(token.dpop_jkt.as_deref(), Some(jkt.as_str()));
+        repo.cancel().await.unwrap();
+
+        // The access token can't be used as a bearer token on the userinfo endpoint
+        let request = Request::get(mas_router::OidcUserinfo::PATH)
+            .bearer(&access_token)
+            .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::UNAUTHORIZED);
+
+        // It can be used with a proof of possession of the key
+        let ath = Base64UrlUnpadded::encode_string(&Sha256::digest(&access_token));
+        let proof = sign_proof(
+            &key,
+            serde_json::json!({
+                "jti": "third",
+                "htm": "GET",
+                "htu": state.url_builder.oidc_userinfo_endpoint(),
+                "iat": state.clock.now().timestamp(),
+                "ath": ath,
+            }),
+        );
+        let request = Request::get(mas_router::OidcUserinfo::PATH)
+            .header("Authorization", format!("DPoP {access_token}"))
+            .header("DPoP", proof)
+            .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+
+        // The new refresh token can't be used with a proof signed by another key
+        let other_key = PrivateKey::generate_ec_p256(&mut state.rng());
+        let proof = sign_proof(
+            &other_key,
+            serde_json::json!({
+                "jti": "fourth",
+                "htm": "POST",
+                "htu": state.url_builder.oauth_token_endpoint(),
+                "iat": state.clock.now().timestamp(),
+                "nonce": nonce,
+            }),
+        );
+        let response = state.request(refresh(&refresh_token, Some(proof))).await;
+        response.assert_status(StatusCode::BAD_REQUEST);
+        let error: ClientError = response.json();
+        assert_eq!(error.error, ClientErrorCode::InvalidDpopProof);
+    }
+
     #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
     async fn test_unsupported_grant(pool: PgPool) {
         init_tracing();
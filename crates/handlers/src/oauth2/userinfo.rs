@@ -12,17 +12,23 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::sync::Arc;
+
 use axum::{
     extract::State,
     response::{IntoResponse, Response},
     Json,
 };
-use hyper::StatusCode;
+use chrono::{DateTime, Utc};
+use hyper::{header::WWW_AUTHENTICATE, HeaderMap, HeaderValue, StatusCode};
 use mas_axum_utils::{
+    dpop::DpopNonceStore,
     jwt::JwtResponse,
     sentry::SentryEventID,
     user_authorization::{AuthorizationVerificationError, UserAuthorization},
 };
+use mas_data_model::{User, UserEmail};
+use mas_iana::jose::JsonWebSignatureAlg;
 use mas_jose::{
     constraints::Constrainable,
     jwt::{JsonWebSignatureHeader, Jwt},
@@ -39,13 +45,44 @@ use thiserror::Error;
 
 use crate::{impl_from_error_for_route, BoundActivityTracker};
 
+use super::accepts_media_type;
+
+/// The claims returned by the userinfo endpoint
 #[skip_serializing_none]
 #[derive(Serialize)]
-struct UserInfo {
+struct UserInfoClaims {
     sub: String,
     username: String,
     email: Option<String>,
     email_verified: Option<bool>,
+    auth_time: Option<i64>,
+}
+
+impl UserInfoClaims {
+    /// Start building the claims of a [`User`]
+    fn new(user: &User) -> Self {
+        Self {
+            sub: user.sub.clone(),
+            username: user.username.clone(),
+            email: None,
+            email_verified: None,
+            auth_time: None,
+        }
+    }
+
+    /// Add the `email` and `email_verified` claims out of the primary email of
+    /// the user
+    fn with_email(mut self, user_email: UserEmail) -> Self {
+        self.email_verified = Some(user_email.confirmed_at.is_some());
+        self.email = Some(user_email.email);
+        self
+    }
+
+    /// Add the `auth_time` claim
+    fn with_auth_time(mut self, auth_time: DateTime<Utc>) -> Self {
+        self.auth_time = Some(auth_time.timestamp());
+        self
+    }
 }
 
 #[derive(Serialize)]
@@ -53,9 +90,12 @@ struct SignedUserInfo {
     iss: String,
     aud: String,
     #[serde(flatten)]
-    user_info: UserInfo,
+    user_info: UserInfoClaims,
 }
 
+/// The media type used to ask for a signed userinfo response
+const APPLICATION_JWT: &str = "application/jwt";
+
 #[derive(Debug, Error)]
 pub enum RouteError {
     #[error(transparent)]
@@ -87,12 +127,34 @@ impl IntoResponse for RouteError {
     fn into_response(self) -> axum::response::Response {
         let event_id = sentry::capture_error(&self);
         let response = match self {
-            Self::Internal(_) | Self::InvalidSigningKey | Self::NoSuchClient | Self::NoSuchUser => {
+            Self::Internal(_)
+            | Self::AuthorizationVerificationError(AuthorizationVerificationError::Internal(_))
+            | Self::InvalidSigningKey
+            | Self::NoSuchClient
+            | Self::NoSuchUser => {
                 (StatusCode::INTERNAL_SERVER_ERROR, self.to_string()).into_response()
             }
-            Self::AuthorizationVerificationError(_) | Self::Unauthorized => {
-                StatusCode::UNAUTHORIZED.into_response()
-            }
+            // The token is missing: don't send any error code, as per RFC 6750
+            Self::AuthorizationVerificationError(
+                AuthorizationVerificationError::MissingToken
+                | AuthorizationVerificationError::MissingForm,
+            ) => (
+                StatusCode::UNAUTHORIZED,
+                [(WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"))],
+            )
+                .into_response(),
+            Self::AuthorizationVerificationError(AuthorizationVerificationError::InvalidToken)
+            | Self::Unauthorized => (
+                StatusCode::UNAUTHORIZED,
+                [(
+                    WWW_AUTHENTICATE,
+                    HeaderValue::from_static(r#"Bearer error="invalid_token""#),
+                )],
+            )
+                .into_response(),
+            Self::AuthorizationVerificationError(
+                e @ AuthorizationVerificationError::InvalidDpopProof(_),
+            ) => e.into_response(),
         };
 
         (SentryEventID::from(event_id), response).into_response()
@@ -107,12 +169,23 @@ pub async fn get(
     activity_tracker: BoundActivityTracker,
     mut repo: BoxRepository,
     State(key_store): State<Keystore>,
+    State(dpop_nonce_store): State<Arc<dyn DpopNonceStore>>,
+    headers: HeaderMap,
     user_authorization: UserAuthorization,
 ) -> Result<Response, RouteError> {
-    let session = user_authorization.protected(&mut repo, &clock).await?;
+    let (session, scope) = user_authorization
+        .protected(
+            &mut repo,
+            &clock,
+            &key_store,
+            &*dpop_nonce_store,
+            &url_builder.oidc_issuer(),
+            &url_builder.oidc_userinfo_endpoint(),
+        )
+        .await?;
 
     // This endpoint requires the `openid` scope.
-    if !session.scope.contains("openid") {
+    if !scope.contains("openid") {
         return Err(RouteError::Unauthorized);
     }
 
@@ -131,18 +204,19 @@ pub async fn get(
         .await?
         .ok_or(RouteError::NoSuchUser)?;
 
-    let user_email = if session.scope.contains(&scope::EMAIL) {
+    let user_email = if scope.contains(&scope::EMAIL) {
         repo.user_email().get_primary(&user).await?
     } else {
         None
     };
 
-    let user_info = UserInfo {
-        sub: user.sub.clone(),
-        username: user.username.clone(),
-        email_verified: user_email.as_ref().map(|u| u.confirmed_at.is_some()),
-        email: user_email.map(|u| u.email),
-    };
+    let mut user_info = UserInfoClaims::new(&user);
+    if let Some(user_email) = user_email {
+        user_info = user_info.with_email(user_email);
+    }
+    if let Some(auth_time) = session.auth_time {
+        user_info = user_info.with_auth_time(auth_time);
+    }
 
     let client = repo
         .oauth2_client()
@@ -150,7 +224,22 @@ pub async fn get(
         .await?
         .ok_or(RouteError::NoSuchClient)?;
 
-    if let Some(alg) = client.userinfo_signed_response_alg {
+    // The response is signed if the client registered a signing algorithm for
+    // it, or if it asked for a JWT. In the latter case, use the same algorithm
+    // as for the ID tokens
+    let alg = if let Some(alg) = client.userinfo_signed_response_alg {
+        Some(alg)
+    } else if accepts_media_type(&headers, APPLICATION_JWT) {
+        Some(
+            client
+                .id_token_signed_response_alg
+                .unwrap_or(JsonWebSignatureAlg::Rs256),
+        )
+    } else {
+        None
+    };
+
+    if let Some(alg) = alg {
         let key = key_store
             .signing_key_for_algorithm(&alg)
             .ok_or(RouteError::InvalidSigningKey)?;
@@ -171,3 +260,295 @@ pub async fn get(
         Ok(Json(user_info).into_response())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use hyper::{
+        header::{ACCEPT, CONTENT_TYPE, WWW_AUTHENTICATE},
+        Request, StatusCode,
+    };
+    use mas_data_model::TokenType;
+    use mas_jose::jwt::Jwt;
+    use mas_router::{OAuth2RegistrationEndpoint, OidcUserinfo, SimpleRoute};
+    use oauth2_types::{
+        registration::ClientRegistrationResponse,
+        scope::{Scope, EMAIL, OPENID},
+    };
+    use serde_json::Value;
+    use sqlx::PgPool;
+
+    use crate::test_utils::{init_tracing, RequestBuilderExt, ResponseExt, TestState};
+
+    /// Register a client, provision a user named `alice` with a verified
+    /// primary email, and issue an access token with the given scope.
+    ///
+    /// Returns the client ID and the access token.
+    async fn provision_access_token(state: &TestState, scope: Scope) -> (String, String) {
+        let request = Request::post(OAuth2RegistrationEndpoint::PATH).json(serde_json::json!({
+            "client_uri": "https://example.com/",
+            "redirect_uris": ["https://example.com/callback"],
+            "contacts": ["contact@example.com"],
+            "token_endpoint_auth_method": "none",
+            "response_types": ["code"],
+            "grant_types": ["authorization_code"],
+        }));
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::CREATED);
+        let ClientRegistrationResponse { client_id, .. } = response.json();
+
+        let mut repo = state.repository().await.unwrap();
+        let mut rng = state.rng();
+
+        let client = repo
+            .oauth2_client()
+            .find_by_client_id(&client_id)
+            .await
+            .unwrap()
+            .unwrap();
+
+        let user = repo
+            .user()
+            .add(&mut rng, &state.clock, "alice".to_owned())
+            .await
+            .unwrap();
+
+        let user_email = repo
+            .user_email()
+            .add(
+                &mut rng,
+                &state.clock,
+                &user,
+                "alice@example.com".to_owned(),
+            )
+            .await
+            .unwrap();
+        let user_email = repo
+            .user_email()
+            .mark_as_verified(&state.clock, user_email)
+            .await
+            .unwrap();
+        repo.user_email().set_as_primary(&user_email).await.unwrap();
+
+        let browser_session = repo
+            .browser_session()
+            .add(&mut rng, &state.clock, &user, None, None, None)
+            .await
+            .unwrap();
+
+        let session = repo
+            .oauth2_session()
+            .add_from_browser_session(&mut rng, &state.clock, &client, &browser_session, scope)
+            .await
+            .unwrap();
+
+        let access_token = TokenType::AccessToken.generate(&mut rng);
+        repo.oauth2_access_token()
+            .add(&mut rng, &state.clock, &session, access_token.clone(), None)
+            .await
+            .unwrap();
+
+        repo.save().await.unwrap();
+
+        (client_id, access_token)
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_userinfo(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let (_client_id, access_token) =
+            provision_access_token(&state, Scope::from_iter([OPENID, EMAIL])).await;
+
+        let request = Request::get(OidcUserinfo::PATH)
+            .bearer(&access_token)
+            .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let claims: Value = response.json();
+        assert!(claims["sub"].is_string());
+        assert_eq!(claims["username"], "alice");
+        assert_eq!(claims["email"], "alice@example.com");
+        assert_eq!(claims["email_verified"], true);
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_userinfo_without_email_scope(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let (_client_id, access_token) =
+            provision_access_token(&state, Scope::from_iter([OPENID])).await;
+
+        // The email claims are only returned with the email scope
+        let request = Request::get(OidcUserinfo::PATH)
+            .bearer(&access_token)
+            .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let claims: Value = response.json();
+        assert!(claims["sub"].is_string());
+        assert!(claims.get("email").is_none());
+        assert!(claims.get("email_verified").is_none());
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_userinfo_narrowed_scope(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let (_client_id, access_token) =
+            provision_access_token(&state, Scope::from_iter([OPENID, EMAIL])).await;
+
+        // Narrow down the scope of the access token, like a refresh would
+        let mut repo = state.repository().await.unwrap();
+        let token = repo
+            .oauth2_access_token()
+            .find_by_token(&access_token)
+            .await
+            .unwrap()
+            .unwrap();
+        let token = repo
+            .oauth2_access_token()
+            .set_scope(token, Scope::from_iter([OPENID]))
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        // The email claims are not returned, even though the session has the
+        // email scope
+        let request = Request::get(OidcUserinfo::PATH)
+            .bearer(&access_token)
+            .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        let claims: Value = response.json();
+        assert!(claims["sub"].is_string());
+        assert!(claims.get("email").is_none());
+
+        // Without the openid scope, the token can't be used at all
+        let mut repo = state.repository().await.unwrap();
+        repo.oauth2_access_token()
+            .set_scope(token, Scope::from_iter([EMAIL]))
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        let request = Request::get(OidcUserinfo::PATH)
+            .bearer(&access_token)
+            .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::UNAUTHORIZED);
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_userinfo_audience_restricted(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let (_client_id, access_token) =
+            provision_access_token(&state, Scope::from_iter([OPENID])).await;
+
+        // Restrict the session to another resource
+        let mut repo = state.repository().await.unwrap();
+        let token = repo
+            .oauth2_access_token()
+            .find_by_token(&access_token)
+            .await
+            .unwrap()
+            .unwrap();
+        let session = repo
+            .oauth2_session()
+            .lookup(token.session_id)
+            .await
+            .unwrap()
+            .unwrap();
+        let session = repo
+            .oauth2_session()
+            .record_resource(session, vec!["https://a.example.com/".parse().unwrap()])
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        // The token can't be used on the userinfo endpoint
+        let request = Request::get(OidcUserinfo::PATH)
+            .bearer(&access_token)
+            .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::UNAUTHORIZED);
+        response.assert_header_value(WWW_AUTHENTICATE, r#"Bearer error="invalid_token""#);
+
+        // Unless the userinfo endpoint is one of the resources
+        let mut repo = state.repository().await.unwrap();
+        repo.oauth2_session()
+            .record_resource(
+                session,
+                vec![
+                    "https://a.example.com/".parse().unwrap(),
+                    state.url_builder.oidc_userinfo_endpoint(),
+                ],
+            )
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        let request = Request::get(OidcUserinfo::PATH)
+            .bearer(&access_token)
+            .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_userinfo_unauthorized(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let (_client_id, access_token) =
+            provision_access_token(&state, Scope::from_iter([EMAIL])).await;
+
+        // Without a token
+        let request = Request::get(OidcUserinfo::PATH).empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::UNAUTHORIZED);
+        response.assert_header_value(WWW_AUTHENTICATE, "Bearer");
+
+        // With an unknown token
+        let request = Request::get(OidcUserinfo::PATH)
+            .bearer("mat_invalidtoken")
+            .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::UNAUTHORIZED);
+        response.assert_header_value(WWW_AUTHENTICATE, r#"Bearer error="invalid_token""#);
+
+        // With a token which lacks the openid scope
+        let request = Request::get(OidcUserinfo::PATH)
+            .bearer(&access_token)
+            .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::UNAUTHORIZED);
+        response.assert_header_value(WWW_AUTHENTICATE, r#"Bearer error="invalid_token""#);
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_userinfo_signed(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let (client_id, access_token) =
+            provision_access_token(&state, Scope::from_iter([OPENID, EMAIL])).await;
+
+        let request = Request::get(OidcUserinfo::PATH)
+            .bearer(&access_token)
+            .header(ACCEPT, "application/jwt")
+            .empty();
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        response.assert_header_value(CONTENT_TYPE, "application/jwt");
+
+        let jwt: Jwt<'_, HashMap<String, Value>> = Jwt::try_from(response.body().as_str()).unwrap();
+        jwt.verify_with_jwks(&state.key_store.public_jwks())
+            .unwrap();
+        let claims = jwt.payload();
+        assert_eq!(claims["iss"], state.url_builder.oidc_issuer().as_str());
+        assert_eq!(claims["aud"], client_id.as_str());
+        assert_eq!(claims["username"], "alice");
+        assert_eq!(claims["email"], "alice@example.com");
+    }
+}
@@ -16,9 +16,9 @@ use std::{collections::HashMap, sync::Arc};
 
 use anyhow::Context;
 use argon2::{password_hash::SaltString, Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
-use futures_util::future::OptionFuture;
 use pbkdf2::Pbkdf2;
 use rand::{CryptoRng, Rng, RngCore, SeedableRng};
+use scrypt::Scrypt;
 use thiserror::Error;
 use zeroize::Zeroizing;
 
@@ -174,7 +174,8 @@ impl PasswordManager {
     }
 
     /// Verify a password hash for the given hashing scheme, and upgrade it on
-    /// the fly, if it was not hashed with the default scheme
+    /// the fly, if it was not hashed with the default scheme or with outdated
+    /// parameters
     ///
     /// # Errors
     ///
@@ -190,20 +191,22 @@ impl PasswordManager {
     ) -> Result<Option<(SchemeVersion, String)>, anyhow::Error> {
         let inner = self.get_inner()?;
 
-        // If the current scheme isn't the default one, we also hash with the default
-        // one so that
-        let new_hash_fut: OptionFuture<_> = (scheme != inner.current_version)
-            .then(|| self.hash(rng, password.clone()))
-            .into();
+        // The password needs to be re-hashed if it wasn't hashed with the default
+        // scheme, or if the default scheme parameters changed since then
+        let needs_upgrade =
+            scheme != inner.current_version || inner.current_hasher.needs_rehash(&hashed_password);
 
-        let verify_fut = self.verify(scheme, password, hashed_password);
+        self.verify(scheme, password.clone(), hashed_password)
+            .await?;
 
-        let (new_hash_res, verify_res) = tokio::join!(new_hash_fut, verify_fut);
-        verify_res?;
+        // Only hash the password again once it was verified
+        if !needs_upgrade {
+            return Ok(None);
+        }
 
-        let new_hash = new_hash_res.transpose()?;
+        let new_hash = self.hash(rng, password).await?;
 
-        Ok(new_hash)
+        Ok(Some(new_hash))
     }
 }
 
@@ -224,7 +227,42 @@ impl Hasher {
     /// Creates a new hashing scheme based on the argon2id algorithm
     #[must_use]
     pub const fn argon2id(pepper: Option<Vec<u8>>) -> Self {
-        let algorithm = Algorithm::Argon2id;
+        Self::argon2id_with_params(None, None, None, pepper)
+    }
+
+    /// Creates a new hashing scheme based on the argon2id algorithm, with the
+    /// given memory cost (in KiB), time cost and parallelism. Unset
+    /// parameters use the argon2 defaults
+    #[must_use]
+    pub const fn argon2id_with_params(
+        memory_cost: Option<u32>,
+        time_cost: Option<u32>,
+        parallelism: Option<u32>,
+        pepper: Option<Vec<u8>>,
+    ) -> Self {
+        let algorithm = Algorithm::Argon2id {
+            memory_cost,
+            time_cost,
+            parallelism,
+        };
+        Self { algorithm, pepper }
+    }
+
+    /// Creates a new hashing scheme based on the argon2i algorithm, with the
+    /// given memory cost (in KiB), time cost and parallelism. Unset
+    /// parameters use the argon2 defaults
+    #[must_use]
+    pub const fn argon2i_with_params(
+        memory_cost: Option<u32>,
+        time_cost: Option<u32>,
+        parallelism: Option<u32>,
+        pepper: Option<Vec<u8>>,
+    ) -> Self {
+        let algorithm = Algorithm::Argon2i {
+            memory_cost,
+            time_cost,
+            parallelism,
+        };
         Self { algorithm, pepper }
     }
 
@@ -235,6 +273,13 @@ impl Hasher {
         Self { algorithm, pepper }
     }
 
+    /// Creates a new hashing scheme based on the scrypt algorithm
+    #[must_use]
+    pub const fn scrypt(pepper: Option<Vec<u8>>) -> Self {
+        let algorithm = Algorithm::Scrypt;
+        Self { algorithm, pepper }
+    }
+
     fn hash_blocking<R: CryptoRng + RngCore>(
         &self,
         rng: R,
@@ -248,16 +293,75 @@ impl Hasher {
         self.algorithm
             .verify_blocking(hashed_password, password, self.pepper.as_deref())
     }
+
+    fn needs_rehash(&self, hashed_password: &str) -> bool {
+        self.algorithm.needs_rehash(hashed_password)
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
 enum Algorithm {
-    Bcrypt { cost: Option<u32> },
-    Argon2id,
+    Bcrypt {
+        cost: Option<u32>,
+    },
+    Argon2id {
+        memory_cost: Option<u32>,
+        time_cost: Option<u32>,
+        parallelism: Option<u32>,
+    },
+    Argon2i {
+        memory_cost: Option<u32>,
+        time_cost: Option<u32>,
+        parallelism: Option<u32>,
+    },
     Pbkdf2,
+    Scrypt,
 }
 
 impl Algorithm {
+    /// The argon2 variant used by this algorithm, if it is one of the argon2
+    /// algorithms
+    const fn argon2_algorithm(self) -> argon2::Algorithm {
+        match self {
+            Self::Argon2i { .. } => argon2::Algorithm::Argon2i,
+            _ => argon2::Algorithm::Argon2id,
+        }
+    }
+
+    /// Build the argon2 parameters, falling back to the defaults for the
+    /// unset ones
+    fn argon2_params(
+        memory_cost: Option<u32>,
+        time_cost: Option<u32>,
+        parallelism: Option<u32>,
+    ) -> Result<argon2::Params, argon2::Error> {
+        argon2::Params::new(
+            memory_cost.unwrap_or(argon2::Params::DEFAULT_M_COST),
+            time_cost.unwrap_or(argon2::Params::DEFAULT_T_COST),
+            parallelism.unwrap_or(argon2::Params::DEFAULT_P_COST),
+            None,
+        )
+    }
+
+    /// Build the argon2 hasher for the given variant and parameters, with an
+    /// optional pepper
+    fn argon2(
+        algorithm: argon2::Algorithm,
+        memory_cost: Option<u32>,
+        time_cost: Option<u32>,
+        parallelism: Option<u32>,
+        pepper: Option<&[u8]>,
+    ) -> Result<Argon2<'_>, argon2::Error> {
+        let version = argon2::Version::default();
+        let params = Self::argon2_params(memory_cost, time_cost, parallelism)?;
+
+        if let Some(secret) = pepper {
+            Argon2::new_with_secret(secret, algorithm, version, params)
+        } else {
+            Ok(Argon2::new(algorithm, version, params))
+        }
+    }
+
     fn hash_blocking<R: CryptoRng + RngCore>(
         self,
         mut rng: R,
@@ -277,16 +381,23 @@ impl Algorithm {
                 Ok(hashed.format_for_version(bcrypt::Version::TwoB))
             }
 
-            Self::Argon2id => {
-                let algorithm = argon2::Algorithm::default();
-                let version = argon2::Version::default();
-                let params = argon2::Params::default();
-
-                let phf = if let Some(secret) = pepper {
-                    Argon2::new_with_secret(secret, algorithm, version, params)?
-                } else {
-                    Argon2::new(algorithm, version, params)
-                };
+            Self::Argon2id {
+                memory_cost,
+                time_cost,
+                parallelism,
+            }
+            | Self::Argon2i {
+                memory_cost,
+                time_cost,
+                parallelism,
+            } => {
+                let phf = Self::argon2(
+                    self.argon2_algorithm(),
+                    memory_cost,
+                    time_cost,
+                    parallelism,
+                    pepper,
+                )?;
 
                 let salt = SaltString::generate(rng);
                 let hashed = phf.hash_password(password.as_ref(), &salt)?;
@@ -303,6 +414,87 @@ impl Algorithm {
                 let hashed = Pbkdf2.hash_password(password.as_ref(), &salt)?;
                 Ok(hashed.to_string())
             }
+
+            Self::Scrypt => {
+                let mut password = Zeroizing::new(password.to_vec());
+                if let Some(pepper) = pepper {
+                    password.extend_from_slice(pepper);
+                }
+
+                let salt = SaltString::generate(rng);
+                let hashed = Scrypt.hash_password(password.as_ref(), &salt)?;
+                Ok(hashed.to_string())
+            }
+        }
+    }
+
+    /// Check whether the given hash was produced with other parameters than the
+    /// ones this algorithm currently hashes with
+    fn needs_rehash(self, hashed_password: &str) -> bool {
+        match self {
+            Self::Bcrypt { cost } => {
+                // bcrypt hashes are formatted as `$<version>$<cost>$<salt><hash>`
+                let hash_cost = hashed_password
+                    .split('$')
+                    .nth(2)
+                    .and_then(|cost| cost.parse::<u32>().ok());
+
+                hash_cost != Some(cost.unwrap_or(12))
+            }
+
+            Self::Argon2id {
+                memory_cost,
+                time_cost,
+                parallelism,
+            }
+            | Self::Argon2i {
+                memory_cost,
+                time_cost,
+                parallelism,
+            } => {
+                let Ok(hashed_password) = PasswordHash::new(hashed_password) else {
+                    return true;
+                };
+                let Ok(params) = argon2::Params::try_from(&hashed_password) else {
+                    return true;
+                };
+                let Ok(expected) = Self::argon2_params(memory_cost, time_cost, parallelism) else {
+                    return true;
+                };
+
+                hashed_password.algorithm != self.argon2_algorithm().ident()
+                    || hashed_password.version != Some(argon2::Version::default().into())
+                    || params.m_cost() != expected.m_cost()
+                    || params.t_cost() != expected.t_cost()
+                    || params.p_cost() != expected.p_cost()
+            }
+
+            Self::Pbkdf2 => {
+                let Ok(hashed_password) = PasswordHash::new(hashed_password) else {
+                    return true;
+                };
+                let Ok(params) = pbkdf2::Params::try_from(&hashed_password) else {
+                    return true;
+                };
+
+                hashed_password.algorithm != pbkdf2::Algorithm::default().ident()
+                    || params.rounds != pbkdf2::Params::default().rounds
+            }
+
+            Self::Scrypt => {
+                let Ok(hashed_password) = PasswordHash::new(hashed_password) else {
+                    return true;
+                };
+                let Ok(params) = scrypt::Params::try_from(&hashed_password) else {
+                    return true;
+                };
+                let expected = scrypt::Params::default();
+
+                hashed_password.algorithm != scrypt::ALG_ID
+                    || params.log_n() != expected.log_n()
+                    || params.r() != expected.r()
+                    || params.p() != expected.p()
+            }
         }
     }
 
@@ -323,16 +515,23 @@ impl Algorithm {
                 anyhow::ensure!(result, "wrong password");
             }
 
-            Algorithm::Argon2id => {
-                let algorithm = argon2::Algorithm::default();
-                let version = argon2::Version::default();
-                let params = argon2::Params::default();
-
-                let phf = if let Some(secret) = pepper {
-                    Argon2::new_with_secret(secret, algorithm, version, params)?
-                } else {
-                    Argon2::new(algorithm, version, params)
-                };
+            Algorithm::Argon2id {
+                memory_cost,
+                time_cost,
+                parallelism,
+            }
+            | Algorithm::Argon2i {
+                memory_cost,
+                time_cost,
+                parallelism,
+            } => {
+                let phf = Self::argon2(
+                    self.argon2_algorithm(),
+                    memory_cost,
+                    time_cost,
+                    parallelism,
+                    pepper,
+                )?;
 
                 let hashed_password = PasswordHash::new(hashed_password)?;
 
@@ -349,6 +548,17 @@ impl Algorithm {
 
                 Pbkdf2.verify_password(password.as_ref(), &hashed_password)?;
             }
+
+            Algorithm::Scrypt => {
+                let mut password = Zeroizing::new(password.to_vec());
+                if let Some(pepper) = pepper {
+                    password.extend_from_slice(pepper);
+                }
+
+                let hashed_password = PasswordHash::new(hashed_password)?;
+
+                Scrypt.verify_password(password.as_ref(), &hashed_password)?;
+            }
         };
 
         Ok(())
@@ -400,7 +610,11 @@ mod tests {
         let pepper = b"a-secret-pepper";
         let pepper2 = b"the-wrong-pepper";
 
-        let alg = Algorithm::Argon2id;
+        let alg = Algorithm::Argon2id {
+            memory_cost: None,
+            time_cost: None,
+            parallelism: None,
+        };
         // Hash with a pepper
         let hash = alg
             .hash_blocking(&mut rng, password, Some(pepper))
@@ -423,6 +637,52 @@ mod tests {
         assert!(alg.verify_blocking(&hash, password, Some(pepper)).is_err());
     }
 
+    #[tokio::test]
+    async fn hashing_argon2id_custom_params() {
+        let mut rng = rand_chacha::ChaChaRng::seed_from_u64(42);
+        let password = Zeroizing::new(b"hunter2".to_vec());
+        let wrong_password = Zeroizing::new(b"wrong-password".to_vec());
+
+        let manager = PasswordManager::new([(
+            1,
+            Hasher::argon2id_with_params(Some(8 * 1024), Some(3), Some(2), None),
+        )])
+        .unwrap();
+
+        let (version, hash) = manager
+            .hash(&mut rng, password.clone())
+            .await
+            .expect("Failed to hash");
+        assert_eq!(version, 1);
+        assert!(hash.starts_with("$argon2id$v=19$m=8192,t=3,p=2$"));
+
+        manager
+            .verify(version, password.clone(), hash.clone())
+            .await
+            .expect("Failed to verify");
+        manager
+            .verify(version, wrong_password, hash.clone())
+            .await
+            .expect_err("Verification should have failed");
+
+        // The hash matches the configured parameters, so it isn't upgraded
+        let res = manager
+            .verify_and_upgrade(&mut rng, version, password.clone(), hash.clone())
+            .await
+            .expect("Failed to verify");
+        assert!(res.is_none());
+
+        // Changing the parameters upgrades the hash on the next verification
+        let manager = PasswordManager::new([(1, Hasher::argon2id(None))]).unwrap();
+        let res = manager
+            .verify_and_upgrade(&mut rng, version, password, hash)
+            .await
+            .expect("Failed to verify");
+        let (version, hash) = res.expect("Hash should have been upgraded");
+        assert_eq!(version, 1);
+        assert!(hash.starts_with("$argon2id$v=19$m=19456,t=2,p=1$"));
+    }
+
     #[test]
     fn hashing_pbkdf2() {
         let mut rng = rand_chacha::ChaChaRng::seed_from_u64(42);
@@ -454,6 +714,71 @@ mod tests {
         assert!(alg.verify_blocking(&hash, password, Some(pepper)).is_err());
     }
 
+    #[test]
+    fn hashing_argon2i() {
+        let mut rng = rand_chacha::ChaChaRng::seed_from_u64(42);
+        let password = b"hunter2";
+        let password2 = b"wrong-password";
+        let pepper = b"a-secret-pepper";
+
+        let alg = Algorithm::Argon2i {
+            memory_cost: Some(8 * 1024),
+            time_cost: Some(3),
+            parallelism: None,
+        };
+        let hash = alg
+            .hash_blocking(&mut rng, password, Some(pepper))
+            .expect("Couldn't hash password");
+        assert!(hash.starts_with("$argon2i$v=19$m=8192,t=3,p=1$"));
+
+        assert!(alg.verify_blocking(&hash, password, Some(pepper)).is_ok());
+        assert!(alg.verify_blocking(&hash, password2, Some(pepper)).is_err());
+        assert!(alg.verify_blocking(&hash, password, None).is_err());
+        assert!(!alg.needs_rehash(&hash));
+
+        // An argon2id hash with the same parameters still needs to be upgraded
+        let argon2id = Algorithm::Argon2id {
+            memory_cost: Some(8 * 1024),
+            time_cost: Some(3),
+            parallelism: None,
+        };
+        let hash = argon2id
+            .hash_blocking(&mut rng, password, None)
+            .expect("Couldn't hash password");
+        assert!(alg.needs_rehash(&hash));
+    }
+
+    #[test]
+    fn hashing_scrypt() {
+        let mut rng = rand_chacha::ChaChaRng::seed_from_u64(42);
+        let password = b"hunter2";
+        let password2 = b"wrong-password";
+        let pepper = b"a-secret-pepper";
+        let pepper2 = b"the-wrong-pepper";
+
+        let alg = Algorithm::Scrypt;
+        // Hash with a pepper
+        let hash = alg
+            .hash_blocking(&mut rng, password, Some(pepper))
+            .expect("Couldn't hash password");
+        assert!(hash.starts_with("$scrypt$"));
+
+        assert!(alg.verify_blocking(&hash, password, Some(pepper)).is_ok());
+        assert!(alg.verify_blocking(&hash, password2, Some(pepper)).is_err());
+        assert!(alg.verify_blocking(&hash, password, Some(pepper2)).is_err());
+        assert!(alg.verify_blocking(&hash, password, None).is_err());
+        assert!(!alg.needs_rehash(&hash));
+
+        // Hash without pepper
+        let hash = alg
+            .hash_blocking(&mut rng, password, None)
+            .expect("Couldn't hash password");
+
+        assert!(alg.verify_blocking(&hash, password, None).is_ok());
+        assert!(alg.verify_blocking(&hash, password2, None).is_err());
+        assert!(alg.verify_blocking(&hash, password, Some(pepper)).is_err());
+    }
+
     #[allow(clippy::too_many_lines)]
     #[tokio::test]
     async fn hash_verify_and_upgrade() {
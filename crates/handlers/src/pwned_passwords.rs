@@ -0,0 +1,200 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Detection of the passwords which appeared in known data breaches, using the
+//! k-anonymity range API of Have I Been Pwned
+
+use std::time::Duration;
+
+use axum::{async_trait, BoxError};
+use mas_axum_utils::http_client_factory::HttpClientFactory;
+use mas_http::HttpServiceExt;
+use sha1::{Digest, Sha1};
+use tower::{Service, ServiceExt};
+use url::Url;
+
+/// Something which knows how many times a password appeared in data breaches
+#[async_trait]
+pub trait PwnedPasswords: Send + Sync {
+    /// Count how many times this password appeared in known data breaches
+    ///
+    /// Returns 0 if the password was never seen in a breach, or if it could
+    /// not be checked
+    async fn breach_count(&self, password: &str) -> u32;
+}
+
+/// A [`PwnedPasswords`] implementation querying the Have I Been Pwned range
+/// API
+///
+/// Only the first 5 characters of the SHA-1 hash of the password are sent to
+/// the API, which answers with the suffixes of all the breached hashes
+/// starting with them.
+///
+/// If the API can't be reached, or doesn't answer within a few seconds, the
+/// password is considered safe, so that an outage doesn't prevent users from
+/// setting their password.
+#[derive(Clone)]
+pub struct PwnedPasswordChecker {
+    inner: Option<(HttpClientFactory, Url)>,
+}
+
+impl PwnedPasswordChecker {
+    /// Create a new checker, querying the API at `api_url`
+    #[must_use]
+    pub fn new(http_client_factory: HttpClientFactory, api_url: Url) -> Self {
+        Self {
+            inner: Some((http_client_factory, api_url)),
+        }
+    }
+
+    /// Create a new disabled checker, which considers all passwords safe
+    #[must_use]
+    pub const fn disabled() -> Self {
+        Self { inner: None }
+    }
+}
+
+#[async_trait]
+impl PwnedPasswords for PwnedPasswordChecker {
+    #[tracing::instrument(name = "pwned_passwords.breach_count", skip_all)]
+    async fn breach_count(&self, password: &str) -> u32 {
+        let Some((http_client_factory, api_url)) = &self.inner else {
+            return 0;
+        };
+
+        match fetch_breach_count(http_client_factory, api_url, password).await {
+            Ok(count) => count,
+            Err(e) => {
+                tracing::warn!(
+                    error = &*e as &dyn std::error::Error,
+                    "Could not check the password against known data breaches"
+                );
+                0
+            }
+        }
+    }
+}
+
+/// How long to wait for the API to answer before considering the password safe
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+async fn fetch_breach_count(
+    http_client_factory: &HttpClientFactory,
+    api_url: &Url,
+    password: &str,
+) -> Result<u32, BoxError> {
+    let hash = format!("{:X}", Sha1::digest(password.as_bytes()));
+    let (prefix, suffix) = hash.split_at(5);
+
+    let uri = api_url.join(&format!("range/{prefix}"))?;
+    let request = hyper::Request::builder()
+        .uri(uri.as_str())
+        .body(mas_http::EmptyBody::new())?;
+
+    let mut client = http_client_factory
+        .client("client.pwned_passwords")
+        .response_body_to_bytes();
+
+    let response =
+        tokio::time::timeout(REQUEST_TIMEOUT, client.ready().await?.call(request)).await??;
+
+    if !response.status().is_success() {
+        return Err(format!("API responded with status {}", response.status()).into());
+    }
+
+    let body = std::str::from_utf8(response.body())?;
+    parse_breach_count(body, suffix)
+}
+
+/// Find the count of a hash suffix in a range API response, made of
+/// `SUFFIX:COUNT` lines
+fn parse_breach_count(body: &str, suffix: &str) -> Result<u32, BoxError> {
+    for line in body.lines() {
+        let Some((line_suffix, count)) = line.trim().split_once(':') else {
+            continue;
+        };
+
+        if line_suffix.eq_ignore_ascii_case(suffix) {
+            return Ok(count.parse()?);
+        }
+    }
+
+    Ok(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use wiremock::{
+        matchers::{method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_parse_breach_count() {
+        let body = "0018A45C4D1DEF81644B54AB7F969B88D65:1\r\n\
+                    1E4C9B93F3F0682250B6CF8331B7EE68FD8:3730471\r\n\
+                    011053FD0102E94D6AE2F8B83D76FAF94F6:13\r\n";
+
+        assert_eq!(
+            parse_breach_count(body, "1E4C9B93F3F0682250B6CF8331B7EE68FD8").unwrap(),
+            3_730_471
+        );
+        // The suffix is matched regardless of its case
+        assert_eq!(
+            parse_breach_count(body, "0018a45c4d1def81644b54ab7f969b88d65").unwrap(),
+            1
+        );
+        assert_eq!(
+            parse_breach_count(body, "FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF").unwrap(),
+            0
+        );
+        assert!(parse_breach_count(
+            "1E4C9B93F3F0682250B6CF8331B7EE68FD8:lots",
+            "1E4C9B93F3F0682250B6CF8331B7EE68FD8"
+        )
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_breach_count() {
+        // SHA-1 of "password" is 5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/range/5BAA6"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                "1E4C9B93F3F0682250B6CF8331B7EE68FD8:3730471\r\n\
+                 011053FD0102E94D6AE2F8B83D76FAF94F6:13\r\n",
+            ))
+            .mount(&mock_server)
+            .await;
+
+        let api_url = Url::parse(&format!("{}/", mock_server.uri())).unwrap();
+        let checker = PwnedPasswordChecker::new(HttpClientFactory::new(), api_url);
+
+        // Only the hash prefix is sent, and the suffix is found in the response
+        assert_eq!(checker.breach_count("password").await, 3_730_471);
+
+        // Passwords which can't be checked are considered safe
+        assert_eq!(
+            checker.breach_count("correct horse battery staple").await,
+            0
+        );
+
+        // A disabled checker never reaches the API
+        let checker = PwnedPasswordChecker::disabled();
+        assert_eq!(checker.breach_count("password").await, 0);
+    }
+}
@@ -0,0 +1,136 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Rate limiting of the requests which are cheap to make but can be abused,
+//! like asking for password reset emails or guessing device codes
+
+use std::{
+    collections::HashMap,
+    sync::{Mutex, PoisonError},
+};
+
+use chrono::{DateTime, Duration, Utc};
+
+/// How many requests are allowed for a key in a window of time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    /// The number of requests allowed in the window
+    pub max_requests: u32,
+
+    /// The duration of the window
+    pub window: Duration,
+}
+
+/// A store of the recent requests, keyed on what they should be limited by,
+/// like the client IP address
+pub trait RateLimiter: Send + Sync {
+    /// Record a request under this key, if it is allowed right now
+    ///
+    /// # Errors
+    ///
+    /// Returns how long to wait before trying again if the limit was reached,
+    /// in which case the request isn't recorded
+    fn hit(&self, key: &str, limit: RateLimit, now: DateTime<Utc>) -> Result<(), Duration>;
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Window {
+    count: u32,
+    ends_at: DateTime<Utc>,
+}
+
+/// A [`RateLimiter`] which counts the requests in memory, in fixed windows
+/// starting with the first request
+///
+/// Requests are not shared between instances of the service.
+#[derive(Default)]
+pub struct InMemoryRateLimiter {
+    windows: Mutex<Windows>,
+}
+
+/// The current windows of all the keys
+#[derive(Default)]
+struct Windows {
+    entries: HashMap<String, Window>,
+
+    /// The size of the map at which the past windows are removed. It doubles
+    /// after each cleanup, so that it doesn't happen on every request.
+    cleanup_at: usize,
+}
+
+impl RateLimiter for InMemoryRateLimiter {
+    fn hit(&self, key: &str, limit: RateLimit, now: DateTime<Utc>) -> Result<(), Duration> {
+        let mut windows = self.windows.lock().unwrap_or_else(PoisonError::into_inner);
+
+        // Forget about the past windows, so that the map doesn't grow forever
+        if windows.entries.len() >= windows.cleanup_at {
+            windows.entries.retain(|_, window| window.ends_at > now);
+            windows.cleanup_at = (windows.entries.len() * 2).max(64);
+        }
+
+        let window = windows
+            .entries
+            .entry(key.to_owned())
+            .and_modify(|window| {
+                if window.ends_at <= now {
+                    *window = Window {
+                        count: 0,
+                        ends_at: now + limit.window,
+                    };
+                }
+            })
+            .or_insert(Window {
+                count: 0,
+                ends_at: now + limit.window,
+            });
+
+        if window.count >= limit.max_requests {
+            return Err(window.ends_at - now);
+        }
+
+        window.count += 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_limit() {
+        let mut now = DateTime::<Utc>::UNIX_EPOCH;
+        let limiter = InMemoryRateLimiter::default();
+        let limit = RateLimit {
+            max_requests: 2,
+            window: Duration::minutes(10),
+        };
+
+        assert!(limiter.hit("a", limit, now).is_ok());
+        now += Duration::minutes(1);
+        assert!(limiter.hit("a", limit, now).is_ok());
+
+        // The window started with the first request
+        assert_eq!(limiter.hit("a", limit, now), Err(Duration::minutes(9)));
+
+        // Other keys are not affected
+        assert!(limiter.hit("b", limit, now).is_ok());
+
+        // Once the window is over, the counter starts over
+        now += Duration::minutes(9);
+        assert!(limiter.hit("a", limit, now).is_ok());
+        assert!(limiter.hit("a", limit, now).is_ok());
+        assert!(limiter.hit("a", limit, now).is_err());
+    }
+}
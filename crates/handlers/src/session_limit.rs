@@ -0,0 +1,373 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Enforcement of the maximum number of active browser sessions a user can
+//! have at once
+
+use std::net::IpAddr;
+
+use chrono::{DateTime, Utc};
+use mas_data_model::{BrowserSession, BrowserSessionLimitPolicy, SiteConfig, User, UserAgent};
+use mas_storage::{
+    user::{BrowserSessionFilter, BrowserSessionRepository},
+    Clock, Pagination, RepositoryAccess, RepositoryError,
+};
+use thiserror::Error;
+
+use crate::oauth2::backchannel_logout::{BackchannelLogoutDispatcher, PendingLogout};
+
+#[derive(Debug, Error)]
+pub(crate) enum StartBrowserSessionError {
+    #[error("the user already has too many active sessions")]
+    TooManySessions,
+
+    #[error(transparent)]
+    Repository(#[from] RepositoryError),
+}
+
+/// When a browser session started now should expire, depending on whether the
+/// user asked to be remembered
+pub(crate) fn browser_session_expiry(
+    site_config: &SiteConfig,
+    now: DateTime<Utc>,
+    remember_me: bool,
+) -> DateTime<Utc> {
+    if remember_me {
+        now + site_config.remembered_session_ttl
+    } else {
+        now + site_config.session_ttl
+    }
+}
+
+/// Start a new browser session for a user, enforcing the maximum number of
+/// active browser sessions set in the site configuration
+///
+/// If the user already has as many active sessions as allowed, either the
+/// oldest ones are finished to make room for the new one, or the new one is
+/// rejected, depending on the configured policy. The clients of the evicted
+/// sessions are notified through the returned logouts, which should be
+/// dispatched once the change is saved.
+///
+/// The session expires after the configured time-to-live, which is longer if
+/// `remember_me` is set.
+///
+/// # Errors
+///
+/// Returns [`StartBrowserSessionError::TooManySessions`] if the new session is
+/// rejected, or [`StartBrowserSessionError::Repository`] if the underlying
+/// repository fails
+pub(crate) async fn start_browser_session<R>(
+    rng: &mut (impl rand::RngCore + rand::CryptoRng + Send),
+    clock: &impl Clock,
+    repo: &mut R,
+    backchannel_logout: &BackchannelLogoutDispatcher,
+    site_config: &SiteConfig,
+    user: &User,
+    user_agent: Option<UserAgent>,
+    ip_address: Option<IpAddr>,
+    remember_me: bool,
+) -> Result<(BrowserSession, Vec<PendingLogout>), StartBrowserSessionError>
+where
+    R: RepositoryAccess<Error = RepositoryError>,
+{
+    let mut logouts = Vec::new();
+
+    if let Some(max_active) = site_config.max_active_browser_sessions {
+        let filter = BrowserSessionFilter::new()
+            .for_user(user)
+            .active_only(clock.now());
+        let active = repo.browser_session().count(filter).await?;
+
+        if active >= max_active {
+            match site_config.browser_session_limit_policy {
+                BrowserSessionLimitPolicy::RejectNew => {
+                    return Err(StartBrowserSessionError::TooManySessions);
+                }
+
+                BrowserSessionLimitPolicy::EvictOldest => {
+                    // Session IDs are ULIDs, so the first ones are the oldest
+                    let excess = active + 1 - max_active;
+                    let page = repo
+                        .browser_session()
+                        .list(filter, Pagination::first(excess))
+                        .await?;
+
+                    for session in page.edges {
+                        logouts.extend(
+                            backchannel_logout
+                                .prepare(rng, clock, repo, &session)
+                                .await?,
+                        );
+                        repo.browser_session().finish(clock, session).await?;
+                    }
+                }
+            }
+        }
+    }
+
+    let expires_at = browser_session_expiry(site_config, clock.now(), remember_me);
+    let session = repo
+        .browser_session()
+        .add(rng, clock, user, user_agent, ip_address, Some(expires_at))
+        .await?;
+
+    Ok((session, logouts))
+}
+
+#[cfg(test)]
+mod tests {
+    use mas_storage::user::UserRepository;
+    use sqlx::PgPool;
+
+    use super::*;
+    use crate::test_utils::{init_tracing, test_site_config, TestState};
+
+    fn backchannel_logout_dispatcher(state: &TestState) -> BackchannelLogoutDispatcher {
+        BackchannelLogoutDispatcher::new(
+            state.http_client_factory.clone(),
+            state.key_store.clone(),
+            state.url_builder.clone(),
+        )
+    }
+
+    async fn limited_state(pool: PgPool, policy: BrowserSessionLimitPolicy) -> TestState {
+        let site_config = SiteConfig {
+            max_active_browser_sessions: Some(2),
+            browser_session_limit_policy: policy,
+            ..test_site_config()
+        };
+
+        TestState::from_pool_with_site_config(pool, site_config)
+            .await
+            .unwrap()
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_evict_oldest(pool: PgPool) {
+        init_tracing();
+        let state = limited_state(pool, BrowserSessionLimitPolicy::EvictOldest).await;
+        let mut rng = state.rng();
+        let mut repo = state.repository().await.unwrap();
+        let dispatcher = backchannel_logout_dispatcher(&state);
+
+        let user = repo
+            .user()
+            .add(&mut rng, &state.clock, "alice".to_owned())
+            .await
+            .unwrap();
+
+        let mut sessions = Vec::new();
+        for _ in 0..3 {
+            let (session, _) = start_browser_session(
+                &mut rng,
+                &state.clock,
+                &mut repo,
+                &dispatcher,
+                &state.site_config,
+                &user,
+                None,
+                None,
+                false,
+            )
+            .await
+            .unwrap();
+            sessions.push(session);
+            state
+                .clock
+                .advance(chrono::Duration::try_minutes(1).unwrap());
+        }
+
+        // Only the two most recent sessions are still active
+        let filter = BrowserSessionFilter::new()
+            .for_user(&user)
+            .active_only(state.clock.now());
+        assert_eq!(repo.browser_session().count(filter).await.unwrap(), 2);
+
+        let oldest = repo
+            .browser_session()
+            .lookup(&state.clock, sessions[0].id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(oldest.finished_at.is_some());
+
+        for session in &sessions[1..] {
+            let session = repo
+                .browser_session()
+                .lookup(&state.clock, session.id)
+                .await
+                .unwrap()
+                .unwrap();
+            assert!(session.active());
+        }
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_reject_new(pool: PgPool) {
+        init_tracing();
+        let state = limited_state(pool, BrowserSessionLimitPolicy::RejectNew).await;
+        let mut rng = state.rng();
+        let mut repo = state.repository().await.unwrap();
+        let dispatcher = backchannel_logout_dispatcher(&state);
+
+        let user = repo
+            .user()
+            .add(&mut rng, &state.clock, "alice".to_owned())
+            .await
+            .unwrap();
+
+        for _ in 0..2 {
+            start_browser_session(
+                &mut rng,
+                &state.clock,
+                &mut repo,
+                &dispatcher,
+                &state.site_config,
+                &user,
+                None,
+                None,
+                false,
+            )
+            .await
+            .unwrap();
+        }
+
+        // The third session is rejected
+        let res = start_browser_session(
+            &mut rng,
+            &state.clock,
+            &mut repo,
+            &dispatcher,
+            &state.site_config,
+            &user,
+            None,
+            None,
+            false,
+        )
+        .await;
+        assert!(matches!(
+            res,
+            Err(StartBrowserSessionError::TooManySessions)
+        ));
+
+        // And the existing ones were left untouched
+        let filter = BrowserSessionFilter::new()
+            .for_user(&user)
+            .active_only(state.clock.now());
+        assert_eq!(repo.browser_session().count(filter).await.unwrap(), 2);
+
+        // Once one of them is finished, a new session can be started
+        let page = repo
+            .browser_session()
+            .list(filter, Pagination::first(1))
+            .await
+            .unwrap();
+        let session = page.edges.into_iter().next().unwrap();
+        repo.browser_session()
+            .finish(&state.clock, session)
+            .await
+            .unwrap();
+
+        start_browser_session(
+            &mut rng,
+            &state.clock,
+            &mut repo,
+            &dispatcher,
+            &state.site_config,
+            &user,
+            None,
+            None,
+            false,
+        )
+        .await
+        .unwrap();
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_remember_me(pool: PgPool) {
+        init_tracing();
+        let site_config = SiteConfig {
+            session_ttl: chrono::Duration::try_hours(1).unwrap(),
+            ..test_site_config()
+        };
+        let state = TestState::from_pool_with_site_config(pool, site_config)
+            .await
+            .unwrap();
+        let mut rng = state.rng();
+        let mut repo = state.repository().await.unwrap();
+        let dispatcher = backchannel_logout_dispatcher(&state);
+
+        let user = repo
+            .user()
+            .add(&mut rng, &state.clock, "alice".to_owned())
+            .await
+            .unwrap();
+
+        let now = state.clock.now();
+        let (session, _) = start_browser_session(
+            &mut rng,
+            &state.clock,
+            &mut repo,
+            &dispatcher,
+            &state.site_config,
+            &user,
+            None,
+            None,
+            false,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            session.expires_at,
+            Some(now + chrono::Duration::try_hours(1).unwrap())
+        );
+
+        let (remembered, _) = start_browser_session(
+            &mut rng,
+            &state.clock,
+            &mut repo,
+            &dispatcher,
+            &state.site_config,
+            &user,
+            None,
+            None,
+            true,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            remembered.expires_at,
+            Some(now + state.site_config.remembered_session_ttl)
+        );
+
+        // Only the session which wasn't remembered expired after a day
+        state.clock.advance(chrono::Duration::try_days(1).unwrap());
+
+        let session = repo
+            .browser_session()
+            .lookup(&state.clock, session.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(!session.active());
+
+        let remembered = repo
+            .browser_session()
+            .lookup(&state.clock, remembered.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(remembered.active());
+    }
+}
@@ -13,6 +13,7 @@
 // limitations under the License.
 
 use std::{
+    collections::HashMap,
     convert::Infallible,
     sync::{Arc, Mutex, RwLock},
     task::{Context, Poll},
@@ -34,29 +35,38 @@ use hyper::{
 };
 use mas_axum_utils::{
     cookies::{CookieJar, CookieManager},
+    dpop::{DpopNonceStore, InMemoryDpopNonceStore},
     http_client_factory::HttpClientFactory,
     ErrorWrapper,
 };
-use mas_data_model::SiteConfig;
+use mas_data_model::{BrowserSessionLimitPolicy, SiteConfig};
 use mas_i18n::Translator;
+use mas_iana::oauth::PkceCodeChallengeMethod;
 use mas_keystore::{Encrypter, JsonWebKey, JsonWebKeySet, Keystore, PrivateKey};
 use mas_matrix::{BoxHomeserverConnection, HomeserverConnection, MockHomeserverConnection};
 use mas_policy::{InstantiateError, Policy, PolicyFactory};
 use mas_router::{SimpleRoute, UrlBuilder};
 use mas_storage::{clock::MockClock, BoxClock, BoxRepository, BoxRng, Repository};
+use mas_storage_memory::MemoryStoreHandle;
 use mas_storage_pg::{DatabaseError, PgRepository};
 use mas_templates::{SiteConfigExt, Templates};
 use rand::SeedableRng;
 use rand_chacha::ChaChaRng;
 use serde::{de::DeserializeOwned, Serialize};
-use sqlx::PgPool;
+use sqlx::{
+    postgres::{PgConnectOptions, PgPoolOptions},
+    PgPool,
+};
 use tower::{Layer, Service, ServiceExt};
 use url::Url;
 
 use crate::{
+    login_throttle::{InMemoryLoginThrottle, LoginThrottle},
+    oauth2::{backchannel_logout::BackchannelLogoutDispatcher, jar::JarVerifier},
     passwords::{Hasher, PasswordManager},
-    upstream_oauth2::cache::MetadataCache,
-    ActivityTracker, BoundActivityTracker,
+    rate_limit::{InMemoryRateLimiter, RateLimiter},
+    upstream_oauth2::cache::{JwksCache, MetadataCache},
+    ActivityTracker, BoundActivityTracker, PwnedPasswords,
 };
 
 // This might fail if it's not the first time it's being called, which is fine,
@@ -94,10 +104,14 @@ pub(crate) async fn policy_factory(
 #[derive(Clone)]
 pub(crate) struct TestState {
     pub pool: PgPool,
+    /// If set, repositories are backed by this in-memory store instead of the
+    /// database
+    pub memory_store: Option<MemoryStoreHandle>,
     pub templates: Templates,
     pub key_store: Keystore,
     pub cookie_manager: CookieManager,
     pub metadata_cache: MetadataCache,
+    pub jwks_cache: JwksCache,
     pub encrypter: Encrypter,
     pub url_builder: UrlBuilder,
     pub homeserver_connection: Arc<MockHomeserverConnection>,
@@ -105,8 +119,13 @@ pub(crate) struct TestState {
     pub graphql_schema: mas_graphql::Schema,
     pub http_client_factory: HttpClientFactory,
     pub password_manager: PasswordManager,
+    pub login_throttle: Arc<dyn LoginThrottle>,
+    pub pwned_passwords: Arc<dyn PwnedPasswords>,
+    pub rate_limiter: Arc<dyn RateLimiter>,
     pub site_config: SiteConfig,
     pub activity_tracker: ActivityTracker,
+    pub dpop_nonce_store: Arc<dyn DpopNonceStore>,
+    pub jar_verifier: JarVerifier,
     pub clock: Arc<MockClock>,
     pub rng: Arc<Mutex<ChaChaRng>>,
 }
@@ -122,7 +141,18 @@ fn workspace_root() -> camino::Utf8PathBuf {
 pub fn test_site_config() -> SiteConfig {
     SiteConfig {
         access_token_ttl: Duration::try_minutes(5).unwrap(),
+        refresh_token_ttl: None,
         compat_token_ttl: Duration::try_minutes(5).unwrap(),
+        authorization_code_ttl: Duration::try_minutes(1).unwrap(),
+        pushed_authorization_request_ttl: Duration::try_seconds(90).unwrap(),
+        discovery_cache_ttl: Duration::try_minutes(5).unwrap(),
+        upstream_oauth2_session_max_age: Duration::try_minutes(10).unwrap(),
+        upstream_oauth2_max_pending_sessions: 8,
+        allowed_code_challenge_methods: vec![
+            PkceCodeChallengeMethod::Plain,
+            PkceCodeChallengeMethod::S256,
+        ],
+        disable_implicit_flow: false,
         server_name: "example.com".to_owned(),
         policy_uri: Some("https://example.com/policy".parse().unwrap()),
         tos_uri: Some("https://example.com/tos".parse().unwrap()),
@@ -132,6 +162,13 @@ pub fn test_site_config() -> SiteConfig {
         email_change_allowed: true,
         displayname_change_allowed: true,
         password_change_allowed: true,
+        password_reset_enabled: true,
+        password_reset_token_ttl: Duration::try_hours(1).unwrap(),
+        service_accounts: Vec::new(),
+        max_active_browser_sessions: None,
+        browser_session_limit_policy: BrowserSessionLimitPolicy::default(),
+        session_ttl: Duration::try_days(1).unwrap(),
+        remembered_session_ttl: Duration::try_days(30).unwrap(),
     }
 }
 
@@ -141,6 +178,18 @@ impl TestState {
         Self::from_pool_with_site_config(pool, test_site_config()).await
     }
 
+    /// Create a new test state whose repositories are backed by an in-memory
+    /// store, for tests which don't need a database
+    ///
+    /// The GraphQL API and the activity tracker still use the database pool,
+    /// which is never connected to, so they can't be used with this state.
+    pub async fn in_memory() -> Result<Self, anyhow::Error> {
+        let pool = PgPoolOptions::new().connect_lazy_with(PgConnectOptions::new());
+        let mut state = Self::from_pool(pool).await?;
+        state.memory_store = Some(MemoryStoreHandle::new());
+        Ok(state)
+    }
+
     /// Create a new test state from the given database pool and site config
     pub async fn from_pool_with_site_config(
         pool: PgPool,
@@ -171,7 +220,12 @@ impl TestState {
         let encrypter = Encrypter::new(&[0x42; 32]);
         let cookie_manager = CookieManager::derive_from(url_builder.http_base(), &[0x42; 32]);
 
-        let metadata_cache = MetadataCache::new();
+        let metadata_cache = MetadataCache::new(
+            Duration::try_minutes(5).unwrap(),
+            Duration::try_hours(1).unwrap(),
+        );
+
+        let jwks_cache = JwksCache::new();
 
         let password_manager = if site_config.password_login_enabled {
             PasswordManager::new([(1, Hasher::argon2id(None))])?
@@ -194,22 +248,45 @@ impl TestState {
             policy_factory: Arc::clone(&policy_factory),
             homeserver_connection: Arc::clone(&homeserver_connection),
             site_config: site_config.clone(),
+            admin_scopes: vec!["urn:mas:admin".to_owned()],
             rng: Arc::clone(&rng),
             clock: Arc::clone(&clock),
+            backchannel_logout: BackchannelLogoutDispatcher::new(
+                http_client_factory.clone(),
+                key_store.clone(),
+                url_builder.clone(),
+            ),
         };
+        let last_authentication_loader =
+            mas_graphql::LastAuthenticationLoader::new(Box::new(graphql_state.clone()));
         let state: mas_graphql::BoxState = Box::new(graphql_state);
 
-        let graphql_schema = mas_graphql::schema_builder().data(state).finish();
+        let graphql_schema = mas_graphql::schema_builder()
+            .data(state)
+            .data(last_authentication_loader)
+            .finish();
 
         let activity_tracker =
             ActivityTracker::new(pool.clone(), std::time::Duration::from_secs(1));
 
+        let login_throttle = Arc::new(InMemoryLoginThrottle::default());
+
+        let pwned_passwords = Arc::new(MockPwnedPasswords::default());
+
+        let rate_limiter = Arc::new(InMemoryRateLimiter::default());
+
+        let dpop_nonce_store = Arc::new(InMemoryDpopNonceStore::default());
+
+        let jar_verifier = JarVerifier::new(Duration::try_minutes(5).unwrap());
+
         Ok(Self {
             pool,
+            memory_store: None,
             templates,
             key_store,
             cookie_manager,
             metadata_cache,
+            jwks_cache,
             encrypter,
             url_builder,
             homeserver_connection,
@@ -217,8 +294,13 @@ impl TestState {
             graphql_schema,
             http_client_factory,
             password_manager,
+            login_throttle,
+            pwned_passwords,
+            rate_limiter,
             site_config,
             activity_tracker,
+            dpop_nonce_store,
+            jar_verifier,
             clock,
             rng,
         })
@@ -263,6 +345,13 @@ impl TestState {
     }
 
     pub async fn repository(&self) -> Result<BoxRepository, DatabaseError> {
+        if let Some(memory_store) = &self.memory_store {
+            return Ok(memory_store
+                .repository()
+                .map_err(mas_storage::RepositoryError::from_error)
+                .boxed());
+        }
+
         let repo = PgRepository::from_pool(&self.pool).await?;
         Ok(repo
             .map_err(mas_storage::RepositoryError::from_error)
@@ -305,13 +394,16 @@ impl TestState {
     }
 }
 
+#[derive(Clone)]
 struct TestGraphQLState {
     pool: PgPool,
     homeserver_connection: Arc<MockHomeserverConnection>,
     site_config: SiteConfig,
+    admin_scopes: Vec<String>,
     policy_factory: Arc<PolicyFactory>,
     clock: Arc<MockClock>,
     rng: Arc<Mutex<ChaChaRng>>,
+    backchannel_logout: BackchannelLogoutDispatcher,
 }
 
 #[async_trait]
@@ -342,11 +434,27 @@ impl mas_graphql::State for TestGraphQLState {
         &self.site_config
     }
 
+    fn admin_scopes(&self) -> &[String] {
+        &self.admin_scopes
+    }
+
     fn rng(&self) -> BoxRng {
         let mut parent_rng = self.rng.lock().expect("Failed to lock RNG");
         let rng = ChaChaRng::from_rng(&mut *parent_rng).expect("Failed to seed RNG");
         Box::new(rng)
     }
+
+    async fn prepare_backchannel_logout(
+        &self,
+        repo: &mut BoxRepository,
+        sessions: mas_graphql::EndingSessions<'_>,
+    ) -> Result<mas_graphql::PendingLogouts, mas_storage::RepositoryError> {
+        let mut rng = self.rng();
+        let clock = self.clock();
+        self.backchannel_logout
+            .prepare_for_graphql(&mut rng, &clock, repo, sessions)
+            .await
+    }
 }
 
 impl FromRef<TestState> for PgPool {
@@ -415,6 +523,42 @@ impl FromRef<TestState> for MetadataCache {
     }
 }
 
+impl FromRef<TestState> for JwksCache {
+    fn from_ref(input: &TestState) -> Self {
+        input.jwks_cache.clone()
+    }
+}
+
+impl FromRef<TestState> for Arc<dyn LoginThrottle> {
+    fn from_ref(input: &TestState) -> Self {
+        Arc::clone(&input.login_throttle)
+    }
+}
+
+impl FromRef<TestState> for Arc<dyn PwnedPasswords> {
+    fn from_ref(input: &TestState) -> Self {
+        Arc::clone(&input.pwned_passwords)
+    }
+}
+
+impl FromRef<TestState> for Arc<dyn RateLimiter> {
+    fn from_ref(input: &TestState) -> Self {
+        Arc::clone(&input.rate_limiter)
+    }
+}
+
+impl FromRef<TestState> for Arc<dyn DpopNonceStore> {
+    fn from_ref(input: &TestState) -> Self {
+        Arc::clone(&input.dpop_nonce_store)
+    }
+}
+
+impl FromRef<TestState> for JarVerifier {
+    fn from_ref(input: &TestState) -> Self {
+        input.jar_verifier.clone()
+    }
+}
+
 impl FromRef<TestState> for SiteConfig {
     fn from_ref(input: &TestState) -> Self {
         input.site_config.clone()
@@ -444,10 +588,15 @@ impl FromRequestParts<TestState> for BoundActivityTracker {
     type Rejection = Infallible;
 
     async fn from_request_parts(
-        _parts: &mut axum::http::request::Parts,
+        parts: &mut axum::http::request::Parts,
         state: &TestState,
     ) -> Result<Self, Self::Rejection> {
-        let ip = None;
+        // Tests can set the client IP address with the X-Forwarded-For header
+        let ip = parts
+            .headers
+            .get("x-forwarded-for")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse().ok());
         Ok(state.activity_tracker.clone().bind(ip))
     }
 }
@@ -486,10 +635,7 @@ impl FromRequestParts<TestState> for BoxRepository {
         _parts: &mut axum::http::request::Parts,
         state: &TestState,
     ) -> Result<Self, Self::Rejection> {
-        let repo = PgRepository::from_pool(&state.pool).await?;
-        Ok(repo
-            .map_err(mas_storage::RepositoryError::from_error)
-            .boxed())
+        Ok(state.repository().await?)
     }
 }
 
@@ -622,6 +768,31 @@ impl ResponseExt for Response<String> {
     }
 }
 
+/// A [`PwnedPasswords`] which knows a fixed set of breached passwords
+#[derive(Debug, Default)]
+pub struct MockPwnedPasswords {
+    breached: HashMap<String, u32>,
+}
+
+impl MockPwnedPasswords {
+    /// Create a mock which considers the given passwords breached that many
+    /// times
+    pub fn new<'a>(breached: impl IntoIterator<Item = (&'a str, u32)>) -> Self {
+        let breached = breached
+            .into_iter()
+            .map(|(password, count)| (password.to_owned(), count))
+            .collect();
+        Self { breached }
+    }
+}
+
+#[async_trait]
+impl PwnedPasswords for MockPwnedPasswords {
+    async fn breach_count(&self, password: &str) -> u32 {
+        self.breached.get(password).copied().unwrap_or(0)
+    }
+}
+
 /// A helper for storing and retrieving cookies in tests.
 #[derive(Clone, Debug, Default)]
 pub struct CookieHelper {
@@ -0,0 +1,217 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! TOTP (RFC 6238) codes and recovery codes, used as a second factor.
+
+use base64ct::{Base64, Encoding};
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use rand::{distributions::Alphanumeric, Rng};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+
+/// Codes change every 30 seconds
+const STEP: i64 = 30;
+
+/// Codes are 6 digits long
+const DIGITS: u32 = 6;
+
+/// Number of time steps accepted before and after the current one, to account
+/// for clock drift
+const SKEW: u64 = 1;
+
+/// Length of the generated secrets, 160 bits as recommended by RFC 4226
+const SECRET_LENGTH: usize = 20;
+
+/// Number of recovery codes generated at once
+const RECOVERY_CODE_COUNT: usize = 10;
+
+/// Length of each recovery code
+const RECOVERY_CODE_LENGTH: usize = 12;
+
+/// Compute a HOTP (RFC 4226) value
+fn hotp(secret: &[u8], counter: u64, digits: u32) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).expect("HMAC accepts keys of any size");
+    mac.update(&counter.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+
+    // Dynamic truncation
+    let offset = usize::from(hash[hash.len() - 1] & 0x0f);
+    let binary = u32::from_be_bytes([
+        hash[offset],
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]) & 0x7fff_ffff;
+
+    binary % 10u32.pow(digits)
+}
+
+/// Get the time step counter for the given time
+fn time_step(now: DateTime<Utc>) -> u64 {
+    u64::try_from(now.timestamp().div_euclid(STEP)).unwrap_or(0)
+}
+
+/// Generate a new random TOTP secret
+pub fn generate_secret(rng: &mut impl Rng) -> Vec<u8> {
+    let mut secret = vec![0; SECRET_LENGTH];
+    rng.fill(&mut secret[..]);
+    secret
+}
+
+/// Generate the TOTP code for the given secret at the given time
+#[must_use]
+pub fn generate(secret: &[u8], now: DateTime<Utc>) -> String {
+    let code = hotp(secret, time_step(now), DIGITS);
+    format!("{code:0width$}", width = DIGITS as usize)
+}
+
+/// Verify a TOTP code
+///
+/// Returns the time step counter which matched the code, so that it can be
+/// recorded to prevent the code from being replayed, or `None` if the code is
+/// invalid
+#[must_use]
+pub fn verify(secret: &[u8], code: &str, now: DateTime<Utc>) -> Option<u64> {
+    let code = code.trim();
+    if code.len() != DIGITS as usize || !code.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let code: u32 = code.parse().ok()?;
+
+    let current = time_step(now);
+    (current.saturating_sub(SKEW)..=current.saturating_add(SKEW))
+        .rev()
+        .find(|&counter| hotp(secret, counter, DIGITS) == code)
+}
+
+/// Whether the given input looks like a TOTP code, as opposed to a recovery
+/// code
+#[must_use]
+pub fn is_totp_code(code: &str) -> bool {
+    let code = code.trim();
+    code.len() == DIGITS as usize && code.bytes().all(|b| b.is_ascii_digit())
+}
+
+/// Generate a new set of recovery codes
+pub fn generate_recovery_codes(rng: &mut impl Rng) -> Vec<String> {
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            (&mut *rng)
+                .sample_iter(&Alphanumeric)
+                .take(RECOVERY_CODE_LENGTH)
+                .map(|c| char::from(c).to_ascii_lowercase())
+                .collect()
+        })
+        .collect()
+}
+
+/// Hash a recovery code for storage
+///
+/// Recovery codes are random and long enough that a fast hash is sufficient.
+/// The code is normalized first, so that the user can enter it regardless of
+/// casing and spacing.
+#[must_use]
+pub fn hash_recovery_code(code: &str) -> String {
+    let normalized: String = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+
+    Base64::encode_string(&Sha256::digest(normalized.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use rand::SeedableRng;
+
+    use super::*;
+
+    /// The secret used in the test vectors of RFC 4226 and RFC 6238
+    const SECRET: &[u8] = b"12345678901234567890";
+
+    #[test]
+    fn test_hotp_vectors() {
+        // RFC 4226, Appendix D
+        let expected = [
+            755_224, 287_082, 359_152, 969_429, 338_314, 254_676, 287_922, 162_583, 399_871,
+            520_489,
+        ];
+        for (counter, expected) in expected.into_iter().enumerate() {
+            assert_eq!(hotp(SECRET, counter as u64, 6), expected);
+        }
+    }
+
+    #[test]
+    fn test_totp_vectors() {
+        // RFC 6238, Appendix B, SHA-1 variant
+        let vectors = [
+            (59, 94_287_082),
+            (1_111_111_109, 7_081_804),
+            (1_111_111_111, 14_050_471),
+            (1_234_567_890, 89_005_924),
+            (2_000_000_000, 69_279_037),
+        ];
+        for (timestamp, expected) in vectors {
+            let now = Utc.timestamp_opt(timestamp, 0).unwrap();
+            assert_eq!(hotp(SECRET, time_step(now), 8), expected);
+        }
+    }
+
+    #[test]
+    fn test_verify() {
+        let now = Utc.timestamp_opt(1_111_111_111, 0).unwrap();
+        let code = generate(SECRET, now);
+        assert_eq!(code, "050471");
+
+        let counter = time_step(now);
+        assert_eq!(verify(SECRET, &code, now), Some(counter));
+
+        // Codes from adjacent time steps are accepted
+        let before = now - chrono::Duration::seconds(30);
+        assert_eq!(verify(SECRET, &code, before), Some(counter));
+        let after = now + chrono::Duration::seconds(30);
+        assert_eq!(verify(SECRET, &code, after), Some(counter));
+
+        // But not further away
+        let later = now + chrono::Duration::seconds(90);
+        assert_eq!(verify(SECRET, &code, later), None);
+
+        // Malformed codes are rejected
+        assert_eq!(verify(SECRET, "12345", now), None);
+        assert_eq!(verify(SECRET, "abcdef", now), None);
+        assert!(is_totp_code(" 050471 "));
+        assert!(!is_totp_code("abcdef123456"));
+    }
+
+    #[test]
+    fn test_recovery_codes() {
+        let mut rng = rand_chacha::ChaChaRng::seed_from_u64(42);
+        let codes = generate_recovery_codes(&mut rng);
+        assert_eq!(codes.len(), RECOVERY_CODE_COUNT);
+        for code in &codes {
+            assert_eq!(code.len(), RECOVERY_CODE_LENGTH);
+            assert!(!is_totp_code(code));
+        }
+
+        // Hashes are insensitive to casing and spacing
+        let code = &codes[0];
+        let hash = hash_recovery_code(code);
+        assert_eq!(hash_recovery_code(&code.to_ascii_uppercase()), hash);
+        assert_eq!(hash_recovery_code(&format!(" {code} ")), hash);
+        assert_ne!(hash_recovery_code(&codes[1]), hash);
+    }
+}
@@ -22,10 +22,11 @@ use mas_axum_utils::{
 };
 use mas_data_model::UpstreamOAuthProvider;
 use mas_oidc_client::requests::authorization_code::AuthorizationRequestData;
-use mas_router::UrlBuilder;
+use mas_router::{PostAuthAction, UrlBuilder};
 use mas_storage::{
+    oauth2::OAuth2AuthorizationGrantRepository,
     upstream_oauth2::{UpstreamOAuthProviderRepository, UpstreamOAuthSessionRepository},
-    BoxClock, BoxRepository, BoxRng,
+    BoxClock, BoxRepository, BoxRng, Clock,
 };
 use thiserror::Error;
 use ulid::Ulid;
@@ -33,7 +34,7 @@ use ulid::Ulid;
 use super::{cache::LazyProviderInfos, UpstreamSessionsCookie};
 use crate::{
     impl_from_error_for_route, upstream_oauth2::cache::MetadataCache,
-    views::shared::OptionalPostAuthAction,
+    views::shared::OptionalPostAuthAction, SiteConfig,
 };
 
 #[derive(Debug, Error)]
@@ -74,6 +75,7 @@ pub(crate) async fn get(
     State(metadata_cache): State<MetadataCache>,
     mut repo: BoxRepository,
     State(url_builder): State<UrlBuilder>,
+    State(site_config): State<SiteConfig>,
     cookie_jar: CookieJar,
     Path(provider_id): Path<Ulid>,
     Query(query): Query<OptionalPostAuthAction>,
@@ -90,7 +92,8 @@ pub(crate) async fn get(
     // First, discover the provider
     // This is done lazyly according to provider.discovery_mode and the various
     // endpoint overrides
-    let mut lazy_metadata = LazyProviderInfos::new(&metadata_cache, &provider, &http_service);
+    let mut lazy_metadata =
+        LazyProviderInfos::new(&metadata_cache, &provider, &http_service, clock.now());
     lazy_metadata.maybe_discover().await?;
 
     let redirect_uri = url_builder.upstream_oauth_callback(provider.id);
@@ -114,6 +117,18 @@ pub(crate) async fn get(
         &mut rng,
     )?;
 
+    // Forward the login hint the client sent in its authorization request, if
+    // the provider is configured to
+    let login_hint = match &query.post_auth_action {
+        Some(PostAuthAction::ContinueAuthorizationGrant { id }) if provider.forward_login_hint => {
+            repo.oauth2_authorization_grant()
+                .lookup(*id)
+                .await?
+                .and_then(|grant| grant.login_hint)
+        }
+        _ => None,
+    };
+
     // We do that in a block because params borrows url mutably
     {
         // Add any additional parameters to the query
@@ -121,6 +136,10 @@ pub(crate) async fn get(
         for (key, value) in &provider.additional_authorization_parameters {
             params.append_pair(key, value);
         }
+
+        if let Some(login_hint) = &login_hint {
+            params.append_pair("login_hint", login_hint);
+        }
     }
 
     let session = repo
@@ -136,8 +155,18 @@ pub(crate) async fn get(
         .await?;
 
     let cookie_jar = UpstreamSessionsCookie::load(&cookie_jar)
-        .add(session.id, provider.id, data.state, query.post_auth_action)
-        .save(cookie_jar, &clock);
+        .add(
+            session.id,
+            provider.id,
+            data.state,
+            query.post_auth_action,
+            site_config.upstream_oauth2_max_pending_sessions,
+        )
+        .save(
+            cookie_jar,
+            &clock,
+            site_config.upstream_oauth2_session_max_age,
+        );
 
     repo.save().await?;
 
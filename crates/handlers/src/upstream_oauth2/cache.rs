@@ -12,25 +12,33 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::{collections::HashMap, sync::Arc};
+use std::sync::Arc;
 
+use chrono::{DateTime, Duration, Utc};
 use mas_data_model::{
     UpstreamOAuthProvider, UpstreamOAuthProviderDiscoveryMode, UpstreamOAuthProviderPkceMode,
 };
 use mas_http::HttpService;
 use mas_iana::oauth::PkceCodeChallengeMethod;
-use mas_oidc_client::error::DiscoveryError;
-use mas_storage::{upstream_oauth2::UpstreamOAuthProviderRepository, RepositoryAccess};
+use mas_jose::{
+    constraints::{Constraint, ConstraintSet},
+    jwk::PublicJsonWebKeySet,
+};
+use mas_oidc_client::error::{DiscoveryError, JwksError};
+use mas_storage::{upstream_oauth2::UpstreamOAuthProviderRepository, Clock, RepositoryAccess};
 use oauth2_types::oidc::VerifiedProviderMetadata;
-use tokio::sync::RwLock;
+use ulid::Ulid;
 use url::Url;
 
+use crate::fetch_cache::{CachedValue, FetchCache};
+
 /// A high-level layer over metadata cache and provider configuration, which
 /// resolves endpoint overrides and discovery modes.
 pub struct LazyProviderInfos<'a> {
     cache: &'a MetadataCache,
     provider: &'a UpstreamOAuthProvider,
     http_service: &'a HttpService,
+    now: DateTime<Utc>,
     loaded_metadata: Option<Arc<VerifiedProviderMetadata>>,
 }
 
@@ -39,11 +47,13 @@ impl<'a> LazyProviderInfos<'a> {
         cache: &'a MetadataCache,
         provider: &'a UpstreamOAuthProvider,
         http_service: &'a HttpService,
+        now: DateTime<Utc>,
     ) -> Self {
         Self {
             cache,
             provider,
             http_service,
+            now,
             loaded_metadata: None,
         }
     }
@@ -72,7 +82,7 @@ impl<'a> LazyProviderInfos<'a> {
 
             let metadata = self
                 .cache
-                .get(self.http_service, &self.provider.issuer, verify)
+                .get(self.http_service, self.now, &self.provider.issuer, verify)
                 .await?;
 
             self.loaded_metadata = Some(metadata);
@@ -139,20 +149,41 @@ impl<'a> LazyProviderInfos<'a> {
 
 /// A simple OIDC metadata cache
 ///
-/// It never evicts entries, does not cache failures and has no locking.
+/// Entries are kept for as long as the `Cache-Control` header of the discovery
+/// document allows, bounded by a minimum and a maximum TTL. Failures are not
+/// cached, and evict the previously cached entry for this issuer, if any.
 /// It can also be refreshed in the background, and warmed up on startup.
 /// It is good enough for our use case.
 #[allow(clippy::module_name_repetitions)]
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone)]
 pub struct MetadataCache {
-    cache: Arc<RwLock<HashMap<String, Arc<VerifiedProviderMetadata>>>>,
-    insecure_cache: Arc<RwLock<HashMap<String, Arc<VerifiedProviderMetadata>>>>,
+    cache: FetchCache<String, VerifiedProviderMetadata>,
+    insecure_cache: FetchCache<String, VerifiedProviderMetadata>,
+    min_ttl: Duration,
+    max_ttl: Duration,
 }
 
 impl MetadataCache {
+    /// Create a new cache, keeping the discovery documents for at least
+    /// `min_ttl` and at most `max_ttl`
     #[must_use]
-    pub fn new() -> Self {
-        Self::default()
+    pub fn new(min_ttl: Duration, max_ttl: Duration) -> Self {
+        Self {
+            cache: FetchCache::default(),
+            insecure_cache: FetchCache::default(),
+            min_ttl,
+            max_ttl,
+        }
+    }
+
+    /// Compute how long a document served with the given `max-age` should be
+    /// cached for
+    fn ttl(&self, max_age: Option<std::time::Duration>) -> Duration {
+        let ttl = max_age
+            .and_then(|max_age| Duration::from_std(max_age).ok())
+            .unwrap_or(self.max_ttl);
+
+        ttl.max(self.min_ttl).min(self.max_ttl)
     }
 
     /// Warm up the cache by fetching all the known providers from the database
@@ -161,9 +192,10 @@ impl MetadataCache {
     /// This spawns a background task that will refresh the cache at the given
     /// interval.
     #[tracing::instrument(name = "metadata_cache.warm_up_and_run", skip_all, err)]
-    pub async fn warm_up_and_run<R: RepositoryAccess>(
+    pub async fn warm_up_and_run<R: RepositoryAccess, C: Clock + Send + 'static>(
         &self,
         http_service: HttpService,
+        clock: C,
         interval: std::time::Duration,
         repository: &mut R,
     ) -> Result<tokio::task::JoinHandle<()>, R::Error> {
@@ -176,7 +208,10 @@ impl MetadataCache {
                 UpstreamOAuthProviderDiscoveryMode::Disabled => continue,
             };
 
-            if let Err(e) = self.fetch(&http_service, &provider.issuer, verify).await {
+            if let Err(e) = self
+                .fetch(&http_service, clock.now(), &provider.issuer, verify)
+                .await
+            {
                 tracing::error!(issuer = %provider.issuer, error = &e as &dyn std::error::Error, "Failed to fetch provider metadata");
             }
         }
@@ -187,101 +222,209 @@ impl MetadataCache {
             loop {
                 // Re-fetch the known metadata at the given interval
                 tokio::time::sleep(interval).await;
-                cache.refresh_all(&http_service).await;
+                cache.refresh_all(&http_service, clock.now()).await;
             }
         }))
     }
 
+    /// Discover the metadata of the given issuer, along with when it expires
+    async fn discover(
+        &self,
+        http_service: &HttpService,
+        now: DateTime<Utc>,
+        issuer: &str,
+        verify: bool,
+    ) -> Result<(VerifiedProviderMetadata, DateTime<Utc>), DiscoveryError> {
+        let (metadata, max_age) = if verify {
+            mas_oidc_client::requests::discovery::discover_with_max_age(http_service, issuer)
+                .await?
+        } else {
+            mas_oidc_client::requests::discovery::insecure_discover_with_max_age(
+                http_service,
+                issuer,
+            )
+            .await?
+        };
+
+        Ok((metadata, now + self.ttl(max_age)))
+    }
+
     #[tracing::instrument(name = "metadata_cache.fetch", fields(%issuer), skip_all, err)]
     async fn fetch(
         &self,
         http_service: &HttpService,
+        now: DateTime<Utc>,
         issuer: &str,
         verify: bool,
     ) -> Result<Arc<VerifiedProviderMetadata>, DiscoveryError> {
-        if verify {
-            let metadata =
-                mas_oidc_client::requests::discovery::discover(http_service, issuer).await?;
-            let metadata = Arc::new(metadata);
-
-            self.cache
-                .write()
-                .await
-                .insert(issuer.to_owned(), metadata.clone());
-
-            Ok(metadata)
+        let cache = if verify {
+            &self.cache
         } else {
-            let metadata =
-                mas_oidc_client::requests::discovery::insecure_discover(http_service, issuer)
-                    .await?;
-            let metadata = Arc::new(metadata);
-
-            self.insecure_cache
-                .write()
-                .await
-                .insert(issuer.to_owned(), metadata.clone());
+            &self.insecure_cache
+        };
 
-            Ok(metadata)
-        }
+        cache
+            .refresh(issuer, now, || {
+                self.discover(http_service, now, issuer, verify)
+            })
+            .await
     }
 
-    /// Get the metadata for the given issuer.
+    /// Get the metadata for the given issuer, fetching it if it is not cached
+    /// or if the cached entry expired.
     #[tracing::instrument(name = "metadata_cache.get", fields(%issuer), skip_all, err)]
     pub async fn get(
         &self,
         http_service: &HttpService,
+        now: DateTime<Utc>,
         issuer: &str,
         verify: bool,
     ) -> Result<Arc<VerifiedProviderMetadata>, DiscoveryError> {
         let cache = if verify {
-            self.cache.read().await
+            &self.cache
         } else {
-            self.insecure_cache.read().await
+            &self.insecure_cache
         };
 
-        if let Some(metadata) = cache.get(issuer) {
-            return Ok(Arc::clone(metadata));
-        }
-        // Drop the cache guard so that we don't deadlock when we try to fetch
-        drop(cache);
-
-        let metadata = self.fetch(http_service, issuer, verify).await?;
-        Ok(metadata)
+        cache
+            .get_or_fetch(
+                issuer,
+                now,
+                |cached| now < cached.expires_at,
+                || self.discover(http_service, now, issuer, verify),
+            )
+            .await
     }
 
     #[tracing::instrument(name = "metadata_cache.refresh_all", skip_all)]
-    async fn refresh_all(&self, http_service: &HttpService) {
-        // Grab all the keys first to avoid locking the cache for too long
-        let keys: Vec<String> = {
-            let cache = self.cache.read().await;
-            cache.keys().cloned().collect()
-        };
-
-        for issuer in keys {
-            if let Err(e) = self.fetch(http_service, &issuer, true).await {
+    async fn refresh_all(&self, http_service: &HttpService, now: DateTime<Utc>) {
+        for issuer in self.cache.keys().await {
+            if let Err(e) = self.fetch(http_service, now, &issuer, true).await {
                 tracing::error!(issuer = %issuer, error = &e as &dyn std::error::Error, "Failed to refresh provider metadata");
             }
         }
 
         // Do the same for the insecure cache
-        let keys: Vec<String> = {
-            let cache = self.insecure_cache.read().await;
-            cache.keys().cloned().collect()
-        };
-
-        for issuer in keys {
-            if let Err(e) = self.fetch(http_service, &issuer, false).await {
+        for issuer in self.insecure_cache.keys().await {
+            if let Err(e) = self.fetch(http_service, now, &issuer, false).await {
                 tracing::error!(issuer = %issuer, error = &e as &dyn std::error::Error, "Failed to refresh provider metadata");
             }
         }
     }
 }
 
+/// How long the JWKS of the upstream providers are cached for
+const JWKS_TTL: Duration = Duration::microseconds(60 * 60 * 1000 * 1000);
+
+/// The minimum time between two fetches of the JWKS of a provider, when the
+/// provider uses a key we don't know about
+const JWKS_MIN_REFRESH_INTERVAL: Duration = Duration::microseconds(60 * 1000 * 1000);
+
+/// A cached JWKS, along with where it was fetched from
+#[derive(Debug)]
+struct JwksCacheEntry {
+    jwks_uri: Url,
+    jwks: Arc<PublicJsonWebKeySet>,
+}
+
+/// Whether this cached JWKS can be used to find the key with the given
+/// `kid`, or if it should be fetched again
+fn is_usable(
+    cached: &CachedValue<JwksCacheEntry>,
+    now: DateTime<Utc>,
+    jwks_uri: &Url,
+    kid: Option<&str>,
+) -> bool {
+    if cached.value.jwks_uri != *jwks_uri {
+        return false;
+    }
+
+    let has_key = kid.map_or(true, |kid| {
+        cached
+            .value
+            .jwks
+            .find_key(&ConstraintSet::new([Constraint::kid(kid)]))
+            .is_some()
+    });
+
+    if has_key {
+        now < cached.fetched_at + JWKS_TTL
+    } else {
+        // The provider may have rotated its keys, but don't refetch the
+        // JWKS too often, in case the key is really unknown
+        now < cached.fetched_at + JWKS_MIN_REFRESH_INTERVAL
+    }
+}
+
+/// A cache of the JWKS of the upstream providers, keyed by provider
+///
+/// The JWKS is fetched again when the provider signs with a key we don't
+/// know about, to support key rotations, but at most once every minute.
+#[allow(clippy::module_name_repetitions)]
+#[derive(Debug, Clone, Default)]
+pub struct JwksCache {
+    cache: FetchCache<Ulid, JwksCacheEntry>,
+}
+
+impl JwksCache {
+    /// Create a new, empty, JWKS cache
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get the JWKS of the given provider, which should contain the key with
+    /// the given `kid`.
+    ///
+    /// If the cached JWKS doesn't contain this key, it is fetched again,
+    /// unless it was fetched too recently. In that case the cached JWKS is
+    /// returned, and verifying the signature will fail.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the JWKS could not be fetched
+    #[tracing::instrument(
+        name = "jwks_cache.get",
+        fields(upstream_oauth_provider.id = %provider_id, %jwks_uri),
+        skip_all,
+        err,
+    )]
+    pub async fn get(
+        &self,
+        http_service: &HttpService,
+        now: DateTime<Utc>,
+        provider_id: Ulid,
+        jwks_uri: &Url,
+        kid: Option<&str>,
+    ) -> Result<Arc<PublicJsonWebKeySet>, JwksError> {
+        let entry = self
+            .cache
+            .get_or_fetch(
+                &provider_id,
+                now,
+                |cached| is_usable(cached, now, jwks_uri, kid),
+                || async {
+                    let jwks =
+                        mas_oidc_client::requests::jose::fetch_jwks(http_service, jwks_uri).await?;
+                    let entry = JwksCacheEntry {
+                        jwks_uri: jwks_uri.clone(),
+                        jwks: Arc::new(jwks),
+                    };
+                    // Expiry depends on the requested key, see `is_usable`
+                    Ok::<_, JwksError>((entry, now + JWKS_TTL))
+                },
+            )
+            .await?;
+
+        Ok(Arc::clone(&entry.jwks))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     #![allow(clippy::too_many_lines)]
 
-    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
     use hyper::{body::Bytes, Request, Response, StatusCode};
     use mas_data_model::UpstreamOAuthProviderClaimsImports;
@@ -365,40 +508,49 @@ mod tests {
             }
         };
 
+        let clock = MockClock::default();
         let service = BoxCloneSyncService::new(tower::service_fn(handler));
-        let cache = MetadataCache::new();
+        let cache = MetadataCache::new(
+            Duration::try_minutes(5).unwrap(),
+            Duration::try_hours(1).unwrap(),
+        );
 
         // An inexistant issuer should fail
         cache
-            .get(&service, "https://inexistant.example.com/", true)
+            .get(
+                &service,
+                clock.now(),
+                "https://inexistant.example.com/",
+                true,
+            )
             .await
             .unwrap_err();
         assert_eq!(calls.load(Ordering::SeqCst), 1);
 
         // A valid issuer should succeed
         cache
-            .get(&service, "https://valid.example.com/", true)
+            .get(&service, clock.now(), "https://valid.example.com/", true)
             .await
             .unwrap();
         assert_eq!(calls.load(Ordering::SeqCst), 2);
 
         // Calling again should not trigger a new fetch
         cache
-            .get(&service, "https://valid.example.com/", true)
+            .get(&service, clock.now(), "https://valid.example.com/", true)
             .await
             .unwrap();
         assert_eq!(calls.load(Ordering::SeqCst), 2);
 
         // An insecure issuer should work with insecure discovery
         cache
-            .get(&service, "http://insecure.example.com/", false)
+            .get(&service, clock.now(), "http://insecure.example.com/", false)
             .await
             .unwrap();
         assert_eq!(calls.load(Ordering::SeqCst), 3);
 
         // Doing it again shpoild not trigger a new fetch
         cache
-            .get(&service, "http://insecure.example.com/", false)
+            .get(&service, clock.now(), "http://insecure.example.com/", false)
             .await
             .unwrap();
         assert_eq!(calls.load(Ordering::SeqCst), 3);
@@ -406,14 +558,142 @@ mod tests {
         // But it should fail with secure discovery
         // Note that it still fetched because secure and insecure caches are distinct
         cache
-            .get(&service, "http://insecure.example.com/", true)
+            .get(&service, clock.now(), "http://insecure.example.com/", true)
             .await
             .unwrap_err();
         assert_eq!(calls.load(Ordering::SeqCst), 4);
 
         // Calling refresh should refresh all the known valid issuers
-        cache.refresh_all(&service).await;
+        cache.refresh_all(&service, clock.now()).await;
+        assert_eq!(calls.load(Ordering::SeqCst), 6);
+    }
+
+    #[tokio::test]
+    async fn test_metadata_cache_expiry() {
+        init_tracing();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let broken = Arc::new(AtomicBool::new(false));
+        let closure_calls = Arc::clone(&calls);
+        let closure_broken = Arc::clone(&broken);
+        let handler = move |req: Request<Bytes>| {
+            let calls = Arc::clone(&closure_calls);
+            let broken = Arc::clone(&closure_broken);
+            async move {
+                calls.fetch_add(1, Ordering::SeqCst);
+
+                if broken.load(Ordering::SeqCst) {
+                    return Ok::<_, BoxError>(Response::new(Bytes::from_static(b"not json")));
+                }
+
+                // The valid issuer asks to be cached for 10 minutes, the other one
+                // doesn't send any caching directive
+                let (body, cache_control) = match req.uri().authority().unwrap().as_str() {
+                    "valid.example.com" => (
+                        br#"{
+                            "issuer": "https://valid.example.com/",
+                            "authorization_endpoint": "https://valid.example.com/authorize",
+                            "token_endpoint": "https://valid.example.com/token",
+                            "jwks_uri": "https://valid.example.com/jwks",
+                            "response_types_supported": ["code"],
+                            "subject_types_supported": ["public"],
+                            "id_token_signing_alg_values_supported": ["RS256"]
+                        }"#
+                        .as_slice(),
+                        Some("public, max-age=600"),
+                    ),
+                    "nocache.example.com" => (
+                        br#"{
+                            "issuer": "https://nocache.example.com/",
+                            "authorization_endpoint": "https://nocache.example.com/authorize",
+                            "token_endpoint": "https://nocache.example.com/token",
+                            "jwks_uri": "https://nocache.example.com/jwks",
+                            "response_types_supported": ["code"],
+                            "subject_types_supported": ["public"],
+                            "id_token_signing_alg_values_supported": ["RS256"]
+                        }"#
+                        .as_slice(),
+                        None,
+                    ),
+                    _ => (b"".as_slice(), None),
+                };
+
+                let mut response = Response::new(Bytes::from_static(body));
+                if let Some(cache_control) = cache_control {
+                    response.headers_mut().insert(
+                        hyper::header::CACHE_CONTROL,
+                        hyper::header::HeaderValue::from_static(cache_control),
+                    );
+                }
+                Ok(response)
+            }
+        };
+
+        let clock = MockClock::default();
+        let service = BoxCloneSyncService::new(tower::service_fn(handler));
+        let cache = MetadataCache::new(
+            Duration::try_minutes(5).unwrap(),
+            Duration::try_hours(1).unwrap(),
+        );
+
+        cache
+            .get(&service, clock.now(), "https://valid.example.com/", true)
+            .await
+            .unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // A second call hits the cache
+        clock.advance(Duration::try_minutes(9).unwrap());
+        cache
+            .get(&service, clock.now(), "https://valid.example.com/", true)
+            .await
+            .unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // Once the max-age is over, it is fetched again
+        clock.advance(Duration::try_minutes(2).unwrap());
+        cache
+            .get(&service, clock.now(), "https://valid.example.com/", true)
+            .await
+            .unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        // Without any caching directive, the maximum TTL is used
+        cache
+            .get(&service, clock.now(), "https://nocache.example.com/", true)
+            .await
+            .unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+        clock.advance(Duration::try_minutes(59).unwrap());
+        cache
+            .get(&service, clock.now(), "https://nocache.example.com/", true)
+            .await
+            .unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+        clock.advance(Duration::try_minutes(2).unwrap());
+        cache
+            .get(&service, clock.now(), "https://nocache.example.com/", true)
+            .await
+            .unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 4);
+
+        // If the provider starts serving an invalid document, the entry is
+        // evicted on refresh…
+        broken.store(true, Ordering::SeqCst);
+        cache.refresh_all(&service, clock.now()).await;
         assert_eq!(calls.load(Ordering::SeqCst), 6);
+
+        // …and fetched again on the next call, even though it did not expire
+        broken.store(false, Ordering::SeqCst);
+        cache
+            .get(&service, clock.now(), "https://valid.example.com/", true)
+            .await
+            .unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 7);
+        cache
+            .get(&service, clock.now(), "https://valid.example.com/", true)
+            .await
+            .unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 7);
     }
 
     #[tokio::test]
@@ -507,12 +787,17 @@ mod tests {
             disabled_at: None,
             claims_imports: UpstreamOAuthProviderClaimsImports::default(),
             additional_authorization_parameters: Vec::new(),
+            forward_login_hint: false,
         };
 
         // Without any override, it should just use discovery
         {
-            let cache = MetadataCache::new();
-            let mut lazy_metadata = LazyProviderInfos::new(&cache, &provider, &service);
+            let cache = MetadataCache::new(
+                Duration::try_minutes(5).unwrap(),
+                Duration::try_hours(1).unwrap(),
+            );
+            let mut lazy_metadata =
+                LazyProviderInfos::new(&cache, &provider, &service, clock.now());
             assert_eq!(calls.load(Ordering::SeqCst), 0);
             lazy_metadata.maybe_discover().await.unwrap();
             assert_eq!(calls.load(Ordering::SeqCst), 1);
@@ -540,8 +825,12 @@ mod tests {
                 ),
                 ..provider.clone()
             };
-            let cache = MetadataCache::new();
-            let mut lazy_metadata = LazyProviderInfos::new(&cache, &provider, &service);
+            let cache = MetadataCache::new(
+                Duration::try_minutes(5).unwrap(),
+                Duration::try_hours(1).unwrap(),
+            );
+            let mut lazy_metadata =
+                LazyProviderInfos::new(&cache, &provider, &service, clock.now());
             assert_eq!(
                 lazy_metadata.jwks_uri().await.unwrap().as_str(),
                 "https://valid.example.com/jwks_override"
@@ -568,8 +857,12 @@ mod tests {
                 issuer: "http://insecure.example.com/".to_owned(),
                 ..provider.clone()
             };
-            let cache = MetadataCache::new();
-            let mut lazy_metadata = LazyProviderInfos::new(&cache, &provider, &service);
+            let cache = MetadataCache::new(
+                Duration::try_minutes(5).unwrap(),
+                Duration::try_hours(1).unwrap(),
+            );
+            let mut lazy_metadata =
+                LazyProviderInfos::new(&cache, &provider, &service, clock.now());
             lazy_metadata.authorization_endpoint().await.unwrap_err();
             // This triggered a fetch, even though it failed
             assert_eq!(calls.load(Ordering::SeqCst), 2);
@@ -582,8 +875,12 @@ mod tests {
                 discovery_mode: UpstreamOAuthProviderDiscoveryMode::Insecure,
                 ..provider.clone()
             };
-            let cache = MetadataCache::new();
-            let mut lazy_metadata = LazyProviderInfos::new(&cache, &provider, &service);
+            let cache = MetadataCache::new(
+                Duration::try_minutes(5).unwrap(),
+                Duration::try_hours(1).unwrap(),
+            );
+            let mut lazy_metadata =
+                LazyProviderInfos::new(&cache, &provider, &service, clock.now());
             assert_eq!(
                 lazy_metadata
                     .authorization_endpoint()
@@ -606,8 +903,12 @@ mod tests {
                 token_endpoint_override: None,
                 ..provider.clone()
             };
-            let cache = MetadataCache::new();
-            let mut lazy_metadata = LazyProviderInfos::new(&cache, &provider, &service);
+            let cache = MetadataCache::new(
+                Duration::try_minutes(5).unwrap(),
+                Duration::try_hours(1).unwrap(),
+            );
+            let mut lazy_metadata =
+                LazyProviderInfos::new(&cache, &provider, &service, clock.now());
             // This should not fail, but also does nothing
             assert!(lazy_metadata.maybe_discover().await.unwrap().is_none());
             assert_eq!(
@@ -626,4 +927,120 @@ mod tests {
             assert_eq!(calls.load(Ordering::SeqCst), 3);
         }
     }
+
+    #[tokio::test]
+    async fn test_jwks_cache() {
+        init_tracing();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let rotated = Arc::new(AtomicBool::new(false));
+        let closure_calls = Arc::clone(&calls);
+        let closure_rotated = Arc::clone(&rotated);
+        let handler = move |_req: Request<Bytes>| {
+            let calls = Arc::clone(&closure_calls);
+            let rotated = Arc::clone(&closure_rotated);
+            async move {
+                calls.fetch_add(1, Ordering::SeqCst);
+
+                let kid = if rotated.load(Ordering::SeqCst) {
+                    "key-2"
+                } else {
+                    "key-1"
+                };
+
+                let body = serde_json::json!({
+                    "keys": [{
+                        "kty": "EC",
+                        "crv": "P-256",
+                        "kid": kid,
+                        "x": "4rnALl_X1zeOJtDmxz-YiUR1-9QGBfRE90qy_rqe0N0",
+                        "y": "qGl3Telg02usgXK9jQTwcNQRLLovo07vffwaaZ3Dc5o",
+                    }]
+                });
+
+                Ok::<_, BoxError>(Response::new(Bytes::from(body.to_string())))
+            }
+        };
+
+        let clock = MockClock::default();
+        let service = BoxCloneSyncService::new(tower::service_fn(handler));
+        let cache = JwksCache::new();
+        let provider_id = Ulid::nil();
+        let jwks_uri = Url::parse("https://valid.example.com/jwks").unwrap();
+
+        let has_key = |jwks: &PublicJsonWebKeySet, kid: &str| {
+            jwks.find_key(&ConstraintSet::new([Constraint::kid(kid)]))
+                .is_some()
+        };
+
+        let jwks = cache
+            .get(&service, clock.now(), provider_id, &jwks_uri, Some("key-1"))
+            .await
+            .unwrap();
+        assert!(has_key(&jwks, "key-1"));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // A second call with a known key hits the cache
+        clock.advance(Duration::try_minutes(5).unwrap());
+        cache
+            .get(&service, clock.now(), provider_id, &jwks_uri, Some("key-1"))
+            .await
+            .unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // The provider rotates its keys. Because the JWKS was fetched more than
+        // a minute ago, it is fetched again when seeing the new key ID
+        rotated.store(true, Ordering::SeqCst);
+        let jwks = cache
+            .get(&service, clock.now(), provider_id, &jwks_uri, Some("key-2"))
+            .await
+            .unwrap();
+        assert!(has_key(&jwks, "key-2"));
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        // An unknown key ID doesn't trigger a fetch if the JWKS was fetched
+        // less than a minute ago
+        clock.advance(Duration::try_seconds(30).unwrap());
+        let jwks = cache
+            .get(
+                &service,
+                clock.now(),
+                provider_id,
+                &jwks_uri,
+                Some("unknown"),
+            )
+            .await
+            .unwrap();
+        assert!(!has_key(&jwks, "unknown"));
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        // ...but it does once the minimum interval is over
+        clock.advance(Duration::try_seconds(31).unwrap());
+        cache
+            .get(
+                &service,
+                clock.now(),
+                provider_id,
+                &jwks_uri,
+                Some("unknown"),
+            )
+            .await
+            .unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+        // The JWKS is fetched again once it expired, even for known keys
+        clock.advance(Duration::try_minutes(61).unwrap());
+        cache
+            .get(&service, clock.now(), provider_id, &jwks_uri, Some("key-2"))
+            .await
+            .unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 4);
+
+        // Changing the JWKS URI of the provider invalidates the cache
+        let other_jwks_uri = Url::parse("https://valid.example.com/other-jwks").unwrap();
+        cache
+            .get(&service, clock.now(), provider_id, &other_jwks_uri, None)
+            .await
+            .unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 5);
+    }
 }
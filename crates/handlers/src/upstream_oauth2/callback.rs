@@ -21,9 +21,11 @@ use mas_axum_utils::{
     cookies::CookieJar, http_client_factory::HttpClientFactory, sentry::SentryEventID,
 };
 use mas_data_model::UpstreamOAuthProvider;
+use mas_jose::jwt::Jwt;
 use mas_keystore::{Encrypter, Keystore};
 use mas_oidc_client::requests::{
-    authorization_code::AuthorizationValidationData, jose::JwtVerificationData,
+    authorization_code::{verify_authorization_code_id_token, AuthorizationValidationData},
+    jose::JwtVerificationData,
 };
 use mas_router::UrlBuilder;
 use mas_storage::{
@@ -39,7 +41,11 @@ use thiserror::Error;
 use ulid::Ulid;
 
 use super::{cache::LazyProviderInfos, client_credentials_for_provider, UpstreamSessionsCookie};
-use crate::{impl_from_error_for_route, upstream_oauth2::cache::MetadataCache};
+use crate::{
+    impl_from_error_for_route, metrics,
+    upstream_oauth2::cache::{JwksCache, MetadataCache},
+    SiteConfig,
+};
 
 #[derive(Deserialize)]
 pub struct QueryParams {
@@ -106,12 +112,33 @@ impl_from_error_for_route!(mas_storage::RepositoryError);
 impl_from_error_for_route!(mas_oidc_client::error::DiscoveryError);
 impl_from_error_for_route!(mas_oidc_client::error::JwksError);
 impl_from_error_for_route!(mas_oidc_client::error::TokenAuthorizationCodeError);
+impl_from_error_for_route!(mas_oidc_client::error::IdTokenError);
 impl_from_error_for_route!(super::ProviderCredentialsError);
 impl_from_error_for_route!(super::cookie::UpstreamSessionNotFound);
 
+impl RouteError {
+    /// A short description of the error, used in the metrics attributes
+    fn kind(&self) -> &'static str {
+        match self {
+            Self::SessionNotFound => "session_not_found",
+            Self::ProviderNotFound => "provider_not_found",
+            Self::ProviderMismatch => "provider_mismatch",
+            Self::AlreadyCompleted => "already_completed",
+            Self::StateMismatch => "state_mismatch",
+            Self::MissingIDToken => "missing_id_token",
+            Self::ExtractSubject(_) => "extract_subject",
+            Self::EmptySubject => "empty_subject",
+            Self::ClientError { .. } => "client_error",
+            Self::MissingCookie => "missing_cookie",
+            Self::Internal(_) => "internal",
+        }
+    }
+}
+
 impl IntoResponse for RouteError {
     fn into_response(self) -> axum::response::Response {
         let event_id = sentry::capture_error(&self);
+        metrics::record_upstream_oauth2_callback_error(self.kind());
         let response = match self {
             Self::ProviderNotFound => (StatusCode::NOT_FOUND, "Provider not found").into_response(),
             Self::SessionNotFound => (StatusCode::NOT_FOUND, "Session not found").into_response(),
@@ -135,10 +162,12 @@ pub(crate) async fn get(
     clock: BoxClock,
     State(http_client_factory): State<HttpClientFactory>,
     State(metadata_cache): State<MetadataCache>,
+    State(jwks_cache): State<JwksCache>,
     mut repo: BoxRepository,
     State(url_builder): State<UrlBuilder>,
     State(encrypter): State<Encrypter>,
     State(keystore): State<Keystore>,
+    State(site_config): State<SiteConfig>,
     cookie_jar: CookieJar,
     Path(provider_id): Path<Ulid>,
     Query(params): Query<QueryParams>,
@@ -192,12 +221,8 @@ pub(crate) async fn get(
     };
 
     let http_service = http_client_factory.http_service("upstream_oauth2.callback");
-    let mut lazy_metadata = LazyProviderInfos::new(&metadata_cache, &provider, &http_service);
-
-    // Fetch the JWKS
-    let jwks =
-        mas_oidc_client::requests::jose::fetch_jwks(&http_service, lazy_metadata.jwks_uri().await?)
-            .await?;
+    let mut lazy_metadata =
+        LazyProviderInfos::new(&metadata_cache, &provider, &http_service, clock.now());
 
     // Figure out the client credentials
     let client_credentials = client_credentials_for_provider(
@@ -217,28 +242,58 @@ pub(crate) async fn get(
         redirect_uri,
     };
 
-    let id_token_verification_data = JwtVerificationData {
-        issuer: &provider.issuer,
-        jwks: &jwks,
-        // TODO: make that configurable
-        signing_algorithm: &mas_iana::jose::JsonWebSignatureAlg::Rs256,
-        client_id: &provider.client_id,
-    };
-
-    let (response, id_token) =
+    // The ID token is verified separately, as we need to know which key signed
+    // it to get the right JWKS
+    let (response, _id_token) =
         mas_oidc_client::requests::authorization_code::access_token_with_authorization_code(
             &http_service,
             client_credentials,
             lazy_metadata.token_endpoint().await?,
-            code,
+            code.clone(),
             validation_data,
-            Some(id_token_verification_data),
+            None,
             clock.now(),
             &mut rng,
         )
         .await?;
 
-    let (_header, id_token) = id_token.ok_or(RouteError::MissingIDToken)?.into_parts();
+    let id_token = response
+        .id_token
+        .as_deref()
+        .ok_or(RouteError::MissingIDToken)?;
+
+    // Fetch the JWKS, or get it from the cache. If the ID token is signed with
+    // a key we don't know, the provider might have rotated its keys, and the
+    // cache will fetch the JWKS again
+    let jwt: Option<Jwt<'_, serde_json::Value>> = Jwt::try_from(id_token).ok();
+    let kid = jwt.as_ref().and_then(|jwt| jwt.header().kid());
+    let jwks = jwks_cache
+        .get(
+            &http_service,
+            clock.now(),
+            provider.id,
+            lazy_metadata.jwks_uri().await?,
+            kid,
+        )
+        .await?;
+
+    let id_token_verification_data = JwtVerificationData {
+        issuer: &provider.issuer,
+        jwks: &jwks,
+        // TODO: make that configurable
+        signing_algorithm: &mas_iana::jose::JsonWebSignatureAlg::Rs256,
+        client_id: &provider.client_id,
+    };
+
+    let id_token = verify_authorization_code_id_token(
+        &response,
+        &code,
+        &session.nonce,
+        id_token_verification_data,
+        clock.now(),
+    )?;
+
+    let (_header, id_token) = id_token.into_parts();
 
     let env = {
         let mut env = minijinja::Environment::new();
@@ -281,7 +336,11 @@ pub(crate) async fn get(
 
     let cookie_jar = sessions_cookie
         .add_link_to_session(session.id, link.id)?
-        .save(cookie_jar, &clock);
+        .save(
+            cookie_jar,
+            &clock,
+            site_config.upstream_oauth2_session_max_age,
+        );
 
     repo.save().await?;
 
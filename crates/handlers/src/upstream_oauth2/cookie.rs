@@ -25,9 +25,6 @@ use ulid::Ulid;
 /// Name of the cookie
 static COOKIE_NAME: &str = "upstream-oauth2-sessions";
 
-/// Sessions expire after 10 minutes
-static SESSION_MAX_TIME: Duration = Duration::microseconds(10 * 60 * 1000 * 1000);
-
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Payload {
     session: Ulid,
@@ -38,14 +35,14 @@ pub struct Payload {
 }
 
 impl Payload {
-    fn expired(&self, now: DateTime<Utc>) -> bool {
+    fn expired(&self, now: DateTime<Utc>, max_age: Duration) -> bool {
         let Ok(ts) = self.session.timestamp_ms().try_into() else {
             return true;
         };
         let Some(when) = DateTime::from_timestamp_millis(ts) else {
             return true;
         };
-        now - when > SESSION_MAX_TIME
+        now - when > max_age
     }
 }
 
@@ -69,27 +66,33 @@ impl UpstreamSessions {
         }
     }
 
-    /// Save the upstreams sessions to the cookie jar
-    pub fn save<C>(self, cookie_jar: CookieJar, clock: &C) -> CookieJar
+    /// Save the upstreams sessions to the cookie jar, dropping the ones older
+    /// than `max_age`
+    pub fn save<C>(self, cookie_jar: CookieJar, clock: &C, max_age: Duration) -> CookieJar
     where
         C: Clock,
     {
-        let this = self.expire(clock.now());
+        let this = self.expire(clock.now(), max_age);
         cookie_jar.save(COOKIE_NAME, &this, false)
     }
 
-    fn expire(mut self, now: DateTime<Utc>) -> Self {
-        self.0.retain(|p| !p.expired(now));
+    fn expire(mut self, now: DateTime<Utc>, max_age: Duration) -> Self {
+        self.0.retain(|p| !p.expired(now, max_age));
         self
     }
 
     /// Add a new session, for a provider and a random state
+    ///
+    /// If this makes the cookie hold more than `max_sessions` pending sessions,
+    /// the oldest ones are dropped. Sessions which already have a link are
+    /// kept, so that the logins which are being completed don't break
     pub fn add(
         mut self,
         session: Ulid,
         provider: Ulid,
         state: String,
         post_auth_action: Option<PostAuthAction>,
+        max_sessions: usize,
     ) -> Self {
         self.0.push(Payload {
             session,
@@ -98,6 +101,22 @@ impl UpstreamSessions {
             link: None,
             post_auth_action,
         });
+
+        let pending = self.0.iter().filter(|p| p.link.is_none()).count();
+        if pending > max_sessions {
+            // Session IDs are ULIDs, so sorting them puts the oldest first
+            self.0.sort_by_key(|p| p.session);
+            let mut excess = pending - max_sessions;
+            self.0.retain(|p| {
+                if excess > 0 && p.link.is_none() {
+                    excess -= 1;
+                    false
+                } else {
+                    true
+                }
+            });
+        }
+
         self
     }
 
@@ -172,6 +191,7 @@ mod tests {
             .with_ymd_and_hms(2018, 1, 18, 1, 30, 22)
             .unwrap();
         let mut rng = ChaChaRng::seed_from_u64(42);
+        let max_age = Duration::microseconds(10 * 60 * 1000 * 1000);
 
         let sessions = UpstreamSessions::default();
 
@@ -180,15 +200,15 @@ mod tests {
 
         let first_session = Ulid::from_datetime_with_source(now.into(), &mut rng);
         let first_state = "first-state";
-        let sessions = sessions.add(first_session, provider_a, first_state.into(), None);
+        let sessions = sessions.add(first_session, provider_a, first_state.into(), None, 8);
 
         let now = now + Duration::microseconds(5 * 60 * 1000 * 1000);
 
         let second_session = Ulid::from_datetime_with_source(now.into(), &mut rng);
         let second_state = "second-state";
-        let sessions = sessions.add(second_session, provider_b, second_state.into(), None);
+        let sessions = sessions.add(second_session, provider_b, second_state.into(), None, 8);
 
-        let sessions = sessions.expire(now);
+        let sessions = sessions.expire(now, max_age);
         assert_eq!(
             sessions.find_session(provider_a, first_state).unwrap().0,
             first_session,
@@ -202,7 +222,7 @@ mod tests {
 
         // Make the first session expire
         let now = now + Duration::microseconds(6 * 60 * 1000 * 1000);
-        let sessions = sessions.expire(now);
+        let sessions = sessions.expire(now, max_age);
         assert!(sessions.find_session(provider_a, first_state).is_err());
         assert_eq!(
             sessions.find_session(provider_b, second_state).unwrap().0,
@@ -225,4 +245,79 @@ mod tests {
         // But only once
         assert!(sessions.consume_link(second_link).is_err());
     }
+
+    #[test]
+    fn test_session_cookie_max_age() {
+        let now = chrono::Utc
+            .with_ymd_and_hms(2018, 1, 18, 1, 30, 22)
+            .unwrap();
+        let mut rng = ChaChaRng::seed_from_u64(42);
+        let max_age = Duration::try_minutes(30).unwrap();
+
+        let provider = Ulid::from_datetime_with_source(now.into(), &mut rng);
+        let session = Ulid::from_datetime_with_source(now.into(), &mut rng);
+        let sessions =
+            UpstreamSessions::default().add(session, provider, "state".to_owned(), None, 8);
+
+        // The session is still valid right at the max age...
+        let sessions = sessions.expire(now + max_age, max_age);
+        assert_eq!(sessions.find_session(provider, "state").unwrap().0, session);
+
+        // ...even if it is older than the default max age, but not after it
+        let sessions = sessions.expire(
+            now + max_age + Duration::try_milliseconds(1).unwrap(),
+            max_age,
+        );
+        assert!(sessions.find_session(provider, "state").is_err());
+    }
+
+    #[test]
+    fn test_session_cookie_max_sessions() {
+        let now = chrono::Utc
+            .with_ymd_and_hms(2018, 1, 18, 1, 30, 22)
+            .unwrap();
+        let mut rng = ChaChaRng::seed_from_u64(42);
+        let max_sessions = 3;
+
+        let provider = Ulid::from_datetime_with_source(now.into(), &mut rng);
+
+        // Start with a session which already has a link
+        let linked_session = Ulid::from_datetime_with_source(now.into(), &mut rng);
+        let link = Ulid::from_datetime_with_source(now.into(), &mut rng);
+        let mut sessions = UpstreamSessions::default()
+            .add(
+                linked_session,
+                provider,
+                "linked".to_owned(),
+                None,
+                max_sessions,
+            )
+            .add_link_to_session(linked_session, link)
+            .unwrap();
+
+        let mut ids = Vec::new();
+        for i in 0..5 {
+            let now = now + Duration::try_seconds(i).unwrap();
+            let session = Ulid::from_datetime_with_source(now.into(), &mut rng);
+            sessions = sessions.add(session, provider, format!("state-{i}"), None, max_sessions);
+            ids.push(session);
+        }
+
+        // Only the three most recent pending sessions are kept
+        assert_eq!(sessions.0.len(), max_sessions + 1);
+        assert!(sessions.find_session(provider, "state-0").is_err());
+        assert!(sessions.find_session(provider, "state-1").is_err());
+        for (i, session) in ids.iter().enumerate().skip(2) {
+            assert_eq!(
+                sessions
+                    .find_session(provider, &format!("state-{i}"))
+                    .unwrap()
+                    .0,
+                *session,
+            );
+        }
+
+        // The linked session is never evicted, even though it is the oldest
+        assert_eq!(sessions.lookup_link(link).unwrap().0, linked_session);
+    }
 }
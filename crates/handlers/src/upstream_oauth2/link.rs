@@ -33,7 +33,7 @@ use mas_storage::{
     job::{JobRepositoryExt, ProvisionUserJob},
     upstream_oauth2::{UpstreamOAuthLinkRepository, UpstreamOAuthSessionRepository},
     user::{BrowserSessionRepository, UserEmailRepository, UserRepository},
-    BoxClock, BoxRepository, BoxRng, RepositoryAccess,
+    BoxClock, BoxRepository, BoxRng, Clock, RepositoryAccess,
 };
 use mas_templates::{
     ErrorContext, FieldError, FormError, TemplateContext, Templates, ToFormState,
@@ -47,7 +47,10 @@ use ulid::Ulid;
 
 use super::{template::environment, UpstreamSessionsCookie};
 use crate::{
-    impl_from_error_for_route, views::shared::OptionalPostAuthAction, PreferredLanguage, SiteConfig,
+    impl_from_error_for_route,
+    session_limit::{browser_session_expiry, start_browser_session, StartBrowserSessionError},
+    views::shared::OptionalPostAuthAction,
+    BackchannelLogoutDispatcher, BoundActivityTracker, PreferredLanguage, SiteConfig,
 };
 
 const DEFAULT_LOCALPART_TEMPLATE: &str = "{{ user.preferred_username }}";
@@ -98,6 +101,10 @@ pub(crate) enum RouteError {
     #[error("Homeserver connection error")]
     HomeserverConnection(#[source] anyhow::Error),
 
+    /// The user already has as many active sessions as allowed
+    #[error("Too many active sessions")]
+    TooManySessions,
+
     #[error(transparent)]
     Internal(Box<dyn std::error::Error + Send + Sync + 'static>),
 }
@@ -109,6 +116,15 @@ impl_from_error_for_route!(mas_storage::RepositoryError);
 impl_from_error_for_route!(mas_policy::EvaluationError);
 impl_from_error_for_route!(mas_jose::jwt::JwtDecodeError);
 
+impl From<StartBrowserSessionError> for RouteError {
+    fn from(e: StartBrowserSessionError) -> Self {
+        match e {
+            StartBrowserSessionError::TooManySessions => Self::TooManySessions,
+            StartBrowserSessionError::Repository(e) => e.into(),
+        }
+    }
+}
+
 impl IntoResponse for RouteError {
     fn into_response(self) -> axum::response::Response {
         let event_id = sentry::capture_error(&self);
@@ -201,8 +217,11 @@ pub(crate) async fn get(
     State(templates): State<Templates>,
     State(url_builder): State<UrlBuilder>,
     State(homeserver): State<BoxHomeserverConnection>,
+    State(site_config): State<SiteConfig>,
     cookie_jar: CookieJar,
     user_agent: Option<TypedHeader<headers::UserAgent>>,
+    activity_tracker: BoundActivityTracker,
+    backchannel_logout: BackchannelLogoutDispatcher,
     Path(link_id): Path<Ulid>,
 ) -> Result<impl IntoResponse, RouteError> {
     let user_agent = user_agent.map(|ua| UserAgent::parse(ua.as_str().to_owned()));
@@ -237,9 +256,9 @@ pub(crate) async fn get(
         return Err(RouteError::SessionConsumed);
     }
 
-    let (user_session_info, cookie_jar) = cookie_jar.session_info();
+    let (user_session_info, cookie_jar) = cookie_jar.session_info(&clock);
     let (csrf_token, mut cookie_jar) = cookie_jar.csrf_token(&clock, &mut rng);
-    let maybe_user_session = user_session_info.load_session(&mut repo).await?;
+    let maybe_user_session = user_session_info.load_session(&clock, &mut repo).await?;
 
     let response = match (maybe_user_session, link.user_id) {
         (Some(session), Some(user_id)) if session.user.id == user_id => {
@@ -254,8 +273,6 @@ pub(crate) async fn get(
                 .authenticate_with_upstream(&mut rng, &clock, &session, &upstream_session)
                 .await?;
 
-            cookie_jar = cookie_jar.set_session(&session);
-
             repo.save().await?;
 
             post_auth_action.go_next(&url_builder).into_response()
@@ -300,10 +317,18 @@ pub(crate) async fn get(
                 .filter(mas_data_model::User::is_valid)
                 .ok_or(RouteError::UserNotFound)?;
 
-            let session = repo
-                .browser_session()
-                .add(&mut rng, &clock, &user, user_agent)
-                .await?;
+            let (session, logouts) = start_browser_session(
+                &mut rng,
+                &clock,
+                &mut repo,
+                &backchannel_logout,
+                &site_config,
+                &user,
+                user_agent,
+                activity_tracker.ip(),
+                false,
+            )
+            .await?;
 
             let upstream_session = repo
                 .upstream_oauth_session()
@@ -314,13 +339,17 @@ pub(crate) async fn get(
                 .authenticate_with_upstream(&mut rng, &clock, &session, &upstream_session)
                 .await?;
 
-            cookie_jar = sessions_cookie
-                .consume_link(link_id)?
-                .save(cookie_jar, &clock);
-            cookie_jar = cookie_jar.set_session(&session);
+            cookie_jar = sessions_cookie.consume_link(link_id)?.save(
+                cookie_jar,
+                &clock,
+                site_config.upstream_oauth2_session_max_age,
+            );
+            cookie_jar = cookie_jar.set_session(&clock, &session, false);
 
             repo.save().await?;
 
+            backchannel_logout.dispatch(logouts);
+
             post_auth_action.go_next(&url_builder).into_response()
         }
 
@@ -485,6 +514,7 @@ pub(crate) async fn post(
     mut repo: BoxRepository,
     cookie_jar: CookieJar,
     user_agent: Option<TypedHeader<headers::UserAgent>>,
+    activity_tracker: BoundActivityTracker,
     mut policy: Policy,
     PreferredLanguage(locale): PreferredLanguage,
     State(templates): State<Templates>,
@@ -529,8 +559,8 @@ pub(crate) async fn post(
     }
 
     let (csrf_token, cookie_jar) = cookie_jar.csrf_token(&clock, &mut rng);
-    let (user_session_info, cookie_jar) = cookie_jar.session_info();
-    let maybe_user_session = user_session_info.load_session(&mut repo).await?;
+    let (user_session_info, cookie_jar) = cookie_jar.session_info(&clock);
+    let maybe_user_session = user_session_info.load_session(&clock, &mut repo).await?;
     let form_state = form.to_form_state();
 
     let session = match (maybe_user_session, link.user_id, form) {
@@ -818,8 +848,16 @@ pub(crate) async fn post(
                 .associate_to_user(&link, &user)
                 .await?;
 
+            let expires_at = browser_session_expiry(&site_config, clock.now(), false);
             repo.browser_session()
-                .add(&mut rng, &clock, &user, user_agent)
+                .add(
+                    &mut rng,
+                    &clock,
+                    &user,
+                    user_agent,
+                    activity_tracker.ip(),
+                    Some(expires_at),
+                )
                 .await?
         }
 
@@ -835,10 +873,12 @@ pub(crate) async fn post(
         .authenticate_with_upstream(&mut rng, &clock, &session, &upstream_session)
         .await?;
 
-    let cookie_jar = sessions_cookie
-        .consume_link(link_id)?
-        .save(cookie_jar, &clock);
-    let cookie_jar = cookie_jar.set_session(&session);
+    let cookie_jar = sessions_cookie.consume_link(link_id)?.save(
+        cookie_jar,
+        &clock,
+        site_config.upstream_oauth2_session_max_age,
+    );
+    let cookie_jar = cookie_jar.set_session(&clock, &session, false);
 
     repo.save().await?;
 
@@ -849,12 +889,15 @@ pub(crate) async fn post(
 mod tests {
     use hyper::{header::CONTENT_TYPE, Request, StatusCode};
     use mas_data_model::{
+        AuthenticationMethod, UpstreamOAuthLink, UpstreamOAuthProvider,
         UpstreamOAuthProviderClaimsImports, UpstreamOAuthProviderImportPreference,
     };
     use mas_iana::{jose::JsonWebSignatureAlg, oauth::OAuthClientAuthenticationMethod};
     use mas_jose::jwt::{JsonWebSignatureHeader, Jwt};
     use mas_router::Route;
-    use mas_storage::upstream_oauth2::UpstreamOAuthProviderParams;
+    use mas_storage::{
+        upstream_oauth2::UpstreamOAuthProviderParams, user::BrowserSessionFilter, Pagination,
+    };
     use oauth2_types::scope::{Scope, OPENID};
     use sqlx::PgPool;
 
@@ -863,30 +906,16 @@ mod tests {
         init_tracing, CookieHelper, RequestBuilderExt, ResponseExt, TestState,
     };
 
-    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
-    async fn test_register(pool: PgPool) {
-        init_tracing();
-        let state = TestState::from_pool(pool).await.unwrap();
+    /// Provision an upstream provider with the given claims imports, and a
+    /// link whose upstream session got the given ID token claims, saving the
+    /// session in the cookies
+    async fn provision_link(
+        state: &TestState,
+        cookies: &CookieHelper,
+        claims_imports: UpstreamOAuthProviderClaimsImports,
+        id_token: serde_json::Value,
+    ) -> (UpstreamOAuthProvider, UpstreamOAuthLink) {
         let mut rng = state.rng();
-        let cookies = CookieHelper::new();
-
-        let claims_imports = UpstreamOAuthProviderClaimsImports {
-            localpart: UpstreamOAuthProviderImportPreference {
-                action: mas_data_model::UpstreamOAuthProviderImportAction::Force,
-                template: None,
-            },
-            email: UpstreamOAuthProviderImportPreference {
-                action: mas_data_model::UpstreamOAuthProviderImportAction::Force,
-                template: None,
-            },
-            ..UpstreamOAuthProviderClaimsImports::default()
-        };
-
-        let id_token = serde_json::json!({
-            "preferred_username": "john",
-            "email": "john@example.com",
-            "email_verified": true,
-        });
 
         // Grab a key to sign the id_token
         // We could generate a key on the fly, but because we have one available here,
@@ -926,6 +955,7 @@ mod tests {
                     discovery_mode: mas_data_model::UpstreamOAuthProviderDiscoveryMode::Oidc,
                     pkce_mode: mas_data_model::UpstreamOAuthProviderPkceMode::Auto,
                     additional_authorization_parameters: Vec::new(),
+                    forward_login_hint: false,
                 },
             )
             .await
@@ -960,12 +990,51 @@ mod tests {
 
         let cookie_jar = state.cookie_jar();
         let upstream_sessions = UpstreamSessionsCookie::default()
-            .add(session.id, provider.id, "state".to_owned(), None)
+            .add(
+                session.id,
+                provider.id,
+                "state".to_owned(),
+                None,
+                state.site_config.upstream_oauth2_max_pending_sessions,
+            )
             .add_link_to_session(session.id, link.id)
             .unwrap();
-        let cookie_jar = upstream_sessions.save(cookie_jar, &state.clock);
+        let cookie_jar = upstream_sessions.save(
+            cookie_jar,
+            &state.clock,
+            state.site_config.upstream_oauth2_session_max_age,
+        );
         cookies.import(cookie_jar);
 
+        (provider, link)
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_register(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let cookies = CookieHelper::new();
+
+        let claims_imports = UpstreamOAuthProviderClaimsImports {
+            localpart: UpstreamOAuthProviderImportPreference {
+                action: mas_data_model::UpstreamOAuthProviderImportAction::Force,
+                template: None,
+            },
+            email: UpstreamOAuthProviderImportPreference {
+                action: mas_data_model::UpstreamOAuthProviderImportAction::Force,
+                template: None,
+            },
+            ..UpstreamOAuthProviderClaimsImports::default()
+        };
+
+        let id_token = serde_json::json!({
+            "preferred_username": "john",
+            "email": "john@example.com",
+            "email_verified": true,
+        });
+
+        let (provider, link) = provision_link(&state, &cookies, claims_imports, id_token).await;
+
         let request = Request::get(&*mas_router::UpstreamOAuth2Link::new(link.id).path()).empty();
         let request = cookies.with_cookies(request);
         let response = state.request(request).await;
@@ -1023,5 +1092,69 @@ mod tests {
 
         assert_eq!(email.email, "john@example.com");
         assert!(email.confirmed_at.is_some());
+
+        // The browser session was authenticated with the upstream link
+        let page = repo
+            .browser_session()
+            .list(
+                BrowserSessionFilter::new().for_user(&user),
+                Pagination::first(1),
+            )
+            .await
+            .unwrap();
+        let browser_session = page.edges.first().expect("browser session exists");
+        let authentication = repo
+            .browser_session()
+            .get_last_authentication(browser_session)
+            .await
+            .unwrap()
+            .expect("authentication exists");
+
+        assert_eq!(
+            authentication.authentication_method,
+            AuthenticationMethod::UpstreamOAuth2 {
+                upstream_oauth2_session_id: session.id,
+                upstream_oauth_link_id: Some(link.id),
+            }
+        );
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_register_required_claim_missing(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let cookies = CookieHelper::new();
+
+        // The localpart is required, but the upstream provider doesn't give a
+        // preferred_username
+        let claims_imports = UpstreamOAuthProviderClaimsImports {
+            localpart: UpstreamOAuthProviderImportPreference {
+                action: mas_data_model::UpstreamOAuthProviderImportAction::Require,
+                template: None,
+            },
+            ..UpstreamOAuthProviderClaimsImports::default()
+        };
+
+        let id_token = serde_json::json!({
+            "email": "john@example.com",
+            "email_verified": true,
+        });
+
+        let (_provider, link) = provision_link(&state, &cookies, claims_imports, id_token).await;
+
+        let request = Request::get(&*mas_router::UpstreamOAuth2Link::new(link.id).path()).empty();
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::INTERNAL_SERVER_ERROR);
+
+        // The link is still not associated with any user
+        let mut repo = state.repository().await.unwrap();
+        let link = repo
+            .upstream_oauth_link()
+            .lookup(link.id)
+            .await
+            .unwrap()
+            .expect("link exists");
+        assert_eq!(link.user_id, None);
     }
 }
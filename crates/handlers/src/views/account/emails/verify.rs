@@ -58,9 +58,9 @@ pub(crate) async fn get(
     cookie_jar: CookieJar,
 ) -> Result<Response, FancyError> {
     let (csrf_token, cookie_jar) = cookie_jar.csrf_token(&clock, &mut rng);
-    let (session_info, cookie_jar) = cookie_jar.session_info();
+    let (session_info, cookie_jar) = cookie_jar.session_info(&clock);
 
-    let maybe_session = session_info.load_session(&mut repo).await?;
+    let maybe_session = session_info.load_session(&clock, &mut repo).await?;
 
     let Some(session) = maybe_session else {
         let login = mas_router::Login::default();
@@ -111,9 +111,9 @@ pub(crate) async fn post(
     Form(form): Form<ProtectedForm<CodeForm>>,
 ) -> Result<Response, FancyError> {
     let form = cookie_jar.verify_form(&clock, form)?;
-    let (session_info, cookie_jar) = cookie_jar.session_info();
+    let (session_info, cookie_jar) = cookie_jar.session_info(&clock);
 
-    let maybe_session = session_info.load_session(&mut repo).await?;
+    let maybe_session = session_info.load_session(&clock, &mut repo).await?;
 
     let Some(session) = maybe_session else {
         let login = mas_router::Login::default();
@@ -133,8 +133,18 @@ pub(crate) async fn post(
     let verification = repo
         .user_email()
         .find_verification_code(&clock, &user_email, &form.code)
-        .await?
-        .context("Invalid code")?;
+        .await?;
+
+    let Some(verification) = verification else {
+        // Count the failed attempt, so that the pending codes get invalidated
+        // after too many of them
+        repo.user_email()
+            .record_failed_verification_attempt(&clock, &user_email)
+            .await?;
+        repo.save().await?;
+
+        return Err(FancyError::from(anyhow::anyhow!("Invalid code")));
+    };
 
     // TODO: display nice errors if the code was already consumed or expired
     repo.user_email()
@@ -12,6 +12,8 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::sync::Arc;
+
 use anyhow::Context;
 use axum::{
     extract::{Form, State},
@@ -23,28 +25,36 @@ use mas_axum_utils::{
     csrf::{CsrfExt, ProtectedForm},
     FancyError, SessionInfoExt,
 };
-use mas_data_model::{BrowserSession, SiteConfig};
+use mas_data_model::{AuditAction, BrowserSession, SiteConfig};
 use mas_i18n::DataLocale;
 use mas_policy::Policy;
 use mas_router::UrlBuilder;
 use mas_storage::{
+    audit_log::AuditLogParams,
     user::{BrowserSessionRepository, UserPasswordRepository},
     BoxClock, BoxRepository, BoxRng, Clock,
 };
-use mas_templates::{EmptyContext, TemplateContext, Templates};
+use mas_templates::{
+    ChangePasswordContext, ChangePasswordFormField, FieldError, FormError, FormState,
+    TemplateContext, Templates, ToFormState,
+};
 use rand::Rng;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use zeroize::Zeroizing;
 
-use crate::{passwords::PasswordManager, BoundActivityTracker, PreferredLanguage};
+use crate::{passwords::PasswordManager, BoundActivityTracker, PreferredLanguage, PwnedPasswords};
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize)]
 pub struct ChangeForm {
     current_password: String,
     new_password: String,
     new_password_confirm: String,
 }
 
+impl ToFormState for ChangeForm {
+    type Field = ChangePasswordFormField;
+}
+
 #[tracing::instrument(name = "handlers.views.account_password.get", skip_all, err)]
 pub(crate) async fn get(
     mut rng: BoxRng,
@@ -64,16 +74,25 @@ pub(crate) async fn get(
             .into_response());
     }
 
-    let (session_info, cookie_jar) = cookie_jar.session_info();
+    let (session_info, cookie_jar) = cookie_jar.session_info(&clock);
 
-    let maybe_session = session_info.load_session(&mut repo).await?;
+    let maybe_session = session_info.load_session(&clock, &mut repo).await?;
 
     if let Some(session) = maybe_session {
         activity_tracker
             .record_browser_session(&clock, &session)
             .await;
 
-        render(&mut rng, &clock, locale, templates, session, cookie_jar).await
+        render(
+            &mut rng,
+            &clock,
+            locale,
+            FormState::default(),
+            templates,
+            session,
+            cookie_jar,
+        )
+        .await
     } else {
         let login = mas_router::Login::and_then(mas_router::PostAuthAction::ChangePassword);
         Ok((cookie_jar, url_builder.redirect(&login)).into_response())
@@ -84,13 +103,15 @@ async fn render(
     rng: impl Rng + Send,
     clock: &impl Clock,
     locale: DataLocale,
+    form_state: FormState<ChangePasswordFormField>,
     templates: Templates,
     session: BrowserSession,
     cookie_jar: CookieJar,
 ) -> Result<Response, FancyError> {
     let (csrf_token, cookie_jar) = cookie_jar.csrf_token(clock, rng);
 
-    let ctx = EmptyContext
+    let ctx = ChangePasswordContext::default()
+        .with_form_state(form_state)
         .with_session(session)
         .with_csrf(csrf_token.form_value())
         .with_language(locale);
@@ -106,6 +127,7 @@ pub(crate) async fn post(
     clock: BoxClock,
     PreferredLanguage(locale): PreferredLanguage,
     State(password_manager): State<PasswordManager>,
+    State(pwned_passwords): State<Arc<dyn PwnedPasswords>>,
     State(site_config): State<SiteConfig>,
     State(templates): State<Templates>,
     activity_tracker: BoundActivityTracker,
@@ -122,9 +144,9 @@ pub(crate) async fn post(
 
     let form = cookie_jar.verify_form(&clock, form)?;
 
-    let (session_info, cookie_jar) = cookie_jar.session_info();
+    let (session_info, cookie_jar) = cookie_jar.session_info(&clock);
 
-    let maybe_session = session_info.load_session(&mut repo).await?;
+    let maybe_session = session_info.load_session(&clock, &mut repo).await?;
 
     let Some(session) = maybe_session else {
         let login = mas_router::Login::and_then(mas_router::PostAuthAction::ChangePassword);
@@ -137,16 +159,51 @@ pub(crate) async fn post(
         .await?
         .context("user has no password")?;
 
+    let mut state = form.to_form_state();
+
     let res = policy.evaluate_password(&form.new_password).await?;
+    for violation in res.violations {
+        state.add_error_on_field(
+            ChangePasswordFormField::NewPassword,
+            FieldError::Policy {
+                message: violation.msg,
+            },
+        );
+    }
+
+    if form.new_password != form.new_password_confirm {
+        state.add_error_on_form(FormError::PasswordMismatch);
+        state.add_error_on_field(
+            ChangePasswordFormField::NewPassword,
+            FieldError::Unspecified,
+        );
+        state.add_error_on_field(
+            ChangePasswordFormField::NewPasswordConfirm,
+            FieldError::Unspecified,
+        );
+    }
 
-    // TODO: display nice form errors
-    if !res.valid() {
-        return Err(anyhow::anyhow!("Password policy violation: {res}").into());
+    // Only check the password against known data breaches if everything else
+    // is valid, to avoid querying the API needlessly
+    if state.is_valid() {
+        let count = pwned_passwords.breach_count(&form.new_password).await;
+        if count > 0 {
+            state.add_error_on_field(
+                ChangePasswordFormField::NewPassword,
+                FieldError::PasswordBreached { count },
+            );
+        }
+    }
+
+    if !state.is_valid() {
+        return render(
+            &mut rng, &clock, locale, state, templates, session, cookie_jar,
+        )
+        .await;
     }
 
     let password = Zeroizing::new(form.current_password.into_bytes());
     let new_password = Zeroizing::new(form.new_password.into_bytes());
-    let new_password_confirm = Zeroizing::new(form.new_password_confirm.into_bytes());
 
     password_manager
         .verify(
@@ -156,11 +213,6 @@ pub(crate) async fn post(
         )
         .await?;
 
-    // TODO: display nice form errors
-    if new_password != new_password_confirm {
-        return Err(anyhow::anyhow!("Password mismatch").into());
-    }
-
     let (version, hashed_password) = password_manager.hash(&mut rng, new_password).await?;
     let user_password = repo
         .user_password()
@@ -178,6 +230,17 @@ pub(crate) async fn post(
         .authenticate_with_password(&mut rng, &clock, &session, &user_password)
         .await?;
 
+    repo.audit_log()
+        .record(
+            &mut rng,
+            &clock,
+            AuditLogParams::new(AuditAction::PasswordChanged)
+                .with_actor(&session.user)
+                .with_actor_browser_session(&session)
+                .with_target(&session.user),
+        )
+        .await?;
+
     activity_tracker
         .record_browser_session(&clock, &session)
         .await;
@@ -186,6 +249,7 @@ pub(crate) async fn post(
         &mut rng,
         &clock,
         locale,
+        FormState::default(),
         templates.clone(),
         session,
         cookie_jar,
@@ -34,8 +34,8 @@ pub async fn get(
     clock: BoxClock,
     cookie_jar: CookieJar,
 ) -> Result<impl IntoResponse, FancyError> {
-    let (session_info, cookie_jar) = cookie_jar.session_info();
-    let session = session_info.load_session(&mut repo).await?;
+    let (session_info, cookie_jar) = cookie_jar.session_info(&clock);
+    let session = session_info.load_session(&clock, &mut repo).await?;
     let action = action.map(|Query(a)| a);
 
     // TODO: keep the full path, not just the action
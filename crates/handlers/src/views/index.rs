@@ -35,8 +35,8 @@ pub async fn get(
     PreferredLanguage(locale): PreferredLanguage,
 ) -> Result<impl IntoResponse, FancyError> {
     let (csrf_token, cookie_jar) = cookie_jar.csrf_token(&clock, &mut rng);
-    let (session_info, cookie_jar) = cookie_jar.session_info();
-    let session = session_info.load_session(&mut repo).await?;
+    let (session_info, cookie_jar) = cookie_jar.session_info(&clock);
+    let session = session_info.load_session(&clock, &mut repo).await?;
 
     if let Some(session) = session.as_ref() {
         activity_tracker
@@ -12,45 +12,107 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::sync::Arc;
+
 use axum::{
     extract::{Form, Query, State},
     response::{Html, IntoResponse, Response},
     TypedHeader,
 };
+use chrono::Duration;
 use hyper::StatusCode;
 use mas_axum_utils::{
     cookies::CookieJar,
     csrf::{CsrfExt, CsrfToken, ProtectedForm},
     FancyError, SessionInfoExt,
 };
-use mas_data_model::{BrowserSession, UserAgent};
+use mas_data_model::{AuditAction, Password, User, UserAgent};
 use mas_i18n::DataLocale;
-use mas_router::{UpstreamOAuth2Authorize, UrlBuilder};
+use mas_matrix::BoxHomeserverConnection;
+use mas_router::{PostAuthAction, UpstreamOAuth2Authorize, UrlBuilder};
 use mas_storage::{
+    audit_log::AuditLogParams,
+    oauth2::OAuth2AuthorizationGrantRepository,
     upstream_oauth2::UpstreamOAuthProviderRepository,
-    user::{BrowserSessionRepository, UserPasswordRepository, UserRepository},
-    BoxClock, BoxRepository, BoxRng, Clock, RepositoryAccess,
+    user::{BrowserSessionRepository, UserPasswordRepository, UserRepository, UserTotpRepository},
+    BoxClock, BoxRepository, BoxRng, Clock, Repository, RepositoryAccess, RepositoryError,
 };
+use mas_storage_pg::PgRepository;
 use mas_templates::{
     FieldError, FormError, LoginContext, LoginFormField, TemplateContext, Templates, ToFormState,
 };
-use rand::{CryptoRng, Rng};
+use rand::{CryptoRng, Rng, RngCore};
 use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use thiserror::Error;
 use zeroize::Zeroizing;
 
-use super::shared::OptionalPostAuthAction;
-use crate::{passwords::PasswordManager, BoundActivityTracker, PreferredLanguage, SiteConfig};
+use super::{login_totp::PendingTotpLogin, shared::OptionalPostAuthAction};
+use crate::{
+    login_throttle::{throttle_password_check, Throttled},
+    metrics,
+    passwords::{PasswordManager, SchemeVersion},
+    session_limit::{start_browser_session, StartBrowserSessionError},
+    BackchannelLogoutDispatcher, BoundActivityTracker, LoginThrottle, PreferredLanguage,
+    SiteConfig,
+};
 
 #[derive(Debug, Deserialize, Serialize)]
 pub(crate) struct LoginForm {
     username: String,
     password: String,
+    #[serde(default)]
+    remember_me: String,
 }
 
 impl ToFormState for LoginForm {
     type Field = LoginFormField;
 }
 
+#[derive(Debug, Error)]
+enum LoginError {
+    #[error("invalid credentials")]
+    InvalidCredentials,
+
+    #[error("account is locked")]
+    AccountLocked,
+
+    #[error("account is deactivated")]
+    AccountDeactivated,
+
+    #[error("too many failed login attempts, retry after {retry_after}")]
+    RateLimited { retry_after: Duration },
+
+    #[error("internal error")]
+    Internal,
+}
+
+impl From<Throttled> for LoginError {
+    fn from(e: Throttled) -> Self {
+        Self::RateLimited {
+            retry_after: e.retry_after,
+        }
+    }
+}
+
+impl From<LoginError> for FormError {
+    fn from(e: LoginError) -> Self {
+        match e {
+            LoginError::InvalidCredentials => Self::InvalidCredentials,
+            LoginError::AccountLocked => Self::AccountLocked,
+            LoginError::AccountDeactivated => Self::AccountDeactivated,
+            LoginError::RateLimited { retry_after } => {
+                // Round up to the next second, so that retrying right on time works
+                let seconds = (retry_after + Duration::milliseconds(999)).num_seconds();
+                Self::RateLimited {
+                    retry_after: seconds.try_into().unwrap_or_default(),
+                }
+            }
+            LoginError::Internal => Self::Internal,
+        }
+    }
+}
+
 #[tracing::instrument(name = "handlers.views.login.get", skip_all, err)]
 pub(crate) async fn get(
     mut rng: BoxRng,
@@ -59,15 +121,16 @@ pub(crate) async fn get(
     State(templates): State<Templates>,
     State(url_builder): State<UrlBuilder>,
     State(site_config): State<SiteConfig>,
+    State(homeserver): State<BoxHomeserverConnection>,
     mut repo: BoxRepository,
     activity_tracker: BoundActivityTracker,
     Query(query): Query<OptionalPostAuthAction>,
     cookie_jar: CookieJar,
 ) -> Result<Response, FancyError> {
     let (csrf_token, cookie_jar) = cookie_jar.csrf_token(&clock, &mut rng);
-    let (session_info, cookie_jar) = cookie_jar.session_info();
+    let (session_info, cookie_jar) = cookie_jar.session_info(&clock);
 
-    let maybe_session = session_info.load_session(&mut repo).await?;
+    let maybe_session = session_info.load_session(&clock, &mut repo).await?;
 
     if let Some(session) = maybe_session {
         activity_tracker
@@ -94,15 +157,25 @@ pub(crate) async fn get(
         return Ok((cookie_jar, url_builder.redirect(&destination)).into_response());
     };
 
-    let content = render(
-        locale,
-        LoginContext::default().with_upstream_providers(providers),
-        query,
-        csrf_token,
-        &mut repo,
-        &templates,
-    )
-    .await?;
+    let mut ctx = LoginContext::default().with_upstream_providers(providers);
+
+    // Pre-fill the username if the client hinted at a user of this homeserver
+    if let Some(PostAuthAction::ContinueAuthorizationGrant { id }) = &query.post_auth_action {
+        let grant = repo.oauth2_authorization_grant().lookup(*id).await?;
+        let username = grant
+            .as_ref()
+            .and_then(|grant| grant.login_hint_username(homeserver.homeserver()));
+        if let Some(username) = username {
+            let form = LoginForm {
+                username: username.to_owned(),
+                password: String::new(),
+                remember_me: String::new(),
+            };
+            ctx = ctx.with_form_state(form.to_form_state());
+        }
+    }
+
+    let content = render(locale, ctx, query, csrf_token, &mut repo, &templates).await?;
 
     Ok((cookie_jar, Html(content)).into_response())
 }
@@ -113,11 +186,14 @@ pub(crate) async fn post(
     clock: BoxClock,
     PreferredLanguage(locale): PreferredLanguage,
     State(password_manager): State<PasswordManager>,
+    State(login_throttle): State<Arc<dyn LoginThrottle>>,
     State(site_config): State<SiteConfig>,
     State(templates): State<Templates>,
     State(url_builder): State<UrlBuilder>,
+    State(pool): State<PgPool>,
     mut repo: BoxRepository,
     activity_tracker: BoundActivityTracker,
+    backchannel_logout: BackchannelLogoutDispatcher,
     Query(query): Query<OptionalPostAuthAction>,
     cookie_jar: CookieJar,
     user_agent: Option<TypedHeader<headers::UserAgent>>,
@@ -165,30 +241,65 @@ pub(crate) async fn post(
         return Ok((cookie_jar, Html(content)).into_response());
     }
 
-    match login(
+    let (user, user_password) = match login(
         password_manager,
+        &*login_throttle,
+        &pool,
         &mut repo,
-        rng,
+        &mut rng,
         &clock,
         &form.username,
         &form.password,
-        user_agent,
     )
     .await
     {
-        Ok(session_info) => {
-            repo.save().await?;
+        Ok(res) => res,
+        Err(e) => {
+            let state = state.with_error_on_form(e.into());
 
-            activity_tracker
-                .record_browser_session(&clock, &session_info)
-                .await;
+            let content = render(
+                locale,
+                LoginContext::default().with_form_state(state),
+                query,
+                csrf_token,
+                &mut repo,
+                &templates,
+            )
+            .await?;
 
-            let cookie_jar = cookie_jar.set_session(&session_info);
-            let reply = query.go_next(&url_builder);
-            Ok((cookie_jar, reply).into_response())
+            return Ok((cookie_jar, Html(content)).into_response());
         }
-        Err(e) => {
-            let state = state.with_error_on_form(e);
+    };
+
+    let remember_me = form.remember_me == "on";
+
+    // If the user has a TOTP enrolled, ask for it before starting a session
+    if repo.user_totp().lookup_for_user(&user).await?.is_some() {
+        repo.save().await?;
+
+        let cookie_jar = PendingTotpLogin::start(&user, &user_password, remember_me, clock.now())
+            .save(cookie_jar);
+        let destination = mas_router::LoginTotp::from(query.post_auth_action);
+        return Ok((cookie_jar, url_builder.redirect(&destination)).into_response());
+    }
+
+    // Start a new session
+    let (user_session, logouts) = match start_browser_session(
+        &mut rng,
+        &clock,
+        &mut repo,
+        &backchannel_logout,
+        &site_config,
+        &user,
+        user_agent,
+        activity_tracker.ip(),
+        remember_me,
+    )
+    .await
+    {
+        Ok(user_session) => user_session,
+        Err(StartBrowserSessionError::TooManySessions) => {
+            let state = state.with_error_on_form(FormError::TooManySessions);
 
             let content = render(
                 locale,
@@ -200,83 +311,183 @@ pub(crate) async fn post(
             )
             .await?;
 
-            Ok((cookie_jar, Html(content)).into_response())
+            return Ok((cookie_jar, Html(content)).into_response());
         }
-    }
+        Err(StartBrowserSessionError::Repository(e)) => return Err(e.into()),
+    };
+
+    // And mark it as authenticated by the password
+    repo.browser_session()
+        .authenticate_with_password(&mut rng, &clock, &user_session, &user_password)
+        .await?;
+
+    repo.audit_log()
+        .record(
+            &mut rng,
+            &clock,
+            AuditLogParams::new(AuditAction::UserLoggedIn)
+                .with_actor(&user)
+                .with_actor_browser_session(&user_session)
+                .with_target(&user),
+        )
+        .await?;
+
+    repo.save().await?;
+
+    backchannel_logout.dispatch(logouts);
+
+    activity_tracker
+        .record_browser_session(&clock, &user_session)
+        .await;
+
+    let cookie_jar = cookie_jar.set_session(&clock, &user_session, remember_me);
+    let reply = query.go_next(&url_builder);
+    Ok((cookie_jar, reply).into_response())
 }
 
 // TODO: move that logic elsewhere?
 async fn login(
     password_manager: PasswordManager,
+    login_throttle: &dyn LoginThrottle,
+    pool: &PgPool,
     repo: &mut impl RepositoryAccess,
     mut rng: impl Rng + CryptoRng + Send,
     clock: &impl Clock,
     username: &str,
     password: &str,
-    user_agent: Option<UserAgent>,
-) -> Result<BrowserSession, FormError> {
+) -> Result<(User, Password), LoginError> {
+    // Don't even try to verify the password if there were too many failed
+    // attempts recently
+    let res = throttle_password_check(
+        login_throttle,
+        clock,
+        username,
+        verify_credentials(password_manager, repo, &mut rng, username, password),
+    )
+    .await;
+
+    let (user, user_password, new_password_hash) = match res {
+        Ok(res) => res,
+        Err(LoginError::InvalidCredentials) => {
+            metrics::record_authentication_failure("password");
+            return Err(LoginError::InvalidCredentials);
+        }
+        Err(e) => return Err(e),
+    };
+
+    // Only tell that the account is deactivated or locked once the credentials
+    // were verified, so that this can't be used to probe for such accounts
+    if user.is_deactivated() {
+        return Err(LoginError::AccountDeactivated);
+    }
+
+    if !user.is_valid() {
+        return Err(LoginError::AccountLocked);
+    }
+
+    let user_password = if let Some((version, new_password_hash)) = new_password_hash {
+        // Save the upgraded password. The credentials were already verified, so
+        // failing to do so shouldn't prevent the user from logging in. This is done
+        // in a separate transaction, as a failed query would otherwise abort the
+        // transaction of the login itself.
+        let res = save_upgraded_password(
+            pool,
+            &mut rng,
+            clock,
+            &user,
+            version,
+            new_password_hash,
+            &user_password,
+        )
+        .await;
+
+        match res {
+            Ok(upgraded_password) => upgraded_password,
+            Err(e) => {
+                tracing::warn!(
+                    error = &e as &dyn std::error::Error,
+                    "Failed to save the upgraded password hash"
+                );
+                user_password
+            }
+        }
+    } else {
+        user_password
+    };
+
+    Ok((user, user_password))
+}
+
+/// Save an upgraded password hash in its own transaction
+async fn save_upgraded_password(
+    pool: &PgPool,
+    rng: &mut (dyn RngCore + Send),
+    clock: &impl Clock,
+    user: &User,
+    version: SchemeVersion,
+    hashed_password: String,
+    upgraded_from: &Password,
+) -> Result<Password, RepositoryError> {
+    let mut repo = PgRepository::from_pool(pool)
+        .await
+        .map_err(RepositoryError::from_error)?
+        .map_err(RepositoryError::from_error)
+        .boxed();
+    let password = repo
+        .user_password()
+        .add(
+            rng,
+            clock,
+            user,
+            version,
+            hashed_password,
+            Some(upgraded_from),
+        )
+        .await?;
+    repo.save().await?;
+    Ok(password)
+}
+
+/// Lookup the user and its password, and verify it. Returns the new password
+/// hash if it needs to be upgraded
+async fn verify_credentials(
+    password_manager: PasswordManager,
+    repo: &mut impl RepositoryAccess,
+    rng: impl Rng + CryptoRng + Send,
+    username: &str,
+    password: &str,
+) -> Result<(User, Password, Option<(SchemeVersion, String)>), LoginError> {
     // XXX: we're loosing the error context here
     // First, lookup the user
     let user = repo
         .user()
         .find_by_username(username)
         .await
-        .map_err(|_e| FormError::Internal)?
-        .filter(mas_data_model::User::is_valid)
-        .ok_or(FormError::InvalidCredentials)?;
+        .map_err(|_e| LoginError::Internal)?
+        .ok_or(LoginError::InvalidCredentials)?;
 
     // And its password
     let user_password = repo
         .user_password()
         .active(&user)
         .await
-        .map_err(|_e| FormError::Internal)?
-        .ok_or(FormError::InvalidCredentials)?;
+        .map_err(|_e| LoginError::Internal)?
+        .ok_or(LoginError::InvalidCredentials)?;
 
     let password = Zeroizing::new(password.as_bytes().to_vec());
 
     // Verify the password, and upgrade it on-the-fly if needed
     let new_password_hash = password_manager
         .verify_and_upgrade(
-            &mut rng,
+            rng,
             user_password.version,
             password,
             user_password.hashed_password.clone(),
         )
         .await
-        .map_err(|_| FormError::InvalidCredentials)?;
-
-    let user_password = if let Some((version, new_password_hash)) = new_password_hash {
-        // Save the upgraded password
-        repo.user_password()
-            .add(
-                &mut rng,
-                clock,
-                &user,
-                version,
-                new_password_hash,
-                Some(&user_password),
-            )
-            .await
-            .map_err(|_| FormError::Internal)?
-    } else {
-        user_password
-    };
-
-    // Start a new session
-    let user_session = repo
-        .browser_session()
-        .add(&mut rng, clock, &user, user_agent)
-        .await
-        .map_err(|_| FormError::Internal)?;
-
-    // And mark it as authenticated by the password
-    repo.browser_session()
-        .authenticate_with_password(&mut rng, clock, &user_session, &user_password)
-        .await
-        .map_err(|_| FormError::Internal)?;
+        .map_err(|_| LoginError::InvalidCredentials)?;
 
-    Ok(user_session)
+    Ok((user, user_password, new_password_hash))
 }
 
 async fn render(
@@ -301,6 +512,9 @@ async fn render(
 
 #[cfg(test)]
 mod test {
+    use std::sync::Arc;
+
+    use chrono::Duration;
     use hyper::{
         header::{CONTENT_TYPE, LOCATION},
         Request, StatusCode,
@@ -310,7 +524,8 @@ mod test {
     use mas_router::Route;
     use mas_storage::{
         upstream_oauth2::{UpstreamOAuthProviderParams, UpstreamOAuthProviderRepository},
-        RepositoryAccess,
+        user::{BrowserSessionFilter, UserTotpRepository},
+        Clock, RepositoryAccess,
     };
     use mas_templates::escape_html;
     use oauth2_types::scope::OPENID;
@@ -318,10 +533,11 @@ mod test {
     use zeroize::Zeroizing;
 
     use crate::{
+        passwords::{Hasher, PasswordManager},
         test_utils::{
             init_tracing, test_site_config, CookieHelper, RequestBuilderExt, ResponseExt, TestState,
         },
-        SiteConfig,
+        totp, InMemoryLoginThrottle, SiteConfig,
     };
 
     #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
@@ -373,6 +589,7 @@ mod test {
                     discovery_mode: mas_data_model::UpstreamOAuthProviderDiscoveryMode::Oidc,
                     pkce_mode: mas_data_model::UpstreamOAuthProviderPkceMode::Auto,
                     additional_authorization_parameters: Vec::new(),
+                    forward_login_hint: false,
                 },
             )
             .await
@@ -408,6 +625,7 @@ mod test {
                     discovery_mode: mas_data_model::UpstreamOAuthProviderDiscoveryMode::Oidc,
                     pkce_mode: mas_data_model::UpstreamOAuthProviderPkceMode::Auto,
                     additional_authorization_parameters: Vec::new(),
+                    forward_login_hint: false,
                 },
             )
             .await
@@ -491,4 +709,599 @@ mod test {
         response.assert_header_value(CONTENT_TYPE, "text/html; charset=utf-8");
         assert!(response.body().contains("john"));
     }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_password_login_legacy_hash(pool: PgPool) {
+        init_tracing();
+        let mut state = TestState::from_pool(pool).await.unwrap();
+        let mut rng = state.rng();
+        let cookies = CookieHelper::new();
+
+        // Hash the password with bcrypt, like a deployment migrating from Synapse
+        let legacy_manager = PasswordManager::new([(1, Hasher::bcrypt(Some(10), None))]).unwrap();
+        let (version, hash) = legacy_manager
+            .hash(&mut rng, Zeroizing::new("hunter2".as_bytes().to_vec()))
+            .await
+            .unwrap();
+        assert!(hash.starts_with("$2"));
+
+        // The server hashes new passwords with argon2id, but still knows about bcrypt
+        state.password_manager = PasswordManager::new([
+            (2, Hasher::argon2id(None)),
+            (1, Hasher::bcrypt(Some(10), None)),
+        ])
+        .unwrap();
+
+        let mut repo = state.repository().await.unwrap();
+        let user = repo
+            .user()
+            .add(&mut rng, &state.clock, "john".to_owned())
+            .await
+            .unwrap();
+        repo.user_password()
+            .add(&mut rng, &state.clock, &user, version, hash, None)
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        // Render the login page to get a CSRF token
+        let request = Request::get("/login").empty();
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        cookies.save_cookies(&response);
+        response.assert_status(StatusCode::OK);
+        let csrf_token = response
+            .body()
+            .split("name=\"csrf\" value=\"")
+            .nth(1)
+            .unwrap()
+            .split('\"')
+            .next()
+            .unwrap();
+
+        // Logging in with the bcrypt hash works
+        state.clock.advance(Duration::minutes(1));
+        let request = Request::post("/login").form(serde_json::json!({
+            "csrf": csrf_token,
+            "username": "john",
+            "password": "hunter2",
+        }));
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        cookies.save_cookies(&response);
+        response.assert_status(StatusCode::SEE_OTHER);
+
+        // And the password was upgraded to the current hashing scheme
+        let mut repo = state.repository().await.unwrap();
+        let password = repo.user_password().active(&user).await.unwrap().unwrap();
+        assert_eq!(password.version, 2);
+        assert!(password.hashed_password.starts_with("$argon2id$"));
+        assert!(password.upgraded_from_id.is_some());
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_password_login_weak_hash(pool: PgPool) {
+        init_tracing();
+        let mut state = TestState::from_pool(pool).await.unwrap();
+        let mut rng = state.rng();
+        let cookies = CookieHelper::new();
+
+        // Hash the password with argon2id, but with weaker parameters than the
+        // default ones
+        let params = argon2::Params::new(1024, 1, 1, None).unwrap();
+        let phf = argon2::Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params);
+        let salt = argon2::password_hash::SaltString::generate(&mut rng);
+        let hash = argon2::PasswordHasher::hash_password(&phf, b"hunter2", &salt)
+            .unwrap()
+            .to_string();
+
+        state.password_manager = PasswordManager::new([(1, Hasher::argon2id(None))]).unwrap();
+
+        let mut repo = state.repository().await.unwrap();
+        let user = repo
+            .user()
+            .add(&mut rng, &state.clock, "john".to_owned())
+            .await
+            .unwrap();
+        let weak_password = repo
+            .user_password()
+            .add(&mut rng, &state.clock, &user, 1, hash, None)
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        // Render the login page to get a CSRF token
+        let request = Request::get("/login").empty();
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        cookies.save_cookies(&response);
+        response.assert_status(StatusCode::OK);
+        let csrf_token = response
+            .body()
+            .split("name=\"csrf\" value=\"")
+            .nth(1)
+            .unwrap()
+            .split('\"')
+            .next()
+            .unwrap();
+
+        // Logging in with the weak hash works
+        state.clock.advance(Duration::minutes(1));
+        let request = Request::post("/login").form(serde_json::json!({
+            "csrf": csrf_token,
+            "username": "john",
+            "password": "hunter2",
+        }));
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        cookies.save_cookies(&response);
+        response.assert_status(StatusCode::SEE_OTHER);
+
+        // And the password was re-hashed with the default parameters, keeping the
+        // same scheme version
+        let mut repo = state.repository().await.unwrap();
+        let password = repo.user_password().active(&user).await.unwrap().unwrap();
+        assert_eq!(password.version, 1);
+        assert_eq!(password.upgraded_from_id, Some(weak_password.id));
+        assert!(password
+            .hashed_password
+            .starts_with("$argon2id$v=19$m=19456,t=2,p=1$"));
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_password_login_locked(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let mut rng = state.rng();
+        let cookies = CookieHelper::new();
+
+        // Provision a locked user with a password
+        let mut repo = state.repository().await.unwrap();
+        let user = repo
+            .user()
+            .add(&mut rng, &state.clock, "john".to_owned())
+            .await
+            .unwrap();
+        let (version, hash) = state
+            .password_manager
+            .hash(&mut rng, Zeroizing::new("hunter2".as_bytes().to_vec()))
+            .await
+            .unwrap();
+        repo.user_password()
+            .add(&mut rng, &state.clock, &user, version, hash, None)
+            .await
+            .unwrap();
+        repo.user().lock(&state.clock, user).await.unwrap();
+        repo.save().await.unwrap();
+
+        // Render the login page to get a CSRF token
+        let request = Request::get("/login").empty();
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        cookies.save_cookies(&response);
+        response.assert_status(StatusCode::OK);
+        let csrf_token = response
+            .body()
+            .split("name=\"csrf\" value=\"")
+            .nth(1)
+            .unwrap()
+            .split('\"')
+            .next()
+            .unwrap();
+
+        // A wrong password should still give the generic error
+        let request = Request::post("/login").form(serde_json::json!({
+            "csrf": csrf_token,
+            "username": "john",
+            "password": "wrong",
+        }));
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        cookies.save_cookies(&response);
+        response.assert_status(StatusCode::OK);
+        assert!(response.body().contains("Invalid credentials"));
+
+        // The right password should tell that the account is locked, without
+        // starting a session
+        let request = Request::post("/login").form(serde_json::json!({
+            "csrf": csrf_token,
+            "username": "john",
+            "password": "hunter2",
+        }));
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        cookies.save_cookies(&response);
+        response.assert_status(StatusCode::OK);
+        assert!(response.body().contains("This account has been locked"));
+
+        let mut repo = state.repository().await.unwrap();
+        let user = repo.user().find_by_username("john").await.unwrap().unwrap();
+        let sessions = repo
+            .browser_session()
+            .count(BrowserSessionFilter::new().for_user(&user))
+            .await
+            .unwrap();
+        assert_eq!(sessions, 0);
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_password_login_deactivated(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let mut rng = state.rng();
+        let cookies = CookieHelper::new();
+
+        // Provision a deactivated user with a password
+        let mut repo = state.repository().await.unwrap();
+        let user = repo
+            .user()
+            .add(&mut rng, &state.clock, "john".to_owned())
+            .await
+            .unwrap();
+        let (version, hash) = state
+            .password_manager
+            .hash(&mut rng, Zeroizing::new("hunter2".as_bytes().to_vec()))
+            .await
+            .unwrap();
+        repo.user_password()
+            .add(&mut rng, &state.clock, &user, version, hash, None)
+            .await
+            .unwrap();
+        repo.user().deactivate(&state.clock, user).await.unwrap();
+        repo.save().await.unwrap();
+
+        // Render the login page to get a CSRF token
+        let request = Request::get("/login").empty();
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        cookies.save_cookies(&response);
+        response.assert_status(StatusCode::OK);
+        let csrf_token = response
+            .body()
+            .split("name=\"csrf\" value=\"")
+            .nth(1)
+            .unwrap()
+            .split('\"')
+            .next()
+            .unwrap()
+            .to_owned();
+
+        // The right password should tell that the account is deactivated
+        let request = Request::post("/login").form(serde_json::json!({
+            "csrf": csrf_token,
+            "username": "john",
+            "password": "hunter2",
+        }));
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        cookies.save_cookies(&response);
+        response.assert_status(StatusCode::OK);
+        assert!(response
+            .body()
+            .contains("This account has been deactivated"));
+
+        // Once reactivated, the user can log in again
+        let mut repo = state.repository().await.unwrap();
+        let user = repo.user().find_by_username("john").await.unwrap().unwrap();
+        repo.user().reactivate(user).await.unwrap();
+        repo.save().await.unwrap();
+
+        let request = Request::post("/login").form(serde_json::json!({
+            "csrf": csrf_token,
+            "username": "john",
+            "password": "hunter2",
+        }));
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        cookies.save_cookies(&response);
+        response.assert_status(StatusCode::SEE_OTHER);
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_password_login_throttled(pool: PgPool) {
+        init_tracing();
+        let mut state = TestState::from_pool(pool).await.unwrap();
+        state.login_throttle = Arc::new(InMemoryLoginThrottle::new(
+            3,
+            Duration::minutes(10),
+            Duration::seconds(1),
+            Duration::minutes(5),
+        ));
+        let mut rng = state.rng();
+        let cookies = CookieHelper::new();
+
+        // Provision a user with a password
+        let mut repo = state.repository().await.unwrap();
+        let user = repo
+            .user()
+            .add(&mut rng, &state.clock, "john".to_owned())
+            .await
+            .unwrap();
+        let (version, hash) = state
+            .password_manager
+            .hash(&mut rng, Zeroizing::new("hunter2".as_bytes().to_vec()))
+            .await
+            .unwrap();
+        repo.user_password()
+            .add(&mut rng, &state.clock, &user, version, hash, None)
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        // Render the login page to get a CSRF token
+        let request = Request::get("/login").empty();
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        cookies.save_cookies(&response);
+        response.assert_status(StatusCode::OK);
+        let csrf_token = response
+            .body()
+            .split("name=\"csrf\" value=\"")
+            .nth(1)
+            .unwrap()
+            .split('\"')
+            .next()
+            .unwrap()
+            .to_owned();
+
+        // Each step waits for some time, tries a password, and checks the error
+        // message, if any
+        let steps = [
+            (0, "wrong", Some("Invalid credentials")),
+            // The second failure starts the backoff
+            (0, "wrong", Some("Invalid credentials")),
+            (0, "hunter2", Some("try again in 1 seconds")),
+            (1, "wrong", Some("Invalid credentials")),
+            // The third failure locked the account, even the right password is rejected
+            (0, "hunter2", Some("try again in 300 seconds")),
+            (299, "hunter2", Some("try again in 1 seconds")),
+            // Once the lockout is over, the right password works
+            (1, "hunter2", None),
+        ];
+
+        for (wait, password, error) in steps {
+            state.clock.advance(Duration::seconds(wait));
+            let request = Request::post("/login").form(serde_json::json!({
+                "csrf": csrf_token,
+                "username": "john",
+                "password": password,
+            }));
+            let request = cookies.with_cookies(request);
+            let response = state.request(request).await;
+
+            if let Some(error) = error {
+                response.assert_status(StatusCode::OK);
+                assert!(
+                    response.body().contains(error),
+                    "Response body: {}",
+                    response.body()
+                );
+            } else {
+                response.assert_status(StatusCode::SEE_OTHER);
+            }
+        }
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_password_login_with_totp(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let mut rng = state.rng();
+        let cookies = CookieHelper::new();
+
+        // Provision a user with a password and a TOTP secret
+        let secret = totp::generate_secret(&mut rng);
+        let mut repo = state.repository().await.unwrap();
+        let user = repo
+            .user()
+            .add(&mut rng, &state.clock, "john".to_owned())
+            .await
+            .unwrap();
+        let (version, hash) = state
+            .password_manager
+            .hash(&mut rng, Zeroizing::new("hunter2".as_bytes().to_vec()))
+            .await
+            .unwrap();
+        repo.user_password()
+            .add(&mut rng, &state.clock, &user, version, hash, None)
+            .await
+            .unwrap();
+        repo.user_totp()
+            .add(
+                &mut rng,
+                &state.clock,
+                &user,
+                state.encrypter.encrypt_to_string(&secret).unwrap(),
+            )
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        // Render the login page to get a CSRF token
+        let request = Request::get("/login").empty();
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        cookies.save_cookies(&response);
+        response.assert_status(StatusCode::OK);
+        let csrf_token = response
+            .body()
+            .split("name=\"csrf\" value=\"")
+            .nth(1)
+            .unwrap()
+            .split('\"')
+            .next()
+            .unwrap()
+            .to_owned();
+
+        // Submit the login form, it should redirect to the TOTP step
+        let request = Request::post("/login").form(serde_json::json!({
+            "csrf": csrf_token,
+            "username": "john",
+            "password": "hunter2",
+        }));
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        cookies.save_cookies(&response);
+        response.assert_status(StatusCode::SEE_OTHER);
+        response.assert_header_value(LOCATION, "/login/totp");
+
+        // We should not be logged in yet
+        let request = Request::get("/").empty();
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        assert!(!response.body().contains("john"));
+
+        // Render the TOTP page
+        let request = Request::get("/login/totp").empty();
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        cookies.save_cookies(&response);
+        response.assert_status(StatusCode::OK);
+        response.assert_header_value(CONTENT_TYPE, "text/html; charset=utf-8");
+
+        // Submitting a wrong code should render the form again
+        let wrong_code = if totp::generate(&secret, state.clock.now()) == "000000" {
+            "111111"
+        } else {
+            "000000"
+        };
+        let request = Request::post("/login/totp").form(serde_json::json!({
+            "csrf": csrf_token,
+            "code": wrong_code,
+        }));
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        cookies.save_cookies(&response);
+        response.assert_status(StatusCode::OK);
+
+        // Submitting the right code should log us in
+        let request = Request::post("/login/totp").form(serde_json::json!({
+            "csrf": csrf_token,
+            "code": totp::generate(&secret, state.clock.now()),
+        }));
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        cookies.save_cookies(&response);
+        response.assert_status(StatusCode::SEE_OTHER);
+
+        let request = Request::get("/").empty();
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        response.assert_status(StatusCode::OK);
+        assert!(response.body().contains("john"));
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_password_login_with_totp_throttled(pool: PgPool) {
+        init_tracing();
+        let mut state = TestState::from_pool(pool).await.unwrap();
+        state.login_throttle = Arc::new(InMemoryLoginThrottle::new(
+            2,
+            Duration::minutes(10),
+            Duration::seconds(1),
+            Duration::minutes(5),
+        ));
+        let mut rng = state.rng();
+        let cookies = CookieHelper::new();
+
+        // Provision a user with a password and a TOTP secret
+        let secret = totp::generate_secret(&mut rng);
+        let mut repo = state.repository().await.unwrap();
+        let user = repo
+            .user()
+            .add(&mut rng, &state.clock, "john".to_owned())
+            .await
+            .unwrap();
+        let (version, hash) = state
+            .password_manager
+            .hash(&mut rng, Zeroizing::new("hunter2".as_bytes().to_vec()))
+            .await
+            .unwrap();
+        repo.user_password()
+            .add(&mut rng, &state.clock, &user, version, hash, None)
+            .await
+            .unwrap();
+        repo.user_totp()
+            .add(
+                &mut rng,
+                &state.clock,
+                &user,
+                state.encrypter.encrypt_to_string(&secret).unwrap(),
+            )
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        // Render the login page to get a CSRF token
+        let request = Request::get("/login").empty();
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        cookies.save_cookies(&response);
+        response.assert_status(StatusCode::OK);
+        let csrf_token = response
+            .body()
+            .split("name=\"csrf\" value=\"")
+            .nth(1)
+            .unwrap()
+            .split('\"')
+            .next()
+            .unwrap()
+            .to_owned();
+
+        // Go through the password step
+        let request = Request::post("/login").form(serde_json::json!({
+            "csrf": csrf_token,
+            "username": "john",
+            "password": "hunter2",
+        }));
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        cookies.save_cookies(&response);
+        response.assert_status(StatusCode::SEE_OTHER);
+        response.assert_header_value(LOCATION, "/login/totp");
+
+        // Each step waits for some time, tries a code, and checks the error
+        // message, if any
+        let steps = [
+            (0, false, Some("data-invalid")),
+            // The second failure locks the second factor
+            (0, false, Some("data-invalid")),
+            (0, true, Some("try again in 300 seconds")),
+            (299, true, Some("try again in 1 seconds")),
+            // Once the lockout is over, the right code works
+            (1, true, None),
+        ];
+
+        for (wait, right, error) in steps {
+            state.clock.advance(Duration::seconds(wait));
+            let code = totp::generate(&secret, state.clock.now());
+            let code = if right {
+                code
+            } else if code == "000000" {
+                "111111".to_owned()
+            } else {
+                "000000".to_owned()
+            };
+
+            let request = Request::post("/login/totp").form(serde_json::json!({
+                "csrf": csrf_token,
+                "code": code,
+            }));
+            let request = cookies.with_cookies(request);
+            let response = state.request(request).await;
+            cookies.save_cookies(&response);
+
+            if let Some(error) = error {
+                response.assert_status(StatusCode::OK);
+                assert!(
+                    response.body().contains(error),
+                    "Response body: {}",
+                    response.body()
+                );
+            } else {
+                response.assert_status(StatusCode::SEE_OTHER);
+            }
+        }
+    }
 }
@@ -0,0 +1,406 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+
+use axum::{
+    extract::{Form, Query, State},
+    response::{Html, IntoResponse, Response},
+    TypedHeader,
+};
+use chrono::{DateTime, Duration, Utc};
+use mas_axum_utils::{
+    cookies::CookieJar,
+    csrf::{CsrfExt, CsrfToken, ProtectedForm},
+    FancyError, SessionInfoExt,
+};
+use mas_data_model::{AuditAction, Password, User, UserAgent};
+use mas_i18n::DataLocale;
+use mas_keystore::Encrypter;
+use mas_router::UrlBuilder;
+use mas_storage::{
+    audit_log::AuditLogParams,
+    user::{
+        BrowserSessionRepository, UserPasswordRepository, UserRecoveryCodeRepository,
+        UserRepository, UserTotpRepository,
+    },
+    BoxClock, BoxRepository, BoxRng, Clock, RepositoryAccess,
+};
+use mas_templates::{
+    FieldError, FormError, LoginTotpContext, LoginTotpFormField, TemplateContext, Templates,
+    ToFormState,
+};
+use serde::{Deserialize, Serialize};
+use ulid::Ulid;
+
+use super::shared::OptionalPostAuthAction;
+use crate::{
+    metrics,
+    session_limit::{start_browser_session, StartBrowserSessionError},
+    totp, BackchannelLogoutDispatcher, BoundActivityTracker, LoginThrottle, PreferredLanguage,
+    SiteConfig,
+};
+
+/// Name of the cookie
+static COOKIE_NAME: &str = "totp-login";
+
+/// The user has 10 minutes to enter their TOTP code after entering their
+/// password
+static PENDING_LOGIN_MAX_TIME: Duration = Duration::microseconds(10 * 60 * 1000 * 1000);
+
+/// The key under which failed second factor attempts of a user are throttled.
+///
+/// Those share the login throttle, but can't collide with usernames, which
+/// can't contain a colon.
+fn throttle_key(user: &User) -> String {
+    format!("totp:{}", user.id)
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Payload {
+    user_id: Ulid,
+    user_password_id: Ulid,
+    #[serde(default)]
+    remember_me: bool,
+    created_at: DateTime<Utc>,
+}
+
+/// A login which went through the password step, and is waiting for the
+/// second factor, saved in a cookie
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub(crate) struct PendingTotpLogin(Option<Payload>);
+
+impl PendingTotpLogin {
+    /// Load the pending login from the cookie jar
+    pub fn load(cookie_jar: &CookieJar) -> Self {
+        match cookie_jar.load(COOKIE_NAME) {
+            Ok(Some(pending)) => pending,
+            Ok(None) => Self::default(),
+            Err(e) => {
+                tracing::warn!("Invalid pending TOTP login cookie: {}", e);
+                Self::default()
+            }
+        }
+    }
+
+    /// Start a pending login for the given user, authenticated with the given
+    /// password, remembering whether they asked to stay signed in
+    pub fn start(
+        user: &User,
+        user_password: &Password,
+        remember_me: bool,
+        now: DateTime<Utc>,
+    ) -> Self {
+        Self(Some(Payload {
+            user_id: user.id,
+            user_password_id: user_password.id,
+            remember_me,
+            created_at: now,
+        }))
+    }
+
+    /// Get the pending login, if it did not expire
+    fn get(&self, now: DateTime<Utc>) -> Option<&Payload> {
+        self.0
+            .as_ref()
+            .filter(|payload| now - payload.created_at <= PENDING_LOGIN_MAX_TIME)
+    }
+
+    /// Save the pending login to the cookie jar
+    pub fn save(self, cookie_jar: CookieJar) -> CookieJar {
+        cookie_jar.save(COOKIE_NAME, &self, false)
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub(crate) struct LoginTotpForm {
+    code: String,
+}
+
+impl ToFormState for LoginTotpForm {
+    type Field = LoginTotpFormField;
+}
+
+/// The second factor used to complete the login
+enum SecondFactor {
+    Totp(mas_data_model::Totp),
+    RecoveryCode(mas_data_model::UserRecoveryCode),
+}
+
+#[tracing::instrument(name = "handlers.views.login_totp.get", skip_all, err)]
+pub(crate) async fn get(
+    mut rng: BoxRng,
+    clock: BoxClock,
+    PreferredLanguage(locale): PreferredLanguage,
+    State(templates): State<Templates>,
+    State(url_builder): State<UrlBuilder>,
+    mut repo: BoxRepository,
+    Query(query): Query<OptionalPostAuthAction>,
+    cookie_jar: CookieJar,
+) -> Result<Response, FancyError> {
+    let (csrf_token, cookie_jar) = cookie_jar.csrf_token(&clock, &mut rng);
+
+    // If there is no pending login, start over from the login page
+    let pending = PendingTotpLogin::load(&cookie_jar);
+    if pending.get(clock.now()).is_none() {
+        let destination = mas_router::Login::from(query.post_auth_action);
+        return Ok((cookie_jar, url_builder.redirect(&destination)).into_response());
+    }
+
+    let content = render(
+        locale,
+        LoginTotpContext::default(),
+        query,
+        csrf_token,
+        &mut repo,
+        &templates,
+    )
+    .await?;
+
+    Ok((cookie_jar, Html(content)).into_response())
+}
+
+#[tracing::instrument(name = "handlers.views.login_totp.post", skip_all, err)]
+pub(crate) async fn post(
+    mut rng: BoxRng,
+    clock: BoxClock,
+    PreferredLanguage(locale): PreferredLanguage,
+    State(templates): State<Templates>,
+    State(url_builder): State<UrlBuilder>,
+    State(encrypter): State<Encrypter>,
+    State(site_config): State<SiteConfig>,
+    State(login_throttle): State<Arc<dyn LoginThrottle>>,
+    mut repo: BoxRepository,
+    activity_tracker: BoundActivityTracker,
+    backchannel_logout: BackchannelLogoutDispatcher,
+    Query(query): Query<OptionalPostAuthAction>,
+    cookie_jar: CookieJar,
+    user_agent: Option<TypedHeader<headers::UserAgent>>,
+    Form(form): Form<ProtectedForm<LoginTotpForm>>,
+) -> Result<Response, FancyError> {
+    let user_agent = user_agent.map(|ua| UserAgent::parse(ua.as_str().to_owned()));
+    let form = cookie_jar.verify_form(&clock, form)?;
+
+    let (csrf_token, cookie_jar) = cookie_jar.csrf_token(&clock, &mut rng);
+
+    let pending = PendingTotpLogin::load(&cookie_jar);
+    let Some(payload) = pending.get(clock.now()) else {
+        let destination = mas_router::Login::from(query.post_auth_action);
+        return Ok((cookie_jar, url_builder.redirect(&destination)).into_response());
+    };
+    let remember_me = payload.remember_me;
+
+    let user = repo
+        .user()
+        .lookup(payload.user_id)
+        .await?
+        .filter(mas_data_model::User::is_valid);
+
+    // Make sure the password which was used is still the active one
+    let user_password = if let Some(user) = &user {
+        repo.user_password()
+            .active(user)
+            .await?
+            .filter(|password| password.id == payload.user_password_id)
+    } else {
+        None
+    };
+
+    let totp = if let Some(user) = &user {
+        repo.user_totp().lookup_for_user(user).await?
+    } else {
+        None
+    };
+
+    let (Some(user), Some(user_password), Some(totp)) = (user, user_password, totp) else {
+        // Something changed since the password step, start over
+        let cookie_jar = PendingTotpLogin::default().save(cookie_jar);
+        let destination = mas_router::Login::from(query.post_auth_action);
+        return Ok((cookie_jar, url_builder.redirect(&destination)).into_response());
+    };
+
+    // Don't even try to verify the code if there were too many failed attempts
+    // recently, so that the codes can't be brute-forced
+    let throttle_key = throttle_key(&user);
+    if let Err(retry_after) = login_throttle.attempt(&throttle_key, clock.now()) {
+        // Round up to the next second, so that retrying right on time works
+        let seconds = (retry_after + Duration::milliseconds(999)).num_seconds();
+        let state = form
+            .to_form_state()
+            .with_error_on_form(FormError::RateLimited {
+                retry_after: seconds.try_into().unwrap_or_default(),
+            });
+
+        let content = render(
+            locale,
+            LoginTotpContext::default().with_form_state(state),
+            query,
+            csrf_token,
+            &mut repo,
+            &templates,
+        )
+        .await?;
+
+        return Ok((cookie_jar, Html(content)).into_response());
+    }
+
+    // The code can either be a TOTP code, or one of the recovery codes
+    let factor = if totp::is_totp_code(&form.code) {
+        let secret = encrypter.decrypt_string(&totp.encrypted_secret)?;
+        if let Some(counter) = totp::verify(&secret, &form.code, clock.now()) {
+            // This fails if the code was already used
+            repo.user_totp()
+                .verify(totp, counter)
+                .await?
+                .map(SecondFactor::Totp)
+        } else {
+            None
+        }
+    } else {
+        let hashed_code = totp::hash_recovery_code(&form.code);
+        if let Some(recovery_code) = repo
+            .user_recovery_code()
+            .find_active(&user, &hashed_code)
+            .await?
+        {
+            // This fails if the code was used concurrently
+            repo.user_recovery_code()
+                .consume(&clock, recovery_code)
+                .await?
+                .map(SecondFactor::RecoveryCode)
+        } else {
+            None
+        }
+    };
+
+    let Some(factor) = factor else {
+        metrics::record_authentication_failure("totp");
+
+        let state = form
+            .to_form_state()
+            .with_error_on_field(LoginTotpFormField::Code, FieldError::Invalid);
+
+        let content = render(
+            locale,
+            LoginTotpContext::default().with_form_state(state),
+            query,
+            csrf_token,
+            &mut repo,
+            &templates,
+        )
+        .await?;
+
+        return Ok((cookie_jar, Html(content)).into_response());
+    };
+
+    login_throttle.record_success(&throttle_key);
+
+    // Start a new session. If it is rejected, nothing is saved, so the second
+    // factor can be used again
+    let (user_session, logouts) = match start_browser_session(
+        &mut rng,
+        &clock,
+        &mut repo,
+        &backchannel_logout,
+        &site_config,
+        &user,
+        user_agent,
+        activity_tracker.ip(),
+        remember_me,
+    )
+    .await
+    {
+        Ok(user_session) => user_session,
+        Err(StartBrowserSessionError::TooManySessions) => {
+            let state = form
+                .to_form_state()
+                .with_error_on_form(FormError::TooManySessions);
+
+            let content = render(
+                locale,
+                LoginTotpContext::default().with_form_state(state),
+                query,
+                csrf_token,
+                &mut repo,
+                &templates,
+            )
+            .await?;
+
+            return Ok((cookie_jar, Html(content)).into_response());
+        }
+        Err(StartBrowserSessionError::Repository(e)) => return Err(e.into()),
+    };
+
+    // And mark it as authenticated by both the password and the second factor
+    repo.browser_session()
+        .authenticate_with_password(&mut rng, &clock, &user_session, &user_password)
+        .await?;
+
+    match &factor {
+        SecondFactor::Totp(totp) => {
+            repo.browser_session()
+                .authenticate_with_totp(&mut rng, &clock, &user_session, totp)
+                .await?;
+        }
+        SecondFactor::RecoveryCode(recovery_code) => {
+            repo.browser_session()
+                .authenticate_with_recovery_code(&mut rng, &clock, &user_session, recovery_code)
+                .await?;
+        }
+    }
+
+    repo.audit_log()
+        .record(
+            &mut rng,
+            &clock,
+            AuditLogParams::new(AuditAction::UserLoggedIn)
+                .with_actor(&user)
+                .with_actor_browser_session(&user_session)
+                .with_target(&user),
+        )
+        .await?;
+
+    repo.save().await?;
+
+    backchannel_logout.dispatch(logouts);
+
+    activity_tracker
+        .record_browser_session(&clock, &user_session)
+        .await;
+
+    let cookie_jar = PendingTotpLogin::default().save(cookie_jar);
+    let cookie_jar = cookie_jar.set_session(&clock, &user_session, remember_me);
+    let reply = query.go_next(&url_builder);
+    Ok((cookie_jar, reply).into_response())
+}
+
+async fn render(
+    locale: DataLocale,
+    ctx: LoginTotpContext,
+    action: OptionalPostAuthAction,
+    csrf_token: CsrfToken,
+    repo: &mut impl RepositoryAccess,
+    templates: &Templates,
+) -> Result<String, FancyError> {
+    let next = action.load_context(repo).await?;
+    let ctx = if let Some(next) = next {
+        ctx.with_post_action(next)
+    } else {
+        ctx
+    };
+    let ctx = ctx.with_csrf(csrf_token.form_value()).with_language(locale);
+
+    let content = templates.render_login_totp(&ctx)?;
+    Ok(content)
+}
@@ -21,37 +21,62 @@ use mas_axum_utils::{
     csrf::{CsrfExt, ProtectedForm},
     FancyError, SessionInfoExt,
 };
+use mas_data_model::AuditAction;
 use mas_router::{PostAuthAction, UrlBuilder};
-use mas_storage::{user::BrowserSessionRepository, BoxClock, BoxRepository};
+use mas_storage::{
+    audit_log::AuditLogParams, user::BrowserSessionRepository, BoxClock, BoxRepository, BoxRng,
+};
 
-use crate::BoundActivityTracker;
+use crate::{BackchannelLogoutDispatcher, BoundActivityTracker};
 
 #[tracing::instrument(name = "handlers.views.logout.post", skip_all, err)]
 pub(crate) async fn post(
     clock: BoxClock,
+    mut rng: BoxRng,
     mut repo: BoxRepository,
     cookie_jar: CookieJar,
     State(url_builder): State<UrlBuilder>,
     activity_tracker: BoundActivityTracker,
+    backchannel_logout: BackchannelLogoutDispatcher,
     Form(form): Form<ProtectedForm<Option<PostAuthAction>>>,
 ) -> Result<impl IntoResponse, FancyError> {
     let form = cookie_jar.verify_form(&clock, form)?;
 
-    let (session_info, mut cookie_jar) = cookie_jar.session_info();
+    let (session_info, mut cookie_jar) = cookie_jar.session_info(&clock);
 
-    let maybe_session = session_info.load_session(&mut repo).await?;
+    let maybe_session = session_info.load_session(&clock, &mut repo).await?;
 
+    let mut logouts = Vec::new();
     if let Some(session) = maybe_session {
         activity_tracker
             .record_browser_session(&clock, &session)
             .await;
 
+        logouts = backchannel_logout
+            .prepare(&mut rng, &clock, &mut repo, &session)
+            .await?;
+
+        repo.audit_log()
+            .record(
+                &mut rng,
+                &clock,
+                AuditLogParams::new(AuditAction::UserLoggedOut)
+                    .with_actor(&session.user)
+                    .with_actor_browser_session(&session)
+                    .with_target(&session.user),
+            )
+            .await?;
+
         repo.browser_session().finish(&clock, session).await?;
-        cookie_jar = cookie_jar.update_session_info(&session_info.mark_session_ended());
+        cookie_jar = cookie_jar.update_session_info(&clock, &session_info.mark_session_ended());
     }
 
     repo.save().await?;
 
+    // Clients are notified in the background, so that they don't hold up the
+    // response
+    backchannel_logout.dispatch(logouts);
+
     let destination = if let Some(action) = form {
         action.go_next(&url_builder)
     } else {
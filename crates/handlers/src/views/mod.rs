@@ -16,7 +16,10 @@ pub mod account;
 pub mod app;
 pub mod index;
 pub mod login;
+pub mod login_totp;
 pub mod logout;
 pub mod reauth;
 pub mod register;
+pub mod reset_password;
+pub mod reset_password_request;
 pub mod shared;
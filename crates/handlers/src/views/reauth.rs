@@ -12,6 +12,8 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::sync::Arc;
+
 use anyhow::Context;
 use axum::{
     extract::{Form, Query, State},
@@ -33,7 +35,10 @@ use serde::Deserialize;
 use zeroize::Zeroizing;
 
 use super::shared::OptionalPostAuthAction;
-use crate::{passwords::PasswordManager, BoundActivityTracker, PreferredLanguage, SiteConfig};
+use crate::{
+    login_throttle::throttle_password_check, passwords::PasswordManager, BoundActivityTracker,
+    LoginThrottle, PreferredLanguage, SiteConfig,
+};
 
 #[derive(Deserialize, Debug)]
 pub(crate) struct ReauthForm {
@@ -61,9 +66,9 @@ pub(crate) async fn get(
     }
 
     let (csrf_token, cookie_jar) = cookie_jar.csrf_token(&clock, &mut rng);
-    let (session_info, cookie_jar) = cookie_jar.session_info();
+    let (session_info, cookie_jar) = cookie_jar.session_info(&clock);
 
-    let maybe_session = session_info.load_session(&mut repo).await?;
+    let maybe_session = session_info.load_session(&clock, &mut repo).await?;
 
     let Some(session) = maybe_session else {
         // If there is no session, redirect to the login screen, keeping the
@@ -98,6 +103,7 @@ pub(crate) async fn post(
     mut rng: BoxRng,
     clock: BoxClock,
     State(password_manager): State<PasswordManager>,
+    State(login_throttle): State<Arc<dyn LoginThrottle>>,
     State(url_builder): State<UrlBuilder>,
     State(site_config): State<SiteConfig>,
     mut repo: BoxRepository,
@@ -112,9 +118,9 @@ pub(crate) async fn post(
 
     let form = cookie_jar.verify_form(&clock, form)?;
 
-    let (session_info, cookie_jar) = cookie_jar.session_info();
+    let (session_info, cookie_jar) = cookie_jar.session_info(&clock);
 
-    let maybe_session = session_info.load_session(&mut repo).await?;
+    let maybe_session = session_info.load_session(&clock, &mut repo).await?;
 
     let Some(session) = maybe_session else {
         // If there is no session, redirect to the login screen, keeping the
@@ -133,15 +139,20 @@ pub(crate) async fn post(
     let password = Zeroizing::new(form.password.as_bytes().to_vec());
 
     // TODO: recover from errors
-    // Verify the password, and upgrade it on-the-fly if needed
-    let new_password_hash = password_manager
-        .verify_and_upgrade(
+    // Verify the password, and upgrade it on-the-fly if needed. Failures count
+    // towards the same throttle as the login page.
+    let new_password_hash = throttle_password_check(
+        &*login_throttle,
+        &clock,
+        &session.user.username,
+        password_manager.verify_and_upgrade(
             &mut rng,
             user_password.version,
             password,
             user_password.hashed_password.clone(),
-        )
-        .await?;
+        ),
+    )
+    .await?;
 
     let user_password = if let Some((version, new_password_hash)) = new_password_hash {
         // Save the upgraded password
@@ -164,7 +175,6 @@ pub(crate) async fn post(
         .authenticate_with_password(&mut rng, &clock, &session, &user_password)
         .await?;
 
-    let cookie_jar = cookie_jar.set_session(&session);
     repo.save().await?;
 
     let reply = query.go_next(&url_builder);
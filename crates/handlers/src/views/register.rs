@@ -12,7 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::str::FromStr;
+use std::{str::FromStr, sync::Arc};
 
 use axum::{
     extract::{Form, Query, State},
@@ -34,7 +34,7 @@ use mas_router::UrlBuilder;
 use mas_storage::{
     job::{JobRepositoryExt, ProvisionUserJob, VerifyEmailJob},
     user::{BrowserSessionRepository, UserEmailRepository, UserPasswordRepository, UserRepository},
-    BoxClock, BoxRepository, BoxRng, RepositoryAccess,
+    BoxClock, BoxRepository, BoxRng, Clock, RepositoryAccess,
 };
 use mas_templates::{
     FieldError, FormError, RegisterContext, RegisterFormField, TemplateContext, Templates,
@@ -44,7 +44,10 @@ use serde::{Deserialize, Serialize};
 use zeroize::Zeroizing;
 
 use super::shared::OptionalPostAuthAction;
-use crate::{passwords::PasswordManager, BoundActivityTracker, PreferredLanguage, SiteConfig};
+use crate::{
+    passwords::PasswordManager, session_limit::browser_session_expiry, BoundActivityTracker,
+    PreferredLanguage, PwnedPasswords, SiteConfig,
+};
 
 #[derive(Debug, Deserialize, Serialize)]
 pub(crate) struct RegisterForm {
@@ -73,9 +76,9 @@ pub(crate) async fn get(
     cookie_jar: CookieJar,
 ) -> Result<Response, FancyError> {
     let (csrf_token, cookie_jar) = cookie_jar.csrf_token(&clock, &mut rng);
-    let (session_info, cookie_jar) = cookie_jar.session_info();
+    let (session_info, cookie_jar) = cookie_jar.session_info(&clock);
 
-    let maybe_session = session_info.load_session(&mut repo).await?;
+    let maybe_session = session_info.load_session(&clock, &mut repo).await?;
 
     if maybe_session.is_some() {
         let reply = query.go_next(&url_builder);
@@ -109,6 +112,7 @@ pub(crate) async fn post(
     clock: BoxClock,
     PreferredLanguage(locale): PreferredLanguage,
     State(password_manager): State<PasswordManager>,
+    State(pwned_passwords): State<Arc<dyn PwnedPasswords>>,
     State(templates): State<Templates>,
     State(url_builder): State<UrlBuilder>,
     State(site_config): State<SiteConfig>,
@@ -205,6 +209,18 @@ pub(crate) async fn post(
             }
         }
 
+        // Only check the password against known data breaches if everything
+        // else is valid, to avoid querying the API needlessly
+        if state.is_valid() {
+            let count = pwned_passwords.breach_count(&form.password).await;
+            if count > 0 {
+                state.add_error_on_field(
+                    RegisterFormField::Password,
+                    FieldError::PasswordBreached { count },
+                );
+            }
+        }
+
         state
     };
 
@@ -244,9 +260,17 @@ pub(crate) async fn post(
 
     let next = mas_router::AccountVerifyEmail::new(user_email.id).and_maybe(query.post_auth_action);
 
+    let expires_at = browser_session_expiry(&site_config, clock.now(), false);
     let session = repo
         .browser_session()
-        .add(&mut rng, &clock, &user, user_agent)
+        .add(
+            &mut rng,
+            &clock,
+            &user,
+            user_agent,
+            activity_tracker.ip(),
+            Some(expires_at),
+        )
         .await?;
 
     repo.browser_session()
@@ -267,7 +291,7 @@ pub(crate) async fn post(
         .record_browser_session(&clock, &session)
         .await;
 
-    let cookie_jar = cookie_jar.set_session(&session);
+    let cookie_jar = cookie_jar.set_session(&clock, &session, false);
     Ok((cookie_jar, url_builder.redirect(&next)).into_response())
 }
 
@@ -297,12 +321,15 @@ mod tests {
         header::{CONTENT_TYPE, LOCATION},
         Request, StatusCode,
     };
+    use std::sync::Arc;
+
     use mas_router::Route;
     use sqlx::PgPool;
 
     use crate::{
         test_utils::{
-            init_tracing, test_site_config, CookieHelper, RequestBuilderExt, ResponseExt, TestState,
+            init_tracing, test_site_config, CookieHelper, MockPwnedPasswords, RequestBuilderExt,
+            ResponseExt, TestState,
         },
         SiteConfig,
     };
@@ -431,6 +458,54 @@ mod tests {
         assert!(response.body().contains("Password fields don't match"));
     }
 
+    /// When the password appeared in a data breach, it should give an error
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_register_password_breached(pool: PgPool) {
+        init_tracing();
+        let mut state = TestState::from_pool(pool).await.unwrap();
+        state.pwned_passwords = Arc::new(MockPwnedPasswords::new([("hunter2", 42)]));
+        let cookies = CookieHelper::new();
+
+        // Render the registration page and get the CSRF token
+        let request = Request::get(&*mas_router::Register::default().path_and_query()).empty();
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        cookies.save_cookies(&response);
+        response.assert_status(StatusCode::OK);
+        // Extract the CSRF token from the response body
+        let csrf_token = response
+            .body()
+            .split("name=\"csrf\" value=\"")
+            .nth(1)
+            .unwrap()
+            .split('\"')
+            .next()
+            .unwrap();
+
+        // Submit the registration form
+        let request = Request::post(&*mas_router::Register::default().path_and_query()).form(
+            serde_json::json!({
+                "csrf": csrf_token,
+                "username": "john",
+                "email": "john@example.com",
+                "password": "hunter2",
+                "password_confirm": "hunter2",
+                "accept_terms": "on",
+            }),
+        );
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        cookies.save_cookies(&response);
+        response.assert_status(StatusCode::OK);
+        assert!(response
+            .body()
+            .contains("This password appeared in a known data breach"));
+
+        // The user wasn't created
+        let mut repo = state.repository().await.unwrap();
+        assert!(!repo.user().exists("john").await.unwrap());
+    }
+
     #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
     async fn test_register_username_too_short(pool: PgPool) {
         init_tracing();
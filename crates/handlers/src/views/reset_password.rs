@@ -0,0 +1,582 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::Arc;
+
+use anyhow::Context;
+use axum::{
+    extract::{Form, Path, State},
+    http::StatusCode,
+    response::{Html, IntoResponse, Response},
+};
+use mas_axum_utils::{
+    cookies::CookieJar,
+    csrf::{CsrfExt, CsrfToken, ProtectedForm},
+    FancyError,
+};
+use mas_data_model::{
+    password_reset::{self, ResetError},
+    AuditAction, Device, SiteConfig, User, UserPasswordResetToken,
+};
+use mas_i18n::DataLocale;
+use mas_policy::Policy;
+use mas_router::UrlBuilder;
+use mas_storage::{
+    audit_log::AuditLogParams,
+    compat::{CompatSessionFilter, CompatSessionRepository},
+    job::{DeleteDeviceJob, JobRepositoryExt},
+    oauth2::{OAuth2SessionFilter, OAuth2SessionRepository},
+    user::{
+        BrowserSessionRepository, UserPasswordRepository, UserPasswordResetTokenRepository,
+        UserRepository,
+    },
+    BoxClock, BoxRepository, BoxRng, Clock, Pagination, RepositoryAccess,
+};
+use mas_templates::{
+    FieldError, FormError, FormState, ResetPasswordContext, ResetPasswordFormField,
+    TemplateContext, Templates, ToFormState,
+};
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroizing;
+
+use crate::{
+    oauth2::backchannel_logout::PendingLogout, passwords::PasswordManager,
+    BackchannelLogoutDispatcher, PreferredLanguage, PwnedPasswords,
+};
+
+#[derive(Debug, Deserialize, Serialize)]
+pub(crate) struct ResetForm {
+    new_password: String,
+    new_password_confirm: String,
+}
+
+impl ToFormState for ResetForm {
+    type Field = ResetPasswordFormField;
+}
+
+impl From<ResetError> for FormError {
+    fn from(e: ResetError) -> Self {
+        match e {
+            ResetError::UnknownToken | ResetError::AlreadyUsed => Self::InvalidResetToken,
+            ResetError::ExpiredToken => Self::ExpiredResetToken,
+        }
+    }
+}
+
+#[tracing::instrument(name = "handlers.views.reset_password.get", skip_all, err)]
+pub(crate) async fn get(
+    mut rng: BoxRng,
+    clock: BoxClock,
+    PreferredLanguage(locale): PreferredLanguage,
+    State(templates): State<Templates>,
+    State(url_builder): State<UrlBuilder>,
+    State(site_config): State<SiteConfig>,
+    mut repo: BoxRepository,
+    Path(token): Path<String>,
+    cookie_jar: CookieJar,
+) -> Result<Response, FancyError> {
+    if !site_config.password_change_allowed {
+        return Ok(url_builder
+            .redirect(&mas_router::Login::default())
+            .into_response());
+    }
+
+    let (csrf_token, cookie_jar) = cookie_jar.csrf_token(&clock, &mut rng);
+
+    // Check the token upfront, so that the user knows before choosing a new
+    // password
+    let mut form_state = FormState::default();
+    if let Err(e) = find_token(&mut repo, &clock, &token).await? {
+        form_state.add_error_on_form(e.into());
+    }
+
+    let content = render(locale, form_state, csrf_token, &templates)?;
+
+    Ok((cookie_jar, Html(content)).into_response())
+}
+
+#[tracing::instrument(name = "handlers.views.reset_password.post", skip_all, err)]
+pub(crate) async fn post(
+    mut rng: BoxRng,
+    clock: BoxClock,
+    PreferredLanguage(locale): PreferredLanguage,
+    State(password_manager): State<PasswordManager>,
+    State(pwned_passwords): State<Arc<dyn PwnedPasswords>>,
+    State(templates): State<Templates>,
+    State(url_builder): State<UrlBuilder>,
+    State(site_config): State<SiteConfig>,
+    mut policy: Policy,
+    mut repo: BoxRepository,
+    backchannel_logout: BackchannelLogoutDispatcher,
+    Path(token): Path<String>,
+    cookie_jar: CookieJar,
+    Form(form): Form<ProtectedForm<ResetForm>>,
+) -> Result<Response, FancyError> {
+    if !site_config.password_change_allowed {
+        return Ok(StatusCode::METHOD_NOT_ALLOWED.into_response());
+    }
+
+    let form = cookie_jar.verify_form(&clock, form)?;
+
+    let (csrf_token, cookie_jar) = cookie_jar.csrf_token(&clock, &mut rng);
+
+    let mut state = form.to_form_state();
+
+    let reset_token = match find_token(&mut repo, &clock, &token).await? {
+        Ok(reset_token) => reset_token,
+        Err(e) => {
+            state.add_error_on_form(e.into());
+            let content = render(locale, state, csrf_token, &templates)?;
+            return Ok((cookie_jar, Html(content)).into_response());
+        }
+    };
+
+    let user = repo
+        .user()
+        .lookup(reset_token.user_id)
+        .await?
+        .context("could not load user")?;
+
+    // Locked users can't get their account back by resetting their password
+    if !user.is_valid() {
+        state.add_error_on_form(ResetError::UnknownToken.into());
+    }
+
+    if form.new_password.is_empty() {
+        state.add_error_on_field(ResetPasswordFormField::NewPassword, FieldError::Required);
+    }
+
+    if form.new_password_confirm.is_empty() {
+        state.add_error_on_field(
+            ResetPasswordFormField::NewPasswordConfirm,
+            FieldError::Required,
+        );
+    }
+
+    if form.new_password != form.new_password_confirm {
+        state.add_error_on_form(FormError::PasswordMismatch);
+        state.add_error_on_field(ResetPasswordFormField::NewPassword, FieldError::Unspecified);
+        state.add_error_on_field(
+            ResetPasswordFormField::NewPasswordConfirm,
+            FieldError::Unspecified,
+        );
+    }
+
+    let res = policy.evaluate_password(&form.new_password).await?;
+    for violation in res.violations {
+        state.add_error_on_field(
+            ResetPasswordFormField::NewPassword,
+            FieldError::Policy {
+                message: violation.msg,
+            },
+        );
+    }
+
+    // Only check the password against known data breaches if everything else
+    // is valid, to avoid querying the API needlessly
+    if state.is_valid() {
+        let count = pwned_passwords.breach_count(&form.new_password).await;
+        if count > 0 {
+            state.add_error_on_field(
+                ResetPasswordFormField::NewPassword,
+                FieldError::PasswordBreached { count },
+            );
+        }
+    }
+
+    if !state.is_valid() {
+        let content = render(locale, state, csrf_token, &templates)?;
+        return Ok((cookie_jar, Html(content)).into_response());
+    }
+
+    // Consuming the token only succeeds once, even with concurrent requests.
+    // If it fails, the whole transaction is rolled back.
+    repo.user_password_reset_token()
+        .consume(&clock, reset_token)
+        .await?;
+
+    let new_password = Zeroizing::new(form.new_password.into_bytes());
+    let (version, hashed_password) = password_manager.hash(&mut rng, new_password).await?;
+    repo.user_password()
+        .add(&mut rng, &clock, &user, version, hashed_password, None)
+        .await?;
+
+    repo.audit_log()
+        .record(
+            &mut rng,
+            &clock,
+            AuditLogParams::new(AuditAction::PasswordChanged)
+                .with_target(&user)
+                .with_metadata(serde_json::json!({ "via": "password_reset" })),
+        )
+        .await?;
+
+    let logouts = end_all_sessions(&mut rng, &clock, &mut repo, &backchannel_logout, &user).await?;
+
+    repo.save().await?;
+
+    backchannel_logout.dispatch(logouts);
+
+    Ok((
+        cookie_jar,
+        url_builder.redirect(&mas_router::Login::default()),
+    )
+        .into_response())
+}
+
+/// Find the stored token matching the token given by the user, and check that
+/// it can still be used
+async fn find_token(
+    repo: &mut BoxRepository,
+    clock: &impl Clock,
+    token: &str,
+) -> Result<Result<UserPasswordResetToken, ResetError>, FancyError> {
+    let (id, secret) = match password_reset::parse_token(token) {
+        Ok(parts) => parts,
+        Err(e) => return Ok(Err(e)),
+    };
+
+    let Some(reset_token) = repo.user_password_reset_token().lookup(id).await? else {
+        return Ok(Err(ResetError::UnknownToken));
+    };
+
+    Ok(password_reset::verify(&reset_token, secret, clock.now()).map(|()| reset_token))
+}
+
+/// End all the sessions of the user, so that whoever had access to the account
+/// before the reset loses it
+///
+/// Returns the back-channel logouts to dispatch once the change is saved
+async fn end_all_sessions(
+    rng: &mut BoxRng,
+    clock: &impl Clock,
+    repo: &mut BoxRepository,
+    backchannel_logout: &BackchannelLogoutDispatcher,
+    user: &User,
+) -> Result<Vec<PendingLogout>, FancyError> {
+    let logouts = backchannel_logout
+        .prepare_for_user(rng, clock, repo, user)
+        .await?;
+
+    repo.browser_session()
+        .finish_all_for_user(clock, user)
+        .await?;
+
+    let filter = OAuth2SessionFilter::new().for_user(user).active_only();
+    loop {
+        let page = repo
+            .oauth2_session()
+            .list(filter, Pagination::first(100))
+            .await?;
+
+        for session in page.edges {
+            // Delete the devices associated with the session on the homeserver
+            for scope in &*session.scope {
+                if let Some(device) = Device::from_scope_token(scope) {
+                    repo.job()
+                        .schedule_job(DeleteDeviceJob::new(user, &device))
+                        .await?;
+                }
+            }
+
+            repo.oauth2_session().finish(clock, session).await?;
+        }
+
+        if !page.has_next_page {
+            break;
+        }
+    }
+
+    let filter = CompatSessionFilter::new().for_user(user).active_only();
+    loop {
+        let page = repo
+            .compat_session()
+            .list(filter, Pagination::first(100))
+            .await?;
+
+        for (session, _) in page.edges {
+            repo.job()
+                .schedule_job(DeleteDeviceJob::new(user, &session.device))
+                .await?;
+
+            repo.compat_session().finish(clock, session).await?;
+        }
+
+        if !page.has_next_page {
+            break;
+        }
+    }
+
+    Ok(logouts)
+}
+
+fn render(
+    locale: DataLocale,
+    form_state: FormState<ResetPasswordFormField>,
+    csrf_token: CsrfToken,
+    templates: &Templates,
+) -> Result<String, FancyError> {
+    let ctx = ResetPasswordContext::default()
+        .with_form_state(form_state)
+        .with_csrf(csrf_token.form_value())
+        .with_language(locale);
+
+    let content = templates.render_reset_password(&ctx)?;
+    Ok(content)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use chrono::Duration;
+    use hyper::{header::LOCATION, Request, StatusCode};
+    use mas_data_model::{password_reset, User};
+    use mas_router::Route;
+    use mas_storage::{
+        user::{
+            BrowserSessionFilter, BrowserSessionRepository, UserPasswordRepository,
+            UserPasswordResetTokenRepository, UserRepository,
+        },
+        Clock, RepositoryAccess,
+    };
+    use sqlx::PgPool;
+    use zeroize::Zeroizing;
+
+    use crate::test_utils::{
+        init_tracing, CookieHelper, MockPwnedPasswords, RequestBuilderExt, ResponseExt, TestState,
+    };
+
+    /// Provision a user with a password and an active browser session, and
+    /// create a password reset token for them
+    async fn setup(state: &TestState) -> (User, String) {
+        let mut rng = state.rng();
+        let mut repo = state.repository().await.unwrap();
+
+        let user = repo
+            .user()
+            .add(&mut rng, &state.clock, "john".to_owned())
+            .await
+            .unwrap();
+        let (version, hash) = state
+            .password_manager
+            .hash(&mut rng, Zeroizing::new(b"hunter2".to_vec()))
+            .await
+            .unwrap();
+        repo.user_password()
+            .add(&mut rng, &state.clock, &user, version, hash, None)
+            .await
+            .unwrap();
+        repo.browser_session()
+            .add(&mut rng, &state.clock, &user, None, None, None)
+            .await
+            .unwrap();
+
+        let secret = password_reset::generate_secret(&mut rng);
+        let reset_token = repo
+            .user_password_reset_token()
+            .add(
+                &mut rng,
+                &state.clock,
+                &user,
+                password_reset::hash_secret(&secret),
+                Duration::try_hours(1).unwrap(),
+            )
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        (user, password_reset::format_token(reset_token.id, &secret))
+    }
+
+    /// Render the reset page, returning the CSRF token and the response body
+    async fn render_page(
+        state: &TestState,
+        cookies: &CookieHelper,
+        token: &str,
+    ) -> (String, String) {
+        let path = mas_router::ResetPassword(token.to_owned()).path_and_query();
+        let request = cookies.with_cookies(Request::get(&*path).empty());
+        let response = state.request(request).await;
+        cookies.save_cookies(&response);
+        response.assert_status(StatusCode::OK);
+        let body = response.body().clone();
+        let csrf_token = body
+            .split("name=\"csrf\" value=\"")
+            .nth(1)
+            .unwrap()
+            .split('\"')
+            .next()
+            .unwrap()
+            .to_owned();
+        (csrf_token, body)
+    }
+
+    async fn submit(
+        state: &TestState,
+        cookies: &CookieHelper,
+        token: &str,
+        csrf_token: &str,
+    ) -> hyper::Response<String> {
+        let path = mas_router::ResetPassword(token.to_owned()).path_and_query();
+        let request = Request::post(&*path).form(serde_json::json!({
+            "csrf": csrf_token,
+            "new_password": "correct horse battery staple",
+            "new_password_confirm": "correct horse battery staple",
+        }));
+        let request = cookies.with_cookies(request);
+        state.request(request).await
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_reset_password(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let cookies = CookieHelper::new();
+        let (user, token) = setup(&state).await;
+
+        let (csrf_token, body) = render_page(&state, &cookies, &token).await;
+        assert!(!body.contains("password reset link"), "{body}");
+
+        let response = submit(&state, &cookies, &token, &csrf_token).await;
+        response.assert_status(StatusCode::SEE_OTHER);
+        response.assert_header_value(LOCATION, "/login");
+
+        // The new password was set
+        let mut repo = state.repository().await.unwrap();
+        let password = repo.user_password().active(&user).await.unwrap().unwrap();
+        state
+            .password_manager
+            .verify(
+                password.version,
+                Zeroizing::new(b"correct horse battery staple".to_vec()),
+                password.hashed_password,
+            )
+            .await
+            .unwrap();
+
+        // The existing sessions were ended
+        let active_sessions = repo
+            .browser_session()
+            .count(
+                BrowserSessionFilter::new()
+                    .for_user(&user)
+                    .active_only(state.clock.now()),
+            )
+            .await
+            .unwrap();
+        assert_eq!(active_sessions, 0);
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_reset_password_expired(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let cookies = CookieHelper::new();
+        let (user, token) = setup(&state).await;
+
+        state.clock.advance(Duration::try_hours(1).unwrap());
+
+        let (csrf_token, body) = render_page(&state, &cookies, &token).await;
+        assert!(
+            body.contains("This password reset link has expired"),
+            "{body}"
+        );
+
+        let response = submit(&state, &cookies, &token, &csrf_token).await;
+        response.assert_status(StatusCode::OK);
+        assert!(response
+            .body()
+            .contains("This password reset link has expired"));
+
+        // The password and the sessions were left untouched
+        let mut repo = state.repository().await.unwrap();
+        let password = repo.user_password().active(&user).await.unwrap().unwrap();
+        state
+            .password_manager
+            .verify(
+                password.version,
+                Zeroizing::new(b"hunter2".to_vec()),
+                password.hashed_password,
+            )
+            .await
+            .unwrap();
+        let active_sessions = repo
+            .browser_session()
+            .count(
+                BrowserSessionFilter::new()
+                    .for_user(&user)
+                    .active_only(state.clock.now()),
+            )
+            .await
+            .unwrap();
+        assert_eq!(active_sessions, 1);
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_reset_password_reuse(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let cookies = CookieHelper::new();
+        let (_user, token) = setup(&state).await;
+
+        let (csrf_token, _) = render_page(&state, &cookies, &token).await;
+        let response = submit(&state, &cookies, &token, &csrf_token).await;
+        response.assert_status(StatusCode::SEE_OTHER);
+
+        // The token can't be used a second time
+        let response = submit(&state, &cookies, &token, &csrf_token).await;
+        response.assert_status(StatusCode::OK);
+        assert!(response
+            .body()
+            .contains("This password reset link is invalid or was already used"));
+
+        // Neither can a token with a wrong secret
+        let (id, _) = token.split_once('.').unwrap();
+        let (_, body) = render_page(&state, &cookies, &format!("{id}.wrong")).await;
+        assert!(body.contains("This password reset link is invalid or was already used"));
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_reset_password_breached(pool: PgPool) {
+        init_tracing();
+        let mut state = TestState::from_pool(pool).await.unwrap();
+        state.pwned_passwords = Arc::new(MockPwnedPasswords::new([(
+            "correct horse battery staple",
+            42,
+        )]));
+        let cookies = CookieHelper::new();
+        let (user, token) = setup(&state).await;
+
+        let (csrf_token, _) = render_page(&state, &cookies, &token).await;
+        let response = submit(&state, &cookies, &token, &csrf_token).await;
+        response.assert_status(StatusCode::OK);
+        assert!(response
+            .body()
+            .contains("This password appeared in a known data breach"));
+
+        // The password was left untouched
+        let mut repo = state.repository().await.unwrap();
+        let password = repo.user_password().active(&user).await.unwrap().unwrap();
+        state
+            .password_manager
+            .verify(
+                password.version,
+                Zeroizing::new(b"hunter2".to_vec()),
+                password.hashed_password,
+            )
+            .await
+            .unwrap();
+    }
+}
@@ -0,0 +1,337 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{net::IpAddr, sync::Arc};
+
+use axum::{
+    extract::{Form, State},
+    response::{Html, IntoResponse, Response},
+};
+use chrono::Duration;
+use hyper::StatusCode;
+use mas_axum_utils::{
+    cookies::CookieJar,
+    csrf::{CsrfExt, CsrfToken, ProtectedForm},
+    FancyError,
+};
+use mas_data_model::SiteConfig;
+use mas_i18n::DataLocale;
+use mas_router::UrlBuilder;
+use mas_storage::{
+    job::{JobRepositoryExt, SendPasswordResetEmailJob},
+    user::{UserEmailFilter, UserEmailRepository, UserRepository},
+    BoxClock, BoxRepository, BoxRng, Pagination, RepositoryAccess,
+};
+use mas_templates::{
+    FieldError, FormError, FormState, ResetPasswordRequestContext, ResetPasswordRequestFormField,
+    TemplateContext, Templates, ToFormState,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{BoundActivityTracker, PreferredLanguage, RateLimit, RateLimiter};
+
+/// How many password resets can be asked for to a single address, so that
+/// this can't be used to flood someone's inbox
+const PER_ADDRESS_LIMIT: RateLimit = RateLimit {
+    max_requests: 3,
+    window: Duration::microseconds(60 * 60 * 1000 * 1000),
+};
+
+/// How many password resets can be asked for from a single IP address
+const PER_IP_LIMIT: RateLimit = RateLimit {
+    max_requests: 10,
+    window: Duration::microseconds(60 * 60 * 1000 * 1000),
+};
+
+/// The keys under which a password reset request is rate-limited.
+///
+/// Requests are only limited by IP address if it is known, so that clients
+/// without one don't share a single limit.
+fn rate_limit_keys(email: &str, ip: Option<IpAddr>) -> Vec<(String, RateLimit)> {
+    let mut keys = vec![(
+        format!("password_reset:email:{}", email.to_lowercase()),
+        PER_ADDRESS_LIMIT,
+    )];
+
+    if let Some(ip) = ip {
+        keys.push((format!("password_reset:ip:{ip}"), PER_IP_LIMIT));
+    }
+
+    keys
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub(crate) struct RequestForm {
+    email: String,
+}
+
+impl ToFormState for RequestForm {
+    type Field = ResetPasswordRequestFormField;
+}
+
+#[tracing::instrument(name = "handlers.views.reset_password_request.get", skip_all, err)]
+pub(crate) async fn get(
+    mut rng: BoxRng,
+    clock: BoxClock,
+    PreferredLanguage(locale): PreferredLanguage,
+    State(templates): State<Templates>,
+    State(url_builder): State<UrlBuilder>,
+    State(site_config): State<SiteConfig>,
+    cookie_jar: CookieJar,
+) -> Result<Response, FancyError> {
+    if !site_config.password_reset_enabled {
+        return Ok(url_builder
+            .redirect(&mas_router::Login::default())
+            .into_response());
+    }
+
+    let (csrf_token, cookie_jar) = cookie_jar.csrf_token(&clock, &mut rng);
+
+    let ctx = ResetPasswordRequestContext::default();
+    let content = render(locale, ctx, csrf_token, &templates)?;
+
+    Ok((cookie_jar, Html(content)).into_response())
+}
+
+#[tracing::instrument(name = "handlers.views.reset_password_request.post", skip_all, err)]
+pub(crate) async fn post(
+    mut rng: BoxRng,
+    clock: BoxClock,
+    PreferredLanguage(locale): PreferredLanguage,
+    State(templates): State<Templates>,
+    State(site_config): State<SiteConfig>,
+    State(rate_limiter): State<Arc<dyn RateLimiter>>,
+    activity_tracker: BoundActivityTracker,
+    mut repo: BoxRepository,
+    cookie_jar: CookieJar,
+    Form(form): Form<ProtectedForm<RequestForm>>,
+) -> Result<Response, FancyError> {
+    if !site_config.password_reset_enabled {
+        return Ok(StatusCode::METHOD_NOT_ALLOWED.into_response());
+    }
+
+    let form = cookie_jar.verify_form(&clock, form)?;
+    let (csrf_token, cookie_jar) = cookie_jar.csrf_token(&clock, &mut rng);
+
+    let email = form.email.trim();
+    if email.is_empty() {
+        let form_state = form
+            .to_form_state()
+            .with_error_on_field(ResetPasswordRequestFormField::Email, FieldError::Required);
+        let ctx = ResetPasswordRequestContext::default().with_form_state(form_state);
+        let content = render(locale, ctx, csrf_token, &templates)?;
+        return Ok((cookie_jar, Html(content)).into_response());
+    }
+
+    let rate_limit_keys = rate_limit_keys(email, activity_tracker.ip());
+    let retry_after = rate_limit_keys
+        .iter()
+        .filter_map(|(key, limit)| rate_limiter.hit(key, *limit, clock.now()).err())
+        .max();
+    if let Some(retry_after) = retry_after {
+        // Round up to the next second, so that retrying right on time works
+        let seconds = (retry_after + Duration::milliseconds(999)).num_seconds();
+        let form_state = form
+            .to_form_state()
+            .with_error_on_form(FormError::RateLimited {
+                retry_after: seconds.try_into().unwrap_or_default(),
+            });
+        let ctx = ResetPasswordRequestContext::default().with_form_state(form_state);
+        let content = render(locale, ctx, csrf_token, &templates)?;
+        return Ok((cookie_jar, Html(content)).into_response());
+    }
+
+    // Only verified addresses can be used to get an account back, and the same
+    // address may have been verified by multiple users
+    let page = repo
+        .user_email()
+        .list(
+            UserEmailFilter::new().for_email(email).verified_only(),
+            Pagination::first(10),
+        )
+        .await?;
+
+    let expires_at = clock.now() + site_config.password_reset_token_ttl;
+    for user_email in page.edges {
+        let Some(user) = repo.user().lookup(user_email.user_id).await? else {
+            continue;
+        };
+
+        // Locked users can't get their account back by resetting their password
+        if !user.is_valid() {
+            continue;
+        }
+
+        repo.job()
+            .schedule_job(
+                SendPasswordResetEmailJob::new(&user_email, expires_at)
+                    .with_language(locale.to_string()),
+            )
+            .await?;
+    }
+
+    repo.save().await?;
+
+    // Whether an account matched or not, show the same page, so that this can't
+    // be used to find out which addresses are registered
+    let ctx = ResetPasswordRequestContext::default().sent();
+    let content = render(locale, ctx, csrf_token, &templates)?;
+
+    Ok((cookie_jar, Html(content)).into_response())
+}
+
+fn render(
+    locale: DataLocale,
+    ctx: ResetPasswordRequestContext,
+    csrf_token: CsrfToken,
+    templates: &Templates,
+) -> Result<String, FancyError> {
+    let ctx = ctx.with_csrf(csrf_token.form_value()).with_language(locale);
+
+    let content = templates.render_reset_password_request(&ctx)?;
+    Ok(content)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use hyper::{Request, StatusCode};
+    use mas_router::SimpleRoute;
+    use mas_storage::{
+        user::{UserEmailRepository, UserRepository},
+        RepositoryAccess,
+    };
+    use sqlx::PgPool;
+
+    use crate::test_utils::{
+        init_tracing, CookieHelper, RequestBuilderExt, ResponseExt, TestState,
+    };
+
+    /// Count the password reset emails which were scheduled to be sent
+    async fn scheduled_emails(state: &TestState) -> i64 {
+        sqlx::query_scalar("SELECT COUNT(*) FROM apalis.jobs WHERE job_type = $1")
+            .bind("send-password-reset-email")
+            .fetch_one(&state.pool)
+            .await
+            .unwrap()
+    }
+
+    /// Request a password reset link for the given email address, returning
+    /// the response body
+    async fn request_reset(state: &TestState, cookies: &CookieHelper, email: &str) -> String {
+        let request =
+            cookies.with_cookies(Request::get(mas_router::ResetPasswordRequest::PATH).empty());
+        let response = state.request(request).await;
+        cookies.save_cookies(&response);
+        response.assert_status(StatusCode::OK);
+        let csrf_token = response
+            .body()
+            .split("name=\"csrf\" value=\"")
+            .nth(1)
+            .unwrap()
+            .split('\"')
+            .next()
+            .unwrap()
+            .to_owned();
+
+        let request =
+            Request::post(mas_router::ResetPasswordRequest::PATH).form(serde_json::json!({
+                "csrf": csrf_token,
+                "email": email,
+            }));
+        let request = cookies.with_cookies(request);
+        let response = state.request(request).await;
+        cookies.save_cookies(&response);
+        response.assert_status(StatusCode::OK);
+        response.body().clone()
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_reset_password_request(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let cookies = CookieHelper::new();
+
+        let mut rng = state.rng();
+        let mut repo = state.repository().await.unwrap();
+        let user = repo
+            .user()
+            .add(&mut rng, &state.clock, "john".to_owned())
+            .await
+            .unwrap();
+        let verified = repo
+            .user_email()
+            .add(&mut rng, &state.clock, &user, "john@example.com".to_owned())
+            .await
+            .unwrap();
+        repo.user_email()
+            .mark_as_verified(&state.clock, verified)
+            .await
+            .unwrap();
+        repo.user_email()
+            .add(
+                &mut rng,
+                &state.clock,
+                &user,
+                "pending@example.com".to_owned(),
+            )
+            .await
+            .unwrap();
+        repo.save().await.unwrap();
+
+        // An unknown address gets the same response as a known one, but no
+        // email is sent
+        let unknown = request_reset(&state, &cookies, "unknown@example.com").await;
+        assert_eq!(scheduled_emails(&state).await, 0);
+
+        // Neither is one to an address which wasn't verified
+        let pending = request_reset(&state, &cookies, "pending@example.com").await;
+        assert_eq!(scheduled_emails(&state).await, 0);
+
+        let known = request_reset(&state, &cookies, "john@example.com").await;
+        assert_eq!(scheduled_emails(&state).await, 1);
+
+        // The address is matched whatever its case
+        request_reset(&state, &cookies, " John@Example.com").await;
+        assert_eq!(scheduled_emails(&state).await, 2);
+
+        assert!(known.contains("we sent it a link to reset your password"));
+        assert!(unknown.contains("we sent it a link to reset your password"));
+        assert!(pending.contains("we sent it a link to reset your password"));
+    }
+
+    #[sqlx::test(migrator = "mas_storage_pg::MIGRATOR")]
+    async fn test_reset_password_request_rate_limited(pool: PgPool) {
+        init_tracing();
+        let state = TestState::from_pool(pool).await.unwrap();
+        let cookies = CookieHelper::new();
+
+        for _ in 0..3 {
+            let body = request_reset(&state, &cookies, "john@example.com").await;
+            assert!(body.contains("we sent it a link to reset your password"));
+        }
+
+        // The address can't get more requests for an hour, whatever its case
+        let body = request_reset(&state, &cookies, "John@example.com").await;
+        assert!(body.contains("try again in 3600 seconds"));
+
+        // Other addresses are not affected
+        let body = request_reset(&state, &cookies, "jane@example.com").await;
+        assert!(body.contains("we sent it a link to reset your password"));
+
+        state.clock.advance(Duration::try_hours(1).unwrap());
+        let body = request_reset(&state, &cookies, "john@example.com").await;
+        assert!(body.contains("we sent it a link to reset your password"));
+    }
+}
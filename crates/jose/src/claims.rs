@@ -506,6 +506,7 @@ mod oidc_core {
     pub const NONCE: Claim<String, Equality<str>> = Claim::new("nonce");
     pub const AT_HASH: Claim<String, TokenHash> = Claim::new("at_hash");
     pub const C_HASH: Claim<String, TokenHash> = Claim::new("c_hash");
+    pub const AMR: Claim<Vec<String>> = Claim::new("amr");
 
     pub const NAME: Claim<String> = Claim::new("name");
     pub const GIVEN_NAME: Claim<String> = Claim::new("given_name");
@@ -533,7 +534,29 @@ mod oidc_core {
     pub const UPDATED_AT: Claim<Timestamp> = Claim::new("updated_at");
 }
 
-pub use self::{oidc_core::*, rfc7519::*};
+/// Claims defined in OIDC.FrontChannel sec. 3 and OIDC.BackChannel sec. 2.4
+/// <https://openid.net/specs/openid-connect-frontchannel-1_0.html#ClaimsContents>
+/// <https://openid.net/specs/openid-connect-backchannel-1_0.html#LogoutToken>
+mod oidc_session {
+    use std::collections::HashMap;
+
+    use super::Claim;
+
+    pub const SID: Claim<String> = Claim::new("sid");
+    pub const EVENTS: Claim<HashMap<String, serde_json::Value>> = Claim::new("events");
+}
+
+/// Claims defined in RFC8693 sec. 4.2 and 4.3, used in JWT access tokens
+/// <https://www.rfc-editor.org/rfc/rfc8693.html#section-4.2>
+/// <https://www.rfc-editor.org/rfc/rfc9068.html#section-2.2>
+mod rfc8693 {
+    use super::Claim;
+
+    pub const SCOPE: Claim<String> = Claim::new("scope");
+    pub const CLIENT_ID: Claim<String> = Claim::new("client_id");
+}
+
+pub use self::{oidc_core::*, oidc_session::*, rfc7519::*, rfc8693::*};
 
 #[cfg(test)]
 mod tests {
@@ -17,6 +17,7 @@ use mas_iana::jose::{
 };
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 
 use super::ParametersInfo;
 use crate::base64::Base64UrlNoPad;
@@ -58,6 +59,36 @@ impl JsonWebKeyPublicParameters {
             _ => None,
         }
     }
+
+    /// Compute the base64url-encoded SHA-256 thumbprint of this key, as
+    /// defined by [RFC7638]
+    ///
+    /// [RFC7638]: https://www.rfc-editor.org/rfc/rfc7638
+    #[must_use]
+    pub fn thumbprint_sha256(&self) -> String {
+        // The required members, in lexicographic order and without whitespace
+        let canonical = match self {
+            Self::Rsa(params) => format!(
+                r#"{{"e":"{}","kty":"RSA","n":"{}"}}"#,
+                params.e.encode(),
+                params.n.encode(),
+            ),
+            Self::Ec(params) => format!(
+                r#"{{"crv":"{}","kty":"EC","x":"{}","y":"{}"}}"#,
+                params.crv,
+                params.x.encode(),
+                params.y.encode(),
+            ),
+            Self::Okp(params) => format!(
+                r#"{{"crv":"{}","kty":"OKP","x":"{}"}}"#,
+                params.crv,
+                params.x.encode(),
+            ),
+        };
+
+        let digest = Sha256::digest(canonical.as_bytes());
+        Base64UrlNoPad::new(digest.to_vec()).encode()
+    }
 }
 
 impl ParametersInfo for JsonWebKeyPublicParameters {
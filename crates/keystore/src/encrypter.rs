@@ -18,12 +18,43 @@ use aead::Aead;
 use base64ct::{Base64, Encoding};
 use chacha20poly1305::{ChaCha20Poly1305, KeyInit};
 use generic_array::GenericArray;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
 use thiserror::Error;
 
+/// Separates the key ID from the payload in self-contained encrypted strings
+const KEY_ID_SEPARATOR: char = ':';
+
+/// A single encryption key, along with its short identifier
+#[derive(Clone)]
+struct Key {
+    id: String,
+    aead: ChaCha20Poly1305,
+}
+
+impl Key {
+    fn new(key: &[u8; 32]) -> Self {
+        let aead = ChaCha20Poly1305::new(GenericArray::from_slice(key));
+
+        // The key ID is derived from the key with a keyed hash, which doesn't
+        // reveal anything about the key itself
+        let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any size");
+        mac.update(b"key-id");
+        let digest = mac.finalize().into_bytes();
+        let id = Base64::encode_string(&digest[..6]);
+
+        Self { id, aead }
+    }
+}
+
 /// Helps encrypting and decrypting data
+///
+/// Payloads are always encrypted with the current key. Previous keys can be
+/// added with [`Encrypter::with_previous_key`] so that payloads encrypted
+/// before a key rotation can still be decrypted until they get re-encrypted.
 #[derive(Clone)]
 pub struct Encrypter {
-    aead: Arc<ChaCha20Poly1305>,
+    keys: Arc<[Key]>,
 }
 
 #[derive(Debug, Error)]
@@ -32,16 +63,39 @@ pub enum DecryptError {
     Aead(#[from] aead::Error),
     Base64(#[from] base64ct::Error),
     Shape,
+    UnknownKey,
 }
 
 impl Encrypter {
     /// Creates an [`Encrypter`] out of an encryption key
     #[must_use]
     pub fn new(key: &[u8; 32]) -> Self {
-        let key = GenericArray::from_slice(key);
-        let aead = ChaCha20Poly1305::new(key);
-        let aead = Arc::new(aead);
-        Self { aead }
+        Self {
+            keys: Arc::new([Key::new(key)]),
+        }
+    }
+
+    /// Add a previous encryption key, which will only be used to decrypt
+    /// payloads
+    #[must_use]
+    pub fn with_previous_key(self, key: &[u8; 32]) -> Self {
+        let keys = self
+            .keys
+            .iter()
+            .cloned()
+            .chain(std::iter::once(Key::new(key)))
+            .collect();
+        Self { keys }
+    }
+
+    fn current(&self) -> &Key {
+        &self.keys[0]
+    }
+
+    /// The identifier of the key used to encrypt payloads
+    #[must_use]
+    pub fn key_id(&self) -> &str {
+        &self.current().id
     }
 
     /// Encrypt a payload
@@ -51,7 +105,7 @@ impl Encrypter {
     /// Will return `Err` when the payload failed to encrypt
     pub fn encrypt(&self, nonce: &[u8; 12], decrypted: &[u8]) -> Result<Vec<u8>, aead::Error> {
         let nonce = GenericArray::from_slice(&nonce[..]);
-        let encrypted = self.aead.encrypt(nonce, decrypted)?;
+        let encrypted = self.current().aead.encrypt(nonce, decrypted)?;
         Ok(encrypted)
     }
 
@@ -62,12 +116,14 @@ impl Encrypter {
     /// Will return `Err` when the payload failed to decrypt
     pub fn decrypt(&self, nonce: &[u8; 12], encrypted: &[u8]) -> Result<Vec<u8>, aead::Error> {
         let nonce = GenericArray::from_slice(&nonce[..]);
-        let encrypted = self.aead.decrypt(nonce, encrypted)?;
+        let encrypted = self.current().aead.decrypt(nonce, encrypted)?;
         Ok(encrypted)
     }
 
     /// Encrypt a payload to a self-contained base64-encoded string
     ///
+    /// The string is prefixed with the ID of the key used to encrypt it.
+    ///
     /// # Errors
     ///
     /// Will return `Err` when the payload failed to encrypt
@@ -76,27 +132,52 @@ impl Encrypter {
         let encrypted = self.encrypt(&nonce, decrypted)?;
         let encrypted = [&nonce[..], &encrypted].concat();
         let encrypted = Base64::encode_string(&encrypted);
-        Ok(encrypted)
+        Ok(format!("{}{KEY_ID_SEPARATOR}{encrypted}", self.key_id()))
     }
 
     /// Decrypt a payload from a self-contained base64-encoded string
     ///
+    /// If the string is prefixed with a key ID, the matching key is used,
+    /// which can be a previous key. Strings encrypted before key IDs were
+    /// introduced are tried against all the keys.
+    ///
     /// # Errors
     ///
     /// Will return `Err` when the payload failed to decrypt
     pub fn decrypt_string(&self, encrypted: &str) -> Result<Vec<u8>, DecryptError> {
+        let (key_id, encrypted) = match encrypted.split_once(KEY_ID_SEPARATOR) {
+            Some((key_id, encrypted)) => (Some(key_id), encrypted),
+            None => (None, encrypted),
+        };
+
         let encrypted = Base64::decode_vec(encrypted)?;
 
-        let nonce: &[u8; 12] = encrypted
-            .get(0..12)
-            .ok_or(DecryptError::Shape)?
-            .try_into()
-            .map_err(|_| DecryptError::Shape)?;
+        let nonce = encrypted.get(0..12).ok_or(DecryptError::Shape)?;
+        let nonce = GenericArray::from_slice(nonce);
 
         let payload = encrypted.get(12..).ok_or(DecryptError::Shape)?;
 
-        let decrypted_client_secret = self.decrypt(nonce, payload)?;
+        if let Some(key_id) = key_id {
+            let key = self
+                .keys
+                .iter()
+                .find(|key| key.id == key_id)
+                .ok_or(DecryptError::UnknownKey)?;
+            return Ok(key.aead.decrypt(nonce, payload)?);
+        }
 
-        Ok(decrypted_client_secret)
+        self.keys
+            .iter()
+            .find_map(|key| key.aead.decrypt(nonce, payload).ok())
+            .ok_or(DecryptError::Aead(aead::Error))
+    }
+
+    /// Whether a self-contained encrypted string was encrypted with the
+    /// current key, or needs to be re-encrypted
+    #[must_use]
+    pub fn is_current(&self, encrypted: &str) -> bool {
+        encrypted
+            .split_once(KEY_ID_SEPARATOR)
+            .is_some_and(|(key_id, _)| key_id == self.key_id())
     }
 }
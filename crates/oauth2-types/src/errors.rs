@@ -273,6 +273,28 @@ pub enum ClientErrorCode {
     /// From [RFC7009](https://www.rfc-editor.org/rfc/rfc7009#section-2.2.1).
     UnsupportedTokenType,
 
+    /// `invalid_dpop_proof`
+    ///
+    /// The `DPoP` proof is missing, malformed or invalid.
+    ///
+    /// From [RFC9449](https://www.rfc-editor.org/rfc/rfc9449#section-5).
+    InvalidDpopProof,
+
+    /// `use_dpop_nonce`
+    ///
+    /// The authorization server requires a nonce in the `DPoP` proof. The
+    /// nonce to use is provided in the `DPoP-Nonce` header of the response.
+    ///
+    /// From [RFC9449](https://www.rfc-editor.org/rfc/rfc9449#section-8).
+    UseDpopNonce,
+
+    /// `invalid_target`
+    ///
+    /// The requested resource is invalid, missing, unknown, or malformed.
+    ///
+    /// From [RFC8707](https://www.rfc-editor.org/rfc/rfc8707#section-2).
+    InvalidTarget,
+
     /// Another error code.
     Unknown(String),
 }
@@ -305,6 +327,9 @@ impl core::fmt::Display for ClientErrorCode {
             ClientErrorCode::SlowDown => f.write_str("slow_down"),
             ClientErrorCode::ExpiredToken => f.write_str("expired_token"),
             ClientErrorCode::UnsupportedTokenType => f.write_str("unsupported_token_type"),
+            ClientErrorCode::InvalidDpopProof => f.write_str("invalid_dpop_proof"),
+            ClientErrorCode::UseDpopNonce => f.write_str("use_dpop_nonce"),
+            ClientErrorCode::InvalidTarget => f.write_str("invalid_target"),
             ClientErrorCode::Unknown(value) => f.write_str(value),
         }
     }
@@ -340,6 +365,9 @@ impl core::str::FromStr for ClientErrorCode {
             "slow_down" => Ok(ClientErrorCode::SlowDown),
             "expired_token" => Ok(ClientErrorCode::ExpiredToken),
             "unsupported_token_type" => Ok(ClientErrorCode::UnsupportedTokenType),
+            "invalid_dpop_proof" => Ok(ClientErrorCode::InvalidDpopProof),
+            "use_dpop_nonce" => Ok(ClientErrorCode::UseDpopNonce),
+            "invalid_target" => Ok(ClientErrorCode::InvalidTarget),
             _ => Ok(ClientErrorCode::Unknown(s.to_owned())),
         }
     }
@@ -431,6 +459,13 @@ impl ClientErrorCode {
             ClientErrorCode::UnsupportedTokenType => {
                 "The authorization server does not support the revocation of the presented token type."
             },
+            ClientErrorCode::InvalidDpopProof => "The DPoP proof is invalid.",
+            ClientErrorCode::UseDpopNonce => {
+                "The authorization server requires a nonce in the DPoP proof."
+            }
+            ClientErrorCode::InvalidTarget => {
+                "The requested resource is invalid, missing, unknown, or malformed."
+            }
             ClientErrorCode::Unknown(_) => "",
         }
     }
@@ -526,6 +561,10 @@ mod tests {
             serde_json::to_string(&ClientErrorCode::InvalidClientMetadata).unwrap(),
             "\"invalid_client_metadata\""
         );
+        assert_eq!(
+            serde_json::to_string(&ClientErrorCode::InvalidTarget).unwrap(),
+            "\"invalid_target\""
+        );
 
         assert_eq!(
             serde_json::to_string(&ClientErrorCode::Unknown("unknown_error_code".to_owned()))
@@ -620,6 +659,10 @@ mod tests {
             serde_json::from_str::<ClientErrorCode>("\"invalid_client_metadata\"").unwrap(),
             ClientErrorCode::InvalidClientMetadata
         );
+        assert_eq!(
+            serde_json::from_str::<ClientErrorCode>("\"invalid_target\"").unwrap(),
+            ClientErrorCode::InvalidTarget
+        );
 
         assert_eq!(
             serde_json::from_str::<ClientErrorCode>("\"unknown_error_code\"").unwrap(),
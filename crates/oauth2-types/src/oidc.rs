@@ -617,6 +617,18 @@ pub struct ProviderMetadata {
     /// [device authorization endpoint]: https://www.rfc-editor.org/rfc/rfc8628
     pub device_authorization_endpoint: Option<Url>,
 
+    /// JSON array containing a list of the JWS algorithms supported for
+    /// [`DPoP`] proofs.
+    ///
+    /// [`DPoP`]: https://www.rfc-editor.org/rfc/rfc9449
+    pub dpop_signing_alg_values_supported: Option<Vec<JsonWebSignatureAlg>>,
+
+    /// JSON array containing a list of the JWS algorithms supported for
+    /// signing [JWT-secured authorization responses].
+    ///
+    /// [JWT-secured authorization responses]: https://openid.net/specs/oauth-v2-jarm.html
+    pub authorization_signing_alg_values_supported: Option<Vec<JsonWebSignatureAlg>>,
+
     /// URL of the authorization server's [RP-Initiated Logout endpoint].
     ///
     /// [RP-Initiated Logout endpoint]: https://openid.net/specs/openid-connect-rpinitiated-1_0.html
@@ -22,6 +22,7 @@ use data_encoding::BASE64URL_NOPAD;
 use mas_iana::oauth::PkceCodeChallengeMethod;
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
 use thiserror::Error;
 
 /// Errors that can occur when verifying a code challenge.
@@ -88,7 +89,10 @@ pub trait CodeChallengeMethodExt {
     where
         Self: Sized,
     {
-        if self.compute_challenge(verifier)? == challenge {
+        // Compare in constant time, so that the challenge can't be guessed by
+        // timing the verification
+        let computed = self.compute_challenge(verifier)?;
+        if bool::from(computed.as_bytes().ct_eq(challenge.as_bytes())) {
             Ok(())
         } else {
             Err(CodeChallengeError::VerificationFailed)
@@ -154,6 +158,11 @@ mod tests {
             Err(CodeChallengeError::VerificationFailed),
         );
 
+        assert_eq!(
+            S256.verify("short", "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk"),
+            Err(CodeChallengeError::VerificationFailed),
+        );
+
         assert_eq!(
             S256.verify(challenge, "tooshort"),
             Err(CodeChallengeError::TooShort),
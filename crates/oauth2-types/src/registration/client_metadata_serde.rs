@@ -126,6 +126,7 @@ pub struct ClientMetadataSerdeHelper {
     introspection_signed_response_alg: Option<JsonWebSignatureAlg>,
     introspection_encrypted_response_alg: Option<JsonWebEncryptionAlg>,
     introspection_encrypted_response_enc: Option<JsonWebEncryptionEnc>,
+    authorization_signed_response_alg: Option<JsonWebSignatureAlg>,
     post_logout_redirect_uris: Option<Vec<Url>>,
     #[serde(flatten)]
     extra: ClientMetadataLocalizedFields,
@@ -173,6 +174,7 @@ impl From<VerifiedClientMetadata> for ClientMetadataSerdeHelper {
                     introspection_signed_response_alg,
                     introspection_encrypted_response_alg,
                     introspection_encrypted_response_enc,
+                    authorization_signed_response_alg,
                     post_logout_redirect_uris,
                 },
         } = metadata;
@@ -210,6 +212,7 @@ impl From<VerifiedClientMetadata> for ClientMetadataSerdeHelper {
             introspection_signed_response_alg,
             introspection_encrypted_response_alg,
             introspection_encrypted_response_enc,
+            authorization_signed_response_alg,
             post_logout_redirect_uris,
             extra: ClientMetadataLocalizedFields {
                 client_name,
@@ -257,6 +260,7 @@ impl From<ClientMetadataSerdeHelper> for ClientMetadata {
             introspection_signed_response_alg,
             introspection_encrypted_response_alg,
             introspection_encrypted_response_enc,
+            authorization_signed_response_alg,
             post_logout_redirect_uris,
             extra:
                 ClientMetadataLocalizedFields {
@@ -306,6 +310,7 @@ impl From<ClientMetadataSerdeHelper> for ClientMetadata {
             introspection_signed_response_alg,
             introspection_encrypted_response_alg,
             introspection_encrypted_response_enc,
+            authorization_signed_response_alg,
             post_logout_redirect_uris,
         }
     }
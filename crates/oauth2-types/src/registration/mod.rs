@@ -16,7 +16,10 @@
 //!
 //! [Dynamic Client Registration]: https://openid.net/specs/openid-connect-registration-1_0.html
 
-use std::{collections::HashMap, ops::Deref};
+use std::{
+    collections::{HashMap, HashSet},
+    ops::Deref,
+};
 
 use chrono::{DateTime, Duration, Utc};
 use language_tags::LanguageTag;
@@ -431,6 +434,18 @@ pub struct ClientMetadata {
     /// [introspection endpoint]: https://www.rfc-editor.org/info/rfc7662
     pub introspection_encrypted_response_enc: Option<JsonWebEncryptionEnc>,
 
+    /// [JWS] `alg` algorithm required for signing [JWT-secured authorization
+    /// responses].
+    ///
+    /// If this field is present, it must not be
+    /// [`JsonWebSignatureAlg::None`]. If it is not, the provider uses
+    /// [`DEFAULT_SIGNING_ALGORITHM`] if it can, or any other algorithm it
+    /// supports.
+    ///
+    /// [JWS]: http://tools.ietf.org/html/draft-ietf-jose-json-web-signature
+    /// [JWT-secured authorization responses]: https://openid.net/specs/oauth-v2-jarm.html
+    pub authorization_signed_response_alg: Option<JsonWebSignatureAlg>,
+
     /// `post_logout_redirect_uri` values that are pre-registered by the client
     /// for use at the provider's [RP-Initiated Logout endpoint].
     ///
@@ -460,6 +475,14 @@ impl ClientMetadata {
                     uri.clone(),
                 ));
             }
+
+            if let Some(uri) = uris.iter().find(|uri| has_duplicate_query_params(uri)) {
+                return Err(
+                    ClientMetadataVerificationError::RedirectUriWithDuplicateQueryParam(
+                        uri.clone(),
+                    ),
+                );
+            }
         } else if has_authorization_code || has_implicit {
             // Required for authorization code and implicit flows
             return Err(ClientMetadataVerificationError::MissingRedirectUris);
@@ -533,6 +556,12 @@ impl ClientMetadata {
             return Err(ClientMetadataVerificationError::IdTokenSigningAlgNone);
         }
 
+        if self.authorization_signed_response_alg == Some(JsonWebSignatureAlg::None) {
+            return Err(ClientMetadataVerificationError::UnauthorizedSigningAlgNone(
+                "authorization",
+            ));
+        }
+
         if self.id_token_encrypted_response_enc.is_some() {
             self.id_token_encrypted_response_alg.as_ref().ok_or(
                 ClientMetadataVerificationError::MissingEncryptionAlg("id_token"),
@@ -836,6 +865,16 @@ impl Deref for VerifiedClientMetadata {
     }
 }
 
+/// Whether the given redirect URI has the same query parameter more than once.
+///
+/// The query parameters of a redirect URI are merged with the ones of the
+/// authorization response, so they must be unambiguous.
+#[must_use]
+pub fn has_duplicate_query_params(uri: &Url) -> bool {
+    let mut seen = HashSet::new();
+    uri.query_pairs().any(|(name, _)| !seen.insert(name))
+}
+
 /// All errors that can happen when verifying [`ClientMetadata`].
 #[derive(Debug, Error)]
 pub enum ClientMetadataVerificationError {
@@ -847,6 +886,11 @@ pub enum ClientMetadataVerificationError {
     #[error("redirect URI with fragment: {0}")]
     RedirectUriWithFragment(Url),
 
+    /// The redirect URI has the same query parameter more than once, which is
+    /// not allowed.
+    #[error("redirect URI with duplicate query parameter: {0}")]
+    RedirectUriWithDuplicateQueryParam(Url),
+
     /// The given response type is not compatible with the grant types.
     #[error("'{0}' response type not compatible with grant types")]
     IncoherentResponseType(ResponseType),
@@ -972,12 +1016,25 @@ mod tests {
         );
         assert_eq!(uri, wrong_uri);
 
+        // Err - Duplicate query parameter
+        let wrong_uri = Url::parse("http://localhost/?state=a&oidc&state=b").unwrap();
+        metadata.redirect_uris = Some(vec![
+            Url::parse("http://localhost/").unwrap(),
+            wrong_uri.clone(),
+        ]);
+        let uri = assert_matches!(
+            metadata.clone().validate(),
+            Err(ClientMetadataVerificationError::RedirectUriWithDuplicateQueryParam(uri)) => uri
+        );
+        assert_eq!(uri, wrong_uri);
+
         // Ok - Path & Query
         metadata.redirect_uris = Some(vec![
             Url::parse("http://localhost/").unwrap(),
             Url::parse("http://localhost/oidc").unwrap(),
             Url::parse("http://localhost/?oidc").unwrap(),
             Url::parse("http://localhost/my-client?oidc").unwrap(),
+            Url::parse("http://localhost/my-client?oidc&code=a%26b").unwrap(),
         ]);
         metadata.validate().unwrap();
     }
@@ -1358,6 +1415,23 @@ mod tests {
         metadata.validate().unwrap();
     }
 
+    #[test]
+    fn validate_authorization_signed_response_alg() {
+        let mut metadata = valid_client_metadata();
+
+        // Err - none
+        metadata.authorization_signed_response_alg = Some(JsonWebSignatureAlg::None);
+        let endpoint = assert_matches!(
+            metadata.clone().validate(),
+            Err(ClientMetadataVerificationError::UnauthorizedSigningAlgNone(endpoint)) => endpoint
+        );
+        assert_eq!(endpoint, "authorization");
+
+        // Ok - Other algorithm
+        metadata.authorization_signed_response_alg = Some(JsonWebSignatureAlg::Es256);
+        metadata.validate().unwrap();
+    }
+
     #[test]
     fn validate_request_object_encryption() {
         let mut metadata = valid_client_metadata();
@@ -16,15 +16,16 @@
 //!
 //! [OAuth 2.0]: https://oauth.net/2/
 
-use std::{collections::HashSet, fmt, hash::Hash, num::NonZeroU32};
+use std::{collections::HashSet, fmt, hash::Hash};
 
 use chrono::{DateTime, Duration, Utc};
 use language_tags::LanguageTag;
 use mas_iana::oauth::{OAuthAccessTokenType, OAuthTokenTypeHint};
 use serde::{Deserialize, Serialize};
 use serde_with::{
-    formats::SpaceSeparator, serde_as, skip_serializing_none, DeserializeFromStr, DisplayFromStr,
-    DurationSeconds, SerializeDisplay, StringWithSeparator, TimestampSeconds,
+    formats::{PreferOne, SpaceSeparator},
+    serde_as, skip_serializing_none, DeserializeFromStr, DisplayFromStr, DurationSeconds,
+    OneOrMany, SerializeDisplay, StringWithSeparator, TimestampSeconds,
 };
 use url::Url;
 
@@ -58,16 +59,66 @@ pub enum ResponseMode {
     /// Defined in [OAuth 2.0 Form Post Response Mode](https://openid.net/specs/oauth-v2-form-post-response-mode-1_0.html).
     FormPost,
 
+    /// Authorization Response parameters are sent to the Client through the
+    /// HTML5 Web Messaging API, by a page which targets the origin of the
+    /// `redirect_uri`.
+    ///
+    /// Used by single-page applications which silently renew their session
+    /// from a hidden iframe.
+    WebMessage,
+
+    /// Authorization Response parameters are signed in a JWT, sent with the
+    /// default response mode of the response type: `query.jwt` for the `code`
+    /// response type, `fragment.jwt` otherwise.
+    ///
+    /// Defined in [JWT Secured Authorization Response Mode for OAuth 2.0](https://openid.net/specs/oauth-v2-jarm.html).
+    Jwt,
+
+    /// Authorization Response parameters are signed in a JWT, which is added to
+    /// the query string of the `redirect_uri`.
+    ///
+    /// Defined in [JWT Secured Authorization Response Mode for OAuth 2.0](https://openid.net/specs/oauth-v2-jarm.html).
+    QueryJwt,
+
+    /// Authorization Response parameters are signed in a JWT, which is added to
+    /// the fragment of the `redirect_uri`.
+    ///
+    /// Defined in [JWT Secured Authorization Response Mode for OAuth 2.0](https://openid.net/specs/oauth-v2-jarm.html).
+    FragmentJwt,
+
+    /// Authorization Response parameters are signed in a JWT, which is sent as
+    /// an auto-submitted HTML form value.
+    ///
+    /// Defined in [JWT Secured Authorization Response Mode for OAuth 2.0](https://openid.net/specs/oauth-v2-jarm.html).
+    FormPostJwt,
+
     /// An unknown value.
     Unknown(String),
 }
 
+impl ResponseMode {
+    /// Whether the Authorization Response parameters are signed in a JWT with
+    /// this response mode.
+    #[must_use]
+    pub fn is_jwt(&self) -> bool {
+        matches!(
+            self,
+            Self::Jwt | Self::QueryJwt | Self::FragmentJwt | Self::FormPostJwt
+        )
+    }
+}
+
 impl core::fmt::Display for ResponseMode {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         match self {
             ResponseMode::Query => f.write_str("query"),
             ResponseMode::Fragment => f.write_str("fragment"),
             ResponseMode::FormPost => f.write_str("form_post"),
+            ResponseMode::WebMessage => f.write_str("web_message"),
+            ResponseMode::Jwt => f.write_str("jwt"),
+            ResponseMode::QueryJwt => f.write_str("query.jwt"),
+            ResponseMode::FragmentJwt => f.write_str("fragment.jwt"),
+            ResponseMode::FormPostJwt => f.write_str("form_post.jwt"),
             ResponseMode::Unknown(s) => f.write_str(s),
         }
     }
@@ -81,6 +132,11 @@ impl core::str::FromStr for ResponseMode {
             "query" => Ok(ResponseMode::Query),
             "fragment" => Ok(ResponseMode::Fragment),
             "form_post" => Ok(ResponseMode::FormPost),
+            "web_message" => Ok(ResponseMode::WebMessage),
+            "jwt" => Ok(ResponseMode::Jwt),
+            "query.jwt" => Ok(ResponseMode::QueryJwt),
+            "fragment.jwt" => Ok(ResponseMode::FragmentJwt),
+            "form_post.jwt" => Ok(ResponseMode::FormPostJwt),
             s => Ok(ResponseMode::Unknown(s.to_owned())),
         }
     }
@@ -273,9 +329,11 @@ pub struct AuthorizationRequest {
 
     /// The allowable elapsed time in seconds since the last time the End-User
     /// was actively authenticated by the OpenID Provider.
+    ///
+    /// A value of `0` is equivalent to `prompt=login`.
     #[serde(default)]
     #[serde_as(as = "Option<DisplayFromStr>")]
-    pub max_age: Option<NonZeroU32>,
+    pub max_age: Option<u32>,
 
     /// End-User's preferred languages and scripts for the user interface.
     #[serde_as(as = "Option<StringWithSeparator::<SpaceSeparator, LanguageTag>>")]
@@ -314,6 +372,16 @@ pub struct AuthorizationRequest {
     ///
     /// [Self-Issued OpenID Provider]: https://openid.net/specs/openid-connect-core-1_0.html#SelfIssued
     pub registration: Option<String>,
+
+    /// The [resources] the client wants to use the access tokens for.
+    ///
+    /// Several resources are separated by spaces. Servers receiving repeated
+    /// `resource` parameters must join them before deserializing the request.
+    ///
+    /// [resources]: https://www.rfc-editor.org/rfc/rfc8707
+    #[serde_as(as = "Option<StringWithSeparator::<SpaceSeparator, Url>>")]
+    #[serde(default)]
+    pub resource: Option<Vec<Url>>,
 }
 
 impl AuthorizationRequest {
@@ -338,6 +406,7 @@ impl AuthorizationRequest {
             request: None,
             request_uri: None,
             registration: None,
+            resource: None,
         }
     }
 }
@@ -358,6 +427,7 @@ impl fmt::Debug for AuthorizationRequest {
             .field("request", &self.request)
             .field("request_uri", &self.request_uri)
             .field("registration", &self.registration)
+            .field("resource", &self.resource)
             .finish_non_exhaustive()
     }
 }
@@ -467,11 +537,29 @@ impl fmt::Debug for DeviceAuthorizationResponse {
     }
 }
 
+/// A successful response from the [Pushed Authorization Request Endpoint].
+///
+/// [Pushed Authorization Request Endpoint]: https://www.rfc-editor.org/rfc/rfc9126
+#[serde_as]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct PushedAuthorizationResponse {
+    /// The request URI corresponding to the authorization request posted.
+    ///
+    /// It should be used as the `request_uri` parameter of the authorization
+    /// request.
+    pub request_uri: String,
+
+    /// The lifetime of the request URI.
+    #[serde_as(as = "DurationSeconds<i64>")]
+    pub expires_in: Duration,
+}
+
 /// A request to the [Token Endpoint] for the [Authorization Code] grant type.
 ///
 /// [Token Endpoint]: https://www.rfc-editor.org/rfc/rfc6749#section-3.2
 /// [Authorization Code]: https://www.rfc-editor.org/rfc/rfc6749#section-4.1
 #[skip_serializing_none]
+#[serde_as]
 #[derive(Serialize, Deserialize, Clone, PartialEq, Eq)]
 pub struct AuthorizationCodeGrant {
     /// The authorization code that was returned from the authorization
@@ -488,12 +576,24 @@ pub struct AuthorizationCodeGrant {
     /// authorization endpoint.
     // TODO: move this somehow in the pkce module
     pub code_verifier: Option<String>,
+
+    /// The [resources] the access token is requested for.
+    ///
+    /// They must have been requested in the authorization request. If omitted,
+    /// the access token is restricted to all of them. Several resources are
+    /// requested by repeating the `resource` parameter.
+    ///
+    /// [resources]: https://www.rfc-editor.org/rfc/rfc8707
+    #[serde_as(as = "Option<OneOrMany<_, PreferOne>>")]
+    #[serde(default)]
+    pub resource: Option<Vec<Url>>,
 }
 
 impl fmt::Debug for AuthorizationCodeGrant {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("AuthorizationCodeGrant")
             .field("redirect_uri", &self.redirect_uri)
+            .field("resource", &self.resource)
             .finish_non_exhaustive()
     }
 }
@@ -503,6 +603,7 @@ impl fmt::Debug for AuthorizationCodeGrant {
 /// [Token Endpoint]: https://www.rfc-editor.org/rfc/rfc6749#section-3.2
 /// [refreshing an access token]: https://www.rfc-editor.org/rfc/rfc6749#section-6
 #[skip_serializing_none]
+#[serde_as]
 #[derive(Serialize, Deserialize, Clone, PartialEq, Eq)]
 pub struct RefreshTokenGrant {
     /// The refresh token issued to the client.
@@ -514,12 +615,24 @@ pub struct RefreshTokenGrant {
     /// the resource owner, and if omitted is treated as equal to the scope
     /// originally granted by the resource owner.
     pub scope: Option<Scope>,
+
+    /// The [resources] the access token is requested for.
+    ///
+    /// They must be part of the resources originally authorized, and if
+    /// omitted, the access token is restricted to all of them. Several
+    /// resources are requested by repeating the `resource` parameter.
+    ///
+    /// [resources]: https://www.rfc-editor.org/rfc/rfc8707
+    #[serde_as(as = "Option<OneOrMany<_, PreferOne>>")]
+    #[serde(default)]
+    pub resource: Option<Vec<Url>>,
 }
 
 impl fmt::Debug for RefreshTokenGrant {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("RefreshTokenGrant")
             .field("scope", &self.scope)
+            .field("resource", &self.resource)
             .finish_non_exhaustive()
     }
 }
@@ -528,10 +641,22 @@ impl fmt::Debug for RefreshTokenGrant {
 ///
 /// [Token Endpoint]: https://www.rfc-editor.org/rfc/rfc6749#section-3.2
 /// [Client Credentials]: https://www.rfc-editor.org/rfc/rfc6749#section-4.4
+#[skip_serializing_none]
+#[serde_as]
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct ClientCredentialsGrant {
     /// The scope of the access request.
     pub scope: Option<Scope>,
+
+    /// The [resources] the access token is requested for.
+    ///
+    /// They must be part of the resources the client is allowed to request.
+    /// Several resources are requested by repeating the `resource` parameter.
+    ///
+    /// [resources]: https://www.rfc-editor.org/rfc/rfc8707
+    #[serde_as(as = "Option<OneOrMany<_, PreferOne>>")]
+    #[serde(default)]
+    pub resource: Option<Vec<Url>>,
 }
 
 /// A request to the [Token Endpoint] for the [Device Authorization] grant type.
@@ -550,6 +675,97 @@ impl fmt::Debug for DeviceCodeGrant {
     }
 }
 
+/// A request to the [Token Endpoint] for the [Token Exchange] grant type.
+///
+/// [Token Endpoint]: https://www.rfc-editor.org/rfc/rfc6749#section-3.2
+/// [Token Exchange]: https://www.rfc-editor.org/rfc/rfc8693
+#[skip_serializing_none]
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct TokenExchangeGrant {
+    /// The token that represents the identity of the party on behalf of whom
+    /// the request is being made.
+    pub subject_token: String,
+
+    /// The type of the `subject_token`.
+    pub subject_token_type: TokenTypeIdentifier,
+
+    /// The type of the requested security token.
+    pub requested_token_type: Option<TokenTypeIdentifier>,
+
+    /// The scope of the requested security token.
+    ///
+    /// If omitted, the scope of the `subject_token` is used.
+    pub scope: Option<Scope>,
+}
+
+impl fmt::Debug for TokenExchangeGrant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TokenExchangeGrant")
+            .field("subject_token_type", &self.subject_token_type)
+            .field("requested_token_type", &self.requested_token_type)
+            .field("scope", &self.scope)
+            .finish_non_exhaustive()
+    }
+}
+
+/// All possible values for the token type identifiers used in the [Token
+/// Exchange] grant type.
+///
+/// [Token Exchange]: https://www.rfc-editor.org/rfc/rfc8693#section-3
+#[derive(
+    Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Clone, SerializeDisplay, DeserializeFromStr,
+)]
+pub enum TokenTypeIdentifier {
+    /// `urn:ietf:params:oauth:token-type:access_token`
+    AccessToken,
+
+    /// `urn:ietf:params:oauth:token-type:refresh_token`
+    RefreshToken,
+
+    /// `urn:ietf:params:oauth:token-type:id_token`
+    IdToken,
+
+    /// `urn:ietf:params:oauth:token-type:jwt`
+    Jwt,
+
+    /// An unknown value.
+    Unknown(String),
+}
+
+impl core::fmt::Display for TokenTypeIdentifier {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            TokenTypeIdentifier::AccessToken => {
+                f.write_str("urn:ietf:params:oauth:token-type:access_token")
+            }
+            TokenTypeIdentifier::RefreshToken => {
+                f.write_str("urn:ietf:params:oauth:token-type:refresh_token")
+            }
+            TokenTypeIdentifier::IdToken => {
+                f.write_str("urn:ietf:params:oauth:token-type:id_token")
+            }
+            TokenTypeIdentifier::Jwt => f.write_str("urn:ietf:params:oauth:token-type:jwt"),
+            TokenTypeIdentifier::Unknown(s) => f.write_str(s),
+        }
+    }
+}
+
+impl core::str::FromStr for TokenTypeIdentifier {
+    type Err = core::convert::Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "urn:ietf:params:oauth:token-type:access_token" => Ok(TokenTypeIdentifier::AccessToken),
+            "urn:ietf:params:oauth:token-type:refresh_token" => {
+                Ok(TokenTypeIdentifier::RefreshToken)
+            }
+            "urn:ietf:params:oauth:token-type:id_token" => Ok(TokenTypeIdentifier::IdToken),
+            "urn:ietf:params:oauth:token-type:jwt" => Ok(TokenTypeIdentifier::Jwt),
+            s => Ok(TokenTypeIdentifier::Unknown(s.to_owned())),
+        }
+    }
+}
+
 /// All possible values for the `grant_type` parameter.
 #[derive(
     Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Clone, SerializeDisplay, DeserializeFromStr,
@@ -579,6 +795,9 @@ pub enum GrantType {
     /// [`urn:openid:params:grant-type:ciba`](https://openid.net/specs/openid-client-initiated-backchannel-authentication-core-1_0.html)
     ClientInitiatedBackchannelAuthentication,
 
+    /// [`urn:ietf:params:oauth:grant-type:token-exchange`](https://www.rfc-editor.org/rfc/rfc8693)
+    TokenExchange,
+
     /// An unknown value.
     Unknown(String),
 }
@@ -596,6 +815,9 @@ impl core::fmt::Display for GrantType {
             GrantType::ClientInitiatedBackchannelAuthentication => {
                 f.write_str("urn:openid:params:grant-type:ciba")
             }
+            GrantType::TokenExchange => {
+                f.write_str("urn:ietf:params:oauth:grant-type:token-exchange")
+            }
             GrantType::Unknown(s) => f.write_str(s),
         }
     }
@@ -616,6 +838,7 @@ impl core::str::FromStr for GrantType {
             "urn:openid:params:grant-type:ciba" => {
                 Ok(GrantType::ClientInitiatedBackchannelAuthentication)
             }
+            "urn:ietf:params:oauth:grant-type:token-exchange" => Ok(GrantType::TokenExchange),
             s => Ok(GrantType::Unknown(s.to_owned())),
         }
     }
@@ -641,6 +864,10 @@ pub enum AccessTokenRequest {
     #[serde(rename = "urn:ietf:params:oauth:grant-type:device_code")]
     DeviceCode(DeviceCodeGrant),
 
+    /// A request in the Token Exchange flow.
+    #[serde(rename = "urn:ietf:params:oauth:grant-type:token-exchange")]
+    TokenExchange(TokenExchangeGrant),
+
     /// An unsupported request.
     #[serde(skip_serializing, other)]
     Unsupported,
@@ -672,6 +899,9 @@ pub struct AccessTokenResponse {
 
     /// The scope of the access token.
     pub scope: Option<Scope>,
+
+    /// The type of the issued token, in the Token Exchange flow.
+    pub issued_token_type: Option<TokenTypeIdentifier>,
 }
 
 impl AccessTokenResponse {
@@ -685,6 +915,7 @@ impl AccessTokenResponse {
             token_type: OAuthAccessTokenType::Bearer,
             expires_in: None,
             scope: None,
+            issued_token_type: None,
         }
     }
 
@@ -715,6 +946,20 @@ impl AccessTokenResponse {
         self.expires_in = Some(expires_in);
         self
     }
+
+    /// Adds the type of the issued token to an `AccessTokenResponse`.
+    #[must_use]
+    pub fn with_issued_token_type(mut self, issued_token_type: TokenTypeIdentifier) -> Self {
+        self.issued_token_type = Some(issued_token_type);
+        self
+    }
+
+    /// Sets the type of the access token of an `AccessTokenResponse`.
+    #[must_use]
+    pub fn with_token_type(mut self, token_type: OAuthAccessTokenType) -> Self {
+        self.token_type = token_type;
+        self
+    }
 }
 
 impl fmt::Debug for AccessTokenResponse {
@@ -723,6 +968,7 @@ impl fmt::Debug for AccessTokenResponse {
             .field("token_type", &self.token_type)
             .field("expires_in", &self.expires_in)
             .field("scope", &self.scope)
+            .field("issued_token_type", &self.issued_token_type)
             .finish_non_exhaustive()
     }
 }
@@ -787,13 +1033,38 @@ pub struct IntrospectionResponse {
     pub sub: Option<String>,
 
     /// Intended audience of the token.
-    pub aud: Option<String>,
+    ///
+    /// Serialized as a single string if there is only one audience.
+    #[serde_as(as = "Option<OneOrMany<_, PreferOne>>")]
+    #[serde(default)]
+    pub aud: Option<Vec<String>>,
 
     /// Issuer of the token.
     pub iss: Option<String>,
 
     /// String identifier for the token.
     pub jti: Option<String>,
+
+    /// The Matrix device ID the token is bound to.
+    ///
+    /// This is not part of the specification, and is only set for tokens of
+    /// compatibility sessions.
+    pub device_id: Option<String>,
+
+    /// The confirmation method the token is bound to.
+    ///
+    /// Set for tokens bound to a DPoP proof key, as per [RFC 9449].
+    ///
+    /// [RFC 9449]: https://www.rfc-editor.org/rfc/rfc9449#section-6.2
+    pub cnf: Option<Confirmation>,
+}
+
+/// The confirmation claim of a sender-constrained token.
+#[skip_serializing_none]
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Default, Debug)]
+pub struct Confirmation {
+    /// The JWK SHA-256 thumbprint of the key the token is bound to.
+    pub jkt: Option<String>,
 }
 
 /// A request to the [Revocation Endpoint].
@@ -858,6 +1129,63 @@ mod tests {
         let req = AccessTokenRequest::RefreshToken(RefreshTokenGrant {
             refresh_token: "abcd".into(),
             scope,
+            resource: None,
+        });
+
+        assert_serde_json(&req, expected);
+    }
+
+    #[test]
+    fn serde_refresh_token_grant_with_resources() {
+        let expected = json!({
+            "grant_type": "refresh_token",
+            "refresh_token": "abcd",
+            "resource": ["https://a.example.com/", "https://b.example.com/api"],
+        });
+
+        let req = AccessTokenRequest::RefreshToken(RefreshTokenGrant {
+            refresh_token: "abcd".into(),
+            scope: None,
+            resource: Some(vec![
+                "https://a.example.com/".parse().unwrap(),
+                "https://b.example.com/api".parse().unwrap(),
+            ]),
+        });
+
+        assert_serde_json(&req, expected);
+    }
+
+    #[test]
+    fn serde_client_credentials_grant_with_resources() {
+        let expected = json!({
+            "grant_type": "client_credentials",
+            "resource": "https://a.example.com/",
+        });
+
+        let req = AccessTokenRequest::ClientCredentials(ClientCredentialsGrant {
+            scope: None,
+            resource: Some(vec!["https://a.example.com/".parse().unwrap()]),
+        });
+
+        assert_serde_json(&req, expected);
+    }
+
+    #[test]
+    fn serde_token_exchange_grant() {
+        let expected = json!({
+            "grant_type": "urn:ietf:params:oauth:grant-type:token-exchange",
+            "subject_token": "abcd",
+            "subject_token_type": "urn:ietf:params:oauth:token-type:access_token",
+            "scope": "openid",
+        });
+
+        let scope: Option<Scope> = Some(vec![OPENID].into_iter().collect());
+
+        let req = AccessTokenRequest::TokenExchange(TokenExchangeGrant {
+            subject_token: "abcd".into(),
+            subject_token_type: TokenTypeIdentifier::AccessToken,
+            requested_token_type: None,
+            scope,
         });
 
         assert_serde_json(&req, expected);
@@ -875,11 +1203,80 @@ mod tests {
             code: "abcd".into(),
             redirect_uri: Some("https://example.com/redirect".parse().unwrap()),
             code_verifier: None,
+            resource: None,
         });
 
         assert_serde_json(&req, expected);
     }
 
+    #[test]
+    fn serde_introspection_response_audience() {
+        let response = IntrospectionResponse {
+            active: true,
+            aud: Some(vec!["https://a.example.com/".to_owned()]),
+            ..IntrospectionResponse::default()
+        };
+        assert_serde_json(
+            &response,
+            json!({
+                "active": true,
+                "aud": "https://a.example.com/",
+            }),
+        );
+
+        let response = IntrospectionResponse {
+            active: true,
+            aud: Some(vec![
+                "https://a.example.com/".to_owned(),
+                "https://b.example.com/".to_owned(),
+            ]),
+            ..IntrospectionResponse::default()
+        };
+        assert_serde_json(
+            &response,
+            json!({
+                "active": true,
+                "aud": ["https://a.example.com/", "https://b.example.com/"],
+            }),
+        );
+    }
+
+    #[test]
+    fn serde_introspection_response_confirmation() {
+        let response = IntrospectionResponse {
+            active: true,
+            cnf: Some(Confirmation {
+                jkt: Some("0ZcOCORZNYy-DWpqq30jZyJGHTN0d2HglBV3uiguA4I".to_owned()),
+            }),
+            ..IntrospectionResponse::default()
+        };
+        assert_serde_json(
+            &response,
+            json!({
+                "active": true,
+                "cnf": {
+                    "jkt": "0ZcOCORZNYy-DWpqq30jZyJGHTN0d2HglBV3uiguA4I",
+                },
+            }),
+        );
+    }
+
+    #[test]
+    fn parse_jwt_response_modes() {
+        for (value, mode) in [
+            ("jwt", ResponseMode::Jwt),
+            ("query.jwt", ResponseMode::QueryJwt),
+            ("fragment.jwt", ResponseMode::FragmentJwt),
+            ("form_post.jwt", ResponseMode::FormPostJwt),
+        ] {
+            assert_eq!(value.parse::<ResponseMode>().unwrap(), mode);
+            assert_eq!(mode.to_string(), value);
+            assert!(mode.is_jwt());
+        }
+
+        assert!(!ResponseMode::Query.is_jwt());
+    }
+
     #[test]
     fn serialize_grant_type() {
         assert_eq!(
@@ -959,6 +1356,10 @@ mod tests {
             serde_json::to_string(&ResponseMode::FormPost).unwrap(),
             "\"form_post\""
         );
+        assert_eq!(
+            serde_json::to_string(&ResponseMode::WebMessage).unwrap(),
+            "\"web_message\""
+        );
     }
 
     #[test]
@@ -975,6 +1376,10 @@ mod tests {
             serde_json::from_str::<ResponseMode>("\"form_post\"").unwrap(),
             ResponseMode::FormPost
         );
+        assert_eq!(
+            serde_json::from_str::<ResponseMode>("\"web_message\"").unwrap(),
+            ResponseMode::WebMessage
+        );
     }
 
     #[test]
@@ -1046,4 +1451,34 @@ mod tests {
             Prompt::Create
         );
     }
+
+    #[test]
+    fn deserialize_max_age() {
+        let request: AuthorizationRequest = serde_json::from_value(json!({
+            "response_type": "code",
+            "client_id": "abcd",
+            "scope": "openid",
+            "max_age": "3600",
+        }))
+        .unwrap();
+        assert_eq!(request.max_age, Some(3600));
+
+        // `max_age=0` is valid, and means the user must authenticate again
+        let request: AuthorizationRequest = serde_json::from_value(json!({
+            "response_type": "code",
+            "client_id": "abcd",
+            "scope": "openid",
+            "max_age": "0",
+        }))
+        .unwrap();
+        assert_eq!(request.max_age, Some(0));
+
+        let request: AuthorizationRequest = serde_json::from_value(json!({
+            "response_type": "code",
+            "client_id": "abcd",
+            "scope": "openid",
+        }))
+        .unwrap();
+        assert_eq!(request.max_age, None);
+    }
 }
@@ -612,6 +612,10 @@ pub enum JwtVerificationError {
     /// requested.
     #[error("wrong signature alg")]
     WrongSignatureAlg,
+
+    /// The `typ` in the header of the JWT is not the expected one.
+    #[error("wrong JWT type")]
+    WrongType,
 }
 
 /// All possible errors when verifying an ID token.
@@ -276,7 +276,7 @@ fn build_authorization_request(
             nonce: Some(nonce.clone()),
             display,
             prompt,
-            max_age,
+            max_age: max_age.map(NonZeroU32::get),
             ui_locales,
             id_token_hint,
             login_hint,
@@ -284,6 +284,7 @@ fn build_authorization_request(
             request: None,
             request_uri: None,
             registration: None,
+            resource: None,
         },
         pkce,
     };
@@ -529,6 +530,7 @@ pub async fn access_token_with_authorization_code(
             code: code.clone(),
             redirect_uri: Some(validation_data.redirect_uri),
             code_verifier: validation_data.code_challenge_verifier,
+            resource: None,
         }),
         now,
         rng,
@@ -536,39 +538,71 @@ pub async fn access_token_with_authorization_code(
     .await?;
 
     let id_token = if let Some(verification_data) = id_token_verification_data {
-        let signing_alg = verification_data.signing_algorithm;
+        Some(verify_authorization_code_id_token(
+            &token_response,
+            &code,
+            &validation_data.nonce,
+            verification_data,
+            now,
+        )?)
+    } else {
+        None
+    };
 
-        let id_token = token_response
-            .id_token
-            .as_deref()
-            .ok_or(IdTokenError::MissingIdToken)?;
+    Ok((token_response, id_token))
+}
 
-        let id_token = verify_id_token(id_token, verification_data, None, now)?;
+/// Verify the ID Token returned by an authorization code exchange.
+///
+/// This is done by [`access_token_with_authorization_code()`] if it is given
+/// the verification data, but can be done separately, for example to choose
+/// the JWKS to use according to the header of the ID Token.
+///
+/// # Arguments
+///
+/// * `token_response` - The response of the Token endpoint.
+///
+/// * `code` - The authorization code which was exchanged.
+///
+/// * `nonce` - The nonce from the validation data that was returned when
+///   building the Authorization URL.
+///
+/// * `verification_data` - The data required to verify the ID Token.
+///
+/// * `now` - The current time.
+///
+/// # Errors
+///
+/// Returns an error if the ID Token is missing or its verification fails.
+pub fn verify_authorization_code_id_token(
+    token_response: &AccessTokenResponse,
+    code: &str,
+    nonce: &str,
+    verification_data: JwtVerificationData<'_>,
+    now: DateTime<Utc>,
+) -> Result<IdToken<'static>, IdTokenError> {
+    let signing_alg = verification_data.signing_algorithm;
 
-        let mut claims = id_token.payload().clone();
+    let id_token = token_response
+        .id_token
+        .as_deref()
+        .ok_or(IdTokenError::MissingIdToken)?;
 
-        // Access token hash must match.
-        claims::AT_HASH
-            .extract_optional_with_options(
-                &mut claims,
-                TokenHash::new(signing_alg, &token_response.access_token),
-            )
-            .map_err(IdTokenError::from)?;
+    let id_token = verify_id_token(id_token, verification_data, None, now)?;
 
-        // Code hash must match.
-        claims::C_HASH
-            .extract_optional_with_options(&mut claims, TokenHash::new(signing_alg, &code))
-            .map_err(IdTokenError::from)?;
+    let mut claims = id_token.payload().clone();
 
-        // Nonce must match.
-        claims::NONCE
-            .extract_required_with_options(&mut claims, validation_data.nonce.as_str())
-            .map_err(IdTokenError::from)?;
+    // Access token hash must match.
+    claims::AT_HASH.extract_optional_with_options(
+        &mut claims,
+        TokenHash::new(signing_alg, &token_response.access_token),
+    )?;
 
-        Some(id_token.into_owned())
-    } else {
-        None
-    };
+    // Code hash must match.
+    claims::C_HASH.extract_optional_with_options(&mut claims, TokenHash::new(signing_alg, code))?;
 
-    Ok((token_response, id_token))
+    // Nonce must match.
+    claims::NONCE.extract_required_with_options(&mut claims, nonce)?;
+
+    Ok(id_token.into_owned())
 }
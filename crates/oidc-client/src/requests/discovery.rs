@@ -16,7 +16,10 @@
 //!
 //! [Discovery]: https://openid.net/specs/openid-connect-discovery-1_0.html
 
+use std::time::Duration;
+
 use bytes::Bytes;
+use headers::{CacheControl, HeaderMapExt};
 use mas_http::{CatchHttpCodesLayer, JsonResponseLayer};
 use oauth2_types::oidc::{ProviderMetadata, VerifiedProviderMetadata};
 use tower::{Layer, Service, ServiceExt};
@@ -28,11 +31,12 @@ use crate::{
     utils::{http_all_error_status_codes, http_error_mapper},
 };
 
-/// Fetch the provider metadata.
+/// Fetch the provider metadata, along with how long it may be cached for
+/// according to the `Cache-Control` header of the response.
 async fn discover_inner(
     http_service: &HttpService,
     issuer: Url,
-) -> Result<ProviderMetadata, DiscoveryError> {
+) -> Result<(ProviderMetadata, Option<Duration>), DiscoveryError> {
     tracing::debug!("Fetching provider metadata...");
 
     let mut config_url = issuer;
@@ -58,7 +62,18 @@ async fn discover_inner(
     let response = service.ready_oneshot().await?.call(config_req).await?;
     tracing::debug!(?response);
 
-    Ok(response.into_body())
+    let max_age = response
+        .headers()
+        .typed_get::<CacheControl>()
+        .and_then(|cache_control| {
+            if cache_control.no_store() || cache_control.no_cache() {
+                Some(Duration::ZERO)
+            } else {
+                cache_control.max_age()
+            }
+        });
+
+    Ok((response.into_body(), max_age))
 }
 
 /// Fetch the provider metadata and validate it.
@@ -71,11 +86,31 @@ pub async fn discover(
     http_service: &HttpService,
     issuer: &str,
 ) -> Result<VerifiedProviderMetadata, DiscoveryError> {
-    let provider_metadata = discover_inner(http_service, issuer.parse()?).await?;
+    let (provider_metadata, _max_age) = discover_inner(http_service, issuer.parse()?).await?;
 
     Ok(provider_metadata.validate(issuer)?)
 }
 
+/// Fetch the provider metadata and validate it, returning how long it may be
+/// cached for.
+///
+/// The duration is taken from the `Cache-Control` header of the response. It
+/// is `None` if the header is missing or has no `max-age` directive, and zero
+/// if the response must not be cached.
+///
+/// # Errors
+///
+/// Returns an error if the request fails or if the data is invalid.
+#[tracing::instrument(skip_all, fields(issuer))]
+pub async fn discover_with_max_age(
+    http_service: &HttpService,
+    issuer: &str,
+) -> Result<(VerifiedProviderMetadata, Option<Duration>), DiscoveryError> {
+    let (provider_metadata, max_age) = discover_inner(http_service, issuer.parse()?).await?;
+
+    Ok((provider_metadata.validate(issuer)?, max_age))
+}
+
 /// Fetch the [provider metadata] and make basic checks.
 ///
 /// Contrary to [`discover()`], this uses
@@ -103,7 +138,33 @@ pub async fn insecure_discover(
     http_service: &HttpService,
     issuer: &str,
 ) -> Result<VerifiedProviderMetadata, DiscoveryError> {
-    let provider_metadata = discover_inner(http_service, issuer.parse()?).await?;
+    let (provider_metadata, _max_age) = discover_inner(http_service, issuer.parse()?).await?;
 
     Ok(provider_metadata.insecure_verify_metadata()?)
 }
+
+/// Fetch the [provider metadata] and make basic checks, returning how long it
+/// may be cached for.
+///
+/// This is the same as [`insecure_discover()`], with the cache duration
+/// returned like [`discover_with_max_age()`] does.
+///
+/// # Errors
+///
+/// Returns an error if the request fails or if the data is invalid.
+///
+/// # Warning
+///
+/// It is not recommended to use this method in production as it doesn't
+/// ensure that the issuer implements the proper security practices.
+///
+/// [provider metadata]: https://openid.net/specs/openid-connect-discovery-1_0.html
+#[tracing::instrument(skip_all, fields(issuer))]
+pub async fn insecure_discover_with_max_age(
+    http_service: &HttpService,
+    issuer: &str,
+) -> Result<(VerifiedProviderMetadata, Option<Duration>), DiscoveryError> {
+    let (provider_metadata, max_age) = discover_inner(http_service, issuer.parse()?).await?;
+
+    Ok((provider_metadata.insecure_verify_metadata()?, max_age))
+}
@@ -140,6 +140,69 @@ pub fn verify_signed_jwt<'a>(
     Ok(jwt)
 }
 
+/// The `typ` header of JWT access tokens, as per RFC9068.
+const ACCESS_TOKEN_TYPE: &str = "at+jwt";
+
+/// Decode and verify a JWT access token, as a resource server.
+///
+/// Besides the checks of [`verify_signed_jwt()`], the following checks are
+/// performed:
+///
+/// * The `typ` in the header must be `at+jwt`.
+///
+/// * The `exp` claim must be present and the token must not have expired.
+///
+/// Only access tokens restricted to the resource server with a resource
+/// indicator have its URL in their `aud` claim.
+///
+/// This can't tell whether the access token was revoked before it expired.
+/// Resource servers which need to know that should use the introspection
+/// endpoint instead.
+///
+/// # Arguments
+///
+/// * `access_token` - The serialized access token to decode and verify.
+///
+/// * `issuer` - The issuer of the access token.
+///
+/// * `jwks` - The issuer's JWKS.
+///
+/// * `audience` - The URL of the resource server.
+///
+/// * `now` - The current time.
+///
+/// # Errors
+///
+/// Returns an error if the data is invalid or verification fails.
+pub fn verify_access_token<'a>(
+    access_token: &'a str,
+    issuer: &str,
+    jwks: &PublicJsonWebKeySet,
+    audience: &String,
+    now: DateTime<Utc>,
+) -> Result<Jwt<'a, HashMap<String, Value>>, JwtVerificationError> {
+    // JWT access tokens are always signed with RS256
+    let verification_data = JwtVerificationData {
+        issuer,
+        jwks,
+        client_id: audience,
+        signing_algorithm: &JsonWebSignatureAlg::Rs256,
+    };
+    let access_token = verify_signed_jwt(access_token, verification_data)?;
+
+    // Must be typed as an access token, so that ID tokens can't be used instead.
+    if access_token.header().typ() != Some(ACCESS_TOKEN_TYPE) {
+        return Err(JwtVerificationError::WrongType);
+    }
+
+    let mut claims = access_token.payload().clone();
+
+    // Must not have expired.
+    claims::EXP.extract_required_with_options(&mut claims, &TimeOptions::new(now))?;
+
+    Ok(access_token)
+}
+
 /// Decode and verify an ID Token.
 ///
 /// Besides the checks of [`verify_signed_jwt()`], the following checks are
@@ -95,6 +95,7 @@ pub async fn refresh_access_token(
         AccessTokenRequest::RefreshToken(RefreshTokenGrant {
             refresh_token,
             scope,
+            resource: None,
         }),
         now,
         rng,
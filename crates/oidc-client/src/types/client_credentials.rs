@@ -655,7 +655,10 @@ mod test {
     async fn build_request_private_key_jwt() {
         let rng = rand_chacha::ChaCha8Rng::seed_from_u64(42);
         let key = PrivateKey::generate_rsa(rng).unwrap();
-        let keystore = Keystore::new(JsonWebKeySet::<PrivateKey>::new(vec![JsonWebKey::new(key)]));
+        let keystore = Keystore::new(JsonWebKeySet::<PrivateKey>::new(vec![
+            JsonWebKey::new(key).with_kid("private-key")
+        ]));
+        let public_jwks = keystore.public_jwks();
         let jwt_signing_method = JwtSigningMethod::with_keystore(keystore);
         let now = now();
         let mut rng = ChaCha8Rng::seed_from_u64(42);
@@ -680,7 +683,22 @@ mod test {
         let credentials = body.credentials.unwrap();
         assert_eq!(credentials.client_id, CLIENT_ID);
         assert_eq!(credentials.client_secret, None);
-        credentials.client_assertion.unwrap();
         credentials.client_assertion_type.unwrap();
+
+        // The assertion is signed by the key from the keystore, and identifies it
+        let client_assertion = credentials.client_assertion.unwrap();
+        let jwt = Jwt::<HashMap<String, Value>>::try_from(client_assertion.as_str()).unwrap();
+        assert_eq!(jwt.header().alg(), &JsonWebSignatureAlg::Rs256);
+        assert_eq!(jwt.header().kid(), Some("private-key"));
+        jwt.verify_with_jwks(&public_jwks).unwrap();
+
+        // The client authenticates itself to the token endpoint
+        let claims = jwt.payload();
+        assert_eq!(claims["iss"], CLIENT_ID);
+        assert_eq!(claims["sub"], CLIENT_ID);
+        assert_eq!(claims["aud"], "http://localhost/");
+        assert_eq!(claims["iat"], now.timestamp());
+        assert_eq!(claims["exp"], now.timestamp() + 300);
+        assert!(claims["jti"].is_string());
     }
 }
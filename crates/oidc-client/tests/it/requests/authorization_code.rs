@@ -37,7 +37,10 @@ use mas_oidc_client::{
     },
     types::scope::{ScopeExt, ScopeToken},
 };
-use oauth2_types::requests::{AccessTokenResponse, Display, Prompt, PushedAuthorizationResponse};
+use oauth2_types::{
+    pkce::CodeChallengeMethodExt,
+    requests::{AccessTokenResponse, Display, Prompt, PushedAuthorizationResponse},
+};
 use rand::SeedableRng;
 use tokio::sync::oneshot;
 use url::Url;
@@ -97,6 +100,50 @@ fn pass_authorization_url() {
     assert_eq!(query_pairs.get("code_challenge_method").unwrap(), "S256");
 }
 
+#[test]
+fn pass_authorization_url_pkce_challenge() {
+    let issuer = Url::parse("http://localhost/").unwrap();
+    let authorization_endpoint = issuer.join("authorize").unwrap();
+    let redirect_uri = Url::parse(REDIRECT_URI).unwrap();
+    let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(42);
+
+    let (url, validation_data) = build_authorization_url(
+        authorization_endpoint,
+        AuthorizationRequestData::new(
+            CLIENT_ID.to_owned(),
+            [ScopeToken::Openid].into_iter().collect(),
+            redirect_uri,
+        )
+        .with_code_challenge_methods_supported(vec![
+            PkceCodeChallengeMethod::Plain,
+            PkceCodeChallengeMethod::S256,
+        ]),
+        &mut rng,
+    )
+    .unwrap();
+
+    // The verifier is 32 random bytes, encoded in unpadded base64url
+    let verifier = validation_data.code_challenge_verifier.unwrap();
+    assert_eq!(verifier.len(), 43);
+    assert!(verifier
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'));
+
+    // S256 is always preferred, and the challenge is derived from the verifier
+    let query_pairs = url.query_pairs().collect::<HashMap<_, _>>();
+    assert_eq!(query_pairs.get("code_challenge_method").unwrap(), "S256");
+    let code_challenge = query_pairs.get("code_challenge").unwrap();
+    assert_eq!(
+        PkceCodeChallengeMethod::S256
+            .compute_challenge(&verifier)
+            .unwrap(),
+        *code_challenge
+    );
+    PkceCodeChallengeMethod::S256
+        .verify(code_challenge, &verifier)
+        .unwrap();
+}
+
 #[test]
 fn pass_full_authorization_url() {
     let issuer = Url::parse("http://localhost/").unwrap();
@@ -334,6 +381,7 @@ async fn pass_access_token_with_authorization_code() {
                 token_type: OAuthAccessTokenType::Bearer,
                 expires_in: None,
                 scope: Some([ScopeToken::Openid].into_iter().collect()),
+                issued_token_type: None,
             }),
         )
         .mount(&mock_server)
@@ -393,6 +441,7 @@ async fn fail_access_token_with_authorization_code_wrong_nonce() {
                 token_type: OAuthAccessTokenType::Bearer,
                 expires_in: None,
                 scope: Some([ScopeToken::Openid].into_iter().collect()),
+                issued_token_type: None,
             }),
         )
         .mount(&mock_server)
@@ -455,6 +504,7 @@ async fn fail_access_token_with_authorization_code_no_id_token() {
                 token_type: OAuthAccessTokenType::Bearer,
                 expires_in: None,
                 scope: Some([ScopeToken::Openid].into_iter().collect()),
+                issued_token_type: None,
             }),
         )
         .mount(&mock_server)
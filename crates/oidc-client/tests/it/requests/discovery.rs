@@ -12,12 +12,14 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::time::Duration;
+
 use assert_matches::assert_matches;
 use mas_iana::oauth::{OAuthAuthorizationEndpointResponseType, PkceCodeChallengeMethod};
 use mas_jose::jwa::SUPPORTED_SIGNING_ALGORITHMS;
 use mas_oidc_client::{
     error::DiscoveryError,
-    requests::discovery::{discover, insecure_discover},
+    requests::discovery::{discover, insecure_discover, insecure_discover_with_max_age},
 };
 use oauth2_types::oidc::{ProviderMetadata, SubjectType};
 use url::Url;
@@ -59,6 +61,29 @@ async fn pass_discover() {
     assert_eq!(provider_metadata.issuer(), issuer.as_str());
 }
 
+#[tokio::test]
+async fn pass_discover_max_age() {
+    let (http_service, mock_server, issuer) = init_test().await;
+
+    Mock::given(method("GET"))
+        .and(path("/.well-known/openid-configuration"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header("cache-control", "public, max-age=600")
+                .set_body_json(provider_metadata(&issuer)),
+        )
+        .mount(&mock_server)
+        .await;
+
+    let (provider_metadata, max_age) =
+        insecure_discover_with_max_age(&http_service, issuer.as_str())
+            .await
+            .unwrap();
+
+    assert_eq!(provider_metadata.issuer(), issuer.as_str());
+    assert_eq!(max_age, Some(Duration::from_secs(600)));
+}
+
 #[tokio::test]
 async fn fail_discover_404() {
     let (http_service, _mock_server, issuer) = init_test().await;
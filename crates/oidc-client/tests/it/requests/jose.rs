@@ -25,7 +25,7 @@ use mas_jose::{
 };
 use mas_oidc_client::{
     error::{IdTokenError, JwtVerificationError},
-    requests::jose::{verify_id_token, JwtVerificationData},
+    requests::jose::{verify_access_token, verify_id_token, JwtVerificationData},
     types::IdToken,
 };
 
@@ -248,3 +248,97 @@ async fn fail_verify_id_token_wrong_auth_time() {
 
     assert_matches!(error, IdTokenError::WrongAuthTime);
 }
+
+const RESOURCE: &str = "https://api.localhost/";
+
+/// Generate a JWT access token for the given resource, expiring at `exp`.
+fn access_token(issuer: &str, exp: DateTime<Utc>) -> (String, PublicJsonWebKeySet) {
+    let signing_alg = JsonWebSignatureAlg::Rs256;
+
+    let keystore = keystore(&signing_alg);
+    let mut claims = HashMap::new();
+
+    claims::ISS.insert(&mut claims, issuer.to_owned()).unwrap();
+    claims::AUD
+        .insert(&mut claims, RESOURCE.to_owned())
+        .unwrap();
+    claims::SUB
+        .insert(&mut claims, SUBJECT_IDENTIFIER.to_owned())
+        .unwrap();
+    claims::CLIENT_ID
+        .insert(&mut claims, CLIENT_ID.to_owned())
+        .unwrap();
+    claims::IAT.insert(&mut claims, now()).unwrap();
+    claims::EXP.insert(&mut claims, exp).unwrap();
+
+    let key = keystore.signing_key_for_algorithm(&signing_alg).unwrap();
+    let signer = key.params().signing_key_for_alg(&signing_alg).unwrap();
+    let header = JsonWebSignatureHeader::new(signing_alg)
+        .with_kid(key.kid().unwrap())
+        .with_typ("at+jwt".to_owned());
+    let access_token = Jwt::sign(header, claims, &signer).unwrap();
+
+    (access_token.into_string(), keystore.public_jwks())
+}
+
+#[tokio::test]
+async fn pass_verify_access_token() {
+    let issuer = "http://localhost/";
+    let now = now();
+    let (access_token, jwks) = access_token(issuer, now + Duration::try_minutes(5).unwrap());
+
+    let access_token =
+        verify_access_token(&access_token, issuer, &jwks, &RESOURCE.to_owned(), now).unwrap();
+
+    let mut claims = access_token.payload().clone();
+    assert_eq!(
+        claims::CLIENT_ID.extract_required(&mut claims).unwrap(),
+        CLIENT_ID
+    );
+}
+
+#[tokio::test]
+async fn fail_verify_access_token_wrong_audience() {
+    let issuer = "http://localhost/";
+    let now = now();
+    let (access_token, jwks) = access_token(issuer, now + Duration::try_minutes(5).unwrap());
+
+    let error = verify_access_token(
+        &access_token,
+        issuer,
+        &jwks,
+        &"https://other.localhost/".to_owned(),
+        now,
+    )
+    .unwrap_err();
+
+    assert_matches!(
+        error,
+        JwtVerificationError::Claim(ClaimError::ValidationError { claim: "aud", .. })
+    );
+}
+
+#[tokio::test]
+async fn fail_verify_access_token_expired() {
+    let issuer = "http://localhost/";
+    let now = now();
+    let (access_token, jwks) = access_token(issuer, now - Duration::try_minutes(5).unwrap());
+
+    let error =
+        verify_access_token(&access_token, issuer, &jwks, &RESOURCE.to_owned(), now).unwrap_err();
+
+    assert_matches!(error, JwtVerificationError::Claim(_));
+}
+
+#[tokio::test]
+async fn fail_verify_access_token_id_token() {
+    let issuer = "http://localhost/";
+    let now = now();
+    let (id_token, jwks) = id_token(issuer, None, None);
+
+    // ID tokens are not access tokens, even with the right audience
+    let error = verify_access_token(id_token.as_str(), issuer, &jwks, &CLIENT_ID.to_owned(), now)
+        .unwrap_err();
+
+    assert_matches!(error, JwtVerificationError::WrongType);
+}
@@ -94,6 +94,14 @@ impl SimpleRoute for OidcConfiguration {
     const PATH: &'static str = "/.well-known/openid-configuration";
 }
 
+/// `GET /.well-known/oauth-authorization-server`
+#[derive(Default, Debug, Clone)]
+pub struct OAuth2AuthorizationServerMetadata;
+
+impl SimpleRoute for OAuth2AuthorizationServerMetadata {
+    const PATH: &'static str = "/.well-known/oauth-authorization-server";
+}
+
 /// `GET /.well-known/webfinger`
 #[derive(Default, Debug, Clone)]
 pub struct Webfinger;
@@ -157,6 +165,14 @@ impl SimpleRoute for OAuth2RegistrationEndpoint {
     const PATH: &'static str = "/oauth2/registration";
 }
 
+/// `POST /oauth2/par`
+#[derive(Default, Debug, Clone)]
+pub struct OAuth2PushedAuthorizationEndpoint;
+
+impl SimpleRoute for OAuth2PushedAuthorizationEndpoint {
+    const PATH: &'static str = "/oauth2/par";
+}
+
 /// `GET /authorize`
 #[derive(Default, Debug, Clone)]
 pub struct OAuth2AuthorizationEndpoint;
@@ -165,6 +181,14 @@ impl SimpleRoute for OAuth2AuthorizationEndpoint {
     const PATH: &'static str = "/authorize";
 }
 
+/// `GET|POST /oauth2/logout`
+#[derive(Default, Debug, Clone)]
+pub struct OAuth2EndSessionEndpoint;
+
+impl SimpleRoute for OAuth2EndSessionEndpoint {
+    const PATH: &'static str = "/oauth2/logout";
+}
+
 /// `GET /`
 #[derive(Default, Debug, Clone)]
 pub struct Index;
@@ -255,6 +279,45 @@ impl From<Option<PostAuthAction>> for Login {
     }
 }
 
+/// `GET|POST /login/totp`
+#[derive(Default, Debug, Clone)]
+pub struct LoginTotp {
+    post_auth_action: Option<PostAuthAction>,
+}
+
+impl Route for LoginTotp {
+    type Query = PostAuthAction;
+
+    fn route() -> &'static str {
+        "/login/totp"
+    }
+
+    fn query(&self) -> Option<&Self::Query> {
+        self.post_auth_action.as_ref()
+    }
+}
+
+impl LoginTotp {
+    #[must_use]
+    pub const fn and_then(action: PostAuthAction) -> Self {
+        Self {
+            post_auth_action: Some(action),
+        }
+    }
+
+    /// Get a reference to the post auth action.
+    #[must_use]
+    pub fn post_auth_action(&self) -> Option<&PostAuthAction> {
+        self.post_auth_action.as_ref()
+    }
+}
+
+impl From<Option<PostAuthAction>> for LoginTotp {
+    fn from(post_auth_action: Option<PostAuthAction>) -> Self {
+        Self { post_auth_action }
+    }
+}
+
 /// `POST /logout`
 #[derive(Default, Debug, Clone)]
 pub struct Logout;
@@ -514,6 +577,29 @@ impl SimpleRoute for AccountPassword {
     const PATH: &'static str = "/change-password";
 }
 
+/// `GET|POST /reset-password`
+#[derive(Default, Debug, Clone)]
+pub struct ResetPasswordRequest;
+
+impl SimpleRoute for ResetPasswordRequest {
+    const PATH: &'static str = "/reset-password";
+}
+
+/// `GET|POST /reset-password/:token`
+#[derive(Debug, Clone)]
+pub struct ResetPassword(pub String);
+
+impl Route for ResetPassword {
+    type Query = ();
+    fn route() -> &'static str {
+        "/reset-password/:token"
+    }
+
+    fn path(&self) -> std::borrow::Cow<'static, str> {
+        format!("/reset-password/{}", self.0).into()
+    }
+}
+
 /// `GET /authorize/:grant_id`
 #[derive(Debug, Clone)]
 pub struct ContinueAuthorizationGrant(pub Ulid);
@@ -136,6 +136,12 @@ impl UrlBuilder {
         self.absolute_url_for(&crate::endpoints::OAuth2AuthorizationEndpoint)
     }
 
+    /// OIDC RP-initiated logout endpoint
+    #[must_use]
+    pub fn oidc_end_session_endpoint(&self) -> Url {
+        self.absolute_url_for(&crate::endpoints::OAuth2EndSessionEndpoint)
+    }
+
     /// OAuth 2.0 token endpoint
     #[must_use]
     pub fn oauth_token_endpoint(&self) -> Url {
@@ -160,6 +166,12 @@ impl UrlBuilder {
         self.absolute_url_for(&crate::endpoints::OAuth2RegistrationEndpoint)
     }
 
+    /// OAuth 2.0 pushed authorization request endpoint
+    #[must_use]
+    pub fn oauth_pushed_authorization_request_endpoint(&self) -> Url {
+        self.absolute_url_for(&crate::endpoints::OAuth2PushedAuthorizationEndpoint)
+    }
+
     /// OAuth 2.0 device authorization endpoint
     #[must_use]
     pub fn oauth_device_authorization_endpoint(&self) -> Url {
@@ -0,0 +1,116 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! An in-memory implementation of the [`AppSessionRepository`], which lists
+//! both the OAuth 2.0 and the compatibility sessions
+
+use async_trait::async_trait;
+use mas_data_model::{CompatSession, Session};
+use mas_storage::{
+    app_session::{AppSession, AppSessionFilter, AppSessionRepository},
+    Page, Pagination,
+};
+use ulid::Ulid;
+
+use crate::{pagination::paginate, MemoryError, MemoryStore};
+
+/// An implementation of [`AppSessionRepository`] for the in-memory store
+pub(crate) struct MemoryAppSessionRepository<'c> {
+    store: &'c mut MemoryStore,
+}
+
+impl<'c> MemoryAppSessionRepository<'c> {
+    /// Create a new [`MemoryAppSessionRepository`] from the in-memory store
+    pub(crate) fn new(store: &'c mut MemoryStore) -> Self {
+        Self { store }
+    }
+
+    /// Get the sessions matching the filter, in no particular order
+    fn filtered<'a>(
+        &'a self,
+        filter: &'a AppSessionFilter<'a>,
+    ) -> impl Iterator<Item = AppSession> + 'a {
+        let oauth2_sessions = self
+            .store
+            .oauth2_sessions
+            .values()
+            .filter(move |session| oauth2_session_matches_filter(session, filter))
+            .map(|session| AppSession::OAuth2(Box::new(session.clone())));
+
+        let compat_sessions = self
+            .store
+            .compat_sessions
+            .values()
+            .filter(move |session| compat_session_matches_filter(session, filter))
+            .map(|session| AppSession::Compat(Box::new(session.clone())));
+
+        oauth2_sessions.chain(compat_sessions)
+    }
+}
+
+fn oauth2_session_matches_filter(session: &Session, filter: &AppSessionFilter<'_>) -> bool {
+    filter
+        .user()
+        .map_or(true, |user| session.user_id == Some(user.id))
+        && filter.browser_session().map_or(true, |browser_session| {
+            session.user_session_id == Some(browser_session.id)
+        })
+        && filter
+            .state()
+            .map_or(true, |state| state.is_active() == session.is_valid())
+        && filter.device().map_or(true, |device| {
+            session.scope.contains(&device.to_scope_token())
+        })
+}
+
+fn compat_session_matches_filter(session: &CompatSession, filter: &AppSessionFilter<'_>) -> bool {
+    filter
+        .user()
+        .map_or(true, |user| session.user_id == user.id)
+        && filter.browser_session().map_or(true, |browser_session| {
+            session.user_session_id == Some(browser_session.id)
+        })
+        && filter
+            .state()
+            .map_or(true, |state| state.is_active() == session.is_valid())
+        && filter
+            .device()
+            .map_or(true, |device| session.device == *device)
+}
+
+fn app_session_id(session: &AppSession) -> Ulid {
+    match session {
+        AppSession::Compat(session) => session.id,
+        AppSession::OAuth2(session) => session.id,
+    }
+}
+
+#[async_trait]
+impl<'c> AppSessionRepository for MemoryAppSessionRepository<'c> {
+    type Error = MemoryError;
+
+    async fn list(
+        &mut self,
+        filter: AppSessionFilter<'_>,
+        pagination: Pagination,
+    ) -> Result<Page<AppSession>, Self::Error> {
+        let sessions = self.filtered(&filter);
+
+        Ok(paginate(sessions, pagination, app_session_id))
+    }
+
+    async fn count(&mut self, filter: AppSessionFilter<'_>) -> Result<usize, Self::Error> {
+        Ok(self.filtered(&filter).count())
+    }
+}
@@ -0,0 +1,92 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use mas_data_model::{AuditLogEntry, User};
+use mas_storage::{
+    audit_log::{AuditLogParams, AuditLogRepository},
+    Clock, Page, Pagination,
+};
+use rand_core::RngCore;
+use ulid::Ulid;
+
+use crate::{pagination::paginate, MemoryError, MemoryStore};
+
+/// An implementation of [`AuditLogRepository`] for the in-memory store
+pub(crate) struct MemoryAuditLogRepository<'c> {
+    store: &'c mut MemoryStore,
+}
+
+impl<'c> MemoryAuditLogRepository<'c> {
+    /// Create a new [`MemoryAuditLogRepository`] from the in-memory store
+    pub(crate) fn new(store: &'c mut MemoryStore) -> Self {
+        Self { store }
+    }
+}
+
+#[async_trait]
+impl<'c> AuditLogRepository for MemoryAuditLogRepository<'c> {
+    type Error = MemoryError;
+
+    async fn record(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        params: AuditLogParams,
+    ) -> Result<AuditLogEntry, Self::Error> {
+        let created_at = clock.now();
+        let id = Ulid::from_datetime_with_source(created_at.into(), rng);
+
+        let entry = AuditLogEntry {
+            id,
+            created_at,
+            actor_user_id: params.actor_user_id,
+            actor_session_id: params.actor_session_id,
+            actor_session_kind: params.actor_session_kind,
+            action: params.action,
+            target_user_id: params.target_user_id,
+            metadata: params.metadata,
+        };
+
+        self.store.audit_log_entries.insert(id, entry.clone());
+
+        Ok(entry)
+    }
+
+    async fn list_for_user(
+        &mut self,
+        user: &User,
+        pagination: Pagination,
+    ) -> Result<Page<AuditLogEntry>, Self::Error> {
+        let entries = self
+            .store
+            .audit_log_entries
+            .values()
+            .filter(|entry| {
+                entry.actor_user_id == Some(user.id) || entry.target_user_id == Some(user.id)
+            })
+            .cloned();
+
+        Ok(paginate(entries, pagination, |entry| entry.id))
+    }
+
+    async fn cleanup(&mut self, before: DateTime<Utc>) -> Result<usize, Self::Error> {
+        let count = self.store.audit_log_entries.len();
+        self.store
+            .audit_log_entries
+            .retain(|_, entry| entry.created_at >= before);
+        Ok(count - self.store.audit_log_entries.len())
+    }
+}
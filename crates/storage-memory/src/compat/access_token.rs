@@ -0,0 +1,102 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use async_trait::async_trait;
+use chrono::Duration;
+use mas_data_model::{CompatAccessToken, CompatSession};
+use mas_storage::{compat::CompatAccessTokenRepository, Clock};
+use rand_core::RngCore;
+use ulid::Ulid;
+
+use crate::{MemoryError, MemoryStore};
+
+/// An implementation of [`CompatAccessTokenRepository`] for the in-memory
+/// store
+pub(crate) struct MemoryCompatAccessTokenRepository<'c> {
+    store: &'c mut MemoryStore,
+}
+
+impl<'c> MemoryCompatAccessTokenRepository<'c> {
+    /// Create a new [`MemoryCompatAccessTokenRepository`] from the in-memory
+    /// store
+    pub(crate) fn new(store: &'c mut MemoryStore) -> Self {
+        Self { store }
+    }
+}
+
+#[async_trait]
+impl<'c> CompatAccessTokenRepository for MemoryCompatAccessTokenRepository<'c> {
+    type Error = MemoryError;
+
+    async fn lookup(&mut self, id: Ulid) -> Result<Option<CompatAccessToken>, Self::Error> {
+        Ok(self.store.compat_access_tokens.get(&id).cloned())
+    }
+
+    async fn find_by_token(
+        &mut self,
+        access_token: &str,
+    ) -> Result<Option<CompatAccessToken>, Self::Error> {
+        Ok(self
+            .store
+            .compat_access_tokens
+            .values()
+            .find(|token| token.token == access_token)
+            .cloned())
+    }
+
+    async fn add(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        compat_session: &CompatSession,
+        token: String,
+        expires_after: Option<Duration>,
+    ) -> Result<CompatAccessToken, Self::Error> {
+        let created_at = clock.now();
+        let id = Ulid::from_datetime_with_source(created_at.into(), rng);
+
+        let compat_access_token = CompatAccessToken {
+            id,
+            session_id: compat_session.id,
+            token,
+            created_at,
+            expires_at: expires_after.map(|expires_after| created_at + expires_after),
+        };
+
+        self.store
+            .compat_access_tokens
+            .insert(id, compat_access_token.clone());
+
+        Ok(compat_access_token)
+    }
+
+    async fn expire(
+        &mut self,
+        clock: &dyn Clock,
+        mut compat_access_token: CompatAccessToken,
+    ) -> Result<CompatAccessToken, Self::Error> {
+        let expires_at = clock.now();
+
+        let row = self
+            .store
+            .compat_access_tokens
+            .get_mut(&compat_access_token.id)
+            .ok_or(MemoryError::not_found())?;
+
+        row.expires_at = Some(expires_at);
+        compat_access_token.expires_at = Some(expires_at);
+
+        Ok(compat_access_token)
+    }
+}
@@ -0,0 +1,105 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use async_trait::async_trait;
+use mas_data_model::{
+    CompatAccessToken, CompatRefreshToken, CompatRefreshTokenState, CompatSession,
+};
+use mas_storage::{compat::CompatRefreshTokenRepository, Clock};
+use rand_core::RngCore;
+use ulid::Ulid;
+
+use crate::{MemoryError, MemoryStore};
+
+/// An implementation of [`CompatRefreshTokenRepository`] for the in-memory
+/// store
+pub(crate) struct MemoryCompatRefreshTokenRepository<'c> {
+    store: &'c mut MemoryStore,
+}
+
+impl<'c> MemoryCompatRefreshTokenRepository<'c> {
+    /// Create a new [`MemoryCompatRefreshTokenRepository`] from the in-memory
+    /// store
+    pub(crate) fn new(store: &'c mut MemoryStore) -> Self {
+        Self { store }
+    }
+}
+
+#[async_trait]
+impl<'c> CompatRefreshTokenRepository for MemoryCompatRefreshTokenRepository<'c> {
+    type Error = MemoryError;
+
+    async fn lookup(&mut self, id: Ulid) -> Result<Option<CompatRefreshToken>, Self::Error> {
+        Ok(self.store.compat_refresh_tokens.get(&id).cloned())
+    }
+
+    async fn find_by_token(
+        &mut self,
+        refresh_token: &str,
+    ) -> Result<Option<CompatRefreshToken>, Self::Error> {
+        Ok(self
+            .store
+            .compat_refresh_tokens
+            .values()
+            .find(|token| token.token == refresh_token)
+            .cloned())
+    }
+
+    async fn add(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        compat_session: &CompatSession,
+        compat_access_token: &CompatAccessToken,
+        token: String,
+    ) -> Result<CompatRefreshToken, Self::Error> {
+        let created_at = clock.now();
+        let id = Ulid::from_datetime_with_source(created_at.into(), rng);
+
+        let compat_refresh_token = CompatRefreshToken {
+            id,
+            state: CompatRefreshTokenState::default(),
+            session_id: compat_session.id,
+            access_token_id: compat_access_token.id,
+            token,
+            created_at,
+        };
+
+        self.store
+            .compat_refresh_tokens
+            .insert(id, compat_refresh_token.clone());
+
+        Ok(compat_refresh_token)
+    }
+
+    async fn consume(
+        &mut self,
+        clock: &dyn Clock,
+        compat_refresh_token: CompatRefreshToken,
+    ) -> Result<CompatRefreshToken, Self::Error> {
+        let row = self
+            .store
+            .compat_refresh_tokens
+            .get_mut(&compat_refresh_token.id)
+            .ok_or(MemoryError::not_found())?;
+
+        let compat_refresh_token = compat_refresh_token
+            .consume(clock.now())
+            .map_err(MemoryError::to_invalid_operation)?;
+
+        row.state = compat_refresh_token.state.clone();
+
+        Ok(compat_refresh_token)
+    }
+}
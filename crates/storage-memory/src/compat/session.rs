@@ -0,0 +1,192 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::net::IpAddr;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use mas_data_model::{
+    BrowserSession, CompatSession, CompatSessionState, CompatSsoLogin, Device, User, UserAgent,
+};
+use mas_storage::{
+    compat::{CompatSessionFilter, CompatSessionRepository},
+    Clock, Page, Pagination,
+};
+use rand_core::RngCore;
+use ulid::Ulid;
+
+use crate::{pagination::paginate, MemoryError, MemoryStore};
+
+/// An implementation of [`CompatSessionRepository`] for the in-memory store
+pub(crate) struct MemoryCompatSessionRepository<'c> {
+    store: &'c mut MemoryStore,
+}
+
+impl<'c> MemoryCompatSessionRepository<'c> {
+    /// Create a new [`MemoryCompatSessionRepository`] from the in-memory store
+    pub(crate) fn new(store: &'c mut MemoryStore) -> Self {
+        Self { store }
+    }
+
+    /// Get the sessions matching the filter, along with the SSO login which
+    /// created them, if any
+    fn filtered<'a>(
+        &'a self,
+        filter: &'a CompatSessionFilter<'a>,
+    ) -> impl Iterator<Item = (&'a CompatSession, Option<&'a CompatSsoLogin>)> + 'a {
+        self.store
+            .compat_sessions
+            .values()
+            .map(move |session| {
+                let sso_login = self
+                    .store
+                    .compat_sso_logins
+                    .values()
+                    .find(|login| login.session_id() == Some(session.id));
+                (session, sso_login)
+            })
+            .filter(move |(session, sso_login)| {
+                filter
+                    .user()
+                    .map_or(true, |user| session.user_id == user.id)
+                    && filter
+                        .state()
+                        .map_or(true, |state| state.is_active() == session.is_valid())
+                    && filter.auth_type().map_or(true, |auth_type| {
+                        auth_type.is_sso_login() == sso_login.is_some()
+                    })
+                    && filter
+                        .device()
+                        .map_or(true, |device| session.device == *device)
+                    && filter.is_synapse_admin().map_or(true, |is_synapse_admin| {
+                        session.is_synapse_admin == is_synapse_admin
+                    })
+            })
+    }
+}
+
+#[async_trait]
+impl<'c> CompatSessionRepository for MemoryCompatSessionRepository<'c> {
+    type Error = MemoryError;
+
+    async fn lookup(&mut self, id: Ulid) -> Result<Option<CompatSession>, Self::Error> {
+        Ok(self.store.compat_sessions.get(&id).cloned())
+    }
+
+    async fn add(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        user: &User,
+        device: Device,
+        browser_session: Option<&BrowserSession>,
+        is_synapse_admin: bool,
+    ) -> Result<CompatSession, Self::Error> {
+        let created_at = clock.now();
+        let id = Ulid::from_datetime_with_source(created_at.into(), rng);
+
+        let compat_session = CompatSession {
+            id,
+            state: CompatSessionState::default(),
+            user_id: user.id,
+            device,
+            user_session_id: browser_session.map(|s| s.id),
+            created_at,
+            is_synapse_admin,
+            user_agent: None,
+            last_active_at: None,
+            last_active_ip: None,
+        };
+
+        self.store
+            .compat_sessions
+            .insert(id, compat_session.clone());
+
+        Ok(compat_session)
+    }
+
+    async fn finish(
+        &mut self,
+        clock: &dyn Clock,
+        compat_session: CompatSession,
+    ) -> Result<CompatSession, Self::Error> {
+        let finished_at = clock.now();
+
+        let row = self
+            .store
+            .compat_sessions
+            .get_mut(&compat_session.id)
+            .ok_or(MemoryError::not_found())?;
+
+        let compat_session = compat_session
+            .finish(finished_at)
+            .map_err(MemoryError::to_invalid_operation)?;
+
+        row.state = compat_session.state.clone();
+
+        Ok(compat_session)
+    }
+
+    async fn list(
+        &mut self,
+        filter: CompatSessionFilter<'_>,
+        pagination: Pagination,
+    ) -> Result<Page<(CompatSession, Option<CompatSsoLogin>)>, Self::Error> {
+        let edges = self
+            .filtered(&filter)
+            .map(|(session, sso_login)| (session.clone(), sso_login.cloned()));
+
+        Ok(paginate(edges, pagination, |(session, _)| session.id))
+    }
+
+    async fn count(&mut self, filter: CompatSessionFilter<'_>) -> Result<usize, Self::Error> {
+        Ok(self.filtered(&filter).count())
+    }
+
+    async fn record_batch_activity(
+        &mut self,
+        activity: Vec<(Ulid, DateTime<Utc>, Option<IpAddr>)>,
+    ) -> Result<(), Self::Error> {
+        let expected = activity.len();
+        let mut actual = 0;
+        for (id, last_active_at, last_active_ip) in activity {
+            let Some(session) = self.store.compat_sessions.get_mut(&id) else {
+                continue;
+            };
+
+            session.last_active_at = session.last_active_at.max(Some(last_active_at));
+            session.last_active_ip = last_active_ip.or(session.last_active_ip);
+            actual += 1;
+        }
+
+        MemoryError::ensure_affected_rows(actual, expected)
+    }
+
+    async fn record_user_agent(
+        &mut self,
+        mut compat_session: CompatSession,
+        user_agent: UserAgent,
+    ) -> Result<CompatSession, Self::Error> {
+        let row = self
+            .store
+            .compat_sessions
+            .get_mut(&compat_session.id)
+            .ok_or(MemoryError::not_found())?;
+
+        row.user_agent = Some(user_agent.clone());
+        compat_session.user_agent = Some(user_agent);
+
+        Ok(compat_session)
+    }
+}
@@ -0,0 +1,172 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use async_trait::async_trait;
+use mas_data_model::{CompatSession, CompatSsoLogin, CompatSsoLoginState};
+use mas_storage::{
+    compat::{CompatSsoLoginFilter, CompatSsoLoginRepository},
+    Clock, Page, Pagination,
+};
+use rand_core::RngCore;
+use ulid::Ulid;
+use url::Url;
+
+use crate::{pagination::paginate, MemoryError, MemoryStore};
+
+/// An implementation of [`CompatSsoLoginRepository`] for the in-memory store
+pub(crate) struct MemoryCompatSsoLoginRepository<'c> {
+    store: &'c mut MemoryStore,
+}
+
+impl<'c> MemoryCompatSsoLoginRepository<'c> {
+    /// Create a new [`MemoryCompatSsoLoginRepository`] from the in-memory
+    /// store
+    pub(crate) fn new(store: &'c mut MemoryStore) -> Self {
+        Self { store }
+    }
+
+    fn filtered<'a>(
+        &'a self,
+        filter: &'a CompatSsoLoginFilter<'a>,
+    ) -> impl Iterator<Item = &'a CompatSsoLogin> + 'a {
+        self.store.compat_sso_logins.values().filter(move |login| {
+            filter.user().map_or(true, |user| {
+                login
+                    .session_id()
+                    .and_then(|session_id| self.store.compat_sessions.get(&session_id))
+                    .is_some_and(|session| session.user_id == user.id)
+            }) && filter.state().map_or(true, |state| {
+                if state.is_exchanged() {
+                    login.is_exchanged()
+                } else if state.is_fulfilled() {
+                    login.is_fulfilled()
+                } else {
+                    login.is_pending()
+                }
+            })
+        })
+    }
+
+    /// Replace the stored SSO login, making sure it exists
+    fn update(&mut self, compat_sso_login: &CompatSsoLogin) -> Result<(), MemoryError> {
+        let row = self
+            .store
+            .compat_sso_logins
+            .get_mut(&compat_sso_login.id)
+            .ok_or(MemoryError::not_found())?;
+
+        row.state = compat_sso_login.state.clone();
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<'c> CompatSsoLoginRepository for MemoryCompatSsoLoginRepository<'c> {
+    type Error = MemoryError;
+
+    async fn lookup(&mut self, id: Ulid) -> Result<Option<CompatSsoLogin>, Self::Error> {
+        Ok(self.store.compat_sso_logins.get(&id).cloned())
+    }
+
+    async fn find_for_session(
+        &mut self,
+        session: &CompatSession,
+    ) -> Result<Option<CompatSsoLogin>, Self::Error> {
+        Ok(self
+            .store
+            .compat_sso_logins
+            .values()
+            .find(|login| login.session_id() == Some(session.id))
+            .cloned())
+    }
+
+    async fn find_by_token(
+        &mut self,
+        login_token: &str,
+    ) -> Result<Option<CompatSsoLogin>, Self::Error> {
+        Ok(self
+            .store
+            .compat_sso_logins
+            .values()
+            .find(|login| login.login_token == login_token)
+            .cloned())
+    }
+
+    async fn add(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        login_token: String,
+        redirect_uri: Url,
+    ) -> Result<CompatSsoLogin, Self::Error> {
+        let created_at = clock.now();
+        let id = Ulid::from_datetime_with_source(created_at.into(), rng);
+
+        let compat_sso_login = CompatSsoLogin {
+            id,
+            login_token,
+            redirect_uri,
+            created_at,
+            state: CompatSsoLoginState::default(),
+        };
+
+        self.store
+            .compat_sso_logins
+            .insert(id, compat_sso_login.clone());
+
+        Ok(compat_sso_login)
+    }
+
+    async fn fulfill(
+        &mut self,
+        clock: &dyn Clock,
+        compat_sso_login: CompatSsoLogin,
+        compat_session: &CompatSession,
+    ) -> Result<CompatSsoLogin, Self::Error> {
+        let compat_sso_login = compat_sso_login
+            .fulfill(clock.now(), compat_session)
+            .map_err(MemoryError::to_invalid_operation)?;
+
+        self.update(&compat_sso_login)?;
+
+        Ok(compat_sso_login)
+    }
+
+    async fn exchange(
+        &mut self,
+        clock: &dyn Clock,
+        compat_sso_login: CompatSsoLogin,
+    ) -> Result<CompatSsoLogin, Self::Error> {
+        let compat_sso_login = compat_sso_login
+            .exchange(clock.now())
+            .map_err(MemoryError::to_invalid_operation)?;
+
+        self.update(&compat_sso_login)?;
+
+        Ok(compat_sso_login)
+    }
+
+    async fn list(
+        &mut self,
+        filter: CompatSsoLoginFilter<'_>,
+        pagination: Pagination,
+    ) -> Result<Page<CompatSsoLogin>, Self::Error> {
+        let logins = self.filtered(&filter).cloned();
+        Ok(paginate(logins, pagination, |login| login.id))
+    }
+
+    async fn count(&mut self, filter: CompatSsoLoginFilter<'_>) -> Result<usize, Self::Error> {
+        Ok(self.filtered(&filter).count())
+    }
+}
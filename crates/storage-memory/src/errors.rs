@@ -0,0 +1,86 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use thiserror::Error;
+use ulid::Ulid;
+
+/// Generic error when interacting with the in-memory store
+#[derive(Debug, Error)]
+pub enum MemoryError {
+    /// An error which happens when an operation affects not enough or too many
+    /// rows
+    #[error("Expected {expected} rows to be affected, but {actual} rows were affected")]
+    RowsAffected {
+        /// How many rows were expected to be affected
+        expected: u64,
+
+        /// How many rows were actually affected
+        actual: u64,
+    },
+
+    /// An error which happens when inserting a row would break a uniqueness
+    /// constraint
+    #[error("Unique constraint {constraint:?} violated")]
+    UniqueViolation {
+        /// The name of the constraint which was violated
+        constraint: &'static str,
+    },
+
+    /// An error which happened because the requested operation is invalid
+    #[error("Invalid operation")]
+    InvalidOperation {
+        /// The source of the error, if any
+        #[source]
+        source: Option<Box<dyn std::error::Error + Send + Sync + 'static>>,
+    },
+
+    /// An error which happens when trying to act on behalf of a deactivated
+    /// user
+    #[error("User {user_id} is deactivated")]
+    UserDeactivated {
+        /// The ID of the deactivated user
+        user_id: Ulid,
+    },
+}
+
+impl MemoryError {
+    pub(crate) fn ensure_affected_rows(actual: usize, expected: usize) -> Result<(), Self> {
+        if actual == expected {
+            Ok(())
+        } else {
+            Err(Self::RowsAffected {
+                expected: expected as u64,
+                actual: actual as u64,
+            })
+        }
+    }
+
+    /// The error returned when the row an operation acts on doesn't exist
+    pub(crate) const fn not_found() -> Self {
+        Self::RowsAffected {
+            expected: 1,
+            actual: 0,
+        }
+    }
+
+    pub(crate) fn to_invalid_operation<E: std::error::Error + Send + Sync + 'static>(e: E) -> Self {
+        Self::InvalidOperation {
+            source: Some(Box::new(e)),
+        }
+    }
+
+    pub(crate) const fn invalid_operation() -> Self {
+        Self::InvalidOperation { source: None }
+    }
+}
@@ -0,0 +1,54 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! An in-memory implementation of the [`JobRepository`], which keeps the
+//! scheduled jobs in the store instead of running them
+
+use async_trait::async_trait;
+use mas_storage::job::{JobId, JobRepository, JobSubmission};
+
+use crate::{store::ScheduledJob, MemoryError, MemoryStore};
+
+/// An implementation of [`JobRepository`] for the in-memory store
+pub(crate) struct MemoryJobRepository<'c> {
+    store: &'c mut MemoryStore,
+}
+
+impl<'c> MemoryJobRepository<'c> {
+    /// Create a new [`MemoryJobRepository`] from the in-memory store
+    pub(crate) fn new(store: &'c mut MemoryStore) -> Self {
+        Self { store }
+    }
+}
+
+#[async_trait]
+impl<'c> JobRepository for MemoryJobRepository<'c> {
+    type Error = MemoryError;
+
+    async fn schedule_submission(
+        &mut self,
+        submission: JobSubmission,
+    ) -> Result<JobId, Self::Error> {
+        // XXX: This does not use the clock nor the rng
+        let id = JobId::new();
+
+        self.store.jobs.push(ScheduledJob {
+            id: id.to_string(),
+            name: submission.name(),
+            payload: submission.payload().clone(),
+        });
+
+        Ok(id)
+    }
+}
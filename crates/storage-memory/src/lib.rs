@@ -0,0 +1,70 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! An implementation of the storage traits which keeps everything in memory
+//!
+//! This backend is meant for tests and for running the service without a
+//! database. Nothing is persisted: all the data is lost when the last
+//! [`MemoryStoreHandle`] is dropped.
+//!
+//! Each [`MemoryRepository`] works on a snapshot of the store, which is written
+//! back when the repository is saved, and discarded when it is cancelled. This
+//! mimics the transactions of the PostgreSQL backend, except that concurrent
+//! transactions don't conflict: only the rows changed by a transaction are
+//! written back, and if two transactions change the same row, the last one to
+//! be saved wins.
+//!
+//! ```rust
+//! use mas_storage::{
+//!     clock::MockClock, user::UserRepository, RepositoryAccess, RepositoryTransaction,
+//! };
+//! use mas_storage_memory::MemoryStoreHandle;
+//! use rand::SeedableRng;
+//!
+//! # tokio::runtime::Builder::new_current_thread().build().unwrap().block_on(async {
+//! let store = MemoryStoreHandle::new();
+//! let clock = MockClock::default();
+//! let mut rng = rand_chacha::ChaChaRng::seed_from_u64(42);
+//!
+//! let mut repo = store.repository();
+//! let user = repo.user().add(&mut rng, &clock, "alice".to_owned()).await?;
+//! Box::new(repo).save().await?;
+//!
+//! let mut repo = store.repository();
+//! assert_eq!(repo.user().lookup(user.id).await?, Some(user));
+//! # Ok::<(), mas_storage_memory::MemoryError>(())
+//! # }).unwrap();
+//! ```
+
+#![deny(clippy::future_not_send, missing_docs)]
+#![allow(clippy::module_name_repetitions)]
+
+mod app_session;
+mod audit_log;
+mod compat;
+mod errors;
+mod job;
+mod oauth2;
+mod pagination;
+mod repository;
+mod store;
+mod upstream_oauth2;
+mod user;
+
+pub(crate) use self::store::MemoryStore;
+pub use self::{
+    errors::MemoryError,
+    repository::MemoryRepository,
+    store::{MemoryStoreHandle, ScheduledJob},
+};
@@ -0,0 +1,183 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use async_trait::async_trait;
+use chrono::Duration;
+use mas_data_model::{AccessToken, AccessTokenState, Session, User};
+use mas_storage::{oauth2::OAuth2AccessTokenRepository, Clock};
+use oauth2_types::scope::Scope;
+use rand_core::RngCore;
+use ulid::Ulid;
+use url::Url;
+
+use crate::{MemoryError, MemoryStore};
+
+/// An implementation of [`OAuth2AccessTokenRepository`] for the in-memory
+/// store
+pub(crate) struct MemoryOAuth2AccessTokenRepository<'c> {
+    store: &'c mut MemoryStore,
+}
+
+impl<'c> MemoryOAuth2AccessTokenRepository<'c> {
+    /// Create a new [`MemoryOAuth2AccessTokenRepository`] from the in-memory
+    /// store
+    pub(crate) fn new(store: &'c mut MemoryStore) -> Self {
+        Self { store }
+    }
+
+    /// Get the stored access token, making sure it exists
+    fn row_mut(&mut self, access_token: &AccessToken) -> Result<&mut AccessToken, MemoryError> {
+        self.store
+            .oauth2_access_tokens
+            .get_mut(&access_token.id)
+            .ok_or(MemoryError::not_found())
+    }
+}
+
+#[async_trait]
+impl<'c> OAuth2AccessTokenRepository for MemoryOAuth2AccessTokenRepository<'c> {
+    type Error = MemoryError;
+
+    async fn lookup(&mut self, id: Ulid) -> Result<Option<AccessToken>, Self::Error> {
+        Ok(self.store.oauth2_access_tokens.get(&id).cloned())
+    }
+
+    async fn find_by_token(
+        &mut self,
+        access_token: &str,
+    ) -> Result<Option<AccessToken>, Self::Error> {
+        Ok(self
+            .store
+            .oauth2_access_tokens
+            .values()
+            .find(|token| token.access_token == access_token)
+            .cloned())
+    }
+
+    async fn lookup_for_introspection(
+        &mut self,
+        access_token: &str,
+    ) -> Result<Option<(AccessToken, Session, Option<User>)>, Self::Error> {
+        let Some(token) = self
+            .store
+            .oauth2_access_tokens
+            .values()
+            .find(|token| token.access_token == access_token)
+        else {
+            return Ok(None);
+        };
+
+        let Some(session) = self.store.oauth2_sessions.get(&token.session_id) else {
+            return Ok(None);
+        };
+
+        let user = session
+            .user_id
+            .and_then(|user_id| self.store.users.get(&user_id))
+            .cloned();
+
+        Ok(Some((token.clone(), session.clone(), user)))
+    }
+
+    async fn add(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        session: &Session,
+        access_token: String,
+        expires_after: Option<Duration>,
+    ) -> Result<AccessToken, Self::Error> {
+        let created_at = clock.now();
+        let id = Ulid::from_datetime_with_source(created_at.into(), rng);
+
+        let access_token = AccessToken {
+            id,
+            state: AccessTokenState::default(),
+            session_id: session.id,
+            access_token,
+            created_at,
+            expires_at: expires_after.map(|expires_after| created_at + expires_after),
+            dpop_jkt: None,
+            scope: None,
+            resource: None,
+        };
+
+        self.store
+            .oauth2_access_tokens
+            .insert(id, access_token.clone());
+
+        Ok(access_token)
+    }
+
+    async fn bind_dpop_key(
+        &mut self,
+        mut access_token: AccessToken,
+        jkt: String,
+    ) -> Result<AccessToken, Self::Error> {
+        self.row_mut(&access_token)?.dpop_jkt = Some(jkt.clone());
+        access_token.dpop_jkt = Some(jkt);
+
+        Ok(access_token)
+    }
+
+    async fn set_scope(
+        &mut self,
+        mut access_token: AccessToken,
+        scope: Scope,
+    ) -> Result<AccessToken, Self::Error> {
+        self.row_mut(&access_token)?.scope = Some(scope.clone());
+        access_token.scope = Some(scope);
+
+        Ok(access_token)
+    }
+
+    async fn set_resource(
+        &mut self,
+        mut access_token: AccessToken,
+        resource: Vec<Url>,
+    ) -> Result<AccessToken, Self::Error> {
+        self.row_mut(&access_token)?.resource = Some(resource.clone());
+        access_token.resource = Some(resource);
+
+        Ok(access_token)
+    }
+
+    async fn revoke(
+        &mut self,
+        clock: &dyn Clock,
+        access_token: AccessToken,
+    ) -> Result<AccessToken, Self::Error> {
+        let row = self.row_mut(&access_token)?;
+
+        let access_token = access_token
+            .revoke(clock.now())
+            .map_err(MemoryError::to_invalid_operation)?;
+
+        row.state = access_token.state.clone();
+
+        Ok(access_token)
+    }
+
+    async fn cleanup_expired(&mut self, clock: &dyn Clock) -> Result<usize, Self::Error> {
+        // Cleanup token which expired more than 15 minutes ago
+        let threshold = clock.now() - Duration::microseconds(15 * 60 * 1000 * 1000);
+
+        let before = self.store.oauth2_access_tokens.len();
+        self.store
+            .oauth2_access_tokens
+            .retain(|_, token| token.expires_at.map_or(true, |at| at >= threshold));
+
+        Ok(before - self.store.oauth2_access_tokens.len())
+    }
+}
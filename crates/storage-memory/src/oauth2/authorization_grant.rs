@@ -0,0 +1,181 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::num::NonZeroU32;
+
+use async_trait::async_trait;
+use chrono::Duration;
+use mas_data_model::{
+    AuthorizationCode, AuthorizationGrant, AuthorizationGrantStage, Client, Session,
+};
+use mas_storage::{oauth2::OAuth2AuthorizationGrantRepository, Clock};
+use oauth2_types::{requests::ResponseMode, scope::Scope};
+use rand_core::RngCore;
+use ulid::Ulid;
+use url::Url;
+
+use crate::{MemoryError, MemoryStore};
+
+/// An implementation of [`OAuth2AuthorizationGrantRepository`] for the
+/// in-memory store
+pub(crate) struct MemoryOAuth2AuthorizationGrantRepository<'c> {
+    store: &'c mut MemoryStore,
+}
+
+impl<'c> MemoryOAuth2AuthorizationGrantRepository<'c> {
+    /// Create a new [`MemoryOAuth2AuthorizationGrantRepository`] from the
+    /// in-memory store
+    pub(crate) fn new(store: &'c mut MemoryStore) -> Self {
+        Self { store }
+    }
+
+    /// Get the stored grant, making sure it exists
+    fn row_mut(
+        &mut self,
+        grant: &AuthorizationGrant,
+    ) -> Result<&mut AuthorizationGrant, MemoryError> {
+        self.store
+            .oauth2_authorization_grants
+            .get_mut(&grant.id)
+            .ok_or(MemoryError::not_found())
+    }
+}
+
+#[async_trait]
+impl<'c> OAuth2AuthorizationGrantRepository for MemoryOAuth2AuthorizationGrantRepository<'c> {
+    type Error = MemoryError;
+
+    async fn add(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        client: &Client,
+        redirect_uri: Url,
+        scope: Scope,
+        code: Option<AuthorizationCode>,
+        state: Option<String>,
+        nonce: Option<String>,
+        max_age: Option<NonZeroU32>,
+        response_mode: ResponseMode,
+        response_type_id_token: bool,
+        requires_consent: bool,
+        requires_reauth: bool,
+        login_hint: Option<String>,
+        resource: Vec<Url>,
+    ) -> Result<AuthorizationGrant, Self::Error> {
+        let created_at = clock.now();
+        let id = Ulid::from_datetime_with_source(created_at.into(), rng);
+
+        let grant = AuthorizationGrant {
+            id,
+            stage: AuthorizationGrantStage::Pending,
+            code,
+            redirect_uri,
+            client_id: client.id,
+            scope,
+            state,
+            nonce,
+            login_hint,
+            resource,
+            max_age,
+            response_mode,
+            created_at,
+            response_type_id_token,
+            requires_consent,
+            requires_reauth,
+        };
+
+        self.store
+            .oauth2_authorization_grants
+            .insert(id, grant.clone());
+
+        Ok(grant)
+    }
+
+    async fn lookup(&mut self, id: Ulid) -> Result<Option<AuthorizationGrant>, Self::Error> {
+        Ok(self.store.oauth2_authorization_grants.get(&id).cloned())
+    }
+
+    async fn find_by_code(
+        &mut self,
+        code: &str,
+    ) -> Result<Option<AuthorizationGrant>, Self::Error> {
+        Ok(self
+            .store
+            .oauth2_authorization_grants
+            .values()
+            .find(|grant| grant.code.as_ref().is_some_and(|c| c.code == code))
+            .cloned())
+    }
+
+    async fn fulfill(
+        &mut self,
+        clock: &dyn Clock,
+        session: &Session,
+        grant: AuthorizationGrant,
+        code_expires_after: Duration,
+    ) -> Result<AuthorizationGrant, Self::Error> {
+        let fulfilled_at = clock.now();
+        let code_expires_at = fulfilled_at + code_expires_after;
+
+        let row = self.row_mut(&grant)?;
+
+        let grant = grant
+            .fulfill(fulfilled_at, code_expires_at, session)
+            .map_err(MemoryError::to_invalid_operation)?;
+
+        row.stage = grant.stage.clone();
+
+        Ok(grant)
+    }
+
+    async fn exchange(
+        &mut self,
+        clock: &dyn Clock,
+        grant: AuthorizationGrant,
+    ) -> Result<Option<AuthorizationGrant>, Self::Error> {
+        let row = self.row_mut(&grant)?;
+        if row.is_exchanged() {
+            return Ok(None);
+        }
+
+        let grant = grant
+            .exchange(clock.now())
+            .map_err(MemoryError::to_invalid_operation)?;
+
+        row.stage = grant.stage.clone();
+
+        Ok(Some(grant))
+    }
+
+    async fn give_consent(
+        &mut self,
+        mut grant: AuthorizationGrant,
+    ) -> Result<AuthorizationGrant, Self::Error> {
+        self.row_mut(&grant)?.requires_consent = false;
+        grant.requires_consent = false;
+
+        Ok(grant)
+    }
+
+    async fn reauthenticated(
+        &mut self,
+        mut grant: AuthorizationGrant,
+    ) -> Result<AuthorizationGrant, Self::Error> {
+        self.row_mut(&grant)?.requires_reauth = false;
+        grant.requires_reauth = false;
+
+        Ok(grant)
+    }
+}
@@ -0,0 +1,335 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use async_trait::async_trait;
+use mas_data_model::{AccessTokenFormat, Client, JwksOrJwksUri, RedirectUriMatching};
+use mas_iana::{
+    jose::JsonWebSignatureAlg,
+    oauth::{OAuthAuthorizationEndpointResponseType, OAuthClientAuthenticationMethod},
+};
+use mas_jose::jwk::PublicJsonWebKeySet;
+use mas_storage::{
+    oauth2::{OAuth2ClientRepository, UpsertStaticClientParams},
+    Clock,
+};
+use oauth2_types::{oidc::ApplicationType, requests::GrantType};
+use rand_core::RngCore;
+use ulid::Ulid;
+use url::Url;
+
+use crate::{store::OAuth2ClientRow, MemoryError, MemoryStore};
+
+/// An implementation of [`OAuth2ClientRepository`] for the in-memory store
+pub(crate) struct MemoryOAuth2ClientRepository<'c> {
+    store: &'c mut MemoryStore,
+}
+
+impl<'c> MemoryOAuth2ClientRepository<'c> {
+    /// Create a new [`MemoryOAuth2ClientRepository`] from the in-memory store
+    pub(crate) fn new(store: &'c mut MemoryStore) -> Self {
+        Self { store }
+    }
+}
+
+/// Combine the JWKS and JWKS URI of a client, which can't be both set
+fn jwks_or_jwks_uri(
+    jwks: Option<PublicJsonWebKeySet>,
+    jwks_uri: Option<Url>,
+) -> Result<Option<JwksOrJwksUri>, MemoryError> {
+    match (jwks, jwks_uri) {
+        (None, None) => Ok(None),
+        (Some(jwks), None) => Ok(Some(JwksOrJwksUri::Jwks(jwks))),
+        (None, Some(jwks_uri)) => Ok(Some(JwksOrJwksUri::JwksUri(jwks_uri))),
+        _ => Err(MemoryError::invalid_operation()),
+    }
+}
+
+#[async_trait]
+impl<'c> OAuth2ClientRepository for MemoryOAuth2ClientRepository<'c> {
+    type Error = MemoryError;
+
+    async fn lookup(&mut self, id: Ulid) -> Result<Option<Client>, Self::Error> {
+        Ok(self
+            .store
+            .oauth2_clients
+            .get(&id)
+            .map(|row| row.client.clone()))
+    }
+
+    async fn load_batch(
+        &mut self,
+        ids: BTreeSet<Ulid>,
+    ) -> Result<BTreeMap<Ulid, Client>, Self::Error> {
+        Ok(ids
+            .into_iter()
+            .filter_map(|id| {
+                let row = self.store.oauth2_clients.get(&id)?;
+                Some((id, row.client.clone()))
+            })
+            .collect())
+    }
+
+    async fn add(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        redirect_uris: Vec<Url>,
+        encrypted_client_secret: Option<String>,
+        application_type: Option<ApplicationType>,
+        response_types: Vec<OAuthAuthorizationEndpointResponseType>,
+        grant_types: Vec<GrantType>,
+        contacts: Vec<String>,
+        client_name: Option<String>,
+        logo_uri: Option<Url>,
+        client_uri: Option<Url>,
+        policy_uri: Option<Url>,
+        tos_uri: Option<Url>,
+        jwks_uri: Option<Url>,
+        jwks: Option<PublicJsonWebKeySet>,
+        id_token_signed_response_alg: Option<JsonWebSignatureAlg>,
+        userinfo_signed_response_alg: Option<JsonWebSignatureAlg>,
+        authorization_signed_response_alg: Option<JsonWebSignatureAlg>,
+        token_endpoint_auth_method: Option<OAuthClientAuthenticationMethod>,
+        token_endpoint_auth_signing_alg: Option<JsonWebSignatureAlg>,
+        initiate_login_uri: Option<Url>,
+        post_logout_redirect_uris: Vec<Url>,
+    ) -> Result<Client, Self::Error> {
+        let jwks = jwks_or_jwks_uri(jwks, jwks_uri)?;
+
+        let now = clock.now();
+        let id = Ulid::from_datetime_with_source(now.into(), rng);
+
+        let client = Client {
+            id,
+            client_id: id.to_string(),
+            encrypted_client_secret,
+            application_type,
+            redirect_uris,
+            redirect_uri_matching: RedirectUriMatching::Exact,
+            response_types,
+            grant_types,
+            contacts,
+            client_name,
+            logo_uri,
+            client_uri,
+            policy_uri,
+            tos_uri,
+            jwks,
+            id_token_signed_response_alg,
+            userinfo_signed_response_alg,
+            authorization_signed_response_alg,
+            token_endpoint_auth_method,
+            token_endpoint_auth_signing_alg,
+            initiate_login_uri,
+            require_signed_request_object: false,
+            require_pkce: None,
+            access_token_ttl: None,
+            refresh_token_ttl: None,
+            access_token_format: AccessTokenFormat::Opaque,
+            client_credentials_scope: None,
+            allowed_resources: Vec::new(),
+            introspection_endpoint_auth_method: None,
+            post_logout_redirect_uris,
+            backchannel_logout_uri: None,
+            backchannel_logout_session_required: false,
+            trusted: false,
+        };
+
+        self.store.oauth2_clients.insert(
+            id,
+            OAuth2ClientRow {
+                client: client.clone(),
+                is_static: false,
+            },
+        );
+
+        Ok(client)
+    }
+
+    async fn upsert_static(
+        &mut self,
+        params: UpsertStaticClientParams,
+    ) -> Result<Client, Self::Error> {
+        let UpsertStaticClientParams {
+            client_id,
+            client_auth_method,
+            encrypted_client_secret,
+            jwks,
+            jwks_uri,
+            redirect_uris,
+            redirect_uri_matching,
+            response_types,
+            allow_token_exchange,
+            allow_client_credentials,
+            client_credentials_scope,
+            allowed_resources,
+            require_signed_request_object,
+            require_pkce,
+            access_token_ttl,
+            refresh_token_ttl,
+            access_token_format,
+            introspection_endpoint_auth_method,
+            post_logout_redirect_uris,
+            backchannel_logout_uri,
+            backchannel_logout_session_required,
+            trusted,
+        } = params;
+
+        let jwks = jwks_or_jwks_uri(jwks, jwks_uri)?;
+
+        let mut grant_types = vec![GrantType::AuthorizationCode, GrantType::RefreshToken];
+        if allow_client_credentials {
+            grant_types.push(GrantType::ClientCredentials);
+        }
+        if allow_token_exchange {
+            grant_types.push(GrantType::TokenExchange);
+        }
+
+        let client = Client {
+            id: client_id,
+            client_id: client_id.to_string(),
+            encrypted_client_secret,
+            application_type: None,
+            redirect_uris,
+            redirect_uri_matching,
+            response_types,
+            grant_types,
+            contacts: Vec::new(),
+            client_name: None,
+            logo_uri: None,
+            client_uri: None,
+            policy_uri: None,
+            tos_uri: None,
+            jwks,
+            id_token_signed_response_alg: None,
+            userinfo_signed_response_alg: None,
+            authorization_signed_response_alg: None,
+            token_endpoint_auth_method: None,
+            token_endpoint_auth_signing_alg: None,
+            initiate_login_uri: None,
+            require_signed_request_object,
+            require_pkce,
+            access_token_ttl,
+            refresh_token_ttl,
+            access_token_format,
+            client_credentials_scope,
+            allowed_resources,
+            introspection_endpoint_auth_method,
+            post_logout_redirect_uris,
+            backchannel_logout_uri,
+            backchannel_logout_session_required,
+            trusted,
+        };
+
+        // Like the PostgreSQL backend, the stored client also allows the device
+        // code grant and remembers its authentication method, and the fields
+        // which aren't set by the configuration are kept as they were
+        let stored = match self.store.oauth2_clients.get(&client_id) {
+            Some(existing) => Client {
+                application_type: existing.client.application_type.clone(),
+                contacts: existing.client.contacts.clone(),
+                client_name: existing.client.client_name.clone(),
+                logo_uri: existing.client.logo_uri.clone(),
+                client_uri: existing.client.client_uri.clone(),
+                policy_uri: existing.client.policy_uri.clone(),
+                tos_uri: existing.client.tos_uri.clone(),
+                id_token_signed_response_alg: existing.client.id_token_signed_response_alg.clone(),
+                userinfo_signed_response_alg: existing.client.userinfo_signed_response_alg.clone(),
+                authorization_signed_response_alg: existing
+                    .client
+                    .authorization_signed_response_alg
+                    .clone(),
+                token_endpoint_auth_signing_alg: existing
+                    .client
+                    .token_endpoint_auth_signing_alg
+                    .clone(),
+                initiate_login_uri: existing.client.initiate_login_uri.clone(),
+                ..client.clone()
+            },
+            None => client.clone(),
+        };
+
+        let mut stored_grant_types = vec![GrantType::AuthorizationCode, GrantType::RefreshToken];
+        if allow_client_credentials {
+            stored_grant_types.push(GrantType::ClientCredentials);
+        }
+        stored_grant_types.push(GrantType::DeviceCode);
+        if allow_token_exchange {
+            stored_grant_types.push(GrantType::TokenExchange);
+        }
+
+        self.store.oauth2_clients.insert(
+            client_id,
+            OAuth2ClientRow {
+                client: Client {
+                    grant_types: stored_grant_types,
+                    token_endpoint_auth_method: Some(client_auth_method),
+                    ..stored
+                },
+                is_static: true,
+            },
+        );
+
+        Ok(client)
+    }
+
+    async fn all_static(&mut self) -> Result<Vec<Client>, Self::Error> {
+        Ok(self
+            .store
+            .oauth2_clients
+            .values()
+            .filter(|row| row.is_static)
+            .map(|row| row.client.clone())
+            .collect())
+    }
+
+    async fn delete_by_id(&mut self, id: Ulid) -> Result<(), Self::Error> {
+        let store = &mut *self.store;
+
+        // Delete the authorization grants and consents of the client
+        store
+            .oauth2_authorization_grants
+            .retain(|_, grant| grant.client_id != id);
+        store
+            .oauth2_consents
+            .retain(|(client_id, _), _| *client_id != id);
+
+        // Delete the sessions of the client, along with their tokens
+        let session_ids: BTreeSet<Ulid> = store
+            .oauth2_sessions
+            .values()
+            .filter(|session| session.client_id == id)
+            .map(|session| session.id)
+            .collect();
+        store
+            .oauth2_access_tokens
+            .retain(|_, token| !session_ids.contains(&token.session_id));
+        store
+            .oauth2_refresh_tokens
+            .retain(|_, token| !session_ids.contains(&token.session_id));
+        store
+            .oauth2_sessions
+            .retain(|session_id, _| !session_ids.contains(session_id));
+
+        // Now delete the client itself
+        store
+            .oauth2_clients
+            .remove(&id)
+            .ok_or(MemoryError::not_found())?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,74 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use async_trait::async_trait;
+use mas_data_model::{Client, User};
+use mas_storage::{oauth2::OAuth2ConsentRepository, Clock};
+use oauth2_types::scope::Scope;
+use rand_core::RngCore;
+
+use crate::{MemoryError, MemoryStore};
+
+/// An implementation of [`OAuth2ConsentRepository`] for the in-memory store
+pub(crate) struct MemoryOAuth2ConsentRepository<'c> {
+    store: &'c mut MemoryStore,
+}
+
+impl<'c> MemoryOAuth2ConsentRepository<'c> {
+    /// Create a new [`MemoryOAuth2ConsentRepository`] from the in-memory store
+    pub(crate) fn new(store: &'c mut MemoryStore) -> Self {
+        Self { store }
+    }
+}
+
+#[async_trait]
+impl<'c> OAuth2ConsentRepository for MemoryOAuth2ConsentRepository<'c> {
+    type Error = MemoryError;
+
+    async fn find(&mut self, client: &Client, user: &User) -> Result<Scope, Self::Error> {
+        Ok(self
+            .store
+            .oauth2_consents
+            .get(&(client.id, user.id))
+            .cloned()
+            .unwrap_or_else(|| Scope::from_iter(std::iter::empty())))
+    }
+
+    async fn add(
+        &mut self,
+        _rng: &mut (dyn RngCore + Send),
+        _clock: &dyn Clock,
+        client: &Client,
+        user: &User,
+        scope: &Scope,
+    ) -> Result<(), Self::Error> {
+        let consent = self
+            .store
+            .oauth2_consents
+            .entry((client.id, user.id))
+            .or_insert_with(|| Scope::from_iter(std::iter::empty()));
+
+        for token in scope.iter() {
+            consent.insert(token.clone());
+        }
+
+        Ok(())
+    }
+
+    async fn revoke(&mut self, client: &Client, user: &User) -> Result<bool, Self::Error> {
+        let removed = self.store.oauth2_consents.remove(&(client.id, user.id));
+
+        Ok(removed.is_some_and(|scope| !scope.is_empty()))
+    }
+}
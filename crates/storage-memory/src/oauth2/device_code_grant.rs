@@ -0,0 +1,182 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use async_trait::async_trait;
+use chrono::Duration;
+use mas_data_model::{BrowserSession, DeviceCodeGrant, DeviceCodeGrantState, Session};
+use mas_storage::{
+    oauth2::{OAuth2DeviceCodeGrantParams, OAuth2DeviceCodeGrantRepository},
+    Clock,
+};
+use rand_core::RngCore;
+use ulid::Ulid;
+
+use crate::{MemoryError, MemoryStore};
+
+/// An implementation of [`OAuth2DeviceCodeGrantRepository`] for the in-memory
+/// store
+pub(crate) struct MemoryOAuth2DeviceCodeGrantRepository<'c> {
+    store: &'c mut MemoryStore,
+}
+
+impl<'c> MemoryOAuth2DeviceCodeGrantRepository<'c> {
+    /// Create a new [`MemoryOAuth2DeviceCodeGrantRepository`] from the
+    /// in-memory store
+    pub(crate) fn new(store: &'c mut MemoryStore) -> Self {
+        Self { store }
+    }
+
+    /// Replace the state of the stored grant, making sure it exists
+    fn update(&mut self, device_code_grant: &DeviceCodeGrant) -> Result<(), MemoryError> {
+        let row = self
+            .store
+            .oauth2_device_code_grants
+            .get_mut(&device_code_grant.id)
+            .ok_or(MemoryError::not_found())?;
+
+        row.state = device_code_grant.state.clone();
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<'c> OAuth2DeviceCodeGrantRepository for MemoryOAuth2DeviceCodeGrantRepository<'c> {
+    type Error = MemoryError;
+
+    async fn add(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        params: OAuth2DeviceCodeGrantParams<'_>,
+    ) -> Result<DeviceCodeGrant, Self::Error> {
+        let now = clock.now();
+        let id = Ulid::from_datetime_with_source(now.into(), rng);
+
+        let device_code_grant = DeviceCodeGrant {
+            id,
+            state: DeviceCodeGrantState::Pending,
+            client_id: params.client.id,
+            scope: params.scope,
+            user_code: params.user_code,
+            device_code: params.device_code,
+            created_at: now,
+            expires_at: now + params.expires_in,
+            last_polled_at: None,
+            poll_interval: params.poll_interval,
+            ip_address: params.ip_address,
+            user_agent: params.user_agent,
+        };
+
+        self.store
+            .oauth2_device_code_grants
+            .insert(id, device_code_grant.clone());
+
+        Ok(device_code_grant)
+    }
+
+    async fn lookup(&mut self, id: Ulid) -> Result<Option<DeviceCodeGrant>, Self::Error> {
+        Ok(self.store.oauth2_device_code_grants.get(&id).cloned())
+    }
+
+    async fn find_by_device_code(
+        &mut self,
+        device_code: &str,
+    ) -> Result<Option<DeviceCodeGrant>, Self::Error> {
+        Ok(self
+            .store
+            .oauth2_device_code_grants
+            .values()
+            .find(|grant| grant.device_code == device_code)
+            .cloned())
+    }
+
+    async fn find_by_user_code(
+        &mut self,
+        user_code: &str,
+    ) -> Result<Option<DeviceCodeGrant>, Self::Error> {
+        Ok(self
+            .store
+            .oauth2_device_code_grants
+            .values()
+            .find(|grant| grant.user_code == user_code)
+            .cloned())
+    }
+
+    async fn fulfill(
+        &mut self,
+        clock: &dyn Clock,
+        device_code_grant: DeviceCodeGrant,
+        browser_session: &BrowserSession,
+    ) -> Result<DeviceCodeGrant, Self::Error> {
+        let device_code_grant = device_code_grant
+            .fulfill(browser_session, clock.now())
+            .map_err(MemoryError::to_invalid_operation)?;
+
+        self.update(&device_code_grant)?;
+
+        Ok(device_code_grant)
+    }
+
+    async fn reject(
+        &mut self,
+        clock: &dyn Clock,
+        device_code_grant: DeviceCodeGrant,
+        browser_session: &BrowserSession,
+    ) -> Result<DeviceCodeGrant, Self::Error> {
+        let device_code_grant = device_code_grant
+            .reject(browser_session, clock.now())
+            .map_err(MemoryError::to_invalid_operation)?;
+
+        self.update(&device_code_grant)?;
+
+        Ok(device_code_grant)
+    }
+
+    async fn exchange(
+        &mut self,
+        clock: &dyn Clock,
+        device_code_grant: DeviceCodeGrant,
+        session: &Session,
+    ) -> Result<DeviceCodeGrant, Self::Error> {
+        let device_code_grant = device_code_grant
+            .exchange(session, clock.now())
+            .map_err(MemoryError::to_invalid_operation)?;
+
+        self.update(&device_code_grant)?;
+
+        Ok(device_code_grant)
+    }
+
+    async fn record_poll(
+        &mut self,
+        clock: &dyn Clock,
+        mut device_code_grant: DeviceCodeGrant,
+        poll_interval: Duration,
+    ) -> Result<DeviceCodeGrant, Self::Error> {
+        let now = clock.now();
+
+        let row = self
+            .store
+            .oauth2_device_code_grants
+            .get_mut(&device_code_grant.id)
+            .ok_or(MemoryError::not_found())?;
+
+        row.last_polled_at = Some(now);
+        row.poll_interval = poll_interval;
+        device_code_grant.last_polled_at = Some(now);
+        device_code_grant.poll_interval = poll_interval;
+
+        Ok(device_code_grant)
+    }
+}
@@ -0,0 +1,87 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::BTreeMap;
+
+use async_trait::async_trait;
+use chrono::Duration;
+use mas_data_model::{Client, PushedAuthorizationRequest};
+use mas_storage::{oauth2::OAuth2PushedAuthRequestRepository, Clock};
+use rand_core::RngCore;
+use ulid::Ulid;
+
+use crate::{MemoryError, MemoryStore};
+
+/// An implementation of [`OAuth2PushedAuthRequestRepository`] for the
+/// in-memory store
+pub(crate) struct MemoryOAuth2PushedAuthRequestRepository<'c> {
+    store: &'c mut MemoryStore,
+}
+
+impl<'c> MemoryOAuth2PushedAuthRequestRepository<'c> {
+    /// Create a new [`MemoryOAuth2PushedAuthRequestRepository`] from the
+    /// in-memory store
+    pub(crate) fn new(store: &'c mut MemoryStore) -> Self {
+        Self { store }
+    }
+}
+
+#[async_trait]
+impl<'c> OAuth2PushedAuthRequestRepository for MemoryOAuth2PushedAuthRequestRepository<'c> {
+    type Error = MemoryError;
+
+    async fn add(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        client: &Client,
+        parameters: BTreeMap<String, String>,
+        expires_in: Duration,
+    ) -> Result<PushedAuthorizationRequest, Self::Error> {
+        let created_at = clock.now();
+        let id = Ulid::from_datetime_with_source(created_at.into(), rng);
+
+        let request = PushedAuthorizationRequest {
+            id,
+            client_id: client.id,
+            parameters,
+            created_at,
+            expires_at: created_at + expires_in,
+        };
+
+        self.store
+            .oauth2_pushed_auth_requests
+            .insert(id, request.clone());
+
+        Ok(request)
+    }
+
+    async fn lookup(
+        &mut self,
+        id: Ulid,
+    ) -> Result<Option<PushedAuthorizationRequest>, Self::Error> {
+        Ok(self.store.oauth2_pushed_auth_requests.get(&id).cloned())
+    }
+
+    async fn cleanup_expired(&mut self, clock: &dyn Clock) -> Result<usize, Self::Error> {
+        let now = clock.now();
+
+        let before = self.store.oauth2_pushed_auth_requests.len();
+        self.store
+            .oauth2_pushed_auth_requests
+            .retain(|_, request| request.expires_at >= now);
+
+        Ok(before - self.store.oauth2_pushed_auth_requests.len())
+    }
+}
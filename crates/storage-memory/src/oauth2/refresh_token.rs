@@ -0,0 +1,124 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use async_trait::async_trait;
+use mas_data_model::{AccessToken, RefreshToken, RefreshTokenState, Session};
+use mas_storage::{oauth2::OAuth2RefreshTokenRepository, Clock};
+use rand_core::RngCore;
+use ulid::Ulid;
+
+use crate::{MemoryError, MemoryStore};
+
+/// An implementation of [`OAuth2RefreshTokenRepository`] for the in-memory
+/// store
+pub(crate) struct MemoryOAuth2RefreshTokenRepository<'c> {
+    store: &'c mut MemoryStore,
+}
+
+impl<'c> MemoryOAuth2RefreshTokenRepository<'c> {
+    /// Create a new [`MemoryOAuth2RefreshTokenRepository`] from the in-memory
+    /// store
+    pub(crate) fn new(store: &'c mut MemoryStore) -> Self {
+        Self { store }
+    }
+
+    /// Replace the state of the stored refresh token, making sure it exists
+    fn update(&mut self, refresh_token: &RefreshToken) -> Result<(), MemoryError> {
+        let row = self
+            .store
+            .oauth2_refresh_tokens
+            .get_mut(&refresh_token.id)
+            .ok_or(MemoryError::not_found())?;
+
+        row.state = refresh_token.state.clone();
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<'c> OAuth2RefreshTokenRepository for MemoryOAuth2RefreshTokenRepository<'c> {
+    type Error = MemoryError;
+
+    async fn lookup(&mut self, id: Ulid) -> Result<Option<RefreshToken>, Self::Error> {
+        Ok(self.store.oauth2_refresh_tokens.get(&id).cloned())
+    }
+
+    async fn find_by_token(
+        &mut self,
+        refresh_token: &str,
+    ) -> Result<Option<RefreshToken>, Self::Error> {
+        Ok(self
+            .store
+            .oauth2_refresh_tokens
+            .values()
+            .find(|token| token.refresh_token == refresh_token)
+            .cloned())
+    }
+
+    async fn add(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        session: &Session,
+        access_token: &AccessToken,
+        refresh_token: String,
+    ) -> Result<RefreshToken, Self::Error> {
+        let created_at = clock.now();
+        let id = Ulid::from_datetime_with_source(created_at.into(), rng);
+
+        let refresh_token = RefreshToken {
+            id,
+            state: RefreshTokenState::default(),
+            session_id: session.id,
+            refresh_token,
+            access_token_id: Some(access_token.id),
+            created_at,
+        };
+
+        self.store
+            .oauth2_refresh_tokens
+            .insert(id, refresh_token.clone());
+
+        Ok(refresh_token)
+    }
+
+    async fn consume(
+        &mut self,
+        clock: &dyn Clock,
+        refresh_token: RefreshToken,
+        replaced_by: &RefreshToken,
+    ) -> Result<RefreshToken, Self::Error> {
+        let refresh_token = refresh_token
+            .consume(clock.now(), replaced_by)
+            .map_err(MemoryError::to_invalid_operation)?;
+
+        self.update(&refresh_token)?;
+
+        Ok(refresh_token)
+    }
+
+    async fn revoke(
+        &mut self,
+        clock: &dyn Clock,
+        refresh_token: RefreshToken,
+    ) -> Result<RefreshToken, Self::Error> {
+        let refresh_token = refresh_token
+            .revoke(clock.now())
+            .map_err(MemoryError::to_invalid_operation)?;
+
+        self.update(&refresh_token)?;
+
+        Ok(refresh_token)
+    }
+}
@@ -0,0 +1,240 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::net::IpAddr;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use mas_data_model::{BrowserSession, Client, Session, SessionState, User, UserAgent};
+use mas_storage::{
+    oauth2::{OAuth2SessionFilter, OAuth2SessionRepository},
+    pagination::PaginationOrderBy,
+    Clock, Page, Pagination,
+};
+use oauth2_types::scope::Scope;
+use rand_core::RngCore;
+use ulid::Ulid;
+use url::Url;
+
+use crate::{
+    pagination::{paginate, paginate_ordered},
+    MemoryError, MemoryStore,
+};
+
+/// An implementation of [`OAuth2SessionRepository`] for the in-memory store
+pub(crate) struct MemoryOAuth2SessionRepository<'c> {
+    store: &'c mut MemoryStore,
+}
+
+impl<'c> MemoryOAuth2SessionRepository<'c> {
+    /// Create a new [`MemoryOAuth2SessionRepository`] from the in-memory store
+    pub(crate) fn new(store: &'c mut MemoryStore) -> Self {
+        Self { store }
+    }
+
+    /// Get the stored session, making sure it exists
+    fn row_mut(&mut self, session: &Session) -> Result<&mut Session, MemoryError> {
+        self.store
+            .oauth2_sessions
+            .get_mut(&session.id)
+            .ok_or(MemoryError::not_found())
+    }
+
+    /// Get the sessions matching the filter
+    fn filtered<'a>(
+        &'a self,
+        filter: &'a OAuth2SessionFilter<'a>,
+    ) -> impl Iterator<Item = &'a Session> + 'a {
+        self.store
+            .oauth2_sessions
+            .values()
+            .filter(move |session| matches_filter(session, filter))
+    }
+}
+
+fn finished_at(session: &Session) -> Option<DateTime<Utc>> {
+    match session.state {
+        SessionState::Valid => None,
+        SessionState::Finished { finished_at } => Some(finished_at),
+    }
+}
+
+fn matches_filter(session: &Session, filter: &OAuth2SessionFilter<'_>) -> bool {
+    filter
+        .user()
+        .map_or(true, |user| session.user_id == Some(user.id))
+        && filter.browser_session().map_or(true, |browser_session| {
+            session.user_session_id == Some(browser_session.id)
+        })
+        && filter
+            .client()
+            .map_or(true, |client| session.client_id == client.id)
+        && filter
+            .state()
+            .map_or(true, |state| state.is_active() == session.is_valid())
+        && filter.scope().map_or(true, |scope| {
+            scope.iter().all(|token| session.scope.contains(token))
+        })
+}
+
+#[async_trait]
+impl<'c> OAuth2SessionRepository for MemoryOAuth2SessionRepository<'c> {
+    type Error = MemoryError;
+
+    async fn lookup(&mut self, id: Ulid) -> Result<Option<Session>, Self::Error> {
+        Ok(self.store.oauth2_sessions.get(&id).cloned())
+    }
+
+    async fn add(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        client: &Client,
+        user: Option<&User>,
+        user_session: Option<&BrowserSession>,
+        scope: Scope,
+    ) -> Result<Session, Self::Error> {
+        let created_at = clock.now();
+        let id = Ulid::from_datetime_with_source(created_at.into(), rng);
+
+        let session = Session {
+            id,
+            state: SessionState::Valid,
+            created_at,
+            user_id: user.map(|u| u.id),
+            user_session_id: user_session.map(|s| s.id),
+            client_id: client.id,
+            scope,
+            user_agent: None,
+            last_active_at: None,
+            last_active_ip: None,
+            auth_time: None,
+            resource: Vec::new(),
+            dpop_jkt: None,
+        };
+
+        self.store.oauth2_sessions.insert(id, session.clone());
+
+        Ok(session)
+    }
+
+    async fn finish(
+        &mut self,
+        clock: &dyn Clock,
+        session: Session,
+    ) -> Result<Session, Self::Error> {
+        let row = self.row_mut(&session)?;
+
+        let session = session
+            .finish(clock.now())
+            .map_err(MemoryError::to_invalid_operation)?;
+
+        row.state = session.state.clone();
+
+        Ok(session)
+    }
+
+    async fn list(
+        &mut self,
+        filter: OAuth2SessionFilter<'_>,
+        pagination: Pagination,
+    ) -> Result<Page<Session>, Self::Error> {
+        let sessions = self.filtered(&filter).cloned();
+
+        let page = match pagination.order_by {
+            PaginationOrderBy::Id => paginate(sessions, pagination, |session| session.id),
+            PaginationOrderBy::CreatedAt => paginate_ordered(
+                sessions,
+                pagination,
+                |session| session.id,
+                |session| Some(session.created_at),
+            ),
+            PaginationOrderBy::FinishedAt => paginate_ordered(
+                sessions,
+                pagination,
+                |session| session.id,
+                |session| finished_at(session),
+            ),
+        };
+
+        Ok(page)
+    }
+
+    async fn count(&mut self, filter: OAuth2SessionFilter<'_>) -> Result<usize, Self::Error> {
+        Ok(self.filtered(&filter).count())
+    }
+
+    async fn record_batch_activity(
+        &mut self,
+        activity: Vec<(Ulid, DateTime<Utc>, Option<IpAddr>)>,
+    ) -> Result<(), Self::Error> {
+        let expected = activity.len();
+        let mut actual = 0;
+        for (id, last_active_at, last_active_ip) in activity {
+            let Some(session) = self.store.oauth2_sessions.get_mut(&id) else {
+                continue;
+            };
+
+            session.last_active_at = session.last_active_at.max(Some(last_active_at));
+            session.last_active_ip = last_active_ip.or(session.last_active_ip);
+            actual += 1;
+        }
+
+        MemoryError::ensure_affected_rows(actual, expected)
+    }
+
+    async fn record_user_agent(
+        &mut self,
+        mut session: Session,
+        user_agent: UserAgent,
+    ) -> Result<Session, Self::Error> {
+        self.row_mut(&session)?.user_agent = Some(user_agent.clone());
+        session.user_agent = Some(user_agent);
+
+        Ok(session)
+    }
+
+    async fn record_auth_time(
+        &mut self,
+        mut session: Session,
+        auth_time: DateTime<Utc>,
+    ) -> Result<Session, Self::Error> {
+        self.row_mut(&session)?.auth_time = Some(auth_time);
+        session.auth_time = Some(auth_time);
+
+        Ok(session)
+    }
+
+    async fn record_resource(
+        &mut self,
+        mut session: Session,
+        resource: Vec<Url>,
+    ) -> Result<Session, Self::Error> {
+        self.row_mut(&session)?.resource.clone_from(&resource);
+        session.resource = resource;
+
+        Ok(session)
+    }
+
+    async fn bind_dpop_key(
+        &mut self,
+        mut session: Session,
+        jkt: String,
+    ) -> Result<Session, Self::Error> {
+        self.row_mut(&session)?.dpop_jkt = Some(jkt.clone());
+        session.dpop_jkt = Some(jkt);
+
+        Ok(session)
+    }
+}
@@ -0,0 +1,139 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Utilities to paginate over the rows of the in-memory store, with the same
+//! semantics as the PostgreSQL backend
+
+use chrono::{DateTime, Utc};
+use mas_storage::{
+    pagination::{PaginationCursor, PaginationDirection, PaginationOrder},
+    Page, Pagination,
+};
+use ulid::Ulid;
+
+/// The key items are sorted on.
+///
+/// When ordering by a column, `NULL` values are sorted after all other values,
+/// which is why `is_null` comes first. When only ordering by ID, both `is_null`
+/// and `value` are the same for all items.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct SortKey {
+    is_null: bool,
+    value: Option<DateTime<Utc>>,
+    id: Ulid,
+}
+
+impl SortKey {
+    fn new(id: Ulid, value: Option<Option<DateTime<Utc>>>) -> Self {
+        match value {
+            Some(value) => Self {
+                is_null: value.is_none(),
+                value,
+                id,
+            },
+            None => Self {
+                is_null: false,
+                value: None,
+                id,
+            },
+        }
+    }
+
+    fn from_cursor(cursor: PaginationCursor, ordered: bool) -> Self {
+        Self::new(cursor.id, ordered.then_some(cursor.value))
+    }
+}
+
+/// Paginate over a list of items, ordering them by ID
+pub(crate) fn paginate<T>(
+    items: impl IntoIterator<Item = T>,
+    pagination: Pagination,
+    id: impl Fn(&T) -> Ulid,
+) -> Page<T> {
+    paginate_inner(
+        items,
+        pagination,
+        |item| SortKey::new(id(item), None),
+        false,
+    )
+}
+
+/// Paginate over a list of items, ordering them by the given column first and
+/// then by ID
+///
+/// `NULL` values in the ordering column are sorted after all other values.
+pub(crate) fn paginate_ordered<T>(
+    items: impl IntoIterator<Item = T>,
+    pagination: Pagination,
+    id: impl Fn(&T) -> Ulid,
+    order_value: impl Fn(&T) -> Option<DateTime<Utc>>,
+) -> Page<T> {
+    paginate_inner(
+        items,
+        pagination,
+        |item| SortKey::new(id(item), Some(order_value(item))),
+        true,
+    )
+}
+
+fn paginate_inner<T>(
+    items: impl IntoIterator<Item = T>,
+    pagination: Pagination,
+    key: impl Fn(&T) -> SortKey,
+    ordered: bool,
+) -> Page<T> {
+    // When sorting in descending order, the items after the cursor are the ones
+    // with a lower key
+    let descending = pagination.order == PaginationOrder::Descending;
+    let after = pagination
+        .after
+        .map(|cursor| SortKey::from_cursor(cursor, ordered));
+    let before = pagination
+        .before
+        .map(|cursor| SortKey::from_cursor(cursor, ordered));
+
+    let mut edges: Vec<(SortKey, T)> = items
+        .into_iter()
+        .map(|item| (key(&item), item))
+        .filter(|(key, _)| match after {
+            Some(after) if descending => *key < after,
+            Some(after) => *key > after,
+            None => true,
+        })
+        .filter(|(key, _)| match before {
+            Some(before) if descending => *key > before,
+            Some(before) => *key < before,
+            None => true,
+        })
+        .collect();
+
+    let ascending = matches!(
+        (pagination.direction, descending),
+        (PaginationDirection::Forward, false) | (PaginationDirection::Backward, true)
+    );
+
+    if ascending {
+        edges.sort_by(|(a, _), (b, _)| a.cmp(b));
+    } else {
+        edges.sort_by(|(a, _), (b, _)| b.cmp(a));
+    }
+
+    let edges = edges
+        .into_iter()
+        .take(pagination.count + 1)
+        .map(|(_, item)| item)
+        .collect();
+
+    pagination.process(edges)
+}
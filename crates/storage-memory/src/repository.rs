@@ -0,0 +1,356 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use futures_util::{future::BoxFuture, FutureExt};
+use mas_storage::{
+    app_session::AppSessionRepository,
+    audit_log::AuditLogRepository,
+    compat::{
+        CompatAccessTokenRepository, CompatRefreshTokenRepository, CompatSessionRepository,
+        CompatSsoLoginRepository,
+    },
+    job::JobRepository,
+    oauth2::{
+        OAuth2AccessTokenRepository, OAuth2AuthorizationGrantRepository, OAuth2ClientRepository,
+        OAuth2ConsentRepository, OAuth2DeviceCodeGrantRepository,
+        OAuth2PushedAuthRequestRepository, OAuth2RefreshTokenRepository, OAuth2SessionRepository,
+    },
+    upstream_oauth2::{
+        UpstreamOAuthLinkRepository, UpstreamOAuthProviderRepository,
+        UpstreamOAuthSessionRepository,
+    },
+    user::{
+        BrowserSessionRepository, UserEmailRepository, UserPasswordRepository,
+        UserPasswordResetTokenRepository, UserRecoveryCodeRepository, UserRepository,
+        UserTermsRepository, UserTotpRepository, UserWebAuthnRepository,
+    },
+    Repository, RepositoryAccess, RepositoryTransaction,
+};
+
+use crate::{
+    app_session::MemoryAppSessionRepository,
+    audit_log::MemoryAuditLogRepository,
+    compat::{
+        MemoryCompatAccessTokenRepository, MemoryCompatRefreshTokenRepository,
+        MemoryCompatSessionRepository, MemoryCompatSsoLoginRepository,
+    },
+    job::MemoryJobRepository,
+    oauth2::{
+        MemoryOAuth2AccessTokenRepository, MemoryOAuth2AuthorizationGrantRepository,
+        MemoryOAuth2ClientRepository, MemoryOAuth2ConsentRepository,
+        MemoryOAuth2DeviceCodeGrantRepository, MemoryOAuth2PushedAuthRequestRepository,
+        MemoryOAuth2RefreshTokenRepository, MemoryOAuth2SessionRepository,
+    },
+    upstream_oauth2::{
+        MemoryUpstreamOAuthLinkRepository, MemoryUpstreamOAuthProviderRepository,
+        MemoryUpstreamOAuthSessionRepository,
+    },
+    user::{
+        MemoryBrowserSessionRepository, MemoryUserEmailRepository, MemoryUserPasswordRepository,
+        MemoryUserPasswordResetTokenRepository, MemoryUserRecoveryCodeRepository,
+        MemoryUserRepository, MemoryUserTermsRepository, MemoryUserTotpRepository,
+        MemoryUserWebAuthnRepository,
+    },
+    MemoryError, MemoryStore, MemoryStoreHandle,
+};
+
+/// An implementation of the [`Repository`] trait backed by an in-memory
+/// store.
+///
+/// The repository works on its own copy of the store, taken when it is
+/// created. When it is saved, the rows it inserted, updated or removed are
+/// written back to the store, leaving the other rows as they are. There is no
+/// conflict detection: if two repositories concurrently change the same row,
+/// the last one to be saved wins.
+pub struct MemoryRepository {
+    handle: MemoryStoreHandle,
+    base: MemoryStore,
+    store: MemoryStore,
+}
+
+impl MemoryRepository {
+    /// Create a new [`MemoryRepository`] working on a snapshot of the store
+    #[must_use]
+    pub fn new(handle: &MemoryStoreHandle) -> Self {
+        let base = handle.snapshot();
+        Self {
+            handle: handle.clone(),
+            store: base.clone(),
+            base,
+        }
+    }
+}
+
+impl Repository<MemoryError> for MemoryRepository {}
+
+impl RepositoryTransaction for MemoryRepository {
+    type Error = MemoryError;
+
+    fn save(self: Box<Self>) -> BoxFuture<'static, Result<(), Self::Error>> {
+        let Self {
+            handle,
+            base,
+            store,
+        } = *self;
+        async move { handle.apply(&base, store) }.boxed()
+    }
+
+    fn cancel(self: Box<Self>) -> BoxFuture<'static, Result<(), Self::Error>> {
+        // Dropping the snapshot is enough to forget about the changes
+        async move { Ok(()) }.boxed()
+    }
+}
+
+impl RepositoryAccess for MemoryRepository {
+    type Error = MemoryError;
+
+    fn upstream_oauth_link<'c>(
+        &'c mut self,
+    ) -> Box<dyn UpstreamOAuthLinkRepository<Error = Self::Error> + 'c> {
+        Box::new(MemoryUpstreamOAuthLinkRepository::new(&mut self.store))
+    }
+
+    fn upstream_oauth_provider<'c>(
+        &'c mut self,
+    ) -> Box<dyn UpstreamOAuthProviderRepository<Error = Self::Error> + 'c> {
+        Box::new(MemoryUpstreamOAuthProviderRepository::new(&mut self.store))
+    }
+
+    fn upstream_oauth_session<'c>(
+        &'c mut self,
+    ) -> Box<dyn UpstreamOAuthSessionRepository<Error = Self::Error> + 'c> {
+        Box::new(MemoryUpstreamOAuthSessionRepository::new(&mut self.store))
+    }
+
+    fn user<'c>(&'c mut self) -> Box<dyn UserRepository<Error = Self::Error> + 'c> {
+        Box::new(MemoryUserRepository::new(&mut self.store))
+    }
+
+    fn user_email<'c>(&'c mut self) -> Box<dyn UserEmailRepository<Error = Self::Error> + 'c> {
+        Box::new(MemoryUserEmailRepository::new(&mut self.store))
+    }
+
+    fn user_password<'c>(
+        &'c mut self,
+    ) -> Box<dyn UserPasswordRepository<Error = Self::Error> + 'c> {
+        Box::new(MemoryUserPasswordRepository::new(&mut self.store))
+    }
+
+    fn user_terms<'c>(&'c mut self) -> Box<dyn UserTermsRepository<Error = Self::Error> + 'c> {
+        Box::new(MemoryUserTermsRepository::new(&mut self.store))
+    }
+
+    fn user_webauthn<'c>(
+        &'c mut self,
+    ) -> Box<dyn UserWebAuthnRepository<Error = Self::Error> + 'c> {
+        Box::new(MemoryUserWebAuthnRepository::new(&mut self.store))
+    }
+
+    fn user_totp<'c>(&'c mut self) -> Box<dyn UserTotpRepository<Error = Self::Error> + 'c> {
+        Box::new(MemoryUserTotpRepository::new(&mut self.store))
+    }
+
+    fn user_recovery_code<'c>(
+        &'c mut self,
+    ) -> Box<dyn UserRecoveryCodeRepository<Error = Self::Error> + 'c> {
+        Box::new(MemoryUserRecoveryCodeRepository::new(&mut self.store))
+    }
+
+    fn user_password_reset_token<'c>(
+        &'c mut self,
+    ) -> Box<dyn UserPasswordResetTokenRepository<Error = Self::Error> + 'c> {
+        Box::new(MemoryUserPasswordResetTokenRepository::new(&mut self.store))
+    }
+
+    fn browser_session<'c>(
+        &'c mut self,
+    ) -> Box<dyn BrowserSessionRepository<Error = Self::Error> + 'c> {
+        Box::new(MemoryBrowserSessionRepository::new(&mut self.store))
+    }
+
+    fn app_session<'c>(&'c mut self) -> Box<dyn AppSessionRepository<Error = Self::Error> + 'c> {
+        Box::new(MemoryAppSessionRepository::new(&mut self.store))
+    }
+
+    fn audit_log<'c>(&'c mut self) -> Box<dyn AuditLogRepository<Error = Self::Error> + 'c> {
+        Box::new(MemoryAuditLogRepository::new(&mut self.store))
+    }
+
+    fn oauth2_client<'c>(
+        &'c mut self,
+    ) -> Box<dyn OAuth2ClientRepository<Error = Self::Error> + 'c> {
+        Box::new(MemoryOAuth2ClientRepository::new(&mut self.store))
+    }
+
+    fn oauth2_consent<'c>(
+        &'c mut self,
+    ) -> Box<dyn OAuth2ConsentRepository<Error = Self::Error> + 'c> {
+        Box::new(MemoryOAuth2ConsentRepository::new(&mut self.store))
+    }
+
+    fn oauth2_authorization_grant<'c>(
+        &'c mut self,
+    ) -> Box<dyn OAuth2AuthorizationGrantRepository<Error = Self::Error> + 'c> {
+        Box::new(MemoryOAuth2AuthorizationGrantRepository::new(
+            &mut self.store,
+        ))
+    }
+
+    fn oauth2_session<'c>(
+        &'c mut self,
+    ) -> Box<dyn OAuth2SessionRepository<Error = Self::Error> + 'c> {
+        Box::new(MemoryOAuth2SessionRepository::new(&mut self.store))
+    }
+
+    fn oauth2_access_token<'c>(
+        &'c mut self,
+    ) -> Box<dyn OAuth2AccessTokenRepository<Error = Self::Error> + 'c> {
+        Box::new(MemoryOAuth2AccessTokenRepository::new(&mut self.store))
+    }
+
+    fn oauth2_refresh_token<'c>(
+        &'c mut self,
+    ) -> Box<dyn OAuth2RefreshTokenRepository<Error = Self::Error> + 'c> {
+        Box::new(MemoryOAuth2RefreshTokenRepository::new(&mut self.store))
+    }
+
+    fn oauth2_device_code_grant<'c>(
+        &'c mut self,
+    ) -> Box<dyn OAuth2DeviceCodeGrantRepository<Error = Self::Error> + 'c> {
+        Box::new(MemoryOAuth2DeviceCodeGrantRepository::new(&mut self.store))
+    }
+
+    fn oauth2_pushed_auth_request<'c>(
+        &'c mut self,
+    ) -> Box<dyn OAuth2PushedAuthRequestRepository<Error = Self::Error> + 'c> {
+        Box::new(MemoryOAuth2PushedAuthRequestRepository::new(
+            &mut self.store,
+        ))
+    }
+
+    fn compat_session<'c>(
+        &'c mut self,
+    ) -> Box<dyn CompatSessionRepository<Error = Self::Error> + 'c> {
+        Box::new(MemoryCompatSessionRepository::new(&mut self.store))
+    }
+
+    fn compat_sso_login<'c>(
+        &'c mut self,
+    ) -> Box<dyn CompatSsoLoginRepository<Error = Self::Error> + 'c> {
+        Box::new(MemoryCompatSsoLoginRepository::new(&mut self.store))
+    }
+
+    fn compat_access_token<'c>(
+        &'c mut self,
+    ) -> Box<dyn CompatAccessTokenRepository<Error = Self::Error> + 'c> {
+        Box::new(MemoryCompatAccessTokenRepository::new(&mut self.store))
+    }
+
+    fn compat_refresh_token<'c>(
+        &'c mut self,
+    ) -> Box<dyn CompatRefreshTokenRepository<Error = Self::Error> + 'c> {
+        Box::new(MemoryCompatRefreshTokenRepository::new(&mut self.store))
+    }
+
+    fn job<'c>(&'c mut self) -> Box<dyn JobRepository<Error = Self::Error> + 'c> {
+        Box::new(MemoryJobRepository::new(&mut self.store))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mas_storage::{clock::MockClock, user::UserRepository, RepositoryAccess};
+    use rand::SeedableRng;
+    use rand_chacha::ChaChaRng;
+
+    use super::*;
+
+    /// Test that changes are only visible once the repository is saved
+    #[tokio::test]
+    async fn test_save_and_cancel() {
+        let store = MemoryStoreHandle::new();
+        let mut rng = ChaChaRng::seed_from_u64(42);
+        let clock = MockClock::default();
+
+        // Cancelled changes are forgotten
+        let mut repo = store.repository();
+        repo.user()
+            .add(&mut rng, &clock, "alice".to_owned())
+            .await
+            .unwrap();
+        Box::new(repo).cancel().await.unwrap();
+
+        let mut repo = store.repository();
+        assert!(!repo.user().exists("alice").await.unwrap());
+
+        // Saved changes are visible to new repositories, but not to the ones
+        // which were started before
+        let mut other = store.repository();
+        let user = repo
+            .user()
+            .add(&mut rng, &clock, "alice".to_owned())
+            .await
+            .unwrap();
+        assert!(!other.user().exists("alice").await.unwrap());
+        Box::new(repo).save().await.unwrap();
+        assert!(!other.user().exists("alice").await.unwrap());
+
+        let mut repo = store.repository();
+        assert_eq!(
+            repo.user().lookup(user.id).await.unwrap(),
+            Some(user.clone())
+        );
+
+        // Saving a repository which started before doesn't undo the changes
+        let bob = other
+            .user()
+            .add(&mut rng, &clock, "bob".to_owned())
+            .await
+            .unwrap();
+        Box::new(other).save().await.unwrap();
+        let mut repo = store.repository();
+        assert_eq!(repo.user().lookup(user.id).await.unwrap(), Some(user));
+        assert_eq!(repo.user().lookup(bob.id).await.unwrap(), Some(bob));
+
+        // Two transactions can't both add a user with the same username: the
+        // last one to be saved fails, without writing anything
+        let mut repo = store.repository();
+        let mut other = store.repository();
+        repo.user()
+            .add(&mut rng, &clock, "charlie".to_owned())
+            .await
+            .unwrap();
+        let charlie = other
+            .user()
+            .add(&mut rng, &clock, "charlie".to_owned())
+            .await
+            .unwrap();
+        let dave = other
+            .user()
+            .add(&mut rng, &clock, "dave".to_owned())
+            .await
+            .unwrap();
+        Box::new(repo).save().await.unwrap();
+        assert!(matches!(
+            Box::new(other).save().await,
+            Err(MemoryError::UniqueViolation {
+                constraint: "users_username_unique"
+            })
+        ));
+
+        let mut repo = store.repository();
+        assert_eq!(repo.user().lookup(charlie.id).await.unwrap(), None);
+        assert_eq!(repo.user().lookup(dave.id).await.unwrap(), None);
+    }
+}
@@ -0,0 +1,405 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The in-memory store itself, holding all the rows of the "database"
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::{Arc, Mutex, PoisonError},
+};
+
+use chrono::{DateTime, Utc};
+use mas_data_model::{
+    AccessToken, AuditLogEntry, Authentication, AuthorizationGrant, BrowserSession, Client,
+    CompatAccessToken, CompatRefreshToken, CompatSession, CompatSsoLogin, DeviceCodeGrant,
+    Password, PushedAuthorizationRequest, RefreshToken, Session, Totp,
+    UpstreamOAuthAuthorizationSession, UpstreamOAuthLink, UpstreamOAuthProvider, User, UserEmail,
+    UserPasswordResetToken, UserRecoveryCode, WebAuthnCredential,
+};
+use oauth2_types::scope::Scope;
+use ulid::Ulid;
+use url::Url;
+
+use crate::{MemoryError, MemoryRepository};
+
+/// A snapshot of all the data held by the in-memory backend
+///
+/// Rows are keyed by their ID, so that iterating over them yields them in
+/// creation order.
+#[derive(Clone, Default)]
+pub(crate) struct MemoryStore {
+    pub(crate) users: BTreeMap<Ulid, User>,
+    pub(crate) user_emails: BTreeMap<Ulid, UserEmail>,
+    pub(crate) user_email_verifications: BTreeMap<Ulid, UserEmailVerificationRow>,
+    pub(crate) user_passwords: BTreeMap<Ulid, UserPasswordRow>,
+    pub(crate) user_password_reset_tokens: BTreeMap<Ulid, UserPasswordResetToken>,
+    pub(crate) user_recovery_codes: BTreeMap<Ulid, UserRecoveryCode>,
+    pub(crate) user_terms: BTreeMap<Ulid, UserTermsRow>,
+    pub(crate) user_totps: BTreeMap<Ulid, Totp>,
+    pub(crate) user_webauthn_credentials: BTreeMap<Ulid, WebAuthnCredential>,
+    pub(crate) browser_sessions: BTreeMap<Ulid, BrowserSession>,
+    pub(crate) authentications: BTreeMap<Ulid, AuthenticationRow>,
+    pub(crate) audit_log_entries: BTreeMap<Ulid, AuditLogEntry>,
+
+    pub(crate) oauth2_clients: BTreeMap<Ulid, OAuth2ClientRow>,
+    /// The consented scopes, keyed by client ID and user ID
+    pub(crate) oauth2_consents: BTreeMap<(Ulid, Ulid), Scope>,
+    pub(crate) oauth2_authorization_grants: BTreeMap<Ulid, AuthorizationGrant>,
+    pub(crate) oauth2_sessions: BTreeMap<Ulid, Session>,
+    pub(crate) oauth2_access_tokens: BTreeMap<Ulid, AccessToken>,
+    pub(crate) oauth2_refresh_tokens: BTreeMap<Ulid, RefreshToken>,
+    pub(crate) oauth2_device_code_grants: BTreeMap<Ulid, DeviceCodeGrant>,
+    pub(crate) oauth2_pushed_auth_requests: BTreeMap<Ulid, PushedAuthorizationRequest>,
+
+    pub(crate) compat_sessions: BTreeMap<Ulid, CompatSession>,
+    pub(crate) compat_sso_logins: BTreeMap<Ulid, CompatSsoLogin>,
+    pub(crate) compat_access_tokens: BTreeMap<Ulid, CompatAccessToken>,
+    pub(crate) compat_refresh_tokens: BTreeMap<Ulid, CompatRefreshToken>,
+
+    pub(crate) upstream_oauth_providers: BTreeMap<Ulid, UpstreamOAuthProvider>,
+    pub(crate) upstream_oauth_links: BTreeMap<Ulid, UpstreamOAuthLink>,
+    pub(crate) upstream_oauth_authorization_sessions:
+        BTreeMap<Ulid, UpstreamOAuthAuthorizationSession>,
+
+    pub(crate) jobs: Vec<ScheduledJob>,
+}
+
+impl MemoryStore {
+    /// Get a browser session, with its user as it is currently stored
+    ///
+    /// Browser sessions embed their user, which might have been updated since
+    /// the session was created.
+    pub(crate) fn browser_session(&self, id: Ulid) -> Option<BrowserSession> {
+        let mut session = self.browser_sessions.get(&id)?.clone();
+        if let Some(user) = self.users.get(&session.user.id) {
+            session.user.clone_from(user);
+        }
+        Some(session)
+    }
+
+    /// Apply the changes made by a transaction to this store
+    ///
+    /// `base` is the state of the store when the transaction started, and
+    /// `changed` the state at the end of the transaction. Only the rows which
+    /// differ between the two are written, so that the changes made by other
+    /// transactions in the meantime are kept.
+    fn apply(&mut self, base: &MemoryStore, changed: MemoryStore) {
+        merge(&mut self.users, &base.users, changed.users);
+        merge(
+            &mut self.user_emails,
+            &base.user_emails,
+            changed.user_emails,
+        );
+        merge(
+            &mut self.user_email_verifications,
+            &base.user_email_verifications,
+            changed.user_email_verifications,
+        );
+        merge(
+            &mut self.user_passwords,
+            &base.user_passwords,
+            changed.user_passwords,
+        );
+        merge(
+            &mut self.user_password_reset_tokens,
+            &base.user_password_reset_tokens,
+            changed.user_password_reset_tokens,
+        );
+        merge(
+            &mut self.user_recovery_codes,
+            &base.user_recovery_codes,
+            changed.user_recovery_codes,
+        );
+        merge(&mut self.user_terms, &base.user_terms, changed.user_terms);
+        merge(&mut self.user_totps, &base.user_totps, changed.user_totps);
+        merge(
+            &mut self.user_webauthn_credentials,
+            &base.user_webauthn_credentials,
+            changed.user_webauthn_credentials,
+        );
+        merge(
+            &mut self.browser_sessions,
+            &base.browser_sessions,
+            changed.browser_sessions,
+        );
+        merge(
+            &mut self.authentications,
+            &base.authentications,
+            changed.authentications,
+        );
+        merge(
+            &mut self.audit_log_entries,
+            &base.audit_log_entries,
+            changed.audit_log_entries,
+        );
+
+        merge(
+            &mut self.oauth2_clients,
+            &base.oauth2_clients,
+            changed.oauth2_clients,
+        );
+        merge(
+            &mut self.oauth2_consents,
+            &base.oauth2_consents,
+            changed.oauth2_consents,
+        );
+        merge(
+            &mut self.oauth2_authorization_grants,
+            &base.oauth2_authorization_grants,
+            changed.oauth2_authorization_grants,
+        );
+        merge(
+            &mut self.oauth2_sessions,
+            &base.oauth2_sessions,
+            changed.oauth2_sessions,
+        );
+        merge(
+            &mut self.oauth2_access_tokens,
+            &base.oauth2_access_tokens,
+            changed.oauth2_access_tokens,
+        );
+        merge(
+            &mut self.oauth2_refresh_tokens,
+            &base.oauth2_refresh_tokens,
+            changed.oauth2_refresh_tokens,
+        );
+        merge(
+            &mut self.oauth2_device_code_grants,
+            &base.oauth2_device_code_grants,
+            changed.oauth2_device_code_grants,
+        );
+        merge(
+            &mut self.oauth2_pushed_auth_requests,
+            &base.oauth2_pushed_auth_requests,
+            changed.oauth2_pushed_auth_requests,
+        );
+
+        merge(
+            &mut self.compat_sessions,
+            &base.compat_sessions,
+            changed.compat_sessions,
+        );
+        merge(
+            &mut self.compat_sso_logins,
+            &base.compat_sso_logins,
+            changed.compat_sso_logins,
+        );
+        merge(
+            &mut self.compat_access_tokens,
+            &base.compat_access_tokens,
+            changed.compat_access_tokens,
+        );
+        merge(
+            &mut self.compat_refresh_tokens,
+            &base.compat_refresh_tokens,
+            changed.compat_refresh_tokens,
+        );
+
+        merge(
+            &mut self.upstream_oauth_providers,
+            &base.upstream_oauth_providers,
+            changed.upstream_oauth_providers,
+        );
+        merge(
+            &mut self.upstream_oauth_links,
+            &base.upstream_oauth_links,
+            changed.upstream_oauth_links,
+        );
+        merge(
+            &mut self.upstream_oauth_authorization_sessions,
+            &base.upstream_oauth_authorization_sessions,
+            changed.upstream_oauth_authorization_sessions,
+        );
+
+        // Jobs are only ever appended
+        self.jobs
+            .extend(changed.jobs.into_iter().skip(base.jobs.len()));
+    }
+
+    /// Check the uniqueness constraints the repositories check when inserting
+    /// rows
+    ///
+    /// Transactions only see the rows which existed when they started, so two
+    /// concurrent transactions can insert conflicting rows, like two users
+    /// with the same username.
+    fn check_constraints(&self) -> Result<(), MemoryError> {
+        ensure_unique(
+            self.users.values().map(|user| &user.username),
+            "users_username_unique",
+        )?;
+        ensure_unique(
+            self.user_totps.values().map(|totp| totp.user_id),
+            "user_totps_user_id_unique",
+        )?;
+        ensure_unique(
+            self.user_webauthn_credentials
+                .values()
+                .map(|credential| &credential.credential_id),
+            "user_webauthn_credentials_credential_id_unique",
+        )?;
+        ensure_unique(
+            self.upstream_oauth_links
+                .values()
+                .map(|link| (link.provider_id, &link.subject)),
+            "upstream_oauth_links_subject_unique",
+        )?;
+        Ok(())
+    }
+}
+
+/// Check that the given values are all different
+fn ensure_unique<T: Ord>(
+    values: impl Iterator<Item = T>,
+    constraint: &'static str,
+) -> Result<(), MemoryError> {
+    let mut seen = BTreeSet::new();
+    for value in values {
+        if !seen.insert(value) {
+            return Err(MemoryError::UniqueViolation { constraint });
+        }
+    }
+    Ok(())
+}
+
+/// Apply to `target` the rows which were inserted, updated or removed between
+/// `base` and `changed`
+fn merge<K: Ord, V: PartialEq>(
+    target: &mut BTreeMap<K, V>,
+    base: &BTreeMap<K, V>,
+    mut changed: BTreeMap<K, V>,
+) {
+    for key in base.keys() {
+        if !changed.contains_key(key) {
+            target.remove(key);
+        }
+    }
+
+    changed.retain(|key, row| base.get(key) != Some(row));
+    target.append(&mut changed);
+}
+
+/// A pending email verification code, whose state depends on when it is
+/// looked up
+#[derive(Clone, PartialEq)]
+pub(crate) struct UserEmailVerificationRow {
+    pub(crate) id: Ulid,
+    pub(crate) user_email_id: Ulid,
+    pub(crate) code: String,
+    pub(crate) created_at: DateTime<Utc>,
+    pub(crate) expires_at: DateTime<Utc>,
+    pub(crate) consumed_at: Option<DateTime<Utc>>,
+    pub(crate) failed_attempts: u32,
+}
+
+/// A password, along with the user it belongs to
+#[derive(Clone, PartialEq)]
+pub(crate) struct UserPasswordRow {
+    pub(crate) user_id: Ulid,
+    pub(crate) password: Password,
+}
+
+/// A record of a user accepting some terms of service
+#[derive(Clone, PartialEq)]
+pub(crate) struct UserTermsRow {
+    pub(crate) user_id: Ulid,
+    pub(crate) terms_url: Url,
+}
+
+/// An authentication, along with the browser session it happened in
+#[derive(Clone, PartialEq)]
+pub(crate) struct AuthenticationRow {
+    pub(crate) user_session_id: Ulid,
+    pub(crate) authentication: Authentication,
+}
+
+/// An OAuth 2.0 client, and whether it was loaded from the configuration
+#[derive(Clone, PartialEq)]
+pub(crate) struct OAuth2ClientRow {
+    pub(crate) client: Client,
+    pub(crate) is_static: bool,
+}
+
+/// A job which was scheduled
+///
+/// The in-memory backend doesn't run jobs, it only records them so that they
+/// can be inspected.
+#[derive(Debug, Clone)]
+pub struct ScheduledJob {
+    /// The ID of the job
+    pub id: String,
+
+    /// The name of the job
+    pub name: &'static str,
+
+    /// The serialized payload of the job
+    pub payload: serde_json::Value,
+}
+
+/// A shared handle on an in-memory store, from which repositories are created
+///
+/// Cloning the handle gives another handle on the same store.
+#[derive(Clone, Default)]
+pub struct MemoryStoreHandle {
+    inner: Arc<Mutex<MemoryStore>>,
+}
+
+impl MemoryStoreHandle {
+    /// Create a handle on a new, empty store
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start a new [`MemoryRepository`] on this store
+    #[must_use]
+    pub fn repository(&self) -> MemoryRepository {
+        MemoryRepository::new(self)
+    }
+
+    /// Take a copy of the current state of the store
+    pub(crate) fn snapshot(&self) -> MemoryStore {
+        self.inner
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Get the jobs which were scheduled on this store, oldest first
+    #[must_use]
+    pub fn scheduled_jobs(&self) -> Vec<ScheduledJob> {
+        self.inner
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .jobs
+            .clone()
+    }
+
+    /// Write back the changes made by a transaction which started from the
+    /// `base` snapshot
+    ///
+    /// Nothing is written if the changes conflict with the ones made by other
+    /// transactions in the meantime.
+    pub(crate) fn apply(
+        &self,
+        base: &MemoryStore,
+        changed: MemoryStore,
+    ) -> Result<(), MemoryError> {
+        let mut store = self.inner.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut merged = store.clone();
+        merged.apply(base, changed);
+        merged.check_constraints()?;
+        *store = merged;
+        Ok(())
+    }
+}
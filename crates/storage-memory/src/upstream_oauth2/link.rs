@@ -0,0 +1,154 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use async_trait::async_trait;
+use mas_data_model::{UpstreamOAuthLink, UpstreamOAuthProvider, User};
+use mas_storage::{
+    upstream_oauth2::{UpstreamOAuthLinkFilter, UpstreamOAuthLinkRepository},
+    Clock, Page, Pagination,
+};
+use rand_core::RngCore;
+use ulid::Ulid;
+
+use crate::{pagination::paginate, MemoryError, MemoryStore};
+
+/// An implementation of [`UpstreamOAuthLinkRepository`] for the in-memory
+/// store
+pub(crate) struct MemoryUpstreamOAuthLinkRepository<'c> {
+    store: &'c mut MemoryStore,
+}
+
+impl<'c> MemoryUpstreamOAuthLinkRepository<'c> {
+    /// Create a new [`MemoryUpstreamOAuthLinkRepository`] from the in-memory
+    /// store
+    pub(crate) fn new(store: &'c mut MemoryStore) -> Self {
+        Self { store }
+    }
+
+    /// Get the links matching the filter
+    fn filtered<'a>(
+        &'a self,
+        filter: &'a UpstreamOAuthLinkFilter<'a>,
+    ) -> impl Iterator<Item = &'a UpstreamOAuthLink> + 'a {
+        self.store
+            .upstream_oauth_links
+            .values()
+            .filter(move |link| self.matches_filter(link, filter))
+    }
+
+    fn matches_filter(
+        &self,
+        link: &UpstreamOAuthLink,
+        filter: &UpstreamOAuthLinkFilter<'_>,
+    ) -> bool {
+        filter
+            .user()
+            .map_or(true, |user| link.user_id == Some(user.id))
+            && filter
+                .provider()
+                .map_or(true, |provider| link.provider_id == provider.id)
+            && filter.provider_enabled().map_or(true, |enabled| {
+                self.store
+                    .upstream_oauth_providers
+                    .get(&link.provider_id)
+                    .is_some_and(|provider| provider.enabled() == enabled)
+            })
+    }
+}
+
+#[async_trait]
+impl<'c> UpstreamOAuthLinkRepository for MemoryUpstreamOAuthLinkRepository<'c> {
+    type Error = MemoryError;
+
+    async fn lookup(&mut self, id: Ulid) -> Result<Option<UpstreamOAuthLink>, Self::Error> {
+        Ok(self.store.upstream_oauth_links.get(&id).cloned())
+    }
+
+    async fn find_by_subject(
+        &mut self,
+        upstream_oauth_provider: &UpstreamOAuthProvider,
+        subject: &str,
+    ) -> Result<Option<UpstreamOAuthLink>, Self::Error> {
+        Ok(self
+            .store
+            .upstream_oauth_links
+            .values()
+            .find(|link| link.provider_id == upstream_oauth_provider.id && link.subject == subject)
+            .cloned())
+    }
+
+    async fn add(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        upstream_oauth_provider: &UpstreamOAuthProvider,
+        subject: String,
+    ) -> Result<UpstreamOAuthLink, Self::Error> {
+        if self
+            .store
+            .upstream_oauth_links
+            .values()
+            .any(|link| link.provider_id == upstream_oauth_provider.id && link.subject == subject)
+        {
+            return Err(MemoryError::UniqueViolation {
+                constraint: "upstream_oauth_links_subject_unique",
+            });
+        }
+
+        let created_at = clock.now();
+        let id = Ulid::from_datetime_with_source(created_at.into(), rng);
+
+        let link = UpstreamOAuthLink {
+            id,
+            provider_id: upstream_oauth_provider.id,
+            user_id: None,
+            subject,
+            created_at,
+        };
+
+        self.store.upstream_oauth_links.insert(id, link.clone());
+
+        Ok(link)
+    }
+
+    async fn associate_to_user(
+        &mut self,
+        upstream_oauth_link: &UpstreamOAuthLink,
+        user: &User,
+    ) -> Result<(), Self::Error> {
+        if let Some(link) = self
+            .store
+            .upstream_oauth_links
+            .get_mut(&upstream_oauth_link.id)
+        {
+            link.user_id = Some(user.id);
+        }
+
+        Ok(())
+    }
+
+    async fn list(
+        &mut self,
+        filter: UpstreamOAuthLinkFilter<'_>,
+        pagination: Pagination,
+    ) -> Result<Page<UpstreamOAuthLink>, Self::Error> {
+        let links = self.filtered(&filter).cloned();
+
+        Ok(paginate(links, pagination, |link| link.id))
+    }
+
+    async fn count(&mut self, filter: UpstreamOAuthLinkFilter<'_>) -> Result<usize, Self::Error> {
+        Ok(self.filtered(&filter).count())
+    }
+}
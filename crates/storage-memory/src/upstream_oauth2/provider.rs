@@ -0,0 +1,216 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use mas_data_model::UpstreamOAuthProvider;
+use mas_storage::{
+    upstream_oauth2::{
+        UpstreamOAuthProviderFilter, UpstreamOAuthProviderParams, UpstreamOAuthProviderRepository,
+    },
+    Clock, Page, Pagination,
+};
+use rand_core::RngCore;
+use ulid::Ulid;
+
+use crate::{pagination::paginate, MemoryError, MemoryStore};
+
+/// An implementation of [`UpstreamOAuthProviderRepository`] for the in-memory
+/// store
+pub(crate) struct MemoryUpstreamOAuthProviderRepository<'c> {
+    store: &'c mut MemoryStore,
+}
+
+impl<'c> MemoryUpstreamOAuthProviderRepository<'c> {
+    /// Create a new [`MemoryUpstreamOAuthProviderRepository`] from the
+    /// in-memory store
+    pub(crate) fn new(store: &'c mut MemoryStore) -> Self {
+        Self { store }
+    }
+
+    /// Get the stored provider, making sure it exists
+    fn row_mut(
+        &mut self,
+        provider: &UpstreamOAuthProvider,
+    ) -> Result<&mut UpstreamOAuthProvider, MemoryError> {
+        self.store
+            .upstream_oauth_providers
+            .get_mut(&provider.id)
+            .ok_or(MemoryError::not_found())
+    }
+
+    /// Get the providers matching the filter
+    fn filtered<'a>(
+        &'a self,
+        filter: &'a UpstreamOAuthProviderFilter<'a>,
+    ) -> impl Iterator<Item = &'a UpstreamOAuthProvider> + 'a {
+        self.store
+            .upstream_oauth_providers
+            .values()
+            .filter(move |provider| {
+                filter
+                    .enabled()
+                    .map_or(true, |enabled| provider.enabled() == enabled)
+            })
+    }
+}
+
+/// Build a provider from its parameters
+fn provider_from_params(
+    id: Ulid,
+    created_at: DateTime<Utc>,
+    params: UpstreamOAuthProviderParams,
+) -> UpstreamOAuthProvider {
+    UpstreamOAuthProvider {
+        id,
+        issuer: params.issuer,
+        human_name: params.human_name,
+        brand_name: params.brand_name,
+        scope: params.scope,
+        client_id: params.client_id,
+        encrypted_client_secret: params.encrypted_client_secret,
+        token_endpoint_signing_alg: params.token_endpoint_signing_alg,
+        token_endpoint_auth_method: params.token_endpoint_auth_method,
+        created_at,
+        disabled_at: None,
+        claims_imports: params.claims_imports,
+        authorization_endpoint_override: params.authorization_endpoint_override,
+        token_endpoint_override: params.token_endpoint_override,
+        jwks_uri_override: params.jwks_uri_override,
+        discovery_mode: params.discovery_mode,
+        pkce_mode: params.pkce_mode,
+        additional_authorization_parameters: params.additional_authorization_parameters,
+        forward_login_hint: params.forward_login_hint,
+    }
+}
+
+#[async_trait]
+impl<'c> UpstreamOAuthProviderRepository for MemoryUpstreamOAuthProviderRepository<'c> {
+    type Error = MemoryError;
+
+    async fn lookup(&mut self, id: Ulid) -> Result<Option<UpstreamOAuthProvider>, Self::Error> {
+        Ok(self.store.upstream_oauth_providers.get(&id).cloned())
+    }
+
+    async fn add(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        params: UpstreamOAuthProviderParams,
+    ) -> Result<UpstreamOAuthProvider, Self::Error> {
+        let created_at = clock.now();
+        let id = Ulid::from_datetime_with_source(created_at.into(), rng);
+
+        let provider = provider_from_params(id, created_at, params);
+
+        self.store
+            .upstream_oauth_providers
+            .insert(id, provider.clone());
+
+        Ok(provider)
+    }
+
+    async fn delete_by_id(&mut self, id: Ulid) -> Result<(), Self::Error> {
+        // Delete the authorization sessions and the links first, like the
+        // PostgreSQL backend has to because of the foreign key constraints
+        self.store
+            .upstream_oauth_authorization_sessions
+            .retain(|_, session| session.provider_id != id);
+        self.store
+            .upstream_oauth_links
+            .retain(|_, link| link.provider_id != id);
+
+        self.store
+            .upstream_oauth_providers
+            .remove(&id)
+            .ok_or(MemoryError::not_found())?;
+
+        Ok(())
+    }
+
+    async fn upsert(
+        &mut self,
+        clock: &dyn Clock,
+        id: Ulid,
+        params: UpstreamOAuthProviderParams,
+    ) -> Result<UpstreamOAuthProvider, Self::Error> {
+        // Keep the creation date of the provider if it already exists
+        let created_at = self
+            .store
+            .upstream_oauth_providers
+            .get(&id)
+            .map_or_else(|| clock.now(), |provider| provider.created_at);
+
+        let provider = provider_from_params(id, created_at, params);
+
+        self.store
+            .upstream_oauth_providers
+            .insert(id, provider.clone());
+
+        Ok(provider)
+    }
+
+    async fn disable(
+        &mut self,
+        clock: &dyn Clock,
+        mut upstream_oauth_provider: UpstreamOAuthProvider,
+    ) -> Result<UpstreamOAuthProvider, Self::Error> {
+        let disabled_at = clock.now();
+
+        self.row_mut(&upstream_oauth_provider)?.disabled_at = Some(disabled_at);
+        upstream_oauth_provider.disabled_at = Some(disabled_at);
+
+        Ok(upstream_oauth_provider)
+    }
+
+    async fn set_encrypted_client_secret(
+        &mut self,
+        mut upstream_oauth_provider: UpstreamOAuthProvider,
+        encrypted_client_secret: Option<String>,
+    ) -> Result<UpstreamOAuthProvider, Self::Error> {
+        self.row_mut(&upstream_oauth_provider)?
+            .encrypted_client_secret
+            .clone_from(&encrypted_client_secret);
+        upstream_oauth_provider.encrypted_client_secret = encrypted_client_secret;
+
+        Ok(upstream_oauth_provider)
+    }
+
+    async fn list(
+        &mut self,
+        filter: UpstreamOAuthProviderFilter<'_>,
+        pagination: Pagination,
+    ) -> Result<Page<UpstreamOAuthProvider>, Self::Error> {
+        let providers = self.filtered(&filter).cloned();
+
+        Ok(paginate(providers, pagination, |provider| provider.id))
+    }
+
+    async fn count(
+        &mut self,
+        filter: UpstreamOAuthProviderFilter<'_>,
+    ) -> Result<usize, Self::Error> {
+        Ok(self.filtered(&filter).count())
+    }
+
+    async fn all_enabled(&mut self) -> Result<Vec<UpstreamOAuthProvider>, Self::Error> {
+        Ok(self
+            .store
+            .upstream_oauth_providers
+            .values()
+            .filter(|provider| provider.enabled())
+            .cloned()
+            .collect())
+    }
+}
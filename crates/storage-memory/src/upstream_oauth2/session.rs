@@ -0,0 +1,125 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use async_trait::async_trait;
+use mas_data_model::{
+    UpstreamOAuthAuthorizationSession, UpstreamOAuthAuthorizationSessionState, UpstreamOAuthLink,
+    UpstreamOAuthProvider,
+};
+use mas_storage::{upstream_oauth2::UpstreamOAuthSessionRepository, Clock};
+use rand_core::RngCore;
+use ulid::Ulid;
+
+use crate::{MemoryError, MemoryStore};
+
+/// An implementation of [`UpstreamOAuthSessionRepository`] for the in-memory
+/// store
+pub(crate) struct MemoryUpstreamOAuthSessionRepository<'c> {
+    store: &'c mut MemoryStore,
+}
+
+impl<'c> MemoryUpstreamOAuthSessionRepository<'c> {
+    /// Create a new [`MemoryUpstreamOAuthSessionRepository`] from the
+    /// in-memory store
+    pub(crate) fn new(store: &'c mut MemoryStore) -> Self {
+        Self { store }
+    }
+
+    /// Replace the state of the stored session, making sure it exists
+    fn update(&mut self, session: &UpstreamOAuthAuthorizationSession) -> Result<(), MemoryError> {
+        let row = self
+            .store
+            .upstream_oauth_authorization_sessions
+            .get_mut(&session.id)
+            .ok_or(MemoryError::not_found())?;
+
+        row.state = session.state.clone();
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<'c> UpstreamOAuthSessionRepository for MemoryUpstreamOAuthSessionRepository<'c> {
+    type Error = MemoryError;
+
+    async fn lookup(
+        &mut self,
+        id: Ulid,
+    ) -> Result<Option<UpstreamOAuthAuthorizationSession>, Self::Error> {
+        Ok(self
+            .store
+            .upstream_oauth_authorization_sessions
+            .get(&id)
+            .cloned())
+    }
+
+    async fn add(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        upstream_oauth_provider: &UpstreamOAuthProvider,
+        state_str: String,
+        code_challenge_verifier: Option<String>,
+        nonce: String,
+    ) -> Result<UpstreamOAuthAuthorizationSession, Self::Error> {
+        let created_at = clock.now();
+        let id = Ulid::from_datetime_with_source(created_at.into(), rng);
+
+        let session = UpstreamOAuthAuthorizationSession {
+            id,
+            state: UpstreamOAuthAuthorizationSessionState::default(),
+            provider_id: upstream_oauth_provider.id,
+            state_str,
+            code_challenge_verifier,
+            nonce,
+            created_at,
+        };
+
+        self.store
+            .upstream_oauth_authorization_sessions
+            .insert(id, session.clone());
+
+        Ok(session)
+    }
+
+    async fn complete_with_link(
+        &mut self,
+        clock: &dyn Clock,
+        upstream_oauth_authorization_session: UpstreamOAuthAuthorizationSession,
+        upstream_oauth_link: &UpstreamOAuthLink,
+        id_token: Option<String>,
+    ) -> Result<UpstreamOAuthAuthorizationSession, Self::Error> {
+        let upstream_oauth_authorization_session = upstream_oauth_authorization_session
+            .complete(clock.now(), upstream_oauth_link, id_token)
+            .map_err(MemoryError::to_invalid_operation)?;
+
+        self.update(&upstream_oauth_authorization_session)?;
+
+        Ok(upstream_oauth_authorization_session)
+    }
+
+    async fn consume(
+        &mut self,
+        clock: &dyn Clock,
+        upstream_oauth_authorization_session: UpstreamOAuthAuthorizationSession,
+    ) -> Result<UpstreamOAuthAuthorizationSession, Self::Error> {
+        let upstream_oauth_authorization_session = upstream_oauth_authorization_session
+            .consume(clock.now())
+            .map_err(MemoryError::to_invalid_operation)?;
+
+        self.update(&upstream_oauth_authorization_session)?;
+
+        Ok(upstream_oauth_authorization_session)
+    }
+}
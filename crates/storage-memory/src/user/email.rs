@@ -0,0 +1,322 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use async_trait::async_trait;
+use mas_data_model::{User, UserEmail, UserEmailVerification, UserEmailVerificationState};
+use mas_storage::{
+    user::{UserEmailFilter, UserEmailRepository},
+    Clock, Page, Pagination,
+};
+use rand_core::RngCore;
+use ulid::Ulid;
+
+use crate::{pagination::paginate, store::UserEmailVerificationRow, MemoryError, MemoryStore};
+
+/// An implementation of [`UserEmailRepository`] for the in-memory store
+pub(crate) struct MemoryUserEmailRepository<'c> {
+    store: &'c mut MemoryStore,
+}
+
+impl<'c> MemoryUserEmailRepository<'c> {
+    /// Create a new [`MemoryUserEmailRepository`] from the in-memory store
+    pub(crate) fn new(store: &'c mut MemoryStore) -> Self {
+        Self { store }
+    }
+}
+
+impl UserEmailVerificationRow {
+    fn into_verification(self, clock: &dyn Clock) -> UserEmailVerification {
+        let now = clock.now();
+        let state = if let Some(when) = self.consumed_at {
+            UserEmailVerificationState::AlreadyUsed { when }
+        } else if self.expires_at <= now {
+            UserEmailVerificationState::Expired {
+                when: self.expires_at,
+            }
+        } else {
+            UserEmailVerificationState::Valid
+        };
+
+        UserEmailVerification {
+            id: self.id,
+            user_email_id: self.user_email_id,
+            code: self.code,
+            state,
+            created_at: self.created_at,
+        }
+    }
+}
+
+fn matches_filter(user_email: &UserEmail, filter: &UserEmailFilter<'_>) -> bool {
+    filter
+        .user()
+        .map_or(true, |user| user_email.user_id == user.id)
+        && filter.email().map_or(true, |email| {
+            user_email.email.to_lowercase() == email.to_lowercase()
+        })
+        && filter.state().map_or(true, |state| {
+            state.is_verified() == user_email.confirmed_at.is_some()
+        })
+}
+
+#[async_trait]
+impl<'c> UserEmailRepository for MemoryUserEmailRepository<'c> {
+    type Error = MemoryError;
+
+    async fn lookup(&mut self, id: Ulid) -> Result<Option<UserEmail>, Self::Error> {
+        Ok(self.store.user_emails.get(&id).cloned())
+    }
+
+    async fn find(&mut self, user: &User, email: &str) -> Result<Option<UserEmail>, Self::Error> {
+        Ok(self
+            .store
+            .user_emails
+            .values()
+            .find(|user_email| user_email.user_id == user.id && user_email.email == email)
+            .cloned())
+    }
+
+    async fn get_primary(&mut self, user: &User) -> Result<Option<UserEmail>, Self::Error> {
+        let Some(id) = user.primary_user_email_id else {
+            return Ok(None);
+        };
+
+        self.lookup(id).await
+    }
+
+    async fn all(&mut self, user: &User) -> Result<Vec<UserEmail>, Self::Error> {
+        let mut user_emails: Vec<UserEmail> = self
+            .store
+            .user_emails
+            .values()
+            .filter(|user_email| user_email.user_id == user.id)
+            .cloned()
+            .collect();
+
+        user_emails.sort_by(|a, b| a.email.cmp(&b.email));
+
+        Ok(user_emails)
+    }
+
+    async fn list(
+        &mut self,
+        filter: UserEmailFilter<'_>,
+        pagination: Pagination,
+    ) -> Result<Page<UserEmail>, Self::Error> {
+        let user_emails = self
+            .store
+            .user_emails
+            .values()
+            .filter(|user_email| matches_filter(user_email, &filter))
+            .cloned();
+
+        Ok(paginate(user_emails, pagination, |user_email| {
+            user_email.id
+        }))
+    }
+
+    async fn count(&mut self, filter: UserEmailFilter<'_>) -> Result<usize, Self::Error> {
+        Ok(self
+            .store
+            .user_emails
+            .values()
+            .filter(|user_email| matches_filter(user_email, &filter))
+            .count())
+    }
+
+    async fn add(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        user: &User,
+        email: String,
+    ) -> Result<UserEmail, Self::Error> {
+        let created_at = clock.now();
+        let id = Ulid::from_datetime_with_source(created_at.into(), rng);
+
+        let user_email = UserEmail {
+            id,
+            user_id: user.id,
+            email,
+            created_at,
+            confirmed_at: None,
+        };
+
+        self.store.user_emails.insert(id, user_email.clone());
+
+        Ok(user_email)
+    }
+
+    async fn remove(&mut self, user_email: UserEmail) -> Result<(), Self::Error> {
+        self.store
+            .user_email_verifications
+            .retain(|_, verification| verification.user_email_id != user_email.id);
+
+        self.store
+            .user_emails
+            .remove(&user_email.id)
+            .ok_or(MemoryError::not_found())?;
+
+        // Like the foreign key in the database, this unsets the primary email
+        // of the user if it was this one
+        for user in self.store.users.values_mut() {
+            if user.primary_user_email_id == Some(user_email.id) {
+                user.primary_user_email_id = None;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn mark_as_verified(
+        &mut self,
+        clock: &dyn Clock,
+        mut user_email: UserEmail,
+    ) -> Result<UserEmail, Self::Error> {
+        let confirmed_at = clock.now();
+        if let Some(row) = self.store.user_emails.get_mut(&user_email.id) {
+            row.confirmed_at = Some(confirmed_at);
+        }
+
+        user_email.confirmed_at = Some(confirmed_at);
+        Ok(user_email)
+    }
+
+    async fn set_as_primary(&mut self, user_email: &UserEmail) -> Result<(), Self::Error> {
+        let Some(row) = self.store.user_emails.get(&user_email.id) else {
+            return Ok(());
+        };
+
+        if let Some(user) = self.store.users.get_mut(&row.user_id) {
+            user.primary_user_email_id = Some(row.id);
+        }
+
+        Ok(())
+    }
+
+    async fn add_verification_code(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        user_email: &UserEmail,
+        max_age: chrono::Duration,
+        code: String,
+    ) -> Result<UserEmailVerification, Self::Error> {
+        let created_at = clock.now();
+        let id = Ulid::from_datetime_with_source(created_at.into(), rng);
+
+        let row = UserEmailVerificationRow {
+            id,
+            user_email_id: user_email.id,
+            code,
+            created_at,
+            expires_at: created_at + max_age,
+            consumed_at: None,
+            failed_attempts: 0,
+        };
+
+        self.store.user_email_verifications.insert(id, row.clone());
+
+        Ok(row.into_verification(clock))
+    }
+
+    async fn find_verification_code(
+        &mut self,
+        clock: &dyn Clock,
+        user_email: &UserEmail,
+        code: &str,
+    ) -> Result<Option<UserEmailVerification>, Self::Error> {
+        Ok(self
+            .store
+            .user_email_verifications
+            .values()
+            .find(|row| row.user_email_id == user_email.id && row.code == code)
+            .cloned()
+            .map(|row| row.into_verification(clock)))
+    }
+
+    async fn find_latest_verification_code(
+        &mut self,
+        clock: &dyn Clock,
+        user_email: &UserEmail,
+    ) -> Result<Option<UserEmailVerification>, Self::Error> {
+        let user_email_ids: Vec<Ulid> = self
+            .store
+            .user_emails
+            .values()
+            .filter(|row| row.email == user_email.email)
+            .map(|row| row.id)
+            .collect();
+
+        Ok(self
+            .store
+            .user_email_verifications
+            .values()
+            .filter(|row| user_email_ids.contains(&row.user_email_id))
+            .max_by_key(|row| row.created_at)
+            .cloned()
+            .map(|row| row.into_verification(clock)))
+    }
+
+    async fn record_failed_verification_attempt(
+        &mut self,
+        clock: &dyn Clock,
+        user_email: &UserEmail,
+    ) -> Result<(), Self::Error> {
+        let now = clock.now();
+
+        for row in self.store.user_email_verifications.values_mut() {
+            if row.user_email_id != user_email.id
+                || row.consumed_at.is_some()
+                || row.expires_at <= now
+            {
+                continue;
+            }
+
+            row.failed_attempts += 1;
+            if row.failed_attempts >= UserEmailVerification::MAX_FAILED_ATTEMPTS {
+                row.expires_at = now;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn consume_verification_code(
+        &mut self,
+        clock: &dyn Clock,
+        mut user_email_verification: UserEmailVerification,
+    ) -> Result<UserEmailVerification, Self::Error> {
+        if !matches!(
+            user_email_verification.state,
+            UserEmailVerificationState::Valid
+        ) {
+            return Err(MemoryError::invalid_operation());
+        }
+
+        let consumed_at = clock.now();
+        if let Some(row) = self
+            .store
+            .user_email_verifications
+            .get_mut(&user_email_verification.id)
+        {
+            row.consumed_at = Some(consumed_at);
+        }
+
+        user_email_verification.state =
+            UserEmailVerificationState::AlreadyUsed { when: consumed_at };
+
+        Ok(user_email_verification)
+    }
+}
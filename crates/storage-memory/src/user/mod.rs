@@ -0,0 +1,225 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A module containing the in-memory implementation of the user-related
+//! repositories
+
+use async_trait::async_trait;
+use mas_data_model::User;
+use mas_storage::{
+    user::{UserFilter, UserRepository},
+    Clock, Page, Pagination,
+};
+use rand_core::RngCore;
+use ulid::Ulid;
+
+use crate::{pagination::paginate, MemoryError, MemoryStore};
+
+mod email;
+mod password;
+mod password_reset;
+mod recovery_code;
+mod session;
+mod terms;
+mod totp;
+mod webauthn;
+
+#[cfg(test)]
+mod tests;
+
+pub(crate) use self::{
+    email::MemoryUserEmailRepository, password::MemoryUserPasswordRepository,
+    password_reset::MemoryUserPasswordResetTokenRepository,
+    recovery_code::MemoryUserRecoveryCodeRepository, session::MemoryBrowserSessionRepository,
+    terms::MemoryUserTermsRepository, totp::MemoryUserTotpRepository,
+    webauthn::MemoryUserWebAuthnRepository,
+};
+
+/// An implementation of [`UserRepository`] for the in-memory store
+pub(crate) struct MemoryUserRepository<'c> {
+    store: &'c mut MemoryStore,
+}
+
+impl<'c> MemoryUserRepository<'c> {
+    /// Create a new [`MemoryUserRepository`] from the in-memory store
+    pub(crate) fn new(store: &'c mut MemoryStore) -> Self {
+        Self { store }
+    }
+
+    /// Get the stored row of a user, making sure it exists
+    fn row_mut(&mut self, user: &User) -> Result<&mut User, MemoryError> {
+        self.store
+            .users
+            .get_mut(&user.id)
+            .ok_or(MemoryError::not_found())
+    }
+}
+
+fn matches_filter(user: &User, filter: &UserFilter<'_>) -> bool {
+    filter
+        .username_prefix()
+        .map_or(true, |prefix| user.username.starts_with(prefix))
+        && filter
+            .can_request_admin()
+            .map_or(true, |can_request_admin| {
+                user.can_request_admin == can_request_admin
+            })
+}
+
+#[async_trait]
+impl<'c> UserRepository for MemoryUserRepository<'c> {
+    type Error = MemoryError;
+
+    async fn lookup(&mut self, id: Ulid) -> Result<Option<User>, Self::Error> {
+        Ok(self.store.users.get(&id).cloned())
+    }
+
+    async fn find_by_username(&mut self, username: &str) -> Result<Option<User>, Self::Error> {
+        Ok(self
+            .store
+            .users
+            .values()
+            .find(|user| user.username == username)
+            .cloned())
+    }
+
+    async fn add(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        username: String,
+    ) -> Result<User, Self::Error> {
+        let created_at = clock.now();
+        let id = Ulid::from_datetime_with_source(created_at.into(), rng);
+
+        // Like the PostgreSQL backend, an existing user with the same username
+        // is reported as no row being inserted
+        let exists = self
+            .store
+            .users
+            .values()
+            .any(|user| user.username == username);
+        MemoryError::ensure_affected_rows(usize::from(!exists), 1)?;
+
+        let user = User {
+            id,
+            username,
+            sub: id.to_string(),
+            primary_user_email_id: None,
+            created_at,
+            locked_at: None,
+            deactivated_at: None,
+            can_request_admin: false,
+            is_admin: false,
+        };
+
+        self.store.users.insert(id, user.clone());
+
+        Ok(user)
+    }
+
+    async fn exists(&mut self, username: &str) -> Result<bool, Self::Error> {
+        Ok(self
+            .store
+            .users
+            .values()
+            .any(|user| user.username == username))
+    }
+
+    async fn lock(&mut self, clock: &dyn Clock, mut user: User) -> Result<User, Self::Error> {
+        if user.locked_at.is_some() {
+            return Ok(user);
+        }
+
+        user.locked_at = Some(clock.now());
+        self.row_mut(&user)?.locked_at = user.locked_at;
+
+        Ok(user)
+    }
+
+    async fn unlock(&mut self, mut user: User) -> Result<User, Self::Error> {
+        if user.locked_at.is_none() {
+            return Ok(user);
+        }
+
+        user.locked_at = None;
+        self.row_mut(&user)?.locked_at = None;
+
+        Ok(user)
+    }
+
+    async fn deactivate(&mut self, clock: &dyn Clock, mut user: User) -> Result<User, Self::Error> {
+        if user.deactivated_at.is_some() {
+            return Ok(user);
+        }
+
+        user.deactivated_at = Some(clock.now());
+        self.row_mut(&user)?.deactivated_at = user.deactivated_at;
+
+        Ok(user)
+    }
+
+    async fn reactivate(&mut self, mut user: User) -> Result<User, Self::Error> {
+        if user.deactivated_at.is_none() {
+            return Ok(user);
+        }
+
+        user.deactivated_at = None;
+        self.row_mut(&user)?.deactivated_at = None;
+
+        Ok(user)
+    }
+
+    async fn set_can_request_admin(
+        &mut self,
+        mut user: User,
+        can_request_admin: bool,
+    ) -> Result<User, Self::Error> {
+        user.can_request_admin = can_request_admin;
+        self.row_mut(&user)?.can_request_admin = can_request_admin;
+
+        Ok(user)
+    }
+
+    async fn set_admin(&mut self, mut user: User, is_admin: bool) -> Result<User, Self::Error> {
+        user.is_admin = is_admin;
+        self.row_mut(&user)?.is_admin = is_admin;
+
+        Ok(user)
+    }
+
+    async fn list(
+        &mut self,
+        filter: UserFilter<'_>,
+        pagination: Pagination,
+    ) -> Result<Page<User>, Self::Error> {
+        let users = self
+            .store
+            .users
+            .values()
+            .filter(|user| matches_filter(user, &filter))
+            .cloned();
+
+        Ok(paginate(users, pagination, |user| user.id))
+    }
+
+    async fn count(&mut self, filter: UserFilter<'_>) -> Result<usize, Self::Error> {
+        Ok(self
+            .store
+            .users
+            .values()
+            .filter(|user| matches_filter(user, &filter))
+            .count())
+    }
+}
@@ -0,0 +1,79 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use async_trait::async_trait;
+use mas_data_model::{Password, User};
+use mas_storage::{user::UserPasswordRepository, Clock};
+use rand_core::RngCore;
+use ulid::Ulid;
+
+use crate::{store::UserPasswordRow, MemoryError, MemoryStore};
+
+/// An implementation of [`UserPasswordRepository`] for the in-memory store
+pub(crate) struct MemoryUserPasswordRepository<'c> {
+    store: &'c mut MemoryStore,
+}
+
+impl<'c> MemoryUserPasswordRepository<'c> {
+    /// Create a new [`MemoryUserPasswordRepository`] from the in-memory store
+    pub(crate) fn new(store: &'c mut MemoryStore) -> Self {
+        Self { store }
+    }
+}
+
+#[async_trait]
+impl<'c> UserPasswordRepository for MemoryUserPasswordRepository<'c> {
+    type Error = MemoryError;
+
+    async fn active(&mut self, user: &User) -> Result<Option<Password>, Self::Error> {
+        Ok(self
+            .store
+            .user_passwords
+            .values()
+            .filter(|row| row.user_id == user.id)
+            .max_by_key(|row| row.password.created_at)
+            .map(|row| row.password.clone()))
+    }
+
+    async fn add(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        user: &User,
+        version: u16,
+        hashed_password: String,
+        upgraded_from: Option<&Password>,
+    ) -> Result<Password, Self::Error> {
+        let created_at = clock.now();
+        let id = Ulid::from_datetime_with_source(created_at.into(), rng);
+
+        let password = Password {
+            id,
+            hashed_password,
+            version,
+            upgraded_from_id: upgraded_from.map(|p| p.id),
+            created_at,
+        };
+
+        self.store.user_passwords.insert(
+            id,
+            UserPasswordRow {
+                user_id: user.id,
+                password: password.clone(),
+            },
+        );
+
+        Ok(password)
+    }
+}
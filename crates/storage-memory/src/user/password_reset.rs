@@ -0,0 +1,92 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use async_trait::async_trait;
+use chrono::Duration;
+use mas_data_model::{User, UserPasswordResetToken};
+use mas_storage::{user::UserPasswordResetTokenRepository, Clock};
+use rand_core::RngCore;
+use ulid::Ulid;
+
+use crate::{MemoryError, MemoryStore};
+
+/// An implementation of [`UserPasswordResetTokenRepository`] for the in-memory
+/// store
+pub(crate) struct MemoryUserPasswordResetTokenRepository<'c> {
+    store: &'c mut MemoryStore,
+}
+
+impl<'c> MemoryUserPasswordResetTokenRepository<'c> {
+    /// Create a new [`MemoryUserPasswordResetTokenRepository`] from the
+    /// in-memory store
+    pub(crate) fn new(store: &'c mut MemoryStore) -> Self {
+        Self { store }
+    }
+}
+
+#[async_trait]
+impl<'c> UserPasswordResetTokenRepository for MemoryUserPasswordResetTokenRepository<'c> {
+    type Error = MemoryError;
+
+    async fn lookup(&mut self, id: Ulid) -> Result<Option<UserPasswordResetToken>, Self::Error> {
+        Ok(self.store.user_password_reset_tokens.get(&id).cloned())
+    }
+
+    async fn add(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        user: &User,
+        hashed_secret: String,
+        expires_in: Duration,
+    ) -> Result<UserPasswordResetToken, Self::Error> {
+        let created_at = clock.now();
+        let id = Ulid::from_datetime_with_source(created_at.into(), rng);
+
+        let token = UserPasswordResetToken {
+            id,
+            user_id: user.id,
+            hashed_secret,
+            created_at,
+            expires_at: created_at + expires_in,
+            consumed_at: None,
+        };
+
+        self.store
+            .user_password_reset_tokens
+            .insert(id, token.clone());
+
+        Ok(token)
+    }
+
+    async fn consume(
+        &mut self,
+        clock: &dyn Clock,
+        mut token: UserPasswordResetToken,
+    ) -> Result<UserPasswordResetToken, Self::Error> {
+        let consumed_at = clock.now();
+
+        // The same token can't be consumed twice, nor after it expired
+        let row = self
+            .store
+            .user_password_reset_tokens
+            .get_mut(&token.id)
+            .filter(|row| row.consumed_at.is_none() && row.expires_at > consumed_at)
+            .ok_or(MemoryError::not_found())?;
+
+        row.consumed_at = Some(consumed_at);
+        token.consumed_at = Some(consumed_at);
+        Ok(token)
+    }
+}
@@ -0,0 +1,121 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use async_trait::async_trait;
+use mas_data_model::{User, UserRecoveryCode};
+use mas_storage::{user::UserRecoveryCodeRepository, Clock};
+use rand_core::RngCore;
+use ulid::Ulid;
+
+use crate::{MemoryError, MemoryStore};
+
+/// An implementation of [`UserRecoveryCodeRepository`] for the in-memory store
+pub(crate) struct MemoryUserRecoveryCodeRepository<'c> {
+    store: &'c mut MemoryStore,
+}
+
+impl<'c> MemoryUserRecoveryCodeRepository<'c> {
+    /// Create a new [`MemoryUserRecoveryCodeRepository`] from the in-memory
+    /// store
+    pub(crate) fn new(store: &'c mut MemoryStore) -> Self {
+        Self { store }
+    }
+}
+
+#[async_trait]
+impl<'c> UserRecoveryCodeRepository for MemoryUserRecoveryCodeRepository<'c> {
+    type Error = MemoryError;
+
+    async fn find_active(
+        &mut self,
+        user: &User,
+        hashed_code: &str,
+    ) -> Result<Option<UserRecoveryCode>, Self::Error> {
+        Ok(self
+            .store
+            .user_recovery_codes
+            .values()
+            .find(|code| {
+                code.user_id == user.id && code.hashed_code == hashed_code && !code.is_consumed()
+            })
+            .cloned())
+    }
+
+    async fn count_active(&mut self, user: &User) -> Result<usize, Self::Error> {
+        Ok(self
+            .store
+            .user_recovery_codes
+            .values()
+            .filter(|code| code.user_id == user.id && !code.is_consumed())
+            .count())
+    }
+
+    async fn add(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        user: &User,
+        hashed_codes: Vec<String>,
+    ) -> Result<Vec<UserRecoveryCode>, Self::Error> {
+        let created_at = clock.now();
+
+        let codes: Vec<UserRecoveryCode> = hashed_codes
+            .into_iter()
+            .map(|hashed_code| UserRecoveryCode {
+                id: Ulid::from_datetime_with_source(created_at.into(), rng),
+                user_id: user.id,
+                hashed_code,
+                created_at,
+                consumed_at: None,
+            })
+            .collect();
+
+        for code in &codes {
+            self.store.user_recovery_codes.insert(code.id, code.clone());
+        }
+
+        Ok(codes)
+    }
+
+    async fn consume(
+        &mut self,
+        clock: &dyn Clock,
+        mut recovery_code: UserRecoveryCode,
+    ) -> Result<Option<UserRecoveryCode>, Self::Error> {
+        let consumed_at = clock.now();
+
+        // The same code can't be consumed twice
+        let Some(row) = self
+            .store
+            .user_recovery_codes
+            .get_mut(&recovery_code.id)
+            .filter(|row| !row.is_consumed())
+        else {
+            return Ok(None);
+        };
+
+        row.consumed_at = Some(consumed_at);
+        recovery_code.consumed_at = Some(consumed_at);
+        Ok(Some(recovery_code))
+    }
+
+    async fn delete_all_for_user(&mut self, user: &User) -> Result<usize, Self::Error> {
+        let before = self.store.user_recovery_codes.len();
+        self.store
+            .user_recovery_codes
+            .retain(|_, code| code.user_id != user.id);
+
+        Ok(before - self.store.user_recovery_codes.len())
+    }
+}
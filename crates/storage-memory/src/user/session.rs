@@ -0,0 +1,405 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    net::IpAddr,
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use mas_data_model::{
+    Authentication, AuthenticationMethod, BrowserSession, Password, Totp,
+    UpstreamOAuthAuthorizationSession, User, UserAgent, UserRecoveryCode, WebAuthnCredential,
+};
+use mas_storage::{
+    pagination::{PaginationOrder, PaginationOrderBy},
+    user::{BrowserSessionFilter, BrowserSessionRepository},
+    Clock, Page, Pagination,
+};
+use rand_core::RngCore;
+use ulid::Ulid;
+
+use crate::{
+    pagination::{paginate, paginate_ordered},
+    store::AuthenticationRow,
+    MemoryError, MemoryStore,
+};
+
+/// An implementation of [`BrowserSessionRepository`] for the in-memory store
+pub(crate) struct MemoryBrowserSessionRepository<'c> {
+    store: &'c mut MemoryStore,
+}
+
+impl<'c> MemoryBrowserSessionRepository<'c> {
+    /// Create a new [`MemoryBrowserSessionRepository`] from the in-memory
+    /// store
+    pub(crate) fn new(store: &'c mut MemoryStore) -> Self {
+        Self { store }
+    }
+
+    /// Get the sessions matching the filter, with their user up to date
+    fn filtered<'a>(
+        &'a self,
+        filter: &'a BrowserSessionFilter<'a>,
+    ) -> impl Iterator<Item = BrowserSession> + 'a {
+        self.store
+            .browser_sessions
+            .keys()
+            .filter_map(move |id| self.store.browser_session(*id))
+            .filter(move |session| self.matches_filter(session, filter))
+    }
+
+    fn matches_filter(&self, session: &BrowserSession, filter: &BrowserSessionFilter<'_>) -> bool {
+        let authentications = || {
+            self.store
+                .authentications
+                .values()
+                .filter(|row| row.user_session_id == session.id)
+        };
+
+        filter.user().map_or(true, |user| session.user.id == user.id)
+            && filter.state().map_or(true, |(state, now)| {
+                let session = session.clone().finish_if_expired(now);
+                state.is_active() == session.finished_at.is_none()
+            })
+            && filter
+                .created_after()
+                .map_or(true, |created_after| session.created_at >= created_after)
+            && filter
+                .created_before()
+                .map_or(true, |created_before| session.created_at <= created_before)
+            && filter.last_authenticated_after().map_or(true, |after| {
+                authentications().any(|row| row.authentication.created_at >= after)
+            })
+            // Sessions without any authentication are considered to have been
+            // authenticated infinitely long ago
+            && filter.last_authenticated_before().map_or(true, |before| {
+                !authentications().any(|row| row.authentication.created_at > before)
+            })
+    }
+
+    /// Record a new authentication of the session
+    fn authenticate(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        user_session: &BrowserSession,
+        authentication_method: AuthenticationMethod,
+    ) -> Authentication {
+        let created_at = clock.now();
+        let id = Ulid::from_datetime_with_source(created_at.into(), rng);
+
+        let authentication = Authentication {
+            id,
+            created_at,
+            authentication_method,
+        };
+
+        self.store.authentications.insert(
+            id,
+            AuthenticationRow {
+                user_session_id: user_session.id,
+                authentication: authentication.clone(),
+            },
+        );
+
+        authentication
+    }
+}
+
+#[async_trait]
+impl<'c> BrowserSessionRepository for MemoryBrowserSessionRepository<'c> {
+    type Error = MemoryError;
+
+    async fn lookup(
+        &mut self,
+        clock: &dyn Clock,
+        id: Ulid,
+    ) -> Result<Option<BrowserSession>, Self::Error> {
+        Ok(self
+            .store
+            .browser_session(id)
+            .map(|session| session.finish_if_expired(clock.now())))
+    }
+
+    async fn lookup_batch(
+        &mut self,
+        clock: &dyn Clock,
+        ids: &[Ulid],
+    ) -> Result<HashMap<Ulid, BrowserSession>, Self::Error> {
+        let now = clock.now();
+        Ok(ids
+            .iter()
+            .filter_map(|id| self.store.browser_session(*id))
+            .map(|session| (session.id, session.finish_if_expired(now)))
+            .collect())
+    }
+
+    async fn add(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        user: &User,
+        user_agent: Option<UserAgent>,
+        ip_address: Option<IpAddr>,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Result<BrowserSession, Self::Error> {
+        if user.deactivated_at.is_some() {
+            return Err(MemoryError::UserDeactivated { user_id: user.id });
+        }
+
+        let created_at = clock.now();
+        let id = Ulid::from_datetime_with_source(created_at.into(), rng);
+
+        let session = BrowserSession {
+            id,
+            user: user.clone(),
+            created_at,
+            finished_at: None,
+            expires_at,
+            user_agent,
+            ip_address,
+            last_active_at: None,
+            last_active_ip: None,
+        };
+
+        self.store.browser_sessions.insert(id, session.clone());
+
+        Ok(session)
+    }
+
+    async fn finish(
+        &mut self,
+        clock: &dyn Clock,
+        mut user_session: BrowserSession,
+    ) -> Result<BrowserSession, Self::Error> {
+        let finished_at = clock.now();
+        let row = self
+            .store
+            .browser_sessions
+            .get_mut(&user_session.id)
+            .ok_or(MemoryError::not_found())?;
+
+        row.finished_at = Some(finished_at);
+        user_session.finished_at = Some(finished_at);
+
+        Ok(user_session)
+    }
+
+    async fn finish_all_for_user(
+        &mut self,
+        clock: &dyn Clock,
+        user: &User,
+    ) -> Result<usize, Self::Error> {
+        let finished_at = clock.now();
+        let mut count = 0;
+        for session in self.store.browser_sessions.values_mut() {
+            if session.user.id == user.id && session.finished_at.is_none() {
+                session.finished_at = Some(finished_at);
+                count += 1;
+            }
+        }
+
+        Ok(count)
+    }
+
+    async fn list(
+        &mut self,
+        filter: BrowserSessionFilter<'_>,
+        pagination: Pagination,
+    ) -> Result<Page<BrowserSession>, Self::Error> {
+        let sessions: Vec<BrowserSession> = self.filtered(&filter).collect();
+
+        // Unlike the cursors, the total count ignores the pagination
+        let total_count = pagination.with_total_count.then(|| sessions.len() as u64);
+
+        let id = |session: &BrowserSession| session.id;
+        let page = match pagination.order_by {
+            PaginationOrderBy::Id => paginate(sessions, pagination, id),
+            PaginationOrderBy::CreatedAt => {
+                paginate_ordered(sessions, pagination, id, |session| Some(session.created_at))
+            }
+            PaginationOrderBy::FinishedAt => {
+                paginate_ordered(sessions, pagination, id, |session| session.finished_at)
+            }
+        };
+
+        Ok(page.with_total_count(total_count))
+    }
+
+    async fn count(&mut self, filter: BrowserSessionFilter<'_>) -> Result<usize, Self::Error> {
+        Ok(self.filtered(&filter).count())
+    }
+
+    async fn authenticate_with_password(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        user_session: &BrowserSession,
+        user_password: &Password,
+    ) -> Result<Authentication, Self::Error> {
+        Ok(self.authenticate(
+            rng,
+            clock,
+            user_session,
+            AuthenticationMethod::Password {
+                user_password_id: user_password.id,
+            },
+        ))
+    }
+
+    async fn authenticate_with_upstream(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        user_session: &BrowserSession,
+        upstream_oauth_session: &UpstreamOAuthAuthorizationSession,
+    ) -> Result<Authentication, Self::Error> {
+        Ok(self.authenticate(
+            rng,
+            clock,
+            user_session,
+            AuthenticationMethod::UpstreamOAuth2 {
+                upstream_oauth2_session_id: upstream_oauth_session.id,
+                upstream_oauth_link_id: upstream_oauth_session.link_id(),
+            },
+        ))
+    }
+
+    async fn authenticate_with_webauthn(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        user_session: &BrowserSession,
+        credential: &WebAuthnCredential,
+    ) -> Result<Authentication, Self::Error> {
+        Ok(self.authenticate(
+            rng,
+            clock,
+            user_session,
+            AuthenticationMethod::WebAuthn {
+                user_webauthn_credential_id: credential.id,
+            },
+        ))
+    }
+
+    async fn authenticate_with_totp(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        user_session: &BrowserSession,
+        totp: &Totp,
+    ) -> Result<Authentication, Self::Error> {
+        Ok(self.authenticate(
+            rng,
+            clock,
+            user_session,
+            AuthenticationMethod::Totp {
+                user_totp_id: totp.id,
+            },
+        ))
+    }
+
+    async fn authenticate_with_recovery_code(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        user_session: &BrowserSession,
+        recovery_code: &UserRecoveryCode,
+    ) -> Result<Authentication, Self::Error> {
+        Ok(self.authenticate(
+            rng,
+            clock,
+            user_session,
+            AuthenticationMethod::RecoveryCode {
+                user_recovery_code_id: recovery_code.id,
+            },
+        ))
+    }
+
+    async fn get_last_authentication(
+        &mut self,
+        user_session: &BrowserSession,
+    ) -> Result<Option<Authentication>, Self::Error> {
+        Ok(self
+            .store
+            .authentications
+            .values()
+            .filter(|row| row.user_session_id == user_session.id)
+            .max_by_key(|row| row.authentication.created_at)
+            .map(|row| row.authentication.clone()))
+    }
+
+    async fn get_last_authentication_batch(
+        &mut self,
+        user_session_ids: BTreeSet<Ulid>,
+    ) -> Result<BTreeMap<Ulid, Authentication>, Self::Error> {
+        let mut result: BTreeMap<Ulid, Authentication> = BTreeMap::new();
+        for row in self.store.authentications.values() {
+            if !user_session_ids.contains(&row.user_session_id) {
+                continue;
+            }
+
+            let is_latest = result.get(&row.user_session_id).map_or(true, |latest| {
+                latest.created_at <= row.authentication.created_at
+            });
+            if is_latest {
+                result.insert(row.user_session_id, row.authentication.clone());
+            }
+        }
+
+        Ok(result)
+    }
+
+    async fn list_authentications(
+        &mut self,
+        user_session: &BrowserSession,
+        pagination: Pagination,
+    ) -> Result<Page<Authentication>, Self::Error> {
+        // Authentication IDs are ULIDs, so ordering them by ID in descending
+        // order lists the most recent first
+        let pagination = pagination.ordered_by(PaginationOrderBy::Id, PaginationOrder::Descending);
+
+        let authentications = self
+            .store
+            .authentications
+            .values()
+            .filter(|row| row.user_session_id == user_session.id)
+            .map(|row| row.authentication.clone());
+
+        Ok(paginate(authentications, pagination, |authentication| {
+            authentication.id
+        }))
+    }
+
+    async fn record_batch_activity(
+        &mut self,
+        activity: Vec<(Ulid, DateTime<Utc>, Option<IpAddr>)>,
+    ) -> Result<(), Self::Error> {
+        let expected = activity.len();
+        let mut actual = 0;
+        for (id, last_active_at, last_active_ip) in activity {
+            let Some(session) = self.store.browser_sessions.get_mut(&id) else {
+                continue;
+            };
+
+            session.last_active_at = session.last_active_at.max(Some(last_active_at));
+            session.last_active_ip = last_active_ip.or(session.last_active_ip);
+            actual += 1;
+        }
+
+        MemoryError::ensure_affected_rows(actual, expected)
+    }
+}
@@ -0,0 +1,70 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use async_trait::async_trait;
+use mas_data_model::User;
+use mas_storage::{user::UserTermsRepository, Clock};
+use rand_core::RngCore;
+use ulid::Ulid;
+use url::Url;
+
+use crate::{store::UserTermsRow, MemoryError, MemoryStore};
+
+/// An implementation of [`UserTermsRepository`] for the in-memory store
+pub(crate) struct MemoryUserTermsRepository<'c> {
+    store: &'c mut MemoryStore,
+}
+
+impl<'c> MemoryUserTermsRepository<'c> {
+    /// Create a new [`MemoryUserTermsRepository`] from the in-memory store
+    pub(crate) fn new(store: &'c mut MemoryStore) -> Self {
+        Self { store }
+    }
+}
+
+#[async_trait]
+impl<'c> UserTermsRepository for MemoryUserTermsRepository<'c> {
+    type Error = MemoryError;
+
+    async fn accept_terms(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        user: &User,
+        terms_url: Url,
+    ) -> Result<(), Self::Error> {
+        let created_at = clock.now();
+        let id = Ulid::from_datetime_with_source(created_at.into(), rng);
+
+        // Accepting the same terms twice is a no-op
+        let already_accepted = self
+            .store
+            .user_terms
+            .values()
+            .any(|row| row.user_id == user.id && row.terms_url == terms_url);
+        if already_accepted {
+            return Ok(());
+        }
+
+        self.store.user_terms.insert(
+            id,
+            UserTermsRow {
+                user_id: user.id,
+                terms_url,
+            },
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,273 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use chrono::Duration;
+use mas_storage::{
+    clock::MockClock,
+    user::{BrowserSessionFilter, BrowserSessionRepository, UserFilter, UserRepository},
+    Clock, Pagination, RepositoryAccess,
+};
+use rand::SeedableRng;
+use rand_chacha::ChaChaRng;
+
+use crate::{MemoryError, MemoryStoreHandle};
+
+/// Test the user repository, by adding and looking up a user
+#[tokio::test]
+async fn test_user_repo() {
+    const USERNAME: &str = "john";
+
+    let mut repo = MemoryStoreHandle::new().repository();
+    let mut rng = ChaChaRng::seed_from_u64(42);
+    let clock = MockClock::default();
+
+    // Initially, the user shouldn't exist
+    assert!(!repo.user().exists(USERNAME).await.unwrap());
+    assert!(repo
+        .user()
+        .find_by_username(USERNAME)
+        .await
+        .unwrap()
+        .is_none());
+
+    // Adding the user should work
+    let user = repo
+        .user()
+        .add(&mut rng, &clock, USERNAME.to_owned())
+        .await
+        .unwrap();
+
+    // And now it should exist
+    assert!(repo.user().exists(USERNAME).await.unwrap());
+    assert_eq!(
+        repo.user().find_by_username(USERNAME).await.unwrap(),
+        Some(user.clone())
+    );
+    assert_eq!(
+        repo.user().lookup(user.id).await.unwrap(),
+        Some(user.clone())
+    );
+
+    // Adding a second time should give a conflict
+    assert!(repo
+        .user()
+        .add(&mut rng, &clock, USERNAME.to_owned())
+        .await
+        .is_err());
+
+    // Try locking a user
+    assert!(user.is_valid());
+    let user = repo.user().lock(&clock, user).await.unwrap();
+    assert!(!user.is_valid());
+
+    // Check that the property is retrieved on lookup
+    let user = repo.user().lookup(user.id).await.unwrap().unwrap();
+    assert!(!user.is_valid());
+
+    // Try unlocking a user
+    let user = repo.user().unlock(user).await.unwrap();
+    let user = repo.user().lookup(user.id).await.unwrap().unwrap();
+    assert!(user.is_valid());
+
+    // Try deactivating a user
+    let user = repo.user().deactivate(&clock, user).await.unwrap();
+    let user = repo.user().lookup(user.id).await.unwrap().unwrap();
+    assert!(user.is_deactivated());
+
+    // Deactivated users can't start new browser sessions
+    assert!(matches!(
+        repo.browser_session()
+            .add(&mut rng, &clock, &user, None, None, None)
+            .await,
+        Err(MemoryError::UserDeactivated { user_id }) if user_id == user.id
+    ));
+
+    // Try reactivating a user
+    let user = repo.user().reactivate(user).await.unwrap();
+    let user = repo.user().lookup(user.id).await.unwrap().unwrap();
+    assert!(!user.is_deactivated());
+
+    // Reactivated users can start browser sessions again
+    repo.browser_session()
+        .add(&mut rng, &clock, &user, None, None, None)
+        .await
+        .unwrap();
+}
+
+/// Test listing and counting users with filters
+#[tokio::test]
+async fn test_user_list() {
+    let mut repo = MemoryStoreHandle::new().repository();
+    let mut rng = ChaChaRng::seed_from_u64(42);
+    let clock = MockClock::default();
+
+    let alice = repo
+        .user()
+        .add(&mut rng, &clock, "alice".to_owned())
+        .await
+        .unwrap();
+    let alice = repo
+        .user()
+        .set_can_request_admin(alice, true)
+        .await
+        .unwrap();
+    clock.advance(chrono::Duration::try_minutes(1).unwrap());
+    let bob = repo
+        .user()
+        .add(&mut rng, &clock, "bob".to_owned())
+        .await
+        .unwrap();
+
+    let all = UserFilter::new();
+    assert_eq!(repo.user().count(all).await.unwrap(), 2);
+    let page = repo.user().list(all, Pagination::first(1)).await.unwrap();
+    assert!(page.has_next_page);
+    assert_eq!(page.edges, vec![alice.clone()]);
+
+    // The next page starts after the first user
+    let page = repo
+        .user()
+        .list(all, Pagination::first(1).after(alice.id))
+        .await
+        .unwrap();
+    assert!(!page.has_next_page);
+    assert_eq!(page.edges, vec![bob.clone()]);
+
+    // Going backwards gives the last users first
+    let page = repo.user().list(all, Pagination::last(1)).await.unwrap();
+    assert!(page.has_previous_page);
+    assert_eq!(page.edges, vec![bob]);
+
+    let admins = UserFilter::new().with_can_request_admin(true);
+    assert_eq!(repo.user().count(admins).await.unwrap(), 1);
+    let page = repo
+        .user()
+        .list(admins, Pagination::first(10))
+        .await
+        .unwrap();
+    assert_eq!(page.edges, vec![alice]);
+}
+
+/// Test starting and finishing browser sessions
+#[tokio::test]
+async fn test_user_session() {
+    let mut repo = MemoryStoreHandle::new().repository();
+    let mut rng = ChaChaRng::seed_from_u64(42);
+    let clock = MockClock::default();
+
+    let user = repo
+        .user()
+        .add(&mut rng, &clock, "john".to_owned())
+        .await
+        .unwrap();
+
+    let all = BrowserSessionFilter::default().for_user(&user);
+    let active = all.active_only(clock.now());
+    let finished = all.finished_only(clock.now());
+
+    let ip_address = "203.0.113.42".parse().unwrap();
+    let session = repo
+        .browser_session()
+        .add(&mut rng, &clock, &user, None, Some(ip_address), None)
+        .await
+        .unwrap();
+    assert_eq!(session.user.id, user.id);
+    assert_eq!(session.ip_address, Some(ip_address));
+
+    assert_eq!(repo.browser_session().count(all).await.unwrap(), 1);
+    assert_eq!(repo.browser_session().count(active).await.unwrap(), 1);
+    assert_eq!(repo.browser_session().count(finished).await.unwrap(), 0);
+
+    // Locking the user is reflected on the session
+    let user = repo.user().lock(&clock, user).await.unwrap();
+    let session = repo
+        .browser_session()
+        .lookup(&clock, session.id)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(session.user, user);
+
+    // Finish the session
+    let session = repo
+        .browser_session()
+        .finish(&clock, session)
+        .await
+        .unwrap();
+    assert!(session.finished_at.is_some());
+
+    assert_eq!(repo.browser_session().count(all).await.unwrap(), 1);
+    assert_eq!(repo.browser_session().count(active).await.unwrap(), 0);
+    assert_eq!(repo.browser_session().count(finished).await.unwrap(), 1);
+
+    let page = repo
+        .browser_session()
+        .list(finished, Pagination::first(10))
+        .await
+        .unwrap();
+    assert_eq!(page.edges, vec![session]);
+}
+
+/// Test that browser sessions are considered finished once they expire
+#[tokio::test]
+async fn test_user_session_expiry() {
+    let mut repo = MemoryStoreHandle::new().repository();
+    let mut rng = ChaChaRng::seed_from_u64(42);
+    let clock = MockClock::default();
+
+    let user = repo
+        .user()
+        .add(&mut rng, &clock, "john".to_owned())
+        .await
+        .unwrap();
+
+    let expires_at = clock.now() + Duration::try_hours(1).unwrap();
+    let session = repo
+        .browser_session()
+        .add(&mut rng, &clock, &user, None, None, Some(expires_at))
+        .await
+        .unwrap();
+
+    let session = repo
+        .browser_session()
+        .lookup(&clock, session.id)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(session.active());
+
+    clock.advance(Duration::try_hours(1).unwrap());
+    let session = repo
+        .browser_session()
+        .lookup(&clock, session.id)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(session.finished_at, Some(expires_at));
+    assert!(!session.active());
+
+    let sessions = repo
+        .browser_session()
+        .lookup_batch(&clock, &[session.id])
+        .await
+        .unwrap();
+    assert_eq!(sessions[&session.id].finished_at, Some(expires_at));
+
+    // It is counted as finished, not as active
+    let all = BrowserSessionFilter::default().for_user(&user);
+    let active = all.active_only(clock.now());
+    let finished = all.finished_only(clock.now());
+    assert_eq!(repo.browser_session().count(active).await.unwrap(), 0);
+    assert_eq!(repo.browser_session().count(finished).await.unwrap(), 1);
+}
@@ -0,0 +1,107 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use async_trait::async_trait;
+use mas_data_model::{Totp, User};
+use mas_storage::{user::UserTotpRepository, Clock};
+use rand_core::RngCore;
+use ulid::Ulid;
+
+use crate::{MemoryError, MemoryStore};
+
+/// An implementation of [`UserTotpRepository`] for the in-memory store
+pub(crate) struct MemoryUserTotpRepository<'c> {
+    store: &'c mut MemoryStore,
+}
+
+impl<'c> MemoryUserTotpRepository<'c> {
+    /// Create a new [`MemoryUserTotpRepository`] from the in-memory store
+    pub(crate) fn new(store: &'c mut MemoryStore) -> Self {
+        Self { store }
+    }
+}
+
+#[async_trait]
+impl<'c> UserTotpRepository for MemoryUserTotpRepository<'c> {
+    type Error = MemoryError;
+
+    async fn lookup_for_user(&mut self, user: &User) -> Result<Option<Totp>, Self::Error> {
+        Ok(self
+            .store
+            .user_totps
+            .values()
+            .find(|totp| totp.user_id == user.id)
+            .cloned())
+    }
+
+    async fn add(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        user: &User,
+        encrypted_secret: String,
+    ) -> Result<Totp, Self::Error> {
+        let created_at = clock.now();
+        let id = Ulid::from_datetime_with_source(created_at.into(), rng);
+
+        if self
+            .store
+            .user_totps
+            .values()
+            .any(|totp| totp.user_id == user.id)
+        {
+            return Err(MemoryError::UniqueViolation {
+                constraint: "user_totps_user_id_unique",
+            });
+        }
+
+        let totp = Totp {
+            id,
+            user_id: user.id,
+            encrypted_secret,
+            created_at,
+            last_used_counter: None,
+        };
+
+        self.store.user_totps.insert(id, totp.clone());
+
+        Ok(totp)
+    }
+
+    async fn verify(&mut self, mut totp: Totp, counter: u64) -> Result<Option<Totp>, Self::Error> {
+        // Only bump the counter if it is more recent than the last one used, so
+        // that a code can't be used twice
+        let Some(row) = self
+            .store
+            .user_totps
+            .get_mut(&totp.id)
+            .filter(|row| row.last_used_counter.map_or(true, |last| last < counter))
+        else {
+            return Ok(None);
+        };
+
+        row.last_used_counter = Some(counter);
+        totp.last_used_counter = Some(counter);
+        Ok(Some(totp))
+    }
+
+    async fn delete(&mut self, totp: Totp) -> Result<(), Self::Error> {
+        self.store
+            .user_totps
+            .remove(&totp.id)
+            .ok_or(MemoryError::not_found())?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,134 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use async_trait::async_trait;
+use mas_data_model::{User, WebAuthnCredential};
+use mas_storage::{user::UserWebAuthnRepository, Clock};
+use rand_core::RngCore;
+use ulid::Ulid;
+
+use crate::{MemoryError, MemoryStore};
+
+/// An implementation of [`UserWebAuthnRepository`] for the in-memory store
+pub(crate) struct MemoryUserWebAuthnRepository<'c> {
+    store: &'c mut MemoryStore,
+}
+
+impl<'c> MemoryUserWebAuthnRepository<'c> {
+    /// Create a new [`MemoryUserWebAuthnRepository`] from the in-memory store
+    pub(crate) fn new(store: &'c mut MemoryStore) -> Self {
+        Self { store }
+    }
+}
+
+#[async_trait]
+impl<'c> UserWebAuthnRepository for MemoryUserWebAuthnRepository<'c> {
+    type Error = MemoryError;
+
+    async fn lookup(&mut self, id: Ulid) -> Result<Option<WebAuthnCredential>, Self::Error> {
+        Ok(self.store.user_webauthn_credentials.get(&id).cloned())
+    }
+
+    async fn find_by_credential_id(
+        &mut self,
+        credential_id: &[u8],
+    ) -> Result<Option<WebAuthnCredential>, Self::Error> {
+        Ok(self
+            .store
+            .user_webauthn_credentials
+            .values()
+            .find(|credential| credential.credential_id == credential_id)
+            .cloned())
+    }
+
+    async fn list_for_user(&mut self, user: &User) -> Result<Vec<WebAuthnCredential>, Self::Error> {
+        let mut credentials: Vec<WebAuthnCredential> = self
+            .store
+            .user_webauthn_credentials
+            .values()
+            .filter(|credential| credential.user_id == user.id)
+            .cloned()
+            .collect();
+
+        credentials.sort_by_key(|credential| credential.created_at);
+
+        Ok(credentials)
+    }
+
+    async fn add(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        user: &User,
+        credential_id: Vec<u8>,
+        public_key: Vec<u8>,
+        sign_count: u32,
+        aaguid: [u8; 16],
+    ) -> Result<WebAuthnCredential, Self::Error> {
+        let created_at = clock.now();
+        let id = Ulid::from_datetime_with_source(created_at.into(), rng);
+
+        if self
+            .store
+            .user_webauthn_credentials
+            .values()
+            .any(|credential| credential.credential_id == credential_id)
+        {
+            return Err(MemoryError::UniqueViolation {
+                constraint: "user_webauthn_credentials_credential_id_unique",
+            });
+        }
+
+        let credential = WebAuthnCredential {
+            id,
+            user_id: user.id,
+            credential_id,
+            public_key,
+            sign_count,
+            aaguid,
+            created_at,
+        };
+
+        self.store
+            .user_webauthn_credentials
+            .insert(id, credential.clone());
+
+        Ok(credential)
+    }
+
+    async fn update_sign_count(
+        &mut self,
+        mut credential: WebAuthnCredential,
+        sign_count: u32,
+    ) -> Result<WebAuthnCredential, Self::Error> {
+        let row = self
+            .store
+            .user_webauthn_credentials
+            .get_mut(&credential.id)
+            .ok_or(MemoryError::not_found())?;
+
+        row.sign_count = sign_count;
+        credential.sign_count = sign_count;
+        Ok(credential)
+    }
+
+    async fn delete(&mut self, credential: WebAuthnCredential) -> Result<(), Self::Error> {
+        self.store
+            .user_webauthn_credentials
+            .remove(&credential.id)
+            .ok_or(MemoryError::not_found())?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,47 @@
+-- Copyright 2024 The Matrix.org Foundation C.I.C.
+--
+-- Licensed under the Apache License, Version 2.0 (the "License");
+-- you may not use this file except in compliance with the License.
+-- You may obtain a copy of the License at
+--
+--     http://www.apache.org/licenses/LICENSE-2.0
+--
+-- Unless required by applicable law or agreed to in writing, software
+-- distributed under the License is distributed on an "AS IS" BASIS,
+-- WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+-- See the License for the specific language governing permissions and
+-- limitations under the License.
+
+
+-- Adds a table to store the WebAuthn credentials (security keys, passkeys)
+-- registered by users
+CREATE TABLE "user_webauthn_credentials" (
+  "user_webauthn_credential_id" UUID NOT NULL
+    CONSTRAINT "user_webauthn_credentials_pkey"
+    PRIMARY KEY,
+
+  "user_id" UUID NOT NULL
+    CONSTRAINT "user_webauthn_credentials_user_id_fkey"
+    REFERENCES "users" ("user_id")
+    ON DELETE CASCADE,
+
+  "credential_id" BYTEA NOT NULL
+    CONSTRAINT "user_webauthn_credentials_credential_id_unique"
+    UNIQUE,
+
+  "public_key" BYTEA NOT NULL,
+  "sign_count" BIGINT NOT NULL DEFAULT 0,
+  "aaguid" UUID NOT NULL,
+
+  "created_at" TIMESTAMP WITH TIME ZONE NOT NULL
+);
+
+CREATE INDEX "user_webauthn_credentials_user_id_idx"
+  ON "user_webauthn_credentials" ("user_id");
+
+-- Allow recording authentications made with a WebAuthn credential
+ALTER TABLE "user_session_authentications"
+  ADD COLUMN "user_webauthn_credential_id" UUID
+    CONSTRAINT "user_session_authentications_user_webauthn_credential_id_fkey"
+    REFERENCES "user_webauthn_credentials" ("user_webauthn_credential_id")
+    ON DELETE SET NULL;
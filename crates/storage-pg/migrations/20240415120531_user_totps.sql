@@ -0,0 +1,69 @@
+-- Copyright 2024 The Matrix.org Foundation C.I.C.
+--
+-- Licensed under the Apache License, Version 2.0 (the "License");
+-- you may not use this file except in compliance with the License.
+-- You may obtain a copy of the License at
+--
+--     http://www.apache.org/licenses/LICENSE-2.0
+--
+-- Unless required by applicable law or agreed to in writing, software
+-- distributed under the License is distributed on an "AS IS" BASIS,
+-- WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+-- See the License for the specific language governing permissions and
+-- limitations under the License.
+
+
+-- Adds a table to store the TOTP second factor of users. A user can only
+-- have one TOTP secret enrolled at a time
+CREATE TABLE "user_totps" (
+  "user_totp_id" UUID NOT NULL
+    CONSTRAINT "user_totps_pkey"
+    PRIMARY KEY,
+
+  "user_id" UUID NOT NULL
+    CONSTRAINT "user_totps_user_id_fkey"
+    REFERENCES "users" ("user_id")
+    ON DELETE CASCADE
+    CONSTRAINT "user_totps_user_id_unique"
+    UNIQUE,
+
+  -- The shared secret, encrypted with the site encryption key
+  "encrypted_secret" TEXT NOT NULL,
+
+  -- The last time step which was used, to prevent replaying codes
+  "last_used_counter" BIGINT,
+
+  "created_at" TIMESTAMP WITH TIME ZONE NOT NULL
+);
+
+-- Adds a table to store the single-use recovery codes of users
+CREATE TABLE "user_recovery_codes" (
+  "user_recovery_code_id" UUID NOT NULL
+    CONSTRAINT "user_recovery_codes_pkey"
+    PRIMARY KEY,
+
+  "user_id" UUID NOT NULL
+    CONSTRAINT "user_recovery_codes_user_id_fkey"
+    REFERENCES "users" ("user_id")
+    ON DELETE CASCADE,
+
+  "hashed_code" TEXT NOT NULL,
+
+  "created_at" TIMESTAMP WITH TIME ZONE NOT NULL,
+  "consumed_at" TIMESTAMP WITH TIME ZONE
+);
+
+CREATE INDEX "user_recovery_codes_user_id_idx"
+  ON "user_recovery_codes" ("user_id");
+
+-- Allow recording authentications made with a TOTP code or a recovery code
+ALTER TABLE "user_session_authentications"
+  ADD COLUMN "user_totp_id" UUID
+    CONSTRAINT "user_session_authentications_user_totp_id_fkey"
+    REFERENCES "user_totps" ("user_totp_id")
+    ON DELETE SET NULL,
+
+  ADD COLUMN "user_recovery_code_id" UUID
+    CONSTRAINT "user_session_authentications_user_recovery_code_id_fkey"
+    REFERENCES "user_recovery_codes" ("user_recovery_code_id")
+    ON DELETE SET NULL;
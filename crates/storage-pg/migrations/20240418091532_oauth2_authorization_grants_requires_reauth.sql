@@ -0,0 +1,18 @@
+-- Copyright 2024 The Matrix.org Foundation C.I.C.
+--
+-- Licensed under the Apache License, Version 2.0 (the "License");
+-- you may not use this file except in compliance with the License.
+-- You may obtain a copy of the License at
+--
+--     http://www.apache.org/licenses/LICENSE-2.0
+--
+-- Unless required by applicable law or agreed to in writing, software
+-- distributed under the License is distributed on an "AS IS" BASIS,
+-- WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+-- See the License for the specific language governing permissions and
+-- limitations under the License.
+
+-- Adds a `requires_reauth` column to the `oauth2_authorization_grants` table,
+-- set when the client asked the user to reauthenticate with `prompt=login`
+ALTER TABLE "oauth2_authorization_grants"
+  ADD COLUMN "requires_reauth" BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,37 @@
+-- Copyright 2024 The Matrix.org Foundation C.I.C.
+--
+-- Licensed under the Apache License, Version 2.0 (the "License");
+-- you may not use this file except in compliance with the License.
+-- You may obtain a copy of the License at
+--
+--     http://www.apache.org/licenses/LICENSE-2.0
+--
+-- Unless required by applicable law or agreed to in writing, software
+-- distributed under the License is distributed on an "AS IS" BASIS,
+-- WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+-- See the License for the specific language governing permissions and
+-- limitations under the License.
+
+-- Adds a table to store authorization requests pushed by clients through the
+-- pushed authorization request endpoint (RFC 9126)
+CREATE TABLE "oauth2_pushed_auth_requests" (
+    "oauth2_pushed_auth_request_id" UUID NOT NULL
+        PRIMARY KEY,
+
+    -- The client who pushed the authorization request
+    "oauth2_client_id" UUID NOT NULL
+        REFERENCES "oauth2_clients" ("oauth2_client_id")
+        ON DELETE CASCADE,
+
+    -- The authorization request parameters, as a JSON object of strings
+    "parameters" JSONB NOT NULL,
+
+    -- Timestamp when the request was pushed
+    "created_at" TIMESTAMP WITH TIME ZONE NOT NULL,
+
+    -- Timestamp after which the request_uri can't be used anymore
+    "expires_at" TIMESTAMP WITH TIME ZONE NOT NULL
+);
+
+CREATE INDEX "oauth2_pushed_auth_requests_expires_at_idx"
+    ON "oauth2_pushed_auth_requests" ("expires_at");
@@ -0,0 +1,22 @@
+-- Copyright 2024 The Matrix.org Foundation C.I.C.
+--
+-- Licensed under the Apache License, Version 2.0 (the "License");
+-- you may not use this file except in compliance with the License.
+-- You may obtain a copy of the License at
+--
+--     http://www.apache.org/licenses/LICENSE-2.0
+--
+-- Unless required by applicable law or agreed to in writing, software
+-- distributed under the License is distributed on an "AS IS" BASIS,
+-- WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+-- See the License for the specific language governing permissions and
+-- limitations under the License.
+
+-- Adds the list of response types clients are allowed to use in authorization
+-- requests. Existing clients are limited to the authorization code flow.
+ALTER TABLE "oauth2_clients"
+  ADD COLUMN "response_types" TEXT[] NOT NULL
+    DEFAULT '{"code"}';
+
+ALTER TABLE "oauth2_clients"
+  ALTER COLUMN "response_types" DROP DEFAULT;
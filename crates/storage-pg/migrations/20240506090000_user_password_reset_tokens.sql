@@ -0,0 +1,36 @@
+-- Copyright 2024 The Matrix.org Foundation C.I.C.
+--
+-- Licensed under the Apache License, Version 2.0 (the "License");
+-- you may not use this file except in compliance with the License.
+-- You may obtain a copy of the License at
+--
+--     http://www.apache.org/licenses/LICENSE-2.0
+--
+-- Unless required by applicable law or agreed to in writing, software
+-- distributed under the License is distributed on an "AS IS" BASIS,
+-- WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+-- See the License for the specific language governing permissions and
+-- limitations under the License.
+
+
+-- Adds a table to store the single-use tokens used to reset the password of
+-- users. Only a hash of the secret part of the token is stored.
+CREATE TABLE "user_password_reset_tokens" (
+  "user_password_reset_token_id" UUID NOT NULL
+    CONSTRAINT "user_password_reset_tokens_pkey"
+    PRIMARY KEY,
+
+  "user_id" UUID NOT NULL
+    CONSTRAINT "user_password_reset_tokens_user_id_fkey"
+    REFERENCES "users" ("user_id")
+    ON DELETE CASCADE,
+
+  "hashed_secret" TEXT NOT NULL,
+
+  "created_at" TIMESTAMP WITH TIME ZONE NOT NULL,
+  "expires_at" TIMESTAMP WITH TIME ZONE NOT NULL,
+  "consumed_at" TIMESTAMP WITH TIME ZONE
+);
+
+CREATE INDEX "user_password_reset_tokens_user_id_idx"
+  ON "user_password_reset_tokens" ("user_id");
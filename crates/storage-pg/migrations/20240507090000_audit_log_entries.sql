@@ -0,0 +1,44 @@
+-- Copyright 2024 The Matrix.org Foundation C.I.C.
+--
+-- Licensed under the Apache License, Version 2.0 (the "License");
+-- you may not use this file except in compliance with the License.
+-- You may obtain a copy of the License at
+--
+--     http://www.apache.org/licenses/LICENSE-2.0
+--
+-- Unless required by applicable law or agreed to in writing, software
+-- distributed under the License is distributed on an "AS IS" BASIS,
+-- WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+-- See the License for the specific language governing permissions and
+-- limitations under the License.
+
+
+-- Adds a table to record security-sensitive actions. The entries keep their
+-- references as plain IDs, so that they outlive the users and sessions they
+-- refer to.
+CREATE TABLE "audit_log_entries" (
+  "audit_log_entry_id" UUID NOT NULL
+    CONSTRAINT "audit_log_entries_pkey"
+    PRIMARY KEY,
+
+  "created_at" TIMESTAMP WITH TIME ZONE NOT NULL,
+
+  -- The user who performed the action, if any
+  "actor_user_id" UUID,
+
+  -- The browser or OAuth 2.0 session used to perform the action, if any
+  "actor_session_id" UUID,
+
+  "action" TEXT NOT NULL,
+
+  -- The user affected by the action, if any
+  "target_user_id" UUID,
+
+  "metadata" JSONB NOT NULL DEFAULT '{}'
+);
+
+CREATE INDEX "audit_log_entries_actor_user_id_idx"
+  ON "audit_log_entries" ("actor_user_id");
+
+CREATE INDEX "audit_log_entries_target_user_id_idx"
+  ON "audit_log_entries" ("target_user_id");
@@ -0,0 +1,24 @@
+-- Copyright 2024 The Matrix.org Foundation C.I.C.
+--
+-- Licensed under the Apache License, Version 2.0 (the "License");
+-- you may not use this file except in compliance with the License.
+-- You may obtain a copy of the License at
+--
+--     http://www.apache.org/licenses/LICENSE-2.0
+--
+-- Unless required by applicable law or agreed to in writing, software
+-- distributed under the License is distributed on an "AS IS" BASIS,
+-- WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+-- See the License for the specific language governing permissions and
+-- limitations under the License.
+
+-- Record when the authorization code of a grant expires, so that the lifetime
+-- configured when the code was issued is the one which applies.
+ALTER TABLE "oauth2_authorization_grants"
+  ADD COLUMN "code_expires_at" TIMESTAMP WITH TIME ZONE;
+
+-- Grants which were fulfilled before this migration keep the previous default
+-- lifetime of 60 seconds
+UPDATE "oauth2_authorization_grants"
+  SET "code_expires_at" = "fulfilled_at" + INTERVAL '60 seconds'
+  WHERE "fulfilled_at" IS NOT NULL;
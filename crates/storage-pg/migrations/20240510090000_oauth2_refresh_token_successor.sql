@@ -0,0 +1,19 @@
+-- Copyright 2024 The Matrix.org Foundation C.I.C.
+--
+-- Licensed under the Apache License, Version 2.0 (the "License");
+-- you may not use this file except in compliance with the License.
+-- You may obtain a copy of the License at
+--
+--     http://www.apache.org/licenses/LICENSE-2.0
+--
+-- Unless required by applicable law or agreed to in writing, software
+-- distributed under the License is distributed on an "AS IS" BASIS,
+-- WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+-- See the License for the specific language governing permissions and
+-- limitations under the License.
+
+-- Keep track of which refresh token replaced a consumed one, so that a rotation
+-- chain can be followed when a token is reused
+ALTER TABLE "oauth2_refresh_tokens"
+  ADD COLUMN "next_oauth2_refresh_token_id" UUID
+    REFERENCES "oauth2_refresh_tokens" ("oauth2_refresh_token_id");
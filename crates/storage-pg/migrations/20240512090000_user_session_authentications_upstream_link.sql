@@ -0,0 +1,19 @@
+-- Copyright 2024 The Matrix.org Foundation C.I.C.
+--
+-- Licensed under the Apache License, Version 2.0 (the "License");
+-- you may not use this file except in compliance with the License.
+-- You may obtain a copy of the License at
+--
+--     http://www.apache.org/licenses/LICENSE-2.0
+--
+-- Unless required by applicable law or agreed to in writing, software
+-- distributed under the License is distributed on an "AS IS" BASIS,
+-- WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+-- See the License for the specific language governing permissions and
+-- limitations under the License.
+
+-- Keep track of the upstream link used by an upstream OAuth 2.0 authentication,
+-- so that the provider can be shown in the session history
+ALTER TABLE "user_session_authentications"
+  ADD COLUMN "upstream_oauth_link_id" UUID
+    REFERENCES "upstream_oauth_links" ("upstream_oauth_link_id");
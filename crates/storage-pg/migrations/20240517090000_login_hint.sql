@@ -0,0 +1,22 @@
+-- Copyright 2024 The Matrix.org Foundation C.I.C.
+--
+-- Licensed under the Apache License, Version 2.0 (the "License");
+-- you may not use this file except in compliance with the License.
+-- You may obtain a copy of the License at
+--
+--     http://www.apache.org/licenses/LICENSE-2.0
+--
+-- Unless required by applicable law or agreed to in writing, software
+-- distributed under the License is distributed on an "AS IS" BASIS,
+-- WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+-- See the License for the specific language governing permissions and
+-- limitations under the License.
+
+-- The login_hint the client sent in the authorization request, used to
+-- pre-fill the login form
+ALTER TABLE "oauth2_authorization_grants"
+  ADD COLUMN "login_hint" TEXT;
+
+-- Whether the login_hint should be forwarded to the upstream provider
+ALTER TABLE "upstream_oauth_providers"
+  ADD COLUMN "forward_login_hint" BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,31 @@
+-- Copyright 2024 The Matrix.org Foundation C.I.C.
+--
+-- Licensed under the Apache License, Version 2.0 (the "License");
+-- you may not use this file except in compliance with the License.
+-- You may obtain a copy of the License at
+--
+--     http://www.apache.org/licenses/LICENSE-2.0
+--
+-- Unless required by applicable law or agreed to in writing, software
+-- distributed under the License is distributed on an "AS IS" BASIS,
+-- WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+-- See the License for the specific language governing permissions and
+-- limitations under the License.
+
+-- The resources (RFC 8707) requested in the authorization request, which the
+-- tokens of the session are restricted to. NULL means the tokens are not
+-- audience-restricted
+ALTER TABLE "oauth2_authorization_grants"
+  ADD COLUMN "resource" JSONB;
+
+ALTER TABLE "oauth2_sessions"
+  ADD COLUMN "resource" JSONB;
+
+-- Access tokens can be restricted to a subset of the resources of their
+-- session. A NULL resource means the session resources.
+ALTER TABLE "oauth2_access_tokens"
+  ADD COLUMN "resource" JSONB;
+
+-- The resources a client is allowed to request
+ALTER TABLE "oauth2_clients"
+  ADD COLUMN "allowed_resources" TEXT[] NOT NULL DEFAULT '{}';
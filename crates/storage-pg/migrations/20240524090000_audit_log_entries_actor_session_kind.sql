@@ -0,0 +1,30 @@
+-- Copyright 2024 The Matrix.org Foundation C.I.C.
+--
+-- Licensed under the Apache License, Version 2.0 (the "License");
+-- you may not use this file except in compliance with the License.
+-- You may obtain a copy of the License at
+--
+--     http://www.apache.org/licenses/LICENSE-2.0
+--
+-- Unless required by applicable law or agreed to in writing, software
+-- distributed under the License is distributed on an "AS IS" BASIS,
+-- WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+-- See the License for the specific language governing permissions and
+-- limitations under the License.
+
+-- Records whether the actor session of an audit log entry is a browser session
+-- or an OAuth 2.0 session, as the session ID alone doesn't tell
+ALTER TABLE "audit_log_entries"
+  ADD COLUMN "actor_session_kind" TEXT;
+
+UPDATE "audit_log_entries"
+  SET "actor_session_kind" = 'browser'
+  WHERE "actor_session_id" IN (SELECT "user_session_id" FROM "user_sessions");
+
+UPDATE "audit_log_entries"
+  SET "actor_session_kind" = 'oauth2'
+  WHERE "actor_session_id" IN (SELECT "oauth2_session_id" FROM "oauth2_sessions");
+
+-- Entries are cleaned up by age
+CREATE INDEX "audit_log_entries_created_at_idx"
+  ON "audit_log_entries" ("created_at");
@@ -25,7 +25,7 @@ use sea_query::{
     Alias, ColumnRef, CommonTableExpression, Expr, PgFunc, PostgresQueryBuilder, Query, UnionType,
 };
 use sea_query_binder::SqlxBinder;
-use sqlx::PgConnection;
+use sqlx::{types::Json, PgConnection};
 use ulid::Ulid;
 use uuid::Uuid;
 
@@ -57,6 +57,8 @@ mod priv_ {
 
     use chrono::{DateTime, Utc};
     use sea_query::enum_def;
+    use sqlx::types::Json;
+    use url::Url;
     use uuid::Uuid;
 
     #[derive(sqlx::FromRow)]
@@ -76,6 +78,9 @@ mod priv_ {
         pub(super) user_agent: Option<String>,
         pub(super) last_active_at: Option<DateTime<Utc>>,
         pub(super) last_active_ip: Option<IpAddr>,
+        pub(super) auth_time: Option<DateTime<Utc>>,
+        pub(super) resource: Option<Json<Vec<Url>>>,
+        pub(super) dpop_jkt: Option<String>,
     }
 }
 
@@ -103,6 +108,9 @@ impl TryFrom<AppSessionLookup> for AppSession {
             user_agent,
             last_active_at,
             last_active_ip,
+            auth_time,
+            resource,
+            dpop_jkt,
         } = value;
 
         let user_agent = user_agent.map(UserAgent::parse);
@@ -190,6 +198,9 @@ impl TryFrom<AppSessionLookup> for AppSession {
                     user_agent,
                     last_active_at,
                     last_active_ip,
+                    auth_time,
+                    resource: resource.map(|Json(x)| x).unwrap_or_default(),
+                    dpop_jkt,
                 };
 
                 Ok(AppSession::OAuth2(Box::new(session)))
@@ -268,6 +279,18 @@ impl<'c> AppSessionRepository for PgAppSessionRepository<'c> {
                 Expr::col((OAuth2Sessions::Table, OAuth2Sessions::LastActiveIp)),
                 AppSessionLookupIden::LastActiveIp,
             )
+            .expr_as(
+                Expr::col((OAuth2Sessions::Table, OAuth2Sessions::AuthTime)),
+                AppSessionLookupIden::AuthTime,
+            )
+            .expr_as(
+                Expr::col((OAuth2Sessions::Table, OAuth2Sessions::Resource)),
+                AppSessionLookupIden::Resource,
+            )
+            .expr_as(
+                Expr::col((OAuth2Sessions::Table, OAuth2Sessions::DpopJkt)),
+                AppSessionLookupIden::DpopJkt,
+            )
             .from(OAuth2Sessions::Table)
             .and_where_option(filter.user().map(|user| {
                 Expr::col((OAuth2Sessions::Table, OAuth2Sessions::UserId)).eq(Uuid::from(user.id))
@@ -339,6 +362,9 @@ impl<'c> AppSessionRepository for PgAppSessionRepository<'c> {
                 Expr::col((CompatSessions::Table, CompatSessions::LastActiveIp)),
                 AppSessionLookupIden::LastActiveIp,
             )
+            .expr_as(Expr::cust("NULL"), AppSessionLookupIden::AuthTime)
+            .expr_as(Expr::cust("NULL"), AppSessionLookupIden::Resource)
+            .expr_as(Expr::cust("NULL"), AppSessionLookupIden::DpopJkt)
             .from(CompatSessions::Table)
             .and_where_option(filter.user().map(|user| {
                 Expr::col((CompatSessions::Table, CompatSessions::UserId)).eq(Uuid::from(user.id))
@@ -477,6 +503,7 @@ impl<'c> AppSessionRepository for PgAppSessionRepository<'c> {
 mod tests {
     use chrono::Duration;
     use mas_data_model::Device;
+    use mas_iana::oauth::OAuthAuthorizationEndpointResponseType;
     use mas_storage::{
         app_session::{AppSession, AppSessionFilter},
         clock::MockClock,
@@ -584,6 +611,7 @@ mod tests {
                 vec!["https://example.com/redirect".parse().unwrap()],
                 None,
                 None,
+                vec![OAuthAuthorizationEndpointResponseType::Code],
                 vec![GrantType::AuthorizationCode],
                 Vec::new(), // TODO: contacts are not yet saved
                 // vec!["contact@example.com".to_owned()],
@@ -598,7 +626,9 @@ mod tests {
                 None,
                 None,
                 None,
+                None,
                 Some("https://example.com/login".parse().unwrap()),
+                Vec::new(),
             )
             .await
             .unwrap();
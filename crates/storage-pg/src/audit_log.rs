@@ -0,0 +1,373 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A module containing the PostgreSQL implementation of the audit log
+//! repository
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use mas_data_model::{AuditLogEntry, AuditSessionKind, User};
+use mas_storage::{
+    audit_log::{AuditLogParams, AuditLogRepository},
+    Clock, Page, Pagination,
+};
+use rand::RngCore;
+use sea_query::{Expr, PostgresQueryBuilder, Query};
+use sea_query_binder::SqlxBinder;
+use sqlx::{types::Json, PgConnection};
+use ulid::Ulid;
+use uuid::Uuid;
+
+use crate::{
+    errors::DatabaseInconsistencyError, iden::AuditLogEntries, pagination::QueryBuilderExt,
+    DatabaseError, ExecuteExt,
+};
+
+/// An implementation of [`AuditLogRepository`] for a PostgreSQL connection
+pub struct PgAuditLogRepository<'c> {
+    conn: &'c mut PgConnection,
+}
+
+impl<'c> PgAuditLogRepository<'c> {
+    /// Create a new [`PgAuditLogRepository`] from an active PostgreSQL
+    /// connection
+    pub fn new(conn: &'c mut PgConnection) -> Self {
+        Self { conn }
+    }
+}
+
+mod priv_ {
+    // The enum_def macro generates a public enum, which we don't want, because it
+    // triggers the missing docs warning
+
+    use chrono::{DateTime, Utc};
+    use sea_query::enum_def;
+    use uuid::Uuid;
+
+    #[derive(sqlx::FromRow)]
+    #[enum_def]
+    pub(super) struct AuditLogEntryLookup {
+        pub(super) audit_log_entry_id: Uuid,
+        pub(super) created_at: DateTime<Utc>,
+        pub(super) actor_user_id: Option<Uuid>,
+        pub(super) actor_session_id: Option<Uuid>,
+        pub(super) actor_session_kind: Option<String>,
+        pub(super) action: String,
+        pub(super) target_user_id: Option<Uuid>,
+        pub(super) metadata: serde_json::Value,
+    }
+}
+
+use priv_::{AuditLogEntryLookup, AuditLogEntryLookupIden};
+
+impl TryFrom<AuditLogEntryLookup> for AuditLogEntry {
+    type Error = DatabaseInconsistencyError;
+
+    fn try_from(value: AuditLogEntryLookup) -> Result<Self, Self::Error> {
+        let id = Ulid::from(value.audit_log_entry_id);
+        let action = value.action.parse().map_err(|e| {
+            DatabaseInconsistencyError::on("audit_log_entries")
+                .column("action")
+                .row(id)
+                .source(e)
+        })?;
+
+        let actor_session_kind = value
+            .actor_session_kind
+            .map(|kind| kind.parse())
+            .transpose()
+            .map_err(|e| {
+                DatabaseInconsistencyError::on("audit_log_entries")
+                    .column("actor_session_kind")
+                    .row(id)
+                    .source(e)
+            })?;
+
+        Ok(AuditLogEntry {
+            id,
+            created_at: value.created_at,
+            actor_user_id: value.actor_user_id.map(Ulid::from),
+            actor_session_id: value.actor_session_id.map(Ulid::from),
+            actor_session_kind,
+            action,
+            target_user_id: value.target_user_id.map(Ulid::from),
+            metadata: value.metadata,
+        })
+    }
+}
+
+#[async_trait]
+impl<'c> AuditLogRepository for PgAuditLogRepository<'c> {
+    type Error = DatabaseError;
+
+    #[tracing::instrument(
+        name = "db.audit_log.record",
+        skip_all,
+        fields(
+            db.statement,
+            audit_log_entry.id,
+            audit_log_entry.action = %params.action,
+        ),
+        err,
+    )]
+    async fn record(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        params: AuditLogParams,
+    ) -> Result<AuditLogEntry, Self::Error> {
+        let created_at = clock.now();
+        let id = Ulid::from_datetime_with_source(created_at.into(), rng);
+        tracing::Span::current().record("audit_log_entry.id", tracing::field::display(id));
+
+        sqlx::query!(
+            r#"
+                INSERT INTO audit_log_entries
+                    ( audit_log_entry_id
+                    , created_at
+                    , actor_user_id
+                    , actor_session_id
+                    , actor_session_kind
+                    , action
+                    , target_user_id
+                    , metadata
+                    )
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            "#,
+            Uuid::from(id),
+            created_at,
+            params.actor_user_id.map(Uuid::from),
+            params.actor_session_id.map(Uuid::from),
+            params.actor_session_kind.map(AuditSessionKind::as_str),
+            params.action.as_str(),
+            params.target_user_id.map(Uuid::from),
+            Json(&params.metadata) as _,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        Ok(AuditLogEntry {
+            id,
+            created_at,
+            actor_user_id: params.actor_user_id,
+            actor_session_id: params.actor_session_id,
+            actor_session_kind: params.actor_session_kind,
+            action: params.action,
+            target_user_id: params.target_user_id,
+            metadata: params.metadata,
+        })
+    }
+
+    #[tracing::instrument(
+        name = "db.audit_log.list_for_user",
+        skip_all,
+        fields(
+            db.statement,
+            %user.id,
+        ),
+        err,
+    )]
+    async fn list_for_user(
+        &mut self,
+        user: &User,
+        pagination: Pagination,
+    ) -> Result<Page<AuditLogEntry>, Self::Error> {
+        let (sql, arguments) = Query::select()
+            .expr_as(
+                Expr::col((AuditLogEntries::Table, AuditLogEntries::AuditLogEntryId)),
+                AuditLogEntryLookupIden::AuditLogEntryId,
+            )
+            .expr_as(
+                Expr::col((AuditLogEntries::Table, AuditLogEntries::CreatedAt)),
+                AuditLogEntryLookupIden::CreatedAt,
+            )
+            .expr_as(
+                Expr::col((AuditLogEntries::Table, AuditLogEntries::ActorUserId)),
+                AuditLogEntryLookupIden::ActorUserId,
+            )
+            .expr_as(
+                Expr::col((AuditLogEntries::Table, AuditLogEntries::ActorSessionId)),
+                AuditLogEntryLookupIden::ActorSessionId,
+            )
+            .expr_as(
+                Expr::col((AuditLogEntries::Table, AuditLogEntries::ActorSessionKind)),
+                AuditLogEntryLookupIden::ActorSessionKind,
+            )
+            .expr_as(
+                Expr::col((AuditLogEntries::Table, AuditLogEntries::Action)),
+                AuditLogEntryLookupIden::Action,
+            )
+            .expr_as(
+                Expr::col((AuditLogEntries::Table, AuditLogEntries::TargetUserId)),
+                AuditLogEntryLookupIden::TargetUserId,
+            )
+            .expr_as(
+                Expr::col((AuditLogEntries::Table, AuditLogEntries::Metadata)),
+                AuditLogEntryLookupIden::Metadata,
+            )
+            .from(AuditLogEntries::Table)
+            .cond_where(
+                Expr::col((AuditLogEntries::Table, AuditLogEntries::ActorUserId))
+                    .eq(Uuid::from(user.id))
+                    .or(
+                        Expr::col((AuditLogEntries::Table, AuditLogEntries::TargetUserId))
+                            .eq(Uuid::from(user.id)),
+                    ),
+            )
+            .generate_pagination(
+                (AuditLogEntries::Table, AuditLogEntries::AuditLogEntryId),
+                pagination,
+            )
+            .build_sqlx(PostgresQueryBuilder);
+
+        let edges: Vec<AuditLogEntryLookup> = sqlx::query_as_with(&sql, arguments)
+            .traced()
+            .fetch_all(&mut *self.conn)
+            .await?;
+
+        let page = pagination.process(edges).try_map(TryFrom::try_from)?;
+
+        Ok(page)
+    }
+
+    #[tracing::instrument(
+        name = "db.audit_log.cleanup",
+        skip_all,
+        fields(
+            db.statement,
+            %before,
+        ),
+        err,
+    )]
+    async fn cleanup(&mut self, before: DateTime<Utc>) -> Result<usize, Self::Error> {
+        let res = sqlx::query!(
+            r#"
+                DELETE FROM audit_log_entries
+                WHERE created_at < $1
+            "#,
+            before,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        Ok(res.rows_affected().try_into().unwrap_or(usize::MAX))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mas_data_model::{AuditAction, AuditSessionKind};
+    use mas_storage::{
+        audit_log::AuditLogParams, clock::MockClock, Clock, Pagination, RepositoryAccess,
+    };
+    use rand::SeedableRng;
+    use rand_chacha::ChaChaRng;
+    use sqlx::PgPool;
+
+    use crate::PgRepository;
+
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn test_audit_log_repo(pool: PgPool) {
+        let mut rng = ChaChaRng::seed_from_u64(42);
+        let clock = MockClock::default();
+        let mut repo = PgRepository::from_pool(&pool).await.unwrap();
+
+        let alice = repo
+            .user()
+            .add(&mut rng, &clock, "alice".to_owned())
+            .await
+            .unwrap();
+        let bob = repo
+            .user()
+            .add(&mut rng, &clock, "bob".to_owned())
+            .await
+            .unwrap();
+
+        let session = repo
+            .browser_session()
+            .add(&mut rng, &clock, &alice, None, None, None)
+            .await
+            .unwrap();
+
+        let pagination = Pagination::first(10);
+        let list = repo
+            .audit_log()
+            .list_for_user(&alice, pagination)
+            .await
+            .unwrap();
+        assert!(list.edges.is_empty());
+
+        // Alice logs in
+        let login = repo
+            .audit_log()
+            .record(
+                &mut rng,
+                &clock,
+                AuditLogParams::new(AuditAction::UserLoggedIn)
+                    .with_actor(&alice)
+                    .with_actor_browser_session(&session)
+                    .with_target(&alice),
+            )
+            .await
+            .unwrap();
+        assert_eq!(login.action, AuditAction::UserLoggedIn);
+        assert_eq!(login.actor_user_id, Some(alice.id));
+        assert_eq!(login.actor_session_id, Some(session.id));
+        assert_eq!(login.actor_session_kind, Some(AuditSessionKind::Browser));
+
+        clock.advance(chrono::Duration::minutes(1));
+
+        // Alice locks Bob
+        let lock = repo
+            .audit_log()
+            .record(
+                &mut rng,
+                &clock,
+                AuditLogParams::new(AuditAction::UserLocked)
+                    .with_actor(&alice)
+                    .with_target(&bob)
+                    .with_metadata(serde_json::json!({ "reason": "spam" })),
+            )
+            .await
+            .unwrap();
+
+        let list = repo
+            .audit_log()
+            .list_for_user(&alice, pagination)
+            .await
+            .unwrap();
+        assert_eq!(list.edges, vec![login, lock.clone()]);
+
+        // Bob only sees the lock
+        let list = repo
+            .audit_log()
+            .list_for_user(&bob, pagination)
+            .await
+            .unwrap();
+        assert_eq!(list.edges, vec![lock.clone()]);
+        assert_eq!(list.edges[0].metadata["reason"], "spam");
+
+        // Cleaning up removes the entries recorded before the given time
+        let count = repo.audit_log().cleanup(clock.now()).await.unwrap();
+        assert_eq!(count, 1);
+        let list = repo
+            .audit_log()
+            .list_for_user(&alice, pagination)
+            .await
+            .unwrap();
+        assert_eq!(list.edges, vec![lock]);
+    }
+}
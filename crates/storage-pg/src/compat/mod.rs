@@ -288,6 +288,34 @@ mod tests {
                 .unwrap(),
             1
         );
+
+        // Add a session with Synapse admin privileges
+        let device = Device::generate(&mut rng);
+        let admin_session = repo
+            .compat_session()
+            .add(&mut rng, &clock, &user, device.clone(), None, true)
+            .await
+            .unwrap();
+        assert!(admin_session.is_synapse_admin);
+
+        let admin = all.with_synapse_admin(true);
+        let non_admin = all.with_synapse_admin(false);
+        assert_eq!(repo.compat_session().count(all).await.unwrap(), 3);
+        assert_eq!(repo.compat_session().count(admin).await.unwrap(), 1);
+        assert_eq!(repo.compat_session().count(non_admin).await.unwrap(), 2);
+
+        let list = repo.compat_session().list(admin, pagination).await.unwrap();
+        assert_eq!(list.edges.len(), 1);
+        assert_eq!(list.edges[0].0.id, admin_session.id);
+
+        // The device filter also applies when counting
+        assert_eq!(
+            repo.compat_session()
+                .count(all.for_device(&device))
+                .await
+                .unwrap(),
+            1
+        );
     }
 
     #[sqlx::test(migrator = "crate::MIGRATOR")]
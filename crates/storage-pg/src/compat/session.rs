@@ -446,6 +446,10 @@ impl<'c> CompatSessionRepository for PgCompatSessionRepository<'c> {
             .and_where_option(filter.device().map(|device| {
                 Expr::col((CompatSessions::Table, CompatSessions::DeviceId)).eq(device.as_str())
             }))
+            .and_where_option(filter.is_synapse_admin().map(|is_synapse_admin| {
+                Expr::col((CompatSessions::Table, CompatSessions::IsSynapseAdmin))
+                    .eq(is_synapse_admin)
+            }))
             .generate_pagination(
                 (CompatSessions::Table, CompatSessions::CompatSessionId),
                 pagination,
@@ -504,6 +508,13 @@ impl<'c> CompatSessionRepository for PgCompatSessionRepository<'c> {
                     exists.not()
                 }
             }))
+            .and_where_option(filter.device().map(|device| {
+                Expr::col((CompatSessions::Table, CompatSessions::DeviceId)).eq(device.as_str())
+            }))
+            .and_where_option(filter.is_synapse_admin().map(|is_synapse_admin| {
+                Expr::col((CompatSessions::Table, CompatSessions::IsSynapseAdmin))
+                    .eq(is_synapse_admin)
+            }))
             .build_sqlx(PostgresQueryBuilder);
 
         let count: i64 = sqlx::query_scalar_with(&sql, arguments)
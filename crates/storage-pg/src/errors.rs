@@ -49,6 +49,14 @@ pub enum DatabaseError {
         /// How many rows were actually affected
         actual: u64,
     },
+
+    /// An error which happens when trying to act on behalf of a deactivated
+    /// user
+    #[error("User {user_id} is deactivated")]
+    UserDeactivated {
+        /// The ID of the deactivated user
+        user_id: Ulid,
+    },
 }
 
 impl DatabaseError {
@@ -21,11 +21,27 @@ pub enum UserSessions {
     UserId,
     CreatedAt,
     FinishedAt,
+    ExpiresAt,
     UserAgent,
+    IpAddress,
     LastActiveAt,
     LastActiveIp,
 }
 
+#[derive(sea_query::Iden)]
+pub enum UserSessionAuthentications {
+    Table,
+    UserSessionAuthenticationId,
+    UserSessionId,
+    CreatedAt,
+    UserPasswordId,
+    UpstreamOauthAuthorizationSessionId,
+    UpstreamOauthLinkId,
+    UserWebauthnCredentialId,
+    UserTotpId,
+    UserRecoveryCodeId,
+}
+
 #[derive(sea_query::Iden)]
 pub enum Users {
     Table,
@@ -34,7 +50,9 @@ pub enum Users {
     PrimaryUserEmailId,
     CreatedAt,
     LockedAt,
+    DeactivatedAt,
     CanRequestAdmin,
+    IsAdmin,
 }
 
 #[derive(sea_query::Iden)]
@@ -90,6 +108,9 @@ pub enum OAuth2Sessions {
     UserAgent,
     LastActiveAt,
     LastActiveIp,
+    AuthTime,
+    Resource,
+    DpopJkt,
 }
 
 #[derive(sea_query::Iden)]
@@ -112,6 +133,7 @@ pub enum UpstreamOAuthProviders {
     DiscoveryMode,
     PkceMode,
     AdditionalParameters,
+    ForwardLoginHint,
     JwksUriOverride,
     TokenEndpointOverride,
     AuthorizationEndpointOverride,
@@ -129,3 +151,16 @@ pub enum UpstreamOAuthLinks {
     Subject,
     CreatedAt,
 }
+
+#[derive(sea_query::Iden)]
+pub enum AuditLogEntries {
+    Table,
+    AuditLogEntryId,
+    CreatedAt,
+    ActorUserId,
+    ActorSessionId,
+    ActorSessionKind,
+    Action,
+    TargetUserId,
+    Metadata,
+}
@@ -170,6 +170,7 @@
 use sqlx::migrate::Migrator;
 
 pub mod app_session;
+pub mod audit_log;
 pub mod compat;
 pub mod job;
 pub mod oauth2;
@@ -12,16 +12,20 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::net::IpAddr;
+
 use async_trait::async_trait;
 use chrono::{DateTime, Duration, Utc};
-use mas_data_model::{AccessToken, AccessTokenState, Session};
+use mas_data_model::{AccessToken, AccessTokenState, Session, SessionState, User, UserAgent};
 use mas_storage::{oauth2::OAuth2AccessTokenRepository, Clock};
+use oauth2_types::scope::{Scope, ScopeToken};
 use rand::RngCore;
-use sqlx::PgConnection;
+use sqlx::{types::Json, PgConnection};
 use ulid::Ulid;
+use url::Url;
 use uuid::Uuid;
 
-use crate::{tracing::ExecuteExt, DatabaseError};
+use crate::{tracing::ExecuteExt, DatabaseError, DatabaseInconsistencyError};
 
 /// An implementation of [`OAuth2AccessTokenRepository`] for a PostgreSQL
 /// connection
@@ -44,23 +48,176 @@ struct OAuth2AccessTokenLookup {
     created_at: DateTime<Utc>,
     expires_at: Option<DateTime<Utc>>,
     revoked_at: Option<DateTime<Utc>>,
+    dpop_jkt: Option<String>,
+    scope: Option<String>,
+    resource: Option<Json<Vec<Url>>>,
 }
 
-impl From<OAuth2AccessTokenLookup> for AccessToken {
-    fn from(value: OAuth2AccessTokenLookup) -> Self {
+impl TryFrom<OAuth2AccessTokenLookup> for AccessToken {
+    type Error = DatabaseInconsistencyError;
+
+    fn try_from(value: OAuth2AccessTokenLookup) -> Result<Self, Self::Error> {
+        let id = Ulid::from(value.oauth2_access_token_id);
         let state = match value.revoked_at {
             None => AccessTokenState::Valid,
             Some(revoked_at) => AccessTokenState::Revoked { revoked_at },
         };
 
-        Self {
-            id: value.oauth2_access_token_id.into(),
+        let scope = value
+            .scope
+            .map(|scope| scope.parse::<Scope>())
+            .transpose()
+            .map_err(|e| {
+                DatabaseInconsistencyError::on("oauth2_access_tokens")
+                    .column("scope")
+                    .row(id)
+                    .source(e)
+            })?;
+
+        Ok(Self {
+            id,
             state,
             session_id: value.oauth2_session_id.into(),
             access_token: value.access_token,
             created_at: value.created_at,
             expires_at: value.expires_at,
-        }
+            dpop_jkt: value.dpop_jkt,
+            scope,
+            resource: value.resource.map(|Json(x)| x),
+        })
+    }
+}
+
+struct OAuth2AccessTokenIntrospectionLookup {
+    oauth2_access_token_id: Uuid,
+    access_token: String,
+    access_token_created_at: DateTime<Utc>,
+    access_token_expires_at: Option<DateTime<Utc>>,
+    access_token_revoked_at: Option<DateTime<Utc>>,
+    access_token_dpop_jkt: Option<String>,
+    access_token_scope: Option<String>,
+    access_token_resource: Option<Json<Vec<Url>>>,
+    oauth2_session_id: Uuid,
+    oauth2_client_id: Uuid,
+    user_session_id: Option<Uuid>,
+    scope_list: Vec<String>,
+    session_created_at: DateTime<Utc>,
+    session_finished_at: Option<DateTime<Utc>>,
+    session_user_agent: Option<String>,
+    session_last_active_at: Option<DateTime<Utc>>,
+    session_last_active_ip: Option<IpAddr>,
+    session_auth_time: Option<DateTime<Utc>>,
+    session_resource: Option<Json<Vec<Url>>>,
+    session_dpop_jkt: Option<String>,
+    user_id: Option<Uuid>,
+    user_username: Option<String>,
+    user_primary_user_email_id: Option<Uuid>,
+    user_created_at: Option<DateTime<Utc>>,
+    user_locked_at: Option<DateTime<Utc>>,
+    user_deactivated_at: Option<DateTime<Utc>>,
+    user_can_request_admin: Option<bool>,
+    user_is_admin: Option<bool>,
+}
+
+impl TryFrom<OAuth2AccessTokenIntrospectionLookup> for (AccessToken, Session, Option<User>) {
+    type Error = DatabaseInconsistencyError;
+
+    fn try_from(value: OAuth2AccessTokenIntrospectionLookup) -> Result<Self, Self::Error> {
+        let state = match value.access_token_revoked_at {
+            None => AccessTokenState::Valid,
+            Some(revoked_at) => AccessTokenState::Revoked { revoked_at },
+        };
+
+        let access_token_id = Ulid::from(value.oauth2_access_token_id);
+        let access_token_scope = value
+            .access_token_scope
+            .map(|scope| scope.parse::<Scope>())
+            .transpose()
+            .map_err(|e| {
+                DatabaseInconsistencyError::on("oauth2_access_tokens")
+                    .column("scope")
+                    .row(access_token_id)
+                    .source(e)
+            })?;
+
+        let access_token = AccessToken {
+            id: access_token_id,
+            state,
+            session_id: value.oauth2_session_id.into(),
+            access_token: value.access_token,
+            created_at: value.access_token_created_at,
+            expires_at: value.access_token_expires_at,
+            dpop_jkt: value.access_token_dpop_jkt,
+            scope: access_token_scope,
+            resource: value.access_token_resource.map(|Json(x)| x),
+        };
+
+        let session_id = Ulid::from(value.oauth2_session_id);
+        let scope: Result<Scope, _> = value
+            .scope_list
+            .iter()
+            .map(|s| s.parse::<ScopeToken>())
+            .collect();
+        let scope = scope.map_err(|e| {
+            DatabaseInconsistencyError::on("oauth2_sessions")
+                .column("scope")
+                .row(session_id)
+                .source(e)
+        })?;
+
+        let state = match value.session_finished_at {
+            None => SessionState::Valid,
+            Some(finished_at) => SessionState::Finished { finished_at },
+        };
+
+        let session = Session {
+            id: session_id,
+            state,
+            created_at: value.session_created_at,
+            client_id: value.oauth2_client_id.into(),
+            user_id: value.user_id.map(Ulid::from),
+            user_session_id: value.user_session_id.map(Ulid::from),
+            scope,
+            user_agent: value.session_user_agent.map(UserAgent::parse),
+            last_active_at: value.session_last_active_at,
+            last_active_ip: value.session_last_active_ip,
+            auth_time: value.session_auth_time,
+            resource: value.session_resource.map(|Json(x)| x).unwrap_or_default(),
+            dpop_jkt: value.session_dpop_jkt,
+        };
+
+        let user = match (
+            value.user_id,
+            value.user_username,
+            value.user_created_at,
+            value.user_can_request_admin,
+            value.user_is_admin,
+        ) {
+            (None, None, None, None, None) => None,
+            (
+                Some(id),
+                Some(username),
+                Some(created_at),
+                Some(can_request_admin),
+                Some(is_admin),
+            ) => {
+                let id = Ulid::from(id);
+                Some(User {
+                    id,
+                    username,
+                    sub: id.to_string(),
+                    primary_user_email_id: value.user_primary_user_email_id.map(Ulid::from),
+                    created_at,
+                    locked_at: value.user_locked_at,
+                    deactivated_at: value.user_deactivated_at,
+                    can_request_admin,
+                    is_admin,
+                })
+            }
+            _ => return Err(DatabaseInconsistencyError::on("users").row(session_id)),
+        };
+
+        Ok((access_token, session, user))
     }
 }
 
@@ -78,6 +235,9 @@ impl<'c> OAuth2AccessTokenRepository for PgOAuth2AccessTokenRepository<'c> {
                      , expires_at
                      , revoked_at
                      , oauth2_session_id
+                     , dpop_jkt
+                     , scope
+                     , resource as "resource: Json<Vec<Url>>"
 
                 FROM oauth2_access_tokens
 
@@ -90,7 +250,7 @@ impl<'c> OAuth2AccessTokenRepository for PgOAuth2AccessTokenRepository<'c> {
 
         let Some(res) = res else { return Ok(None) };
 
-        Ok(Some(res.into()))
+        Ok(Some(res.try_into()?))
     }
 
     #[tracing::instrument(
@@ -114,6 +274,9 @@ impl<'c> OAuth2AccessTokenRepository for PgOAuth2AccessTokenRepository<'c> {
                      , expires_at
                      , revoked_at
                      , oauth2_session_id
+                     , dpop_jkt
+                     , scope
+                     , resource as "resource: Json<Vec<Url>>"
 
                 FROM oauth2_access_tokens
 
@@ -126,7 +289,70 @@ impl<'c> OAuth2AccessTokenRepository for PgOAuth2AccessTokenRepository<'c> {
 
         let Some(res) = res else { return Ok(None) };
 
-        Ok(Some(res.into()))
+        Ok(Some(res.try_into()?))
+    }
+
+    #[tracing::instrument(
+        name = "db.oauth2_access_token.lookup_for_introspection",
+        skip_all,
+        fields(
+            db.statement,
+        ),
+        err,
+    )]
+    async fn lookup_for_introspection(
+        &mut self,
+        access_token: &str,
+    ) -> Result<Option<(AccessToken, Session, Option<User>)>, Self::Error> {
+        let res = sqlx::query_as!(
+            OAuth2AccessTokenIntrospectionLookup,
+            r#"
+                SELECT t.oauth2_access_token_id
+                     , t.access_token
+                     , t.created_at AS access_token_created_at
+                     , t.expires_at AS access_token_expires_at
+                     , t.revoked_at AS access_token_revoked_at
+                     , t.dpop_jkt AS access_token_dpop_jkt
+                     , t.scope AS access_token_scope
+                     , t.resource AS "access_token_resource: Json<Vec<Url>>"
+                     , s.oauth2_session_id
+                     , s.oauth2_client_id
+                     , s.user_session_id
+                     , s.scope_list
+                     , s.created_at AS session_created_at
+                     , s.finished_at AS session_finished_at
+                     , s.user_agent AS session_user_agent
+                     , s.last_active_at AS session_last_active_at
+                     , s.last_active_ip AS "session_last_active_ip: IpAddr"
+                     , s.auth_time AS session_auth_time
+                     , s.resource AS "session_resource: Json<Vec<Url>>"
+                     , s.dpop_jkt AS session_dpop_jkt
+                     , u.user_id AS "user_id?"
+                     , u.username AS "user_username?"
+                     , u.primary_user_email_id AS user_primary_user_email_id
+                     , u.created_at AS "user_created_at?"
+                     , u.locked_at AS user_locked_at
+                     , u.deactivated_at AS user_deactivated_at
+                     , u.can_request_admin AS "user_can_request_admin?"
+                     , u.is_admin AS "user_is_admin?"
+
+                FROM oauth2_access_tokens t
+                INNER JOIN oauth2_sessions s
+                  USING (oauth2_session_id)
+                LEFT JOIN users u
+                  ON u.user_id = s.user_id
+
+                WHERE t.access_token = $1
+            "#,
+            access_token,
+        )
+        .traced()
+        .fetch_optional(&mut *self.conn)
+        .await?;
+
+        let Some(res) = res else { return Ok(None) };
+
+        Ok(Some(res.try_into()?))
     }
 
     #[tracing::instrument(
@@ -178,9 +404,113 @@ impl<'c> OAuth2AccessTokenRepository for PgOAuth2AccessTokenRepository<'c> {
             session_id: session.id,
             created_at,
             expires_at,
+            dpop_jkt: None,
+            scope: None,
+            resource: None,
         })
     }
 
+    #[tracing::instrument(
+        name = "db.oauth2_access_token.bind_dpop_key",
+        skip_all,
+        fields(
+            db.statement,
+            %access_token.id,
+            access_token.dpop_jkt = %jkt,
+        ),
+        err,
+    )]
+    async fn bind_dpop_key(
+        &mut self,
+        mut access_token: AccessToken,
+        jkt: String,
+    ) -> Result<AccessToken, Self::Error> {
+        let res = sqlx::query!(
+            r#"
+                UPDATE oauth2_access_tokens
+                SET dpop_jkt = $2
+                WHERE oauth2_access_token_id = $1
+            "#,
+            Uuid::from(access_token.id),
+            &jkt,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        access_token.dpop_jkt = Some(jkt);
+        Ok(access_token)
+    }
+
+    #[tracing::instrument(
+        name = "db.oauth2_access_token.set_scope",
+        skip_all,
+        fields(
+            db.statement,
+            %access_token.id,
+            access_token.scope = %scope,
+        ),
+        err,
+    )]
+    async fn set_scope(
+        &mut self,
+        mut access_token: AccessToken,
+        scope: Scope,
+    ) -> Result<AccessToken, Self::Error> {
+        let res = sqlx::query!(
+            r#"
+                UPDATE oauth2_access_tokens
+                SET scope = $2
+                WHERE oauth2_access_token_id = $1
+            "#,
+            Uuid::from(access_token.id),
+            scope.to_string(),
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        access_token.scope = Some(scope);
+        Ok(access_token)
+    }
+
+    #[tracing::instrument(
+        name = "db.oauth2_access_token.set_resource",
+        skip_all,
+        fields(
+            db.statement,
+            %access_token.id,
+        ),
+        err,
+    )]
+    async fn set_resource(
+        &mut self,
+        mut access_token: AccessToken,
+        resource: Vec<Url>,
+    ) -> Result<AccessToken, Self::Error> {
+        let res = sqlx::query!(
+            r#"
+                UPDATE oauth2_access_tokens
+                SET resource = $2
+                WHERE oauth2_access_token_id = $1
+            "#,
+            Uuid::from(access_token.id),
+            Json(&resource) as _,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        access_token.resource = Some(resource);
+        Ok(access_token)
+    }
+
     async fn revoke(
         &mut self,
         clock: &dyn Clock,
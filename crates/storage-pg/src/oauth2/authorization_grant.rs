@@ -15,7 +15,7 @@
 use std::num::NonZeroU32;
 
 use async_trait::async_trait;
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Duration, Utc};
 use mas_data_model::{
     AuthorizationCode, AuthorizationGrant, AuthorizationGrantStage, Client, Pkce, Session,
 };
@@ -23,7 +23,7 @@ use mas_iana::oauth::PkceCodeChallengeMethod;
 use mas_storage::{oauth2::OAuth2AuthorizationGrantRepository, Clock};
 use oauth2_types::{requests::ResponseMode, scope::Scope};
 use rand::RngCore;
-use sqlx::PgConnection;
+use sqlx::{types::Json, PgConnection};
 use ulid::Ulid;
 use url::Url;
 use uuid::Uuid;
@@ -51,9 +51,12 @@ struct GrantLookup {
     cancelled_at: Option<DateTime<Utc>>,
     fulfilled_at: Option<DateTime<Utc>>,
     exchanged_at: Option<DateTime<Utc>>,
+    code_expires_at: Option<DateTime<Utc>>,
     scope: String,
     state: Option<String>,
     nonce: Option<String>,
+    login_hint: Option<String>,
+    resource: Option<Json<Vec<Url>>>,
     redirect_uri: String,
     response_mode: String,
     max_age: Option<i32>,
@@ -63,6 +66,7 @@ struct GrantLookup {
     code_challenge: Option<String>,
     code_challenge_method: Option<String>,
     requires_consent: bool,
+    requires_reauth: bool,
     oauth2_client_id: Uuid,
     oauth2_session_id: Option<Uuid>,
 }
@@ -85,22 +89,29 @@ impl TryFrom<GrantLookup> for AuthorizationGrant {
             value.exchanged_at,
             value.cancelled_at,
             value.oauth2_session_id,
+            value.code_expires_at,
         ) {
-            (None, None, None, None) => AuthorizationGrantStage::Pending,
-            (Some(fulfilled_at), None, None, Some(session_id)) => {
+            (None, None, None, None, None) => AuthorizationGrantStage::Pending,
+            (Some(fulfilled_at), None, None, Some(session_id), Some(code_expires_at)) => {
                 AuthorizationGrantStage::Fulfilled {
                     session_id: session_id.into(),
                     fulfilled_at,
+                    code_expires_at,
                 }
             }
-            (Some(fulfilled_at), Some(exchanged_at), None, Some(session_id)) => {
-                AuthorizationGrantStage::Exchanged {
-                    session_id: session_id.into(),
-                    fulfilled_at,
-                    exchanged_at,
-                }
-            }
-            (None, None, Some(cancelled_at), None) => {
+            (
+                Some(fulfilled_at),
+                Some(exchanged_at),
+                None,
+                Some(session_id),
+                Some(code_expires_at),
+            ) => AuthorizationGrantStage::Exchanged {
+                session_id: session_id.into(),
+                fulfilled_at,
+                code_expires_at,
+                exchanged_at,
+            },
+            (None, None, Some(cancelled_at), None, None) => {
                 AuthorizationGrantStage::Cancelled { cancelled_at }
             }
             _ => {
@@ -187,12 +198,15 @@ impl TryFrom<GrantLookup> for AuthorizationGrant {
             scope,
             state: value.state,
             nonce: value.nonce,
+            login_hint: value.login_hint,
+            resource: value.resource.map(|Json(x)| x).unwrap_or_default(),
             max_age,
             response_mode,
             redirect_uri,
             created_at: value.created_at,
             response_type_id_token: value.response_type_id_token,
             requires_consent: value.requires_consent,
+            requires_reauth: value.requires_reauth,
         })
     }
 }
@@ -226,6 +240,9 @@ impl<'c> OAuth2AuthorizationGrantRepository for PgOAuth2AuthorizationGrantReposi
         response_mode: ResponseMode,
         response_type_id_token: bool,
         requires_consent: bool,
+        requires_reauth: bool,
+        login_hint: Option<String>,
+        resource: Vec<Url>,
     ) -> Result<AuthorizationGrant, Self::Error> {
         let code_challenge = code
             .as_ref()
@@ -260,10 +277,13 @@ impl<'c> OAuth2AuthorizationGrantRepository for PgOAuth2AuthorizationGrantReposi
                      response_type_id_token,
                      authorization_code,
                      requires_consent,
+                     requires_reauth,
+                     login_hint,
+                     resource,
                      created_at
                 )
                 VALUES
-                    ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15)
+                    ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18)
             "#,
             Uuid::from(id),
             Uuid::from(client.id),
@@ -279,6 +299,9 @@ impl<'c> OAuth2AuthorizationGrantRepository for PgOAuth2AuthorizationGrantReposi
             response_type_id_token,
             code_str,
             requires_consent,
+            requires_reauth,
+            login_hint,
+            Json(&resource) as _,
             created_at,
         )
         .traced()
@@ -294,11 +317,14 @@ impl<'c> OAuth2AuthorizationGrantRepository for PgOAuth2AuthorizationGrantReposi
             scope,
             state,
             nonce,
+            login_hint,
+            resource,
             max_age,
             response_mode,
             created_at,
             response_type_id_token,
             requires_consent,
+            requires_reauth,
         })
     }
 
@@ -320,11 +346,14 @@ impl<'c> OAuth2AuthorizationGrantRepository for PgOAuth2AuthorizationGrantReposi
                      , cancelled_at
                      , fulfilled_at
                      , exchanged_at
+                     , code_expires_at
                      , scope
                      , state
                      , redirect_uri
                      , response_mode
                      , nonce
+                     , login_hint
+                     , resource as "resource: Json<Vec<Url>>"
                      , max_age
                      , oauth2_client_id
                      , authorization_code
@@ -333,6 +362,7 @@ impl<'c> OAuth2AuthorizationGrantRepository for PgOAuth2AuthorizationGrantReposi
                      , code_challenge
                      , code_challenge_method
                      , requires_consent
+                     , requires_reauth
                      , oauth2_session_id
                 FROM
                     oauth2_authorization_grants
@@ -370,11 +400,14 @@ impl<'c> OAuth2AuthorizationGrantRepository for PgOAuth2AuthorizationGrantReposi
                      , cancelled_at
                      , fulfilled_at
                      , exchanged_at
+                     , code_expires_at
                      , scope
                      , state
                      , redirect_uri
                      , response_mode
                      , nonce
+                     , login_hint
+                     , resource as "resource: Json<Vec<Url>>"
                      , max_age
                      , oauth2_client_id
                      , authorization_code
@@ -383,6 +416,7 @@ impl<'c> OAuth2AuthorizationGrantRepository for PgOAuth2AuthorizationGrantReposi
                      , code_challenge
                      , code_challenge_method
                      , requires_consent
+                     , requires_reauth
                      , oauth2_session_id
                 FROM
                     oauth2_authorization_grants
@@ -416,18 +450,22 @@ impl<'c> OAuth2AuthorizationGrantRepository for PgOAuth2AuthorizationGrantReposi
         clock: &dyn Clock,
         session: &Session,
         grant: AuthorizationGrant,
+        code_expires_after: Duration,
     ) -> Result<AuthorizationGrant, Self::Error> {
         let fulfilled_at = clock.now();
+        let code_expires_at = fulfilled_at + code_expires_after;
         let res = sqlx::query!(
             r#"
                 UPDATE oauth2_authorization_grants
                 SET fulfilled_at = $2
                   , oauth2_session_id = $3
+                  , code_expires_at = $4
                 WHERE oauth2_authorization_grant_id = $1
             "#,
             Uuid::from(grant.id),
             fulfilled_at,
             Uuid::from(session.id),
+            code_expires_at,
         )
         .traced()
         .execute(&mut *self.conn)
@@ -437,7 +475,7 @@ impl<'c> OAuth2AuthorizationGrantRepository for PgOAuth2AuthorizationGrantReposi
 
         // XXX: check affected rows & new methods
         let grant = grant
-            .fulfill(fulfilled_at, session)
+            .fulfill(fulfilled_at, code_expires_at, session)
             .map_err(DatabaseError::to_invalid_operation)?;
 
         Ok(grant)
@@ -457,13 +495,14 @@ impl<'c> OAuth2AuthorizationGrantRepository for PgOAuth2AuthorizationGrantReposi
         &mut self,
         clock: &dyn Clock,
         grant: AuthorizationGrant,
-    ) -> Result<AuthorizationGrant, Self::Error> {
+    ) -> Result<Option<AuthorizationGrant>, Self::Error> {
         let exchanged_at = clock.now();
         let res = sqlx::query!(
             r#"
                 UPDATE oauth2_authorization_grants
                 SET exchanged_at = $2
                 WHERE oauth2_authorization_grant_id = $1
+                  AND exchanged_at IS NULL
             "#,
             Uuid::from(grant.id),
             exchanged_at,
@@ -472,13 +511,16 @@ impl<'c> OAuth2AuthorizationGrantRepository for PgOAuth2AuthorizationGrantReposi
         .execute(&mut *self.conn)
         .await?;
 
-        DatabaseError::ensure_affected_rows(&res, 1)?;
+        // Another transaction exchanged it in the meantime
+        if res.rows_affected() == 0 {
+            return Ok(None);
+        }
 
         let grant = grant
             .exchange(exchanged_at)
             .map_err(DatabaseError::to_invalid_operation)?;
 
-        Ok(grant)
+        Ok(Some(grant))
     }
 
     #[tracing::instrument(
@@ -513,4 +555,37 @@ impl<'c> OAuth2AuthorizationGrantRepository for PgOAuth2AuthorizationGrantReposi
 
         Ok(grant)
     }
+
+    #[tracing::instrument(
+        name = "db.oauth2_authorization_grant.reauthenticated",
+        skip_all,
+        fields(
+            db.statement,
+            %grant.id,
+            client.id = %grant.client_id,
+        ),
+        err,
+    )]
+    async fn reauthenticated(
+        &mut self,
+        mut grant: AuthorizationGrant,
+    ) -> Result<AuthorizationGrant, Self::Error> {
+        sqlx::query!(
+            r#"
+                UPDATE oauth2_authorization_grants AS og
+                SET
+                    requires_reauth = 'f'
+                WHERE
+                    og.oauth2_authorization_grant_id = $1
+            "#,
+            Uuid::from(grant.id),
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        grant.requires_reauth = false;
+
+        Ok(grant)
+    }
 }
@@ -14,18 +14,21 @@
 
 use std::{
     collections::{BTreeMap, BTreeSet},
-    str::FromStr,
     string::ToString,
 };
 
 use async_trait::async_trait;
-use mas_data_model::{Client, JwksOrJwksUri, User};
+use chrono::Duration;
+use mas_data_model::{AccessTokenFormat, Client, JwksOrJwksUri, RedirectUriMatching};
 use mas_iana::{
     jose::JsonWebSignatureAlg,
     oauth::{OAuthAuthorizationEndpointResponseType, OAuthClientAuthenticationMethod},
 };
 use mas_jose::jwk::PublicJsonWebKeySet;
-use mas_storage::{oauth2::OAuth2ClientRepository, Clock};
+use mas_storage::{
+    oauth2::{OAuth2ClientRepository, UpsertStaticClientParams},
+    Clock,
+};
 use oauth2_types::{
     oidc::ApplicationType,
     requests::GrantType,
@@ -54,7 +57,7 @@ impl<'c> PgOAuth2ClientRepository<'c> {
     }
 }
 
-// XXX: response_types & contacts
+// XXX: contacts
 #[allow(clippy::struct_excessive_bools)]
 #[derive(Debug)]
 struct OAuth2ClientLookup {
@@ -62,11 +65,13 @@ struct OAuth2ClientLookup {
     encrypted_client_secret: Option<String>,
     application_type: Option<String>,
     redirect_uris: Vec<String>,
-    // response_types: Vec<String>,
+    redirect_uri_matching: String,
+    response_types: Vec<String>,
     grant_type_authorization_code: bool,
     grant_type_refresh_token: bool,
     grant_type_client_credentials: bool,
     grant_type_device_code: bool,
+    grant_type_token_exchange: bool,
     contacts: Vec<String>,
     client_name: Option<String>,
     logo_uri: Option<String>,
@@ -77,9 +82,22 @@ struct OAuth2ClientLookup {
     jwks: Option<serde_json::Value>,
     id_token_signed_response_alg: Option<String>,
     userinfo_signed_response_alg: Option<String>,
+    authorization_signed_response_alg: Option<String>,
     token_endpoint_auth_method: Option<String>,
     token_endpoint_auth_signing_alg: Option<String>,
     initiate_login_uri: Option<String>,
+    require_signed_request_object: bool,
+    require_pkce: Option<bool>,
+    access_token_ttl: Option<i64>,
+    refresh_token_ttl: Option<i64>,
+    access_token_format: String,
+    client_credentials_scope_list: Option<Vec<String>>,
+    allowed_resources: Vec<String>,
+    introspection_endpoint_auth_method: Option<String>,
+    post_logout_redirect_uris: Vec<String>,
+    backchannel_logout_uri: Option<String>,
+    backchannel_logout_session_required: bool,
+    trusted: bool,
 }
 
 impl TryInto<Client> for OAuth2ClientLookup {
@@ -98,6 +116,90 @@ impl TryInto<Client> for OAuth2ClientLookup {
                 .source(e)
         })?;
 
+        let post_logout_redirect_uris: Result<Vec<Url>, _> = self
+            .post_logout_redirect_uris
+            .iter()
+            .map(|s| s.parse())
+            .collect();
+        let post_logout_redirect_uris = post_logout_redirect_uris.map_err(|e| {
+            DatabaseInconsistencyError::on("oauth2_clients")
+                .column("post_logout_redirect_uris")
+                .row(id)
+                .source(e)
+        })?;
+
+        let backchannel_logout_uri = self
+            .backchannel_logout_uri
+            .map(|s| s.parse())
+            .transpose()
+            .map_err(|e| {
+                DatabaseInconsistencyError::on("oauth2_clients")
+                    .column("backchannel_logout_uri")
+                    .row(id)
+                    .source(e)
+            })?;
+
+        let access_token_ttl = self
+            .access_token_ttl
+            .map(|ttl| {
+                Duration::try_seconds(ttl).ok_or_else(|| {
+                    DatabaseInconsistencyError::on("oauth2_clients")
+                        .column("access_token_ttl")
+                        .row(id)
+                })
+            })
+            .transpose()?;
+
+        let refresh_token_ttl = self
+            .refresh_token_ttl
+            .map(|ttl| {
+                Duration::try_seconds(ttl).ok_or_else(|| {
+                    DatabaseInconsistencyError::on("oauth2_clients")
+                        .column("refresh_token_ttl")
+                        .row(id)
+                })
+            })
+            .transpose()?;
+
+        let client_credentials_scope = self
+            .client_credentials_scope_list
+            .map(|scope_list| {
+                scope_list
+                    .iter()
+                    .map(|s| s.parse::<ScopeToken>())
+                    .collect::<Result<Scope, _>>()
+            })
+            .transpose()
+            .map_err(|e| {
+                DatabaseInconsistencyError::on("oauth2_clients")
+                    .column("client_credentials_scope_list")
+                    .row(id)
+                    .source(e)
+            })?;
+
+        let allowed_resources: Result<Vec<Url>, _> =
+            self.allowed_resources.iter().map(|s| s.parse()).collect();
+        let allowed_resources = allowed_resources.map_err(|e| {
+            DatabaseInconsistencyError::on("oauth2_clients")
+                .column("allowed_resources")
+                .row(id)
+                .source(e)
+        })?;
+
+        let redirect_uri_matching = self.redirect_uri_matching.parse().map_err(|e| {
+            DatabaseInconsistencyError::on("oauth2_clients")
+                .column("redirect_uri_matching")
+                .row(id)
+                .source(e)
+        })?;
+
+        let access_token_format = self.access_token_format.parse().map_err(|e| {
+            DatabaseInconsistencyError::on("oauth2_clients")
+                .column("access_token_format")
+                .row(id)
+                .source(e)
+        })?;
+
         let application_type = self
             .application_type
             .map(|s| s.parse())
@@ -109,19 +211,14 @@ impl TryInto<Client> for OAuth2ClientLookup {
                     .source(e)
             })?;
 
-        let response_types = vec![
-            OAuthAuthorizationEndpointResponseType::Code,
-            OAuthAuthorizationEndpointResponseType::IdToken,
-            OAuthAuthorizationEndpointResponseType::None,
-        ];
-        /* XXX
         let response_types: Result<Vec<OAuthAuthorizationEndpointResponseType>, _> =
             self.response_types.iter().map(|s| s.parse()).collect();
-        let response_types = response_types.map_err(|source| ClientFetchError::ParseField {
-            field: "response_types",
-            source,
+        let response_types = response_types.map_err(|e| {
+            DatabaseInconsistencyError::on("oauth2_clients")
+                .column("response_types")
+                .row(id)
+                .source(e)
         })?;
-        */
 
         let mut grant_types = Vec::new();
         if self.grant_type_authorization_code {
@@ -136,6 +233,9 @@ impl TryInto<Client> for OAuth2ClientLookup {
         if self.grant_type_device_code {
             grant_types.push(GrantType::DeviceCode);
         }
+        if self.grant_type_token_exchange {
+            grant_types.push(GrantType::TokenExchange);
+        }
 
         let logo_uri = self.logo_uri.map(|s| s.parse()).transpose().map_err(|e| {
             DatabaseInconsistencyError::on("oauth2_clients")
@@ -195,6 +295,17 @@ impl TryInto<Client> for OAuth2ClientLookup {
                     .source(e)
             })?;
 
+        let authorization_signed_response_alg = self
+            .authorization_signed_response_alg
+            .map(|s| s.parse())
+            .transpose()
+            .map_err(|e| {
+                DatabaseInconsistencyError::on("oauth2_clients")
+                    .column("authorization_signed_response_alg")
+                    .row(id)
+                    .source(e)
+            })?;
+
         let token_endpoint_auth_method = self
             .token_endpoint_auth_method
             .map(|s| s.parse())
@@ -206,6 +317,17 @@ impl TryInto<Client> for OAuth2ClientLookup {
                     .source(e)
             })?;
 
+        let introspection_endpoint_auth_method = self
+            .introspection_endpoint_auth_method
+            .map(|s| s.parse())
+            .transpose()
+            .map_err(|e| {
+                DatabaseInconsistencyError::on("oauth2_clients")
+                    .column("introspection_endpoint_auth_method")
+                    .row(id)
+                    .source(e)
+            })?;
+
         let token_endpoint_auth_signing_alg = self
             .token_endpoint_auth_signing_alg
             .map(|s| s.parse())
@@ -262,6 +384,7 @@ impl TryInto<Client> for OAuth2ClientLookup {
             encrypted_client_secret: self.encrypted_client_secret,
             application_type,
             redirect_uris,
+            redirect_uri_matching,
             response_types,
             grant_types,
             contacts: self.contacts,
@@ -273,9 +396,22 @@ impl TryInto<Client> for OAuth2ClientLookup {
             jwks,
             id_token_signed_response_alg,
             userinfo_signed_response_alg,
+            authorization_signed_response_alg,
             token_endpoint_auth_method,
             token_endpoint_auth_signing_alg,
             initiate_login_uri,
+            require_signed_request_object: self.require_signed_request_object,
+            require_pkce: self.require_pkce,
+            access_token_ttl,
+            refresh_token_ttl,
+            access_token_format,
+            client_credentials_scope,
+            allowed_resources,
+            introspection_endpoint_auth_method,
+            post_logout_redirect_uris,
+            backchannel_logout_uri,
+            backchannel_logout_session_required: self.backchannel_logout_session_required,
+            trusted: self.trusted,
         })
     }
 }
@@ -301,10 +437,13 @@ impl<'c> OAuth2ClientRepository for PgOAuth2ClientRepository<'c> {
                      , encrypted_client_secret
                      , application_type
                      , redirect_uris
+                     , redirect_uri_matching
+                     , response_types
                      , grant_type_authorization_code
                      , grant_type_refresh_token
                      , grant_type_client_credentials
                      , grant_type_device_code
+                     , grant_type_token_exchange
                      , contacts
                      , client_name
                      , logo_uri
@@ -315,9 +454,22 @@ impl<'c> OAuth2ClientRepository for PgOAuth2ClientRepository<'c> {
                      , jwks
                      , id_token_signed_response_alg
                      , userinfo_signed_response_alg
+                     , authorization_signed_response_alg
                      , token_endpoint_auth_method
                      , token_endpoint_auth_signing_alg
                      , initiate_login_uri
+                     , require_signed_request_object
+                     , require_pkce
+                     , access_token_ttl
+                     , refresh_token_ttl
+                     , access_token_format
+                     , client_credentials_scope_list
+                     , allowed_resources
+                     , introspection_endpoint_auth_method
+                     , post_logout_redirect_uris
+                     , backchannel_logout_uri
+                     , backchannel_logout_session_required
+                     , trusted
                 FROM oauth2_clients c
 
                 WHERE oauth2_client_id = $1
@@ -353,10 +505,13 @@ impl<'c> OAuth2ClientRepository for PgOAuth2ClientRepository<'c> {
                      , encrypted_client_secret
                      , application_type
                      , redirect_uris
+                     , redirect_uri_matching
+                     , response_types
                      , grant_type_authorization_code
                      , grant_type_refresh_token
                      , grant_type_client_credentials
                      , grant_type_device_code
+                     , grant_type_token_exchange
                      , contacts
                      , client_name
                      , logo_uri
@@ -367,9 +522,22 @@ impl<'c> OAuth2ClientRepository for PgOAuth2ClientRepository<'c> {
                      , jwks
                      , id_token_signed_response_alg
                      , userinfo_signed_response_alg
+                     , authorization_signed_response_alg
                      , token_endpoint_auth_method
                      , token_endpoint_auth_signing_alg
                      , initiate_login_uri
+                     , require_signed_request_object
+                     , require_pkce
+                     , access_token_ttl
+                     , refresh_token_ttl
+                     , access_token_format
+                     , client_credentials_scope_list
+                     , allowed_resources
+                     , introspection_endpoint_auth_method
+                     , post_logout_redirect_uris
+                     , backchannel_logout_uri
+                     , backchannel_logout_session_required
+                     , trusted
                 FROM oauth2_clients c
 
                 WHERE oauth2_client_id = ANY($1::uuid[])
@@ -407,6 +575,7 @@ impl<'c> OAuth2ClientRepository for PgOAuth2ClientRepository<'c> {
         redirect_uris: Vec<Url>,
         encrypted_client_secret: Option<String>,
         application_type: Option<ApplicationType>,
+        response_types: Vec<OAuthAuthorizationEndpointResponseType>,
         grant_types: Vec<GrantType>,
         contacts: Vec<String>,
         client_name: Option<String>,
@@ -418,9 +587,11 @@ impl<'c> OAuth2ClientRepository for PgOAuth2ClientRepository<'c> {
         jwks: Option<PublicJsonWebKeySet>,
         id_token_signed_response_alg: Option<JsonWebSignatureAlg>,
         userinfo_signed_response_alg: Option<JsonWebSignatureAlg>,
+        authorization_signed_response_alg: Option<JsonWebSignatureAlg>,
         token_endpoint_auth_method: Option<OAuthClientAuthenticationMethod>,
         token_endpoint_auth_signing_alg: Option<JsonWebSignatureAlg>,
         initiate_login_uri: Option<Url>,
+        post_logout_redirect_uris: Vec<Url>,
     ) -> Result<Client, Self::Error> {
         let now = clock.now();
         let id = Ulid::from_datetime_with_source(now.into(), rng);
@@ -433,6 +604,14 @@ impl<'c> OAuth2ClientRepository for PgOAuth2ClientRepository<'c> {
             .map_err(DatabaseError::to_invalid_operation)?;
 
         let redirect_uris_array = redirect_uris.iter().map(Url::to_string).collect::<Vec<_>>();
+        let response_types_array = response_types
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        let post_logout_redirect_uris_array = post_logout_redirect_uris
+            .iter()
+            .map(Url::to_string)
+            .collect::<Vec<_>>();
 
         sqlx::query!(
             r#"
@@ -441,6 +620,7 @@ impl<'c> OAuth2ClientRepository for PgOAuth2ClientRepository<'c> {
                     , encrypted_client_secret
                     , application_type
                     , redirect_uris
+                    , response_types
                     , grant_type_authorization_code
                     , grant_type_refresh_token
                     , grant_type_client_credentials
@@ -457,15 +637,18 @@ impl<'c> OAuth2ClientRepository for PgOAuth2ClientRepository<'c> {
                     , token_endpoint_auth_method
                     , token_endpoint_auth_signing_alg
                     , initiate_login_uri
+                    , post_logout_redirect_uris
+                    , authorization_signed_response_alg
                     , is_static
                     )
                 VALUES
-                    ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19, $20, FALSE)
+                    ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19, $20, $21, $22, $23, FALSE)
             "#,
             Uuid::from(id),
             encrypted_client_secret,
             application_type.as_ref().map(ToString::to_string),
             &redirect_uris_array,
+            &response_types_array,
             grant_types.contains(&GrantType::AuthorizationCode),
             grant_types.contains(&GrantType::RefreshToken),
             grant_types.contains(&GrantType::ClientCredentials),
@@ -488,6 +671,8 @@ impl<'c> OAuth2ClientRepository for PgOAuth2ClientRepository<'c> {
                 .as_ref()
                 .map(ToString::to_string),
             initiate_login_uri.as_ref().map(Url::as_str),
+            &post_logout_redirect_uris_array,
+            authorization_signed_response_alg.as_ref().map(ToString::to_string),
         )
         .traced()
         .execute(&mut *self.conn)
@@ -506,11 +691,8 @@ impl<'c> OAuth2ClientRepository for PgOAuth2ClientRepository<'c> {
             encrypted_client_secret,
             application_type,
             redirect_uris,
-            response_types: vec![
-                OAuthAuthorizationEndpointResponseType::Code,
-                OAuthAuthorizationEndpointResponseType::IdToken,
-                OAuthAuthorizationEndpointResponseType::None,
-            ],
+            redirect_uri_matching: RedirectUriMatching::Exact,
+            response_types,
             grant_types,
             contacts,
             client_name,
@@ -521,9 +703,22 @@ impl<'c> OAuth2ClientRepository for PgOAuth2ClientRepository<'c> {
             jwks,
             id_token_signed_response_alg,
             userinfo_signed_response_alg,
+            authorization_signed_response_alg,
             token_endpoint_auth_method,
             token_endpoint_auth_signing_alg,
             initiate_login_uri,
+            require_signed_request_object: false,
+            require_pkce: None,
+            access_token_ttl: None,
+            refresh_token_ttl: None,
+            access_token_format: AccessTokenFormat::Opaque,
+            client_credentials_scope: None,
+            allowed_resources: Vec::new(),
+            introspection_endpoint_auth_method: None,
+            post_logout_redirect_uris,
+            backchannel_logout_uri: None,
+            backchannel_logout_session_required: false,
+            trusted: false,
         })
     }
 
@@ -532,19 +727,39 @@ impl<'c> OAuth2ClientRepository for PgOAuth2ClientRepository<'c> {
         skip_all,
         fields(
             db.statement,
-            client.id = %client_id,
+            client.id = %params.client_id,
         ),
         err,
     )]
     async fn upsert_static(
         &mut self,
-        client_id: Ulid,
-        client_auth_method: OAuthClientAuthenticationMethod,
-        encrypted_client_secret: Option<String>,
-        jwks: Option<PublicJsonWebKeySet>,
-        jwks_uri: Option<Url>,
-        redirect_uris: Vec<Url>,
+        params: UpsertStaticClientParams,
     ) -> Result<Client, Self::Error> {
+        let UpsertStaticClientParams {
+            client_id,
+            client_auth_method,
+            encrypted_client_secret,
+            jwks,
+            jwks_uri,
+            redirect_uris,
+            redirect_uri_matching,
+            response_types,
+            allow_token_exchange,
+            allow_client_credentials,
+            client_credentials_scope,
+            allowed_resources,
+            require_signed_request_object,
+            require_pkce,
+            access_token_ttl,
+            refresh_token_ttl,
+            access_token_format,
+            introspection_endpoint_auth_method,
+            post_logout_redirect_uris,
+            backchannel_logout_uri,
+            backchannel_logout_session_required,
+            trusted,
+        } = params;
+
         let jwks_json = jwks
             .as_ref()
             .map(serde_json::to_value)
@@ -553,6 +768,24 @@ impl<'c> OAuth2ClientRepository for PgOAuth2ClientRepository<'c> {
 
         let client_auth_method = client_auth_method.to_string();
         let redirect_uris_array = redirect_uris.iter().map(Url::to_string).collect::<Vec<_>>();
+        let response_types_array = response_types
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        let post_logout_redirect_uris_array = post_logout_redirect_uris
+            .iter()
+            .map(Url::to_string)
+            .collect::<Vec<_>>();
+        let allowed_resources_array = allowed_resources
+            .iter()
+            .map(Url::to_string)
+            .collect::<Vec<_>>();
+        let client_credentials_scope_list = client_credentials_scope.as_ref().map(|scope| {
+            scope
+                .iter()
+                .map(|s| s.as_str().to_owned())
+                .collect::<Vec<_>>()
+        });
 
         sqlx::query!(
             r#"
@@ -560,39 +793,86 @@ impl<'c> OAuth2ClientRepository for PgOAuth2ClientRepository<'c> {
                     ( oauth2_client_id
                     , encrypted_client_secret
                     , redirect_uris
+                    , redirect_uri_matching
+                    , response_types
                     , grant_type_authorization_code
                     , grant_type_refresh_token
                     , grant_type_client_credentials
                     , grant_type_device_code
+                    , grant_type_token_exchange
                     , token_endpoint_auth_method
                     , jwks
                     , jwks_uri
+                    , require_signed_request_object
+                    , require_pkce
+                    , access_token_ttl
+                    , refresh_token_ttl
+                    , client_credentials_scope_list
+                    , allowed_resources
+                    , introspection_endpoint_auth_method
+                    , post_logout_redirect_uris
+                    , backchannel_logout_uri
+                    , backchannel_logout_session_required
+                    , trusted
+                    , access_token_format
                     , is_static
                     )
                 VALUES
-                    ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, TRUE)
+                    ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19, $20, $21, $22, $23, $24, $25, TRUE)
                 ON CONFLICT (oauth2_client_id)
                 DO
                     UPDATE SET encrypted_client_secret = EXCLUDED.encrypted_client_secret
+                             , redirect_uri_matching = EXCLUDED.redirect_uri_matching
+                             , response_types = EXCLUDED.response_types
                              , grant_type_authorization_code = EXCLUDED.grant_type_authorization_code
                              , grant_type_refresh_token = EXCLUDED.grant_type_refresh_token
                              , grant_type_client_credentials = EXCLUDED.grant_type_client_credentials
                              , grant_type_device_code = EXCLUDED.grant_type_device_code
+                             , grant_type_token_exchange = EXCLUDED.grant_type_token_exchange
                              , token_endpoint_auth_method = EXCLUDED.token_endpoint_auth_method
                              , jwks = EXCLUDED.jwks
                              , jwks_uri = EXCLUDED.jwks_uri
+                             , require_signed_request_object = EXCLUDED.require_signed_request_object
+                             , require_pkce = EXCLUDED.require_pkce
+                             , access_token_ttl = EXCLUDED.access_token_ttl
+                             , refresh_token_ttl = EXCLUDED.refresh_token_ttl
+                             , client_credentials_scope_list = EXCLUDED.client_credentials_scope_list
+                             , allowed_resources = EXCLUDED.allowed_resources
+                             , introspection_endpoint_auth_method = EXCLUDED.introspection_endpoint_auth_method
+                             , post_logout_redirect_uris = EXCLUDED.post_logout_redirect_uris
+                             , backchannel_logout_uri = EXCLUDED.backchannel_logout_uri
+                             , backchannel_logout_session_required = EXCLUDED.backchannel_logout_session_required
+                             , trusted = EXCLUDED.trusted
+                             , access_token_format = EXCLUDED.access_token_format
                              , is_static = TRUE
             "#,
             Uuid::from(client_id),
             encrypted_client_secret,
             &redirect_uris_array,
+            redirect_uri_matching.as_str(),
+            &response_types_array,
             true,
             true,
+            allow_client_credentials,
             true,
-            true,
+            allow_token_exchange,
             client_auth_method,
             jwks_json,
             jwks_uri.as_ref().map(Url::as_str),
+            require_signed_request_object,
+            require_pkce,
+            access_token_ttl.map(|ttl| ttl.num_seconds()),
+            refresh_token_ttl.map(|ttl| ttl.num_seconds()),
+            client_credentials_scope_list.as_deref(),
+            &allowed_resources_array,
+            introspection_endpoint_auth_method
+                .as_ref()
+                .map(ToString::to_string),
+            &post_logout_redirect_uris_array,
+            backchannel_logout_uri.as_ref().map(Url::as_str),
+            backchannel_logout_session_required,
+            trusted,
+            access_token_format.as_str(),
         )
         .traced()
         .execute(&mut *self.conn)
@@ -605,22 +885,23 @@ impl<'c> OAuth2ClientRepository for PgOAuth2ClientRepository<'c> {
             _ => return Err(DatabaseError::invalid_operation()),
         };
 
+        let mut grant_types = vec![GrantType::AuthorizationCode, GrantType::RefreshToken];
+        if allow_client_credentials {
+            grant_types.push(GrantType::ClientCredentials);
+        }
+        if allow_token_exchange {
+            grant_types.push(GrantType::TokenExchange);
+        }
+
         Ok(Client {
             id: client_id,
             client_id: client_id.to_string(),
             encrypted_client_secret,
             application_type: None,
             redirect_uris,
-            response_types: vec![
-                OAuthAuthorizationEndpointResponseType::Code,
-                OAuthAuthorizationEndpointResponseType::IdToken,
-                OAuthAuthorizationEndpointResponseType::None,
-            ],
-            grant_types: vec![
-                GrantType::AuthorizationCode,
-                GrantType::RefreshToken,
-                GrantType::ClientCredentials,
-            ],
+            redirect_uri_matching,
+            response_types,
+            grant_types,
             contacts: Vec::new(),
             client_name: None,
             logo_uri: None,
@@ -630,9 +911,22 @@ impl<'c> OAuth2ClientRepository for PgOAuth2ClientRepository<'c> {
             jwks,
             id_token_signed_response_alg: None,
             userinfo_signed_response_alg: None,
+            authorization_signed_response_alg: None,
             token_endpoint_auth_method: None,
             token_endpoint_auth_signing_alg: None,
             initiate_login_uri: None,
+            require_signed_request_object,
+            require_pkce,
+            access_token_ttl,
+            refresh_token_ttl,
+            access_token_format,
+            client_credentials_scope,
+            allowed_resources,
+            introspection_endpoint_auth_method,
+            post_logout_redirect_uris,
+            backchannel_logout_uri,
+            backchannel_logout_session_required,
+            trusted,
         })
     }
 
@@ -652,10 +946,13 @@ impl<'c> OAuth2ClientRepository for PgOAuth2ClientRepository<'c> {
                      , encrypted_client_secret
                      , application_type
                      , redirect_uris
+                     , redirect_uri_matching
+                     , response_types
                      , grant_type_authorization_code
                      , grant_type_refresh_token
                      , grant_type_client_credentials
                      , grant_type_device_code
+                     , grant_type_token_exchange
                      , contacts
                      , client_name
                      , logo_uri
@@ -666,9 +963,22 @@ impl<'c> OAuth2ClientRepository for PgOAuth2ClientRepository<'c> {
                      , jwks
                      , id_token_signed_response_alg
                      , userinfo_signed_response_alg
+                     , authorization_signed_response_alg
                      , token_endpoint_auth_method
                      , token_endpoint_auth_signing_alg
                      , initiate_login_uri
+                     , require_signed_request_object
+                     , require_pkce
+                     , access_token_ttl
+                     , refresh_token_ttl
+                     , access_token_format
+                     , client_credentials_scope_list
+                     , allowed_resources
+                     , introspection_endpoint_auth_method
+                     , post_logout_redirect_uris
+                     , backchannel_logout_uri
+                     , backchannel_logout_session_required
+                     , trusted
                 FROM oauth2_clients c
                 WHERE is_static = TRUE
             "#,
@@ -682,97 +992,6 @@ impl<'c> OAuth2ClientRepository for PgOAuth2ClientRepository<'c> {
             .collect()
     }
 
-    #[tracing::instrument(
-        name = "db.oauth2_client.get_consent_for_user",
-        skip_all,
-        fields(
-            db.statement,
-            %user.id,
-            %client.id,
-        ),
-        err,
-    )]
-    async fn get_consent_for_user(
-        &mut self,
-        client: &Client,
-        user: &User,
-    ) -> Result<Scope, Self::Error> {
-        let scope_tokens: Vec<String> = sqlx::query_scalar!(
-            r#"
-                SELECT scope_token
-                FROM oauth2_consents
-                WHERE user_id = $1 AND oauth2_client_id = $2
-            "#,
-            Uuid::from(user.id),
-            Uuid::from(client.id),
-        )
-        .fetch_all(&mut *self.conn)
-        .await?;
-
-        let scope: Result<Scope, _> = scope_tokens
-            .into_iter()
-            .map(|s| ScopeToken::from_str(&s))
-            .collect();
-
-        let scope = scope.map_err(|e| {
-            DatabaseInconsistencyError::on("oauth2_consents")
-                .column("scope_token")
-                .source(e)
-        })?;
-
-        Ok(scope)
-    }
-
-    #[tracing::instrument(
-        name = "db.oauth2_client.give_consent_for_user",
-        skip_all,
-        fields(
-            db.statement,
-            %user.id,
-            %client.id,
-            %scope,
-        ),
-        err,
-    )]
-    async fn give_consent_for_user(
-        &mut self,
-        rng: &mut (dyn RngCore + Send),
-        clock: &dyn Clock,
-        client: &Client,
-        user: &User,
-        scope: &Scope,
-    ) -> Result<(), Self::Error> {
-        let now = clock.now();
-        let (tokens, ids): (Vec<String>, Vec<Uuid>) = scope
-            .iter()
-            .map(|token| {
-                (
-                    token.to_string(),
-                    Uuid::from(Ulid::from_datetime_with_source(now.into(), rng)),
-                )
-            })
-            .unzip();
-
-        sqlx::query!(
-            r#"
-                INSERT INTO oauth2_consents
-                    (oauth2_consent_id, user_id, oauth2_client_id, scope_token, created_at)
-                SELECT id, $2, $3, scope_token, $5 FROM UNNEST($1::uuid[], $4::text[]) u(id, scope_token)
-                ON CONFLICT (user_id, oauth2_client_id, scope_token) DO UPDATE SET refreshed_at = $5
-            "#,
-            &ids,
-            Uuid::from(user.id),
-            Uuid::from(client.id),
-            &tokens,
-            now,
-        )
-        .traced()
-        .execute(&mut *self.conn)
-        .await?;
-
-        Ok(())
-    }
-
     #[tracing::instrument(
         name = "db.oauth2_client.delete_by_id",
         skip_all,
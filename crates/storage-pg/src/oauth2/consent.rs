@@ -0,0 +1,158 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::str::FromStr;
+
+use async_trait::async_trait;
+use mas_data_model::{Client, User};
+use mas_storage::{oauth2::OAuth2ConsentRepository, Clock};
+use oauth2_types::scope::{Scope, ScopeToken};
+use rand::RngCore;
+use sqlx::PgConnection;
+use ulid::Ulid;
+use uuid::Uuid;
+
+use crate::{tracing::ExecuteExt, DatabaseError, DatabaseInconsistencyError};
+
+/// An implementation of [`OAuth2ConsentRepository`] for a PostgreSQL
+/// connection
+pub struct PgOAuth2ConsentRepository<'c> {
+    conn: &'c mut PgConnection,
+}
+
+impl<'c> PgOAuth2ConsentRepository<'c> {
+    /// Create a new [`PgOAuth2ConsentRepository`] from an active PostgreSQL
+    /// connection
+    pub fn new(conn: &'c mut PgConnection) -> Self {
+        Self { conn }
+    }
+}
+
+#[async_trait]
+impl<'c> OAuth2ConsentRepository for PgOAuth2ConsentRepository<'c> {
+    type Error = DatabaseError;
+
+    #[tracing::instrument(
+        name = "db.oauth2_consent.find",
+        skip_all,
+        fields(
+            db.statement,
+            %user.id,
+            %client.id,
+        ),
+        err,
+    )]
+    async fn find(&mut self, client: &Client, user: &User) -> Result<Scope, Self::Error> {
+        let scope_tokens: Vec<String> = sqlx::query_scalar!(
+            r#"
+                SELECT scope_token
+                FROM oauth2_consents
+                WHERE user_id = $1 AND oauth2_client_id = $2
+            "#,
+            Uuid::from(user.id),
+            Uuid::from(client.id),
+        )
+        .fetch_all(&mut *self.conn)
+        .await?;
+
+        let scope: Result<Scope, _> = scope_tokens
+            .into_iter()
+            .map(|s| ScopeToken::from_str(&s))
+            .collect();
+
+        let scope = scope.map_err(|e| {
+            DatabaseInconsistencyError::on("oauth2_consents")
+                .column("scope_token")
+                .source(e)
+        })?;
+
+        Ok(scope)
+    }
+
+    #[tracing::instrument(
+        name = "db.oauth2_consent.add",
+        skip_all,
+        fields(
+            db.statement,
+            %user.id,
+            %client.id,
+            %scope,
+        ),
+        err,
+    )]
+    async fn add(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        client: &Client,
+        user: &User,
+        scope: &Scope,
+    ) -> Result<(), Self::Error> {
+        let now = clock.now();
+        let (tokens, ids): (Vec<String>, Vec<Uuid>) = scope
+            .iter()
+            .map(|token| {
+                (
+                    token.to_string(),
+                    Uuid::from(Ulid::from_datetime_with_source(now.into(), rng)),
+                )
+            })
+            .unzip();
+
+        sqlx::query!(
+            r#"
+                INSERT INTO oauth2_consents
+                    (oauth2_consent_id, user_id, oauth2_client_id, scope_token, created_at)
+                SELECT id, $2, $3, scope_token, $5 FROM UNNEST($1::uuid[], $4::text[]) u(id, scope_token)
+                ON CONFLICT (user_id, oauth2_client_id, scope_token) DO UPDATE SET refreshed_at = $5
+            "#,
+            &ids,
+            Uuid::from(user.id),
+            Uuid::from(client.id),
+            &tokens,
+            now,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(
+        name = "db.oauth2_consent.revoke",
+        skip_all,
+        fields(
+            db.statement,
+            %user.id,
+            %client.id,
+        ),
+        err,
+    )]
+    async fn revoke(&mut self, client: &Client, user: &User) -> Result<bool, Self::Error> {
+        let res = sqlx::query!(
+            r#"
+                DELETE FROM oauth2_consents
+                WHERE user_id = $1 AND oauth2_client_id = $2
+            "#,
+            Uuid::from(user.id),
+            Uuid::from(client.id),
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        Ok(res.rows_affected() > 0)
+    }
+}
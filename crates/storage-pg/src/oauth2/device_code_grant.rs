@@ -15,7 +15,7 @@
 use std::net::IpAddr;
 
 use async_trait::async_trait;
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Duration, Utc};
 use mas_data_model::{BrowserSession, DeviceCodeGrant, DeviceCodeGrantState, Session, UserAgent};
 use mas_storage::{
     oauth2::{OAuth2DeviceCodeGrantParams, OAuth2DeviceCodeGrantRepository},
@@ -51,6 +51,8 @@ struct OAuth2DeviceGrantLookup {
     user_code: String,
     created_at: DateTime<Utc>,
     expires_at: DateTime<Utc>,
+    last_polled_at: Option<DateTime<Utc>>,
+    poll_interval: i64,
     fulfilled_at: Option<DateTime<Utc>>,
     rejected_at: Option<DateTime<Utc>>,
     exchanged_at: Option<DateTime<Utc>>,
@@ -72,6 +74,8 @@ impl TryFrom<OAuth2DeviceGrantLookup> for DeviceCodeGrant {
             user_code,
             created_at,
             expires_at,
+            last_polled_at,
+            poll_interval,
             fulfilled_at,
             rejected_at,
             exchanged_at,
@@ -130,6 +134,12 @@ impl TryFrom<OAuth2DeviceGrantLookup> for DeviceCodeGrant {
             _ => return Err(DatabaseInconsistencyError::on("oauth2_device_code_grant").row(id)),
         };
 
+        let poll_interval = Duration::try_seconds(poll_interval).ok_or_else(|| {
+            DatabaseInconsistencyError::on("oauth2_device_code_grant")
+                .column("poll_interval")
+                .row(id)
+        })?;
+
         Ok(DeviceCodeGrant {
             id,
             state,
@@ -139,6 +149,8 @@ impl TryFrom<OAuth2DeviceGrantLookup> for DeviceCodeGrant {
             device_code,
             created_at,
             expires_at,
+            last_polled_at,
+            poll_interval,
             ip_address,
             user_agent: user_agent.map(UserAgent::parse),
         })
@@ -184,11 +196,12 @@ impl<'c> OAuth2DeviceCodeGrantRepository for PgOAuth2DeviceCodeGrantRepository<'
                     , user_code
                     , created_at
                     , expires_at
+                    , poll_interval
                     , ip_address
                     , user_agent
                     )
                 VALUES
-                    ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+                    ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
             "#,
             Uuid::from(id),
             Uuid::from(client_id),
@@ -197,6 +210,7 @@ impl<'c> OAuth2DeviceCodeGrantRepository for PgOAuth2DeviceCodeGrantRepository<'
             &params.user_code,
             created_at,
             expires_at,
+            params.poll_interval.num_seconds(),
             params.ip_address as Option<IpAddr>,
             params.user_agent.as_deref(),
         )
@@ -213,6 +227,8 @@ impl<'c> OAuth2DeviceCodeGrantRepository for PgOAuth2DeviceCodeGrantRepository<'
             device_code: params.device_code,
             created_at,
             expires_at,
+            last_polled_at: None,
+            poll_interval: params.poll_interval,
             ip_address: params.ip_address,
             user_agent: params.user_agent,
         })
@@ -238,6 +254,8 @@ impl<'c> OAuth2DeviceCodeGrantRepository for PgOAuth2DeviceCodeGrantRepository<'
                      , user_code
                      , created_at
                      , expires_at
+                     , last_polled_at
+                     , poll_interval
                      , fulfilled_at
                      , rejected_at
                      , exchanged_at
@@ -284,6 +302,8 @@ impl<'c> OAuth2DeviceCodeGrantRepository for PgOAuth2DeviceCodeGrantRepository<'
                      , user_code
                      , created_at
                      , expires_at
+                     , last_polled_at
+                     , poll_interval
                      , fulfilled_at
                      , rejected_at
                      , exchanged_at
@@ -330,6 +350,8 @@ impl<'c> OAuth2DeviceCodeGrantRepository for PgOAuth2DeviceCodeGrantRepository<'
                      , user_code
                      , created_at
                      , expires_at
+                     , last_polled_at
+                     , poll_interval
                      , fulfilled_at
                      , rejected_at
                      , exchanged_at
@@ -480,4 +502,44 @@ impl<'c> OAuth2DeviceCodeGrantRepository for PgOAuth2DeviceCodeGrantRepository<'
 
         Ok(device_code_grant)
     }
+
+    #[tracing::instrument(
+        name = "db.oauth2_device_code_grant.record_poll",
+        skip_all,
+        fields(
+            db.statement,
+            oauth2_device_code.id = %device_code_grant.id,
+            oauth2_client.id = %device_code_grant.client_id,
+        ),
+        err,
+    )]
+    async fn record_poll(
+        &mut self,
+        clock: &dyn Clock,
+        mut device_code_grant: DeviceCodeGrant,
+        poll_interval: Duration,
+    ) -> Result<DeviceCodeGrant, Self::Error> {
+        let last_polled_at = clock.now();
+
+        let res = sqlx::query!(
+            r#"
+                UPDATE oauth2_device_code_grant
+                SET last_polled_at = $1
+                  , poll_interval = $2
+                WHERE oauth2_device_code_grant_id = $3
+            "#,
+            last_polled_at,
+            poll_interval.num_seconds(),
+            Uuid::from(device_code_grant.id),
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        device_code_grant.last_polled_at = Some(last_polled_at);
+        device_code_grant.poll_interval = poll_interval;
+        Ok(device_code_grant)
+    }
 }
@@ -18,21 +18,27 @@
 mod access_token;
 mod authorization_grant;
 mod client;
+mod consent;
 mod device_code_grant;
+mod pushed_auth_request;
 mod refresh_token;
 mod session;
 
 pub use self::{
     access_token::PgOAuth2AccessTokenRepository,
     authorization_grant::PgOAuth2AuthorizationGrantRepository, client::PgOAuth2ClientRepository,
-    device_code_grant::PgOAuth2DeviceCodeGrantRepository,
+    consent::PgOAuth2ConsentRepository, device_code_grant::PgOAuth2DeviceCodeGrantRepository,
+    pushed_auth_request::PgOAuth2PushedAuthRequestRepository,
     refresh_token::PgOAuth2RefreshTokenRepository, session::PgOAuth2SessionRepository,
 };
 
 #[cfg(test)]
 mod tests {
+    use std::collections::BTreeMap;
+
     use chrono::Duration;
     use mas_data_model::{AuthorizationCode, UserAgent};
+    use mas_iana::oauth::OAuthAuthorizationEndpointResponseType;
     use mas_storage::{
         clock::MockClock,
         oauth2::{OAuth2DeviceCodeGrantParams, OAuth2SessionFilter, OAuth2SessionRepository},
@@ -76,6 +82,7 @@ mod tests {
                 vec!["https://example.com/redirect".parse().unwrap()],
                 None,
                 None,
+                vec![OAuthAuthorizationEndpointResponseType::Code],
                 vec![GrantType::AuthorizationCode],
                 Vec::new(), // TODO: contacts are not yet saved
                 // vec!["contact@example.com".to_owned()],
@@ -90,7 +97,9 @@ mod tests {
                 None,
                 None,
                 None,
+                None,
                 Some("https://example.com/login".parse().unwrap()),
+                Vec::new(),
             )
             .await
             .unwrap();
@@ -148,10 +157,14 @@ mod tests {
                 ResponseMode::Query,
                 true,
                 false,
+                false,
+                Some("mxid:@alice:example.com".to_owned()),
+                Vec::new(),
             )
             .await
             .unwrap();
         assert!(grant.is_pending());
+        assert_eq!(grant.login_hint.as_deref(), Some("mxid:@alice:example.com"));
 
         // Lookup the same grant by id
         let grant_lookup = repo
@@ -179,33 +192,35 @@ mod tests {
             .unwrap();
         let user_session = repo
             .browser_session()
-            .add(&mut rng, &clock, &user, None)
+            .add(&mut rng, &clock, &user, None, None, None)
             .await
             .unwrap();
 
         // Lookup the consent the user gave to the client
-        let consent = repo
-            .oauth2_client()
-            .get_consent_for_user(&client, &user)
-            .await
-            .unwrap();
+        let consent = repo.oauth2_consent().find(&client, &user).await.unwrap();
         assert!(consent.is_empty());
 
         // Give consent to the client
         let scope = Scope::from_iter([OPENID]);
-        repo.oauth2_client()
-            .give_consent_for_user(&mut rng, &clock, &client, &user, &scope)
+        repo.oauth2_consent()
+            .add(&mut rng, &clock, &client, &user, &scope)
             .await
             .unwrap();
 
         // Lookup the consent the user gave to the client
-        let consent = repo
-            .oauth2_client()
-            .get_consent_for_user(&client, &user)
-            .await
-            .unwrap();
+        let consent = repo.oauth2_consent().find(&client, &user).await.unwrap();
         assert_eq!(scope, consent);
 
+        // Revoke the consent
+        let revoked = repo.oauth2_consent().revoke(&client, &user).await.unwrap();
+        assert!(revoked);
+        let consent = repo.oauth2_consent().find(&client, &user).await.unwrap();
+        assert!(consent.is_empty());
+
+        // Revoking again does nothing
+        let revoked = repo.oauth2_consent().revoke(&client, &user).await.unwrap();
+        assert!(!revoked);
+
         // Lookup a non-existing session
         let session = repo.oauth2_session().lookup(Ulid::nil()).await.unwrap();
         assert_eq!(session, None);
@@ -226,11 +241,20 @@ mod tests {
         // Mark the grant as fulfilled
         let grant = repo
             .oauth2_authorization_grant()
-            .fulfill(&clock, &session, grant)
+            .fulfill(&clock, &session, grant, Duration::try_minutes(10).unwrap())
             .await
             .unwrap();
         assert!(grant.is_fulfilled());
 
+        // The code expiration is saved along with the grant
+        let grant_lookup = repo
+            .oauth2_authorization_grant()
+            .lookup(grant.id)
+            .await
+            .unwrap()
+            .expect("grant not found");
+        assert_eq!(grant, grant_lookup);
+
         // Lookup the same session by id
         let session_lookup = repo
             .oauth2_session()
@@ -243,11 +267,20 @@ mod tests {
         // Mark the grant as exchanged
         let grant = repo
             .oauth2_authorization_grant()
-            .exchange(&clock, grant)
+            .exchange(&clock, grant_lookup.clone())
             .await
-            .unwrap();
+            .unwrap()
+            .expect("grant was not exchanged");
         assert!(grant.is_exchanged());
 
+        // Exchanging it a second time does nothing
+        let res = repo
+            .oauth2_authorization_grant()
+            .exchange(&clock, grant_lookup)
+            .await
+            .unwrap();
+        assert!(res.is_none());
+
         // Lookup a non-existing token
         let token = repo
             .oauth2_access_token()
@@ -295,6 +328,59 @@ mod tests {
             .expect("token not found");
         assert_eq!(access_token, access_token_lookup);
 
+        // Lookup the token along with its session and user, for introspection
+        let token = repo
+            .oauth2_access_token()
+            .lookup_for_introspection("ddeeff")
+            .await
+            .unwrap();
+        assert_eq!(token, None);
+
+        let (access_token_lookup, session_lookup, user_lookup) = repo
+            .oauth2_access_token()
+            .lookup_for_introspection("aabbcc")
+            .await
+            .unwrap()
+            .expect("token not found");
+        assert_eq!(access_token, access_token_lookup);
+        assert_eq!(session, session_lookup);
+        assert_eq!(Some(user.clone()), user_lookup);
+
+        // Bind the token to a DPoP key
+        assert_eq!(access_token.dpop_jkt, None);
+        let access_token = repo
+            .oauth2_access_token()
+            .bind_dpop_key(access_token, "thumbprint".to_owned())
+            .await
+            .unwrap();
+        assert_eq!(access_token.dpop_jkt.as_deref(), Some("thumbprint"));
+
+        let access_token_lookup = repo
+            .oauth2_access_token()
+            .find_by_token("aabbcc")
+            .await
+            .unwrap()
+            .expect("token not found");
+        assert_eq!(access_token, access_token_lookup);
+
+        // Narrow down the scope of the token, without touching the session
+        assert_eq!(access_token.scope, None);
+        let access_token = repo
+            .oauth2_access_token()
+            .set_scope(access_token, Scope::from_iter([OPENID]))
+            .await
+            .unwrap();
+        assert_eq!(access_token.scope, Some(Scope::from_iter([OPENID])));
+
+        let (access_token_lookup, session_lookup, _) = repo
+            .oauth2_access_token()
+            .lookup_for_introspection("aabbcc")
+            .await
+            .unwrap()
+            .expect("token not found");
+        assert_eq!(access_token, access_token_lookup);
+        assert_eq!(session.scope, session_lookup.scope);
+
         // Lookup a non-existing refresh token
         let refresh_token = repo
             .oauth2_refresh_token()
@@ -358,14 +444,56 @@ mod tests {
             .unwrap();
         assert!(!access_token.is_valid(clock.now()));
 
+        // Create another refresh token, which replaces the first one
+        let new_refresh_token = repo
+            .oauth2_refresh_token()
+            .add(
+                &mut rng,
+                &clock,
+                &session,
+                &access_token,
+                "ddeeff".to_owned(),
+            )
+            .await
+            .unwrap();
+
         // Mark the refresh token as consumed
         assert!(refresh_token.is_valid());
         let refresh_token = repo
             .oauth2_refresh_token()
-            .consume(&clock, refresh_token)
+            .consume(&clock, refresh_token, &new_refresh_token)
             .await
             .unwrap();
         assert!(!refresh_token.is_valid());
+        assert_eq!(
+            refresh_token.state.next_refresh_token_id(),
+            Some(new_refresh_token.id)
+        );
+
+        // The successor is persisted
+        let refresh_token_lookup = repo
+            .oauth2_refresh_token()
+            .lookup(refresh_token.id)
+            .await
+            .unwrap()
+            .expect("refresh token not found");
+        assert_eq!(refresh_token, refresh_token_lookup);
+
+        // Revoke the new refresh token
+        let refresh_token = repo
+            .oauth2_refresh_token()
+            .revoke(&clock, refresh_token)
+            .await
+            .unwrap();
+        assert!(refresh_token.is_revoked());
+
+        let refresh_token_lookup = repo
+            .oauth2_refresh_token()
+            .find_by_token("ddeeff")
+            .await
+            .unwrap()
+            .expect("refresh token not found");
+        assert_eq!(refresh_token, refresh_token_lookup);
 
         // Record the user-agent on the session
         assert!(session.user_agent.is_none());
@@ -385,6 +513,25 @@ mod tests {
             .expect("session not found");
         assert_eq!(session.user_agent.as_deref(), Some("Mozilla/5.0"));
 
+        // Record the authentication time on the session
+        assert!(session.auth_time.is_none());
+        let auth_time = clock.now();
+        let session = repo
+            .oauth2_session()
+            .record_auth_time(session, auth_time)
+            .await
+            .unwrap();
+        assert_eq!(session.auth_time, Some(auth_time));
+
+        // Reload the session and check the authentication time
+        let session = repo
+            .oauth2_session()
+            .lookup(session.id)
+            .await
+            .unwrap()
+            .expect("session not found");
+        assert_eq!(session.auth_time, Some(auth_time));
+
         // Mark the session as finished
         assert!(session.is_valid());
         let session = repo.oauth2_session().finish(&clock, session).await.unwrap();
@@ -407,7 +554,7 @@ mod tests {
             .unwrap();
         let user1_session = repo
             .browser_session()
-            .add(&mut rng, &clock, &user1, None)
+            .add(&mut rng, &clock, &user1, None, None, None)
             .await
             .unwrap();
 
@@ -418,7 +565,7 @@ mod tests {
             .unwrap();
         let user2_session = repo
             .browser_session()
-            .add(&mut rng, &clock, &user2, None)
+            .add(&mut rng, &clock, &user2, None, None, None)
             .await
             .unwrap();
 
@@ -431,6 +578,7 @@ mod tests {
                 vec!["https://first.example.com/redirect".parse().unwrap()],
                 None,
                 None,
+                vec![OAuthAuthorizationEndpointResponseType::Code],
                 vec![GrantType::AuthorizationCode],
                 Vec::new(), // TODO: contacts are not yet saved
                 // vec!["contact@first.example.com".to_owned()],
@@ -445,7 +593,9 @@ mod tests {
                 None,
                 None,
                 None,
+                None,
                 Some("https://first.example.com/login".parse().unwrap()),
+                Vec::new(),
             )
             .await
             .unwrap();
@@ -457,6 +607,7 @@ mod tests {
                 vec!["https://second.example.com/redirect".parse().unwrap()],
                 None,
                 None,
+                vec![OAuthAuthorizationEndpointResponseType::Code],
                 vec![GrantType::AuthorizationCode],
                 Vec::new(), // TODO: contacts are not yet saved
                 // vec!["contact@second.example.com".to_owned()],
@@ -471,7 +622,9 @@ mod tests {
                 None,
                 None,
                 None,
+                None,
                 Some("https://second.example.com/login".parse().unwrap()),
+                Vec::new(),
             )
             .await
             .unwrap();
@@ -568,6 +721,20 @@ mod tests {
 
         assert_eq!(repo.oauth2_session().count(filter).await.unwrap(), 2);
 
+        // Filter for only one browser session
+        let filter = OAuth2SessionFilter::new().for_browser_session(&user2_session);
+        let list = repo
+            .oauth2_session()
+            .list(filter, pagination)
+            .await
+            .unwrap();
+        assert!(!list.has_next_page);
+        assert_eq!(list.edges.len(), 2);
+        assert_eq!(list.edges[0], session12);
+        assert_eq!(list.edges[1], session22);
+
+        assert_eq!(repo.oauth2_session().count(filter).await.unwrap(), 2);
+
         // Filter for both a user and a client
         let filter = OAuth2SessionFilter::new()
             .for_user(&user2)
@@ -727,6 +894,7 @@ mod tests {
                 vec!["https://example.com/redirect".parse().unwrap()],
                 None,
                 None,
+                vec![OAuthAuthorizationEndpointResponseType::Code],
                 vec![GrantType::AuthorizationCode],
                 Vec::new(), // TODO: contacts are not yet saved
                 // vec!["contact@example.com".to_owned()],
@@ -741,7 +909,9 @@ mod tests {
                 None,
                 None,
                 None,
+                None,
                 Some("https://example.com/login".parse().unwrap()),
+                Vec::new(),
             )
             .await
             .unwrap();
@@ -756,7 +926,7 @@ mod tests {
         // Provision a browser session
         let browser_session = repo
             .browser_session()
-            .add(&mut rng, &clock, &user, None)
+            .add(&mut rng, &clock, &user, None, None, None)
             .await
             .unwrap();
 
@@ -776,6 +946,7 @@ mod tests {
                     device_code: device_code.to_owned(),
                     user_code: user_code.to_owned(),
                     expires_in: Duration::try_minutes(5).unwrap(),
+                    poll_interval: Duration::try_seconds(5).unwrap(),
                     ip_address: None,
                     user_agent: None,
                 },
@@ -806,6 +977,19 @@ mod tests {
             .unwrap();
         assert_eq!(lookup.as_ref(), Some(&grant));
 
+        // Record a poll of the token endpoint, which increases the interval
+        assert_eq!(grant.last_polled_at, None);
+        assert_eq!(grant.poll_interval, Duration::try_seconds(5).unwrap());
+        let grant = repo
+            .oauth2_device_code_grant()
+            .record_poll(&clock, grant, Duration::try_seconds(10).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(grant.last_polled_at, Some(clock.now()));
+        assert_eq!(grant.poll_interval, Duration::try_seconds(10).unwrap());
+        let lookup = repo.oauth2_device_code_grant().lookup(id).await.unwrap();
+        assert_eq!(lookup.as_ref(), Some(&grant));
+
         // Let's mark it as fulfilled
         let grant = repo
             .oauth2_device_code_grant()
@@ -881,6 +1065,7 @@ mod tests {
                     device_code: "second_devicecode".to_owned(),
                     user_code: "second_usercode".to_owned(),
                     expires_in: Duration::try_minutes(5).unwrap(),
+                    poll_interval: Duration::try_seconds(5).unwrap(),
                     ip_address: None,
                     user_agent: None,
                 },
@@ -936,4 +1121,104 @@ mod tests {
             .await;
         assert!(res.is_err());
     }
+
+    /// Test the [`OAuth2PushedAuthRequestRepository`] implementation
+    #[sqlx::test(migrator = "crate::MIGRATOR")]
+    async fn test_pushed_auth_request_repository(pool: PgPool) {
+        let mut rng = ChaChaRng::seed_from_u64(42);
+        let clock = MockClock::default();
+        let mut repo = PgRepository::from_pool(&pool).await.unwrap().boxed();
+
+        // Provision a client
+        let client = repo
+            .oauth2_client()
+            .add(
+                &mut rng,
+                &clock,
+                vec!["https://example.com/redirect".parse().unwrap()],
+                None,
+                None,
+                vec![OAuthAuthorizationEndpointResponseType::Code],
+                vec![GrantType::AuthorizationCode],
+                Vec::new(),
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+                Vec::new(),
+            )
+            .await
+            .unwrap();
+
+        // Lookup a non-existing request
+        let request = repo
+            .oauth2_pushed_auth_request()
+            .lookup(Ulid::nil())
+            .await
+            .unwrap();
+        assert_eq!(request, None);
+
+        let parameters = BTreeMap::from([
+            ("response_type".to_owned(), "code".to_owned()),
+            ("scope".to_owned(), "openid".to_owned()),
+        ]);
+
+        let request = repo
+            .oauth2_pushed_auth_request()
+            .add(
+                &mut rng,
+                &clock,
+                &client,
+                parameters.clone(),
+                Duration::try_seconds(90).unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(request.client_id, client.id);
+        assert_eq!(request.parameters, parameters);
+        assert!(!request.is_expired(clock.now()));
+
+        // Look it up
+        let lookup = repo
+            .oauth2_pushed_auth_request()
+            .lookup(request.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(lookup, request);
+
+        // Cleaning up doesn't remove it before it expires
+        let count = repo
+            .oauth2_pushed_auth_request()
+            .cleanup_expired(&clock)
+            .await
+            .unwrap();
+        assert_eq!(count, 0);
+
+        clock.advance(Duration::try_minutes(2).unwrap());
+        assert!(request.is_expired(clock.now()));
+
+        let count = repo
+            .oauth2_pushed_auth_request()
+            .cleanup_expired(&clock)
+            .await
+            .unwrap();
+        assert_eq!(count, 1);
+
+        let lookup = repo
+            .oauth2_pushed_auth_request()
+            .lookup(request.id)
+            .await
+            .unwrap();
+        assert_eq!(lookup, None);
+    }
 }
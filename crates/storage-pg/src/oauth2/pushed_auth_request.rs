@@ -0,0 +1,176 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::BTreeMap;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use mas_data_model::{Client, PushedAuthorizationRequest};
+use mas_storage::{oauth2::OAuth2PushedAuthRequestRepository, Clock};
+use rand::RngCore;
+use sqlx::{types::Json, PgConnection};
+use ulid::Ulid;
+use uuid::Uuid;
+
+use crate::{tracing::ExecuteExt, DatabaseError};
+
+/// An implementation of [`OAuth2PushedAuthRequestRepository`] for a
+/// PostgreSQL connection
+pub struct PgOAuth2PushedAuthRequestRepository<'c> {
+    conn: &'c mut PgConnection,
+}
+
+impl<'c> PgOAuth2PushedAuthRequestRepository<'c> {
+    /// Create a new [`PgOAuth2PushedAuthRequestRepository`] from an active
+    /// PostgreSQL connection
+    pub fn new(conn: &'c mut PgConnection) -> Self {
+        Self { conn }
+    }
+}
+
+struct PushedAuthRequestLookup {
+    oauth2_pushed_auth_request_id: Uuid,
+    oauth2_client_id: Uuid,
+    parameters: Json<BTreeMap<String, String>>,
+    created_at: DateTime<Utc>,
+    expires_at: DateTime<Utc>,
+}
+
+impl From<PushedAuthRequestLookup> for PushedAuthorizationRequest {
+    fn from(value: PushedAuthRequestLookup) -> Self {
+        PushedAuthorizationRequest {
+            id: value.oauth2_pushed_auth_request_id.into(),
+            client_id: value.oauth2_client_id.into(),
+            parameters: value.parameters.0,
+            created_at: value.created_at,
+            expires_at: value.expires_at,
+        }
+    }
+}
+
+#[async_trait]
+impl<'c> OAuth2PushedAuthRequestRepository for PgOAuth2PushedAuthRequestRepository<'c> {
+    type Error = DatabaseError;
+
+    #[tracing::instrument(
+        name = "db.oauth2_pushed_auth_request.add",
+        skip_all,
+        fields(
+            db.statement,
+            oauth2_pushed_auth_request.id,
+            %client.id,
+        ),
+        err,
+    )]
+    async fn add(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        client: &Client,
+        parameters: BTreeMap<String, String>,
+        expires_in: Duration,
+    ) -> Result<PushedAuthorizationRequest, Self::Error> {
+        let created_at = clock.now();
+        let id = Ulid::from_datetime_with_source(created_at.into(), rng);
+        tracing::Span::current()
+            .record("oauth2_pushed_auth_request.id", tracing::field::display(id));
+
+        let expires_at = created_at + expires_in;
+
+        sqlx::query!(
+            r#"
+                INSERT INTO oauth2_pushed_auth_requests
+                    ( oauth2_pushed_auth_request_id
+                    , oauth2_client_id
+                    , parameters
+                    , created_at
+                    , expires_at
+                    )
+                VALUES ($1, $2, $3, $4, $5)
+            "#,
+            Uuid::from(id),
+            Uuid::from(client.id),
+            Json(&parameters) as _,
+            created_at,
+            expires_at,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        Ok(PushedAuthorizationRequest {
+            id,
+            client_id: client.id,
+            parameters,
+            created_at,
+            expires_at,
+        })
+    }
+
+    #[tracing::instrument(
+        name = "db.oauth2_pushed_auth_request.lookup",
+        skip_all,
+        fields(
+            db.statement,
+            oauth2_pushed_auth_request.id = %id,
+        ),
+        err,
+    )]
+    async fn lookup(
+        &mut self,
+        id: Ulid,
+    ) -> Result<Option<PushedAuthorizationRequest>, Self::Error> {
+        let res = sqlx::query_as!(
+            PushedAuthRequestLookup,
+            r#"
+                SELECT oauth2_pushed_auth_request_id
+                     , oauth2_client_id
+                     , parameters as "parameters: Json<BTreeMap<String, String>>"
+                     , created_at
+                     , expires_at
+                FROM oauth2_pushed_auth_requests
+                WHERE oauth2_pushed_auth_request_id = $1
+            "#,
+            Uuid::from(id),
+        )
+        .traced()
+        .fetch_optional(&mut *self.conn)
+        .await?;
+
+        Ok(res.map(Into::into))
+    }
+
+    #[tracing::instrument(
+        name = "db.oauth2_pushed_auth_request.cleanup_expired",
+        skip_all,
+        fields(
+            db.statement,
+        ),
+        err,
+    )]
+    async fn cleanup_expired(&mut self, clock: &dyn Clock) -> Result<usize, Self::Error> {
+        let res = sqlx::query!(
+            r#"
+                DELETE FROM oauth2_pushed_auth_requests
+                WHERE expires_at < $1
+            "#,
+            clock.now(),
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        Ok(res.rows_affected().try_into().unwrap_or(usize::MAX))
+    }
+}
@@ -42,15 +42,21 @@ struct OAuth2RefreshTokenLookup {
     refresh_token: String,
     created_at: DateTime<Utc>,
     consumed_at: Option<DateTime<Utc>>,
+    revoked_at: Option<DateTime<Utc>>,
     oauth2_access_token_id: Option<Uuid>,
     oauth2_session_id: Uuid,
+    next_oauth2_refresh_token_id: Option<Uuid>,
 }
 
 impl From<OAuth2RefreshTokenLookup> for RefreshToken {
     fn from(value: OAuth2RefreshTokenLookup) -> Self {
-        let state = match value.consumed_at {
-            None => RefreshTokenState::Valid,
-            Some(consumed_at) => RefreshTokenState::Consumed { consumed_at },
+        let state = match (value.consumed_at, value.revoked_at) {
+            (_, Some(revoked_at)) => RefreshTokenState::Revoked { revoked_at },
+            (Some(consumed_at), None) => RefreshTokenState::Consumed {
+                consumed_at,
+                next_refresh_token_id: value.next_oauth2_refresh_token_id.map(Ulid::from),
+            },
+            (None, None) => RefreshTokenState::Valid,
         };
 
         RefreshToken {
@@ -85,8 +91,10 @@ impl<'c> OAuth2RefreshTokenRepository for PgOAuth2RefreshTokenRepository<'c> {
                      , refresh_token
                      , created_at
                      , consumed_at
+                     , revoked_at
                      , oauth2_access_token_id
                      , oauth2_session_id
+                     , next_oauth2_refresh_token_id
                 FROM oauth2_refresh_tokens
 
                 WHERE oauth2_refresh_token_id = $1
@@ -120,8 +128,10 @@ impl<'c> OAuth2RefreshTokenRepository for PgOAuth2RefreshTokenRepository<'c> {
                      , refresh_token
                      , created_at
                      , consumed_at
+                     , revoked_at
                      , oauth2_access_token_id
                      , oauth2_session_id
+                     , next_oauth2_refresh_token_id
                 FROM oauth2_refresh_tokens
 
                 WHERE refresh_token = $1
@@ -202,16 +212,19 @@ impl<'c> OAuth2RefreshTokenRepository for PgOAuth2RefreshTokenRepository<'c> {
         &mut self,
         clock: &dyn Clock,
         refresh_token: RefreshToken,
+        replaced_by: &RefreshToken,
     ) -> Result<RefreshToken, Self::Error> {
         let consumed_at = clock.now();
         let res = sqlx::query!(
             r#"
                 UPDATE oauth2_refresh_tokens
                 SET consumed_at = $2
+                  , next_oauth2_refresh_token_id = $3
                 WHERE oauth2_refresh_token_id = $1
             "#,
             Uuid::from(refresh_token.id),
             consumed_at,
+            Uuid::from(replaced_by.id),
         )
         .execute(&mut *self.conn)
         .await?;
@@ -219,7 +232,43 @@ impl<'c> OAuth2RefreshTokenRepository for PgOAuth2RefreshTokenRepository<'c> {
         DatabaseError::ensure_affected_rows(&res, 1)?;
 
         refresh_token
-            .consume(consumed_at)
+            .consume(consumed_at, replaced_by)
+            .map_err(DatabaseError::to_invalid_operation)
+    }
+
+    #[tracing::instrument(
+        name = "db.oauth2_refresh_token.revoke",
+        skip_all,
+        fields(
+            db.statement,
+            %refresh_token.id,
+            session.id = %refresh_token.session_id,
+        ),
+        err,
+    )]
+    async fn revoke(
+        &mut self,
+        clock: &dyn Clock,
+        refresh_token: RefreshToken,
+    ) -> Result<RefreshToken, Self::Error> {
+        let revoked_at = clock.now();
+        let res = sqlx::query!(
+            r#"
+                UPDATE oauth2_refresh_tokens
+                SET revoked_at = $2
+                WHERE oauth2_refresh_token_id = $1
+            "#,
+            Uuid::from(refresh_token.id),
+            revoked_at,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        refresh_token
+            .revoke(revoked_at)
             .map_err(DatabaseError::to_invalid_operation)
     }
 }
@@ -19,14 +19,16 @@ use chrono::{DateTime, Utc};
 use mas_data_model::{BrowserSession, Client, Session, SessionState, User, UserAgent};
 use mas_storage::{
     oauth2::{OAuth2SessionFilter, OAuth2SessionRepository},
+    pagination::PaginationOrderBy,
     Clock, Page, Pagination,
 };
 use oauth2_types::scope::{Scope, ScopeToken};
 use rand::RngCore;
 use sea_query::{enum_def, extension::postgres::PgExpr, Expr, PostgresQueryBuilder, Query};
 use sea_query_binder::SqlxBinder;
-use sqlx::PgConnection;
+use sqlx::{types::Json, PgConnection};
 use ulid::Ulid;
+use url::Url;
 use uuid::Uuid;
 
 use crate::{
@@ -62,6 +64,9 @@ struct OAuthSessionLookup {
     user_agent: Option<String>,
     last_active_at: Option<DateTime<Utc>>,
     last_active_ip: Option<IpAddr>,
+    auth_time: Option<DateTime<Utc>>,
+    resource: Option<Json<Vec<Url>>>,
+    dpop_jkt: Option<String>,
 }
 
 impl TryFrom<OAuthSessionLookup> for Session {
@@ -97,6 +102,9 @@ impl TryFrom<OAuthSessionLookup> for Session {
             user_agent: value.user_agent.map(UserAgent::parse),
             last_active_at: value.last_active_at,
             last_active_ip: value.last_active_ip,
+            auth_time: value.auth_time,
+            resource: value.resource.map(|Json(x)| x).unwrap_or_default(),
+            dpop_jkt: value.dpop_jkt,
         })
     }
 }
@@ -128,6 +136,9 @@ impl<'c> OAuth2SessionRepository for PgOAuth2SessionRepository<'c> {
                      , user_agent
                      , last_active_at
                      , last_active_ip as "last_active_ip: IpAddr"
+                     , auth_time
+                     , resource as "resource: Json<Vec<Url>>"
+                     , dpop_jkt
                 FROM oauth2_sessions
 
                 WHERE oauth2_session_id = $1
@@ -203,6 +214,9 @@ impl<'c> OAuth2SessionRepository for PgOAuth2SessionRepository<'c> {
             user_agent: None,
             last_active_at: None,
             last_active_ip: None,
+            auth_time: None,
+            resource: Vec::new(),
+            dpop_jkt: None,
         })
     }
 
@@ -297,10 +311,26 @@ impl<'c> OAuth2SessionRepository for PgOAuth2SessionRepository<'c> {
                 Expr::col((OAuth2Sessions::Table, OAuth2Sessions::LastActiveIp)),
                 OAuthSessionLookupIden::LastActiveIp,
             )
+            .expr_as(
+                Expr::col((OAuth2Sessions::Table, OAuth2Sessions::AuthTime)),
+                OAuthSessionLookupIden::AuthTime,
+            )
+            .expr_as(
+                Expr::col((OAuth2Sessions::Table, OAuth2Sessions::Resource)),
+                OAuthSessionLookupIden::Resource,
+            )
+            .expr_as(
+                Expr::col((OAuth2Sessions::Table, OAuth2Sessions::DpopJkt)),
+                OAuthSessionLookupIden::DpopJkt,
+            )
             .from(OAuth2Sessions::Table)
             .and_where_option(filter.user().map(|user| {
                 Expr::col((OAuth2Sessions::Table, OAuth2Sessions::UserId)).eq(Uuid::from(user.id))
             }))
+            .and_where_option(filter.browser_session().map(|browser_session| {
+                Expr::col((OAuth2Sessions::Table, OAuth2Sessions::UserSessionId))
+                    .eq(Uuid::from(browser_session.id))
+            }))
             .and_where_option(filter.client().map(|client| {
                 Expr::col((OAuth2Sessions::Table, OAuth2Sessions::OAuth2ClientId))
                     .eq(Uuid::from(client.id))
@@ -316,8 +346,17 @@ impl<'c> OAuth2SessionRepository for PgOAuth2SessionRepository<'c> {
                 let scope: Vec<String> = scope.iter().map(|s| s.as_str().to_owned()).collect();
                 Expr::col((OAuth2Sessions::Table, OAuth2Sessions::ScopeList)).contains(scope)
             }))
-            .generate_pagination(
+            .generate_ordered_pagination(
                 (OAuth2Sessions::Table, OAuth2Sessions::OAuth2SessionId),
+                match pagination.order_by {
+                    PaginationOrderBy::Id => None,
+                    PaginationOrderBy::CreatedAt => {
+                        Some((OAuth2Sessions::Table, OAuth2Sessions::CreatedAt))
+                    }
+                    PaginationOrderBy::FinishedAt => {
+                        Some((OAuth2Sessions::Table, OAuth2Sessions::FinishedAt))
+                    }
+                },
                 pagination,
             )
             .build_sqlx(PostgresQueryBuilder);
@@ -362,6 +401,10 @@ impl<'c> OAuth2SessionRepository for PgOAuth2SessionRepository<'c> {
                         .take(),
                 )
             }))
+            .and_where_option(filter.browser_session().map(|browser_session| {
+                Expr::col((OAuth2Sessions::Table, OAuth2Sessions::UserSessionId))
+                    .eq(Uuid::from(browser_session.id))
+            }))
             .and_where_option(filter.client().map(|client| {
                 Expr::col((OAuth2Sessions::Table, OAuth2Sessions::OAuth2ClientId))
                     .eq(Uuid::from(client.id))
@@ -472,4 +515,111 @@ impl<'c> OAuth2SessionRepository for PgOAuth2SessionRepository<'c> {
 
         Ok(session)
     }
+
+    #[tracing::instrument(
+        name = "db.oauth2_session.record_auth_time",
+        skip_all,
+        fields(
+            db.statement,
+            %session.id,
+            %session.scope,
+            client.id = %session.client_id,
+            session.auth_time = %auth_time,
+        ),
+        err,
+    )]
+    async fn record_auth_time(
+        &mut self,
+        mut session: Session,
+        auth_time: DateTime<Utc>,
+    ) -> Result<Session, Self::Error> {
+        let res = sqlx::query!(
+            r#"
+                UPDATE oauth2_sessions
+                SET auth_time = $2
+                WHERE oauth2_session_id = $1
+            "#,
+            Uuid::from(session.id),
+            auth_time,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        session.auth_time = Some(auth_time);
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        Ok(session)
+    }
+
+    #[tracing::instrument(
+        name = "db.oauth2_session.record_resource",
+        skip_all,
+        fields(
+            db.statement,
+            %session.id,
+            %session.scope,
+            client.id = %session.client_id,
+        ),
+        err,
+    )]
+    async fn record_resource(
+        &mut self,
+        mut session: Session,
+        resource: Vec<Url>,
+    ) -> Result<Session, Self::Error> {
+        let res = sqlx::query!(
+            r#"
+                UPDATE oauth2_sessions
+                SET resource = $2
+                WHERE oauth2_session_id = $1
+            "#,
+            Uuid::from(session.id),
+            Json(&resource) as _,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        session.resource = resource;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        Ok(session)
+    }
+
+    #[tracing::instrument(
+        name = "db.oauth2_session.bind_dpop_key",
+        skip_all,
+        fields(
+            db.statement,
+            %session.id,
+            session.dpop_jkt = %jkt,
+        ),
+        err,
+    )]
+    async fn bind_dpop_key(
+        &mut self,
+        mut session: Session,
+        jkt: String,
+    ) -> Result<Session, Self::Error> {
+        let res = sqlx::query!(
+            r#"
+                UPDATE oauth2_sessions
+                SET dpop_jkt = $2
+                WHERE oauth2_session_id = $1
+            "#,
+            Uuid::from(session.id),
+            &jkt,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        session.dpop_jkt = Some(jkt);
+        Ok(session)
+    }
 }
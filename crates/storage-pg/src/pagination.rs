@@ -14,10 +14,17 @@
 
 //! Utilities to manage paginated queries.
 
-use mas_storage::{pagination::PaginationDirection, Pagination};
-use sea_query::IntoColumnRef;
+use mas_storage::{
+    pagination::{PaginationCursor, PaginationDirection, PaginationOrder},
+    Pagination,
+};
+use sea_query::{ColumnRef, Expr, Func, IntoColumnRef, Order, SimpleExpr};
 use uuid::Uuid;
 
+/// The value `NULL`s in the ordering column are replaced with, so that they
+/// are sorted after all other values
+const NULL_ORDER_VALUE: &str = "'infinity'::timestamptz";
+
 /// An extension trait to the `sqlx` [`QueryBuilder`], to help adding pagination
 /// to a query
 pub trait QueryBuilderExt {
@@ -28,6 +35,25 @@ pub trait QueryBuilderExt {
         column: C,
         pagination: Pagination,
     ) -> &mut Self;
+
+    /// Add cursor-based pagination to a query, ordering the items by the
+    /// given column first and then by ID, so that the order stays stable
+    /// when the column has duplicate values.
+    ///
+    /// The ordering column must be a timestamp, and its `NULL` values are
+    /// sorted after all others. If no ordering column is given, the items are
+    /// only ordered by ID.
+    fn generate_ordered_pagination<C: IntoColumnRef, O: IntoColumnRef>(
+        &mut self,
+        id_column: C,
+        order_column: Option<O>,
+        pagination: Pagination,
+    ) -> &mut Self;
+}
+
+/// The value the items are sorted on for the given ordering column
+fn order_value(order_field: ColumnRef) -> SimpleExpr {
+    Func::coalesce([Expr::col(order_field).into(), Expr::cust(NULL_ORDER_VALUE)]).into()
 }
 
 impl QueryBuilderExt for sea_query::SelectStatement {
@@ -36,7 +62,44 @@ impl QueryBuilderExt for sea_query::SelectStatement {
         column: C,
         pagination: Pagination,
     ) -> &mut Self {
-        let id_field = column.into_column_ref();
+        self.generate_ordered_pagination(column, None::<C>, pagination)
+    }
+
+    fn generate_ordered_pagination<C: IntoColumnRef, O: IntoColumnRef>(
+        &mut self,
+        id_column: C,
+        order_column: Option<O>,
+        pagination: Pagination,
+    ) -> &mut Self {
+        let id_field = id_column.into_column_ref();
+        let order_field = order_column.map(IntoColumnRef::into_column_ref);
+
+        // The items are compared on the (value, id) tuple when ordering by a
+        // column, and on the id alone otherwise
+        let key = match &order_field {
+            Some(order_field) => Expr::tuple([
+                order_value(order_field.clone()),
+                Expr::col(id_field.clone()).into(),
+            ]),
+            None => Expr::col(id_field.clone()),
+        };
+
+        let cursor_key = |cursor: PaginationCursor| -> SimpleExpr {
+            let id: SimpleExpr = Expr::val(Uuid::from(cursor.id)).into();
+            if order_field.is_some() {
+                let value = cursor.value.map_or_else(
+                    || Expr::cust(NULL_ORDER_VALUE),
+                    |value| Expr::val(value).into(),
+                );
+                Expr::tuple([value, id]).into()
+            } else {
+                id
+            }
+        };
+
+        // When sorting in descending order, the items after the cursor are the
+        // ones with a lower key
+        let descending = pagination.order == PaginationOrder::Descending;
 
         // ref: https://github.com/graphql/graphql-relay-js/issues/94#issuecomment-232410564
         // 1. Start from the greedy query: SELECT * FROM table
@@ -44,30 +107,46 @@ impl QueryBuilderExt for sea_query::SelectStatement {
         // 2. If the after argument is provided, add `id > parsed_cursor` to the `WHERE`
         // clause
         if let Some(after) = pagination.after {
-            self.and_where(sea_query::Expr::col(id_field.clone()).gt(Uuid::from(after)));
+            let cursor = cursor_key(after);
+            self.and_where(if descending {
+                key.clone().lt(cursor)
+            } else {
+                key.clone().gt(cursor)
+            });
         }
 
         // 3. If the before argument is provided, add `id < parsed_cursor` to the
         // `WHERE` clause
         if let Some(before) = pagination.before {
-            self.and_where(sea_query::Expr::col(id_field.clone()).lt(Uuid::from(before)));
+            let cursor = cursor_key(before);
+            self.and_where(if descending {
+                key.gt(cursor)
+            } else {
+                key.lt(cursor)
+            });
         }
 
-        match pagination.direction {
-            // 4. If the first argument is provided, add `ORDER BY id ASC LIMIT first+1` to the
-            // query
-            PaginationDirection::Forward => {
-                self.order_by(id_field, sea_query::Order::Asc)
-                    .limit((pagination.count + 1) as u64);
+        // 4. If the first argument is provided, add `ORDER BY id ASC LIMIT first+1` to the
+        // query
+        // 5. If the last argument is provided, add `ORDER BY id DESC LIMIT last+1` to the
+        // query
+        // Both are swapped when sorting in descending order
+        let order = match (pagination.direction, descending) {
+            (PaginationDirection::Forward, false) | (PaginationDirection::Backward, true) => {
+                Order::Asc
             }
-            // 5. If the first argument is provided, add `ORDER BY id DESC LIMIT last+1` to the
-            // query
-            PaginationDirection::Backward => {
-                self.order_by(id_field, sea_query::Order::Desc)
-                    .limit((pagination.count + 1) as u64);
+            (PaginationDirection::Forward, true) | (PaginationDirection::Backward, false) => {
+                Order::Desc
             }
         };
 
+        if let Some(order_field) = order_field {
+            self.order_by_expr(order_value(order_field), order.clone());
+        }
+
+        self.order_by(id_field, order)
+            .limit((pagination.count + 1) as u64);
+
         self
     }
 }
@@ -17,6 +17,7 @@ use std::ops::{Deref, DerefMut};
 use futures_util::{future::BoxFuture, FutureExt, TryFutureExt};
 use mas_storage::{
     app_session::AppSessionRepository,
+    audit_log::AuditLogRepository,
     compat::{
         CompatAccessTokenRepository, CompatRefreshTokenRepository, CompatSessionRepository,
         CompatSsoLoginRepository,
@@ -24,7 +25,8 @@ use mas_storage::{
     job::JobRepository,
     oauth2::{
         OAuth2AccessTokenRepository, OAuth2AuthorizationGrantRepository, OAuth2ClientRepository,
-        OAuth2DeviceCodeGrantRepository, OAuth2RefreshTokenRepository, OAuth2SessionRepository,
+        OAuth2ConsentRepository, OAuth2DeviceCodeGrantRepository,
+        OAuth2PushedAuthRequestRepository, OAuth2RefreshTokenRepository, OAuth2SessionRepository,
     },
     upstream_oauth2::{
         UpstreamOAuthLinkRepository, UpstreamOAuthProviderRepository,
@@ -38,6 +40,7 @@ use tracing::Instrument;
 
 use crate::{
     app_session::PgAppSessionRepository,
+    audit_log::PgAuditLogRepository,
     compat::{
         PgCompatAccessTokenRepository, PgCompatRefreshTokenRepository, PgCompatSessionRepository,
         PgCompatSsoLoginRepository,
@@ -45,8 +48,9 @@ use crate::{
     job::PgJobRepository,
     oauth2::{
         PgOAuth2AccessTokenRepository, PgOAuth2AuthorizationGrantRepository,
-        PgOAuth2ClientRepository, PgOAuth2DeviceCodeGrantRepository,
-        PgOAuth2RefreshTokenRepository, PgOAuth2SessionRepository,
+        PgOAuth2ClientRepository, PgOAuth2ConsentRepository, PgOAuth2DeviceCodeGrantRepository,
+        PgOAuth2PushedAuthRequestRepository, PgOAuth2RefreshTokenRepository,
+        PgOAuth2SessionRepository,
     },
     upstream_oauth2::{
         PgUpstreamOAuthLinkRepository, PgUpstreamOAuthProviderRepository,
@@ -54,7 +58,8 @@ use crate::{
     },
     user::{
         PgBrowserSessionRepository, PgUserEmailRepository, PgUserPasswordRepository,
-        PgUserRepository, PgUserTermsRepository,
+        PgUserPasswordResetTokenRepository, PgUserRecoveryCodeRepository, PgUserRepository,
+        PgUserTermsRepository, PgUserTotpRepository, PgUserWebAuthnRepository,
     },
     DatabaseError,
 };
@@ -185,6 +190,31 @@ where
         Box::new(PgUserTermsRepository::new(self.conn.as_mut()))
     }
 
+    fn user_webauthn<'c>(
+        &'c mut self,
+    ) -> Box<dyn mas_storage::user::UserWebAuthnRepository<Error = Self::Error> + 'c> {
+        Box::new(PgUserWebAuthnRepository::new(self.conn.as_mut()))
+    }
+
+    fn user_totp<'c>(
+        &'c mut self,
+    ) -> Box<dyn mas_storage::user::UserTotpRepository<Error = Self::Error> + 'c> {
+        Box::new(PgUserTotpRepository::new(self.conn.as_mut()))
+    }
+
+    fn user_recovery_code<'c>(
+        &'c mut self,
+    ) -> Box<dyn mas_storage::user::UserRecoveryCodeRepository<Error = Self::Error> + 'c> {
+        Box::new(PgUserRecoveryCodeRepository::new(self.conn.as_mut()))
+    }
+
+    fn user_password_reset_token<'c>(
+        &'c mut self,
+    ) -> Box<dyn mas_storage::user::UserPasswordResetTokenRepository<Error = Self::Error> + 'c>
+    {
+        Box::new(PgUserPasswordResetTokenRepository::new(self.conn.as_mut()))
+    }
+
     fn browser_session<'c>(
         &'c mut self,
     ) -> Box<dyn BrowserSessionRepository<Error = Self::Error> + 'c> {
@@ -195,12 +225,22 @@ where
         Box::new(PgAppSessionRepository::new(self.conn.as_mut()))
     }
 
+    fn audit_log<'c>(&'c mut self) -> Box<dyn AuditLogRepository<Error = Self::Error> + 'c> {
+        Box::new(PgAuditLogRepository::new(self.conn.as_mut()))
+    }
+
     fn oauth2_client<'c>(
         &'c mut self,
     ) -> Box<dyn OAuth2ClientRepository<Error = Self::Error> + 'c> {
         Box::new(PgOAuth2ClientRepository::new(self.conn.as_mut()))
     }
 
+    fn oauth2_consent<'c>(
+        &'c mut self,
+    ) -> Box<dyn OAuth2ConsentRepository<Error = Self::Error> + 'c> {
+        Box::new(PgOAuth2ConsentRepository::new(self.conn.as_mut()))
+    }
+
     fn oauth2_authorization_grant<'c>(
         &'c mut self,
     ) -> Box<dyn OAuth2AuthorizationGrantRepository<Error = Self::Error> + 'c> {
@@ -233,6 +273,12 @@ where
         Box::new(PgOAuth2DeviceCodeGrantRepository::new(self.conn.as_mut()))
     }
 
+    fn oauth2_pushed_auth_request<'c>(
+        &'c mut self,
+    ) -> Box<dyn OAuth2PushedAuthRequestRepository<Error = Self::Error> + 'c> {
+        Box::new(PgOAuth2PushedAuthRequestRepository::new(self.conn.as_mut()))
+    }
+
     fn compat_session<'c>(
         &'c mut self,
     ) -> Box<dyn CompatSessionRepository<Error = Self::Error> + 'c> {
@@ -77,6 +77,7 @@ mod tests {
                     discovery_mode: mas_data_model::UpstreamOAuthProviderDiscoveryMode::Oidc,
                     pkce_mode: mas_data_model::UpstreamOAuthProviderPkceMode::Auto,
                     additional_authorization_parameters: Vec::new(),
+                    forward_login_hint: false,
                 },
             )
             .await
@@ -319,6 +320,7 @@ mod tests {
                         discovery_mode: mas_data_model::UpstreamOAuthProviderDiscoveryMode::Oidc,
                         pkce_mode: mas_data_model::UpstreamOAuthProviderPkceMode::Auto,
                         additional_authorization_parameters: Vec::new(),
+                        forward_login_hint: false,
                     },
                 )
                 .await
@@ -70,6 +70,7 @@ struct ProviderLookup {
     discovery_mode: String,
     pkce_mode: String,
     additional_parameters: Option<Json<Vec<(String, String)>>>,
+    forward_login_hint: bool,
 }
 
 impl TryFrom<ProviderLookup> for UpstreamOAuthProvider {
@@ -170,6 +171,7 @@ impl TryFrom<ProviderLookup> for UpstreamOAuthProvider {
             discovery_mode,
             pkce_mode,
             additional_authorization_parameters,
+            forward_login_hint: value.forward_login_hint,
         })
     }
 }
@@ -209,7 +211,8 @@ impl<'c> UpstreamOAuthProviderRepository for PgUpstreamOAuthProviderRepository<'
                     token_endpoint_override,
                     discovery_mode,
                     pkce_mode,
-                    additional_parameters as "additional_parameters: Json<Vec<(String, String)>>"
+                    additional_parameters as "additional_parameters: Json<Vec<(String, String)>>",
+                    forward_login_hint
                 FROM upstream_oauth_providers
                 WHERE upstream_oauth_provider_id = $1
             "#,
@@ -266,9 +269,10 @@ impl<'c> UpstreamOAuthProviderRepository for PgUpstreamOAuthProviderRepository<'
                 jwks_uri_override,
                 discovery_mode,
                 pkce_mode,
+                forward_login_hint,
                 created_at
             ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9,
-                      $10, $11, $12, $13, $14, $15, $16)
+                      $10, $11, $12, $13, $14, $15, $16, $17)
         "#,
             Uuid::from(id),
             &params.issuer,
@@ -294,6 +298,7 @@ impl<'c> UpstreamOAuthProviderRepository for PgUpstreamOAuthProviderRepository<'
             params.jwks_uri_override.as_ref().map(ToString::to_string),
             params.discovery_mode.as_str(),
             params.pkce_mode.as_str(),
+            params.forward_login_hint,
             created_at,
         )
         .traced()
@@ -319,6 +324,7 @@ impl<'c> UpstreamOAuthProviderRepository for PgUpstreamOAuthProviderRepository<'
             discovery_mode: params.discovery_mode,
             pkce_mode: params.pkce_mode,
             additional_authorization_parameters: params.additional_authorization_parameters,
+            forward_login_hint: params.forward_login_hint,
         })
     }
 
@@ -426,9 +432,10 @@ impl<'c> UpstreamOAuthProviderRepository for PgUpstreamOAuthProviderRepository<'
                     discovery_mode,
                     pkce_mode,
                     additional_parameters,
+                    forward_login_hint,
                     created_at
                 ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9,
-                          $10, $11, $12, $13, $14, $15, $16, $17)
+                          $10, $11, $12, $13, $14, $15, $16, $17, $18)
                 ON CONFLICT (upstream_oauth_provider_id) 
                     DO UPDATE
                     SET
@@ -447,7 +454,8 @@ impl<'c> UpstreamOAuthProviderRepository for PgUpstreamOAuthProviderRepository<'
                         jwks_uri_override = EXCLUDED.jwks_uri_override,
                         discovery_mode = EXCLUDED.discovery_mode,
                         pkce_mode = EXCLUDED.pkce_mode,
-                        additional_parameters = EXCLUDED.additional_parameters
+                        additional_parameters = EXCLUDED.additional_parameters,
+                        forward_login_hint = EXCLUDED.forward_login_hint
                 RETURNING created_at
             "#,
             Uuid::from(id),
@@ -475,6 +483,7 @@ impl<'c> UpstreamOAuthProviderRepository for PgUpstreamOAuthProviderRepository<'
             params.discovery_mode.as_str(),
             params.pkce_mode.as_str(),
             Json(&params.additional_authorization_parameters) as _,
+            params.forward_login_hint,
             created_at,
         )
         .traced()
@@ -500,6 +509,7 @@ impl<'c> UpstreamOAuthProviderRepository for PgUpstreamOAuthProviderRepository<'
             discovery_mode: params.discovery_mode,
             pkce_mode: params.pkce_mode,
             additional_authorization_parameters: params.additional_authorization_parameters,
+            forward_login_hint: params.forward_login_hint,
         })
     }
 
@@ -538,6 +548,40 @@ impl<'c> UpstreamOAuthProviderRepository for PgUpstreamOAuthProviderRepository<'
         Ok(upstream_oauth_provider)
     }
 
+    #[tracing::instrument(
+        name = "db.upstream_oauth_provider.set_encrypted_client_secret",
+        skip_all,
+        fields(
+            db.statement,
+            %upstream_oauth_provider.id,
+        ),
+        err,
+    )]
+    async fn set_encrypted_client_secret(
+        &mut self,
+        mut upstream_oauth_provider: UpstreamOAuthProvider,
+        encrypted_client_secret: Option<String>,
+    ) -> Result<UpstreamOAuthProvider, Self::Error> {
+        let res = sqlx::query!(
+            r#"
+                UPDATE upstream_oauth_providers
+                SET encrypted_client_secret = $2
+                WHERE upstream_oauth_provider_id = $1
+            "#,
+            Uuid::from(upstream_oauth_provider.id),
+            encrypted_client_secret.as_deref(),
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        upstream_oauth_provider.encrypted_client_secret = encrypted_client_secret;
+
+        Ok(upstream_oauth_provider)
+    }
+
     #[tracing::instrument(
         name = "db.upstream_oauth_provider.list",
         skip_all,
@@ -675,6 +719,13 @@ impl<'c> UpstreamOAuthProviderRepository for PgUpstreamOAuthProviderRepository<'
                 )),
                 ProviderLookupIden::AdditionalParameters,
             )
+            .expr_as(
+                Expr::col((
+                    UpstreamOAuthProviders::Table,
+                    UpstreamOAuthProviders::ForwardLoginHint,
+                )),
+                ProviderLookupIden::ForwardLoginHint,
+            )
             .from(UpstreamOAuthProviders::Table)
             .and_where_option(filter.enabled().map(|enabled| {
                 Expr::col((
@@ -776,7 +827,8 @@ impl<'c> UpstreamOAuthProviderRepository for PgUpstreamOAuthProviderRepository<'
                     token_endpoint_override,
                     discovery_mode,
                     pkce_mode,
-                    additional_parameters as "additional_parameters: Json<Vec<(String, String)>>"
+                    additional_parameters as "additional_parameters: Json<Vec<(String, String)>>",
+                    forward_login_hint
                 FROM upstream_oauth_providers
                 WHERE disabled_at IS NULL
             "#,
@@ -21,9 +21,10 @@ use mas_storage::{
 };
 use opentelemetry_semantic_conventions::trace::DB_STATEMENT;
 use rand::RngCore;
-use sea_query::{enum_def, Expr, PostgresQueryBuilder, Query};
+use sea_query::{enum_def, Expr, Func, PostgresQueryBuilder, Query};
 use sea_query_binder::SqlxBinder;
 use sqlx::PgConnection;
+use subtle::ConstantTimeEq;
 use tracing::{info_span, Instrument};
 use ulid::Ulid;
 use uuid::Uuid;
@@ -82,7 +83,7 @@ impl UserEmailConfirmationCodeLookup {
         let now = clock.now();
         let state = if let Some(when) = self.consumed_at {
             UserEmailVerificationState::AlreadyUsed { when }
-        } else if self.expires_at < now {
+        } else if self.expires_at <= now {
             UserEmailVerificationState::Expired {
                 when: self.expires_at,
             }
@@ -270,6 +271,13 @@ impl<'c> UserEmailRepository for PgUserEmailRepository<'c> {
             .and_where_option(filter.user().map(|user| {
                 Expr::col((UserEmails::Table, UserEmails::UserId)).eq(Uuid::from(user.id))
             }))
+            .and_where_option(filter.email().map(|email| {
+                Expr::expr(Func::lower(Expr::col((
+                    UserEmails::Table,
+                    UserEmails::Email,
+                ))))
+                .eq(email.to_lowercase())
+            }))
             .and_where_option(filter.state().map(|state| {
                 if state.is_verified() {
                     Expr::col((UserEmails::Table, UserEmails::ConfirmedAt)).is_not_null()
@@ -305,6 +313,13 @@ impl<'c> UserEmailRepository for PgUserEmailRepository<'c> {
             .and_where_option(filter.user().map(|user| {
                 Expr::col((UserEmails::Table, UserEmails::UserId)).eq(Uuid::from(user.id))
             }))
+            .and_where_option(filter.email().map(|email| {
+                Expr::expr(Func::lower(Expr::col((
+                    UserEmails::Table,
+                    UserEmails::Email,
+                ))))
+                .eq(email.to_lowercase())
+            }))
             .and_where_option(filter.state().map(|state| {
                 if state.is_verified() {
                     Expr::col((UserEmails::Table, UserEmails::ConfirmedAt)).is_not_null()
@@ -530,13 +545,62 @@ impl<'c> UserEmailRepository for PgUserEmailRepository<'c> {
                      , expires_at
                      , consumed_at
                 FROM user_email_confirmation_codes
-                WHERE code = $1
-                  AND user_email_id = $2
+                WHERE user_email_id = $1
             "#,
-            code,
             Uuid::from(user_email.id),
         )
         .traced()
+        .fetch_all(&mut *self.conn)
+        .await?;
+
+        // The codes are compared in constant time, so that the time it takes to
+        // answer doesn't leak how much of a code was right
+        let mut found = None;
+        for candidate in res {
+            if bool::from(candidate.code.as_bytes().ct_eq(code.as_bytes())) {
+                found = Some(candidate);
+            }
+        }
+
+        Ok(found.map(|res| res.into_verification(clock)))
+    }
+
+    #[tracing::instrument(
+        name = "db.user_email.find_latest_verification_code",
+        skip_all,
+        fields(
+            db.statement,
+            %user_email.id,
+            user.id = %user_email.user_id,
+        ),
+        err,
+    )]
+    async fn find_latest_verification_code(
+        &mut self,
+        clock: &dyn Clock,
+        user_email: &UserEmail,
+    ) -> Result<Option<UserEmailVerification>, Self::Error> {
+        let res = sqlx::query_as!(
+            UserEmailConfirmationCodeLookup,
+            r#"
+                SELECT user_email_confirmation_code_id
+                     , user_email_id
+                     , code
+                     , created_at
+                     , expires_at
+                     , consumed_at
+                FROM user_email_confirmation_codes
+                WHERE user_email_id IN (
+                    SELECT user_email_id
+                    FROM user_emails
+                    WHERE email = $1
+                )
+                ORDER BY created_at DESC
+                LIMIT 1
+            "#,
+            &user_email.email,
+        )
+        .traced()
         .fetch_optional(&mut *self.conn)
         .await?;
 
@@ -545,6 +609,48 @@ impl<'c> UserEmailRepository for PgUserEmailRepository<'c> {
         Ok(Some(res.into_verification(clock)))
     }
 
+    #[tracing::instrument(
+        name = "db.user_email.record_failed_verification_attempt",
+        skip_all,
+        fields(
+            db.statement,
+            %user_email.id,
+            user.id = %user_email.user_id,
+        ),
+        err,
+    )]
+    async fn record_failed_verification_attempt(
+        &mut self,
+        clock: &dyn Clock,
+        user_email: &UserEmail,
+    ) -> Result<(), Self::Error> {
+        let now = clock.now();
+        let max_attempts =
+            i32::try_from(UserEmailVerification::MAX_FAILED_ATTEMPTS).unwrap_or(i32::MAX);
+
+        sqlx::query!(
+            r#"
+                UPDATE user_email_confirmation_codes
+                SET failed_attempts = failed_attempts + 1
+                  , expires_at = CASE
+                        WHEN failed_attempts + 1 >= $3 THEN $2
+                        ELSE expires_at
+                    END
+                WHERE user_email_id = $1
+                  AND consumed_at IS NULL
+                  AND expires_at > $2
+            "#,
+            Uuid::from(user_email.id),
+            now,
+            max_attempts,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        Ok(())
+    }
+
     #[tracing::instrument(
         name = "db.user_email.consume_verification_code",
         skip_all,
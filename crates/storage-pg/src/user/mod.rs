@@ -18,25 +18,36 @@
 use async_trait::async_trait;
 use chrono::{DateTime, Utc};
 use mas_data_model::User;
-use mas_storage::{user::UserRepository, Clock};
+use mas_storage::{
+    user::{UserFilter, UserRepository},
+    Clock, Page, Pagination,
+};
 use rand::RngCore;
+use sea_query::{Expr, LikeExpr, PostgresQueryBuilder, Query};
+use sea_query_binder::SqlxBinder;
 use sqlx::PgConnection;
 use ulid::Ulid;
 use uuid::Uuid;
 
-use crate::{tracing::ExecuteExt, DatabaseError};
+use crate::{iden::Users, pagination::QueryBuilderExt, tracing::ExecuteExt, DatabaseError};
 
 mod email;
 mod password;
+mod password_reset;
+mod recovery_code;
 mod session;
 mod terms;
+mod totp;
+mod webauthn;
 
 #[cfg(test)]
 mod tests;
 
 pub use self::{
     email::PgUserEmailRepository, password::PgUserPasswordRepository,
-    session::PgBrowserSessionRepository, terms::PgUserTermsRepository,
+    password_reset::PgUserPasswordResetTokenRepository,
+    recovery_code::PgUserRecoveryCodeRepository, session::PgBrowserSessionRepository,
+    terms::PgUserTermsRepository, totp::PgUserTotpRepository, webauthn::PgUserWebAuthnRepository,
 };
 
 /// An implementation of [`UserRepository`] for a PostgreSQL connection
@@ -51,14 +62,17 @@ impl<'c> PgUserRepository<'c> {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, sqlx::FromRow)]
+#[sea_query::enum_def]
 struct UserLookup {
     user_id: Uuid,
     username: String,
     primary_user_email_id: Option<Uuid>,
     created_at: DateTime<Utc>,
     locked_at: Option<DateTime<Utc>>,
+    deactivated_at: Option<DateTime<Utc>>,
     can_request_admin: bool,
+    is_admin: bool,
 }
 
 impl From<UserLookup> for User {
@@ -71,7 +85,9 @@ impl From<UserLookup> for User {
             primary_user_email_id: value.primary_user_email_id.map(Into::into),
             created_at: value.created_at,
             locked_at: value.locked_at,
+            deactivated_at: value.deactivated_at,
             can_request_admin: value.can_request_admin,
+            is_admin: value.is_admin,
         }
     }
 }
@@ -98,7 +114,9 @@ impl<'c> UserRepository for PgUserRepository<'c> {
                      , primary_user_email_id
                      , created_at
                      , locked_at
+                     , deactivated_at
                      , can_request_admin
+                     , is_admin
                 FROM users
                 WHERE user_id = $1
             "#,
@@ -131,7 +149,9 @@ impl<'c> UserRepository for PgUserRepository<'c> {
                      , primary_user_email_id
                      , created_at
                      , locked_at
+                     , deactivated_at
                      , can_request_admin
+                     , is_admin
                 FROM users
                 WHERE username = $1
             "#,
@@ -191,7 +211,9 @@ impl<'c> UserRepository for PgUserRepository<'c> {
             primary_user_email_id: None,
             created_at,
             locked_at: None,
+            deactivated_at: None,
             can_request_admin: false,
+            is_admin: false,
         })
     }
 
@@ -288,6 +310,74 @@ impl<'c> UserRepository for PgUserRepository<'c> {
         Ok(user)
     }
 
+    #[tracing::instrument(
+        name = "db.user.deactivate",
+        skip_all,
+        fields(
+            db.statement,
+            %user.id,
+        ),
+        err,
+    )]
+    async fn deactivate(&mut self, clock: &dyn Clock, mut user: User) -> Result<User, Self::Error> {
+        if user.deactivated_at.is_some() {
+            return Ok(user);
+        }
+
+        let deactivated_at = clock.now();
+        let res = sqlx::query!(
+            r#"
+                UPDATE users
+                SET deactivated_at = $1
+                WHERE user_id = $2
+            "#,
+            deactivated_at,
+            Uuid::from(user.id),
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        user.deactivated_at = Some(deactivated_at);
+
+        Ok(user)
+    }
+
+    #[tracing::instrument(
+        name = "db.user.reactivate",
+        skip_all,
+        fields(
+            db.statement,
+            %user.id,
+        ),
+        err,
+    )]
+    async fn reactivate(&mut self, mut user: User) -> Result<User, Self::Error> {
+        if user.deactivated_at.is_none() {
+            return Ok(user);
+        }
+
+        let res = sqlx::query!(
+            r#"
+                UPDATE users
+                SET deactivated_at = NULL
+                WHERE user_id = $1
+            "#,
+            Uuid::from(user.id),
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        user.deactivated_at = None;
+
+        Ok(user)
+    }
+
     #[tracing::instrument(
         name = "db.user.set_can_request_admin",
         skip_all,
@@ -322,4 +412,142 @@ impl<'c> UserRepository for PgUserRepository<'c> {
 
         Ok(user)
     }
+
+    #[tracing::instrument(
+        name = "db.user.set_admin",
+        skip_all,
+        fields(
+            db.statement,
+            %user.id,
+            user.is_admin = is_admin,
+        ),
+        err,
+    )]
+    async fn set_admin(&mut self, mut user: User, is_admin: bool) -> Result<User, Self::Error> {
+        let res = sqlx::query!(
+            r#"
+                UPDATE users
+                SET is_admin = $2
+                WHERE user_id = $1
+            "#,
+            Uuid::from(user.id),
+            is_admin,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        user.is_admin = is_admin;
+
+        Ok(user)
+    }
+
+    #[tracing::instrument(
+        name = "db.user.list",
+        skip_all,
+        fields(
+            db.statement,
+        ),
+        err,
+    )]
+    async fn list(
+        &mut self,
+        filter: UserFilter<'_>,
+        pagination: Pagination,
+    ) -> Result<Page<User>, Self::Error> {
+        let (sql, arguments) = Query::select()
+            .expr_as(
+                Expr::col((Users::Table, Users::UserId)),
+                UserLookupIden::UserId,
+            )
+            .expr_as(
+                Expr::col((Users::Table, Users::Username)),
+                UserLookupIden::Username,
+            )
+            .expr_as(
+                Expr::col((Users::Table, Users::PrimaryUserEmailId)),
+                UserLookupIden::PrimaryUserEmailId,
+            )
+            .expr_as(
+                Expr::col((Users::Table, Users::CreatedAt)),
+                UserLookupIden::CreatedAt,
+            )
+            .expr_as(
+                Expr::col((Users::Table, Users::LockedAt)),
+                UserLookupIden::LockedAt,
+            )
+            .expr_as(
+                Expr::col((Users::Table, Users::DeactivatedAt)),
+                UserLookupIden::DeactivatedAt,
+            )
+            .expr_as(
+                Expr::col((Users::Table, Users::CanRequestAdmin)),
+                UserLookupIden::CanRequestAdmin,
+            )
+            .expr_as(
+                Expr::col((Users::Table, Users::IsAdmin)),
+                UserLookupIden::IsAdmin,
+            )
+            .from(Users::Table)
+            .apply_filter(filter)
+            .generate_pagination((Users::Table, Users::UserId), pagination)
+            .build_sqlx(PostgresQueryBuilder);
+
+        let edges: Vec<UserLookup> = sqlx::query_as_with(&sql, arguments)
+            .traced()
+            .fetch_all(&mut *self.conn)
+            .await?;
+
+        let page = pagination.process(edges).map(User::from);
+
+        Ok(page)
+    }
+
+    #[tracing::instrument(
+        name = "db.user.count",
+        skip_all,
+        fields(
+            db.statement,
+        ),
+        err,
+    )]
+    async fn count(&mut self, filter: UserFilter<'_>) -> Result<usize, Self::Error> {
+        let (sql, arguments) = Query::select()
+            .expr(Expr::col((Users::Table, Users::UserId)).count())
+            .from(Users::Table)
+            .apply_filter(filter)
+            .build_sqlx(PostgresQueryBuilder);
+
+        let count: i64 = sqlx::query_scalar_with(&sql, arguments)
+            .traced()
+            .fetch_one(&mut *self.conn)
+            .await?;
+
+        count
+            .try_into()
+            .map_err(DatabaseError::to_invalid_operation)
+    }
+}
+
+trait UserFilterExt {
+    fn apply_filter(&mut self, filter: UserFilter<'_>) -> &mut Self;
+}
+
+impl UserFilterExt for sea_query::SelectStatement {
+    fn apply_filter(&mut self, filter: UserFilter<'_>) -> &mut Self {
+        self.and_where_option(filter.username_prefix().map(|prefix| {
+            // Escape the LIKE wildcards, so that the prefix is matched literally
+            let prefix = prefix
+                .replace('\\', "\\\\")
+                .replace('%', "\\%")
+                .replace('_', "\\_");
+            Expr::col((Users::Table, Users::Username))
+                .like(LikeExpr::new(format!("{prefix}%")).escape('\\'))
+        }))
+        .and_where_option(filter.can_request_admin().map(|can_request_admin| {
+            Expr::col((Users::Table, Users::CanRequestAdmin)).eq(can_request_admin)
+        }))
+    }
 }
@@ -0,0 +1,185 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use mas_data_model::{User, UserPasswordResetToken};
+use mas_storage::{user::UserPasswordResetTokenRepository, Clock};
+use rand::RngCore;
+use sqlx::PgConnection;
+use ulid::Ulid;
+use uuid::Uuid;
+
+use crate::{tracing::ExecuteExt, DatabaseError};
+
+/// An implementation of [`UserPasswordResetTokenRepository`] for a PostgreSQL
+/// connection
+pub struct PgUserPasswordResetTokenRepository<'c> {
+    conn: &'c mut PgConnection,
+}
+
+impl<'c> PgUserPasswordResetTokenRepository<'c> {
+    /// Create a new [`PgUserPasswordResetTokenRepository`] from an active
+    /// PostgreSQL connection
+    pub fn new(conn: &'c mut PgConnection) -> Self {
+        Self { conn }
+    }
+}
+
+struct UserPasswordResetTokenLookup {
+    user_password_reset_token_id: Uuid,
+    user_id: Uuid,
+    hashed_secret: String,
+    created_at: DateTime<Utc>,
+    expires_at: DateTime<Utc>,
+    consumed_at: Option<DateTime<Utc>>,
+}
+
+impl From<UserPasswordResetTokenLookup> for UserPasswordResetToken {
+    fn from(value: UserPasswordResetTokenLookup) -> Self {
+        UserPasswordResetToken {
+            id: value.user_password_reset_token_id.into(),
+            user_id: value.user_id.into(),
+            hashed_secret: value.hashed_secret,
+            created_at: value.created_at,
+            expires_at: value.expires_at,
+            consumed_at: value.consumed_at,
+        }
+    }
+}
+
+#[async_trait]
+impl<'c> UserPasswordResetTokenRepository for PgUserPasswordResetTokenRepository<'c> {
+    type Error = DatabaseError;
+
+    #[tracing::instrument(
+        name = "db.user_password_reset_token.lookup",
+        skip_all,
+        fields(
+            db.statement,
+            user_password_reset_token.id = %id,
+        ),
+        err,
+    )]
+    async fn lookup(&mut self, id: Ulid) -> Result<Option<UserPasswordResetToken>, Self::Error> {
+        let res = sqlx::query_as!(
+            UserPasswordResetTokenLookup,
+            r#"
+                SELECT user_password_reset_token_id
+                     , user_id
+                     , hashed_secret
+                     , created_at
+                     , expires_at
+                     , consumed_at
+                FROM user_password_reset_tokens
+                WHERE user_password_reset_token_id = $1
+            "#,
+            Uuid::from(id),
+        )
+        .traced()
+        .fetch_optional(&mut *self.conn)
+        .await?;
+
+        Ok(res.map(Into::into))
+    }
+
+    #[tracing::instrument(
+        name = "db.user_password_reset_token.add",
+        skip_all,
+        fields(
+            db.statement,
+            %user.id,
+            user_password_reset_token.id,
+        ),
+        err,
+    )]
+    async fn add(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        user: &User,
+        hashed_secret: String,
+        expires_in: Duration,
+    ) -> Result<UserPasswordResetToken, Self::Error> {
+        let created_at = clock.now();
+        let expires_at = created_at + expires_in;
+        let id = Ulid::from_datetime_with_source(created_at.into(), rng);
+        tracing::Span::current()
+            .record("user_password_reset_token.id", tracing::field::display(id));
+
+        sqlx::query!(
+            r#"
+                INSERT INTO user_password_reset_tokens
+                    (user_password_reset_token_id, user_id, hashed_secret, created_at, expires_at)
+                VALUES ($1, $2, $3, $4, $5)
+            "#,
+            Uuid::from(id),
+            Uuid::from(user.id),
+            &hashed_secret,
+            created_at,
+            expires_at,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        Ok(UserPasswordResetToken {
+            id,
+            user_id: user.id,
+            hashed_secret,
+            created_at,
+            expires_at,
+            consumed_at: None,
+        })
+    }
+
+    #[tracing::instrument(
+        name = "db.user_password_reset_token.consume",
+        skip_all,
+        fields(
+            db.statement,
+            %token.id,
+        ),
+        err,
+    )]
+    async fn consume(
+        &mut self,
+        clock: &dyn Clock,
+        mut token: UserPasswordResetToken,
+    ) -> Result<UserPasswordResetToken, Self::Error> {
+        let consumed_at = clock.now();
+
+        // The conditions make sure the same token can't be consumed twice by
+        // concurrent requests, nor after it expired
+        let res = sqlx::query!(
+            r#"
+                UPDATE user_password_reset_tokens
+                SET consumed_at = $2
+                WHERE user_password_reset_token_id = $1
+                  AND consumed_at IS NULL
+                  AND expires_at > $2
+            "#,
+            Uuid::from(token.id),
+            consumed_at,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        token.consumed_at = Some(consumed_at);
+        Ok(token)
+    }
+}
@@ -0,0 +1,243 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use mas_data_model::{User, UserRecoveryCode};
+use mas_storage::{user::UserRecoveryCodeRepository, Clock};
+use rand::RngCore;
+use sqlx::PgConnection;
+use ulid::Ulid;
+use uuid::Uuid;
+
+use crate::{tracing::ExecuteExt, DatabaseError};
+
+/// An implementation of [`UserRecoveryCodeRepository`] for a PostgreSQL
+/// connection
+pub struct PgUserRecoveryCodeRepository<'c> {
+    conn: &'c mut PgConnection,
+}
+
+impl<'c> PgUserRecoveryCodeRepository<'c> {
+    /// Create a new [`PgUserRecoveryCodeRepository`] from an active PostgreSQL
+    /// connection
+    pub fn new(conn: &'c mut PgConnection) -> Self {
+        Self { conn }
+    }
+}
+
+struct UserRecoveryCodeLookup {
+    user_recovery_code_id: Uuid,
+    user_id: Uuid,
+    hashed_code: String,
+    created_at: DateTime<Utc>,
+    consumed_at: Option<DateTime<Utc>>,
+}
+
+impl From<UserRecoveryCodeLookup> for UserRecoveryCode {
+    fn from(value: UserRecoveryCodeLookup) -> Self {
+        UserRecoveryCode {
+            id: value.user_recovery_code_id.into(),
+            user_id: value.user_id.into(),
+            hashed_code: value.hashed_code,
+            created_at: value.created_at,
+            consumed_at: value.consumed_at,
+        }
+    }
+}
+
+#[async_trait]
+impl<'c> UserRecoveryCodeRepository for PgUserRecoveryCodeRepository<'c> {
+    type Error = DatabaseError;
+
+    #[tracing::instrument(
+        name = "db.user_recovery_code.find_active",
+        skip_all,
+        fields(
+            db.statement,
+            %user.id,
+        ),
+        err,
+    )]
+    async fn find_active(
+        &mut self,
+        user: &User,
+        hashed_code: &str,
+    ) -> Result<Option<UserRecoveryCode>, Self::Error> {
+        let res = sqlx::query_as!(
+            UserRecoveryCodeLookup,
+            r#"
+                SELECT user_recovery_code_id
+                     , user_id
+                     , hashed_code
+                     , created_at
+                     , consumed_at
+                FROM user_recovery_codes
+                WHERE user_id = $1
+                  AND hashed_code = $2
+                  AND consumed_at IS NULL
+            "#,
+            Uuid::from(user.id),
+            hashed_code,
+        )
+        .traced()
+        .fetch_optional(&mut *self.conn)
+        .await?;
+
+        Ok(res.map(Into::into))
+    }
+
+    #[tracing::instrument(
+        name = "db.user_recovery_code.count_active",
+        skip_all,
+        fields(
+            db.statement,
+            %user.id,
+        ),
+        err,
+    )]
+    async fn count_active(&mut self, user: &User) -> Result<usize, Self::Error> {
+        let count = sqlx::query_scalar!(
+            r#"
+                SELECT COUNT(*) AS "count!"
+                FROM user_recovery_codes
+                WHERE user_id = $1
+                  AND consumed_at IS NULL
+            "#,
+            Uuid::from(user.id),
+        )
+        .traced()
+        .fetch_one(&mut *self.conn)
+        .await?;
+
+        count
+            .try_into()
+            .map_err(DatabaseError::to_invalid_operation)
+    }
+
+    #[tracing::instrument(
+        name = "db.user_recovery_code.add",
+        skip_all,
+        fields(
+            db.statement,
+            %user.id,
+        ),
+        err,
+    )]
+    async fn add(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        user: &User,
+        hashed_codes: Vec<String>,
+    ) -> Result<Vec<UserRecoveryCode>, Self::Error> {
+        let created_at = clock.now();
+        let ids: Vec<Ulid> = hashed_codes
+            .iter()
+            .map(|_| Ulid::from_datetime_with_source(created_at.into(), rng))
+            .collect();
+        let db_ids: Vec<Uuid> = ids.iter().copied().map(Uuid::from).collect();
+
+        sqlx::query!(
+            r#"
+                INSERT INTO user_recovery_codes
+                    (user_recovery_code_id, user_id, hashed_code, created_at)
+                SELECT id, $2, hashed_code, $4 FROM UNNEST($1::uuid[], $3::text[]) u(id, hashed_code)
+            "#,
+            &db_ids,
+            Uuid::from(user.id),
+            &hashed_codes,
+            created_at,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        Ok(ids
+            .into_iter()
+            .zip(hashed_codes)
+            .map(|(id, hashed_code)| UserRecoveryCode {
+                id,
+                user_id: user.id,
+                hashed_code,
+                created_at,
+                consumed_at: None,
+            })
+            .collect())
+    }
+
+    #[tracing::instrument(
+        name = "db.user_recovery_code.consume",
+        skip_all,
+        fields(
+            db.statement,
+            %recovery_code.id,
+        ),
+        err,
+    )]
+    async fn consume(
+        &mut self,
+        clock: &dyn Clock,
+        mut recovery_code: UserRecoveryCode,
+    ) -> Result<Option<UserRecoveryCode>, Self::Error> {
+        let consumed_at = clock.now();
+
+        // The `consumed_at IS NULL` condition makes sure the same code can't be
+        // consumed twice by concurrent requests
+        let res = sqlx::query!(
+            r#"
+                UPDATE user_recovery_codes
+                SET consumed_at = $2
+                WHERE user_recovery_code_id = $1
+                  AND consumed_at IS NULL
+            "#,
+            Uuid::from(recovery_code.id),
+            consumed_at,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        if res.rows_affected() == 0 {
+            return Ok(None);
+        }
+
+        recovery_code.consumed_at = Some(consumed_at);
+        Ok(Some(recovery_code))
+    }
+
+    #[tracing::instrument(
+        name = "db.user_recovery_code.delete_all_for_user",
+        skip_all,
+        fields(
+            db.statement,
+            %user.id,
+        ),
+        err,
+    )]
+    async fn delete_all_for_user(&mut self, user: &User) -> Result<usize, Self::Error> {
+        let res = sqlx::query!(
+            r#"
+                DELETE FROM user_recovery_codes
+                WHERE user_id = $1
+            "#,
+            Uuid::from(user.id),
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        Ok(res.rows_affected().try_into().unwrap_or(usize::MAX))
+    }
+}
@@ -12,24 +12,31 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::net::IpAddr;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    net::IpAddr,
+};
 
 use async_trait::async_trait;
 use chrono::{DateTime, Utc};
 use mas_data_model::{
-    Authentication, AuthenticationMethod, BrowserSession, Password,
-    UpstreamOAuthAuthorizationSession, User, UserAgent,
+    Authentication, AuthenticationMethod, BrowserSession, Password, Totp,
+    UpstreamOAuthAuthorizationSession, User, UserAgent, UserRecoveryCode, WebAuthnCredential,
+};
+use mas_storage::{
+    pagination::{PaginationOrder, PaginationOrderBy},
+    user::{BrowserSessionFilter, BrowserSessionRepository},
+    Clock, Page, Pagination,
 };
-use mas_storage::{user::BrowserSessionRepository, Clock, Page, Pagination};
 use rand::RngCore;
-use sea_query::{Expr, PostgresQueryBuilder};
+use sea_query::{enum_def, Asterisk, Expr, PostgresQueryBuilder, Query};
 use sea_query_binder::SqlxBinder;
 use sqlx::PgConnection;
 use ulid::Ulid;
 use uuid::Uuid;
 
 use crate::{
-    iden::{UserSessions, Users},
+    iden::{UserSessionAuthentications, UserSessions, Users},
     pagination::QueryBuilderExt,
     tracing::ExecuteExt,
     DatabaseError, DatabaseInconsistencyError,
@@ -56,7 +63,9 @@ struct SessionLookup {
     user_session_id: Uuid,
     user_session_created_at: DateTime<Utc>,
     user_session_finished_at: Option<DateTime<Utc>>,
+    user_session_expires_at: Option<DateTime<Utc>>,
     user_session_user_agent: Option<String>,
+    user_session_ip_address: Option<IpAddr>,
     user_session_last_active_at: Option<DateTime<Utc>>,
     user_session_last_active_ip: Option<IpAddr>,
     user_id: Uuid,
@@ -64,9 +73,29 @@ struct SessionLookup {
     user_primary_user_email_id: Option<Uuid>,
     user_created_at: DateTime<Utc>,
     user_locked_at: Option<DateTime<Utc>>,
+    user_deactivated_at: Option<DateTime<Utc>>,
     user_can_request_admin: bool,
+    user_is_admin: bool,
+}
+
+/// A row returned when listing sessions, with the total count of sessions
+/// matching the filter if it was requested
+#[derive(sqlx::FromRow)]
+struct SessionListLookup {
+    #[sqlx(flatten)]
+    session: SessionLookup,
+    #[sqlx(default)]
+    total_count: Option<i64>,
 }
 
+#[derive(sea_query::Iden)]
+#[iden = "sessions"]
+struct SessionsIden;
+
+#[derive(sea_query::Iden)]
+#[iden = "total_count"]
+struct TotalCountIden;
+
 impl TryFrom<SessionLookup> for BrowserSession {
     type Error = DatabaseInconsistencyError;
 
@@ -79,7 +108,9 @@ impl TryFrom<SessionLookup> for BrowserSession {
             primary_user_email_id: value.user_primary_user_email_id.map(Into::into),
             created_at: value.user_created_at,
             locked_at: value.user_locked_at,
+            deactivated_at: value.user_deactivated_at,
             can_request_admin: value.user_can_request_admin,
+            is_admin: value.user_is_admin,
         };
 
         Ok(BrowserSession {
@@ -87,18 +118,26 @@ impl TryFrom<SessionLookup> for BrowserSession {
             user,
             created_at: value.user_session_created_at,
             finished_at: value.user_session_finished_at,
+            expires_at: value.user_session_expires_at,
             user_agent: value.user_session_user_agent.map(UserAgent::parse),
+            ip_address: value.user_session_ip_address,
             last_active_at: value.user_session_last_active_at,
             last_active_ip: value.user_session_last_active_ip,
         })
     }
 }
 
+#[derive(sqlx::FromRow)]
+#[enum_def]
 struct AuthenticationLookup {
     user_session_authentication_id: Uuid,
     created_at: DateTime<Utc>,
     user_password_id: Option<Uuid>,
     upstream_oauth_authorization_session_id: Option<Uuid>,
+    upstream_oauth_link_id: Option<Uuid>,
+    user_webauthn_credential_id: Option<Uuid>,
+    user_totp_id: Option<Uuid>,
+    user_recovery_code_id: Option<Uuid>,
 }
 
 impl TryFrom<AuthenticationLookup> for Authentication {
@@ -111,12 +150,33 @@ impl TryFrom<AuthenticationLookup> for Authentication {
             value
                 .upstream_oauth_authorization_session_id
                 .map(Into::into),
+            value.user_webauthn_credential_id.map(Into::into),
+            value.user_totp_id.map(Into::into),
+            value.user_recovery_code_id.map(Into::into),
         ) {
-            (Some(user_password_id), None) => AuthenticationMethod::Password { user_password_id },
-            (None, Some(upstream_oauth2_session_id)) => AuthenticationMethod::UpstreamOAuth2 {
-                upstream_oauth2_session_id,
-            },
-            (None, None) => AuthenticationMethod::Unknown,
+            (Some(user_password_id), None, None, None, None) => {
+                AuthenticationMethod::Password { user_password_id }
+            }
+            (None, Some(upstream_oauth2_session_id), None, None, None) => {
+                AuthenticationMethod::UpstreamOAuth2 {
+                    upstream_oauth2_session_id,
+                    upstream_oauth_link_id: value.upstream_oauth_link_id.map(Ulid::from),
+                }
+            }
+            (None, None, Some(user_webauthn_credential_id), None, None) => {
+                AuthenticationMethod::WebAuthn {
+                    user_webauthn_credential_id,
+                }
+            }
+            (None, None, None, Some(user_totp_id), None) => {
+                AuthenticationMethod::Totp { user_totp_id }
+            }
+            (None, None, None, None, Some(user_recovery_code_id)) => {
+                AuthenticationMethod::RecoveryCode {
+                    user_recovery_code_id,
+                }
+            }
+            (None, None, None, None, None) => AuthenticationMethod::Unknown,
             _ => {
                 return Err(DatabaseInconsistencyError::on("user_session_authentications").row(id));
             }
@@ -143,14 +203,20 @@ impl<'c> BrowserSessionRepository for PgBrowserSessionRepository<'c> {
         ),
         err,
     )]
-    async fn lookup(&mut self, id: Ulid) -> Result<Option<BrowserSession>, Self::Error> {
+    async fn lookup(
+        &mut self,
+        clock: &dyn Clock,
+        id: Ulid,
+    ) -> Result<Option<BrowserSession>, Self::Error> {
         let res = sqlx::query_as!(
             SessionLookup,
             r#"
                 SELECT s.user_session_id
                      , s.created_at            AS "user_session_created_at"
                      , s.finished_at           AS "user_session_finished_at"
+                     , s.expires_at            AS "user_session_expires_at"
                      , s.user_agent            AS "user_session_user_agent"
+                     , s.ip_address            AS "user_session_ip_address: IpAddr"
                      , s.last_active_at        AS "user_session_last_active_at"
                      , s.last_active_ip        AS "user_session_last_active_ip: IpAddr"
                      , u.user_id
@@ -158,7 +224,9 @@ impl<'c> BrowserSessionRepository for PgBrowserSessionRepository<'c> {
                      , u.primary_user_email_id AS "user_primary_user_email_id"
                      , u.created_at            AS "user_created_at"
                      , u.locked_at             AS "user_locked_at"
+                     , u.deactivated_at        AS "user_deactivated_at"
                      , u.can_request_admin     AS "user_can_request_admin"
+                     , u.is_admin              AS "user_is_admin"
                 FROM user_sessions s
                 INNER JOIN users u
                     USING (user_id)
@@ -172,7 +240,62 @@ impl<'c> BrowserSessionRepository for PgBrowserSessionRepository<'c> {
 
         let Some(res) = res else { return Ok(None) };
 
-        Ok(Some(res.try_into()?))
+        let session = BrowserSession::try_from(res)?;
+        Ok(Some(session.finish_if_expired(clock.now())))
+    }
+
+    #[tracing::instrument(
+        name = "db.browser_session.lookup_batch",
+        skip_all,
+        fields(
+            db.statement,
+        ),
+        err,
+    )]
+    async fn lookup_batch(
+        &mut self,
+        clock: &dyn Clock,
+        ids: &[Ulid],
+    ) -> Result<HashMap<Ulid, BrowserSession>, Self::Error> {
+        let now = clock.now();
+        let ids: Vec<Uuid> = ids.iter().copied().map(Uuid::from).collect();
+        let res = sqlx::query_as!(
+            SessionLookup,
+            r#"
+                SELECT s.user_session_id
+                     , s.created_at            AS "user_session_created_at"
+                     , s.finished_at           AS "user_session_finished_at"
+                     , s.expires_at            AS "user_session_expires_at"
+                     , s.user_agent            AS "user_session_user_agent"
+                     , s.ip_address            AS "user_session_ip_address: IpAddr"
+                     , s.last_active_at        AS "user_session_last_active_at"
+                     , s.last_active_ip        AS "user_session_last_active_ip: IpAddr"
+                     , u.user_id
+                     , u.username              AS "user_username"
+                     , u.primary_user_email_id AS "user_primary_user_email_id"
+                     , u.created_at            AS "user_created_at"
+                     , u.locked_at             AS "user_locked_at"
+                     , u.deactivated_at        AS "user_deactivated_at"
+                     , u.can_request_admin     AS "user_can_request_admin"
+                     , u.is_admin              AS "user_is_admin"
+                FROM user_sessions s
+                INNER JOIN users u
+                    USING (user_id)
+                WHERE s.user_session_id = ANY($1::uuid[])
+            "#,
+            &ids,
+        )
+        .traced()
+        .fetch_all(&mut *self.conn)
+        .await?;
+
+        res.into_iter()
+            .map(|r| {
+                BrowserSession::try_from(r)
+                    .map(|session| (session.id, session.finish_if_expired(now)))
+                    .map_err(DatabaseError::from)
+            })
+            .collect()
     }
 
     #[tracing::instrument(
@@ -191,20 +314,30 @@ impl<'c> BrowserSessionRepository for PgBrowserSessionRepository<'c> {
         clock: &dyn Clock,
         user: &User,
         user_agent: Option<UserAgent>,
+        ip_address: Option<IpAddr>,
+        expires_at: Option<DateTime<Utc>>,
     ) -> Result<BrowserSession, Self::Error> {
+        // Deactivated users keep their data, but can't start new sessions
+        if user.deactivated_at.is_some() {
+            return Err(DatabaseError::UserDeactivated { user_id: user.id });
+        }
+
         let created_at = clock.now();
         let id = Ulid::from_datetime_with_source(created_at.into(), rng);
         tracing::Span::current().record("user_session.id", tracing::field::display(id));
 
         sqlx::query!(
             r#"
-                INSERT INTO user_sessions (user_session_id, user_id, created_at, user_agent)
-                VALUES ($1, $2, $3, $4)
+                INSERT INTO user_sessions
+                    (user_session_id, user_id, created_at, user_agent, ip_address, expires_at)
+                VALUES ($1, $2, $3, $4, $5, $6)
             "#,
             Uuid::from(id),
             Uuid::from(user.id),
             created_at,
             user_agent.as_deref(),
+            ip_address as Option<IpAddr>,
+            expires_at,
         )
         .traced()
         .execute(&mut *self.conn)
@@ -216,7 +349,9 @@ impl<'c> BrowserSessionRepository for PgBrowserSessionRepository<'c> {
             user: user.clone(),
             created_at,
             finished_at: None,
+            expires_at,
             user_agent,
+            ip_address,
             last_active_at: None,
             last_active_ip: None,
         };
@@ -259,6 +394,38 @@ impl<'c> BrowserSessionRepository for PgBrowserSessionRepository<'c> {
         Ok(user_session)
     }
 
+    #[tracing::instrument(
+        name = "db.browser_session.finish_all_for_user",
+        skip_all,
+        fields(
+            db.statement,
+            %user.id,
+        ),
+        err,
+    )]
+    async fn finish_all_for_user(
+        &mut self,
+        clock: &dyn Clock,
+        user: &User,
+    ) -> Result<usize, Self::Error> {
+        let finished_at = clock.now();
+        let res = sqlx::query!(
+            r#"
+                UPDATE user_sessions
+                SET finished_at = $1
+                WHERE user_id = $2
+                  AND finished_at IS NULL
+            "#,
+            finished_at,
+            Uuid::from(user.id),
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        Ok(res.rows_affected().try_into().unwrap_or(usize::MAX))
+    }
+
     #[tracing::instrument(
         name = "db.browser_session.list",
         skip_all,
@@ -269,10 +436,13 @@ impl<'c> BrowserSessionRepository for PgBrowserSessionRepository<'c> {
     )]
     async fn list(
         &mut self,
-        filter: mas_storage::user::BrowserSessionFilter<'_>,
+        filter: BrowserSessionFilter<'_>,
         pagination: Pagination,
     ) -> Result<Page<BrowserSession>, Self::Error> {
-        let (sql, arguments) = sea_query::Query::select()
+        // The filtered sessions are selected in a subquery, so that the total
+        // count is computed before the pagination cursors are applied
+        let mut sessions = sea_query::Query::select();
+        sessions
             .expr_as(
                 Expr::col((UserSessions::Table, UserSessions::UserSessionId)),
                 SessionLookupIden::UserSessionId,
@@ -285,10 +455,18 @@ impl<'c> BrowserSessionRepository for PgBrowserSessionRepository<'c> {
                 Expr::col((UserSessions::Table, UserSessions::FinishedAt)),
                 SessionLookupIden::UserSessionFinishedAt,
             )
+            .expr_as(
+                Expr::col((UserSessions::Table, UserSessions::ExpiresAt)),
+                SessionLookupIden::UserSessionExpiresAt,
+            )
             .expr_as(
                 Expr::col((UserSessions::Table, UserSessions::UserAgent)),
                 SessionLookupIden::UserSessionUserAgent,
             )
+            .expr_as(
+                Expr::col((UserSessions::Table, UserSessions::IpAddress)),
+                SessionLookupIden::UserSessionIpAddress,
+            )
             .expr_as(
                 Expr::col((UserSessions::Table, UserSessions::LastActiveAt)),
                 SessionLookupIden::UserSessionLastActiveAt,
@@ -317,42 +495,81 @@ impl<'c> BrowserSessionRepository for PgBrowserSessionRepository<'c> {
                 Expr::col((Users::Table, Users::LockedAt)),
                 SessionLookupIden::UserLockedAt,
             )
+            .expr_as(
+                Expr::col((Users::Table, Users::DeactivatedAt)),
+                SessionLookupIden::UserDeactivatedAt,
+            )
             .expr_as(
                 Expr::col((Users::Table, Users::CanRequestAdmin)),
                 SessionLookupIden::UserCanRequestAdmin,
             )
+            .expr_as(
+                Expr::col((Users::Table, Users::IsAdmin)),
+                SessionLookupIden::UserIsAdmin,
+            )
             .from(UserSessions::Table)
             .inner_join(
                 Users::Table,
                 Expr::col((UserSessions::Table, UserSessions::UserId))
                     .equals((Users::Table, Users::UserId)),
             )
-            .and_where_option(
-                filter
-                    .user()
-                    .map(|user| Expr::col((Users::Table, Users::UserId)).eq(Uuid::from(user.id))),
-            )
-            .and_where_option(filter.state().map(|state| {
-                if state.is_active() {
-                    Expr::col((UserSessions::Table, UserSessions::FinishedAt)).is_null()
-                } else {
-                    Expr::col((UserSessions::Table, UserSessions::FinishedAt)).is_not_null()
-                }
-            }))
-            .generate_pagination(
-                (UserSessions::Table, UserSessions::UserSessionId),
+            .apply_filter(filter);
+
+        if pagination.with_total_count {
+            sessions.expr_as(Expr::cust("COUNT(*) OVER ()"), TotalCountIden);
+        }
+
+        let (sql, arguments) = sea_query::Query::select()
+            .column(Asterisk)
+            .from_subquery(sessions, SessionsIden)
+            .generate_ordered_pagination(
+                (SessionsIden, SessionLookupIden::UserSessionId),
+                match pagination.order_by {
+                    PaginationOrderBy::Id => None,
+                    PaginationOrderBy::CreatedAt => {
+                        Some((SessionsIden, SessionLookupIden::UserSessionCreatedAt))
+                    }
+                    PaginationOrderBy::FinishedAt => {
+                        Some((SessionsIden, SessionLookupIden::UserSessionFinishedAt))
+                    }
+                },
                 pagination,
             )
             .build_sqlx(PostgresQueryBuilder);
 
-        let edges: Vec<SessionLookup> = sqlx::query_as_with(&sql, arguments)
+        let edges: Vec<SessionListLookup> = sqlx::query_as_with(&sql, arguments)
             .traced()
             .fetch_all(&mut *self.conn)
             .await?;
 
+        let total_count = if !pagination.with_total_count {
+            None
+        } else if let Some(edge) = edges.first() {
+            let total_count = edge.total_count.unwrap_or_default();
+            Some(
+                total_count
+                    .try_into()
+                    .map_err(DatabaseError::to_invalid_operation)?,
+            )
+        } else if pagination.before.is_none() && pagination.after.is_none() {
+            Some(0)
+        } else {
+            // The window function only gives the count when at least one row
+            // is returned, so we need to count separately if the cursors
+            // excluded all the sessions
+            let total_count = self.count(filter).await?;
+            Some(
+                total_count
+                    .try_into()
+                    .map_err(DatabaseError::to_invalid_operation)?,
+            )
+        };
+
         let page = pagination
             .process(edges)
-            .try_map(BrowserSession::try_from)?;
+            .map(|edge| edge.session)
+            .try_map(BrowserSession::try_from)?
+            .with_total_count(total_count);
 
         Ok(page)
     }
@@ -365,23 +582,11 @@ impl<'c> BrowserSessionRepository for PgBrowserSessionRepository<'c> {
         ),
         err,
     )]
-    async fn count(
-        &mut self,
-        filter: mas_storage::user::BrowserSessionFilter<'_>,
-    ) -> Result<usize, Self::Error> {
+    async fn count(&mut self, filter: BrowserSessionFilter<'_>) -> Result<usize, Self::Error> {
         let (sql, arguments) = sea_query::Query::select()
             .expr(Expr::col((UserSessions::Table, UserSessions::UserSessionId)).count())
             .from(UserSessions::Table)
-            .and_where_option(filter.user().map(|user| {
-                Expr::col((UserSessions::Table, UserSessions::UserId)).eq(Uuid::from(user.id))
-            }))
-            .and_where_option(filter.state().map(|state| {
-                if state.is_active() {
-                    Expr::col((UserSessions::Table, UserSessions::FinishedAt)).is_null()
-                } else {
-                    Expr::col((UserSessions::Table, UserSessions::FinishedAt)).is_not_null()
-                }
-            }))
+            .apply_filter(filter)
             .build_sqlx(PostgresQueryBuilder);
 
         let count: i64 = sqlx::query_scalar_with(&sql, arguments)
@@ -467,17 +672,19 @@ impl<'c> BrowserSessionRepository for PgBrowserSessionRepository<'c> {
             "user_session_authentication.id",
             tracing::field::display(id),
         );
+        let upstream_oauth_link_id = upstream_oauth_session.link_id();
 
         sqlx::query!(
             r#"
                 INSERT INTO user_session_authentications
-                    (user_session_authentication_id, user_session_id, created_at, upstream_oauth_authorization_session_id)
-                VALUES ($1, $2, $3, $4)
+                    (user_session_authentication_id, user_session_id, created_at, upstream_oauth_authorization_session_id, upstream_oauth_link_id)
+                VALUES ($1, $2, $3, $4, $5)
             "#,
             Uuid::from(id),
             Uuid::from(user_session.id),
             created_at,
             Uuid::from(upstream_oauth_session.id),
+            upstream_oauth_link_id.map(Uuid::from),
         )
         .traced()
         .execute(&mut *self.conn)
@@ -488,6 +695,154 @@ impl<'c> BrowserSessionRepository for PgBrowserSessionRepository<'c> {
             created_at,
             authentication_method: AuthenticationMethod::UpstreamOAuth2 {
                 upstream_oauth2_session_id: upstream_oauth_session.id,
+                upstream_oauth_link_id,
+            },
+        })
+    }
+
+    #[tracing::instrument(
+        name = "db.browser_session.authenticate_with_webauthn",
+        skip_all,
+        fields(
+            db.statement,
+            %user_session.id,
+            %credential.id,
+            user_session_authentication.id,
+        ),
+        err,
+    )]
+    async fn authenticate_with_webauthn(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        user_session: &BrowserSession,
+        credential: &WebAuthnCredential,
+    ) -> Result<Authentication, Self::Error> {
+        let created_at = clock.now();
+        let id = Ulid::from_datetime_with_source(created_at.into(), rng);
+        tracing::Span::current().record(
+            "user_session_authentication.id",
+            tracing::field::display(id),
+        );
+
+        sqlx::query!(
+            r#"
+                INSERT INTO user_session_authentications
+                    (user_session_authentication_id, user_session_id, created_at, user_webauthn_credential_id)
+                VALUES ($1, $2, $3, $4)
+            "#,
+            Uuid::from(id),
+            Uuid::from(user_session.id),
+            created_at,
+            Uuid::from(credential.id),
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        Ok(Authentication {
+            id,
+            created_at,
+            authentication_method: AuthenticationMethod::WebAuthn {
+                user_webauthn_credential_id: credential.id,
+            },
+        })
+    }
+
+    #[tracing::instrument(
+        name = "db.browser_session.authenticate_with_totp",
+        skip_all,
+        fields(
+            db.statement,
+            %user_session.id,
+            %totp.id,
+            user_session_authentication.id,
+        ),
+        err,
+    )]
+    async fn authenticate_with_totp(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        user_session: &BrowserSession,
+        totp: &Totp,
+    ) -> Result<Authentication, Self::Error> {
+        let created_at = clock.now();
+        let id = Ulid::from_datetime_with_source(created_at.into(), rng);
+        tracing::Span::current().record(
+            "user_session_authentication.id",
+            tracing::field::display(id),
+        );
+
+        sqlx::query!(
+            r#"
+                INSERT INTO user_session_authentications
+                    (user_session_authentication_id, user_session_id, created_at, user_totp_id)
+                VALUES ($1, $2, $3, $4)
+            "#,
+            Uuid::from(id),
+            Uuid::from(user_session.id),
+            created_at,
+            Uuid::from(totp.id),
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        Ok(Authentication {
+            id,
+            created_at,
+            authentication_method: AuthenticationMethod::Totp {
+                user_totp_id: totp.id,
+            },
+        })
+    }
+
+    #[tracing::instrument(
+        name = "db.browser_session.authenticate_with_recovery_code",
+        skip_all,
+        fields(
+            db.statement,
+            %user_session.id,
+            %recovery_code.id,
+            user_session_authentication.id,
+        ),
+        err,
+    )]
+    async fn authenticate_with_recovery_code(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        user_session: &BrowserSession,
+        recovery_code: &UserRecoveryCode,
+    ) -> Result<Authentication, Self::Error> {
+        let created_at = clock.now();
+        let id = Ulid::from_datetime_with_source(created_at.into(), rng);
+        tracing::Span::current().record(
+            "user_session_authentication.id",
+            tracing::field::display(id),
+        );
+
+        sqlx::query!(
+            r#"
+                INSERT INTO user_session_authentications
+                    (user_session_authentication_id, user_session_id, created_at, user_recovery_code_id)
+                VALUES ($1, $2, $3, $4)
+            "#,
+            Uuid::from(id),
+            Uuid::from(user_session.id),
+            created_at,
+            Uuid::from(recovery_code.id),
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        Ok(Authentication {
+            id,
+            created_at,
+            authentication_method: AuthenticationMethod::RecoveryCode {
+                user_recovery_code_id: recovery_code.id,
             },
         })
     }
@@ -512,6 +867,10 @@ impl<'c> BrowserSessionRepository for PgBrowserSessionRepository<'c> {
                      , created_at
                      , user_password_id
                      , upstream_oauth_authorization_session_id
+                     , upstream_oauth_link_id
+                     , user_webauthn_credential_id
+                     , user_totp_id
+                     , user_recovery_code_id
                 FROM user_session_authentications
                 WHERE user_session_id = $1
                 ORDER BY created_at DESC
@@ -531,6 +890,166 @@ impl<'c> BrowserSessionRepository for PgBrowserSessionRepository<'c> {
         Ok(Some(authentication))
     }
 
+    #[tracing::instrument(
+        name = "db.browser_session.get_last_authentication_batch",
+        skip_all,
+        fields(
+            db.statement,
+        ),
+        err,
+    )]
+    async fn get_last_authentication_batch(
+        &mut self,
+        user_session_ids: BTreeSet<Ulid>,
+    ) -> Result<BTreeMap<Ulid, Authentication>, Self::Error> {
+        let ids: Vec<Uuid> = user_session_ids.into_iter().map(Uuid::from).collect();
+        let res = sqlx::query!(
+            r#"
+                SELECT DISTINCT ON (user_session_id)
+                       user_session_id
+                     , user_session_authentication_id
+                     , created_at
+                     , user_password_id
+                     , upstream_oauth_authorization_session_id
+                     , upstream_oauth_link_id
+                     , user_webauthn_credential_id
+                     , user_totp_id
+                     , user_recovery_code_id
+                FROM user_session_authentications
+                WHERE user_session_id = ANY($1::uuid[])
+                ORDER BY user_session_id, created_at DESC
+            "#,
+            &ids,
+        )
+        .traced()
+        .fetch_all(&mut *self.conn)
+        .await?;
+
+        res.into_iter()
+            .map(|r| {
+                let lookup = AuthenticationLookup {
+                    user_session_authentication_id: r.user_session_authentication_id,
+                    created_at: r.created_at,
+                    user_password_id: r.user_password_id,
+                    upstream_oauth_authorization_session_id: r
+                        .upstream_oauth_authorization_session_id,
+                    upstream_oauth_link_id: r.upstream_oauth_link_id,
+                    user_webauthn_credential_id: r.user_webauthn_credential_id,
+                    user_totp_id: r.user_totp_id,
+                    user_recovery_code_id: r.user_recovery_code_id,
+                };
+
+                Authentication::try_from(lookup)
+                    .map(|authentication| (r.user_session_id.into(), authentication))
+                    .map_err(DatabaseError::from)
+            })
+            .collect()
+    }
+
+    #[tracing::instrument(
+        name = "db.browser_session.list_authentications",
+        skip_all,
+        fields(
+            db.statement,
+            %user_session.id,
+        ),
+        err,
+    )]
+    async fn list_authentications(
+        &mut self,
+        user_session: &BrowserSession,
+        pagination: Pagination,
+    ) -> Result<Page<Authentication>, Self::Error> {
+        // Authentication IDs are ULIDs, so ordering them by ID in descending
+        // order lists the most recent first
+        let pagination = pagination.ordered_by(PaginationOrderBy::Id, PaginationOrder::Descending);
+
+        let (sql, arguments) = Query::select()
+            .expr_as(
+                Expr::col((
+                    UserSessionAuthentications::Table,
+                    UserSessionAuthentications::UserSessionAuthenticationId,
+                )),
+                AuthenticationLookupIden::UserSessionAuthenticationId,
+            )
+            .expr_as(
+                Expr::col((
+                    UserSessionAuthentications::Table,
+                    UserSessionAuthentications::CreatedAt,
+                )),
+                AuthenticationLookupIden::CreatedAt,
+            )
+            .expr_as(
+                Expr::col((
+                    UserSessionAuthentications::Table,
+                    UserSessionAuthentications::UserPasswordId,
+                )),
+                AuthenticationLookupIden::UserPasswordId,
+            )
+            .expr_as(
+                Expr::col((
+                    UserSessionAuthentications::Table,
+                    UserSessionAuthentications::UpstreamOauthAuthorizationSessionId,
+                )),
+                AuthenticationLookupIden::UpstreamOauthAuthorizationSessionId,
+            )
+            .expr_as(
+                Expr::col((
+                    UserSessionAuthentications::Table,
+                    UserSessionAuthentications::UpstreamOauthLinkId,
+                )),
+                AuthenticationLookupIden::UpstreamOauthLinkId,
+            )
+            .expr_as(
+                Expr::col((
+                    UserSessionAuthentications::Table,
+                    UserSessionAuthentications::UserWebauthnCredentialId,
+                )),
+                AuthenticationLookupIden::UserWebauthnCredentialId,
+            )
+            .expr_as(
+                Expr::col((
+                    UserSessionAuthentications::Table,
+                    UserSessionAuthentications::UserTotpId,
+                )),
+                AuthenticationLookupIden::UserTotpId,
+            )
+            .expr_as(
+                Expr::col((
+                    UserSessionAuthentications::Table,
+                    UserSessionAuthentications::UserRecoveryCodeId,
+                )),
+                AuthenticationLookupIden::UserRecoveryCodeId,
+            )
+            .from(UserSessionAuthentications::Table)
+            .and_where(
+                Expr::col((
+                    UserSessionAuthentications::Table,
+                    UserSessionAuthentications::UserSessionId,
+                ))
+                .eq(Uuid::from(user_session.id)),
+            )
+            .generate_pagination(
+                (
+                    UserSessionAuthentications::Table,
+                    UserSessionAuthentications::UserSessionAuthenticationId,
+                ),
+                pagination,
+            )
+            .build_sqlx(PostgresQueryBuilder);
+
+        let edges: Vec<AuthenticationLookup> = sqlx::query_as_with(&sql, arguments)
+            .traced()
+            .fetch_all(&mut *self.conn)
+            .await?;
+
+        let page = pagination
+            .process(edges)
+            .try_map(Authentication::try_from)?;
+
+        Ok(page)
+    }
+
     #[tracing::instrument(
         name = "db.browser_session.record_batch_activity",
         skip_all,
@@ -578,3 +1097,92 @@ impl<'c> BrowserSessionRepository for PgBrowserSessionRepository<'c> {
         Ok(())
     }
 }
+
+trait BrowserSessionFilterExt {
+    fn apply_filter(&mut self, filter: BrowserSessionFilter<'_>) -> &mut Self;
+}
+
+impl BrowserSessionFilterExt for sea_query::SelectStatement {
+    fn apply_filter(&mut self, filter: BrowserSessionFilter<'_>) -> &mut Self {
+        self.and_where_option(filter.user().map(|user| {
+            Expr::col((UserSessions::Table, UserSessions::UserId)).eq(Uuid::from(user.id))
+        }))
+        .and_where_option(filter.state().map(|(state, now)| {
+            // Sessions past their expiry are considered finished
+            let active = Expr::col((UserSessions::Table, UserSessions::FinishedAt))
+                .is_null()
+                .and(
+                    Expr::col((UserSessions::Table, UserSessions::ExpiresAt))
+                        .is_null()
+                        .or(Expr::col((UserSessions::Table, UserSessions::ExpiresAt)).gt(now)),
+                );
+
+            if state.is_active() {
+                active
+            } else {
+                active.not()
+            }
+        }))
+        .and_where_option(filter.created_after().map(|created_after| {
+            Expr::col((UserSessions::Table, UserSessions::CreatedAt)).gte(created_after)
+        }))
+        .and_where_option(filter.created_before().map(|created_before| {
+            Expr::col((UserSessions::Table, UserSessions::CreatedAt)).lte(created_before)
+        }))
+        .and_where_option(
+            filter
+                .last_authenticated_after()
+                .map(|last_authenticated_after| {
+                    Expr::exists(
+                        Query::select()
+                            .expr(Expr::cust("1"))
+                            .from(UserSessionAuthentications::Table)
+                            .and_where(
+                                Expr::col((
+                                    UserSessionAuthentications::Table,
+                                    UserSessionAuthentications::UserSessionId,
+                                ))
+                                .equals((UserSessions::Table, UserSessions::UserSessionId)),
+                            )
+                            .and_where(
+                                Expr::col((
+                                    UserSessionAuthentications::Table,
+                                    UserSessionAuthentications::CreatedAt,
+                                ))
+                                .gte(last_authenticated_after),
+                            )
+                            .take(),
+                    )
+                }),
+        )
+        .and_where_option(
+            filter
+                .last_authenticated_before()
+                .map(|last_authenticated_before| {
+                    // Sessions without any authentication are considered to
+                    // have been authenticated infinitely long ago
+                    Expr::exists(
+                        Query::select()
+                            .expr(Expr::cust("1"))
+                            .from(UserSessionAuthentications::Table)
+                            .and_where(
+                                Expr::col((
+                                    UserSessionAuthentications::Table,
+                                    UserSessionAuthentications::UserSessionId,
+                                ))
+                                .equals((UserSessions::Table, UserSessions::UserSessionId)),
+                            )
+                            .and_where(
+                                Expr::col((
+                                    UserSessionAuthentications::Table,
+                                    UserSessionAuthentications::CreatedAt,
+                                ))
+                                .gt(last_authenticated_before),
+                            )
+                            .take(),
+                    )
+                    .not()
+                }),
+        )
+    }
+}
@@ -12,18 +12,26 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use std::collections::BTreeSet;
+
 use chrono::Duration;
+use mas_data_model::{
+    AuthenticationMethod, BrowserSession, UserEmailVerification, UserEmailVerificationState,
+};
 use mas_storage::{
     clock::MockClock,
+    pagination::{PaginationCursor, PaginationOrder, PaginationOrderBy},
     user::{
         BrowserSessionFilter, BrowserSessionRepository, UserEmailFilter, UserEmailRepository,
-        UserPasswordRepository, UserRepository,
+        UserFilter, UserPasswordRepository, UserPasswordResetTokenRepository,
+        UserRecoveryCodeRepository, UserRepository, UserTotpRepository, UserWebAuthnRepository,
     },
-    Pagination, Repository, RepositoryAccess,
+    Clock, Page, Pagination, Repository, RepositoryAccess,
 };
 use rand::SeedableRng;
 use rand_chacha::ChaChaRng;
 use sqlx::PgPool;
+use ulid::Ulid;
 
 use crate::PgRepository;
 
@@ -95,6 +103,49 @@ async fn test_user_repo(pool: PgPool) {
     let user = repo.user().unlock(user).await.unwrap();
     assert!(user.is_valid());
 
+    // Try deactivating a user
+    assert!(!user.is_deactivated());
+    let user = repo.user().deactivate(&clock, user).await.unwrap();
+    assert!(user.is_deactivated());
+    assert!(!user.is_valid());
+
+    // Check that the property is retrieved on lookups
+    let user = repo.user().lookup(user.id).await.unwrap().unwrap();
+    assert!(user.is_deactivated());
+    let user = repo
+        .user()
+        .find_by_username(USERNAME)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(user.is_deactivated());
+
+    // Deactivated users can't start new browser sessions
+    assert!(repo
+        .browser_session()
+        .add(&mut rng, &clock, &user, None, None, None)
+        .await
+        .is_err());
+
+    // Deactivating a second time should not fail
+    let user = repo.user().deactivate(&clock, user).await.unwrap();
+    assert!(user.is_deactivated());
+
+    // Try reactivating a user
+    let user = repo.user().reactivate(user).await.unwrap();
+    assert!(!user.is_deactivated());
+    assert!(user.is_valid());
+
+    // Check that the property is retrieved on lookup
+    let user = repo.user().lookup(user.id).await.unwrap().unwrap();
+    assert!(!user.is_deactivated());
+
+    // Reactivated users can start browser sessions again
+    repo.browser_session()
+        .add(&mut rng, &clock, &user, None, None, None)
+        .await
+        .unwrap();
+
     // Set the can_request_admin flag
     let user = repo.user().set_can_request_admin(user, true).await.unwrap();
     assert!(user.can_request_admin);
@@ -115,6 +166,102 @@ async fn test_user_repo(pool: PgPool) {
     let user = repo.user().lookup(user.id).await.unwrap().unwrap();
     assert!(!user.can_request_admin);
 
+    // Being able to request admin doesn't make the user an admin
+    assert!(!user.is_admin);
+
+    // Set the is_admin flag
+    let user = repo.user().set_admin(user, true).await.unwrap();
+    assert!(user.is_admin);
+
+    // Check that the property is retrieved on lookup
+    let user = repo.user().lookup(user.id).await.unwrap().unwrap();
+    assert!(user.is_admin);
+
+    // Unset the is_admin flag
+    let user = repo.user().set_admin(user, false).await.unwrap();
+    assert!(!user.is_admin);
+
+    // Check that the property is retrieved on lookup
+    let user = repo.user().lookup(user.id).await.unwrap().unwrap();
+    assert!(!user.is_admin);
+
+    repo.save().await.unwrap();
+}
+
+/// Test listing and counting users with filters
+#[sqlx::test(migrator = "crate::MIGRATOR")]
+async fn test_user_list(pool: PgPool) {
+    let mut repo = PgRepository::from_pool(&pool).await.unwrap().boxed();
+    let mut rng = ChaChaRng::seed_from_u64(42);
+    let clock = MockClock::default();
+
+    let alice = repo
+        .user()
+        .add(&mut rng, &clock, "alice".to_owned())
+        .await
+        .unwrap();
+    let alice = repo
+        .user()
+        .set_can_request_admin(alice, true)
+        .await
+        .unwrap();
+    clock.advance(Duration::minutes(1));
+    let albert = repo
+        .user()
+        .add(&mut rng, &clock, "albert".to_owned())
+        .await
+        .unwrap();
+    clock.advance(Duration::minutes(1));
+    let bob = repo
+        .user()
+        .add(&mut rng, &clock, "bob".to_owned())
+        .await
+        .unwrap();
+    clock.advance(Duration::minutes(1));
+    // This one checks that the LIKE wildcards are escaped
+    let underscore = repo
+        .user()
+        .add(&mut rng, &clock, "a_b".to_owned())
+        .await
+        .unwrap();
+
+    let all = UserFilter::new();
+    assert_eq!(repo.user().count(all).await.unwrap(), 4);
+    let page = repo.user().list(all, Pagination::first(10)).await.unwrap();
+    assert!(!page.has_next_page);
+    assert_eq!(
+        page.edges,
+        vec![alice.clone(), albert.clone(), bob, underscore.clone()]
+    );
+
+    let al = UserFilter::new().with_username_prefix("al");
+    assert_eq!(repo.user().count(al).await.unwrap(), 2);
+    let page = repo.user().list(al, Pagination::first(10)).await.unwrap();
+    assert_eq!(page.edges, vec![alice.clone(), albert]);
+
+    let escaped = UserFilter::new().with_username_prefix("a_");
+    assert_eq!(repo.user().count(escaped).await.unwrap(), 1);
+    let page = repo
+        .user()
+        .list(escaped, Pagination::first(10))
+        .await
+        .unwrap();
+    assert_eq!(page.edges, vec![underscore]);
+
+    let admins = UserFilter::new().with_can_request_admin(true);
+    assert_eq!(repo.user().count(admins).await.unwrap(), 1);
+    let page = repo
+        .user()
+        .list(admins, Pagination::first(10))
+        .await
+        .unwrap();
+    assert_eq!(page.edges, vec![alice]);
+
+    // Pagination should work too
+    let page = repo.user().list(all, Pagination::first(2)).await.unwrap();
+    assert!(page.has_next_page);
+    assert_eq!(page.edges.len(), 2);
+
     repo.save().await.unwrap();
 }
 
@@ -202,7 +349,8 @@ async fn test_user_email_repo(pool: PgPool) {
     assert_eq!(verification.code, CODE);
 
     // A single user email can have multiple verification at the same time
-    let _verification2 = repo
+    clock.advance(Duration::try_minutes(1).unwrap());
+    let verification2 = repo
         .user_email()
         .add_verification_code(
             &mut rng,
@@ -214,6 +362,23 @@ async fn test_user_email_repo(pool: PgPool) {
         .await
         .unwrap();
 
+    // The latest verification code is the second one
+    let latest = repo
+        .user_email()
+        .find_latest_verification_code(&clock, &user_email)
+        .await
+        .unwrap()
+        .expect("no user email verification was found");
+    assert_eq!(latest.id, verification2.id);
+
+    // An unknown code is not found
+    assert!(repo
+        .user_email()
+        .find_verification_code(&clock, &user_email, "000000")
+        .await
+        .unwrap()
+        .is_none());
+
     let verification = repo
         .user_email()
         .find_verification_code(&clock, &user_email, CODE)
@@ -346,6 +511,104 @@ async fn test_user_email_repo(pool: PgPool) {
     repo.save().await.unwrap();
 }
 
+/// Test the failed attempts counter and the per-address lookup of the latest
+/// email verification code
+#[sqlx::test(migrator = "crate::MIGRATOR")]
+async fn test_user_email_verification_attempts(pool: PgPool) {
+    const CODE: &str = "012345";
+    const EMAIL: &str = "john@example.com";
+
+    let mut repo = PgRepository::from_pool(&pool).await.unwrap().boxed();
+    let mut rng = ChaChaRng::seed_from_u64(42);
+    let clock = MockClock::default();
+
+    let john = repo
+        .user()
+        .add(&mut rng, &clock, "john".to_owned())
+        .await
+        .unwrap();
+    let john_email = repo
+        .user_email()
+        .add(&mut rng, &clock, &john, EMAIL.to_owned())
+        .await
+        .unwrap();
+
+    // Another user adds the same address
+    let jane = repo
+        .user()
+        .add(&mut rng, &clock, "jane".to_owned())
+        .await
+        .unwrap();
+    let jane_email = repo
+        .user_email()
+        .add(&mut rng, &clock, &jane, EMAIL.to_owned())
+        .await
+        .unwrap();
+
+    let verification = repo
+        .user_email()
+        .add_verification_code(
+            &mut rng,
+            &clock,
+            &john_email,
+            Duration::try_hours(8).unwrap(),
+            CODE.to_owned(),
+        )
+        .await
+        .unwrap();
+
+    // The latest code sent to the address is found through both user emails
+    let latest = repo
+        .user_email()
+        .find_latest_verification_code(&clock, &jane_email)
+        .await
+        .unwrap()
+        .expect("no user email verification was found");
+    assert_eq!(latest.id, verification.id);
+
+    // Failed attempts on another user email don't count
+    for _ in 0..UserEmailVerification::MAX_FAILED_ATTEMPTS {
+        repo.user_email()
+            .record_failed_verification_attempt(&clock, &jane_email)
+            .await
+            .unwrap();
+    }
+
+    for _ in 1..UserEmailVerification::MAX_FAILED_ATTEMPTS {
+        repo.user_email()
+            .record_failed_verification_attempt(&clock, &john_email)
+            .await
+            .unwrap();
+    }
+
+    // The code is still valid after one attempt less than the maximum
+    let verification = repo
+        .user_email()
+        .find_verification_code(&clock, &john_email, CODE)
+        .await
+        .unwrap()
+        .expect("user email verification was not found");
+    assert!(verification.is_valid());
+
+    // It expires after the last one
+    repo.user_email()
+        .record_failed_verification_attempt(&clock, &john_email)
+        .await
+        .unwrap();
+    let verification = repo
+        .user_email()
+        .find_verification_code(&clock, &john_email, CODE)
+        .await
+        .unwrap()
+        .expect("user email verification was not found");
+    assert!(matches!(
+        verification.state,
+        UserEmailVerificationState::Expired { .. }
+    ));
+
+    repo.save().await.unwrap();
+}
+
 /// Test the user password repository implementation.
 #[sqlx::test(migrator = "crate::MIGRATOR")]
 async fn test_user_password_repo(pool: PgPool) {
@@ -444,19 +707,21 @@ async fn test_user_session(pool: PgPool) {
         .unwrap();
 
     let all = BrowserSessionFilter::default().for_user(&user);
-    let active = all.active_only();
-    let finished = all.finished_only();
+    let active = all.active_only(clock.now());
+    let finished = all.finished_only(clock.now());
 
     assert_eq!(repo.browser_session().count(all).await.unwrap(), 0);
     assert_eq!(repo.browser_session().count(active).await.unwrap(), 0);
     assert_eq!(repo.browser_session().count(finished).await.unwrap(), 0);
 
+    let ip_address = "203.0.113.42".parse().unwrap();
     let session = repo
         .browser_session()
-        .add(&mut rng, &clock, &user, None)
+        .add(&mut rng, &clock, &user, None, Some(ip_address), None)
         .await
         .unwrap();
     assert_eq!(session.user.id, user.id);
+    assert_eq!(session.ip_address, Some(ip_address));
     assert!(session.finished_at.is_none());
 
     assert_eq!(repo.browser_session().count(all).await.unwrap(), 1);
@@ -475,13 +740,14 @@ async fn test_user_session(pool: PgPool) {
 
     let session_lookup = repo
         .browser_session()
-        .lookup(session.id)
+        .lookup(&clock, session.id)
         .await
         .unwrap()
         .expect("user session not found");
 
     assert_eq!(session_lookup.id, session.id);
     assert_eq!(session_lookup.user.id, user.id);
+    assert_eq!(session_lookup.ip_address, Some(ip_address));
     assert!(session_lookup.finished_at.is_none());
 
     // Finish the session
@@ -507,7 +773,7 @@ async fn test_user_session(pool: PgPool) {
     // Reload the session
     let session_lookup = repo
         .browser_session()
-        .lookup(session.id)
+        .lookup(&clock, session.id)
         .await
         .unwrap()
         .expect("user session not found");
@@ -518,8 +784,65 @@ async fn test_user_session(pool: PgPool) {
     assert!(session_lookup.finished_at.is_some());
 }
 
+/// Test looking up multiple browser sessions at once
 #[sqlx::test(migrator = "crate::MIGRATOR")]
-async fn test_user_terms(pool: PgPool) {
+async fn test_user_session_lookup_batch(pool: PgPool) {
+    let mut repo = PgRepository::from_pool(&pool).await.unwrap();
+    let mut rng = ChaChaRng::seed_from_u64(42);
+    let clock = MockClock::default();
+
+    let alice = repo
+        .user()
+        .add(&mut rng, &clock, "alice".to_owned())
+        .await
+        .unwrap();
+    let bob = repo
+        .user()
+        .add(&mut rng, &clock, "bob".to_owned())
+        .await
+        .unwrap();
+
+    let session1 = repo
+        .browser_session()
+        .add(&mut rng, &clock, &alice, None, None, None)
+        .await
+        .unwrap();
+    let session2 = repo
+        .browser_session()
+        .add(&mut rng, &clock, &bob, None, None, None)
+        .await
+        .unwrap();
+    // Finished sessions are also returned
+    let session2 = repo
+        .browser_session()
+        .finish(&clock, session2)
+        .await
+        .unwrap();
+
+    // Looking up nothing returns nothing
+    let sessions = repo
+        .browser_session()
+        .lookup_batch(&clock, &[])
+        .await
+        .unwrap();
+    assert!(sessions.is_empty());
+
+    // Sessions which don't exist are not in the map
+    let sessions = repo
+        .browser_session()
+        .lookup_batch(&clock, &[session1.id, Ulid::nil(), session2.id])
+        .await
+        .unwrap();
+    assert_eq!(sessions.len(), 2);
+    assert_eq!(sessions[&session1.id], session1);
+    assert_eq!(sessions[&session2.id], session2);
+    assert_eq!(sessions[&session2.id].user.id, bob.id);
+    assert!(!sessions.contains_key(&Ulid::nil()));
+}
+
+/// Test that browser sessions are considered finished once they expire
+#[sqlx::test(migrator = "crate::MIGRATOR")]
+async fn test_user_session_expiry(pool: PgPool) {
     let mut repo = PgRepository::from_pool(&pool).await.unwrap();
     let mut rng = ChaChaRng::seed_from_u64(42);
     let clock = MockClock::default();
@@ -530,45 +853,1121 @@ async fn test_user_terms(pool: PgPool) {
         .await
         .unwrap();
 
-    // Accepting the terms should work
-    repo.user_terms()
-        .accept_terms(
-            &mut rng,
-            &clock,
-            &user,
-            "https://example.com/terms".parse().unwrap(),
-        )
+    let expires_at = clock.now() + Duration::try_days(1).unwrap();
+    let session = repo
+        .browser_session()
+        .add(&mut rng, &clock, &user, None, None, Some(expires_at))
         .await
         .unwrap();
+    assert_eq!(session.expires_at, Some(expires_at));
 
-    // Accepting a second time should also work
-    repo.user_terms()
-        .accept_terms(
-            &mut rng,
-            &clock,
-            &user,
-            "https://example.com/terms".parse().unwrap(),
-        )
+    let forever = repo
+        .browser_session()
+        .add(&mut rng, &clock, &user, None, None, None)
         .await
         .unwrap();
+    assert_eq!(forever.expires_at, None);
 
-    // Accepting a different terms should also work
-    repo.user_terms()
-        .accept_terms(
-            &mut rng,
-            &clock,
-            &user,
-            "https://example.com/terms?v=2".parse().unwrap(),
-        )
+    // The session is still active right before it expires
+    clock.advance(Duration::try_days(1).unwrap() - Duration::try_seconds(1).unwrap());
+    let session_lookup = repo
+        .browser_session()
+        .lookup(&clock, session.id)
+        .await
+        .unwrap()
+        .expect("user session not found");
+    assert_eq!(session_lookup.expires_at, Some(expires_at));
+    assert!(session_lookup.active());
+
+    // Once it expired, it is considered finished at its expiry
+    clock.advance(Duration::try_seconds(1).unwrap());
+    let session_lookup = repo
+        .browser_session()
+        .lookup(&clock, session.id)
+        .await
+        .unwrap()
+        .expect("user session not found");
+    assert_eq!(session_lookup.finished_at, Some(expires_at));
+    assert!(!session_lookup.active());
+
+    let sessions = repo
+        .browser_session()
+        .lookup_batch(&clock, &[session.id])
         .await
         .unwrap();
+    assert_eq!(sessions[&session.id].finished_at, Some(expires_at));
 
-    let mut conn = repo.into_inner();
+    // It is counted as finished, not as active
+    let all = BrowserSessionFilter::new().for_user(&user);
+    let active = all.active_only(clock.now());
+    let finished = all.finished_only(clock.now());
+    assert_eq!(repo.browser_session().count(active).await.unwrap(), 1);
+    assert_eq!(repo.browser_session().count(finished).await.unwrap(), 1);
+    let page = repo
+        .browser_session()
+        .list(active, Pagination::first(10))
+        .await
+        .unwrap();
+    assert_eq!(page.edges.len(), 1);
+    assert_eq!(page.edges[0].id, forever.id);
 
-    // We should have two rows, as the first terms was deduped
-    let res: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM user_terms")
-        .fetch_one(&mut *conn)
+    // Sessions without an expiry stay active
+    clock.advance(Duration::try_days(365).unwrap());
+    let forever_lookup = repo
+        .browser_session()
+        .lookup(&clock, forever.id)
+        .await
+        .unwrap()
+        .expect("user session not found");
+    assert!(forever_lookup.active());
+}
+
+/// Test ordering browser sessions by their finish date
+#[sqlx::test(migrator = "crate::MIGRATOR")]
+async fn test_user_session_ordering(pool: PgPool) {
+    let mut repo = PgRepository::from_pool(&pool).await.unwrap();
+    let mut rng = ChaChaRng::seed_from_u64(42);
+    let clock = MockClock::default();
+
+    let user = repo
+        .user()
+        .add(&mut rng, &clock, "john".to_owned())
         .await
         .unwrap();
-    assert_eq!(res, 2);
+
+    let mut sessions = Vec::new();
+    for _ in 0..4 {
+        clock.advance(Duration::minutes(1));
+        let session = repo
+            .browser_session()
+            .add(&mut rng, &clock, &user, None, None, None)
+            .await
+            .unwrap();
+        sessions.push(session);
+    }
+    let [session1, session2, session3, session4] = sessions.try_into().unwrap();
+
+    // Finish the second session first, then the first and the fourth at the
+    // same time. The third one stays active
+    clock.advance(Duration::minutes(1));
+    let session2 = repo
+        .browser_session()
+        .finish(&clock, session2)
+        .await
+        .unwrap();
+    clock.advance(Duration::minutes(1));
+    let session1 = repo
+        .browser_session()
+        .finish(&clock, session1)
+        .await
+        .unwrap();
+    let session4 = repo
+        .browser_session()
+        .finish(&clock, session4)
+        .await
+        .unwrap();
+
+    let all = BrowserSessionFilter::new().for_user(&user);
+    let most_recently_finished = |pagination: Pagination| {
+        pagination.ordered_by(PaginationOrderBy::FinishedAt, PaginationOrder::Descending)
+    };
+
+    // Active sessions come first, then sessions finished at the same time are
+    // ordered by ID
+    let page = repo
+        .browser_session()
+        .list(all, most_recently_finished(Pagination::first(2)))
+        .await
+        .unwrap();
+    assert!(page.has_next_page);
+    let ids: Vec<_> = page.edges.iter().map(|s| s.id).collect();
+    assert_eq!(ids, vec![session3.id, session4.id]);
+
+    // Continue from the last session of the page
+    let cursor = PaginationCursor::new(session4.id, session4.finished_at);
+    let page = repo
+        .browser_session()
+        .list(
+            all,
+            most_recently_finished(Pagination::first(2).after(cursor)),
+        )
+        .await
+        .unwrap();
+    assert!(!page.has_next_page);
+    let ids: Vec<_> = page.edges.iter().map(|s| s.id).collect();
+    assert_eq!(ids, vec![session1.id, session2.id]);
+
+    // Paginate backwards from the first session of that page
+    let cursor = PaginationCursor::new(session1.id, session1.finished_at);
+    let page = repo
+        .browser_session()
+        .list(
+            all,
+            most_recently_finished(Pagination::last(10).before(cursor)),
+        )
+        .await
+        .unwrap();
+    assert!(!page.has_previous_page);
+    let ids: Vec<_> = page.edges.iter().map(|s| s.id).collect();
+    assert_eq!(ids, vec![session3.id, session4.id]);
+
+    // When sorting in ascending order, active sessions come last
+    let cursor = PaginationCursor::new(session3.id, None);
+    let page = repo
+        .browser_session()
+        .list(
+            all,
+            Pagination::last(10)
+                .after(cursor)
+                .ordered_by(PaginationOrderBy::FinishedAt, PaginationOrder::Ascending),
+        )
+        .await
+        .unwrap();
+    assert!(page.edges.is_empty());
+
+    // Sessions can also be ordered by creation date
+    let page = repo
+        .browser_session()
+        .list(
+            all,
+            Pagination::last(3)
+                .ordered_by(PaginationOrderBy::CreatedAt, PaginationOrder::Descending),
+        )
+        .await
+        .unwrap();
+    assert!(page.has_previous_page);
+    let ids: Vec<_> = page.edges.iter().map(|s| s.id).collect();
+    assert_eq!(ids, vec![session3.id, session2.id, session1.id]);
+}
+
+/// Test loading the last authentication of multiple browser sessions at once
+#[sqlx::test(migrator = "crate::MIGRATOR")]
+async fn test_user_session_last_authentication_batch(pool: PgPool) {
+    let mut repo = PgRepository::from_pool(&pool).await.unwrap();
+    let mut rng = ChaChaRng::seed_from_u64(42);
+    let clock = MockClock::default();
+
+    let user = repo
+        .user()
+        .add(&mut rng, &clock, "john".to_owned())
+        .await
+        .unwrap();
+    let password = repo
+        .user_password()
+        .add(&mut rng, &clock, &user, 1, "hashed".to_owned(), None)
+        .await
+        .unwrap();
+
+    let session1 = repo
+        .browser_session()
+        .add(&mut rng, &clock, &user, None, None, None)
+        .await
+        .unwrap();
+    let session2 = repo
+        .browser_session()
+        .add(&mut rng, &clock, &user, None, None, None)
+        .await
+        .unwrap();
+
+    // Loading an empty batch should work
+    let batch = repo
+        .browser_session()
+        .get_last_authentication_batch(BTreeSet::new())
+        .await
+        .unwrap();
+    assert!(batch.is_empty());
+
+    // Authenticate the first session twice
+    repo.browser_session()
+        .authenticate_with_password(&mut rng, &clock, &session1, &password)
+        .await
+        .unwrap();
+    clock.advance(Duration::minutes(1));
+    let authentication = repo
+        .browser_session()
+        .authenticate_with_password(&mut rng, &clock, &session1, &password)
+        .await
+        .unwrap();
+
+    // Only the most recent authentication of the first session should be
+    // returned, and the second session should be missing
+    let batch = repo
+        .browser_session()
+        .get_last_authentication_batch(BTreeSet::from([session1.id, session2.id]))
+        .await
+        .unwrap();
+    assert_eq!(batch.len(), 1);
+    assert_eq!(batch.get(&session1.id), Some(&authentication));
+    assert!(!batch.contains_key(&session2.id));
+}
+
+/// Test listing all the authentications of a browser session
+#[sqlx::test(migrator = "crate::MIGRATOR")]
+async fn test_user_session_list_authentications(pool: PgPool) {
+    let mut repo = PgRepository::from_pool(&pool).await.unwrap();
+    let mut rng = ChaChaRng::seed_from_u64(42);
+    let clock = MockClock::default();
+
+    let user = repo
+        .user()
+        .add(&mut rng, &clock, "john".to_owned())
+        .await
+        .unwrap();
+    let password = repo
+        .user_password()
+        .add(&mut rng, &clock, &user, 1, "hashed".to_owned(), None)
+        .await
+        .unwrap();
+
+    let session = repo
+        .browser_session()
+        .add(&mut rng, &clock, &user, None, None, None)
+        .await
+        .unwrap();
+    let other_session = repo
+        .browser_session()
+        .add(&mut rng, &clock, &user, None, None, None)
+        .await
+        .unwrap();
+
+    // The session doesn't have any authentication yet
+    let page = repo
+        .browser_session()
+        .list_authentications(&session, Pagination::first(10))
+        .await
+        .unwrap();
+    assert!(page.edges.is_empty());
+    assert!(!page.has_next_page);
+
+    let mut authentications = Vec::new();
+    for _ in 0..3 {
+        clock.advance(Duration::minutes(1));
+        let authentication = repo
+            .browser_session()
+            .authenticate_with_password(&mut rng, &clock, &session, &password)
+            .await
+            .unwrap();
+        authentications.push(authentication);
+    }
+
+    // Authentications of other sessions are not listed
+    repo.browser_session()
+        .authenticate_with_password(&mut rng, &clock, &other_session, &password)
+        .await
+        .unwrap();
+
+    // The most recent authentications come first
+    let page = repo
+        .browser_session()
+        .list_authentications(&session, Pagination::first(2))
+        .await
+        .unwrap();
+    assert!(page.has_next_page);
+    assert_eq!(
+        page.edges,
+        vec![authentications[2].clone(), authentications[1].clone()]
+    );
+    assert!(matches!(
+        page.edges[0].authentication_method,
+        AuthenticationMethod::Password { user_password_id } if user_password_id == password.id
+    ));
+
+    let page = repo
+        .browser_session()
+        .list_authentications(&session, Pagination::first(2).after(authentications[1].id))
+        .await
+        .unwrap();
+    assert!(!page.has_next_page);
+    assert_eq!(page.edges, vec![authentications[0].clone()]);
+}
+
+/// Test finishing all the sessions of a user at once
+#[sqlx::test(migrator = "crate::MIGRATOR")]
+async fn test_user_session_finish_all_for_user(pool: PgPool) {
+    let mut repo = PgRepository::from_pool(&pool).await.unwrap();
+    let mut rng = ChaChaRng::seed_from_u64(42);
+    let clock = MockClock::default();
+
+    let alice = repo
+        .user()
+        .add(&mut rng, &clock, "alice".to_owned())
+        .await
+        .unwrap();
+    let bob = repo
+        .user()
+        .add(&mut rng, &clock, "bob".to_owned())
+        .await
+        .unwrap();
+
+    let mut alice_sessions = Vec::new();
+    for _ in 0..3 {
+        let session = repo
+            .browser_session()
+            .add(&mut rng, &clock, &alice, None, None, None)
+            .await
+            .unwrap();
+        alice_sessions.push(session);
+    }
+    let bob_session = repo
+        .browser_session()
+        .add(&mut rng, &clock, &bob, None, None, None)
+        .await
+        .unwrap();
+
+    // Sessions which are already finished are left untouched
+    let finished = repo
+        .browser_session()
+        .finish(&clock, alice_sessions.pop().unwrap())
+        .await
+        .unwrap();
+
+    clock.advance(Duration::minutes(1));
+    let count = repo
+        .browser_session()
+        .finish_all_for_user(&clock, &alice)
+        .await
+        .unwrap();
+    assert_eq!(count, 2);
+
+    let alice_filter = BrowserSessionFilter::new().for_user(&alice);
+    assert_eq!(
+        repo.browser_session()
+            .count(alice_filter.active_only(clock.now()))
+            .await
+            .unwrap(),
+        0
+    );
+    for session in alice_sessions {
+        let session = repo
+            .browser_session()
+            .lookup(&clock, session.id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(session.finished_at, Some(clock.now()));
+    }
+    let session = repo
+        .browser_session()
+        .lookup(&clock, finished.id)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(session.finished_at, finished.finished_at);
+
+    // The sessions of other users are still active
+    let session = repo
+        .browser_session()
+        .lookup(&clock, bob_session.id)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(session.active());
+
+    // Nothing is left to finish
+    let count = repo
+        .browser_session()
+        .finish_all_for_user(&clock, &alice)
+        .await
+        .unwrap();
+    assert_eq!(count, 0);
+}
+
+/// Test filtering browser sessions by their creation and authentication dates
+#[sqlx::test(migrator = "crate::MIGRATOR")]
+async fn test_user_session_date_filters(pool: PgPool) {
+    let mut repo = PgRepository::from_pool(&pool).await.unwrap();
+    let mut rng = ChaChaRng::seed_from_u64(42);
+    let clock = MockClock::default();
+
+    let user = repo
+        .user()
+        .add(&mut rng, &clock, "john".to_owned())
+        .await
+        .unwrap();
+    let password = repo
+        .user_password()
+        .add(&mut rng, &clock, &user, 1, "hashed".to_owned(), None)
+        .await
+        .unwrap();
+
+    // Create three sessions, one minute apart
+    let mut sessions = Vec::new();
+    for _ in 0..3 {
+        clock.advance(Duration::minutes(1));
+        let session = repo
+            .browser_session()
+            .add(&mut rng, &clock, &user, None, None, None)
+            .await
+            .unwrap();
+        sessions.push(session);
+    }
+    let [session1, session2, session3] = sessions.try_into().unwrap();
+
+    let all = BrowserSessionFilter::new().for_user(&user);
+    let list = |page: Page<BrowserSession>| page.edges.iter().map(|s| s.id).collect::<Vec<_>>();
+
+    // Bounds are inclusive
+    let filter = all.with_created_after(session2.created_at);
+    let page = repo
+        .browser_session()
+        .list(filter, Pagination::first(10))
+        .await
+        .unwrap();
+    assert_eq!(list(page), vec![session2.id, session3.id]);
+    assert_eq!(repo.browser_session().count(filter).await.unwrap(), 2);
+
+    let filter = all.with_created_before(session2.created_at);
+    let page = repo
+        .browser_session()
+        .list(filter, Pagination::first(10))
+        .await
+        .unwrap();
+    assert_eq!(list(page), vec![session1.id, session2.id]);
+    assert_eq!(repo.browser_session().count(filter).await.unwrap(), 2);
+
+    let filter = all
+        .with_created_after(session2.created_at)
+        .with_created_before(session2.created_at);
+    let page = repo
+        .browser_session()
+        .list(filter, Pagination::first(10))
+        .await
+        .unwrap();
+    assert_eq!(list(page), vec![session2.id]);
+    assert_eq!(repo.browser_session().count(filter).await.unwrap(), 1);
+
+    // Authenticate the first session now, and the third one later
+    repo.browser_session()
+        .authenticate_with_password(&mut rng, &clock, &session1, &password)
+        .await
+        .unwrap();
+    clock.advance(Duration::minutes(10));
+    let recently = clock.now();
+    repo.browser_session()
+        .authenticate_with_password(&mut rng, &clock, &session3, &password)
+        .await
+        .unwrap();
+
+    // Only the third session was authenticated recently, and the second one
+    // was never authenticated
+    let filter = all.with_last_authenticated_after(recently);
+    let page = repo
+        .browser_session()
+        .list(filter, Pagination::first(10))
+        .await
+        .unwrap();
+    assert_eq!(list(page), vec![session3.id]);
+    assert_eq!(repo.browser_session().count(filter).await.unwrap(), 1);
+
+    let filter = all.with_last_authenticated_after(session1.created_at);
+    assert_eq!(repo.browser_session().count(filter).await.unwrap(), 2);
+
+    // The first session was last authenticated before the cutoff, and the
+    // second one, never authenticated, is treated as infinitely old
+    let filter = all.with_last_authenticated_before(recently - Duration::minutes(1));
+    let page = repo
+        .browser_session()
+        .list(filter, Pagination::first(10))
+        .await
+        .unwrap();
+    assert_eq!(list(page), vec![session1.id, session2.id]);
+    assert_eq!(repo.browser_session().count(filter).await.unwrap(), 2);
+
+    // Bounds are inclusive
+    let filter = all.with_last_authenticated_before(recently);
+    assert_eq!(repo.browser_session().count(filter).await.unwrap(), 3);
+
+    // Re-authenticating the first session makes it recent again
+    repo.browser_session()
+        .authenticate_with_password(&mut rng, &clock, &session1, &password)
+        .await
+        .unwrap();
+    let filter = all.with_last_authenticated_before(recently - Duration::minutes(1));
+    let page = repo
+        .browser_session()
+        .list(filter, Pagination::first(10))
+        .await
+        .unwrap();
+    assert_eq!(list(page), vec![session2.id]);
+    assert_eq!(repo.browser_session().count(filter).await.unwrap(), 1);
+
+    // Both bounds can be combined
+    let filter = all
+        .with_last_authenticated_after(recently)
+        .with_last_authenticated_before(recently);
+    let page = repo
+        .browser_session()
+        .list(filter, Pagination::first(10))
+        .await
+        .unwrap();
+    assert_eq!(list(page), vec![session1.id, session3.id]);
+}
+
+/// Test that the total count of browser sessions is returned with the page
+#[sqlx::test(migrator = "crate::MIGRATOR")]
+async fn test_user_session_total_count(pool: PgPool) {
+    let mut repo = PgRepository::from_pool(&pool).await.unwrap();
+    let mut rng = ChaChaRng::seed_from_u64(42);
+    let clock = MockClock::default();
+
+    let user = repo
+        .user()
+        .add(&mut rng, &clock, "john".to_owned())
+        .await
+        .unwrap();
+
+    let all = BrowserSessionFilter::new().for_user(&user);
+    let active = all.active_only(clock.now());
+
+    // The count is only returned when requested
+    let page = repo
+        .browser_session()
+        .list(all, Pagination::first(2))
+        .await
+        .unwrap();
+    assert_eq!(page.total_count, None);
+
+    let page = repo
+        .browser_session()
+        .list(all, Pagination::first(2).with_count(true))
+        .await
+        .unwrap();
+    assert!(page.edges.is_empty());
+    assert_eq!(page.total_count, Some(0));
+
+    let mut sessions = Vec::new();
+    for _ in 0..5 {
+        clock.advance(Duration::try_minutes(1).unwrap());
+        let session = repo
+            .browser_session()
+            .add(&mut rng, &clock, &user, None, None, None)
+            .await
+            .unwrap();
+        sessions.push(session);
+    }
+
+    // The count covers all the sessions, not only the ones in the page
+    let page = repo
+        .browser_session()
+        .list(all, Pagination::first(2).with_count(true))
+        .await
+        .unwrap();
+    assert_eq!(page.edges.len(), 2);
+    assert!(page.has_next_page);
+    assert_eq!(page.total_count, Some(5));
+
+    // ...and isn't affected by the cursors
+    let page = repo
+        .browser_session()
+        .list(
+            all,
+            Pagination::first(2).after(sessions[3].id).with_count(true),
+        )
+        .await
+        .unwrap();
+    assert_eq!(page.edges.len(), 1);
+    assert_eq!(page.total_count, Some(5));
+
+    let page = repo
+        .browser_session()
+        .list(
+            all,
+            Pagination::first(2).after(sessions[4].id).with_count(true),
+        )
+        .await
+        .unwrap();
+    assert!(page.edges.is_empty());
+    assert_eq!(page.total_count, Some(5));
+
+    // Finishing a session removes it from the count of active sessions
+    let session = sessions.remove(0);
+    repo.browser_session()
+        .finish(&clock, session)
+        .await
+        .unwrap();
+    let page = repo
+        .browser_session()
+        .list(active, Pagination::last(2).with_count(true))
+        .await
+        .unwrap();
+    assert_eq!(page.total_count, Some(4));
+    assert_eq!(repo.browser_session().count(active).await.unwrap(), 4);
+
+    repo.save().await.unwrap();
+
+    // Deleting a session removes it from the count
+    sqlx::query("DELETE FROM user_sessions WHERE user_session_id = $1")
+        .bind(uuid::Uuid::from(sessions[0].id))
+        .execute(&pool)
+        .await
+        .unwrap();
+
+    let mut repo = PgRepository::from_pool(&pool).await.unwrap();
+    let page = repo
+        .browser_session()
+        .list(all, Pagination::first(10).with_count(true))
+        .await
+        .unwrap();
+    assert_eq!(page.edges.len(), 4);
+    assert_eq!(page.total_count, Some(4));
+
+    let page = repo
+        .browser_session()
+        .list(active, Pagination::first(10).with_count(true))
+        .await
+        .unwrap();
+    assert_eq!(page.edges.len(), 3);
+    assert_eq!(page.total_count, Some(3));
+}
+
+#[sqlx::test(migrator = "crate::MIGRATOR")]
+async fn test_user_terms(pool: PgPool) {
+    let mut repo = PgRepository::from_pool(&pool).await.unwrap();
+    let mut rng = ChaChaRng::seed_from_u64(42);
+    let clock = MockClock::default();
+
+    let user = repo
+        .user()
+        .add(&mut rng, &clock, "john".to_owned())
+        .await
+        .unwrap();
+
+    // Accepting the terms should work
+    repo.user_terms()
+        .accept_terms(
+            &mut rng,
+            &clock,
+            &user,
+            "https://example.com/terms".parse().unwrap(),
+        )
+        .await
+        .unwrap();
+
+    // Accepting a second time should also work
+    repo.user_terms()
+        .accept_terms(
+            &mut rng,
+            &clock,
+            &user,
+            "https://example.com/terms".parse().unwrap(),
+        )
+        .await
+        .unwrap();
+
+    // Accepting a different terms should also work
+    repo.user_terms()
+        .accept_terms(
+            &mut rng,
+            &clock,
+            &user,
+            "https://example.com/terms?v=2".parse().unwrap(),
+        )
+        .await
+        .unwrap();
+
+    let mut conn = repo.into_inner();
+
+    // We should have two rows, as the first terms was deduped
+    let res: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM user_terms")
+        .fetch_one(&mut *conn)
+        .await
+        .unwrap();
+    assert_eq!(res, 2);
+}
+
+/// Test the user WebAuthn credentials repository
+#[sqlx::test(migrator = "crate::MIGRATOR")]
+async fn test_user_webauthn(pool: PgPool) {
+    let mut repo = PgRepository::from_pool(&pool).await.unwrap().boxed();
+    let mut rng = ChaChaRng::seed_from_u64(42);
+    let clock = MockClock::default();
+
+    let user = repo
+        .user()
+        .add(&mut rng, &clock, "john".to_owned())
+        .await
+        .unwrap();
+
+    // The user shouldn't have any credential initially
+    assert!(repo
+        .user_webauthn()
+        .list_for_user(&user)
+        .await
+        .unwrap()
+        .is_empty());
+
+    let credential = repo
+        .user_webauthn()
+        .add(
+            &mut rng,
+            &clock,
+            &user,
+            b"credential-id".to_vec(),
+            b"public-key".to_vec(),
+            0,
+            [0x42; 16],
+        )
+        .await
+        .unwrap();
+    assert_eq!(credential.user_id, user.id);
+    assert_eq!(credential.sign_count, 0);
+
+    // Lookup by ID and by credential ID should both work
+    let lookup = repo
+        .user_webauthn()
+        .lookup(credential.id)
+        .await
+        .unwrap()
+        .expect("credential not found");
+    assert_eq!(lookup, credential);
+
+    let lookup = repo
+        .user_webauthn()
+        .find_by_credential_id(b"credential-id")
+        .await
+        .unwrap()
+        .expect("credential not found");
+    assert_eq!(lookup, credential);
+
+    assert!(repo
+        .user_webauthn()
+        .find_by_credential_id(b"unknown")
+        .await
+        .unwrap()
+        .is_none());
+
+    // Update the signature counter
+    let credential = repo
+        .user_webauthn()
+        .update_sign_count(credential, 5)
+        .await
+        .unwrap();
+    assert_eq!(credential.sign_count, 5);
+
+    let list = repo.user_webauthn().list_for_user(&user).await.unwrap();
+    assert_eq!(list, vec![credential.clone()]);
+
+    // Authenticate a browser session with the credential
+    let session = repo
+        .browser_session()
+        .add(&mut rng, &clock, &user, None, None, None)
+        .await
+        .unwrap();
+    let authentication = repo
+        .browser_session()
+        .authenticate_with_webauthn(&mut rng, &clock, &session, &credential)
+        .await
+        .unwrap();
+    assert_eq!(
+        authentication.authentication_method,
+        AuthenticationMethod::WebAuthn {
+            user_webauthn_credential_id: credential.id
+        }
+    );
+
+    let last = repo
+        .browser_session()
+        .get_last_authentication(&session)
+        .await
+        .unwrap()
+        .expect("no authentication found");
+    assert_eq!(last, authentication);
+
+    // Delete the credential
+    repo.user_webauthn()
+        .delete(credential.clone())
+        .await
+        .unwrap();
+    assert!(repo
+        .user_webauthn()
+        .lookup(credential.id)
+        .await
+        .unwrap()
+        .is_none());
+
+    // The authentication is kept, but the method is now unknown
+    let last = repo
+        .browser_session()
+        .get_last_authentication(&session)
+        .await
+        .unwrap()
+        .expect("no authentication found");
+    assert_eq!(last.authentication_method, AuthenticationMethod::Unknown);
+}
+
+/// Test the user TOTP and recovery codes repositories
+#[sqlx::test(migrator = "crate::MIGRATOR")]
+async fn test_user_totp(pool: PgPool) {
+    let mut repo = PgRepository::from_pool(&pool).await.unwrap().boxed();
+    let mut rng = ChaChaRng::seed_from_u64(42);
+    let clock = MockClock::default();
+
+    let user = repo
+        .user()
+        .add(&mut rng, &clock, "john".to_owned())
+        .await
+        .unwrap();
+
+    // The user shouldn't have a TOTP initially
+    assert!(repo
+        .user_totp()
+        .lookup_for_user(&user)
+        .await
+        .unwrap()
+        .is_none());
+
+    let totp = repo
+        .user_totp()
+        .add(&mut rng, &clock, &user, "encrypted-secret".to_owned())
+        .await
+        .unwrap();
+    assert_eq!(totp.user_id, user.id);
+    assert_eq!(totp.last_used_counter, None);
+
+    let lookup = repo
+        .user_totp()
+        .lookup_for_user(&user)
+        .await
+        .unwrap()
+        .expect("TOTP not found");
+    assert_eq!(lookup, totp);
+
+    // Verifying a time step should record it
+    let totp = repo
+        .user_totp()
+        .verify(totp, 42)
+        .await
+        .unwrap()
+        .expect("time step should be accepted");
+    assert_eq!(totp.last_used_counter, Some(42));
+
+    // The same time step, or an older one, can't be used again
+    assert!(repo
+        .user_totp()
+        .verify(totp.clone(), 42)
+        .await
+        .unwrap()
+        .is_none());
+    assert!(repo
+        .user_totp()
+        .verify(totp.clone(), 41)
+        .await
+        .unwrap()
+        .is_none());
+
+    // A newer one can
+    let totp = repo
+        .user_totp()
+        .verify(totp, 43)
+        .await
+        .unwrap()
+        .expect("time step should be accepted");
+    assert_eq!(totp.last_used_counter, Some(43));
+
+    // Authenticate a browser session with the TOTP
+    let session = repo
+        .browser_session()
+        .add(&mut rng, &clock, &user, None, None, None)
+        .await
+        .unwrap();
+    let authentication = repo
+        .browser_session()
+        .authenticate_with_totp(&mut rng, &clock, &session, &totp)
+        .await
+        .unwrap();
+    assert_eq!(
+        authentication.authentication_method,
+        AuthenticationMethod::Totp {
+            user_totp_id: totp.id
+        }
+    );
+
+    let last = repo
+        .browser_session()
+        .get_last_authentication(&session)
+        .await
+        .unwrap()
+        .expect("no authentication found");
+    assert_eq!(last, authentication);
+
+    // Save a few recovery codes
+    let codes = repo
+        .user_recovery_code()
+        .add(
+            &mut rng,
+            &clock,
+            &user,
+            vec!["hash-1".to_owned(), "hash-2".to_owned()],
+        )
+        .await
+        .unwrap();
+    assert_eq!(codes.len(), 2);
+    assert_eq!(
+        repo.user_recovery_code().count_active(&user).await.unwrap(),
+        2
+    );
+
+    let code = repo
+        .user_recovery_code()
+        .find_active(&user, "hash-1")
+        .await
+        .unwrap()
+        .expect("recovery code not found");
+    assert_eq!(code, codes[0]);
+    assert!(repo
+        .user_recovery_code()
+        .find_active(&user, "unknown")
+        .await
+        .unwrap()
+        .is_none());
+
+    // Consume it
+    clock.advance(Duration::minutes(1));
+    let code = repo
+        .user_recovery_code()
+        .consume(&clock, code)
+        .await
+        .unwrap()
+        .expect("recovery code should be consumed");
+    assert!(code.is_consumed());
+    assert_eq!(
+        repo.user_recovery_code().count_active(&user).await.unwrap(),
+        1
+    );
+
+    // It can't be found anymore
+    assert!(repo
+        .user_recovery_code()
+        .find_active(&user, "hash-1")
+        .await
+        .unwrap()
+        .is_none());
+
+    // Authenticate the browser session with the recovery code
+    let authentication = repo
+        .browser_session()
+        .authenticate_with_recovery_code(&mut rng, &clock, &session, &code)
+        .await
+        .unwrap();
+    assert_eq!(
+        authentication.authentication_method,
+        AuthenticationMethod::RecoveryCode {
+            user_recovery_code_id: code.id
+        }
+    );
+
+    let last = repo
+        .browser_session()
+        .get_last_authentication(&session)
+        .await
+        .unwrap()
+        .expect("no authentication found");
+    assert_eq!(last, authentication);
+
+    // Consuming a second time should not work
+    assert!(repo
+        .user_recovery_code()
+        .consume(&clock, code)
+        .await
+        .unwrap()
+        .is_none());
+
+    // Adding a second TOTP for the same user should fail
+    assert!(repo
+        .user_totp()
+        .add(&mut rng, &clock, &user, "other-secret".to_owned())
+        .await
+        .is_err());
+}
+
+/// Test the user password reset token repository
+#[sqlx::test(migrator = "crate::MIGRATOR")]
+async fn test_user_password_reset_token(pool: PgPool) {
+    let mut repo = PgRepository::from_pool(&pool).await.unwrap().boxed();
+    let mut rng = ChaChaRng::seed_from_u64(42);
+    let clock = MockClock::default();
+
+    let user = repo
+        .user()
+        .add(&mut rng, &clock, "john".to_owned())
+        .await
+        .unwrap();
+
+    let token = repo
+        .user_password_reset_token()
+        .add(
+            &mut rng,
+            &clock,
+            &user,
+            "hashed-secret".to_owned(),
+            Duration::try_hours(1).unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(token.user_id, user.id);
+    assert_eq!(
+        token.expires_at,
+        clock.now() + Duration::try_hours(1).unwrap()
+    );
+    assert!(!token.is_consumed());
+
+    // Lookup the token
+    let token_lookup = repo
+        .user_password_reset_token()
+        .lookup(token.id)
+        .await
+        .unwrap()
+        .expect("token not found");
+    assert_eq!(token_lookup, token);
+
+    // Lookup a non-existing token
+    assert!(repo
+        .user_password_reset_token()
+        .lookup(Ulid::nil())
+        .await
+        .unwrap()
+        .is_none());
+
+    // Consume the token
+    let token = repo
+        .user_password_reset_token()
+        .consume(&clock, token)
+        .await
+        .unwrap();
+    assert!(token.is_consumed());
+
+    let token_lookup = repo
+        .user_password_reset_token()
+        .lookup(token.id)
+        .await
+        .unwrap()
+        .expect("token not found");
+    assert!(token_lookup.is_consumed());
+
+    // Consuming a second time should not work
+    assert!(repo
+        .user_password_reset_token()
+        .consume(&clock, token)
+        .await
+        .is_err());
+
+    // An expired token can't be consumed
+    let token = repo
+        .user_password_reset_token()
+        .add(
+            &mut rng,
+            &clock,
+            &user,
+            "other-hashed-secret".to_owned(),
+            Duration::try_hours(1).unwrap(),
+        )
+        .await
+        .unwrap();
+    clock.advance(Duration::try_hours(1).unwrap());
+    assert!(token.is_expired(clock.now()));
+    assert!(repo
+        .user_password_reset_token()
+        .consume(&clock, token)
+        .await
+        .is_err());
 }
@@ -0,0 +1,220 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use mas_data_model::{Totp, User};
+use mas_storage::{user::UserTotpRepository, Clock};
+use rand::RngCore;
+use sqlx::PgConnection;
+use ulid::Ulid;
+use uuid::Uuid;
+
+use crate::{tracing::ExecuteExt, DatabaseError, DatabaseInconsistencyError};
+
+/// An implementation of [`UserTotpRepository`] for a PostgreSQL connection
+pub struct PgUserTotpRepository<'c> {
+    conn: &'c mut PgConnection,
+}
+
+impl<'c> PgUserTotpRepository<'c> {
+    /// Create a new [`PgUserTotpRepository`] from an active PostgreSQL
+    /// connection
+    pub fn new(conn: &'c mut PgConnection) -> Self {
+        Self { conn }
+    }
+}
+
+struct TotpLookup {
+    user_totp_id: Uuid,
+    user_id: Uuid,
+    encrypted_secret: String,
+    last_used_counter: Option<i64>,
+    created_at: DateTime<Utc>,
+}
+
+impl TryFrom<TotpLookup> for Totp {
+    type Error = DatabaseInconsistencyError;
+
+    fn try_from(value: TotpLookup) -> Result<Self, Self::Error> {
+        let id = Ulid::from(value.user_totp_id);
+        let last_used_counter = value
+            .last_used_counter
+            .map(u64::try_from)
+            .transpose()
+            .map_err(|e| {
+                DatabaseInconsistencyError::on("user_totps")
+                    .column("last_used_counter")
+                    .row(id)
+                    .source(e)
+            })?;
+
+        Ok(Totp {
+            id,
+            user_id: value.user_id.into(),
+            encrypted_secret: value.encrypted_secret,
+            created_at: value.created_at,
+            last_used_counter,
+        })
+    }
+}
+
+#[async_trait]
+impl<'c> UserTotpRepository for PgUserTotpRepository<'c> {
+    type Error = DatabaseError;
+
+    #[tracing::instrument(
+        name = "db.user_totp.lookup_for_user",
+        skip_all,
+        fields(
+            db.statement,
+            %user.id,
+        ),
+        err,
+    )]
+    async fn lookup_for_user(&mut self, user: &User) -> Result<Option<Totp>, Self::Error> {
+        let res = sqlx::query_as!(
+            TotpLookup,
+            r#"
+                SELECT user_totp_id
+                     , user_id
+                     , encrypted_secret
+                     , last_used_counter
+                     , created_at
+                FROM user_totps
+                WHERE user_id = $1
+            "#,
+            Uuid::from(user.id),
+        )
+        .traced()
+        .fetch_optional(&mut *self.conn)
+        .await?;
+
+        let Some(res) = res else { return Ok(None) };
+
+        Ok(Some(res.try_into()?))
+    }
+
+    #[tracing::instrument(
+        name = "db.user_totp.add",
+        skip_all,
+        fields(
+            db.statement,
+            %user.id,
+            user_totp.id,
+        ),
+        err,
+    )]
+    async fn add(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        user: &User,
+        encrypted_secret: String,
+    ) -> Result<Totp, Self::Error> {
+        let created_at = clock.now();
+        let id = Ulid::from_datetime_with_source(created_at.into(), rng);
+        tracing::Span::current().record("user_totp.id", tracing::field::display(id));
+
+        sqlx::query!(
+            r#"
+                INSERT INTO user_totps
+                    ( user_totp_id
+                    , user_id
+                    , encrypted_secret
+                    , created_at
+                    )
+                VALUES ($1, $2, $3, $4)
+            "#,
+            Uuid::from(id),
+            Uuid::from(user.id),
+            &encrypted_secret,
+            created_at,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        Ok(Totp {
+            id,
+            user_id: user.id,
+            encrypted_secret,
+            created_at,
+            last_used_counter: None,
+        })
+    }
+
+    #[tracing::instrument(
+        name = "db.user_totp.verify",
+        skip_all,
+        fields(
+            db.statement,
+            %totp.id,
+            user_totp.counter = counter,
+        ),
+        err,
+    )]
+    async fn verify(&mut self, mut totp: Totp, counter: u64) -> Result<Option<Totp>, Self::Error> {
+        let db_counter = i64::try_from(counter).map_err(DatabaseError::to_invalid_operation)?;
+
+        // Only bump the counter if it is more recent than the last one used, so
+        // that a code can't be used twice, even by concurrent requests
+        let res = sqlx::query!(
+            r#"
+                UPDATE user_totps
+                SET last_used_counter = $2
+                WHERE user_totp_id = $1
+                  AND (last_used_counter IS NULL OR last_used_counter < $2)
+            "#,
+            Uuid::from(totp.id),
+            db_counter,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        if res.rows_affected() == 0 {
+            return Ok(None);
+        }
+
+        totp.last_used_counter = Some(counter);
+        Ok(Some(totp))
+    }
+
+    #[tracing::instrument(
+        name = "db.user_totp.delete",
+        skip_all,
+        fields(
+            db.statement,
+            %totp.id,
+        ),
+        err,
+    )]
+    async fn delete(&mut self, totp: Totp) -> Result<(), Self::Error> {
+        let res = sqlx::query!(
+            r#"
+                DELETE FROM user_totps
+                WHERE user_totp_id = $1
+            "#,
+            Uuid::from(totp.id),
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,301 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use mas_data_model::{User, WebAuthnCredential};
+use mas_storage::{user::UserWebAuthnRepository, Clock};
+use rand::RngCore;
+use sqlx::PgConnection;
+use ulid::Ulid;
+use uuid::Uuid;
+
+use crate::{tracing::ExecuteExt, DatabaseError, DatabaseInconsistencyError};
+
+/// An implementation of [`UserWebAuthnRepository`] for a PostgreSQL
+/// connection
+pub struct PgUserWebAuthnRepository<'c> {
+    conn: &'c mut PgConnection,
+}
+
+impl<'c> PgUserWebAuthnRepository<'c> {
+    /// Create a new [`PgUserWebAuthnRepository`] from an active PostgreSQL
+    /// connection
+    pub fn new(conn: &'c mut PgConnection) -> Self {
+        Self { conn }
+    }
+}
+
+struct WebAuthnCredentialLookup {
+    user_webauthn_credential_id: Uuid,
+    user_id: Uuid,
+    credential_id: Vec<u8>,
+    public_key: Vec<u8>,
+    sign_count: i64,
+    aaguid: Uuid,
+    created_at: DateTime<Utc>,
+}
+
+impl TryFrom<WebAuthnCredentialLookup> for WebAuthnCredential {
+    type Error = DatabaseInconsistencyError;
+
+    fn try_from(value: WebAuthnCredentialLookup) -> Result<Self, Self::Error> {
+        let id = Ulid::from(value.user_webauthn_credential_id);
+        let sign_count = value.sign_count.try_into().map_err(|e| {
+            DatabaseInconsistencyError::on("user_webauthn_credentials")
+                .column("sign_count")
+                .row(id)
+                .source(e)
+        })?;
+
+        Ok(WebAuthnCredential {
+            id,
+            user_id: value.user_id.into(),
+            credential_id: value.credential_id,
+            public_key: value.public_key,
+            sign_count,
+            aaguid: value.aaguid.into_bytes(),
+            created_at: value.created_at,
+        })
+    }
+}
+
+#[async_trait]
+impl<'c> UserWebAuthnRepository for PgUserWebAuthnRepository<'c> {
+    type Error = DatabaseError;
+
+    #[tracing::instrument(
+        name = "db.user_webauthn.lookup",
+        skip_all,
+        fields(
+            db.statement,
+            user_webauthn_credential.id = %id,
+        ),
+        err,
+    )]
+    async fn lookup(&mut self, id: Ulid) -> Result<Option<WebAuthnCredential>, Self::Error> {
+        let res = sqlx::query_as!(
+            WebAuthnCredentialLookup,
+            r#"
+                SELECT user_webauthn_credential_id
+                     , user_id
+                     , credential_id
+                     , public_key
+                     , sign_count
+                     , aaguid
+                     , created_at
+                FROM user_webauthn_credentials
+                WHERE user_webauthn_credential_id = $1
+            "#,
+            Uuid::from(id),
+        )
+        .traced()
+        .fetch_optional(&mut *self.conn)
+        .await?;
+
+        let Some(res) = res else { return Ok(None) };
+
+        Ok(Some(res.try_into()?))
+    }
+
+    #[tracing::instrument(
+        name = "db.user_webauthn.find_by_credential_id",
+        skip_all,
+        fields(
+            db.statement,
+        ),
+        err,
+    )]
+    async fn find_by_credential_id(
+        &mut self,
+        credential_id: &[u8],
+    ) -> Result<Option<WebAuthnCredential>, Self::Error> {
+        let res = sqlx::query_as!(
+            WebAuthnCredentialLookup,
+            r#"
+                SELECT user_webauthn_credential_id
+                     , user_id
+                     , credential_id
+                     , public_key
+                     , sign_count
+                     , aaguid
+                     , created_at
+                FROM user_webauthn_credentials
+                WHERE credential_id = $1
+            "#,
+            credential_id,
+        )
+        .traced()
+        .fetch_optional(&mut *self.conn)
+        .await?;
+
+        let Some(res) = res else { return Ok(None) };
+
+        Ok(Some(res.try_into()?))
+    }
+
+    #[tracing::instrument(
+        name = "db.user_webauthn.list_for_user",
+        skip_all,
+        fields(
+            db.statement,
+            %user.id,
+        ),
+        err,
+    )]
+    async fn list_for_user(&mut self, user: &User) -> Result<Vec<WebAuthnCredential>, Self::Error> {
+        let res = sqlx::query_as!(
+            WebAuthnCredentialLookup,
+            r#"
+                SELECT user_webauthn_credential_id
+                     , user_id
+                     , credential_id
+                     , public_key
+                     , sign_count
+                     , aaguid
+                     , created_at
+                FROM user_webauthn_credentials
+                WHERE user_id = $1
+                ORDER BY created_at ASC
+            "#,
+            Uuid::from(user.id),
+        )
+        .traced()
+        .fetch_all(&mut *self.conn)
+        .await?;
+
+        let res: Result<Vec<_>, _> = res.into_iter().map(TryInto::try_into).collect();
+        Ok(res?)
+    }
+
+    #[tracing::instrument(
+        name = "db.user_webauthn.add",
+        skip_all,
+        fields(
+            db.statement,
+            %user.id,
+            user_webauthn_credential.id,
+        ),
+        err,
+    )]
+    async fn add(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        user: &User,
+        credential_id: Vec<u8>,
+        public_key: Vec<u8>,
+        sign_count: u32,
+        aaguid: [u8; 16],
+    ) -> Result<WebAuthnCredential, Self::Error> {
+        let created_at = clock.now();
+        let id = Ulid::from_datetime_with_source(created_at.into(), rng);
+        tracing::Span::current().record("user_webauthn_credential.id", tracing::field::display(id));
+
+        sqlx::query!(
+            r#"
+                INSERT INTO user_webauthn_credentials
+                    ( user_webauthn_credential_id
+                    , user_id
+                    , credential_id
+                    , public_key
+                    , sign_count
+                    , aaguid
+                    , created_at
+                    )
+                VALUES ($1, $2, $3, $4, $5, $6, $7)
+            "#,
+            Uuid::from(id),
+            Uuid::from(user.id),
+            &credential_id,
+            &public_key,
+            i64::from(sign_count),
+            Uuid::from_bytes(aaguid),
+            created_at,
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        Ok(WebAuthnCredential {
+            id,
+            user_id: user.id,
+            credential_id,
+            public_key,
+            sign_count,
+            aaguid,
+            created_at,
+        })
+    }
+
+    #[tracing::instrument(
+        name = "db.user_webauthn.update_sign_count",
+        skip_all,
+        fields(
+            db.statement,
+            %credential.id,
+            user_webauthn_credential.sign_count = sign_count,
+        ),
+        err,
+    )]
+    async fn update_sign_count(
+        &mut self,
+        mut credential: WebAuthnCredential,
+        sign_count: u32,
+    ) -> Result<WebAuthnCredential, Self::Error> {
+        let res = sqlx::query!(
+            r#"
+                UPDATE user_webauthn_credentials
+                SET sign_count = $2
+                WHERE user_webauthn_credential_id = $1
+            "#,
+            Uuid::from(credential.id),
+            i64::from(sign_count),
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        credential.sign_count = sign_count;
+        Ok(credential)
+    }
+
+    #[tracing::instrument(
+        name = "db.user_webauthn.delete",
+        skip_all,
+        fields(
+            db.statement,
+            %credential.id,
+        ),
+        err,
+    )]
+    async fn delete(&mut self, credential: WebAuthnCredential) -> Result<(), Self::Error> {
+        let res = sqlx::query!(
+            r#"
+                DELETE FROM user_webauthn_credentials
+                WHERE user_webauthn_credential_id = $1
+            "#,
+            Uuid::from(credential.id),
+        )
+        .traced()
+        .execute(&mut *self.conn)
+        .await?;
+
+        DatabaseError::ensure_affected_rows(&res, 1)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,179 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Repositories to record and list security-sensitive actions
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use mas_data_model::{AuditAction, AuditLogEntry, AuditSessionKind, BrowserSession, Session, User};
+use rand_core::RngCore;
+use ulid::Ulid;
+
+use crate::{repository_impl, Clock, Page, Pagination};
+
+/// Structure which holds the parameters of a new audit log entry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditLogParams {
+    /// The action to record
+    pub action: AuditAction,
+
+    /// The ID of the user who performed the action, if any
+    pub actor_user_id: Option<Ulid>,
+
+    /// The ID of the session used to perform the action, if any
+    pub actor_session_id: Option<Ulid>,
+
+    /// The kind of session used to perform the action, if any
+    pub actor_session_kind: Option<AuditSessionKind>,
+
+    /// The ID of the user affected by the action, if any
+    pub target_user_id: Option<Ulid>,
+
+    /// Additional details about the action
+    pub metadata: serde_json::Value,
+}
+
+impl AuditLogParams {
+    /// Create new parameters for the given action, with no actor, no target and
+    /// empty metadata
+    #[must_use]
+    pub fn new(action: AuditAction) -> Self {
+        Self {
+            action,
+            actor_user_id: None,
+            actor_session_id: None,
+            actor_session_kind: None,
+            target_user_id: None,
+            metadata: serde_json::Value::Object(serde_json::Map::new()),
+        }
+    }
+
+    /// Set the user who performed the action
+    #[must_use]
+    pub fn with_actor(mut self, user: &User) -> Self {
+        self.actor_user_id = Some(user.id);
+        self
+    }
+
+    /// Set the browser session used to perform the action
+    #[must_use]
+    pub fn with_actor_browser_session(mut self, session: &BrowserSession) -> Self {
+        self.actor_session_id = Some(session.id);
+        self.actor_session_kind = Some(AuditSessionKind::Browser);
+        self
+    }
+
+    /// Set the OAuth 2.0 session used to perform the action
+    #[must_use]
+    pub fn with_actor_oauth2_session(mut self, session: &Session) -> Self {
+        self.actor_session_id = Some(session.id);
+        self.actor_session_kind = Some(AuditSessionKind::OAuth2);
+        self
+    }
+
+    /// Set the user affected by the action
+    #[must_use]
+    pub fn with_target(mut self, user: &User) -> Self {
+        self.target_user_id = Some(user.id);
+        self
+    }
+
+    /// Set the user affected by the action, from its ID
+    #[must_use]
+    pub fn with_target_id(mut self, user_id: Ulid) -> Self {
+        self.target_user_id = Some(user_id);
+        self
+    }
+
+    /// Set additional details about the action
+    #[must_use]
+    pub fn with_metadata(mut self, metadata: serde_json::Value) -> Self {
+        self.metadata = metadata;
+        self
+    }
+}
+
+/// An [`AuditLogRepository`] helps recording and listing [`AuditLogEntry`]
+#[async_trait]
+pub trait AuditLogRepository: Send + Sync {
+    /// The error type returned by the repository
+    type Error;
+
+    /// Record a new [`AuditLogEntry`]
+    ///
+    /// Returns the newly created [`AuditLogEntry`]
+    ///
+    /// # Parameters
+    ///
+    /// * `rng`: The random number generator to use
+    /// * `clock`: The clock used to generate timestamps
+    /// * `params`: The parameters of the entry
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn record(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        params: AuditLogParams,
+    ) -> Result<AuditLogEntry, Self::Error>;
+
+    /// List the [`AuditLogEntry`] in which the given [`User`] is either the
+    /// actor or the target, most recent last
+    ///
+    /// # Parameters
+    ///
+    /// * `user`: The [`User`] to list the entries for
+    /// * `pagination`: The pagination parameters
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn list_for_user(
+        &mut self,
+        user: &User,
+        pagination: Pagination,
+    ) -> Result<Page<AuditLogEntry>, Self::Error>;
+
+    /// Delete the [`AuditLogEntry`] recorded before the given time
+    ///
+    /// Returns the number of entries that were deleted
+    ///
+    /// # Parameters
+    ///
+    /// * `before`: The time before which entries are deleted
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn cleanup(&mut self, before: DateTime<Utc>) -> Result<usize, Self::Error>;
+}
+
+repository_impl!(AuditLogRepository:
+    async fn record(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        params: AuditLogParams,
+    ) -> Result<AuditLogEntry, Self::Error>;
+
+    async fn list_for_user(
+        &mut self,
+        user: &User,
+        pagination: Pagination,
+    ) -> Result<Page<AuditLogEntry>, Self::Error>;
+
+    async fn cleanup(&mut self, before: DateTime<Utc>) -> Result<usize, Self::Error>;
+);
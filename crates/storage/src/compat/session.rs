@@ -69,6 +69,7 @@ pub struct CompatSessionFilter<'a> {
     state: Option<CompatSessionState>,
     auth_type: Option<CompatSessionType>,
     device: Option<&'a Device>,
+    is_synapse_admin: Option<bool>,
 }
 
 impl<'a> CompatSessionFilter<'a> {
@@ -143,6 +144,20 @@ impl<'a> CompatSessionFilter<'a> {
     pub fn auth_type(&self) -> Option<CompatSessionType> {
         self.auth_type
     }
+
+    /// Only return compatibility sessions which have, or don't have, Synapse
+    /// admin privileges
+    #[must_use]
+    pub fn with_synapse_admin(mut self, is_synapse_admin: bool) -> Self {
+        self.is_synapse_admin = Some(is_synapse_admin);
+        self
+    }
+
+    /// Get the Synapse admin filter
+    #[must_use]
+    pub fn is_synapse_admin(&self) -> Option<bool> {
+        self.is_synapse_admin
+    }
 }
 
 /// A [`CompatSessionRepository`] helps interacting with
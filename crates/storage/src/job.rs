@@ -231,6 +231,7 @@ where
 mod jobs {
     // XXX: Move this somewhere else?
     use apalis_core::job::Job;
+    use chrono::{DateTime, Utc};
     use mas_data_model::{Device, User, UserEmail};
     use serde::{Deserialize, Serialize};
     use ulid::Ulid;
@@ -276,6 +277,60 @@ mod jobs {
         const NAME: &'static str = "verify-email";
     }
 
+    /// A job to send a password reset link to an email address.
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    pub struct SendPasswordResetEmailJob {
+        user_email_id: Ulid,
+        expires_at: DateTime<Utc>,
+        language: Option<String>,
+    }
+
+    impl SendPasswordResetEmailJob {
+        /// Create a new job to send a password reset link to an email address.
+        ///
+        /// # Parameters
+        ///
+        /// * `user_email` - The email address to send the link to
+        /// * `expires_at` - When the password reset link should expire
+        #[must_use]
+        pub fn new(user_email: &UserEmail, expires_at: DateTime<Utc>) -> Self {
+            Self {
+                user_email_id: user_email.id,
+                expires_at,
+                language: None,
+            }
+        }
+
+        /// Set the language to use for the email.
+        #[must_use]
+        pub fn with_language(mut self, language: String) -> Self {
+            self.language = Some(language);
+            self
+        }
+
+        /// The language to use for the email.
+        #[must_use]
+        pub fn language(&self) -> Option<&str> {
+            self.language.as_deref()
+        }
+
+        /// The ID of the email address to send the link to.
+        #[must_use]
+        pub fn user_email_id(&self) -> Ulid {
+            self.user_email_id
+        }
+
+        /// When the password reset link should expire.
+        #[must_use]
+        pub fn expires_at(&self) -> DateTime<Utc> {
+            self.expires_at
+        }
+    }
+
+    impl Job for SendPasswordResetEmailJob {
+        const NAME: &'static str = "send-password-reset-email";
+    }
+
     /// A job to provision the user on the homeserver.
     #[derive(Serialize, Deserialize, Debug, Clone)]
     pub struct ProvisionUserJob {
@@ -435,5 +490,6 @@ mod jobs {
 }
 
 pub use self::jobs::{
-    DeactivateUserJob, DeleteDeviceJob, ProvisionDeviceJob, ProvisionUserJob, VerifyEmailJob,
+    DeactivateUserJob, DeleteDeviceJob, ProvisionDeviceJob, ProvisionUserJob,
+    SendPasswordResetEmailJob, VerifyEmailJob,
 };
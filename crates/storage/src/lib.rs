@@ -142,6 +142,7 @@ pub(crate) mod repository;
 mod utils;
 
 pub mod app_session;
+pub mod audit_log;
 pub mod compat;
 pub mod job;
 pub mod oauth2;
@@ -14,9 +14,11 @@
 
 use async_trait::async_trait;
 use chrono::Duration;
-use mas_data_model::{AccessToken, Session};
+use mas_data_model::{AccessToken, Session, User};
+use oauth2_types::scope::Scope;
 use rand_core::RngCore;
 use ulid::Ulid;
+use url::Url;
 
 use crate::{repository_impl, Clock};
 
@@ -56,6 +58,27 @@ pub trait OAuth2AccessTokenRepository: Send + Sync {
         access_token: &str,
     ) -> Result<Option<AccessToken>, Self::Error>;
 
+    /// Find an access token by its token, along with its session and the user
+    /// of that session, in a single lookup
+    ///
+    /// This is used by the introspection endpoint, which needs all three to
+    /// answer. The user is `None` for sessions which don't have one, like the
+    /// ones from the client credentials grant.
+    ///
+    /// Returns `None` if the access token doesn't exist
+    ///
+    /// # Parameters
+    ///
+    /// * `access_token`: The token of the access token to lookup
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn lookup_for_introspection(
+        &mut self,
+        access_token: &str,
+    ) -> Result<Option<(AccessToken, Session, Option<User>)>, Self::Error>;
+
     /// Add a new access token to the database
     ///
     /// Returns the newly created access token
@@ -81,6 +104,63 @@ pub trait OAuth2AccessTokenRepository: Send + Sync {
         expires_after: Option<Duration>,
     ) -> Result<AccessToken, Self::Error>;
 
+    /// Bind an access token to a `DPoP` key
+    ///
+    /// Returns the updated access token
+    ///
+    /// # Parameters
+    ///
+    /// * `access_token`: The access token to bind
+    /// * `jkt`: The JWK SHA-256 thumbprint of the `DPoP` key
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn bind_dpop_key(
+        &mut self,
+        access_token: AccessToken,
+        jkt: String,
+    ) -> Result<AccessToken, Self::Error>;
+
+    /// Narrow down the scope of an access token
+    ///
+    /// This doesn't change the scope of the session the access token belongs
+    /// to.
+    ///
+    /// Returns the updated access token
+    ///
+    /// # Parameters
+    ///
+    /// * `access_token`: The access token to update
+    /// * `scope`: The scope the access token grants
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn set_scope(
+        &mut self,
+        access_token: AccessToken,
+        scope: Scope,
+    ) -> Result<AccessToken, Self::Error>;
+
+    /// Restrict an access token to a subset of the resources of its session
+    ///
+    /// Returns the updated access token
+    ///
+    /// # Parameters
+    ///
+    /// * `access_token`: The access token to update
+    /// * `resource`: The resources the access token is restricted to
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn set_resource(
+        &mut self,
+        access_token: AccessToken,
+        resource: Vec<Url>,
+    ) -> Result<AccessToken, Self::Error>;
+
     /// Revoke an access token
     ///
     /// Returns the revoked access token
@@ -121,6 +201,11 @@ repository_impl!(OAuth2AccessTokenRepository:
         access_token: &str,
     ) -> Result<Option<AccessToken>, Self::Error>;
 
+    async fn lookup_for_introspection(
+        &mut self,
+        access_token: &str,
+    ) -> Result<Option<(AccessToken, Session, Option<User>)>, Self::Error>;
+
     async fn add(
         &mut self,
         rng: &mut (dyn RngCore + Send),
@@ -130,6 +215,24 @@ repository_impl!(OAuth2AccessTokenRepository:
         expires_after: Option<Duration>,
     ) -> Result<AccessToken, Self::Error>;
 
+    async fn bind_dpop_key(
+        &mut self,
+        access_token: AccessToken,
+        jkt: String,
+    ) -> Result<AccessToken, Self::Error>;
+
+    async fn set_scope(
+        &mut self,
+        access_token: AccessToken,
+        scope: Scope,
+    ) -> Result<AccessToken, Self::Error>;
+
+    async fn set_resource(
+        &mut self,
+        access_token: AccessToken,
+        resource: Vec<Url>,
+    ) -> Result<AccessToken, Self::Error>;
+
     async fn revoke(
         &mut self,
         clock: &dyn Clock,
@@ -15,6 +15,7 @@
 use std::num::NonZeroU32;
 
 use async_trait::async_trait;
+use chrono::Duration;
 use mas_data_model::{AuthorizationCode, AuthorizationGrant, Client, Session};
 use oauth2_types::{requests::ResponseMode, scope::Scope};
 use rand_core::RngCore;
@@ -51,6 +52,10 @@ pub trait OAuth2AuthorizationGrantRepository: Send + Sync {
     /// * `response_type_id_token`: Whether the `id_token` `response_type` was
     ///   requested
     /// * `requires_consent`: Whether the client explicitly requested consent
+    /// * `requires_reauth`: Whether the client explicitly requested the user to
+    ///   reauthenticate
+    /// * `login_hint`: The `login_hint` the client sent, if set
+    /// * `resource`: The resources the client requested tokens for
     ///
     /// # Errors
     ///
@@ -70,6 +75,9 @@ pub trait OAuth2AuthorizationGrantRepository: Send + Sync {
         response_mode: ResponseMode,
         response_type_id_token: bool,
         requires_consent: bool,
+        requires_reauth: bool,
+        login_hint: Option<String>,
+        resource: Vec<Url>,
     ) -> Result<AuthorizationGrant, Self::Error>;
 
     /// Lookup an authorization grant by its ID
@@ -109,6 +117,8 @@ pub trait OAuth2AuthorizationGrantRepository: Send + Sync {
     /// * `clock`: The clock used to generate timestamps
     /// * `session`: The session that was created using this authorization grant
     /// * `authorization_grant`: The authorization grant to fulfill
+    /// * `code_expires_after`: How long the authorization code can be
+    ///   exchanged for
     ///
     /// # Errors
     ///
@@ -118,11 +128,13 @@ pub trait OAuth2AuthorizationGrantRepository: Send + Sync {
         clock: &dyn Clock,
         session: &Session,
         authorization_grant: AuthorizationGrant,
+        code_expires_after: Duration,
     ) -> Result<AuthorizationGrant, Self::Error>;
 
     /// Mark an authorization grant as exchanged
     ///
-    /// Returns the updated authorization grant
+    /// Returns the updated authorization grant, or `None` if it was already
+    /// exchanged, for example by a concurrent request
     ///
     /// # Parameters
     ///
@@ -136,7 +148,7 @@ pub trait OAuth2AuthorizationGrantRepository: Send + Sync {
         &mut self,
         clock: &dyn Clock,
         authorization_grant: AuthorizationGrant,
-    ) -> Result<AuthorizationGrant, Self::Error>;
+    ) -> Result<Option<AuthorizationGrant>, Self::Error>;
 
     /// Unset the `requires_consent` flag on an authorization grant
     ///
@@ -153,6 +165,23 @@ pub trait OAuth2AuthorizationGrantRepository: Send + Sync {
         &mut self,
         authorization_grant: AuthorizationGrant,
     ) -> Result<AuthorizationGrant, Self::Error>;
+
+    /// Unset the `requires_reauth` flag on an authorization grant, once the
+    /// user reauthenticated
+    ///
+    /// Returns the updated authorization grant
+    ///
+    /// # Parameters
+    ///
+    /// * `authorization_grant`: The authorization grant to update
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn reauthenticated(
+        &mut self,
+        authorization_grant: AuthorizationGrant,
+    ) -> Result<AuthorizationGrant, Self::Error>;
 }
 
 repository_impl!(OAuth2AuthorizationGrantRepository:
@@ -170,6 +199,9 @@ repository_impl!(OAuth2AuthorizationGrantRepository:
         response_mode: ResponseMode,
         response_type_id_token: bool,
         requires_consent: bool,
+        requires_reauth: bool,
+        login_hint: Option<String>,
+        resource: Vec<Url>,
     ) -> Result<AuthorizationGrant, Self::Error>;
 
     async fn lookup(&mut self, id: Ulid) -> Result<Option<AuthorizationGrant>, Self::Error>;
@@ -182,16 +214,22 @@ repository_impl!(OAuth2AuthorizationGrantRepository:
         clock: &dyn Clock,
         session: &Session,
         authorization_grant: AuthorizationGrant,
+        code_expires_after: Duration,
     ) -> Result<AuthorizationGrant, Self::Error>;
 
     async fn exchange(
         &mut self,
         clock: &dyn Clock,
         authorization_grant: AuthorizationGrant,
-    ) -> Result<AuthorizationGrant, Self::Error>;
+    ) -> Result<Option<AuthorizationGrant>, Self::Error>;
 
     async fn give_consent(
         &mut self,
         authorization_grant: AuthorizationGrant,
     ) -> Result<AuthorizationGrant, Self::Error>;
+
+    async fn reauthenticated(
+        &mut self,
+        authorization_grant: AuthorizationGrant,
+    ) -> Result<AuthorizationGrant, Self::Error>;
 );
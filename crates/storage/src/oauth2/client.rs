@@ -15,8 +15,12 @@
 use std::collections::{BTreeMap, BTreeSet};
 
 use async_trait::async_trait;
-use mas_data_model::{Client, User};
-use mas_iana::{jose::JsonWebSignatureAlg, oauth::OAuthClientAuthenticationMethod};
+use chrono::Duration;
+use mas_data_model::{AccessTokenFormat, Client, RedirectUriMatching};
+use mas_iana::{
+    jose::JsonWebSignatureAlg,
+    oauth::{OAuthAuthorizationEndpointResponseType, OAuthClientAuthenticationMethod},
+};
 use mas_jose::jwk::PublicJsonWebKeySet;
 use oauth2_types::{oidc::ApplicationType, requests::GrantType, scope::Scope};
 use rand_core::RngCore;
@@ -25,6 +29,84 @@ use url::Url;
 
 use crate::{repository_impl, Clock};
 
+/// Structure which holds parameters when inserting or updating a static OAuth
+/// 2.0 client
+pub struct UpsertStaticClientParams {
+    /// The client ID
+    pub client_id: Ulid,
+
+    /// The authentication method this client uses
+    pub client_auth_method: OAuthClientAuthenticationMethod,
+
+    /// The encrypted client secret, if any
+    pub encrypted_client_secret: Option<String>,
+
+    /// The client JWKS, if any
+    pub jwks: Option<PublicJsonWebKeySet>,
+
+    /// The client JWKS URI, if any
+    pub jwks_uri: Option<Url>,
+
+    /// The list of redirect URIs used by this client
+    pub redirect_uris: Vec<Url>,
+
+    /// How redirect URIs are matched against the registered ones
+    pub redirect_uri_matching: RedirectUriMatching,
+
+    /// The list of response types this client can use
+    pub response_types: Vec<OAuthAuthorizationEndpointResponseType>,
+
+    /// Whether this client can use the token exchange grant
+    pub allow_token_exchange: bool,
+
+    /// Whether this client can use the client credentials grant
+    pub allow_client_credentials: bool,
+
+    /// The scopes this client can request with the client credentials grant, if
+    /// restricted
+    pub client_credentials_scope: Option<Scope>,
+
+    /// The resources this client can request tokens for, or serves if it is a
+    /// resource server
+    pub allowed_resources: Vec<Url>,
+
+    /// Whether this client must send its authorization requests as signed
+    /// request objects
+    pub require_signed_request_object: bool,
+
+    /// Whether this client must use PKCE, if overridden
+    pub require_pkce: Option<bool>,
+
+    /// The time-to-live of the access tokens issued to this client, if
+    /// overridden
+    pub access_token_ttl: Option<Duration>,
+
+    /// The time-to-live of the refresh tokens issued to this client, if
+    /// overridden
+    pub refresh_token_ttl: Option<Duration>,
+
+    /// The format of the access tokens issued to this client
+    pub access_token_format: AccessTokenFormat,
+
+    /// The authentication method used by this client on the introspection
+    /// endpoint, if it is a resource server
+    pub introspection_endpoint_auth_method: Option<OAuthClientAuthenticationMethod>,
+
+    /// The list of URIs the user can be redirected to after logging out
+    pub post_logout_redirect_uris: Vec<Url>,
+
+    /// The URI on which the client wants to be notified of logouts, if any
+    pub backchannel_logout_uri: Option<Url>,
+
+    /// Whether the client requires the `sid` claim in the logout tokens it
+    /// receives
+    pub backchannel_logout_session_required: bool,
+
+    /// Whether the client is trusted, in which case users are not asked for
+    /// their consent
+    pub trusted: bool,
+}
+
 /// An [`OAuth2ClientRepository`] helps interacting with [`Client`] saved in the
 /// storage backend
 #[async_trait]
@@ -81,6 +163,7 @@ pub trait OAuth2ClientRepository: Send + Sync {
     /// * `redirect_uris`: The list of redirect URIs used by this client
     /// * `encrypted_client_secret`: The encrypted client secret, if any
     /// * `application_type`: The application type of this client
+    /// * `response_types`: The list of response types this client can use
     /// * `grant_types`: The list of grant types this client can use
     /// * `contacts`: The list of contacts for this client
     /// * `client_name`: The human-readable name of this client, if given
@@ -94,12 +177,16 @@ pub trait OAuth2ClientRepository: Send + Sync {
     ///   token
     /// * `userinfo_signed_response_alg`: The algorithm used to sign the user
     ///   info. If none, the user info endpoint will not sign the response
+    /// * `authorization_signed_response_alg`: The algorithm used to sign
+    ///   JWT-secured authorization responses, if given
     /// * `token_endpoint_auth_method`: The authentication method used by this
     ///   client when calling the token endpoint
     /// * `token_endpoint_auth_signing_alg`: The algorithm used to sign the JWT
     ///   when using the `client_secret_jwt` or `private_key_jwt` authentication
     ///   methods
     /// * `initiate_login_uri`: The URI used to initiate a login, if given
+    /// * `post_logout_redirect_uris`: The list of URIs the user can be
+    ///   redirected to after logging out
     ///
     /// # Errors
     ///
@@ -112,6 +199,7 @@ pub trait OAuth2ClientRepository: Send + Sync {
         redirect_uris: Vec<Url>,
         encrypted_client_secret: Option<String>,
         application_type: Option<ApplicationType>,
+        response_types: Vec<OAuthAuthorizationEndpointResponseType>,
         grant_types: Vec<GrantType>,
         contacts: Vec<String>,
         client_name: Option<String>,
@@ -123,9 +211,11 @@ pub trait OAuth2ClientRepository: Send + Sync {
         jwks: Option<PublicJsonWebKeySet>,
         id_token_signed_response_alg: Option<JsonWebSignatureAlg>,
         userinfo_signed_response_alg: Option<JsonWebSignatureAlg>,
+        authorization_signed_response_alg: Option<JsonWebSignatureAlg>,
         token_endpoint_auth_method: Option<OAuthClientAuthenticationMethod>,
         token_endpoint_auth_signing_alg: Option<JsonWebSignatureAlg>,
         initiate_login_uri: Option<Url>,
+        post_logout_redirect_uris: Vec<Url>,
     ) -> Result<Client, Self::Error>;
 
     /// Add or replace a static client
@@ -134,25 +224,14 @@ pub trait OAuth2ClientRepository: Send + Sync {
     ///
     /// # Parameters
     ///
-    /// * `client_id`: The client ID
-    /// * `client_auth_method`: The authentication method this client uses
-    /// * `encrypted_client_secret`: The encrypted client secret, if any
-    /// * `jwks`: The client JWKS, if any
-    /// * `jwks_uri`: The client JWKS URI, if any
-    /// * `redirect_uris`: The list of redirect URIs used by this client
+    /// * `params`: The parameters of the client
     ///
     /// # Errors
     ///
     /// Returns [`Self::Error`] if the underlying repository fails
-    #[allow(clippy::too_many_arguments)]
     async fn upsert_static(
         &mut self,
-        client_id: Ulid,
-        client_auth_method: OAuthClientAuthenticationMethod,
-        encrypted_client_secret: Option<String>,
-        jwks: Option<PublicJsonWebKeySet>,
-        jwks_uri: Option<Url>,
-        redirect_uris: Vec<Url>,
+        params: UpsertStaticClientParams,
     ) -> Result<Client, Self::Error>;
 
     /// List all static clients
@@ -162,45 +241,6 @@ pub trait OAuth2ClientRepository: Send + Sync {
     /// Returns [`Self::Error`] if the underlying repository fails
     async fn all_static(&mut self) -> Result<Vec<Client>, Self::Error>;
 
-    /// Get the list of scopes that the user has given consent for the given
-    /// client
-    ///
-    /// # Parameters
-    ///
-    /// * `client`: The client to get the consent for
-    /// * `user`: The user to get the consent for
-    ///
-    /// # Errors
-    ///
-    /// Returns [`Self::Error`] if the underlying repository fails
-    async fn get_consent_for_user(
-        &mut self,
-        client: &Client,
-        user: &User,
-    ) -> Result<Scope, Self::Error>;
-
-    /// Give consent for a set of scopes for the given client and user
-    ///
-    /// # Parameters
-    ///
-    /// * `rng`: The random number generator to use
-    /// * `clock`: The clock used to generate timestamps
-    /// * `client`: The client to give the consent for
-    /// * `user`: The user to give the consent for
-    /// * `scope`: The scope to give consent for
-    ///
-    /// # Errors
-    ///
-    /// Returns [`Self::Error`] if the underlying repository fails
-    async fn give_consent_for_user(
-        &mut self,
-        rng: &mut (dyn RngCore + Send),
-        clock: &dyn Clock,
-        client: &Client,
-        user: &User,
-        scope: &Scope,
-    ) -> Result<(), Self::Error>;
-
     /// Delete a client
     ///
     /// # Parameters
@@ -243,6 +283,7 @@ repository_impl!(OAuth2ClientRepository:
         redirect_uris: Vec<Url>,
         encrypted_client_secret: Option<String>,
         application_type: Option<ApplicationType>,
+        response_types: Vec<OAuthAuthorizationEndpointResponseType>,
         grant_types: Vec<GrantType>,
         contacts: Vec<String>,
         client_name: Option<String>,
@@ -254,19 +295,16 @@ repository_impl!(OAuth2ClientRepository:
         jwks: Option<PublicJsonWebKeySet>,
         id_token_signed_response_alg: Option<JsonWebSignatureAlg>,
         userinfo_signed_response_alg: Option<JsonWebSignatureAlg>,
+        authorization_signed_response_alg: Option<JsonWebSignatureAlg>,
         token_endpoint_auth_method: Option<OAuthClientAuthenticationMethod>,
         token_endpoint_auth_signing_alg: Option<JsonWebSignatureAlg>,
         initiate_login_uri: Option<Url>,
+        post_logout_redirect_uris: Vec<Url>,
     ) -> Result<Client, Self::Error>;
 
     async fn upsert_static(
         &mut self,
-        client_id: Ulid,
-        client_auth_method: OAuthClientAuthenticationMethod,
-        encrypted_client_secret: Option<String>,
-        jwks: Option<PublicJsonWebKeySet>,
-        jwks_uri: Option<Url>,
-        redirect_uris: Vec<Url>,
+        params: UpsertStaticClientParams,
     ) -> Result<Client, Self::Error>;
 
     async fn all_static(&mut self) -> Result<Vec<Client>, Self::Error>;
@@ -274,19 +312,4 @@ repository_impl!(OAuth2ClientRepository:
     async fn delete(&mut self, client: Client) -> Result<(), Self::Error>;
 
     async fn delete_by_id(&mut self, id: Ulid) -> Result<(), Self::Error>;
-
-    async fn get_consent_for_user(
-        &mut self,
-        client: &Client,
-        user: &User,
-    ) -> Result<Scope, Self::Error>;
-
-    async fn give_consent_for_user(
-        &mut self,
-        rng: &mut (dyn RngCore + Send),
-        clock: &dyn Clock,
-        client: &Client,
-        user: &User,
-        scope: &Scope,
-    ) -> Result<(), Self::Error>;
 );
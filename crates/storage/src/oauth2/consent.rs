@@ -0,0 +1,91 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use async_trait::async_trait;
+use mas_data_model::{Client, User};
+use oauth2_types::scope::Scope;
+use rand_core::RngCore;
+
+use crate::{repository_impl, Clock};
+
+/// An [`OAuth2ConsentRepository`] helps interacting with the scopes users
+/// consented to grant to OAuth 2.0 clients
+#[async_trait]
+pub trait OAuth2ConsentRepository: Send + Sync {
+    /// The error type returned by the repository
+    type Error;
+
+    /// Get the scope that the user has given consent for the given client
+    ///
+    /// # Parameters
+    ///
+    /// * `client`: The client to get the consent for
+    /// * `user`: The user to get the consent for
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn find(&mut self, client: &Client, user: &User) -> Result<Scope, Self::Error>;
+
+    /// Give consent for a set of scopes for the given client and user
+    ///
+    /// # Parameters
+    ///
+    /// * `rng`: The random number generator to use
+    /// * `clock`: The clock used to generate timestamps
+    /// * `client`: The client to give the consent for
+    /// * `user`: The user to give the consent for
+    /// * `scope`: The scope to give consent for
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn add(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        client: &Client,
+        user: &User,
+        scope: &Scope,
+    ) -> Result<(), Self::Error>;
+
+    /// Revoke all the consent the user gave to the given client
+    ///
+    /// Returns `true` if the user had given any consent to the client
+    ///
+    /// # Parameters
+    ///
+    /// * `client`: The client to revoke the consent for
+    /// * `user`: The user to revoke the consent for
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn revoke(&mut self, client: &Client, user: &User) -> Result<bool, Self::Error>;
+}
+
+repository_impl!(OAuth2ConsentRepository:
+    async fn find(&mut self, client: &Client, user: &User) -> Result<Scope, Self::Error>;
+
+    async fn add(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        client: &Client,
+        user: &User,
+        scope: &Scope,
+    ) -> Result<(), Self::Error>;
+
+    async fn revoke(&mut self, client: &Client, user: &User) -> Result<bool, Self::Error>;
+);
@@ -40,6 +40,9 @@ pub struct OAuth2DeviceCodeGrantParams<'a> {
     /// After how long the device code expires
     pub expires_in: Duration,
 
+    /// The minimum interval between two polls of the token endpoint
+    pub poll_interval: Duration,
+
     /// IP address from which the request was made
     pub ip_address: Option<IpAddr>,
 
@@ -191,6 +194,27 @@ pub trait OAuth2DeviceCodeGrantRepository: Send + Sync {
         device_code_grant: DeviceCodeGrant,
         session: &Session,
     ) -> Result<DeviceCodeGrant, Self::Error>;
+
+    /// Record that the client polled the token endpoint with this device code
+    /// grant
+    ///
+    /// Returns the updated device code grant
+    ///
+    /// # Parameters
+    ///
+    /// * `clock`: The clock used to generate timestamps
+    /// * `device_code_grant`: The device code grant which was polled
+    /// * `poll_interval`: The minimum interval before the next poll
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn record_poll(
+        &mut self,
+        clock: &dyn Clock,
+        device_code_grant: DeviceCodeGrant,
+        poll_interval: Duration,
+    ) -> Result<DeviceCodeGrant, Self::Error>;
 }
 
 repository_impl!(OAuth2DeviceCodeGrantRepository:
@@ -233,4 +257,11 @@ repository_impl!(OAuth2DeviceCodeGrantRepository:
         device_code_grant: DeviceCodeGrant,
         session: &Session,
     ) -> Result<DeviceCodeGrant, Self::Error>;
+
+    async fn record_poll(
+        &mut self,
+        clock: &dyn Clock,
+        device_code_grant: DeviceCodeGrant,
+        poll_interval: Duration,
+    ) -> Result<DeviceCodeGrant, Self::Error>;
 );
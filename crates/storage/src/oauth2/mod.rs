@@ -17,15 +17,19 @@
 mod access_token;
 mod authorization_grant;
 mod client;
+mod consent;
 mod device_code_grant;
+mod pushed_auth_request;
 mod refresh_token;
 mod session;
 
 pub use self::{
     access_token::OAuth2AccessTokenRepository,
     authorization_grant::OAuth2AuthorizationGrantRepository,
-    client::OAuth2ClientRepository,
+    client::{OAuth2ClientRepository, UpsertStaticClientParams},
+    consent::OAuth2ConsentRepository,
     device_code_grant::{OAuth2DeviceCodeGrantParams, OAuth2DeviceCodeGrantRepository},
+    pushed_auth_request::OAuth2PushedAuthRequestRepository,
     refresh_token::OAuth2RefreshTokenRepository,
     session::{OAuth2SessionFilter, OAuth2SessionRepository},
 };
@@ -88,6 +88,7 @@ pub trait OAuth2RefreshTokenRepository: Send + Sync {
     ///
     /// * `clock`: The clock used to generate timestamps
     /// * `refresh_token`: The [`RefreshToken`] to consume
+    /// * `replaced_by`: The [`RefreshToken`] which replaces the consumed one
     ///
     /// # Errors
     ///
@@ -97,6 +98,26 @@ pub trait OAuth2RefreshTokenRepository: Send + Sync {
         &mut self,
         clock: &dyn Clock,
         refresh_token: RefreshToken,
+        replaced_by: &RefreshToken,
+    ) -> Result<RefreshToken, Self::Error>;
+
+    /// Revoke a refresh token
+    ///
+    /// Returns the revoked [`RefreshToken`]
+    ///
+    /// # Parameters
+    ///
+    /// * `clock`: The clock used to generate timestamps
+    /// * `refresh_token`: The [`RefreshToken`] to revoke
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails, or if the
+    /// token was already consumed or revoked
+    async fn revoke(
+        &mut self,
+        clock: &dyn Clock,
+        refresh_token: RefreshToken,
     ) -> Result<RefreshToken, Self::Error>;
 }
 
@@ -121,5 +142,12 @@ repository_impl!(OAuth2RefreshTokenRepository:
         &mut self,
         clock: &dyn Clock,
         refresh_token: RefreshToken,
+        replaced_by: &RefreshToken,
+    ) -> Result<RefreshToken, Self::Error>;
+
+    async fn revoke(
+        &mut self,
+        clock: &dyn Clock,
+        refresh_token: RefreshToken,
     ) -> Result<RefreshToken, Self::Error>;
 );
@@ -20,6 +20,7 @@ use mas_data_model::{BrowserSession, Client, Session, User, UserAgent};
 use oauth2_types::scope::Scope;
 use rand_core::RngCore;
 use ulid::Ulid;
+use url::Url;
 
 use crate::{pagination::Page, repository_impl, Clock, Pagination};
 
@@ -43,6 +44,7 @@ impl OAuth2SessionState {
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
 pub struct OAuth2SessionFilter<'a> {
     user: Option<&'a User>,
+    browser_session: Option<&'a BrowserSession>,
     client: Option<&'a Client>,
     state: Option<OAuth2SessionState>,
     scope: Option<&'a Scope>,
@@ -70,6 +72,21 @@ impl<'a> OAuth2SessionFilter<'a> {
         self.user
     }
 
+    /// List sessions started by a specific browser session
+    #[must_use]
+    pub fn for_browser_session(mut self, browser_session: &'a BrowserSession) -> Self {
+        self.browser_session = Some(browser_session);
+        self
+    }
+
+    /// Get the browser session filter
+    ///
+    /// Returns [`None`] if no browser session filter was set
+    #[must_use]
+    pub fn browser_session(&self) -> Option<&BrowserSession> {
+        self.browser_session
+    }
+
     /// List sessions for a specific client
     #[must_use]
     pub fn for_client(mut self, client: &'a Client) -> Self {
@@ -298,6 +315,62 @@ pub trait OAuth2SessionRepository: Send + Sync {
         session: Session,
         user_agent: UserAgent,
     ) -> Result<Session, Self::Error>;
+
+    /// Record the time at which the user authenticated for the authorization
+    /// which created a [`Session`]
+    ///
+    /// Returns the updated [`Session`]
+    ///
+    /// # Parameters
+    ///
+    /// * `session`: The [`Session`] to record the authentication time for
+    /// * `auth_time`: The time at which the user authenticated
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn record_auth_time(
+        &mut self,
+        session: Session,
+        auth_time: DateTime<Utc>,
+    ) -> Result<Session, Self::Error>;
+
+    /// Record the resources the access tokens of a [`Session`] are restricted
+    /// to
+    ///
+    /// Returns the updated [`Session`]
+    ///
+    /// # Parameters
+    ///
+    /// * `session`: The [`Session`] to record the resources for
+    /// * `resource`: The resources the access tokens are restricted to
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn record_resource(
+        &mut self,
+        session: Session,
+        resource: Vec<Url>,
+    ) -> Result<Session, Self::Error>;
+
+    /// Bind the refresh tokens of a [`Session`] to a `DPoP` key
+    ///
+    /// Returns the updated [`Session`]
+    ///
+    /// # Parameters
+    ///
+    /// * `session`: The [`Session`] to bind
+    /// * `jkt`: The JWK SHA-256 thumbprint of the `DPoP` key
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn bind_dpop_key(
+        &mut self,
+        session: Session,
+        jkt: String,
+    ) -> Result<Session, Self::Error>;
 }
 
 repository_impl!(OAuth2SessionRepository:
@@ -351,4 +424,22 @@ repository_impl!(OAuth2SessionRepository:
         session: Session,
         user_agent: UserAgent,
     ) -> Result<Session, Self::Error>;
+
+    async fn record_auth_time(
+        &mut self,
+        session: Session,
+        auth_time: DateTime<Utc>,
+    ) -> Result<Session, Self::Error>;
+
+    async fn record_resource(
+        &mut self,
+        session: Session,
+        resource: Vec<Url>,
+    ) -> Result<Session, Self::Error>;
+
+    async fn bind_dpop_key(
+        &mut self,
+        session: Session,
+        jkt: String,
+    ) -> Result<Session, Self::Error>;
 );
@@ -14,6 +14,7 @@
 
 //! Utilities to manage paginated queries.
 
+use chrono::{DateTime, Utc};
 use thiserror::Error;
 use ulid::Ulid;
 
@@ -26,16 +27,87 @@ pub struct InvalidPagination;
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Pagination {
     /// The cursor to start from
-    pub before: Option<Ulid>,
+    pub before: Option<PaginationCursor>,
 
     /// The cursor to end at
-    pub after: Option<Ulid>,
+    pub after: Option<PaginationCursor>,
 
     /// The maximum number of items to return
     pub count: usize,
 
     /// In which direction to paginate
     pub direction: PaginationDirection,
+
+    /// Which column to order the items by
+    ///
+    /// Repositories which only support ordering by ID ignore this
+    pub order_by: PaginationOrderBy,
+
+    /// In which order the items are sorted
+    pub order: PaginationOrder,
+
+    /// Whether to also count the total number of items matching the query
+    ///
+    /// Repositories which don't support it leave the total count of the
+    /// returned [`Page`] empty
+    pub with_total_count: bool,
+}
+
+/// A cursor pointing to an item in a paginated list
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PaginationCursor {
+    /// The ID of the item
+    pub id: Ulid,
+
+    /// The value of the column the items are ordered by for this item, if
+    /// they are not ordered by ID.
+    ///
+    /// This is `None` when the column is `NULL` for this item, in which case
+    /// it is sorted after all other values
+    pub value: Option<DateTime<Utc>>,
+}
+
+impl PaginationCursor {
+    /// Creates a new [`PaginationCursor`] pointing to the item with the given
+    /// ID, with the value of the column the items are ordered by
+    #[must_use]
+    pub const fn new(id: Ulid, value: Option<DateTime<Utc>>) -> Self {
+        Self { id, value }
+    }
+}
+
+impl From<Ulid> for PaginationCursor {
+    fn from(id: Ulid) -> Self {
+        Self { id, value: None }
+    }
+}
+
+/// The column to order the items of a paginated list by
+///
+/// Items which have the same value in this column are then ordered by ID
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PaginationOrderBy {
+    /// Order by ID
+    #[default]
+    Id,
+
+    /// Order by creation date
+    CreatedAt,
+
+    /// Order by the date at which the item was finished. Items which are not
+    /// finished are sorted after the finished ones
+    FinishedAt,
+}
+
+/// The order in which the items of a paginated list are sorted
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PaginationOrder {
+    /// Sort in ascending order
+    #[default]
+    Ascending,
+
+    /// Sort in descending order
+    Descending,
 }
 
 /// The direction to paginate
@@ -55,9 +127,9 @@ impl Pagination {
     ///
     /// Either `first` or `last` must be provided, else this function will
     /// return an [`InvalidPagination`] error.
-    pub const fn try_new(
-        before: Option<Ulid>,
-        after: Option<Ulid>,
+    pub fn try_new<C: Into<PaginationCursor>>(
+        before: Option<C>,
+        after: Option<C>,
         first: Option<usize>,
         last: Option<usize>,
     ) -> Result<Self, InvalidPagination> {
@@ -68,10 +140,13 @@ impl Pagination {
         };
 
         Ok(Self {
-            before,
-            after,
+            before: before.map(Into::into),
+            after: after.map(Into::into),
             count,
             direction,
+            order_by: PaginationOrderBy::Id,
+            order: PaginationOrder::Ascending,
+            with_total_count: false,
         })
     }
 
@@ -83,6 +158,9 @@ impl Pagination {
             after: None,
             count: first,
             direction: PaginationDirection::Forward,
+            order_by: PaginationOrderBy::Id,
+            order: PaginationOrder::Ascending,
+            with_total_count: false,
         }
     }
 
@@ -94,20 +172,39 @@ impl Pagination {
             after: None,
             count: last,
             direction: PaginationDirection::Backward,
+            order_by: PaginationOrderBy::Id,
+            order: PaginationOrder::Ascending,
+            with_total_count: false,
         }
     }
 
     /// Get items before the given cursor
     #[must_use]
-    pub const fn before(mut self, id: Ulid) -> Self {
-        self.before = Some(id);
+    pub fn before(mut self, cursor: impl Into<PaginationCursor>) -> Self {
+        self.before = Some(cursor.into());
         self
     }
 
     /// Get items after the given cursor
     #[must_use]
-    pub const fn after(mut self, id: Ulid) -> Self {
-        self.after = Some(id);
+    pub fn after(mut self, cursor: impl Into<PaginationCursor>) -> Self {
+        self.after = Some(cursor.into());
+        self
+    }
+
+    /// Order the items by the given column, in the given order
+    #[must_use]
+    pub const fn ordered_by(mut self, order_by: PaginationOrderBy, order: PaginationOrder) -> Self {
+        self.order_by = order_by;
+        self.order = order;
+        self
+    }
+
+    /// Also count the total number of items matching the query, regardless of
+    /// the pagination
+    #[must_use]
+    pub const fn with_count(mut self, with_total_count: bool) -> Self {
+        self.with_total_count = with_total_count;
         self
     }
 
@@ -132,6 +229,7 @@ impl Pagination {
             has_next_page,
             has_previous_page,
             edges,
+            total_count: None,
         }
     }
 }
@@ -147,9 +245,20 @@ pub struct Page<T> {
 
     /// The items in the page
     pub edges: Vec<T>,
+
+    /// The total number of items matching the query, if it was requested with
+    /// [`Pagination::with_count`]
+    pub total_count: Option<u64>,
 }
 
 impl<T> Page<T> {
+    /// Set the total number of items matching the query
+    #[must_use]
+    pub fn with_total_count(mut self, total_count: Option<u64>) -> Self {
+        self.total_count = total_count;
+        self
+    }
+
     /// Map the items in this page with the given function
     ///
     /// # Parameters
@@ -165,6 +274,7 @@ impl<T> Page<T> {
             has_next_page: self.has_next_page,
             has_previous_page: self.has_previous_page,
             edges,
+            total_count: self.total_count,
         }
     }
 
@@ -186,6 +296,7 @@ impl<T> Page<T> {
             has_next_page: self.has_next_page,
             has_previous_page: self.has_previous_page,
             edges: edges?,
+            total_count: self.total_count,
         })
     }
 }
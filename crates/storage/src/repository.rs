@@ -17,6 +17,7 @@ use thiserror::Error;
 
 use crate::{
     app_session::AppSessionRepository,
+    audit_log::AuditLogRepository,
     compat::{
         CompatAccessTokenRepository, CompatRefreshTokenRepository, CompatSessionRepository,
         CompatSsoLoginRepository,
@@ -24,15 +25,17 @@ use crate::{
     job::JobRepository,
     oauth2::{
         OAuth2AccessTokenRepository, OAuth2AuthorizationGrantRepository, OAuth2ClientRepository,
-        OAuth2DeviceCodeGrantRepository, OAuth2RefreshTokenRepository, OAuth2SessionRepository,
+        OAuth2ConsentRepository, OAuth2DeviceCodeGrantRepository,
+        OAuth2PushedAuthRequestRepository, OAuth2RefreshTokenRepository, OAuth2SessionRepository,
     },
     upstream_oauth2::{
         UpstreamOAuthLinkRepository, UpstreamOAuthProviderRepository,
         UpstreamOAuthSessionRepository,
     },
     user::{
-        BrowserSessionRepository, UserEmailRepository, UserPasswordRepository, UserRepository,
-        UserTermsRepository,
+        BrowserSessionRepository, UserEmailRepository, UserPasswordRepository,
+        UserPasswordResetTokenRepository, UserRecoveryCodeRepository, UserRepository,
+        UserTermsRepository, UserTotpRepository, UserWebAuthnRepository,
     },
     MapErr,
 };
@@ -152,6 +155,23 @@ pub trait RepositoryAccess: Send {
     /// Get an [`UserTermsRepository`]
     fn user_terms<'c>(&'c mut self) -> Box<dyn UserTermsRepository<Error = Self::Error> + 'c>;
 
+    /// Get an [`UserWebAuthnRepository`]
+    fn user_webauthn<'c>(&'c mut self)
+        -> Box<dyn UserWebAuthnRepository<Error = Self::Error> + 'c>;
+
+    /// Get an [`UserTotpRepository`]
+    fn user_totp<'c>(&'c mut self) -> Box<dyn UserTotpRepository<Error = Self::Error> + 'c>;
+
+    /// Get an [`UserRecoveryCodeRepository`]
+    fn user_recovery_code<'c>(
+        &'c mut self,
+    ) -> Box<dyn UserRecoveryCodeRepository<Error = Self::Error> + 'c>;
+
+    /// Get an [`UserPasswordResetTokenRepository`]
+    fn user_password_reset_token<'c>(
+        &'c mut self,
+    ) -> Box<dyn UserPasswordResetTokenRepository<Error = Self::Error> + 'c>;
+
     /// Get a [`BrowserSessionRepository`]
     fn browser_session<'c>(
         &'c mut self,
@@ -160,10 +180,18 @@ pub trait RepositoryAccess: Send {
     /// Get a [`AppSessionRepository`]
     fn app_session<'c>(&'c mut self) -> Box<dyn AppSessionRepository<Error = Self::Error> + 'c>;
 
+    /// Get an [`AuditLogRepository`]
+    fn audit_log<'c>(&'c mut self) -> Box<dyn AuditLogRepository<Error = Self::Error> + 'c>;
+
     /// Get an [`OAuth2ClientRepository`]
     fn oauth2_client<'c>(&'c mut self)
         -> Box<dyn OAuth2ClientRepository<Error = Self::Error> + 'c>;
 
+    /// Get an [`OAuth2ConsentRepository`]
+    fn oauth2_consent<'c>(
+        &'c mut self,
+    ) -> Box<dyn OAuth2ConsentRepository<Error = Self::Error> + 'c>;
+
     /// Get an [`OAuth2AuthorizationGrantRepository`]
     fn oauth2_authorization_grant<'c>(
         &'c mut self,
@@ -189,6 +217,11 @@ pub trait RepositoryAccess: Send {
         &'c mut self,
     ) -> Box<dyn OAuth2DeviceCodeGrantRepository<Error = Self::Error> + 'c>;
 
+    /// Get an [`OAuth2PushedAuthRequestRepository`]
+    fn oauth2_pushed_auth_request<'c>(
+        &'c mut self,
+    ) -> Box<dyn OAuth2PushedAuthRequestRepository<Error = Self::Error> + 'c>;
+
     /// Get a [`CompatSessionRepository`]
     fn compat_session<'c>(
         &'c mut self,
@@ -221,6 +254,7 @@ mod impls {
     use super::RepositoryAccess;
     use crate::{
         app_session::AppSessionRepository,
+        audit_log::AuditLogRepository,
         compat::{
             CompatAccessTokenRepository, CompatRefreshTokenRepository, CompatSessionRepository,
             CompatSsoLoginRepository,
@@ -228,7 +262,8 @@ mod impls {
         job::JobRepository,
         oauth2::{
             OAuth2AccessTokenRepository, OAuth2AuthorizationGrantRepository,
-            OAuth2ClientRepository, OAuth2DeviceCodeGrantRepository, OAuth2RefreshTokenRepository,
+            OAuth2ClientRepository, OAuth2ConsentRepository, OAuth2DeviceCodeGrantRepository,
+            OAuth2PushedAuthRequestRepository, OAuth2RefreshTokenRepository,
             OAuth2SessionRepository,
         },
         upstream_oauth2::{
@@ -236,8 +271,9 @@ mod impls {
             UpstreamOAuthSessionRepository,
         },
         user::{
-            BrowserSessionRepository, UserEmailRepository, UserPasswordRepository, UserRepository,
-            UserTermsRepository,
+            BrowserSessionRepository, UserEmailRepository, UserPasswordRepository,
+            UserPasswordResetTokenRepository, UserRecoveryCodeRepository, UserRepository,
+            UserTermsRepository, UserTotpRepository, UserWebAuthnRepository,
         },
         MapErr, Repository, RepositoryTransaction,
     };
@@ -326,6 +362,34 @@ mod impls {
             Box::new(MapErr::new(self.inner.user_terms(), &mut self.mapper))
         }
 
+        fn user_webauthn<'c>(
+            &'c mut self,
+        ) -> Box<dyn UserWebAuthnRepository<Error = Self::Error> + 'c> {
+            Box::new(MapErr::new(self.inner.user_webauthn(), &mut self.mapper))
+        }
+
+        fn user_totp<'c>(&'c mut self) -> Box<dyn UserTotpRepository<Error = Self::Error> + 'c> {
+            Box::new(MapErr::new(self.inner.user_totp(), &mut self.mapper))
+        }
+
+        fn user_recovery_code<'c>(
+            &'c mut self,
+        ) -> Box<dyn UserRecoveryCodeRepository<Error = Self::Error> + 'c> {
+            Box::new(MapErr::new(
+                self.inner.user_recovery_code(),
+                &mut self.mapper,
+            ))
+        }
+
+        fn user_password_reset_token<'c>(
+            &'c mut self,
+        ) -> Box<dyn UserPasswordResetTokenRepository<Error = Self::Error> + 'c> {
+            Box::new(MapErr::new(
+                self.inner.user_password_reset_token(),
+                &mut self.mapper,
+            ))
+        }
+
         fn browser_session<'c>(
             &'c mut self,
         ) -> Box<dyn BrowserSessionRepository<Error = Self::Error> + 'c> {
@@ -338,12 +402,22 @@ mod impls {
             Box::new(MapErr::new(self.inner.app_session(), &mut self.mapper))
         }
 
+        fn audit_log<'c>(&'c mut self) -> Box<dyn AuditLogRepository<Error = Self::Error> + 'c> {
+            Box::new(MapErr::new(self.inner.audit_log(), &mut self.mapper))
+        }
+
         fn oauth2_client<'c>(
             &'c mut self,
         ) -> Box<dyn OAuth2ClientRepository<Error = Self::Error> + 'c> {
             Box::new(MapErr::new(self.inner.oauth2_client(), &mut self.mapper))
         }
 
+        fn oauth2_consent<'c>(
+            &'c mut self,
+        ) -> Box<dyn OAuth2ConsentRepository<Error = Self::Error> + 'c> {
+            Box::new(MapErr::new(self.inner.oauth2_consent(), &mut self.mapper))
+        }
+
         fn oauth2_authorization_grant<'c>(
             &'c mut self,
         ) -> Box<dyn OAuth2AuthorizationGrantRepository<Error = Self::Error> + 'c> {
@@ -386,6 +460,15 @@ mod impls {
             ))
         }
 
+        fn oauth2_pushed_auth_request<'c>(
+            &'c mut self,
+        ) -> Box<dyn OAuth2PushedAuthRequestRepository<Error = Self::Error> + 'c> {
+            Box::new(MapErr::new(
+                self.inner.oauth2_pushed_auth_request(),
+                &mut self.mapper,
+            ))
+        }
+
         fn compat_session<'c>(
             &'c mut self,
         ) -> Box<dyn CompatSessionRepository<Error = Self::Error> + 'c> {
@@ -460,6 +543,28 @@ mod impls {
             (**self).user_terms()
         }
 
+        fn user_webauthn<'c>(
+            &'c mut self,
+        ) -> Box<dyn UserWebAuthnRepository<Error = Self::Error> + 'c> {
+            (**self).user_webauthn()
+        }
+
+        fn user_totp<'c>(&'c mut self) -> Box<dyn UserTotpRepository<Error = Self::Error> + 'c> {
+            (**self).user_totp()
+        }
+
+        fn user_recovery_code<'c>(
+            &'c mut self,
+        ) -> Box<dyn UserRecoveryCodeRepository<Error = Self::Error> + 'c> {
+            (**self).user_recovery_code()
+        }
+
+        fn user_password_reset_token<'c>(
+            &'c mut self,
+        ) -> Box<dyn UserPasswordResetTokenRepository<Error = Self::Error> + 'c> {
+            (**self).user_password_reset_token()
+        }
+
         fn browser_session<'c>(
             &'c mut self,
         ) -> Box<dyn BrowserSessionRepository<Error = Self::Error> + 'c> {
@@ -472,12 +577,22 @@ mod impls {
             (**self).app_session()
         }
 
+        fn audit_log<'c>(&'c mut self) -> Box<dyn AuditLogRepository<Error = Self::Error> + 'c> {
+            (**self).audit_log()
+        }
+
         fn oauth2_client<'c>(
             &'c mut self,
         ) -> Box<dyn OAuth2ClientRepository<Error = Self::Error> + 'c> {
             (**self).oauth2_client()
         }
 
+        fn oauth2_consent<'c>(
+            &'c mut self,
+        ) -> Box<dyn OAuth2ConsentRepository<Error = Self::Error> + 'c> {
+            (**self).oauth2_consent()
+        }
+
         fn oauth2_authorization_grant<'c>(
             &'c mut self,
         ) -> Box<dyn OAuth2AuthorizationGrantRepository<Error = Self::Error> + 'c> {
@@ -508,6 +623,12 @@ mod impls {
             (**self).oauth2_device_code_grant()
         }
 
+        fn oauth2_pushed_auth_request<'c>(
+            &'c mut self,
+        ) -> Box<dyn OAuth2PushedAuthRequestRepository<Error = Self::Error> + 'c> {
+            (**self).oauth2_pushed_auth_request()
+        }
+
         fn compat_session<'c>(
             &'c mut self,
         ) -> Box<dyn CompatSessionRepository<Error = Self::Error> + 'c> {
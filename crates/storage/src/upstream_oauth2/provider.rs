@@ -77,6 +77,10 @@ pub struct UpstreamOAuthProviderParams {
 
     /// Additional parameters to include in the authorization request
     pub additional_authorization_parameters: Vec<(String, String)>,
+
+    /// Whether the `login_hint` sent by the client should be forwarded to the
+    /// provider in the authorization request
+    pub forward_login_hint: bool,
 }
 
 /// Filter parameters for listing upstream OAuth 2.0 providers
@@ -220,6 +224,24 @@ pub trait UpstreamOAuthProviderRepository: Send + Sync {
         provider: UpstreamOAuthProvider,
     ) -> Result<UpstreamOAuthProvider, Self::Error>;
 
+    /// Replace the encrypted client secret of an upstream OAuth provider
+    ///
+    /// Returns the updated provider
+    ///
+    /// # Parameters
+    ///
+    /// * `provider`: The provider to update
+    /// * `encrypted_client_secret`: The new encrypted client secret, if any
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn set_encrypted_client_secret(
+        &mut self,
+        provider: UpstreamOAuthProvider,
+        encrypted_client_secret: Option<String>,
+    ) -> Result<UpstreamOAuthProvider, Self::Error>;
+
     /// List [`UpstreamOAuthProvider`] with the given filter and pagination
     ///
     /// # Parameters
@@ -285,6 +307,12 @@ repository_impl!(UpstreamOAuthProviderRepository:
         provider: UpstreamOAuthProvider
     ) -> Result<UpstreamOAuthProvider, Self::Error>;
 
+    async fn set_encrypted_client_secret(
+        &mut self,
+        provider: UpstreamOAuthProvider,
+        encrypted_client_secret: Option<String>
+    ) -> Result<UpstreamOAuthProvider, Self::Error>;
+
     async fn list(
         &mut self,
         filter: UpstreamOAuthProviderFilter<'_>,
@@ -41,6 +41,7 @@ impl UserEmailState {
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
 pub struct UserEmailFilter<'a> {
     user: Option<&'a User>,
+    email: Option<&'a str>,
     state: Option<UserEmailState>,
 }
 
@@ -66,6 +67,21 @@ impl<'a> UserEmailFilter<'a> {
         self.user
     }
 
+    /// Filter for emails with a specific address, ignoring its case
+    #[must_use]
+    pub fn for_email(mut self, email: &'a str) -> Self {
+        self.email = Some(email);
+        self
+    }
+
+    /// Get the email address filter
+    ///
+    /// Returns [`None`] if no email address filter is set
+    #[must_use]
+    pub fn email(&self) -> Option<&str> {
+        self.email
+    }
+
     /// Filter for emails that are verified
     #[must_use]
     pub fn verified_only(mut self) -> Self {
@@ -280,6 +296,50 @@ pub trait UserEmailRepository: Send + Sync {
         code: &str,
     ) -> Result<Option<UserEmailVerification>, Self::Error>;
 
+    /// Find the most recent [`UserEmailVerification`] sent to the address of
+    /// a [`UserEmail`]
+    ///
+    /// This looks at the verifications of every [`UserEmail`] with the same
+    /// address, regardless of the user it belongs to.
+    ///
+    /// Returns `None` if no [`UserEmailVerification`] was ever added
+    ///
+    /// # Parameters
+    ///
+    /// * `clock`: The clock to use
+    /// * `user_email`: The [`UserEmail`] for which to lookup the
+    ///   [`UserEmailVerification`]
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn find_latest_verification_code(
+        &mut self,
+        clock: &dyn Clock,
+        user_email: &UserEmail,
+    ) -> Result<Option<UserEmailVerification>, Self::Error>;
+
+    /// Record a failed attempt at verifying a [`UserEmail`]
+    ///
+    /// The attempt is counted on every pending [`UserEmailVerification`] of
+    /// the [`UserEmail`]. Those which reach
+    /// [`UserEmailVerification::MAX_FAILED_ATTEMPTS`] failed attempts expire
+    /// immediately.
+    ///
+    /// # Parameters
+    ///
+    /// * `clock`: The clock to use
+    /// * `user_email`: The [`UserEmail`] for which a wrong code was submitted
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn record_failed_verification_attempt(
+        &mut self,
+        clock: &dyn Clock,
+        user_email: &UserEmail,
+    ) -> Result<(), Self::Error>;
+
     /// Consume a [`UserEmailVerification`]
     ///
     /// Returns the consumed [`UserEmailVerification`]
@@ -345,6 +405,18 @@ repository_impl!(UserEmailRepository:
         code: &str,
     ) -> Result<Option<UserEmailVerification>, Self::Error>;
 
+    async fn find_latest_verification_code(
+        &mut self,
+        clock: &dyn Clock,
+        user_email: &UserEmail,
+    ) -> Result<Option<UserEmailVerification>, Self::Error>;
+
+    async fn record_failed_verification_attempt(
+        &mut self,
+        clock: &dyn Clock,
+        user_email: &UserEmail,
+    ) -> Result<(), Self::Error>;
+
     async fn consume_verification_code(
         &mut self,
         clock: &dyn Clock,
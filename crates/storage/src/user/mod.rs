@@ -19,20 +19,73 @@ use mas_data_model::User;
 use rand_core::RngCore;
 use ulid::Ulid;
 
-use crate::{repository_impl, Clock};
+use crate::{repository_impl, Clock, Page, Pagination};
 
 mod email;
 mod password;
+mod password_reset;
+mod recovery_code;
 mod session;
 mod terms;
+mod totp;
+mod webauthn;
 
 pub use self::{
     email::{UserEmailFilter, UserEmailRepository},
     password::UserPasswordRepository,
+    password_reset::UserPasswordResetTokenRepository,
+    recovery_code::UserRecoveryCodeRepository,
     session::{BrowserSessionFilter, BrowserSessionRepository},
     terms::UserTermsRepository,
+    totp::UserTotpRepository,
+    webauthn::UserWebAuthnRepository,
 };
 
+/// Filter parameters for listing users
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct UserFilter<'a> {
+    username_prefix: Option<&'a str>,
+    can_request_admin: Option<bool>,
+}
+
+impl<'a> UserFilter<'a> {
+    /// Create a new [`UserFilter`] with default values
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Filter for users whose username starts with the given prefix
+    #[must_use]
+    pub fn with_username_prefix(mut self, prefix: &'a str) -> Self {
+        self.username_prefix = Some(prefix);
+        self
+    }
+
+    /// Filter for users which can or can't request admin privileges
+    #[must_use]
+    pub fn with_can_request_admin(mut self, can_request_admin: bool) -> Self {
+        self.can_request_admin = Some(can_request_admin);
+        self
+    }
+
+    /// Get the username prefix filter
+    ///
+    /// Returns `None` if no username prefix filter was set
+    #[must_use]
+    pub fn username_prefix(&self) -> Option<&'a str> {
+        self.username_prefix
+    }
+
+    /// Get the `can_request_admin` filter
+    ///
+    /// Returns `None` if no `can_request_admin` filter was set
+    #[must_use]
+    pub fn can_request_admin(&self) -> Option<bool> {
+        self.can_request_admin
+    }
+}
+
 /// A [`UserRepository`] helps interacting with [`User`] saved in the storage
 /// backend
 #[async_trait]
@@ -126,6 +179,33 @@ pub trait UserRepository: Send + Sync {
     /// Returns [`Self::Error`] if the underlying repository fails
     async fn unlock(&mut self, user: User) -> Result<User, Self::Error>;
 
+    /// Deactivate a [`User`], without deleting any of its data
+    ///
+    /// Returns the deactivated [`User`]
+    ///
+    /// # Parameters
+    ///
+    /// * `clock`: The clock used to generate timestamps
+    /// * `user`: The [`User`] to deactivate
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn deactivate(&mut self, clock: &dyn Clock, user: User) -> Result<User, Self::Error>;
+
+    /// Reactivate a deactivated [`User`]
+    ///
+    /// Returns the reactivated [`User`]
+    ///
+    /// # Parameters
+    ///
+    /// * `user`: The [`User`] to reactivate
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn reactivate(&mut self, user: User) -> Result<User, Self::Error>;
+
     /// Set whether a [`User`] can request admin
     ///
     /// Returns the [`User`] with the new `can_request_admin` value
@@ -142,6 +222,47 @@ pub trait UserRepository: Send + Sync {
         user: User,
         can_request_admin: bool,
     ) -> Result<User, Self::Error>;
+
+    /// Set whether a [`User`] is an administrator
+    ///
+    /// Returns the [`User`] with the new `is_admin` value
+    ///
+    /// # Parameters
+    ///
+    /// * `user`: The [`User`] to update
+    /// * `is_admin`: Whether the user should be an administrator
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn set_admin(&mut self, user: User, is_admin: bool) -> Result<User, Self::Error>;
+
+    /// List [`User`] with the given filter and pagination
+    ///
+    /// # Parameters
+    ///
+    /// * `filter`: The filter parameters
+    /// * `pagination`: The pagination parameters
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn list(
+        &mut self,
+        filter: UserFilter<'_>,
+        pagination: Pagination,
+    ) -> Result<Page<User>, Self::Error>;
+
+    /// Count the [`User`] with the given filter
+    ///
+    /// # Parameters
+    ///
+    /// * `filter`: The filter parameters
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn count(&mut self, filter: UserFilter<'_>) -> Result<usize, Self::Error>;
 }
 
 repository_impl!(UserRepository:
@@ -156,9 +277,18 @@ repository_impl!(UserRepository:
     async fn exists(&mut self, username: &str) -> Result<bool, Self::Error>;
     async fn lock(&mut self, clock: &dyn Clock, user: User) -> Result<User, Self::Error>;
     async fn unlock(&mut self, user: User) -> Result<User, Self::Error>;
+    async fn deactivate(&mut self, clock: &dyn Clock, user: User) -> Result<User, Self::Error>;
+    async fn reactivate(&mut self, user: User) -> Result<User, Self::Error>;
     async fn set_can_request_admin(
         &mut self,
         user: User,
         can_request_admin: bool,
     ) -> Result<User, Self::Error>;
+    async fn set_admin(&mut self, user: User, is_admin: bool) -> Result<User, Self::Error>;
+    async fn list(
+        &mut self,
+        filter: UserFilter<'_>,
+        pagination: Pagination,
+    ) -> Result<Page<User>, Self::Error>;
+    async fn count(&mut self, filter: UserFilter<'_>) -> Result<usize, Self::Error>;
 );
@@ -0,0 +1,102 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use async_trait::async_trait;
+use chrono::Duration;
+use mas_data_model::{User, UserPasswordResetToken};
+use rand_core::RngCore;
+use ulid::Ulid;
+
+use crate::{repository_impl, Clock};
+
+/// A [`UserPasswordResetTokenRepository`] helps interacting with the
+/// [`UserPasswordResetToken`] saved in the storage backend
+#[async_trait]
+pub trait UserPasswordResetTokenRepository: Send + Sync {
+    /// The error type returned by the repository
+    type Error;
+
+    /// Lookup a [`UserPasswordResetToken`] by its ID
+    ///
+    /// Returns `None` if no token was found
+    ///
+    /// # Parameters
+    ///
+    /// * `id`: The ID of the token to lookup
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn lookup(&mut self, id: Ulid) -> Result<Option<UserPasswordResetToken>, Self::Error>;
+
+    /// Create a new [`UserPasswordResetToken`] for a [`User`]
+    ///
+    /// Returns the newly created [`UserPasswordResetToken`]
+    ///
+    /// # Parameters
+    ///
+    /// * `rng`: The random number generator to use
+    /// * `clock`: The clock used to generate timestamps
+    /// * `user`: The [`User`] whose password can be reset with the token
+    /// * `hashed_secret`: The hash of the secret part of the token
+    /// * `expires_in`: How long the token is valid for
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn add(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        user: &User,
+        hashed_secret: String,
+        expires_in: Duration,
+    ) -> Result<UserPasswordResetToken, Self::Error>;
+
+    /// Mark a [`UserPasswordResetToken`] as used
+    ///
+    /// Returns the updated [`UserPasswordResetToken`]
+    ///
+    /// # Parameters
+    ///
+    /// * `clock`: The clock used to generate timestamps
+    /// * `token`: The [`UserPasswordResetToken`] to consume
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails, or if the
+    /// token was already used or is expired
+    async fn consume(
+        &mut self,
+        clock: &dyn Clock,
+        token: UserPasswordResetToken,
+    ) -> Result<UserPasswordResetToken, Self::Error>;
+}
+
+repository_impl!(UserPasswordResetTokenRepository:
+    async fn lookup(&mut self, id: Ulid) -> Result<Option<UserPasswordResetToken>, Self::Error>;
+    async fn add(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        user: &User,
+        hashed_secret: String,
+        expires_in: Duration,
+    ) -> Result<UserPasswordResetToken, Self::Error>;
+    async fn consume(
+        &mut self,
+        clock: &dyn Clock,
+        token: UserPasswordResetToken,
+    ) -> Result<UserPasswordResetToken, Self::Error>;
+);
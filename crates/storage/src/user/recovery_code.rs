@@ -0,0 +1,132 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use async_trait::async_trait;
+use mas_data_model::{User, UserRecoveryCode};
+use rand_core::RngCore;
+
+use crate::{repository_impl, Clock};
+
+/// A [`UserRecoveryCodeRepository`] helps interacting with the
+/// [`UserRecoveryCode`] of users saved in the storage backend
+#[async_trait]
+pub trait UserRecoveryCodeRepository: Send + Sync {
+    /// The error type returned by the repository
+    type Error;
+
+    /// Find an unused [`UserRecoveryCode`] of a [`User`] by its hash
+    ///
+    /// Returns `None` if no matching unused recovery code was found
+    ///
+    /// # Parameters
+    ///
+    /// * `user`: The [`User`] who owns the recovery code
+    /// * `hashed_code`: The hash of the recovery code
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn find_active(
+        &mut self,
+        user: &User,
+        hashed_code: &str,
+    ) -> Result<Option<UserRecoveryCode>, Self::Error>;
+
+    /// Count the unused [`UserRecoveryCode`] of a [`User`]
+    ///
+    /// # Parameters
+    ///
+    /// * `user`: The [`User`] for whom to count the recovery codes
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn count_active(&mut self, user: &User) -> Result<usize, Self::Error>;
+
+    /// Save a new set of [`UserRecoveryCode`] for a [`User`]
+    ///
+    /// Returns the newly created [`UserRecoveryCode`]
+    ///
+    /// # Parameters
+    ///
+    /// * `rng`: The random number generator to use
+    /// * `clock`: The clock used to generate timestamps
+    /// * `user`: The [`User`] for whom to save the recovery codes
+    /// * `hashed_codes`: The hashes of the recovery codes
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn add(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        user: &User,
+        hashed_codes: Vec<String>,
+    ) -> Result<Vec<UserRecoveryCode>, Self::Error>;
+
+    /// Mark a [`UserRecoveryCode`] as used
+    ///
+    /// Returns the updated [`UserRecoveryCode`], or `None` if it was already
+    /// used, for example by a concurrent request
+    ///
+    /// # Parameters
+    ///
+    /// * `clock`: The clock used to generate timestamps
+    /// * `recovery_code`: The [`UserRecoveryCode`] to consume
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn consume(
+        &mut self,
+        clock: &dyn Clock,
+        recovery_code: UserRecoveryCode,
+    ) -> Result<Option<UserRecoveryCode>, Self::Error>;
+
+    /// Delete all the [`UserRecoveryCode`] of a [`User`]
+    ///
+    /// Returns the number of recovery codes deleted
+    ///
+    /// # Parameters
+    ///
+    /// * `user`: The [`User`] for whom to delete the recovery codes
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn delete_all_for_user(&mut self, user: &User) -> Result<usize, Self::Error>;
+}
+
+repository_impl!(UserRecoveryCodeRepository:
+    async fn find_active(
+        &mut self,
+        user: &User,
+        hashed_code: &str,
+    ) -> Result<Option<UserRecoveryCode>, Self::Error>;
+    async fn count_active(&mut self, user: &User) -> Result<usize, Self::Error>;
+    async fn add(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        user: &User,
+        hashed_codes: Vec<String>,
+    ) -> Result<Vec<UserRecoveryCode>, Self::Error>;
+    async fn consume(
+        &mut self,
+        clock: &dyn Clock,
+        recovery_code: UserRecoveryCode,
+    ) -> Result<Option<UserRecoveryCode>, Self::Error>;
+    async fn delete_all_for_user(&mut self, user: &User) -> Result<usize, Self::Error>;
+);
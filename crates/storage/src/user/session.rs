@@ -12,12 +12,16 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use std::net::IpAddr;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    net::IpAddr,
+};
 
 use async_trait::async_trait;
 use chrono::{DateTime, Utc};
 use mas_data_model::{
-    Authentication, BrowserSession, Password, UpstreamOAuthAuthorizationSession, User, UserAgent,
+    Authentication, BrowserSession, Password, Totp, UpstreamOAuthAuthorizationSession, User,
+    UserAgent, UserRecoveryCode, WebAuthnCredential,
 };
 use rand_core::RngCore;
 use ulid::Ulid;
@@ -44,7 +48,11 @@ impl BrowserSessionState {
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
 pub struct BrowserSessionFilter<'a> {
     user: Option<&'a User>,
-    state: Option<BrowserSessionState>,
+    state: Option<(BrowserSessionState, DateTime<Utc>)>,
+    created_after: Option<DateTime<Utc>>,
+    created_before: Option<DateTime<Utc>>,
+    last_authenticated_after: Option<DateTime<Utc>>,
+    last_authenticated_before: Option<DateTime<Utc>>,
 }
 
 impl<'a> BrowserSessionFilter<'a> {
@@ -67,25 +75,90 @@ impl<'a> BrowserSessionFilter<'a> {
         self.user
     }
 
-    /// Only return active browser sessions
+    /// Only return browser sessions which are active at the given time,
+    /// meaning they are neither finished nor expired
     #[must_use]
-    pub fn active_only(mut self) -> Self {
-        self.state = Some(BrowserSessionState::Active);
+    pub fn active_only(mut self, now: DateTime<Utc>) -> Self {
+        self.state = Some((BrowserSessionState::Active, now));
         self
     }
 
-    /// Only return finished browser sessions
+    /// Only return browser sessions which are finished or expired at the given
+    /// time
     #[must_use]
-    pub fn finished_only(mut self) -> Self {
-        self.state = Some(BrowserSessionState::Finished);
+    pub fn finished_only(mut self, now: DateTime<Utc>) -> Self {
+        self.state = Some((BrowserSessionState::Finished, now));
         self
     }
 
-    /// Get the state filter
+    /// Get the state filter, along with the time at which the state is
+    /// evaluated
     #[must_use]
-    pub fn state(&self) -> Option<BrowserSessionState> {
+    pub fn state(&self) -> Option<(BrowserSessionState, DateTime<Utc>)> {
         self.state
     }
+
+    /// Only return browser sessions created at or after the given time
+    #[must_use]
+    pub fn with_created_after(mut self, created_after: DateTime<Utc>) -> Self {
+        self.created_after = Some(created_after);
+        self
+    }
+
+    /// Get the created after filter
+    #[must_use]
+    pub fn created_after(&self) -> Option<DateTime<Utc>> {
+        self.created_after
+    }
+
+    /// Only return browser sessions created at or before the given time
+    #[must_use]
+    pub fn with_created_before(mut self, created_before: DateTime<Utc>) -> Self {
+        self.created_before = Some(created_before);
+        self
+    }
+
+    /// Get the created before filter
+    #[must_use]
+    pub fn created_before(&self) -> Option<DateTime<Utc>> {
+        self.created_before
+    }
+
+    /// Only return browser sessions which were authenticated at or after the
+    /// given time
+    #[must_use]
+    pub fn with_last_authenticated_after(
+        mut self,
+        last_authenticated_after: DateTime<Utc>,
+    ) -> Self {
+        self.last_authenticated_after = Some(last_authenticated_after);
+        self
+    }
+
+    /// Get the last authenticated after filter
+    #[must_use]
+    pub fn last_authenticated_after(&self) -> Option<DateTime<Utc>> {
+        self.last_authenticated_after
+    }
+
+    /// Only return browser sessions which were last authenticated at or before
+    /// the given time
+    ///
+    /// Sessions which were never authenticated are always included
+    #[must_use]
+    pub fn with_last_authenticated_before(
+        mut self,
+        last_authenticated_before: DateTime<Utc>,
+    ) -> Self {
+        self.last_authenticated_before = Some(last_authenticated_before);
+        self
+    }
+
+    /// Get the last authenticated before filter
+    #[must_use]
+    pub fn last_authenticated_before(&self) -> Option<DateTime<Utc>> {
+        self.last_authenticated_before
+    }
 }
 
 /// A [`BrowserSessionRepository`] helps interacting with [`BrowserSession`]
@@ -97,16 +170,42 @@ pub trait BrowserSessionRepository: Send + Sync {
 
     /// Lookup a [`BrowserSession`] by its ID
     ///
-    /// Returns `None` if the session is not found
+    /// Returns `None` if the session is not found. Sessions which are past
+    /// their expiry are returned as finished.
     ///
     /// # Parameters
     ///
+    /// * `clock`: The clock used to check whether the session expired
     /// * `id`: The ID of the session to lookup
     ///
     /// # Errors
     ///
     /// Returns [`Self::Error`] if the underlying repository fails
-    async fn lookup(&mut self, id: Ulid) -> Result<Option<BrowserSession>, Self::Error>;
+    async fn lookup(
+        &mut self,
+        clock: &dyn Clock,
+        id: Ulid,
+    ) -> Result<Option<BrowserSession>, Self::Error>;
+
+    /// Lookup multiple [`BrowserSession`]s by their IDs
+    ///
+    /// Returns a map of session IDs to sessions. Sessions which are not found
+    /// are not present in the map. Sessions which are past their expiry are
+    /// returned as finished.
+    ///
+    /// # Parameters
+    ///
+    /// * `clock`: The clock used to check whether the sessions expired
+    /// * `ids`: The IDs of the sessions to lookup
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn lookup_batch(
+        &mut self,
+        clock: &dyn Clock,
+        ids: &[Ulid],
+    ) -> Result<HashMap<Ulid, BrowserSession>, Self::Error>;
 
     /// Create a new [`BrowserSession`] for a [`User`]
     ///
@@ -118,16 +217,21 @@ pub trait BrowserSessionRepository: Send + Sync {
     /// * `clock`: The clock used to generate timestamps
     /// * `user`: The user to create the session for
     /// * `user_agent`: If available, the user agent of the browser
+    /// * `ip_address`: If available, the IP address of the browser
+    /// * `expires_at`: When the session expires, if it does
     ///
     /// # Errors
     ///
-    /// Returns [`Self::Error`] if the underlying repository fails
+    /// Returns [`Self::Error`] if the user is deactivated, or if the
+    /// underlying repository fails
     async fn add(
         &mut self,
         rng: &mut (dyn RngCore + Send),
         clock: &dyn Clock,
         user: &User,
         user_agent: Option<UserAgent>,
+        ip_address: Option<IpAddr>,
+        expires_at: Option<DateTime<Utc>>,
     ) -> Result<BrowserSession, Self::Error>;
 
     /// Finish a [`BrowserSession`]
@@ -148,6 +252,24 @@ pub trait BrowserSessionRepository: Send + Sync {
         user_session: BrowserSession,
     ) -> Result<BrowserSession, Self::Error>;
 
+    /// Finish all the active [`BrowserSession`]s of a [`User`]
+    ///
+    /// Returns the number of sessions which were finished
+    ///
+    /// # Parameters
+    ///
+    /// * `clock`: The clock used to generate timestamps
+    /// * `user`: The user whose sessions should be finished
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn finish_all_for_user(
+        &mut self,
+        clock: &dyn Clock,
+        user: &User,
+    ) -> Result<usize, Self::Error>;
+
     /// List [`BrowserSession`] with the given filter and pagination
     ///
     /// # Parameters
@@ -177,6 +299,11 @@ pub trait BrowserSessionRepository: Send + Sync {
 
     /// Authenticate a [`BrowserSession`] with the given [`Password`]
     ///
+    /// This only records the authentication: the caller is responsible for
+    /// verifying the password attempt against the [`Password`] hash
+    /// beforehand, using the password manager, which knows about the hashing
+    /// schemes and their versions.
+    ///
     /// # Parameters
     ///
     /// * `rng`: The random number generator to use
@@ -217,6 +344,66 @@ pub trait BrowserSessionRepository: Send + Sync {
         upstream_oauth_session: &UpstreamOAuthAuthorizationSession,
     ) -> Result<Authentication, Self::Error>;
 
+    /// Authenticate a [`BrowserSession`] with the given [`WebAuthnCredential`]
+    ///
+    /// # Parameters
+    ///
+    /// * `rng`: The random number generator to use
+    /// * `clock`: The clock used to generate timestamps
+    /// * `user_session`: The session to authenticate
+    /// * `credential`: The WebAuthn credential which was used to authenticate
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn authenticate_with_webauthn(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        user_session: &BrowserSession,
+        credential: &WebAuthnCredential,
+    ) -> Result<Authentication, Self::Error>;
+
+    /// Authenticate a [`BrowserSession`] with the given [`Totp`]
+    ///
+    /// # Parameters
+    ///
+    /// * `rng`: The random number generator to use
+    /// * `clock`: The clock used to generate timestamps
+    /// * `user_session`: The session to authenticate
+    /// * `totp`: The TOTP which was used to authenticate
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn authenticate_with_totp(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        user_session: &BrowserSession,
+        totp: &Totp,
+    ) -> Result<Authentication, Self::Error>;
+
+    /// Authenticate a [`BrowserSession`] with the given [`UserRecoveryCode`]
+    ///
+    /// # Parameters
+    ///
+    /// * `rng`: The random number generator to use
+    /// * `clock`: The clock used to generate timestamps
+    /// * `user_session`: The session to authenticate
+    /// * `recovery_code`: The recovery code which was used to authenticate
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn authenticate_with_recovery_code(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        user_session: &BrowserSession,
+        recovery_code: &UserRecoveryCode,
+    ) -> Result<Authentication, Self::Error>;
+
     /// Get the last successful authentication for a [`BrowserSession`]
     ///
     /// # Params
@@ -231,6 +418,43 @@ pub trait BrowserSessionRepository: Send + Sync {
         user_session: &BrowserSession,
     ) -> Result<Option<Authentication>, Self::Error>;
 
+    /// Get the last successful authentication for a batch of
+    /// [`BrowserSession`]
+    ///
+    /// Returns a map of session IDs to their last authentication. Sessions
+    /// which never authenticated are not present in the map.
+    ///
+    /// # Params
+    ///
+    /// * `user_session_ids`: The IDs of the sessions for which to get the last
+    ///   authentication
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn get_last_authentication_batch(
+        &mut self,
+        user_session_ids: BTreeSet<Ulid>,
+    ) -> Result<BTreeMap<Ulid, Authentication>, Self::Error>;
+
+    /// List the successful authentications of a [`BrowserSession`], the most
+    /// recent first
+    ///
+    /// # Parameters
+    ///
+    /// * `user_session`: The session for which to list the authentications
+    /// * `pagination`: The pagination parameters. The order set on them is
+    ///   ignored, as authentications are always listed from the most recent
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn list_authentications(
+        &mut self,
+        user_session: &BrowserSession,
+        pagination: Pagination,
+    ) -> Result<Page<Authentication>, Self::Error>;
+
     /// Record a batch of [`BrowserSession`] activity
     ///
     /// # Parameters
@@ -248,13 +472,24 @@ pub trait BrowserSessionRepository: Send + Sync {
 }
 
 repository_impl!(BrowserSessionRepository:
-    async fn lookup(&mut self, id: Ulid) -> Result<Option<BrowserSession>, Self::Error>;
+    async fn lookup(
+        &mut self,
+        clock: &dyn Clock,
+        id: Ulid,
+    ) -> Result<Option<BrowserSession>, Self::Error>;
+    async fn lookup_batch(
+        &mut self,
+        clock: &dyn Clock,
+        ids: &[Ulid],
+    ) -> Result<HashMap<Ulid, BrowserSession>, Self::Error>;
     async fn add(
         &mut self,
         rng: &mut (dyn RngCore + Send),
         clock: &dyn Clock,
         user: &User,
         user_agent: Option<UserAgent>,
+        ip_address: Option<IpAddr>,
+        expires_at: Option<DateTime<Utc>>,
     ) -> Result<BrowserSession, Self::Error>;
     async fn finish(
         &mut self,
@@ -262,6 +497,11 @@ repository_impl!(BrowserSessionRepository:
         user_session: BrowserSession,
     ) -> Result<BrowserSession, Self::Error>;
 
+    async fn finish_all_for_user(
+        &mut self,
+        clock: &dyn Clock,
+        user: &User,
+    ) -> Result<usize, Self::Error>;
     async fn list(
         &mut self,
         filter: BrowserSessionFilter<'_>,
@@ -286,11 +526,46 @@ repository_impl!(BrowserSessionRepository:
         upstream_oauth_session: &UpstreamOAuthAuthorizationSession,
     ) -> Result<Authentication, Self::Error>;
 
+    async fn authenticate_with_webauthn(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        user_session: &BrowserSession,
+        credential: &WebAuthnCredential,
+    ) -> Result<Authentication, Self::Error>;
+
+    async fn authenticate_with_totp(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        user_session: &BrowserSession,
+        totp: &Totp,
+    ) -> Result<Authentication, Self::Error>;
+
+    async fn authenticate_with_recovery_code(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        user_session: &BrowserSession,
+        recovery_code: &UserRecoveryCode,
+    ) -> Result<Authentication, Self::Error>;
+
     async fn get_last_authentication(
         &mut self,
         user_session: &BrowserSession,
     ) -> Result<Option<Authentication>, Self::Error>;
 
+    async fn get_last_authentication_batch(
+        &mut self,
+        user_session_ids: BTreeSet<Ulid>,
+    ) -> Result<BTreeMap<Ulid, Authentication>, Self::Error>;
+
+    async fn list_authentications(
+        &mut self,
+        user_session: &BrowserSession,
+        pagination: Pagination,
+    ) -> Result<Page<Authentication>, Self::Error>;
+
     async fn record_batch_activity(
         &mut self,
         activity: Vec<(Ulid, DateTime<Utc>, Option<IpAddr>)>,
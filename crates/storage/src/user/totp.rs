@@ -0,0 +1,106 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use async_trait::async_trait;
+use mas_data_model::{Totp, User};
+use rand_core::RngCore;
+
+use crate::{repository_impl, Clock};
+
+/// A [`UserTotpRepository`] helps interacting with the [`Totp`] second factor
+/// of users saved in the storage backend
+#[async_trait]
+pub trait UserTotpRepository: Send + Sync {
+    /// The error type returned by the repository
+    type Error;
+
+    /// Lookup the [`Totp`] enrolled by a [`User`]
+    ///
+    /// Returns `None` if the user has no TOTP enrolled
+    ///
+    /// # Parameters
+    ///
+    /// * `user`: The [`User`] for whom to lookup the TOTP
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn lookup_for_user(&mut self, user: &User) -> Result<Option<Totp>, Self::Error>;
+
+    /// Enroll a new [`Totp`] for a [`User`]
+    ///
+    /// Returns the newly created [`Totp`]
+    ///
+    /// # Parameters
+    ///
+    /// * `rng`: The random number generator to use
+    /// * `clock`: The clock used to generate timestamps
+    /// * `user`: The [`User`] enrolling the TOTP
+    /// * `encrypted_secret`: The shared secret, encrypted with the site
+    ///   encryption key
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails, or if the
+    /// user already has a TOTP enrolled
+    async fn add(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        user: &User,
+        encrypted_secret: String,
+    ) -> Result<Totp, Self::Error>;
+
+    /// Record that a code for the given time step was successfully used
+    ///
+    /// This only succeeds if the time step is more recent than the last one
+    /// which was used, so that codes can't be replayed.
+    ///
+    /// Returns the updated [`Totp`], or `None` if the time step was already
+    /// used
+    ///
+    /// # Parameters
+    ///
+    /// * `totp`: The [`Totp`] which was used
+    /// * `counter`: The time step of the code which was verified
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn verify(&mut self, totp: Totp, counter: u64) -> Result<Option<Totp>, Self::Error>;
+
+    /// Delete a [`Totp`]
+    ///
+    /// # Parameters
+    ///
+    /// * `totp`: The [`Totp`] to delete
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn delete(&mut self, totp: Totp) -> Result<(), Self::Error>;
+}
+
+repository_impl!(UserTotpRepository:
+    async fn lookup_for_user(&mut self, user: &User) -> Result<Option<Totp>, Self::Error>;
+    async fn add(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        user: &User,
+        encrypted_secret: String,
+    ) -> Result<Totp, Self::Error>;
+    async fn verify(&mut self, totp: Totp, counter: u64) -> Result<Option<Totp>, Self::Error>;
+    async fn delete(&mut self, totp: Totp) -> Result<(), Self::Error>;
+);
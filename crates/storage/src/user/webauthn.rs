@@ -0,0 +1,153 @@
+// Copyright 2024 The Matrix.org Foundation C.I.C.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use async_trait::async_trait;
+use mas_data_model::{User, WebAuthnCredential};
+use rand_core::RngCore;
+use ulid::Ulid;
+
+use crate::{repository_impl, Clock};
+
+/// A [`UserWebAuthnRepository`] helps interacting with [`WebAuthnCredential`]
+/// saved in the storage backend
+#[async_trait]
+pub trait UserWebAuthnRepository: Send + Sync {
+    /// The error type returned by the repository
+    type Error;
+
+    /// Lookup a [`WebAuthnCredential`] by its ID
+    ///
+    /// Returns `None` if no [`WebAuthnCredential`] was found
+    ///
+    /// # Parameters
+    ///
+    /// * `id`: The ID of the [`WebAuthnCredential`] to lookup
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn lookup(&mut self, id: Ulid) -> Result<Option<WebAuthnCredential>, Self::Error>;
+
+    /// Find a [`WebAuthnCredential`] by the credential ID chosen by the
+    /// authenticator
+    ///
+    /// Returns `None` if no [`WebAuthnCredential`] was found
+    ///
+    /// # Parameters
+    ///
+    /// * `credential_id`: The credential ID to lookup
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn find_by_credential_id(
+        &mut self,
+        credential_id: &[u8],
+    ) -> Result<Option<WebAuthnCredential>, Self::Error>;
+
+    /// Get all the [`WebAuthnCredential`] registered by a [`User`]
+    ///
+    /// # Parameters
+    ///
+    /// * `user`: The [`User`] for whom to list the credentials
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn list_for_user(&mut self, user: &User) -> Result<Vec<WebAuthnCredential>, Self::Error>;
+
+    /// Register a new [`WebAuthnCredential`] for a [`User`]
+    ///
+    /// Returns the newly created [`WebAuthnCredential`]
+    ///
+    /// # Parameters
+    ///
+    /// * `rng`: The random number generator to use
+    /// * `clock`: The clock used to generate timestamps
+    /// * `user`: The [`User`] registering the credential
+    /// * `credential_id`: The credential ID chosen by the authenticator
+    /// * `public_key`: The COSE-encoded public key of the credential
+    /// * `sign_count`: The initial signature counter
+    /// * `aaguid`: The AAGUID of the authenticator
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    #[allow(clippy::too_many_arguments)]
+    async fn add(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        user: &User,
+        credential_id: Vec<u8>,
+        public_key: Vec<u8>,
+        sign_count: u32,
+        aaguid: [u8; 16],
+    ) -> Result<WebAuthnCredential, Self::Error>;
+
+    /// Update the signature counter of a [`WebAuthnCredential`] after a
+    /// successful authentication
+    ///
+    /// Returns the updated [`WebAuthnCredential`]
+    ///
+    /// # Parameters
+    ///
+    /// * `credential`: The [`WebAuthnCredential`] to update
+    /// * `sign_count`: The new signature counter
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn update_sign_count(
+        &mut self,
+        credential: WebAuthnCredential,
+        sign_count: u32,
+    ) -> Result<WebAuthnCredential, Self::Error>;
+
+    /// Delete a [`WebAuthnCredential`]
+    ///
+    /// # Parameters
+    ///
+    /// * `credential`: The [`WebAuthnCredential`] to delete
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Self::Error`] if the underlying repository fails
+    async fn delete(&mut self, credential: WebAuthnCredential) -> Result<(), Self::Error>;
+}
+
+repository_impl!(UserWebAuthnRepository:
+    async fn lookup(&mut self, id: Ulid) -> Result<Option<WebAuthnCredential>, Self::Error>;
+    async fn find_by_credential_id(
+        &mut self,
+        credential_id: &[u8],
+    ) -> Result<Option<WebAuthnCredential>, Self::Error>;
+    async fn list_for_user(&mut self, user: &User) -> Result<Vec<WebAuthnCredential>, Self::Error>;
+    async fn add(
+        &mut self,
+        rng: &mut (dyn RngCore + Send),
+        clock: &dyn Clock,
+        user: &User,
+        credential_id: Vec<u8>,
+        public_key: Vec<u8>,
+        sign_count: u32,
+        aaguid: [u8; 16],
+    ) -> Result<WebAuthnCredential, Self::Error>;
+    async fn update_sign_count(
+        &mut self,
+        credential: WebAuthnCredential,
+        sign_count: u32,
+    ) -> Result<WebAuthnCredential, Self::Error>;
+    async fn delete(&mut self, credential: WebAuthnCredential) -> Result<(), Self::Error>;
+);
@@ -25,8 +25,12 @@ use apalis_core::{
     utils::timer::TokioTimer,
 };
 use apalis_cron::CronStream;
-use chrono::{DateTime, Utc};
-use mas_storage::{oauth2::OAuth2AccessTokenRepository, RepositoryAccess};
+use chrono::{DateTime, Duration, Utc};
+use mas_storage::{
+    audit_log::AuditLogRepository,
+    oauth2::{OAuth2AccessTokenRepository, OAuth2PushedAuthRequestRepository},
+    Clock, RepositoryAccess,
+};
 use tracing::{debug, info};
 
 use crate::{
@@ -61,7 +65,11 @@ pub async fn cleanup_expired_tokens(
     let clock = state.clock();
     let mut repo = state.repository().await?;
 
-    let count = repo.oauth2_access_token().cleanup_expired(&clock).await?;
+    let mut count = repo.oauth2_access_token().cleanup_expired(&clock).await?;
+    count += repo
+        .oauth2_pushed_auth_request()
+        .cleanup_expired(&clock)
+        .await?;
     repo.save().await?;
 
     if count == 0 {
@@ -73,6 +81,51 @@ pub async fn cleanup_expired_tokens(
     Ok(())
 }
 
+/// How long the audit log entries are kept
+const AUDIT_LOG_RETENTION: Duration = Duration::microseconds(90 * 24 * 60 * 60 * 1000 * 1000);
+
+#[derive(Default, Clone)]
+pub struct CleanupAuditLogJob {
+    scheduled: DateTime<Utc>,
+}
+
+impl From<DateTime<Utc>> for CleanupAuditLogJob {
+    fn from(scheduled: DateTime<Utc>) -> Self {
+        Self { scheduled }
+    }
+}
+
+impl Job for CleanupAuditLogJob {
+    const NAME: &'static str = "cleanup-audit-log";
+}
+
+impl TracedJob for CleanupAuditLogJob {}
+
+pub async fn cleanup_audit_log(
+    job: CleanupAuditLogJob,
+    ctx: JobContext,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync + 'static>> {
+    debug!("cleanup audit log job scheduled at {}", job.scheduled);
+
+    let state = ctx.state();
+    let clock = state.clock();
+    let mut repo = state.repository().await?;
+
+    let count = repo
+        .audit_log()
+        .cleanup(clock.now() - AUDIT_LOG_RETENTION)
+        .await?;
+    repo.save().await?;
+
+    if count == 0 {
+        debug!("no audit log entry to clean up");
+    } else {
+        info!(count, "cleaned up old audit log entries");
+    }
+
+    Ok(())
+}
+
 pub(crate) fn register(
     suffix: &str,
     monitor: Monitor<TokioExecutor>,
@@ -86,6 +139,16 @@ pub(crate) fn register(
         .layer(metrics_layer())
         .layer(trace_layer())
         .build_fn(cleanup_expired_tokens);
+    let monitor = monitor.register(worker);
+
+    let schedule = apalis_cron::Schedule::from_str("0 0 * * * *").unwrap();
+    let worker_name = format!("{job}-{suffix}", job = CleanupAuditLogJob::NAME);
+    let worker = WorkerBuilder::new(worker_name)
+        .stream(CronStream::new(schedule).timer(TokioTimer).to_stream())
+        .layer(state.inject())
+        .layer(metrics_layer())
+        .layer(trace_layer())
+        .build_fn(cleanup_audit_log);
 
     monitor.register(worker)
 }
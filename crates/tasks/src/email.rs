@@ -15,10 +15,11 @@
 use anyhow::Context;
 use apalis_core::{context::JobContext, executor::TokioExecutor, monitor::Monitor};
 use chrono::Duration;
+use mas_data_model::password_reset;
 use mas_email::{Address, Mailbox};
 use mas_i18n::locale;
-use mas_storage::job::{JobWithSpanContext, VerifyEmailJob};
-use mas_templates::{EmailVerificationContext, TemplateContext};
+use mas_storage::job::{JobWithSpanContext, SendPasswordResetEmailJob, VerifyEmailJob};
+use mas_templates::{EmailPasswordResetContext, EmailVerificationContext, TemplateContext};
 use rand::{distributions::Uniform, Rng};
 use tracing::info;
 
@@ -96,6 +97,97 @@ async fn verify_email(
     Ok(())
 }
 
+#[tracing::instrument(
+    name = "job.send_password_reset_email",
+    fields(user_email.id = %job.user_email_id()),
+    skip_all,
+    err(Debug),
+)]
+async fn send_password_reset_email(
+    job: JobWithSpanContext<SendPasswordResetEmailJob>,
+    ctx: JobContext,
+) -> Result<(), anyhow::Error> {
+    let state = ctx.state();
+    let mut repo = state.repository().await?;
+    let mut rng = state.rng();
+    let mailer = state.mailer();
+    let url_builder = state.url_builder();
+    let clock = state.clock();
+
+    let language = job
+        .language()
+        .and_then(|l| l.parse().ok())
+        .unwrap_or(locale!("en").into());
+
+    // Lookup the user email
+    let user_email = repo
+        .user_email()
+        .lookup(job.user_email_id())
+        .await?
+        .context("User email not found")?;
+
+    // Lookup the user associated with the email
+    let user = repo
+        .user()
+        .lookup(user_email.user_id)
+        .await?
+        .context("User not found")?;
+
+    // Things might have changed since the link was requested
+    if user_email.confirmed_at.is_none() || !user.is_valid() {
+        info!(
+            email.id = %user_email.id,
+            "Not sending a password reset email to an unverified address or a locked user"
+        );
+        return Ok(());
+    }
+
+    // The link should expire at the same time, however long the job waited
+    let expires_in = job.expires_at() - clock.now();
+    if expires_in <= Duration::zero() {
+        info!(
+            email.id = %user_email.id,
+            "Not sending a password reset email, as it would already be expired"
+        );
+        return Ok(());
+    }
+
+    let address: Address = user_email.email.parse()?;
+
+    // Only the hash of the secret is stored, the secret itself is in the link
+    let secret = password_reset::generate_secret(&mut rng);
+    let reset_token = repo
+        .user_password_reset_token()
+        .add(
+            &mut rng,
+            &clock,
+            &user,
+            password_reset::hash_secret(&secret),
+            expires_in,
+        )
+        .await?;
+
+    let link = url_builder.absolute_url_for(&mas_router::ResetPassword(
+        password_reset::format_token(reset_token.id, &secret),
+    ));
+
+    // And send the password reset email
+    let mailbox = Mailbox::new(Some(user.username.clone()), address);
+
+    let context = EmailPasswordResetContext::new(user.clone(), link).with_language(language);
+
+    mailer.send_password_reset_email(mailbox, &context).await?;
+
+    info!(
+        email.id = %user_email.id,
+        "Password reset email sent"
+    );
+
+    repo.save().await?;
+
+    Ok(())
+}
+
 pub(crate) fn register(
     suffix: &str,
     monitor: Monitor<TokioExecutor>,
@@ -104,6 +196,14 @@ pub(crate) fn register(
 ) -> Monitor<TokioExecutor> {
     let verify_email_worker =
         crate::build!(VerifyEmailJob => verify_email, suffix, state, storage_factory);
+    let send_password_reset_email_worker = crate::build!(
+        SendPasswordResetEmailJob => send_password_reset_email,
+        suffix,
+        state,
+        storage_factory
+    );
 
-    monitor.register(verify_email_worker)
+    monitor
+        .register(verify_email_worker)
+        .register(send_password_reset_email_worker)
 }
@@ -32,7 +32,10 @@ use mas_data_model::{
 };
 use mas_i18n::DataLocale;
 use mas_router::{Account, GraphQL, PostAuthAction, UrlBuilder};
-use oauth2_types::scope::OPENID;
+use oauth2_types::{
+    errors::{ClientError, ClientErrorCode},
+    scope::OPENID,
+};
 use rand::{
     distributions::{Alphanumeric, DistString},
     Rng,
@@ -42,7 +45,7 @@ use ulid::Ulid;
 use url::Url;
 
 pub use self::{branding::SiteBranding, ext::SiteConfigExt, features::SiteFeatures};
-use crate::{FieldError, FormField, FormState};
+use crate::{FieldError, FormError, FormField, FormState};
 
 /// Helper trait to construct context wrappers
 pub trait TemplateContext: Serialize {
@@ -335,12 +338,15 @@ pub enum LoginFormField {
 
     /// The password field
     Password,
+
+    /// The "remember me" checkbox
+    RememberMe,
 }
 
 impl FormField for LoginFormField {
     fn keep(&self) -> bool {
         match self {
-            Self::Username => true,
+            Self::Username | Self::RememberMe => true,
             Self::Password => false,
         }
     }
@@ -466,6 +472,218 @@ impl LoginContext {
     }
 }
 
+/// Fields of the TOTP login form
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Hash, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LoginTotpFormField {
+    /// The TOTP or recovery code field
+    Code,
+}
+
+impl FormField for LoginTotpFormField {
+    fn keep(&self) -> bool {
+        match self {
+            Self::Code => false,
+        }
+    }
+}
+
+/// Context used by the `login_totp.html` template
+#[derive(Serialize, Default)]
+pub struct LoginTotpContext {
+    form: FormState<LoginTotpFormField>,
+    next: Option<PostAuthContext>,
+}
+
+impl TemplateContext for LoginTotpContext {
+    fn sample(_now: chrono::DateTime<Utc>, _rng: &mut impl Rng) -> Vec<Self>
+    where
+        Self: Sized,
+    {
+        vec![
+            LoginTotpContext {
+                form: FormState::default(),
+                next: None,
+            },
+            LoginTotpContext {
+                form: FormState::default()
+                    .with_error_on_field(LoginTotpFormField::Code, FieldError::Invalid),
+                next: None,
+            },
+        ]
+    }
+}
+
+impl LoginTotpContext {
+    /// Set the form state
+    #[must_use]
+    pub fn with_form_state(self, form: FormState<LoginTotpFormField>) -> Self {
+        Self { form, ..self }
+    }
+
+    /// Add a post authentication action to the context
+    #[must_use]
+    pub fn with_post_action(self, context: PostAuthContext) -> Self {
+        Self {
+            next: Some(context),
+            ..self
+        }
+    }
+}
+
+/// Fields of the password change form
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Hash, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangePasswordFormField {
+    /// The current password field
+    CurrentPassword,
+
+    /// The new password field
+    NewPassword,
+
+    /// The new password confirmation field
+    NewPasswordConfirm,
+}
+
+impl FormField for ChangePasswordFormField {
+    fn keep(&self) -> bool {
+        match self {
+            Self::CurrentPassword | Self::NewPassword | Self::NewPasswordConfirm => false,
+        }
+    }
+}
+
+/// Context used by the `account/password.html` template
+#[derive(Serialize, Default)]
+pub struct ChangePasswordContext {
+    form: FormState<ChangePasswordFormField>,
+}
+
+impl TemplateContext for ChangePasswordContext {
+    fn sample(_now: chrono::DateTime<Utc>, _rng: &mut impl Rng) -> Vec<Self>
+    where
+        Self: Sized,
+    {
+        vec![
+            ChangePasswordContext {
+                form: FormState::default(),
+            },
+            ChangePasswordContext {
+                form: FormState::default().with_error_on_field(
+                    ChangePasswordFormField::NewPassword,
+                    FieldError::PasswordBreached { count: 42 },
+                ),
+            },
+        ]
+    }
+}
+
+impl ChangePasswordContext {
+    /// Set the form state
+    #[must_use]
+    pub fn with_form_state(self, form: FormState<ChangePasswordFormField>) -> Self {
+        Self { form }
+    }
+}
+
+/// Fields of the password reset form
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Hash, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ResetPasswordFormField {
+    /// The new password field
+    NewPassword,
+
+    /// The new password confirmation field
+    NewPasswordConfirm,
+}
+
+impl FormField for ResetPasswordFormField {
+    fn keep(&self) -> bool {
+        match self {
+            Self::NewPassword | Self::NewPasswordConfirm => false,
+        }
+    }
+}
+
+/// Context used by the `reset_password.html` template
+#[derive(Serialize, Default)]
+pub struct ResetPasswordContext {
+    form: FormState<ResetPasswordFormField>,
+}
+
+impl TemplateContext for ResetPasswordContext {
+    fn sample(_now: chrono::DateTime<Utc>, _rng: &mut impl Rng) -> Vec<Self>
+    where
+        Self: Sized,
+    {
+        vec![
+            ResetPasswordContext {
+                form: FormState::default(),
+            },
+            ResetPasswordContext {
+                form: FormState::default().with_error_on_form(FormError::ExpiredResetToken),
+            },
+        ]
+    }
+}
+
+impl ResetPasswordContext {
+    /// Set the form state
+    #[must_use]
+    pub fn with_form_state(self, form: FormState<ResetPasswordFormField>) -> Self {
+        Self { form }
+    }
+}
+
+/// Fields of the password reset request form
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Hash, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ResetPasswordRequestFormField {
+    /// The email field
+    Email,
+}
+
+impl FormField for ResetPasswordRequestFormField {
+    fn keep(&self) -> bool {
+        match self {
+            Self::Email => true,
+        }
+    }
+}
+
+/// Context used by the `reset_password_request.html` template
+#[derive(Serialize, Default)]
+pub struct ResetPasswordRequestContext {
+    form: FormState<ResetPasswordRequestFormField>,
+    sent: bool,
+}
+
+impl TemplateContext for ResetPasswordRequestContext {
+    fn sample(_now: chrono::DateTime<Utc>, _rng: &mut impl Rng) -> Vec<Self>
+    where
+        Self: Sized,
+    {
+        vec![
+            ResetPasswordRequestContext::default(),
+            ResetPasswordRequestContext::default().sent(),
+        ]
+    }
+}
+
+impl ResetPasswordRequestContext {
+    /// Set the form state
+    #[must_use]
+    pub fn with_form_state(self, form: FormState<ResetPasswordRequestFormField>) -> Self {
+        Self { form, ..self }
+    }
+
+    /// Mark the password reset link as sent
+    #[must_use]
+    pub fn sent(self) -> Self {
+        Self { sent: true, ..self }
+    }
+}
+
 /// Fields of the registration form
 #[derive(Serialize, Deserialize, Debug, Clone, Copy, Hash, PartialEq, Eq)]
 #[serde(rename_all = "snake_case")]
@@ -616,6 +834,8 @@ impl TemplateContext for PolicyViolationContext {
                         device_code: Alphanumeric.sample_string(rng, 32),
                         created_at: now - Duration::try_minutes(5).unwrap(),
                         expires_at: now + Duration::try_minutes(25).unwrap(),
+                        last_polled_at: None,
+                        poll_interval: Duration::try_seconds(5).unwrap(),
                         ip_address: None,
                         user_agent: None,
                     },
@@ -797,6 +1017,44 @@ impl TemplateContext for EmailVerificationContext {
     }
 }
 
+/// Context used by the `emails/password_reset.{txt,html,subject}` templates
+#[derive(Serialize)]
+pub struct EmailPasswordResetContext {
+    user: User,
+    link: Url,
+}
+
+impl EmailPasswordResetContext {
+    /// Constructs a context for the password reset email
+    #[must_use]
+    pub fn new(user: User, link: Url) -> Self {
+        Self { user, link }
+    }
+
+    /// Get the user to which this email is being sent
+    #[must_use]
+    pub fn user(&self) -> &User {
+        &self.user
+    }
+}
+
+impl TemplateContext for EmailPasswordResetContext {
+    fn sample(now: chrono::DateTime<Utc>, rng: &mut impl Rng) -> Vec<Self>
+    where
+        Self: Sized,
+    {
+        User::samples(now, rng)
+            .into_iter()
+            .map(|user| {
+                let link = "https://example.com/reset-password/01FSHN9AG0MZAA6S4AF7CTV32E.secret"
+                    .parse()
+                    .unwrap();
+                Self { user, link }
+            })
+            .collect()
+    }
+}
+
 /// Fields of the email verification form
 #[derive(Serialize, Deserialize, Debug, Clone, Copy, Hash, PartialEq, Eq)]
 #[serde(rename_all = "snake_case")]
@@ -1151,6 +1409,8 @@ impl TemplateContext for DeviceConsentContext {
                     device_code: Alphanumeric.sample_string(rng, 32),
                     created_at: now - Duration::try_minutes(5).unwrap(),
                     expires_at: now + Duration::try_minutes(25).unwrap(),
+                    last_polled_at: None,
+                    poll_interval: Duration::try_seconds(5).unwrap(),
                     ip_address: Some(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1))),
                     user_agent: Some(UserAgent::parse("Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/93.0.0.0 Safari/537.36".to_owned())),
                 };
@@ -1193,6 +1453,41 @@ impl<T> FormPostContext<T> {
     }
 }
 
+/// Context used by the `web_message.html` template
+#[derive(Serialize)]
+pub struct WebMessageContext<T> {
+    target_origin: String,
+    params: T,
+}
+
+impl<T: TemplateContext> TemplateContext for WebMessageContext<T> {
+    fn sample(now: chrono::DateTime<Utc>, rng: &mut impl Rng) -> Vec<Self>
+    where
+        Self: Sized,
+    {
+        let sample_params = T::sample(now, rng);
+        sample_params
+            .into_iter()
+            .map(|params| WebMessageContext {
+                target_origin: "https://example.com".to_owned(),
+                params,
+            })
+            .collect()
+    }
+}
+
+impl<T> WebMessageContext<T> {
+    /// Constructs a context for the `web_message` response mode page
+    ///
+    /// The message is only ever posted to the origin of the given redirect URI
+    pub fn new(redirect_uri: &Url, params: T) -> Self {
+        Self {
+            target_origin: redirect_uri.origin().ascii_serialization(),
+            params,
+        }
+    }
+}
+
 /// Context used by the `error.html` template
 #[derive(Default, Serialize, Debug, Clone)]
 pub struct ErrorContext {
@@ -1289,6 +1584,82 @@ impl ErrorContext {
     }
 }
 
+/// Context used by the `pages/oauth2_error.html` template, shown when an error
+/// in an OAuth 2.0 request can't be sent back to a known client
+#[derive(Serialize, Debug, Clone)]
+pub struct OAuth2ErrorContext {
+    code: String,
+    description: Option<String>,
+    client: Client,
+    return_uri: Option<Url>,
+}
+
+impl TemplateContext for OAuth2ErrorContext {
+    fn sample(now: chrono::DateTime<Utc>, rng: &mut impl Rng) -> Vec<Self>
+    where
+        Self: Sized,
+    {
+        Client::samples(now, rng)
+            .into_iter()
+            .flat_map(|client| {
+                let return_uri = client.redirect_uris.first().cloned();
+                [
+                    Self::new(
+                        client.clone(),
+                        &ClientError::from(ClientErrorCode::InvalidRedirectUri),
+                    ),
+                    Self::new(
+                        client,
+                        &ClientError::new(
+                            ClientErrorCode::InvalidRequest,
+                            "The redirect_uri parameter is included more than once",
+                        ),
+                    )
+                    .with_return_uri(return_uri),
+                ]
+            })
+            .collect()
+    }
+}
+
+impl OAuth2ErrorContext {
+    /// Constructs a context for the OAuth 2.0 error page of a client
+    #[must_use]
+    pub fn new(client: Client, error: &ClientError) -> Self {
+        Self {
+            code: error.error.to_string(),
+            description: error.error_description.as_deref().map(ToOwned::to_owned),
+            client,
+            return_uri: None,
+        }
+    }
+
+    /// Set the URI used to go back to the client, without any parameters
+    #[must_use]
+    pub fn with_return_uri(mut self, return_uri: Option<Url>) -> Self {
+        self.return_uri = return_uri;
+        self
+    }
+
+    /// Get the error code
+    #[must_use]
+    pub fn code(&self) -> &str {
+        &self.code
+    }
+
+    /// Get the description, if any
+    #[must_use]
+    pub fn description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+
+    /// Get the URI used to go back to the client, if any
+    #[must_use]
+    pub fn return_uri(&self) -> Option<&Url> {
+        self.return_uri.as_ref()
+    }
+}
+
 /// Context used by the not found (`404.html`) template
 #[derive(Serialize)]
 pub struct NotFoundContext {
@@ -54,6 +54,7 @@ impl SiteConfigExt for SiteConfig {
         SiteFeatures {
             password_registration: self.password_registration_enabled,
             password_login: self.password_login_enabled,
+            password_reset: self.password_reset_enabled,
         }
     }
 }
@@ -27,6 +27,9 @@ pub struct SiteFeatures {
 
     /// Whether local password-based login is enabled.
     pub password_login: bool,
+
+    /// Whether users can request a link to reset their password.
+    pub password_reset: bool,
 }
 
 impl Object for SiteFeatures {
@@ -34,11 +37,12 @@ impl Object for SiteFeatures {
         match field.as_str()? {
             "password_registration" => Some(Value::from(self.password_registration)),
             "password_login" => Some(Value::from(self.password_login)),
+            "password_reset" => Some(Value::from(self.password_reset)),
             _ => None,
         }
     }
 
     fn enumerate(self: &Arc<Self>) -> Enumerator {
-        Enumerator::Str(&["password_registration", "password_login"])
+        Enumerator::Str(&["password_registration", "password_login", "password_reset"])
     }
 }
@@ -44,6 +44,12 @@ pub enum FieldError {
         /// Message for this policy violation
         message: String,
     },
+
+    /// The password appeared in known data breaches
+    PasswordBreached {
+        /// How many times the password appeared in breaches
+        count: u32,
+    },
 }
 
 /// An error on the whole form
@@ -53,9 +59,30 @@ pub enum FormError {
     /// The given credentials are not valid
     InvalidCredentials,
 
+    /// The account is locked
+    AccountLocked,
+
+    /// The account was deactivated
+    AccountDeactivated,
+
+    /// There were too many failed attempts recently
+    RateLimited {
+        /// How long to wait before trying again, in seconds
+        retry_after: u64,
+    },
+
+    /// The user already has as many active sessions as allowed
+    TooManySessions,
+
     /// Password fields don't match
     PasswordMismatch,
 
+    /// The password reset token is unknown or was already used
+    InvalidResetToken,
+
+    /// The password reset token expired
+    ExpiredResetToken,
+
     /// There was an internal error
     Internal,
 
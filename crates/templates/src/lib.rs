@@ -42,14 +42,17 @@ mod macros;
 
 pub use self::{
     context::{
-        AppContext, CompatSsoContext, ConsentContext, DeviceConsentContext, DeviceLinkContext,
-        DeviceLinkFormField, EmailAddContext, EmailVerificationContext,
+        AppContext, ChangePasswordContext, ChangePasswordFormField, CompatSsoContext,
+        ConsentContext, DeviceConsentContext, DeviceLinkContext, DeviceLinkFormField,
+        EmailAddContext, EmailPasswordResetContext, EmailVerificationContext,
         EmailVerificationPageContext, EmptyContext, ErrorContext, FormPostContext, IndexContext,
-        LoginContext, LoginFormField, NotFoundContext, PolicyViolationContext, PostAuthContext,
-        PostAuthContextInner, ReauthContext, ReauthFormField, RegisterContext, RegisterFormField,
+        LoginContext, LoginFormField, LoginTotpContext, LoginTotpFormField, NotFoundContext,
+        OAuth2ErrorContext, PolicyViolationContext, PostAuthContext, PostAuthContextInner,
+        ReauthContext, ReauthFormField, RegisterContext, RegisterFormField, ResetPasswordContext,
+        ResetPasswordFormField, ResetPasswordRequestContext, ResetPasswordRequestFormField,
         SiteBranding, SiteConfigExt, SiteFeatures, TemplateContext, UpstreamExistingLinkContext,
-        UpstreamRegister, UpstreamRegisterFormField, UpstreamSuggestLink, WithCsrf, WithLanguage,
-        WithOptionalSession, WithSession,
+        UpstreamRegister, UpstreamRegisterFormField, UpstreamSuggestLink, WebMessageContext,
+        WithCsrf, WithLanguage, WithOptionalSession, WithSession,
     },
     forms::{FieldError, FormError, FormField, FormState, ToFormState},
 };
@@ -319,12 +322,24 @@ register_templates! {
     /// Render the not found fallback page
     pub fn render_not_found(WithLanguage<NotFoundContext>) { "pages/404.html" }
 
+    /// Render the page shown after an RP-initiated logout
+    pub fn render_logged_out(WithLanguage<EmptyContext>) { "pages/logged_out.html" }
+
     /// Render the frontend app
     pub fn render_app(WithLanguage<AppContext>) { "app.html" }
 
     /// Render the login page
     pub fn render_login(WithLanguage<WithCsrf<LoginContext>>) { "pages/login.html" }
 
+    /// Render the TOTP step of the login page
+    pub fn render_login_totp(WithLanguage<WithCsrf<LoginTotpContext>>) { "pages/login_totp.html" }
+
+    /// Render the password reset page
+    pub fn render_reset_password(WithLanguage<WithCsrf<ResetPasswordContext>>) { "pages/reset_password.html" }
+
+    /// Render the page to request a password reset link
+    pub fn render_reset_password_request(WithLanguage<WithCsrf<ResetPasswordRequestContext>>) { "pages/reset_password_request.html" }
+
     /// Render the registration page
     pub fn render_register(WithLanguage<WithCsrf<RegisterContext>>) { "pages/register.html" }
 
@@ -341,7 +356,7 @@ register_templates! {
     pub fn render_index(WithLanguage<WithCsrf<WithOptionalSession<IndexContext>>>) { "pages/index.html" }
 
     /// Render the password change page
-    pub fn render_account_password(WithLanguage<WithCsrf<WithSession<EmptyContext>>>) { "pages/account/password.html" }
+    pub fn render_account_password(WithLanguage<WithCsrf<WithSession<ChangePasswordContext>>>) { "pages/account/password.html" }
 
     /// Render the email verification page
     pub fn render_account_verify_email(WithLanguage<WithCsrf<WithSession<EmailVerificationPageContext>>>) { "pages/account/emails/verify.html" }
@@ -355,9 +370,15 @@ register_templates! {
     /// Render the form used by the form_post response mode
     pub fn render_form_post<T: Serialize>(FormPostContext<T>) { "form_post.html" }
 
+    /// Render the page used by the web_message response mode
+    pub fn render_web_message<T: Serialize>(WebMessageContext<T>) { "web_message.html" }
+
     /// Render the HTML error page
     pub fn render_error(ErrorContext) { "pages/error.html" }
 
+    /// Render the error page shown when an OAuth 2.0 error can't be sent back to the client
+    pub fn render_oauth2_error(WithLanguage<OAuth2ErrorContext>) { "pages/oauth2_error.html" }
+
     /// Render the email verification email (plain text variant)
     pub fn render_email_verification_txt(WithLanguage<EmailVerificationContext>) { "emails/verification.txt" }
 
@@ -367,6 +388,15 @@ register_templates! {
     /// Render the email verification subject
     pub fn render_email_verification_subject(WithLanguage<EmailVerificationContext>) { "emails/verification.subject" }
 
+    /// Render the password reset email (plain text variant)
+    pub fn render_email_password_reset_txt(WithLanguage<EmailPasswordResetContext>) { "emails/password_reset.txt" }
+
+    /// Render the password reset email (HTML text variant)
+    pub fn render_email_password_reset_html(WithLanguage<EmailPasswordResetContext>) { "emails/password_reset.html" }
+
+    /// Render the password reset email subject
+    pub fn render_email_password_reset_subject(WithLanguage<EmailPasswordResetContext>) { "emails/password_reset.subject" }
+
     /// Render the upstream link mismatch message
     pub fn render_upstream_oauth2_link_mismatch(WithLanguage<WithCsrf<WithSession<UpstreamExistingLinkContext>>>) { "pages/upstream_oauth2/link_mismatch.html" }
 
@@ -396,9 +426,13 @@ impl Templates {
         rng: &mut impl Rng,
     ) -> anyhow::Result<()> {
         check::render_not_found(self, now, rng)?;
+        check::render_logged_out(self, now, rng)?;
         check::render_app(self, now, rng)?;
         check::render_login(self, now, rng)?;
+        check::render_login_totp(self, now, rng)?;
         check::render_register(self, now, rng)?;
+        check::render_reset_password(self, now, rng)?;
+        check::render_reset_password_request(self, now, rng)?;
         check::render_consent(self, now, rng)?;
         check::render_policy_violation(self, now, rng)?;
         check::render_sso_login(self, now, rng)?;
@@ -408,10 +442,15 @@ impl Templates {
         check::render_account_verify_email(self, now, rng)?;
         check::render_reauth(self, now, rng)?;
         check::render_form_post::<EmptyContext>(self, now, rng)?;
+        check::render_web_message::<EmptyContext>(self, now, rng)?;
         check::render_error(self, now, rng)?;
+        check::render_oauth2_error(self, now, rng)?;
         check::render_email_verification_txt(self, now, rng)?;
         check::render_email_verification_html(self, now, rng)?;
         check::render_email_verification_subject(self, now, rng)?;
+        check::render_email_password_reset_txt(self, now, rng)?;
+        check::render_email_password_reset_html(self, now, rng)?;
+        check::render_email_password_reset_subject(self, now, rng)?;
         check::render_upstream_oauth2_link_mismatch(self, now, rng)?;
         check::render_upstream_oauth2_suggest_link(self, now, rng)?;
         check::render_upstream_oauth2_do_register(self, now, rng)?;
@@ -423,25 +462,20 @@ impl Templates {
 mod tests {
     use super::*;
 
-    #[tokio::test]
-    async fn check_builtin_templates() {
-        #[allow(clippy::disallowed_methods)]
-        let now = chrono::Utc::now();
-        #[allow(clippy::disallowed_methods)]
-        let mut rng = rand::thread_rng();
-
+    async fn load_builtin_templates() -> Templates {
         let path = Utf8Path::new(env!("CARGO_MANIFEST_DIR")).join("../../templates/");
         let url_builder = UrlBuilder::new("https://example.com/".parse().unwrap(), None, None);
         let branding = SiteBranding::new("example.com");
         let features = SiteFeatures {
             password_login: true,
             password_registration: true,
+            password_reset: true,
         };
         let vite_manifest_path =
             Utf8Path::new(env!("CARGO_MANIFEST_DIR")).join("../../frontend/dist/manifest.json");
         let translations_path =
             Utf8Path::new(env!("CARGO_MANIFEST_DIR")).join("../../translations");
-        let templates = Templates::load(
+        Templates::load(
             path,
             url_builder,
             vite_manifest_path,
@@ -450,7 +484,35 @@ mod tests {
             features,
         )
         .await
-        .unwrap();
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn check_builtin_templates() {
+        #[allow(clippy::disallowed_methods)]
+        let now = chrono::Utc::now();
+        #[allow(clippy::disallowed_methods)]
+        let mut rng = rand::thread_rng();
+
+        let templates = load_builtin_templates().await;
         templates.check_render(now, &mut rng).unwrap();
     }
+
+    #[tokio::test]
+    async fn web_message_targets_redirect_uri_origin() {
+        let templates = load_builtin_templates().await;
+
+        let redirect_uri: url::Url = "https://client.example.com:8443/callback?foo=bar"
+            .parse()
+            .unwrap();
+        let params = std::collections::BTreeMap::from([("code", "abcd"), ("state", "xyz")]);
+        let ctx = WebMessageContext::new(&redirect_uri, params);
+        let rendered = templates.render_web_message(&ctx).unwrap();
+
+        assert!(rendered.contains(r#""https://client.example.com:8443""#));
+        assert!(!rendered.contains(r#""*""#));
+        assert!(!rendered.contains("/callback"));
+        assert!(rendered.contains(r#""code":"abcd""#));
+        assert!(rendered.contains(r#""state":"xyz""#));
+    }
 }
@@ -118,7 +118,8 @@ const VerifyEmail: React.FC<{
   const emailSent =
     resendVerificationEmailResult.data?.sendVerificationEmail.status === "SENT";
   const invalidCode =
-    verifyEmailResult.data?.verifyEmail.status === "INVALID_CODE";
+    verifyEmailResult.data?.verifyEmail.status === "INVALID_CODE" ||
+    verifyEmailResult.data?.verifyEmail.status === "CODE_EXPIRED";
   const { email: codeEmail } = data;
 
   return (
@@ -140,6 +140,69 @@ export type AppSessionEdge = {
   node: AppSession;
 };
 
+/** A security-sensitive action recorded in the audit log. */
+export enum AuditAction {
+  /** The password of a user was changed. */
+  PasswordChanged = 'PASSWORD_CHANGED',
+  /** An access token was issued to a client. */
+  TokenIssued = 'TOKEN_ISSUED',
+  /** A token was revoked by a client. */
+  TokenRevoked = 'TOKEN_REVOKED',
+  /** A user was deactivated by an administrator. */
+  UserDeactivated = 'USER_DEACTIVATED',
+  /** A user was locked by an administrator. */
+  UserLocked = 'USER_LOCKED',
+  /** A user logged in. */
+  UserLoggedIn = 'USER_LOGGED_IN',
+  /** A user logged out. */
+  UserLoggedOut = 'USER_LOGGED_OUT',
+  /** A user was reactivated by an administrator. */
+  UserReactivated = 'USER_REACTIVATED',
+  /** A user was unlocked by an administrator. */
+  UserUnlocked = 'USER_UNLOCKED'
+}
+
+/** An entry of the audit log, recording a security-sensitive action. */
+export type AuditLogEntry = {
+  __typename?: 'AuditLogEntry';
+  /** The action which was performed. */
+  action: AuditAction;
+  /** The ID of the user who performed the action, if any. */
+  actorId?: Maybe<Scalars['ID']['output']>;
+  /**
+   * The ID of the browser or OAuth 2.0 session used to perform the action,
+   * if any.
+   */
+  actorSessionId?: Maybe<Scalars['ID']['output']>;
+  /** When the action was performed. */
+  createdAt: Scalars['DateTime']['output'];
+  /** ID of the object. */
+  id: Scalars['ID']['output'];
+  /** Additional details about the action, as a JSON-encoded object. */
+  metadata: Scalars['String']['output'];
+  /** The ID of the user affected by the action, if any. */
+  targetId?: Maybe<Scalars['ID']['output']>;
+};
+
+export type AuditLogEntryConnection = {
+  __typename?: 'AuditLogEntryConnection';
+  /** A list of edges. */
+  edges: Array<AuditLogEntryEdge>;
+  /** A list of nodes. */
+  nodes: Array<AuditLogEntry>;
+  /** Information to aid in pagination. */
+  pageInfo: PageInfo;
+};
+
+/** An edge in a connection. */
+export type AuditLogEntryEdge = {
+  __typename?: 'AuditLogEntryEdge';
+  /** A cursor for use in pagination */
+  cursor: Scalars['String']['output'];
+  /** The item at the end of the edge */
+  node: AuditLogEntry;
+};
+
 /**
  * An authentication records when a user enter their credential in a browser
  * session.
@@ -162,10 +225,14 @@ export type BrowserSession = CreationEvent & Node & {
   appSessions: AppSessionConnection;
   /** When the object was created. */
   createdAt: Scalars['DateTime']['output'];
+  /** When the session expires, if it does. */
+  expiresAt?: Maybe<Scalars['DateTime']['output']>;
   /** When the session was finished. */
   finishedAt?: Maybe<Scalars['DateTime']['output']>;
   /** ID of the object. */
   id: Scalars['ID']['output'];
+  /** The IP address from which the session was created. */
+  ipAddress?: Maybe<Scalars['String']['output']>;
   /** The last time the session was active. */
   lastActiveAt?: Maybe<Scalars['DateTime']['output']>;
   /** The last IP address used by the session. */
@@ -342,6 +409,29 @@ export type CreationEvent = {
   createdAt: Scalars['DateTime']['output'];
 };
 
+/** The input for the `deactivateUser` mutation. */
+export type DeactivateUserInput = {
+  /** The ID of the user to deactivate. */
+  userId: Scalars['ID']['input'];
+};
+
+/** The payload for the `deactivateUser` mutation. */
+export type DeactivateUserPayload = {
+  __typename?: 'DeactivateUserPayload';
+  /** Status of the operation */
+  status: DeactivateUserStatus;
+  /** The user that was deactivated. */
+  user?: Maybe<User>;
+};
+
+/** The status of the `deactivateUser` mutation. */
+export enum DeactivateUserStatus {
+  /** The user was deactivated. */
+  Deactivated = 'DEACTIVATED',
+  /** The user was not found. */
+  NotFound = 'NOT_FOUND'
+}
+
 /** The type of a user agent */
 export enum DeviceType {
   /** A mobile phone. Can also sometimes be a tablet. */
@@ -370,6 +460,8 @@ export type EndBrowserSessionPayload = {
 
 /** The status of the `endBrowserSession` mutation. */
 export enum EndBrowserSessionStatus {
+  /** The session was already ended. */
+  AlreadyFinished = 'ALREADY_FINISHED',
   /** The session was ended. */
   Ended = 'ENDED',
   /** The session was not found. */
@@ -470,13 +562,31 @@ export type Mutation = {
    * Only available for administrators.
    */
   createOauth2Session: CreateOAuth2SessionPayload;
+  /**
+   * Deactivate a user, without deleting any of their data. Deactivated
+   * users can't log in until they are reactivated. This is only available
+   * to administrators.
+   */
+  deactivateUser: DeactivateUserPayload;
   endBrowserSession: EndBrowserSessionPayload;
   endCompatSession: EndCompatSessionPayload;
   endOauth2Session: EndOAuth2SessionPayload;
   /** Lock a user. This is only available to administrators. */
   lockUser: LockUserPayload;
+  /**
+   * Reactivate a deactivated user. This is only available to
+   * administrators.
+   */
+  reactivateUser: ReactivateUserPayload;
   /** Remove an email address */
   removeEmail: RemoveEmailPayload;
+  /**
+   * Revoke the consent the current user gave to an OAuth 2.0 client.
+   *
+   * The user will be asked for consent again the next time the client
+   * requests authorization.
+   */
+  revokeOauth2Consent: RevokeOAuth2ConsentPayload;
   /** Send a verification code for an email address */
   sendVerificationEmail: SendVerificationEmailPayload;
   /**
@@ -488,6 +598,8 @@ export type Mutation = {
   setDisplayName: SetDisplayNamePayload;
   /** Set an email address as primary */
   setPrimaryEmail: SetPrimaryEmailPayload;
+  /** Unlock a user. This is only available to administrators. */
+  unlockUser: UnlockUserPayload;
   /** Submit a verification code for an email address */
   verifyEmail: VerifyEmailPayload;
 };
@@ -517,6 +629,12 @@ export type MutationCreateOauth2SessionArgs = {
 };
 
 
+/** The mutations root of the GraphQL interface. */
+export type MutationDeactivateUserArgs = {
+  input: DeactivateUserInput;
+};
+
+
 /** The mutations root of the GraphQL interface. */
 export type MutationEndBrowserSessionArgs = {
   input: EndBrowserSessionInput;
@@ -541,12 +659,24 @@ export type MutationLockUserArgs = {
 };
 
 
+/** The mutations root of the GraphQL interface. */
+export type MutationReactivateUserArgs = {
+  input: ReactivateUserInput;
+};
+
+
 /** The mutations root of the GraphQL interface. */
 export type MutationRemoveEmailArgs = {
   input: RemoveEmailInput;
 };
 
 
+/** The mutations root of the GraphQL interface. */
+export type MutationRevokeOauth2ConsentArgs = {
+  input: RevokeOAuth2ConsentInput;
+};
+
+
 /** The mutations root of the GraphQL interface. */
 export type MutationSendVerificationEmailArgs = {
   input: SendVerificationEmailInput;
@@ -571,6 +701,12 @@ export type MutationSetPrimaryEmailArgs = {
 };
 
 
+/** The mutations root of the GraphQL interface. */
+export type MutationUnlockUserArgs = {
+  input: UnlockUserInput;
+};
+
+
 /** The mutations root of the GraphQL interface. */
 export type MutationVerifyEmailArgs = {
   input: VerifyEmailInput;
@@ -800,6 +936,29 @@ export type QueryUserEmailArgs = {
   id: Scalars['ID']['input'];
 };
 
+/** The input for the `reactivateUser` mutation. */
+export type ReactivateUserInput = {
+  /** The ID of the user to reactivate. */
+  userId: Scalars['ID']['input'];
+};
+
+/** The payload for the `reactivateUser` mutation. */
+export type ReactivateUserPayload = {
+  __typename?: 'ReactivateUserPayload';
+  /** Status of the operation */
+  status: ReactivateUserStatus;
+  /** The user that was reactivated. */
+  user?: Maybe<User>;
+};
+
+/** The status of the `reactivateUser` mutation. */
+export enum ReactivateUserStatus {
+  /** The user was reactivated. */
+  Reactivated = 'REACTIVATED',
+  /** The user was not found. */
+  NotFound = 'NOT_FOUND'
+}
+
 /** The input for the `removeEmail` mutation */
 export type RemoveEmailInput = {
   /** The ID of the email address to remove */
@@ -827,6 +986,28 @@ export enum RemoveEmailStatus {
   Removed = 'REMOVED'
 }
 
+/** The input of the `revokeOauth2Consent` mutation. */
+export type RevokeOAuth2ConsentInput = {
+  /** The ID of the client to revoke the consent for. */
+  oauth2ClientId: Scalars['ID']['input'];
+};
+
+export type RevokeOAuth2ConsentPayload = {
+  __typename?: 'RevokeOAuth2ConsentPayload';
+  /** Returns the client for which the consent was revoked. */
+  oauth2Client?: Maybe<Oauth2Client>;
+  /** The status of the mutation. */
+  status: RevokeOAuth2ConsentStatus;
+};
+
+/** The status of the `revokeOauth2Consent` mutation. */
+export enum RevokeOAuth2ConsentStatus {
+  /** The client was not found, or the user had not consented to it. */
+  NotFound = 'NOT_FOUND',
+  /** The consent was revoked. */
+  Revoked = 'REVOKED'
+}
+
 /** The input for the `sendVerificationEmail` mutation */
 export type SendVerificationEmailInput = {
   /** The ID of the email address to verify */
@@ -848,6 +1029,8 @@ export type SendVerificationEmailPayload = {
 export enum SendVerificationEmailStatus {
   /** The email address is already verified */
   AlreadyVerified = 'ALREADY_VERIFIED',
+  /** A verification email was sent too recently */
+  RateLimited = 'RATE_LIMITED',
   /** The verification email was sent */
   Sent = 'SENT'
 }
@@ -855,6 +1038,24 @@ export enum SendVerificationEmailStatus {
 /** A client session, either compat or OAuth 2.0 */
 export type Session = CompatSession | Oauth2Session;
 
+/** The order in which sessions are listed */
+export enum SessionOrderBy {
+  /** The oldest sessions come first. */
+  CreatedAtAsc = 'CREATED_AT_ASC',
+  /** The most recent sessions come first. */
+  CreatedAtDesc = 'CREATED_AT_DESC',
+  /**
+   * The sessions which were finished first come first. Active sessions come
+   * last.
+   */
+  FinishedAtAsc = 'FINISHED_AT_ASC',
+  /**
+   * The most recently finished sessions come first. Active sessions come
+   * first.
+   */
+  FinishedAtDesc = 'FINISHED_AT_DESC'
+}
+
 /** The state of a session */
 export enum SessionState {
   /** The session is active. */
@@ -945,6 +1146,29 @@ export type SiteConfig = Node & {
   tosUri?: Maybe<Scalars['Url']['output']>;
 };
 
+/** The input for the `unlockUser` mutation. */
+export type UnlockUserInput = {
+  /** The ID of the user to unlock. */
+  userId: Scalars['ID']['input'];
+};
+
+/** The payload for the `unlockUser` mutation. */
+export type UnlockUserPayload = {
+  __typename?: 'UnlockUserPayload';
+  /** Status of the operation */
+  status: UnlockUserStatus;
+  /** The user that was unlocked. */
+  user?: Maybe<User>;
+};
+
+/** The status of the `unlockUser` mutation. */
+export enum UnlockUserStatus {
+  /** The user was not found. */
+  NotFound = 'NOT_FOUND',
+  /** The user was unlocked. */
+  Unlocked = 'UNLOCKED'
+}
+
 export type UpstreamOAuth2Link = CreationEvent & Node & {
   __typename?: 'UpstreamOAuth2Link';
   /** When the object was created. */
@@ -1021,6 +1245,12 @@ export type User = Node & {
    * sorted
    */
   appSessions: AppSessionConnection;
+  /**
+   * Get the audit log entries in which this user is either the actor or the
+   * target, chronologically sorted. This is only available to
+   * administrators.
+   */
+  auditLogEntries: AuditLogEntryConnection;
   /** Get the list of active browser sessions, chronologically sorted */
   browserSessions: BrowserSessionConnection;
   /** Whether the user can request admin privileges. */
@@ -1031,6 +1261,8 @@ export type User = Node & {
   compatSsoLogins: CompatSsoLoginConnection;
   /** When the object was created. */
   createdAt: Scalars['DateTime']['output'];
+  /** When the user was deactivated. */
+  deactivatedAt?: Maybe<Scalars['DateTime']['output']>;
   /** Get the list of emails, chronologically sorted */
   emails: UserEmailConnection;
   /** ID of the object. */
@@ -1062,12 +1294,22 @@ export type UserAppSessionsArgs = {
 };
 
 
+/** A user is an individual's account. */
+export type UserAuditLogEntriesArgs = {
+  after?: InputMaybe<Scalars['String']['input']>;
+  before?: InputMaybe<Scalars['String']['input']>;
+  first?: InputMaybe<Scalars['Int']['input']>;
+  last?: InputMaybe<Scalars['Int']['input']>;
+};
+
+
 /** A user is an individual's account. */
 export type UserBrowserSessionsArgs = {
   after?: InputMaybe<Scalars['String']['input']>;
   before?: InputMaybe<Scalars['String']['input']>;
   first?: InputMaybe<Scalars['Int']['input']>;
   last?: InputMaybe<Scalars['Int']['input']>;
+  orderBy?: InputMaybe<SessionOrderBy>;
   state?: InputMaybe<SessionState>;
 };
 
@@ -1077,6 +1319,7 @@ export type UserCompatSessionsArgs = {
   after?: InputMaybe<Scalars['String']['input']>;
   before?: InputMaybe<Scalars['String']['input']>;
   first?: InputMaybe<Scalars['Int']['input']>;
+  isSynapseAdmin?: InputMaybe<Scalars['Boolean']['input']>;
   last?: InputMaybe<Scalars['Int']['input']>;
   state?: InputMaybe<SessionState>;
   type?: InputMaybe<CompatSessionType>;
@@ -1109,6 +1352,7 @@ export type UserOauth2SessionsArgs = {
   client?: InputMaybe<Scalars['ID']['input']>;
   first?: InputMaybe<Scalars['Int']['input']>;
   last?: InputMaybe<Scalars['Int']['input']>;
+  orderBy?: InputMaybe<SessionOrderBy>;
   state?: InputMaybe<SessionState>;
 };
 
@@ -1208,6 +1452,8 @@ export type VerifyEmailPayload = {
 export enum VerifyEmailStatus {
   /** The email address was already verified before */
   AlreadyVerified = 'ALREADY_VERIFIED',
+  /** The verification code has expired */
+  CodeExpired = 'CODE_EXPIRED',
   /** The verification code is invalid */
   InvalidCode = 'INVALID_CODE',
   /** The email address was just verified */
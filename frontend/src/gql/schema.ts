@@ -345,6 +345,14 @@ export default {
             },
             "args": []
           },
+          {
+            "name": "expiresAt",
+            "type": {
+              "kind": "SCALAR",
+              "name": "Any"
+            },
+            "args": []
+          },
           {
             "name": "finishedAt",
             "type": {
@@ -364,6 +372,14 @@ export default {
             },
             "args": []
           },
+          {
+            "name": "ipAddress",
+            "type": {
+              "kind": "SCALAR",
+              "name": "Any"
+            },
+            "args": []
+          },
           {
             "name": "lastActiveAt",
             "type": {
@@ -2972,6 +2988,13 @@ export default {
                   "name": "Any"
                 }
               },
+              {
+                "name": "isSynapseAdmin",
+                "type": {
+                  "kind": "SCALAR",
+                  "name": "Any"
+                }
+              },
               {
                 "name": "last",
                 "type": {